@@ -1,14 +1,22 @@
 use async_io::{Async, Timer};
+use futures_lite::future;
 use lapin::{
     executor::Executor,
     heartbeat::Heartbeat,
-    reactor::{Reactor, ReactorBuilder, ReactorHandle, Slot},
+    reactor::{Reactor, ReactorBuilder, ReactorHandle, ReactorSocket, Slot},
     socket_state::{SocketEvent, SocketStateHandle},
-    tcp::{TcpStream, TcpStreamWrapper},
-    ConnectionProperties, Result,
+    CancellationToken, ConnectionProperties, Result,
 };
 use parking_lot::Mutex;
-use std::{collections::HashMap, fmt, sync::Arc};
+use std::{
+    collections::HashMap,
+    fmt,
+    future::Future,
+    io,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll, Waker},
+};
 
 // ConnectionProperties extension
 
@@ -27,17 +35,64 @@ pub trait LapinAsyncIoExt {
 
 impl LapinAsyncIoExt for ConnectionProperties {
     fn with_async_io_reactor(self) -> Self {
-        self.with_reactor(AsyncIoReactorBuilder)
+        self.with_reactor(AsyncIoReactorBuilder::default())
     }
 }
 
 // Reactor
 
-struct AsyncIoReactorBuilder;
+/// A `ReactorBuilder` relying on async-io's reactor.
+///
+/// By default, the heartbeat and each wait for the socket readiness are spawned as their own
+/// task on the executor of the connection. With [`single_threaded`], they are all multiplexed
+/// into one long-lived task per connection instead, which ends once the socket gets released.
+///
+/// With [`shared`], the reactors of all the connections built from clones of the builder share
+/// the same sockets registry instead.
+///
+/// [`single_threaded`]: #method.single_threaded
+/// [`shared`]: #method.shared
+#[derive(Clone, Default)]
+pub struct AsyncIoReactorBuilder {
+    single_threaded: bool,
+    shared: Option<Arc<Mutex<Inner>>>,
+}
+
+impl AsyncIoReactorBuilder {
+    /// Multiplex the heartbeat and the readiness polling of each connection into a single task,
+    /// instead of spawning a task for each of them.
+    pub fn single_threaded() -> Self {
+        Self {
+            single_threaded: true,
+            shared: None,
+        }
+    }
+
+    /// Register the sockets of every connection using a clone of this builder in the same
+    /// reactor, to keep track of them in one place.
+    pub fn shared() -> Self {
+        Self {
+            single_threaded: false,
+            shared: Some(Arc::default()),
+        }
+    }
+
+    /// The number of sockets currently registered in this reactor, if it is [`shared`].
+    ///
+    /// [`shared`]: #method.shared
+    pub fn registered_sockets(&self) -> Option<usize> {
+        self.shared.as_ref().map(|inner| inner.lock().slots.len())
+    }
+}
 
 impl fmt::Debug for AsyncIoReactorBuilder {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_struct("AsyncIoReactorBuilder").finish()
+        let mut debug = f.debug_struct("AsyncIoReactorBuilder");
+        debug.field("single_threaded", &self.single_threaded);
+        if let Some(inner) = self.shared.as_ref().and_then(|inner| inner.try_lock()) {
+            debug.field("registered_sockets", &inner.slots.len());
+        }
+        debug.finish()
     }
 }
 
@@ -54,7 +109,31 @@ struct AsyncIoReactorHandle {
 #[derive(Default)]
 struct Inner {
     slot: Slot,
-    slots: HashMap<usize, (Arc<Async<TcpStreamWrapper>>, SocketStateHandle)>,
+    slots: HashMap<usize, AsyncIoRegistration>,
+}
+
+#[derive(Clone)]
+struct AsyncIoRegistration {
+    socket: Arc<Async<ReactorSocket>>,
+    socket_state: SocketStateHandle,
+    // Cancelled once the socket gets unregistered, to end the pending readiness polls which
+    // would otherwise keep it open
+    released: CancellationToken,
+}
+
+impl AsyncIoRegistration {
+    /// Wait for the socket to be `ready`, unless it gets unregistered first.
+    async fn ready<F: Future<Output = io::Result<()>>>(&self, ready: F) -> bool {
+        let ready = async {
+            ready.await.unwrap();
+            true
+        };
+        let released = async {
+            self.released.cancelled().await;
+            false
+        };
+        future::or(ready, released).await
+    }
 }
 
 impl fmt::Debug for AsyncIoReactorHandle {
@@ -66,33 +145,48 @@ impl fmt::Debug for AsyncIoReactorHandle {
 impl Inner {
     fn register(
         &mut self,
-        socket: Arc<Async<TcpStreamWrapper>>,
+        socket: Arc<Async<ReactorSocket>>,
         socket_state: SocketStateHandle,
     ) -> Result<usize> {
         let slot = self.slot;
         self.slot += 1;
-        self.slots.insert(slot, (socket, socket_state));
+        self.slots.insert(
+            slot,
+            AsyncIoRegistration {
+                socket,
+                socket_state,
+                released: CancellationToken::default(),
+            },
+        );
         Ok(slot)
     }
 }
 
 impl ReactorBuilder for AsyncIoReactorBuilder {
     fn build(&self, heartbeat: Heartbeat, executor: Arc<dyn Executor>) -> Box<dyn Reactor + Send> {
-        Box::new(AsyncIoReactor(AsyncIoReactorHandle {
-            heartbeat,
-            executor,
-            inner: Arc::new(Mutex::new(Default::default())),
-        }))
+        if self.single_threaded {
+            Box::new(SingleThreadedReactor(SingleThreadedReactorHandle {
+                heartbeat,
+                executor,
+                inner: Arc::new(Mutex::new(Default::default())),
+            }))
+        } else {
+            Box::new(AsyncIoReactor(AsyncIoReactorHandle {
+                heartbeat,
+                executor,
+                inner: self.shared.clone().unwrap_or_default(),
+            }))
+        }
     }
 }
 
 impl Reactor for AsyncIoReactor {
     fn register(
         &mut self,
-        socket: &mut TcpStream,
+        socket: ReactorSocket,
         socket_state: SocketStateHandle,
     ) -> Result<usize> {
-        let socket = Arc::new(Async::new(unsafe { TcpStreamWrapper::new(socket) })?);
+        let socket = Arc::new(Async::new(socket)?);
         let slot = self.0.inner.lock().register(socket, socket_state)?;
         self.0.poll_read(slot);
         self.0.poll_write(slot);
@@ -111,16 +205,23 @@ impl ReactorHandle for AsyncIoReactorHandle {
     }
 
     fn poll_read(&self, slot: usize) {
-        if let Some((socket, socket_state)) = self.inner.lock().slots.get(&slot) {
+        if let Some(registration) = self.inner.lock().slots.get(&slot) {
             self.executor
-                .spawn(Box::pin(poll_read(socket.clone(), socket_state.clone())));
+                .spawn(Box::pin(poll_read(registration.clone())));
         }
     }
 
     fn poll_write(&self, slot: usize) {
-        if let Some((socket, socket_state)) = self.inner.lock().slots.get(&slot) {
+        if let Some(registration) = self.inner.lock().slots.get(&slot) {
             self.executor
-                .spawn(Box::pin(poll_write(socket.clone(), socket_state.clone())));
+                .spawn(Box::pin(poll_write(registration.clone())));
+        }
+    }
+
+    fn unregister(&self, slot: usize) {
+        let registration = self.inner.lock().slots.remove(&slot);
+        if let Some(registration) = registration {
+            registration.released.cancel();
         }
     }
 }
@@ -131,12 +232,216 @@ async fn heartbeat(heartbeat: Heartbeat) {
     }
 }
 
-async fn poll_read(socket: Arc<Async<TcpStreamWrapper>>, socket_state: SocketStateHandle) {
-    socket.readable().await.unwrap();
-    socket_state.send(SocketEvent::Readable);
+async fn poll_read(registration: AsyncIoRegistration) {
+    if registration.ready(registration.socket.readable()).await {
+        registration.socket_state.send(SocketEvent::Readable);
+    }
 }
 
-async fn poll_write(socket: Arc<Async<TcpStreamWrapper>>, socket_state: SocketStateHandle) {
-    socket.writable().await.unwrap();
-    socket_state.send(SocketEvent::Writable);
+async fn poll_write(registration: AsyncIoRegistration) {
+    if registration.ready(registration.socket.writable()).await {
+        registration.socket_state.send(SocketEvent::Writable);
+    }
+}
+
+// Single threaded reactor
+
+#[derive(Debug)]
+struct SingleThreadedReactor(SingleThreadedReactorHandle);
+
+#[derive(Clone)]
+struct SingleThreadedReactorHandle {
+    heartbeat: Heartbeat,
+    executor: Arc<dyn Executor>,
+    inner: Arc<Mutex<SingleThreadedInner>>,
+}
+
+#[derive(Default)]
+struct SingleThreadedInner {
+    slot: Slot,
+    slots: HashMap<usize, Registration>,
+    heartbeat: bool,
+    running: bool,
+    /* Bumped each time the task has something new to wait for */
+    generation: u64,
+    waker: Option<Waker>,
+}
+
+struct Registration {
+    socket: Arc<Async<ReactorSocket>>,
+    socket_state: SocketStateHandle,
+    read: bool,
+    write: bool,
+}
+
+enum Wakeup {
+    Changed,
+    Heartbeat,
+    Readable(usize),
+    Writable(usize),
+}
+
+impl fmt::Debug for SingleThreadedReactorHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SingleThreadedReactorHandle").finish()
+    }
+}
+
+impl SingleThreadedInner {
+    fn changed(&mut self) {
+        self.generation += 1;
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+
+    fn update<F: FnOnce(&mut Registration)>(&mut self, slot: usize, f: F) {
+        if let Some(registration) = self.slots.get_mut(&slot) {
+            f(registration);
+            self.changed();
+        }
+    }
+}
+
+impl Reactor for SingleThreadedReactor {
+    fn register(
+        &mut self,
+        socket: ReactorSocket,
+        socket_state: SocketStateHandle,
+    ) -> Result<usize> {
+        let socket = Arc::new(Async::new(socket)?);
+        let mut inner = self.0.inner.lock();
+        let slot = inner.slot;
+        inner.slot += 1;
+        inner.slots.insert(
+            slot,
+            Registration {
+                socket,
+                socket_state,
+                read: true,
+                write: true,
+            },
+        );
+        inner.changed();
+        if !inner.running {
+            inner.running = true;
+            self.0.executor.spawn(Box::pin(drive(self.0.clone())));
+        }
+        Ok(slot)
+    }
+
+    fn handle(&self) -> Box<dyn ReactorHandle + Send> {
+        Box::new(self.0.clone())
+    }
+}
+
+impl ReactorHandle for SingleThreadedReactorHandle {
+    fn start_heartbeat(&self) {
+        let mut inner = self.inner.lock();
+        inner.heartbeat = true;
+        inner.changed();
+    }
+
+    fn poll_read(&self, slot: usize) {
+        self.inner
+            .lock()
+            .update(slot, |registration| registration.read = true);
+    }
+
+    fn poll_write(&self, slot: usize) {
+        self.inner
+            .lock()
+            .update(slot, |registration| registration.write = true);
+    }
+
+    fn unregister(&self, slot: usize) {
+        let mut inner = self.inner.lock();
+        inner.slots.remove(&slot);
+        inner.changed();
+    }
+}
+
+/// Resolves once something changed since `generation`.
+struct Changed {
+    inner: Arc<Mutex<SingleThreadedInner>>,
+    generation: u64,
+}
+
+impl Future for Changed {
+    type Output = Wakeup;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut inner = self.inner.lock();
+        if inner.generation != self.generation {
+            Poll::Ready(Wakeup::Changed)
+        } else {
+            inner.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+async fn drive(handle: SingleThreadedReactorHandle) {
+    loop {
+        let (generation, heartbeat, waits) = {
+            let mut inner = handle.inner.lock();
+            if inner.slots.is_empty() {
+                // The last socket got released, nothing left to drive
+                inner.running = false;
+                return;
+            }
+            let mut waits = Vec::<Pin<Box<dyn Future<Output = Wakeup> + Send>>>::new();
+            for (slot, registration) in inner.slots.iter() {
+                let slot = *slot;
+                if registration.read {
+                    let socket = registration.socket.clone();
+                    waits.push(Box::pin(async move {
+                        let _ = socket.readable().await;
+                        Wakeup::Readable(slot)
+                    }));
+                }
+                if registration.write {
+                    let socket = registration.socket.clone();
+                    waits.push(Box::pin(async move {
+                        let _ = socket.writable().await;
+                        Wakeup::Writable(slot)
+                    }));
+                }
+            }
+            (inner.generation, inner.heartbeat, waits)
+        };
+        let mut wakeup: Pin<Box<dyn Future<Output = Wakeup> + Send>> = Box::pin(Changed {
+            inner: handle.inner.clone(),
+            generation,
+        });
+        if heartbeat {
+            match handle.heartbeat.poll_timeout() {
+                Some(timeout) => {
+                    wakeup = Box::pin(future::or(wakeup, async move {
+                        Timer::after(timeout).await;
+                        Wakeup::Heartbeat
+                    }));
+                }
+                None => handle.inner.lock().heartbeat = false,
+            }
+        }
+        for wait in waits {
+            wakeup = Box::pin(future::or(wakeup, wait));
+        }
+        match wakeup.await {
+            Wakeup::Changed | Wakeup::Heartbeat => {}
+            Wakeup::Readable(slot) => {
+                if let Some(registration) = handle.inner.lock().slots.get_mut(&slot) {
+                    registration.read = false;
+                    registration.socket_state.send(SocketEvent::Readable);
+                }
+            }
+            Wakeup::Writable(slot) => {
+                if let Some(registration) = handle.inner.lock().slots.get_mut(&slot) {
+                    registration.write = false;
+                    registration.socket_state.send(SocketEvent::Writable);
+                }
+            }
+        }
+    }
 }
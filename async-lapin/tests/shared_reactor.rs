@@ -0,0 +1,45 @@
+use async_io::Timer;
+use async_lapin::AsyncIoReactorBuilder;
+use lapin::{Connection, ConnectionProperties};
+use std::time::{Duration, Instant};
+
+#[path = "../../tests/common/mod.rs"]
+mod common;
+
+use common::MockBroker;
+
+#[test]
+fn shared_reactor() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let addr = MockBroker::start(|mut broker| {
+        broker.handshake("en_US", 0);
+        broker.serve(common::unexpected);
+    });
+    let reactor = AsyncIoReactorBuilder::shared();
+    assert_eq!(AsyncIoReactorBuilder::default().registered_sockets(), None);
+    let connect = || {
+        Connection::connect_to_addr(
+            addr,
+            "amqp://127.0.0.1:5672/%2f",
+            ConnectionProperties::default().with_reactor(reactor.clone()),
+        )
+    };
+
+    async_io::block_on(async {
+        let first = connect().await.expect("first connection");
+        let second = connect().await.expect("second connection");
+        assert_eq!(reactor.registered_sockets(), Some(2));
+
+        first.close(200, "OK").await.expect("close");
+        // The io loop releases its socket once it stops
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while reactor.registered_sockets() != Some(1) {
+            assert!(Instant::now() < deadline, "socket wasn't released");
+            Timer::after(Duration::from_millis(10)).await;
+        }
+
+        assert!(second.status().connected());
+        second.create_channel().await.expect("create_channel");
+    });
+}
@@ -0,0 +1,129 @@
+use amq_protocol::frame::AMQPFrame;
+use async_io::Timer;
+use async_lapin::AsyncIoReactorBuilder;
+use lapin::{
+    executor::Executor,
+    heartbeat::Heartbeat,
+    reactor::{Reactor, ReactorBuilder},
+    Connection, ConnectionProperties,
+};
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+#[path = "../../tests/common/mod.rs"]
+mod common;
+
+use common::MockBroker;
+
+/// Runs everything on the executor driven by the test thread, counting the spawned tasks.
+#[derive(Clone)]
+struct CountingExecutor {
+    executor: Arc<async_executor::Executor<'static>>,
+    spawned: Arc<AtomicUsize>,
+    running: Arc<AtomicUsize>,
+}
+
+impl CountingExecutor {
+    fn new(executor: Arc<async_executor::Executor<'static>>) -> Self {
+        Self {
+            executor,
+            spawned: Arc::default(),
+            running: Arc::default(),
+        }
+    }
+}
+
+impl fmt::Debug for CountingExecutor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CountingExecutor")
+            .field("spawned", &self.spawned)
+            .field("running", &self.running)
+            .finish()
+    }
+}
+
+impl Executor for CountingExecutor {
+    fn spawn(&self, f: Pin<Box<dyn Future<Output = ()> + Send>>) {
+        self.spawned.fetch_add(1, Ordering::SeqCst);
+        self.running.fetch_add(1, Ordering::SeqCst);
+        let running = self.running.clone();
+        self.executor
+            .spawn(async move {
+                f.await;
+                running.fetch_sub(1, Ordering::SeqCst);
+            })
+            .detach();
+    }
+
+    fn spawn_blocking(&self, f: Box<dyn FnOnce() + Send>) {
+        thread::spawn(f);
+    }
+}
+
+/// Hands the reactor its own executor to count its tasks apart from lapin's ones.
+#[derive(Debug)]
+struct CountingReactorBuilder(CountingExecutor);
+
+impl ReactorBuilder for CountingReactorBuilder {
+    fn build(&self, heartbeat: Heartbeat, _executor: Arc<dyn Executor>) -> Box<dyn Reactor + Send> {
+        AsyncIoReactorBuilder::single_threaded().build(heartbeat, Arc::new(self.0.clone()))
+    }
+}
+
+#[test]
+fn single_threaded_reactor() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let heartbeats = Arc::new(AtomicUsize::new(0));
+    let addr = {
+        let heartbeats = heartbeats.clone();
+        MockBroker::start(move |mut broker| {
+            broker.handshake("en_US", 1);
+            broker.serve(|_, frame| match frame {
+                AMQPFrame::Heartbeat(_) => {
+                    heartbeats.fetch_add(1, Ordering::SeqCst);
+                }
+                frame => panic!("unexpected frame: {:?}", frame),
+            });
+        })
+    };
+    let executor = Arc::new(async_executor::Executor::new());
+    let reactor_executor = CountingExecutor::new(executor.clone());
+
+    async_io::block_on(executor.run(async {
+        let conn = Connection::connect_to_addr(
+            addr,
+            "amqp://127.0.0.1:5672/%2f?heartbeat=1",
+            ConnectionProperties::default()
+                .with_executor(CountingExecutor::new(executor.clone()))
+                .with_reactor(CountingReactorBuilder(reactor_executor.clone())),
+        )
+        .await
+        .expect("connection");
+        for _ in 0..10 {
+            conn.create_channel().await.expect("create_channel");
+        }
+        // Let a few heartbeats go through the reactor task
+        Timer::after(Duration::from_millis(1500)).await;
+        conn.close(200, "OK").await.expect("close");
+
+        // The reactor task ends once the io loop releases its socket
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while reactor_executor.running.load(Ordering::SeqCst) != 0 {
+            assert!(Instant::now() < deadline, "reactor task didn't end");
+            Timer::after(Duration::from_millis(10)).await;
+        }
+    }));
+
+    assert_eq!(reactor_executor.spawned.load(Ordering::SeqCst), 1);
+    assert!(heartbeats.load(Ordering::SeqCst) > 0);
+}
@@ -0,0 +1,232 @@
+//! Publish and consume throughput, measured against an in-process mock broker.
+//!
+//! No RabbitMQ server is needed: the mock broker listens on the loopback interface, acknowledges
+//! every publish when confirms are enabled and floods consumers with deliveries.
+//!
+//! To flag regressions, save a baseline and compare against it later, as the CI does for every
+//! pull request:
+//!
+//! ```text
+//! CRITERION_HOME=.cargo/criterion cargo bench --bench throughput -- --save-baseline master
+//! CRITERION_HOME=.cargo/criterion cargo bench --bench throughput -- --baseline master
+//! ```
+
+use amq_protocol::frame::{AMQPContentHeader, AMQPFrame};
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use futures_lite::StreamExt;
+use lapin::{
+    options::*,
+    protocol::{basic, confirm, AMQPClass},
+    types::FieldTable,
+    BasicProperties, Channel, Connection, ConnectionProperties,
+};
+use std::{collections::HashMap, net::SocketAddr};
+
+#[path = "../tests/common/mod.rs"]
+mod common;
+
+use common::MockBroker;
+
+const PAYLOAD_SIZE: usize = 1024;
+const MESSAGES: u64 = 1000;
+
+/// What the mock broker keeps track of on a connection to confirm the publishes and flood the
+/// consumers.
+#[derive(Default)]
+struct BrokerState {
+    confirms: HashMap<u16, u64>,
+    remaining_content: HashMap<u16, u64>,
+}
+
+impl BrokerState {
+    fn start() -> SocketAddr {
+        MockBroker::start(|mut broker| {
+            broker.handshake("en_US", 0);
+            let mut state = BrokerState::default();
+            broker.serve(|broker, frame| state.handle(broker, frame));
+        })
+    }
+
+    fn handle(&mut self, broker: &mut MockBroker, frame: AMQPFrame) {
+        match frame {
+            AMQPFrame::Method(id, AMQPClass::Confirm(confirm::AMQPMethod::Select(_))) => {
+                self.confirms.insert(id, 0);
+                broker.send_method(
+                    id,
+                    AMQPClass::Confirm(confirm::AMQPMethod::SelectOk(confirm::SelectOk {})),
+                );
+            }
+            AMQPFrame::Header(id, _, header) => {
+                self.remaining_content.insert(id, header.body_size);
+                if header.body_size == 0 {
+                    self.published(broker, id);
+                }
+            }
+            AMQPFrame::Body(id, data) => {
+                let remaining = self.remaining_content.entry(id).or_default();
+                *remaining = remaining.saturating_sub(data.len() as u64);
+                if *remaining == 0 {
+                    self.published(broker, id);
+                }
+            }
+            AMQPFrame::Method(id, AMQPClass::Basic(basic::AMQPMethod::Consume(consume))) => {
+                broker.send_method(
+                    id,
+                    AMQPClass::Basic(basic::AMQPMethod::ConsumeOk(basic::ConsumeOk {
+                        consumer_tag: consume.consumer_tag.clone(),
+                    })),
+                );
+                for delivery_tag in 1..=MESSAGES {
+                    deliver(broker, id, &consume, delivery_tag);
+                }
+            }
+            AMQPFrame::Method(id, AMQPClass::Basic(basic::AMQPMethod::Cancel(cancel))) => broker
+                .send_method(
+                    id,
+                    AMQPClass::Basic(basic::AMQPMethod::CancelOk(basic::CancelOk {
+                        consumer_tag: cancel.consumer_tag,
+                    })),
+                ),
+            // basic.publish, basic.ack and heartbeats need no answer
+            _ => {}
+        }
+    }
+
+    fn published(&mut self, broker: &mut MockBroker, channel_id: u16) {
+        if let Some(delivery_tag) = self.confirms.get_mut(&channel_id) {
+            *delivery_tag += 1;
+            let delivery_tag = *delivery_tag;
+            broker.send_method(
+                channel_id,
+                AMQPClass::Basic(basic::AMQPMethod::Ack(basic::Ack {
+                    delivery_tag,
+                    multiple: false,
+                })),
+            );
+        }
+    }
+}
+
+fn deliver(broker: &mut MockBroker, channel_id: u16, consume: &basic::Consume, delivery_tag: u64) {
+    broker.send_method(
+        channel_id,
+        AMQPClass::Basic(basic::AMQPMethod::Deliver(basic::Deliver {
+            consumer_tag: consume.consumer_tag.clone(),
+            delivery_tag,
+            redelivered: false,
+            exchange: "".into(),
+            routing_key: consume.queue.clone(),
+        })),
+    );
+    broker.send(AMQPFrame::Header(
+        channel_id,
+        60,
+        Box::new(AMQPContentHeader {
+            class_id: 60,
+            weight: 0,
+            body_size: PAYLOAD_SIZE as u64,
+            properties: BasicProperties::default(),
+        }),
+    ));
+    broker.send(AMQPFrame::Body(channel_id, vec![0; PAYLOAD_SIZE]));
+}
+
+fn setup(confirms: bool) -> (Connection, Channel) {
+    let addr = BrokerState::start();
+    async_global_executor::block_on(async {
+        let conn = Connection::connect(
+            &format!("amqp://127.0.0.1:{}/%2f", addr.port()),
+            ConnectionProperties::default(),
+        )
+        .await
+        .expect("connection error");
+        let channel = conn.create_channel().await.expect("create_channel");
+        if confirms {
+            channel
+                .confirm_select(ConfirmSelectOptions::default())
+                .await
+                .expect("confirm_select");
+        }
+        (conn, channel)
+    })
+}
+
+fn publish(c: &mut Criterion) {
+    let mut group = c.benchmark_group("publish");
+    group.throughput(Throughput::Bytes(PAYLOAD_SIZE as u64 * MESSAGES));
+    for &confirms in &[false, true] {
+        let (_conn, channel) = setup(confirms);
+        let payload = vec![0; PAYLOAD_SIZE];
+        let name = if confirms { "confirms" } else { "no_confirms" };
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                async_global_executor::block_on(async {
+                    for _ in 0..MESSAGES {
+                        channel
+                            .basic_publish(
+                                "",
+                                "bench",
+                                BasicPublishOptions::default(),
+                                payload.clone(),
+                                BasicProperties::default(),
+                            )
+                            .await
+                            .expect("basic_publish");
+                    }
+                    channel
+                        .wait_for_confirms()
+                        .await
+                        .expect("wait_for_confirms");
+                })
+            })
+        });
+    }
+    group.finish();
+}
+
+fn consume(c: &mut Criterion) {
+    let mut group = c.benchmark_group("consume");
+    group.throughput(Throughput::Bytes(PAYLOAD_SIZE as u64 * MESSAGES));
+    for &no_ack in &[false, true] {
+        let (_conn, channel) = setup(false);
+        let name = if no_ack { "no_ack" } else { "manual_ack" };
+        let mut consumer_tags = (0..).map(|id| format!("bench-{}", id));
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let consumer_tag = consumer_tags.next().expect("consumer tag");
+                async_global_executor::block_on(async {
+                    let mut consumer = channel
+                        .basic_consume(
+                            "bench",
+                            &consumer_tag,
+                            BasicConsumeOptions::default().no_ack(no_ack),
+                            FieldTable::default(),
+                        )
+                        .await
+                        .expect("basic_consume");
+                    for _ in 0..MESSAGES {
+                        let (channel, delivery) = consumer
+                            .next()
+                            .await
+                            .expect("consumer ended")
+                            .expect("delivery error");
+                        if !no_ack {
+                            channel
+                                .basic_ack(delivery.delivery_tag, BasicAckOptions::default())
+                                .await
+                                .expect("basic_ack");
+                        }
+                    }
+                    channel
+                        .basic_cancel(&consumer_tag, BasicCancelOptions::default())
+                        .await
+                        .expect("basic_cancel");
+                })
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, publish, consume);
+criterion_main!(benches);
@@ -1,5 +1,5 @@
 use lapin::{
-    message::{BasicReturnMessage, Delivery, DeliveryResult},
+    message::DeliveryResult,
     options::*,
     protocol::{AMQPErrorKind, AMQPSoftError},
     types::FieldTable,
@@ -111,10 +111,7 @@ fn main() {
             .basic_publish(
                 "",
                 "unroutable-routing-key-for-tests",
-                BasicPublishOptions {
-                    mandatory: true,
-                    ..BasicPublishOptions::default()
-                },
+                BasicPublishOptions::default().mandatory(true),
                 payload.to_vec(),
                 BasicProperties::default().with_priority(42),
             )
@@ -124,21 +121,20 @@ fn main() {
             .expect("publisher-confirms");
         assert!(confirm.is_ack());
         let message = confirm.take_message().unwrap();
+        assert_eq!(message.delivery.delivery_tag, 0);
+        assert_eq!(message.delivery.exchange.as_str(), "");
         assert_eq!(
-            message,
-            BasicReturnMessage {
-                delivery: Delivery {
-                    delivery_tag: 0,
-                    exchange: "".into(),
-                    routing_key: "unroutable-routing-key-for-tests".into(),
-                    redelivered: false,
-                    properties: BasicProperties::default().with_priority(42),
-                    data: payload.to_vec(),
-                },
-                reply_code: 312,
-                reply_text: "NO_ROUTE".into(),
-            }
+            message.delivery.routing_key.as_str(),
+            "unroutable-routing-key-for-tests"
         );
+        assert!(!message.delivery.redelivered);
+        assert_eq!(
+            message.delivery.properties,
+            BasicProperties::default().with_priority(42)
+        );
+        assert_eq!(message.delivery.data(), &payload[..]);
+        assert_eq!(message.reply_code, 312);
+        assert_eq!(message.reply_text.as_str(), "NO_ROUTE");
         let error = message.error().unwrap();
         assert_eq!(error.kind(), &AMQPErrorKind::Soft(AMQPSoftError::NOROUTE));
 
@@ -5,11 +5,7 @@ use crate::{
     Error, Promise, Result,
 };
 use parking_lot::Mutex;
-use std::{
-    collections::{HashMap, HashSet},
-    fmt,
-    sync::Arc,
-};
+use std::{collections::HashMap, fmt, sync::Arc};
 
 pub type DeliveryTag = u64;
 
@@ -18,6 +14,7 @@ pub(crate) struct Acknowledgements(Arc<Mutex<Inner>>);
 
 type AMQPResult = std::result::Result<(), AMQPError>;
 type ConfirmationBroadcaster = pinky_swear::PinkyBroadcaster<Result<Confirmation>>;
+type NackHandler = Arc<dyn Fn(DeliveryTag) + Send + Sync>;
 
 impl Acknowledgements {
     pub(crate) fn new(returned_messages: ReturnedMessages) -> Self {
@@ -37,12 +34,23 @@ impl Acknowledgements {
         Some(promise.await)
     }
 
+    pub(crate) fn pending_confirms(&self) -> Vec<Promise<Confirmation>> {
+        self.0
+            .lock()
+            .pending
+            .values()
+            .map(|(_, broadcaster)| broadcaster.subscribe())
+            .collect()
+    }
+
     pub(crate) fn ack(&self, delivery_tag: DeliveryTag, channel_id: u16) -> AMQPResult {
         self.0.lock().drop_pending(delivery_tag, true, channel_id)
     }
 
     pub(crate) fn nack(&self, delivery_tag: DeliveryTag, channel_id: u16) -> AMQPResult {
-        self.0.lock().drop_pending(delivery_tag, false, channel_id)
+        let res = self.0.lock().drop_pending(delivery_tag, false, channel_id);
+        self.notify_nacks();
+        res
     }
 
     pub(crate) fn ack_all_pending(&self) {
@@ -51,6 +59,7 @@ impl Acknowledgements {
 
     pub(crate) fn nack_all_pending(&self) {
         self.0.lock().drop_all(false);
+        self.notify_nacks();
     }
 
     pub(crate) fn ack_all_before(&self, delivery_tag: DeliveryTag, channel_id: u16) -> AMQPResult {
@@ -60,9 +69,32 @@ impl Acknowledgements {
     }
 
     pub(crate) fn nack_all_before(&self, delivery_tag: DeliveryTag, channel_id: u16) -> AMQPResult {
-        self.0
+        let res = self
+            .0
             .lock()
-            .complete_pending_before(delivery_tag, false, channel_id)
+            .complete_pending_before(delivery_tag, false, channel_id);
+        self.notify_nacks();
+        res
+    }
+
+    pub(crate) fn set_nack_handler(&self, handler: NackHandler) {
+        self.0.lock().nack_handler = Some(handler);
+    }
+
+    fn notify_nacks(&self) {
+        // Call the handler without holding the lock so that it can safely publish again
+        let (handler, nacked) = {
+            let mut inner = self.0.lock();
+            (
+                inner.nack_handler.clone(),
+                std::mem::take(&mut inner.nacked),
+            )
+        };
+        if let Some(handler) = handler {
+            for delivery_tag in nacked {
+                handler(delivery_tag);
+            }
+        }
     }
 
     pub(crate) fn on_channel_error(&self, channel_id: u16, error: Error) {
@@ -86,6 +118,8 @@ struct Inner {
     last: Option<(DeliveryTag, Promise<Confirmation>)>,
     pending: HashMap<DeliveryTag, (u16, ConfirmationBroadcaster)>,
     returned_messages: ReturnedMessages,
+    nack_handler: Option<NackHandler>,
+    nacked: Vec<DeliveryTag>,
 }
 
 impl Inner {
@@ -94,6 +128,8 @@ impl Inner {
             last: None,
             pending: HashMap::default(),
             returned_messages,
+            nack_handler: None,
+            nacked: Vec::default(),
         }
     }
 
@@ -111,7 +147,15 @@ impl Inner {
         promise
     }
 
-    fn complete_pending(&mut self, success: bool, resolver: ConfirmationBroadcaster) {
+    fn complete_pending(
+        &mut self,
+        delivery_tag: DeliveryTag,
+        success: bool,
+        resolver: ConfirmationBroadcaster,
+    ) {
+        if !success && self.nack_handler.is_some() {
+            self.nacked.push(delivery_tag);
+        }
         let returned_message = self.returned_messages.get_waiting_message().map(Box::new);
         resolver.swear(Ok(if success {
             Confirmation::Ack(returned_message)
@@ -121,13 +165,14 @@ impl Inner {
     }
 
     fn drop_all(&mut self, success: bool) {
-        for (_, resolver) in self
+        let mut pending = self
             .pending
             .drain()
-            .map(|tup| tup.1)
-            .collect::<Vec<(u16, ConfirmationBroadcaster)>>()
-        {
-            self.complete_pending(success, resolver);
+            .map(|(delivery_tag, (_, resolver))| (delivery_tag, resolver))
+            .collect::<Vec<(DeliveryTag, ConfirmationBroadcaster)>>();
+        pending.sort_by_key(|(delivery_tag, _)| *delivery_tag);
+        for (delivery_tag, resolver) in pending {
+            self.complete_pending(delivery_tag, success, resolver);
         }
     }
 
@@ -138,7 +183,7 @@ impl Inner {
         channel_id: u16,
     ) -> AMQPResult {
         if let Some((_, resolver)) = self.pending.remove(&delivery_tag) {
-            self.complete_pending(success, resolver);
+            self.complete_pending(delivery_tag, success, resolver);
             Ok(())
         } else {
             Err(AMQPError::new(
@@ -161,13 +206,14 @@ impl Inner {
         channel_id: u16,
     ) -> AMQPResult {
         let mut res = Ok(());
-        for tag in self
+        let mut tags = self
             .pending
             .keys()
             .filter(|tag| **tag <= delivery_tag)
             .cloned()
-            .collect::<HashSet<DeliveryTag>>()
-        {
+            .collect::<Vec<DeliveryTag>>();
+        tags.sort_unstable();
+        for tag in tags {
             if let Err(err) = self.drop_pending(tag, success, channel_id) {
                 res = Err(err);
             }
@@ -0,0 +1,258 @@
+use crate::{options::ChannelFlowOptions, types::ShortUInt, Channel, Result};
+use std::{
+    fmt,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// When a consumer is considered to lag behind, and what to do about it, see
+/// [`Consumer::on_backlog`].
+///
+/// [`Consumer::on_backlog`]: ./struct.Consumer.html#method.on_backlog
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BacklogPolicy {
+    high_watermark: usize,
+    low_watermark: usize,
+    grace: Duration,
+    shedding: Shedding,
+}
+
+impl BacklogPolicy {
+    /// A consumer lags once more than `high_watermark` deliveries have been waiting to be read
+    /// for longer than `grace`.
+    ///
+    /// The consumer is considered drained once at most half as many deliveries are waiting, and
+    /// nothing gets shed by default.
+    pub fn new(high_watermark: usize, grace: Duration) -> Self {
+        Self {
+            high_watermark,
+            low_watermark: high_watermark / 2,
+            grace,
+            shedding: Shedding::None,
+        }
+    }
+
+    /// Consider the consumer drained once at most `low_watermark` deliveries are waiting.
+    pub fn with_low_watermark(mut self, low_watermark: usize) -> Self {
+        self.low_watermark = low_watermark;
+        self
+    }
+
+    /// What to do while the consumer lags.
+    pub fn with_shedding(mut self, shedding: Shedding) -> Self {
+        self.shedding = shedding;
+        self
+    }
+
+    pub fn high_watermark(&self) -> usize {
+        self.high_watermark
+    }
+
+    pub fn low_watermark(&self) -> usize {
+        self.low_watermark
+    }
+
+    pub fn grace(&self) -> Duration {
+        self.grace
+    }
+
+    pub fn shedding(&self) -> Shedding {
+        self.shedding
+    }
+}
+
+/// How to slow the server down while a consumer lags.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Shedding {
+    /// Only report the backlog.
+    None,
+    /// Ask the server to pause the deliveries of the channel with `channel.flow`, and to resume
+    /// them once drained.
+    ///
+    /// RabbitMQ doesn't support pausing a channel this way and closes it instead: use `Prefetch`
+    /// with it.
+    ChannelFlow,
+    /// Limit the deliveries of the whole channel to this many unacknowledged messages, then
+    /// restore the channel-wide prefetch set with `Channel::basic_qos` (or no limit) once
+    /// drained.
+    Prefetch(ShortUInt),
+}
+
+/// What [`Consumer::on_backlog`] reports.
+///
+/// [`Consumer::on_backlog`]: ./struct.Consumer.html#method.on_backlog
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BacklogEvent {
+    /// The backlog stayed above the high watermark for longer than the grace period.
+    Lagging {
+        /// The deliveries waiting to be read
+        buffered: usize,
+        /// When the backlog went above the high watermark
+        since: Instant,
+    },
+    /// The backlog went back down to the low watermark.
+    Drained {
+        /// The deliveries waiting to be read
+        buffered: usize,
+    },
+}
+
+pub(crate) type BacklogCallback = Arc<dyn Fn(BacklogEvent) + Send + Sync>;
+
+pub(crate) type BacklogOutcome = (
+    Option<(BacklogCallback, BacklogEvent)>,
+    Option<Action>,
+    BacklogCheck,
+);
+
+/// The backlog state of a consumer.
+pub(crate) struct BacklogMonitor {
+    policy: BacklogPolicy,
+    callback: BacklogCallback,
+    above_since: Option<Instant>,
+    watching: bool,
+    /* The channel of the last buffered delivery, while watching */
+    channel: Option<Channel>,
+    /* How to undo the shedding once drained */
+    shedding: Option<Restore>,
+}
+
+/// What a watcher should do after checking the backlog.
+pub(crate) enum BacklogCheck {
+    /// Check again after this long
+    Wait(Duration),
+    /// Stop watching
+    Stop,
+}
+
+#[derive(Clone, Copy)]
+pub(crate) enum Restore {
+    Nothing,
+    Flow,
+    Prefetch(ShortUInt),
+}
+
+impl BacklogMonitor {
+    pub(crate) fn new(policy: BacklogPolicy, callback: BacklogCallback) -> Self {
+        Self {
+            policy,
+            callback,
+            above_since: None,
+            watching: false,
+            channel: None,
+            shedding: None,
+        }
+    }
+
+    /// Record a new buffered delivery, returning whether a watcher needs to be started.
+    pub(crate) fn grew(&mut self, buffered: usize, channel: &Channel) -> Option<Duration> {
+        if buffered <= self.policy.high_watermark {
+            return None;
+        }
+        self.channel = Some(channel.clone());
+        if self.above_since.is_none() {
+            self.above_since = Some(Instant::now());
+        }
+        if self.watching {
+            None
+        } else {
+            self.watching = true;
+            Some(self.policy.grace)
+        }
+    }
+
+    /// Check the backlog from a watcher, returning the event to report and the change to make
+    /// to the channel, alongside what the watcher should do next.
+    pub(crate) fn check(&mut self, buffered: usize) -> BacklogOutcome {
+        if let Some(restore) = self.shedding {
+            let connected = self
+                .channel
+                .as_ref()
+                .map_or(false, |channel| channel.status().connected());
+            if !connected {
+                // Nothing to restore on a closed channel
+                self.stop_watching();
+                return (None, None, BacklogCheck::Stop);
+            }
+            if buffered > self.policy.low_watermark {
+                return (None, None, BacklogCheck::Wait(self.policy.grace));
+            }
+            let channel = self.stop_watching().expect("backlog channel");
+            let event = BacklogEvent::Drained { buffered };
+            return (
+                Some((self.callback.clone(), event)),
+                Some(Action::Restore(channel, restore)),
+                BacklogCheck::Stop,
+            );
+        }
+        let since = match self.above_since {
+            Some(since) if buffered > self.policy.high_watermark => since,
+            _ => {
+                // Caught up before the end of the grace period
+                self.stop_watching();
+                return (None, None, BacklogCheck::Stop);
+            }
+        };
+        let elapsed = since.elapsed();
+        if elapsed < self.policy.grace {
+            return (None, None, BacklogCheck::Wait(self.policy.grace - elapsed));
+        }
+        let channel = self.channel.clone().expect("backlog channel");
+        self.shedding = Some(match self.policy.shedding {
+            Shedding::None => Restore::Nothing,
+            Shedding::ChannelFlow => Restore::Flow,
+            Shedding::Prefetch(_) => Restore::Prefetch(channel.global_prefetch()),
+        });
+        let event = BacklogEvent::Lagging { buffered, since };
+        (
+            Some((self.callback.clone(), event)),
+            Some(Action::Shed(channel, self.policy.shedding)),
+            BacklogCheck::Wait(self.policy.grace),
+        )
+    }
+
+    fn stop_watching(&mut self) -> Option<Channel> {
+        self.above_since = None;
+        self.watching = false;
+        self.shedding = None;
+        self.channel.take()
+    }
+}
+
+impl fmt::Debug for BacklogMonitor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BacklogMonitor")
+            .field("policy", &self.policy)
+            .field("above_since", &self.above_since)
+            .field("shedding", &self.shedding.is_some())
+            .finish()
+    }
+}
+
+/// A change to apply to the channel of a lagging consumer.
+pub(crate) enum Action {
+    Shed(Channel, Shedding),
+    Restore(Channel, Restore),
+}
+
+impl Action {
+    pub(crate) async fn run(self) -> Result<()> {
+        match self {
+            Action::Shed(_, Shedding::None) | Action::Restore(_, Restore::Nothing) => Ok(()),
+            Action::Shed(channel, Shedding::ChannelFlow) => channel
+                .channel_flow(ChannelFlowOptions::default().active(false))
+                .await
+                .map(|_| ()),
+            Action::Shed(channel, Shedding::Prefetch(prefetch_count)) => {
+                channel.set_transient_prefetch(prefetch_count).await
+            }
+            Action::Restore(channel, Restore::Flow) => channel
+                .channel_flow(ChannelFlowOptions::default().active(true))
+                .await
+                .map(|_| ()),
+            Action::Restore(channel, Restore::Prefetch(prefetch_count)) => {
+                channel.set_transient_prefetch(prefetch_count).await
+            }
+        }
+    }
+}
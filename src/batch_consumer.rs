@@ -0,0 +1,111 @@
+use crate::{
+    message::Delivery,
+    options::{BasicAckOptions, BasicNackOptions},
+    tracing_integration::targets,
+    Channel, Consumer, Result,
+};
+use async_io::Timer;
+use futures_lite::{future, StreamExt};
+use std::{
+    future::Future,
+    mem,
+    time::{Duration, Instant},
+};
+use tracing::{error, trace, warn};
+
+enum Event {
+    Delivery(Option<Result<(Channel, Delivery)>>),
+    Flush,
+}
+
+/// Accumulate the deliveries of `consumer` and hand them to `handler` in batches, settling
+/// each batch with a single multiple ack or nack.
+pub(crate) async fn run<F, Fut>(
+    channel: Channel,
+    mut consumer: Consumer,
+    batch_size: usize,
+    flush_interval: Duration,
+    handler: F,
+) where
+    F: Fn(Vec<Delivery>) -> Fut + Send + 'static,
+    Fut: Future<Output = Result<()>> + Send + 'static,
+{
+    let mut batch = Vec::with_capacity(batch_size);
+    let mut deadline = Instant::now();
+    loop {
+        let event = if batch.is_empty() {
+            Event::Delivery(consumer.next().await)
+        } else {
+            future::or(async { Event::Delivery(consumer.next().await) }, async {
+                Timer::at(deadline).await;
+                Event::Flush
+            })
+            .await
+        };
+        match event {
+            Event::Delivery(Some(Ok((_, delivery)))) => {
+                if batch.is_empty() {
+                    deadline = Instant::now() + flush_interval;
+                }
+                batch.push(delivery);
+                if batch.len() >= batch_size {
+                    flush(&channel, &handler, &mut batch).await;
+                }
+            }
+            Event::Delivery(Some(Err(err))) => {
+                // The channel is gone, the server will deliver the pending batch again
+                error!(
+                    target: targets::CONSUMER,
+                    "batch consumer {} failed: {}",
+                    consumer.tag(),
+                    err
+                );
+                return;
+            }
+            Event::Delivery(None) => {
+                trace!(target: targets::CONSUMER, "batch consumer {} canceled", consumer.tag());
+                flush(&channel, &handler, &mut batch).await;
+                return;
+            }
+            Event::Flush => flush(&channel, &handler, &mut batch).await,
+        }
+    }
+}
+
+async fn flush<F, Fut>(channel: &Channel, handler: &F, batch: &mut Vec<Delivery>)
+where
+    F: Fn(Vec<Delivery>) -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    let deliveries = mem::replace(batch, Vec::with_capacity(batch.capacity()));
+    let (count, last) = match deliveries.last() {
+        Some(delivery) => (deliveries.len(), delivery.delivery_tag),
+        None => return,
+    };
+    let res = match handler(deliveries).await {
+        Ok(()) => {
+            channel
+                .basic_ack(last, BasicAckOptions::default().multiple(true))
+                .await
+        }
+        Err(err) => {
+            warn!(
+                target: targets::CONSUMER,
+                "failed to handle a batch of {} deliveries: {}",
+                count,
+                err
+            );
+            channel
+                .basic_nack(last, BasicNackOptions::default().multiple(true))
+                .await
+        }
+    };
+    if let Err(err) = res {
+        error!(
+            target: targets::CONSUMER,
+            "failed to settle a batch of {} deliveries: {}",
+            count,
+            err
+        );
+    }
+}
@@ -104,6 +104,13 @@ impl Buffer {
         self.capacity - self.available_data
     }
 
+    pub(crate) fn peek(&self, count: usize) -> Vec<u8> {
+        let count = cmp::min(count, self.available_data());
+        (0..count)
+            .map(|i| self.memory[(self.position + i) % self.capacity])
+            .collect()
+    }
+
     pub(crate) fn consume(&mut self, count: usize) -> usize {
         let cnt = cmp::min(count, self.available_data());
         self.position += cnt;
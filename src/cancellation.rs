@@ -0,0 +1,212 @@
+use parking_lot::Mutex;
+use std::{
+    collections::HashMap,
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll, Waker},
+};
+
+type Callback = Box<dyn FnOnce() + Send>;
+
+/// A token shared between the caller and lapin to abort long-running operations.
+///
+/// When set on the [`ConnectionProperties`], cancelling it aborts the connection if it's still
+/// being established, resolves all the pending operations with [`Error::Cancelled`], ends the
+/// consumers and closes the connection gracefully.
+///
+/// When set on a [`Consumer`], cancelling it ends the consumer stream and cancels the consumer on
+/// the server side.
+///
+/// [`ConnectionProperties`]: ./struct.ConnectionProperties.html
+/// [`Error::Cancelled`]: ./enum.Error.html#variant.Cancelled
+/// [`Consumer`]: ./struct.Consumer.html
+#[derive(Clone, Default)]
+pub struct CancellationToken(Arc<Mutex<Inner>>);
+
+#[derive(Default)]
+struct Inner {
+    cancelled: bool,
+    callbacks: Vec<Callback>,
+    waiters: HashMap<usize, Waker>,
+    next_waiter: usize,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancel everything relying on this token. Cancelling it more than once has no effect.
+    pub fn cancel(&self) {
+        let (callbacks, waiters) = {
+            let mut inner = self.0.lock();
+            if inner.cancelled {
+                return;
+            }
+            inner.cancelled = true;
+            (
+                std::mem::take(&mut inner.callbacks),
+                std::mem::take(&mut inner.waiters),
+            )
+        };
+        // Run the callbacks without holding the lock so that they can use the token
+        for callback in callbacks {
+            callback();
+        }
+        for waker in waiters.into_iter().map(|(_, waker)| waker) {
+            waker.wake();
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.lock().cancelled
+    }
+
+    /// Wait for the token to be cancelled.
+    ///
+    /// Dropping the returned future before the token gets cancelled stops waiting for it, so
+    /// that waiting over and over on a token which never gets cancelled doesn't leak anything.
+    pub async fn cancelled(&self) {
+        Cancelled {
+            token: self,
+            waiter: None,
+        }
+        .await
+    }
+
+    /// Run the callback once the token gets cancelled, or right away if it already is.
+    pub(crate) fn on_cancel<F: FnOnce() + Send + 'static>(&self, callback: F) {
+        {
+            let mut inner = self.0.lock();
+            if !inner.cancelled {
+                inner.callbacks.push(Box::new(callback));
+                return;
+            }
+        }
+        callback();
+    }
+}
+
+struct Cancelled<'a> {
+    token: &'a CancellationToken,
+    waiter: Option<usize>,
+}
+
+impl Future for Cancelled<'_> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let token = self.token;
+        let mut inner = token.0.lock();
+        if inner.cancelled {
+            return Poll::Ready(());
+        }
+        let waiter = match self.waiter {
+            Some(waiter) => waiter,
+            None => {
+                let waiter = inner.next_waiter;
+                inner.next_waiter = inner.next_waiter.wrapping_add(1);
+                self.waiter = Some(waiter);
+                waiter
+            }
+        };
+        // Only keep the latest waker of each waiter, however many times it gets polled
+        let waker = inner
+            .waiters
+            .entry(waiter)
+            .or_insert_with(|| cx.waker().clone());
+        if !waker.will_wake(cx.waker()) {
+            *waker = cx.waker().clone();
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Cancelled<'_> {
+    fn drop(&mut self) {
+        if let Some(waiter) = self.waiter {
+            self.token.0.lock().waiters.remove(&waiter);
+        }
+    }
+}
+
+impl fmt::Debug for CancellationToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("CancellationToken");
+        if let Some(inner) = self.0.try_lock() {
+            debug.field("cancelled", &inner.cancelled);
+        }
+        debug.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn callbacks_run_once() {
+        let token = CancellationToken::new();
+        let count = Arc::new(AtomicUsize::new(0));
+        {
+            let count = count.clone();
+            token.on_cancel(move || {
+                count.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+        assert!(!token.is_cancelled());
+        assert_eq!(count.load(Ordering::SeqCst), 0);
+
+        token.cancel();
+        token.cancel();
+        assert!(token.is_cancelled());
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+
+        // Late registrations run right away
+        {
+            let count = count.clone();
+            token.on_cancel(move || {
+                count.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn cancelled_future() {
+        let token = CancellationToken::new();
+        let waiter = token.clone();
+        let task = async_global_executor::spawn(async move { waiter.cancelled().await });
+        token.cancel();
+        async_global_executor::block_on(task);
+        async_global_executor::block_on(token.cancelled());
+    }
+
+    #[test]
+    fn dropped_cancelled_futures() {
+        let token = CancellationToken::new();
+        for _ in 0..100 {
+            assert_eq!(
+                async_global_executor::block_on(futures_lite::future::poll_once(token.cancelled())),
+                None
+            );
+        }
+        assert!(token.0.lock().waiters.is_empty());
+
+        let mut waiter = Box::pin(token.cancelled());
+        for _ in 0..100 {
+            assert_eq!(
+                async_global_executor::block_on(futures_lite::future::poll_once(&mut waiter)),
+                None
+            );
+        }
+        assert_eq!(token.0.lock().waiters.len(), 1);
+        token.cancel();
+        async_global_executor::block_on(waiter);
+        assert!(token.0.lock().waiters.is_empty());
+    }
+}
@@ -1,29 +1,59 @@
 use crate::{
     acknowledgement::{Acknowledgements, DeliveryTag},
     auth::Credentials,
+    batch_consumer,
     channel_closer::ChannelCloser,
+    channel_defaults::ChannelDefaults,
+    channel_receiver_state::ContentReceiver,
+    channel_stats::{ChannelStats, ChannelStatsCounters},
     channel_status::{ChannelState, ChannelStatus},
     connection_closer::ConnectionCloser,
+    connection_properties,
     connection_status::{ConnectionState, ConnectionStep},
-    consumer::Consumer,
+    consumer::{Consumer, ConsumerDelegate, ConsumerSpec, Subscription, UnknownConsumerPolicy},
+    consumer_stats::Settlement,
+    declaration_cache::DeclarationCache,
+    error_handler::ErrorHandler,
     executor::Executor,
     frames::{ExpectedReply, Frames},
+    id,
     id_sequence::IdSequence,
     internal_rpc::InternalRPCHandle,
-    message::{BasicGetMessage, BasicReturnMessage, Delivery},
-    protocol::{self, AMQPClass, AMQPError, AMQPHardError},
-    publisher_confirm::PublisherConfirm,
-    queue::Queue,
+    message::{BasicGetMessage, BasicReturnMessage, Delivery, DeliveryMode, Message},
+    metrics::ConsumerMetrics,
+    outstanding_deliveries::OutstandingDeliveries,
+    protocol::{self, AMQPClass, AMQPError, AMQPErrorKind, AMQPHardError, AMQPSoftError},
+    publisher_confirm::{MandatoryOutcome, PublisherConfirm},
+    queue::{OverflowBehavior, Queue, QueueStats, StreamOffset},
     queues::Queues,
     returned_messages::ReturnedMessages,
+    server_named_queues::ServerNamedQueues,
     socket_state::SocketStateHandle,
+    timestamp::Timestamp,
+    topology::{
+        AlternateExchange, ExchangeDeclaration, QueueDeclaration, Topology, TopologyTransaction,
+        VerificationReport,
+    },
+    tracing_integration::{channel_span, instrument, targets},
     types::*,
-    BasicProperties, Configuration, Connection, ConnectionStatus, Error, ExchangeKind, Promise,
-    PromiseResolver, Result,
+    validation, BasicProperties, Configuration, Connection, ConnectionStatus, Error, ExchangeKind,
+    OversizedMessagePolicy, PriorityValidation, Promise, PromiseResolver, QosScope, Result,
 };
 use amq_protocol::frame::{AMQPContentHeader, AMQPFrame};
-use std::{convert::TryFrom, fmt, sync::Arc};
-use tracing::{debug, error, info, level_enabled, trace, Level};
+use async_io::Timer;
+use futures_lite::{stream, Stream};
+use parking_lot::Mutex;
+#[cfg(feature = "serde")]
+use serde::Serialize;
+use std::{
+    borrow::Cow,
+    convert::TryFrom,
+    fmt,
+    future::Future,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tracing::{debug, error, info, level_enabled, trace, warn, Level};
 
 #[cfg(test)]
 use crate::queue::QueueState;
@@ -45,8 +75,15 @@ pub struct Channel {
     connection_status: ConnectionStatus,
     acknowledgements: Acknowledgements,
     delivery_tag: IdSequence<DeliveryTag>,
+    /* Held while a publish gets its delivery tag and its frames queued */
+    publishing: Arc<Mutex<()>>,
+    outstanding_deliveries: OutstandingDeliveries,
     queues: Queues,
     returned_messages: ReturnedMessages,
+    declaration_cache: DeclarationCache,
+    server_named_queues: ServerNamedQueues,
+    stats: ChannelStatsCounters,
+    error_handler: ErrorHandler,
     waker: SocketStateHandle,
     internal_rpc: InternalRPCHandle,
     frames: Frames,
@@ -70,20 +107,99 @@ impl fmt::Debug for Channel {
             .field("connection_status", &self.connection_status)
             .field("acknowledgements", &self.acknowledgements)
             .field("delivery_tag", &self.delivery_tag)
+            .field("outstanding_deliveries", &self.outstanding_deliveries)
             .field("queues", &self.queues)
             .field("returned_messages", &self.returned_messages)
+            .field("declaration_cache", &self.declaration_cache)
+            .field("server_named_queues", &self.server_named_queues)
+            .field("stats", &self.stats)
+            .field("error_handler", &self.error_handler)
             .field("frames", &self.frames)
             .field("executor", &self.executor)
             .finish()
     }
 }
 
+fn ttl_arguments(ttl_ms: u64) -> Result<FieldTable> {
+    if ttl_ms == 0 {
+        return Err(Error::InvalidArgument(
+            "the message TTL must be greater than 0".into(),
+        ));
+    }
+    let mut arguments = FieldTable::default();
+    arguments.insert(
+        "x-message-ttl".into(),
+        long_long_argument("message TTL", ttl_ms)?,
+    );
+    Ok(arguments)
+}
+
+fn max_length_arguments(
+    argument: &str,
+    max_length: u64,
+    overflow: OverflowBehavior,
+) -> Result<FieldTable> {
+    let mut arguments = FieldTable::default();
+    arguments.insert(
+        argument.into(),
+        long_long_argument("maximum length", max_length)?,
+    );
+    arguments.insert(
+        "x-overflow".into(),
+        AMQPValue::LongString(overflow.as_str().into()),
+    );
+    Ok(arguments)
+}
+
+/// The `x-max-priority` argument of a queue declaration, if it's a valid priority.
+fn max_priority_argument(arguments: &FieldTable) -> Option<ShortShortUInt> {
+    let max_priority = match arguments.inner().get("x-max-priority")? {
+        AMQPValue::ShortShortUInt(value) => i64::from(*value),
+        AMQPValue::ShortUInt(value) => i64::from(*value),
+        AMQPValue::LongUInt(value) => i64::from(*value),
+        AMQPValue::ShortShortInt(value) => i64::from(*value),
+        AMQPValue::ShortInt(value) => i64::from(*value),
+        AMQPValue::LongInt(value) => i64::from(*value),
+        AMQPValue::LongLongInt(value) => *value,
+        _ => return None,
+    };
+    ShortShortUInt::try_from(max_priority).ok()
+}
+
+/// The `x-queue-type` of a queue, which clients send either as a long or as a short string.
+fn queue_type_argument(arguments: &FieldTable) -> Option<&str> {
+    match arguments.inner().get("x-queue-type")? {
+        AMQPValue::LongString(queue_type) => Some(queue_type.as_str()),
+        AMQPValue::ShortString(queue_type) => Some(queue_type.as_str()),
+        _ => None,
+    }
+}
+
+fn long_long_argument(what: &str, value: u64) -> Result<AMQPValue> {
+    i64::try_from(value)
+        .map(AMQPValue::LongLongInt)
+        .map_err(|_| Error::InvalidArgument(format!("the {} is too large: {}", what, value)))
+}
+
+/// The `x-stream-offset` consume argument positioning a stream consumer at `offset`.
+fn stream_offset_argument(offset: StreamOffset) -> Result<AMQPValue> {
+    Ok(match offset {
+        StreamOffset::First => AMQPValue::LongString("first".into()),
+        StreamOffset::Last => AMQPValue::LongString("last".into()),
+        StreamOffset::Next => AMQPValue::LongString("next".into()),
+        StreamOffset::Absolute(offset) => long_long_argument("stream offset", offset)?,
+        StreamOffset::Timestamp(time) => Timestamp::from(time).into(),
+    })
+}
+
 impl Channel {
     #[allow(clippy::too_many_arguments)]
     pub(crate) fn new(
         channel_id: u16,
         configuration: Configuration,
         connection_status: ConnectionStatus,
+        declaration_cache: DeclarationCache,
+        server_named_queues: ServerNamedQueues,
         waker: SocketStateHandle,
         internal_rpc: InternalRPCHandle,
         frames: Frames,
@@ -108,8 +224,14 @@ impl Channel {
             connection_status,
             acknowledgements: Acknowledgements::new(returned_messages.clone()),
             delivery_tag: IdSequence::new(false),
+            publishing: Arc::default(),
+            outstanding_deliveries: OutstandingDeliveries::default(),
             queues: Queues::default(),
             returned_messages,
+            declaration_cache,
+            server_named_queues,
+            stats: ChannelStatsCounters::default(),
+            error_handler: ErrorHandler::default(),
             waker,
             internal_rpc,
             frames,
@@ -123,24 +245,79 @@ impl Channel {
         &self.status
     }
 
+    /// Whether the channel is currently connected.
+    ///
+    /// This is only a snapshot, the channel may get closed right after this returned `true`.
+    pub fn is_open(&self) -> bool {
+        self.status.connected()
+    }
+
+    /// Whether methods can currently be sent on this channel: both the channel and its
+    /// connection are connected.
+    ///
+    /// When this is `false`, the methods fail right away, with the error which closed the
+    /// channel if any, see [`ChannelStatus::error_cause`].
+    ///
+    /// [`ChannelStatus::error_cause`]: ./struct.ChannelStatus.html#method.error_cause
+    pub fn is_usable(&self) -> bool {
+        self.status.connected() && self.connection_status.connected()
+    }
+
+    pub(crate) async fn create_internal_channel(&self) -> Result<Channel> {
+        let connection_closer = self
+            .connection_closer
+            .clone()
+            .ok_or(Error::InvalidChannel(self.id))?;
+        let (promise, resolver) = Promise::new();
+        if level_enabled!(Level::TRACE) {
+            promise.set_marker("CreateInternalChannel".into());
+        }
+        self.internal_rpc
+            .create_channel(connection_closer, resolver);
+        promise.await
+    }
+
     fn set_closed(&self, error: Error) {
+        self.status.set_error_cause(error.clone());
         self.set_state(ChannelState::Closed);
+        self.outstanding_deliveries.clear();
         self.error_publisher_confirms(error.clone());
         self.cancel_consumers();
         self.internal_rpc.remove_channel(self.id, error);
     }
 
     fn set_error(&self, error: Error) {
-        self.set_state(ChannelState::Error);
+        self.status.set_error_cause(error.clone());
+        let notify = self.set_errored();
+        self.outstanding_deliveries.clear();
         self.error_publisher_confirms(error.clone());
         self.error_consumers(error.clone());
-        self.internal_rpc.remove_channel(self.id, error);
+        self.internal_rpc.remove_channel(self.id, error.clone());
+        if notify {
+            self.error_handler.on_error(error);
+        }
+    }
+
+    /// Put the channel in the error state, returning whether the error handler has to be
+    /// notified, which is when it wasn't already in that state.
+    pub(crate) fn set_errored(&self) -> bool {
+        let notify = !matches!(self.status.state(), ChannelState::Error);
+        self.set_state(ChannelState::Error);
+        notify
+    }
+
+    pub(crate) fn on_error(&self, error: Error) {
+        self.error_handler.on_error(error);
     }
 
     pub(crate) fn error_publisher_confirms(&self, error: Error) {
         self.acknowledgements.on_channel_error(self.id, error);
     }
 
+    pub(crate) fn consumers(&self) -> Vec<Consumer> {
+        self.queues.consumers()
+    }
+
     pub(crate) fn cancel_consumers(&self) {
         self.queues.cancel_consumers();
     }
@@ -157,6 +334,45 @@ impl Channel {
         self.id
     }
 
+    /// Call `handler` as soon as the channel fails, with the error which made it fail, instead of
+    /// only finding out when awaiting the next method.
+    ///
+    /// The handler is shared by all the clones of the channel, setting another one replaces it.
+    /// It gets called from the io loop of the connection, it must not block: hand the error
+    /// over to another task if there is work to do with it.
+    pub fn with_error_handler<E: Fn(Error) + Send + Sync + 'static>(self, handler: E) -> Self {
+        self.error_handler.set_handler(handler);
+        self
+    }
+
+    /// The message operations performed on this channel so far.
+    pub fn stats(&self) -> ChannelStats {
+        self.stats.snapshot()
+    }
+
+    /// The message operations performed on this channel since `baseline` was taken from
+    /// [`stats`].
+    ///
+    /// [`stats`]: #method.stats
+    pub fn stats_since(&self, baseline: ChannelStats) -> ChannelStats {
+        self.stats().since(baseline)
+    }
+
+    /// Send the frames pending for this channel before the ones of the other channels.
+    ///
+    /// This only affects the frames which are already queued, such as a `basic.cancel` waiting
+    /// behind a large batch of publishes from other channels. The publishes of this channel
+    /// still wait for the server to let us publish, but go before the ones of the other channels.
+    pub fn prioritize_pending_frames(&self) {
+        self.frames.prioritize_channel(self.id);
+        self.wake();
+    }
+
+    #[cfg(any(test, feature = "test-support"))]
+    pub(crate) fn capture_frames(&self, capture: &Arc<Mutex<Vec<AMQPFrame>>>) {
+        self.frames.capture(self.id, capture);
+    }
+
     pub(crate) fn clone_internal(&self) -> Self {
         Self {
             id: self.id,
@@ -165,8 +381,14 @@ impl Channel {
             connection_status: self.connection_status.clone(),
             acknowledgements: self.acknowledgements.clone(),
             delivery_tag: self.delivery_tag.clone(),
+            publishing: self.publishing.clone(),
+            outstanding_deliveries: self.outstanding_deliveries.clone(),
             queues: self.queues.clone(),
             returned_messages: self.returned_messages.clone(),
+            declaration_cache: self.declaration_cache.clone(),
+            server_named_queues: self.server_named_queues.clone(),
+            stats: self.stats.clone(),
+            error_handler: self.error_handler.clone(),
             waker: self.waker.clone(),
             internal_rpc: self.internal_rpc.clone(),
             frames: self.frames.clone(),
@@ -177,7 +399,7 @@ impl Channel {
     }
 
     fn wake(&self) {
-        trace!("channel {} wake", self.id);
+        trace!(target: targets::CHANNEL, "channel {} wake", self.id);
         self.waker.wake()
     }
 
@@ -186,6 +408,7 @@ impl Channel {
             Ok(())
         } else {
             error!(
+                target: targets::CHANNEL,
                 "Got a connection frame on channel {}, closing connection",
                 self.id
             );
@@ -203,764 +426,5232 @@ impl Channel {
         }
     }
 
+    /// Close the channel with the given reply code and text.
+    ///
+    /// The close isn't caused by a failing method, so the class and method ids sent to the
+    /// server are 0. See [`close_normally`] for the usual shutdown.
+    ///
+    /// [`close_normally`]: #method.close_normally
     pub async fn close(&self, reply_code: ShortUInt, reply_text: &str) -> Result<()> {
-        self.do_channel_close(reply_code, reply_text, 0, 0).await
-    }
-
-    pub async fn exchange_declare(
-        &self,
-        exchange: &str,
-        kind: ExchangeKind,
-        options: ExchangeDeclareOptions,
-        arguments: FieldTable,
-    ) -> Result<()> {
-        self.do_exchange_declare(exchange, kind.kind(), options, arguments)
-            .await
-    }
-
-    pub async fn wait_for_confirms(&self) -> Result<Vec<BasicReturnMessage>> {
-        if self
-            .acknowledgements
-            .get_last_pending()
-            .await
-            .transpose()?
-            .is_some()
-        {
-            trace!("Waiting for pending confirms");
-        } else {
-            trace!("No confirms to wait for");
-        }
-        Ok(self.returned_messages.drain())
-    }
-
-    #[cfg(test)]
-    pub(crate) fn register_queue(&self, queue: QueueState) {
-        self.queues.register(queue);
-    }
-
-    pub(crate) fn send_method_frame(
-        &self,
-        method: AMQPClass,
-        resolver: PromiseResolver<()>,
-        expected_reply: Option<ExpectedReply>,
-    ) {
-        self.send_frame(AMQPFrame::Method(self.id, method), resolver, expected_reply);
-    }
-
-    pub(crate) fn send_frame(
-        &self,
-        frame: AMQPFrame,
-        resolver: PromiseResolver<()>,
-        expected_reply: Option<ExpectedReply>,
-    ) {
-        trace!("channel {} send_frame", self.id);
-        self.frames.push(self.id, frame, resolver, expected_reply);
-        self.wake();
-    }
-
-    async fn send_method_frame_with_body(
-        &self,
-        method: AMQPClass,
-        payload: Vec<u8>,
-        properties: BasicProperties,
-        publisher_confirms_result: Option<PublisherConfirm>,
-    ) -> Result<PublisherConfirm> {
-        let class_id = method.get_amqp_class_id();
-        let header = AMQPContentHeader {
-            class_id,
-            weight: 0,
-            body_size: payload.len() as u64,
-            properties,
-        };
-        let frame_max = self.configuration.frame_max();
-        let mut frames = vec![
-            AMQPFrame::Method(self.id, method),
-            AMQPFrame::Header(self.id, class_id, Box::new(header)),
-        ];
-
-        // a content body frame 8 bytes of overhead
-        frames.extend(
-            payload
-                .as_slice()
-                .chunks(frame_max as usize - 8)
-                .map(|chunk| AMQPFrame::Body(self.id, chunk.into())),
-        );
-
-        trace!("channel {} send_frames", self.id);
-        let promise = self.frames.push_frames(frames);
-        self.wake();
-        promise.await?;
-        Ok(publisher_confirms_result
-            .unwrap_or_else(|| PublisherConfirm::not_requested(self.returned_messages.clone())))
+        let span = channel_span!(self, "channel.close", reply_code, reply_text);
+        instrument(span, async move {
+            self.do_channel_close(reply_code, reply_text, 0, 0).await
+        })
+        .await
     }
 
-    fn handle_invalid_contents(&self, error: String, class_id: u16, method_id: u16) -> Result<()> {
-        error!("{}", error);
-        let error = AMQPError::new(AMQPHardError::UNEXPECTEDFRAME.into(), error.into());
-        self.internal_rpc.close_connection(
-            error.get_id(),
-            error.get_message().to_string(),
-            class_id,
-            method_id,
-        );
-        Err(Error::ProtocolError(error))
+    /// Close the channel with the `200` reply code and the "Normal shutdown" reply text.
+    pub async fn close_normally(&self) -> Result<()> {
+        self.close(
+            protocol::constants::REPLY_SUCCESS as ShortUInt,
+            "Normal shutdown",
+        )
+        .await
     }
 
-    pub(crate) fn handle_content_header_frame(
+    /// Close the channel once its consumers are done with their deliveries.
+    ///
+    /// All the consumers of the channel get canceled first, then the deliveries received on it
+    /// get to be acked, nacked or rejected, and the frames queued for it, such as these
+    /// acknowledgements or pending publishes, get written before the channel gets closed with
+    /// the given reply code and text. Waiting for the cancellations, for the deliveries and for
+    /// the frames stops after `timeout`, the closing goes on anyway.
+    pub async fn close_gracefully(
         &self,
-        class_id: u16,
-        size: u64,
-        properties: BasicProperties,
+        reply_code: ShortUInt,
+        reply_text: &str,
+        timeout: Duration,
     ) -> Result<()> {
-        self.status.set_content_length(
-            self.id,
-            class_id,
-            size as usize,
-            |queue_name, request_id_or_consumer_tag, confirm_mode| {
-                if let Some(queue_name) = queue_name {
-                    self.queues.handle_content_header_frame(
-                        &self,
-                        queue_name.as_str(),
-                        request_id_or_consumer_tag.clone(),
-                        size,
-                        properties,
-                    );
-                } else {
-                    self.returned_messages.set_delivery_properties(properties);
-                    if size == 0 {
-                        self.returned_messages.new_delivery_complete(confirm_mode);
-                    }
+        let deadline = Instant::now() + timeout;
+        let (consumers, canceled) = self.cancel_consumers_before(deadline).await;
+        if !canceled {
+            warn!(
+                target: targets::CHANNEL,
+                "consumers on channel {} didn't get canceled within {:?}, closing anyway",
+                self.id,
+                timeout
+            );
+        }
+        let outstanding_deliveries = self.outstanding_deliveries.clone();
+        let settled = futures_lite::future::or(
+            async {
+                for consumer in &consumers {
+                    consumer.join().await;
                 }
+                outstanding_deliveries.settled().await.is_ok()
             },
-            |msg| {
-                error!("{}", msg);
-                let error = AMQPError::new(AMQPHardError::FRAMEERROR.into(), msg.into());
-                self.internal_rpc.close_connection(
-                    error.get_id(),
-                    error.get_message().to_string(),
-                    class_id,
-                    0,
-                );
-                let error = Error::ProtocolError(error);
-                self.set_error(error.clone());
-                Err(error)
+            async {
+                Timer::at(deadline).await;
+                false
             },
-            |msg| self.handle_invalid_contents(msg, class_id, 0),
         )
+        .await;
+        if !settled {
+            warn!(
+                target: targets::CHANNEL,
+                "deliveries on channel {} didn't get settled within {:?}, closing anyway",
+                self.id,
+                timeout
+            );
+        }
+        self.sync_before(deadline).await;
+        self.close(reply_code, reply_text).await
     }
 
-    pub(crate) fn handle_body_frame(&self, payload: Vec<u8>) -> Result<()> {
-        self.status.receive(
-            self.id,
-            payload.len(),
-            |queue_name, request_id_or_consumer_tag, remaining_size, confirm_mode| {
-                if let Some(queue_name) = queue_name {
-                    self.queues.handle_body_frame(
-                        &self,
-                        queue_name.as_str(),
-                        request_id_or_consumer_tag.clone(),
-                        remaining_size,
-                        payload,
-                    );
-                } else {
-                    self.returned_messages.receive_delivery_content(payload);
-                    if remaining_size == 0 {
-                        self.returned_messages.new_delivery_complete(confirm_mode);
+    /// Cancel all the consumers of the channel, giving up once `deadline` is reached as the
+    /// server may never answer. Returns the consumers and whether they all got canceled in time.
+    pub(crate) async fn cancel_consumers_before(&self, deadline: Instant) -> (Vec<Consumer>, bool) {
+        let consumers = self.consumers();
+        let canceled = futures_lite::future::or(
+            async {
+                for consumer in &consumers {
+                    if let Err(err) = self
+                        .basic_cancel(consumer.tag().as_str(), BasicCancelOptions::default())
+                        .await
+                    {
+                        debug!(
+                            target: targets::CHANNEL,
+                            "failed to cancel consumer {} on channel {}: {}",
+                            consumer.tag(),
+                            self.id,
+                            err
+                        );
                     }
                 }
+                true
+            },
+            async {
+                Timer::at(deadline).await;
+                false
             },
-            |msg| self.handle_invalid_contents(msg, 0, 0),
         )
+        .await;
+        (consumers, canceled)
     }
 
-    fn before_basic_publish(&self) -> Option<PublisherConfirm> {
-        if self.status.confirm() {
-            let delivery_tag = self.delivery_tag.next();
-            Some(
-                self.acknowledgements
-                    .register_pending(delivery_tag, self.id),
-            )
-        } else {
+    /// Wait for the frames queued for this channel to be written, see [`sync`], giving up once
+    /// `deadline` is reached.
+    ///
+    /// [`sync`]: #method.sync
+    pub(crate) async fn sync_before(&self, deadline: Instant) {
+        if !self.status.connected() {
+            return;
+        }
+        let synced = futures_lite::future::or(async { Some(self.sync().await) }, async {
+            Timer::at(deadline).await;
             None
+        })
+        .await;
+        if let Some(Err(err)) = synced {
+            debug!(
+                target: targets::CHANNEL,
+                "channel {} failed to flush its frames: {}",
+                self.id,
+                err
+            );
         }
     }
 
-    fn acknowledgement_error(&self, error: AMQPError, class_id: u16, method_id: u16) -> Result<()> {
-        error!("Got a bad acknowledgement from server, closing channel");
+    /// Send `channel.close` in the background, the returned promise resolves once the server
+    /// acknowledged it.
+    pub(crate) fn start_closing(&self, reply_code: ShortUInt, reply_text: &str) -> Promise<()> {
+        let (promise, resolver) = Promise::new();
+        if level_enabled!(Level::TRACE) {
+            promise.set_marker(format!("channel.{}.close", self.id));
+        }
         let channel = self.clone();
-        let err = error.clone();
+        let reply_text = reply_text.to_string();
         self.internal_rpc.register_internal_future(async move {
-            channel
-                .do_channel_close(
-                    error.get_id(),
-                    error.get_message().as_str(),
-                    class_id,
-                    method_id,
-                )
-                .await
+            resolver.swear(channel.close(reply_code, &reply_text).await);
+            Ok(())
         });
-        Err(Error::ProtocolError(err))
+        promise
     }
 
-    fn on_connection_start_ok_sent(
-        &self,
-        resolver: PromiseResolver<Connection>,
-        connection: Connection,
-        credentials: Credentials,
-    ) {
-        self.connection_status
-            .set_connection_step(ConnectionStep::StartOk(resolver, connection, credentials));
+    /// Open this freshly created channel, passively declare the `amq.direct` exchange on it and
+    /// close it again, in the background. The returned promise resolves with the round trip
+    /// duration once the channel got closed, but the channel gets closed even if nobody waits for
+    /// it anymore.
+    pub(crate) fn start_ping(&self) -> Promise<Duration> {
+        let (promise, resolver) = Promise::new();
+        if level_enabled!(Level::TRACE) {
+            promise.set_marker(format!("channel.{}.ping", self.id));
+        }
+        let channel = self.clone();
+        let start = Instant::now();
+        self.internal_rpc.register_internal_future(async move {
+            let res = match channel.clone().channel_open(channel.clone()).await {
+                Ok(_) => channel
+                    .exchange_declare(
+                        "amq.direct",
+                        ExchangeKind::Direct,
+                        ExchangeDeclareOptions::default().passive(true),
+                        FieldTable::default(),
+                    )
+                    .await
+                    .map(|()| start.elapsed()),
+                Err(err) => Err(err),
+            };
+            if channel.status.connected() {
+                let _ = channel
+                    .close(protocol::constants::REPLY_SUCCESS as ShortUInt, "OK")
+                    .await;
+            }
+            resolver.swear(res);
+            Ok(())
+        });
+        promise
     }
 
-    fn on_connection_open_sent(&self, resolver: PromiseResolver<Connection>) {
-        self.connection_status
-            .set_connection_step(ConnectionStep::Open(resolver));
+    /// Consider the channel closed without waiting for the server to acknowledge it.
+    pub(crate) fn force_closed(&self) {
+        self.set_state(ChannelState::Closed);
+        self.outstanding_deliveries.clear();
+        self.error_publisher_confirms(Error::InvalidChannelState(ChannelState::Closed));
+        self.cancel_consumers();
     }
 
-    fn on_connection_close_sent(&self) {
-        self.internal_rpc.set_connection_closing();
+    pub async fn exchange_declare(
+        &self,
+        exchange: &str,
+        kind: ExchangeKind,
+        options: ExchangeDeclareOptions,
+        arguments: FieldTable,
+    ) -> Result<()> {
+        let span = channel_span!(
+            self,
+            "channel.exchange_declare",
+            exchange,
+            kind = ?kind,
+            passive = options.passive,
+            durable = options.durable
+        );
+        instrument(span, async move {
+            let kind = kind.kind();
+            if self
+                .declaration_cache
+                .has_exchange(exchange, kind, &options, &arguments)
+            {
+                return self.skip_cached_declaration("exchange", exchange);
+            }
+            self.do_exchange_declare(exchange, kind, options, arguments.clone())
+                .await?;
+            self.declaration_cache
+                .register_exchange(exchange, kind, options, arguments);
+            Ok(())
+        })
+        .await
     }
 
-    fn on_connection_close_ok_sent(&self, error: Error) {
-        if let Error::ProtocolError(_) = error {
-            self.internal_rpc.set_connection_error(error);
-        } else {
-            self.internal_rpc.set_connection_closed(error);
-        }
+    pub async fn queue_declare(
+        &self,
+        queue: &str,
+        options: QueueDeclareOptions,
+        arguments: FieldTable,
+    ) -> Result<Queue> {
+        let span = channel_span!(
+            self,
+            "channel.queue_declare",
+            queue,
+            passive = options.passive,
+            durable = options.durable,
+            exclusive = options.exclusive,
+            auto_delete = options.auto_delete
+        );
+        instrument(span, async move {
+            if let Some(cached) = self
+                .declaration_cache
+                .get_queue(queue, &options, &arguments)
+            {
+                self.skip_cached_declaration("queue", queue)?;
+                self.queues.register(cached.clone().into());
+                self.remember_declared_queue(&cached, &arguments);
+                return Ok(cached);
+            }
+            let mut declared = self
+                .do_queue_declare(queue, options, arguments.clone())
+                .await?;
+            if queue.is_empty() {
+                declared.set_server_named();
+                if !options.passive {
+                    self.server_named_queues.register(
+                        declared.name().clone(),
+                        options,
+                        arguments.clone(),
+                    );
+                }
+            }
+            self.remember_declared_queue(&declared, &arguments);
+            self.declaration_cache
+                .register_queue(&declared, options, arguments);
+            Ok(declared)
+        })
+        .await
     }
 
-    fn before_channel_close(&self) {
-        self.set_state(ChannelState::Closing);
-    }
-
-    fn on_channel_close_ok_sent(&self, error: Error) {
-        self.set_closed(error);
-    }
-
-    fn on_basic_recover_async_sent(&self) {
-        self.queues.drop_prefetched_messages();
-    }
-
-    fn on_basic_ack_sent(&self, multiple: bool, delivery_tag: DeliveryTag) {
-        if multiple && delivery_tag == 0 {
-            self.queues.drop_prefetched_messages();
+    fn remember_declared_queue(&self, queue: &Queue, arguments: &FieldTable) {
+        self.status.set_last_declared_queue(queue.name().clone());
+        if let Some(max_priority) = max_priority_argument(arguments) {
+            self.status
+                .set_max_priority(queue.name().clone(), max_priority);
         }
-    }
-
-    fn on_basic_nack_sent(&self, multiple: bool, delivery_tag: DeliveryTag) {
-        if multiple && delivery_tag == 0 {
-            self.queues.drop_prefetched_messages();
+        if let Some(queue_type) = queue_type_argument(arguments) {
+            self.status
+                .set_queue_type(queue.name().clone(), queue_type.into());
         }
     }
 
-    fn tune_connection_configuration(&self, channel_max: u16, frame_max: u32, heartbeat: u16) {
-        // If we disable the heartbeat (0) but the server don't, follow it and enable it too
-        // If both us and the server want heartbeat enabled, pick the lowest value.
-        if self.configuration.heartbeat() == 0
-            || (heartbeat != 0 && heartbeat < self.configuration.heartbeat())
-        {
-            self.configuration.set_heartbeat(heartbeat);
-        }
-
-        if channel_max != 0 {
-            // 0 means we want to take the server's value
-            // If both us and the server specified a channel_max, pick the lowest value.
-            if self.configuration.channel_max() == 0
-                || channel_max < self.configuration.channel_max()
-            {
-                self.configuration.set_channel_max(channel_max);
-            }
-        }
-        if self.configuration.channel_max() == 0 {
-            self.configuration.set_channel_max(u16::max_value());
+    /// The name to send for `queue`: the last queue declared on this channel when `queue` is
+    /// empty, which is what the server would use, so that the actual name shows up in the logs.
+    fn queue_or_last_declared<'a>(&self, queue: &'a str) -> Result<Cow<'a, str>> {
+        if !queue.is_empty() {
+            return Ok(Cow::Borrowed(queue));
         }
+        let last_declared = self
+            .status
+            .last_declared_queue()
+            .ok_or(Error::NoPreviouslyDeclaredQueue)?;
+        trace!(
+            target: targets::CHANNEL,
+            "channel {} using the last declared queue {} for an empty queue name",
+            self.id,
+            last_declared
+        );
+        Ok(Cow::Owned(last_declared.to_string()))
+    }
 
-        if frame_max != 0 {
-            // 0 means we want to take the server's value
-            // If both us and the server specified a frame_max, pick the lowest value.
-            if self.configuration.frame_max() == 0 || frame_max < self.configuration.frame_max() {
-                self.configuration.set_frame_max(frame_max);
+    /// Declare again the server-named queues declared on this connection, along with their
+    /// bindings.
+    ///
+    /// The queues are remembered by the connection rather than by the channel which declared
+    /// them, so that a channel replacing a failed one can declare them again.
+    ///
+    /// The server gives them new names, which get substituted to the previous ones in their
+    /// bindings and in the subscriptions of this channel's consumers consuming from them, so that
+    /// [`Consumer::resubscribe`] consumes from the new queue. This is useful to rebuild the
+    /// exclusive queues which got lost, for example when the server deleted them.
+    ///
+    /// The declared queues are returned in the order they were first declared.
+    ///
+    /// [`Consumer::resubscribe`]: ./struct.Consumer.html#method.resubscribe
+    pub async fn redeclare_server_named_queues(&self) -> Result<Vec<Queue>> {
+        let mut redeclared = Vec::new();
+        for declaration in self.server_named_queues.declarations() {
+            let mut queue = self
+                .do_queue_declare("", declaration.options, declaration.arguments.clone())
+                .await?;
+            queue.set_server_named();
+            trace!(
+                target: targets::CHANNEL,
+                "channel {} redeclared server-named queue {} as {}",
+                self.id,
+                declaration.name,
+                queue.name()
+            );
+            self.server_named_queues
+                .rename(declaration.name.as_str(), queue.name().clone());
+            self.queues
+                .rename(declaration.name.as_str(), queue.name().clone());
+            for binding in declaration.bindings {
+                self.do_queue_bind(
+                    queue.name().as_str(),
+                    binding.exchange.as_str(),
+                    binding.routing_key.as_str(),
+                    binding.options,
+                    binding.arguments,
+                )
+                .await?;
             }
+            redeclared.push(queue);
         }
-        if self.configuration.frame_max() == 0 {
-            self.configuration.set_frame_max(u32::max_value());
-        }
+        Ok(redeclared)
     }
 
-    fn on_connection_start_received(&self, method: protocol::connection::Start) -> Result<()> {
-        trace!("Server sent connection::Start: {:?}", method);
-        let state = self.connection_status.state();
-        if let (
-            ConnectionState::Connecting,
-            Some(ConnectionStep::ProtocolHeader(
-                resolver,
-                connection,
-                credentials,
-                mechanism,
-                mut options,
-            )),
-        ) = (state.clone(), self.connection_status.connection_step())
-        {
-            let mechanism_str = mechanism.to_string();
-            let locale = options.locale.clone();
-
-            if !method
-                .mechanisms
-                .split_whitespace()
-                .any(|m| m == mechanism_str)
-            {
-                error!("unsupported mechanism: {}", mechanism);
-            }
-            if !method.locales.split_whitespace().any(|l| l == locale) {
-                error!("unsupported locale: {}", mechanism);
-            }
-
-            if !options.client_properties.contains_key("product")
-                || !options.client_properties.contains_key("version")
+    /// Bind `queue`, or the last queue declared on this channel if `queue` is empty, to
+    /// `exchange`.
+    ///
+    /// An empty `queue` fails with [`Error::NoPreviouslyDeclaredQueue`] if no queue was declared
+    /// on this channel yet.
+    ///
+    /// [`Error::NoPreviouslyDeclaredQueue`]: ./enum.Error.html#variant.NoPreviouslyDeclaredQueue
+    pub async fn queue_bind(
+        &self,
+        queue: &str,
+        exchange: &str,
+        routing_key: &str,
+        options: QueueBindOptions,
+        arguments: FieldTable,
+    ) -> Result<()> {
+        let queue = self.queue_or_last_declared(queue)?;
+        let queue = queue.as_ref();
+        let span = channel_span!(self, "channel.queue_bind", queue, exchange, routing_key);
+        instrument(span, async move {
+            if self
+                .declaration_cache
+                .has_binding(queue, exchange, routing_key, &arguments)
             {
-                options.client_properties.insert(
-                    "product".into(),
-                    AMQPValue::LongString(env!("CARGO_PKG_NAME").into()),
-                );
-                options.client_properties.insert(
-                    "version".into(),
-                    AMQPValue::LongString(env!("CARGO_PKG_VERSION").into()),
-                );
+                return self.skip_cached_declaration("binding for queue", queue);
             }
-
-            options
-                .client_properties
-                .insert("platform".into(), AMQPValue::LongString("rust".into()));
-
-            let mut capabilities = FieldTable::default();
-            capabilities.insert("publisher_confirms".into(), true.into());
-            capabilities.insert("exchange_exchange_bindings".into(), true.into());
-            capabilities.insert("basic.nack".into(), true.into());
-            capabilities.insert("consumer_cancel_notify".into(), true.into());
-            capabilities.insert("connection.blocked".into(), true.into());
-            capabilities.insert("consumer_priorities".into(), true.into());
-            capabilities.insert("authentication_failure_close".into(), true.into());
-            capabilities.insert("per_consumer_qos".into(), true.into());
-            capabilities.insert("direct_reply_to".into(), true.into());
-
-            options
-                .client_properties
-                .insert("capabilities".into(), AMQPValue::FieldTable(capabilities));
-
-            let channel = self.clone();
-            self.internal_rpc.register_internal_future(async move {
-                channel
-                    .connection_start_ok(
-                        options.client_properties,
-                        &mechanism_str,
-                        &credentials.sasl_auth_string(mechanism),
-                        &locale,
-                        resolver,
-                        connection,
-                        credentials,
-                    )
-                    .await
-            });
+            self.do_queue_bind(queue, exchange, routing_key, options, arguments.clone())
+                .await?;
+            self.server_named_queues.register_binding(
+                queue,
+                exchange,
+                routing_key,
+                options,
+                arguments.clone(),
+            );
+            self.declaration_cache
+                .register_binding(queue, exchange, routing_key, arguments);
             Ok(())
-        } else {
-            error!("Invalid state: {:?}", state);
-            let error = Error::InvalidConnectionState(state);
-            self.internal_rpc.set_connection_error(error.clone());
-            Err(error)
-        }
+        })
+        .await
     }
 
-    fn on_connection_secure_received(&self, method: protocol::connection::Secure) -> Result<()> {
-        trace!("Server sent connection::Secure: {:?}", method);
-
-        let state = self.connection_status.state();
-        if let (ConnectionState::Connecting, Some(ConnectionStep::StartOk(.., credentials))) =
-            (state.clone(), self.connection_status.connection_step())
-        {
-            let channel = self.clone();
-            self.internal_rpc.register_internal_future(async move {
-                channel
-                    .connection_secure_ok(&credentials.rabbit_cr_demo_answer())
-                    .await
-            });
-            Ok(())
-        } else {
-            error!("Invalid state: {:?}", state);
-            let error = Error::InvalidConnectionState(state);
-            self.internal_rpc.set_connection_error(error.clone());
-            Err(error)
-        }
+    /// Purge `queue`, or the last queue declared on this channel if `queue` is empty, like
+    /// [`queue_bind`] does.
+    ///
+    /// [`queue_bind`]: #method.queue_bind
+    pub async fn queue_purge(&self, queue: &str, options: QueuePurgeOptions) -> Result<LongUInt> {
+        let queue = self.queue_or_last_declared(queue)?;
+        let queue = queue.as_ref();
+        let span = channel_span!(self, "channel.queue_purge", queue);
+        instrument(span, self.do_queue_purge(queue, options)).await
     }
 
-    fn on_connection_tune_received(&self, method: protocol::connection::Tune) -> Result<()> {
-        debug!("Server sent Connection::Tune: {:?}", method);
-
-        let state = self.connection_status.state();
-        if let (
-            ConnectionState::Connecting,
-            Some(ConnectionStep::StartOk(resolver, connection, _)),
-        ) = (state.clone(), self.connection_status.connection_step())
-        {
-            self.tune_connection_configuration(
-                method.channel_max,
-                method.frame_max,
-                method.heartbeat,
-            );
-
-            let channel = self.clone();
-            let configuration = self.configuration.clone();
-            self.internal_rpc.register_internal_future(async move {
-                channel
-                    .connection_tune_ok(
-                        configuration.channel_max(),
-                        configuration.frame_max(),
-                        configuration.heartbeat(),
-                    )
-                    .await
-            });
-            let channel = self.clone();
-            let vhost = self.connection_status.vhost();
-            self.internal_rpc.register_internal_future(async move {
-                channel.connection_open(&vhost, connection, resolver).await
-            });
-            Ok(())
-        } else {
-            error!("Invalid state: {:?}", state);
-            let error = Error::InvalidConnectionState(state);
-            self.internal_rpc.set_connection_error(error.clone());
-            Err(error)
-        }
+    /// Delete `queue`, or the last queue declared on this channel if `queue` is empty, like
+    /// [`queue_bind`] does.
+    ///
+    /// [`queue_bind`]: #method.queue_bind
+    pub async fn queue_delete(&self, queue: &str, options: QueueDeleteOptions) -> Result<LongUInt> {
+        let queue = self.queue_or_last_declared(queue)?;
+        let queue = queue.as_ref();
+        let span = channel_span!(self, "channel.queue_delete", queue);
+        instrument(span, self.do_queue_delete(queue, options)).await
     }
 
-    fn on_connection_open_ok_received(
+    /// Declare a queue whose messages expire after `ttl_ms` milliseconds, using the
+    /// `x-message-ttl` argument.
+    pub async fn declare_ttl_queue(
         &self,
-        _: protocol::connection::OpenOk,
-        connection: Connection,
+        name: &str,
+        ttl_ms: u64,
+        options: QueueDeclareOptions,
     ) -> Result<()> {
-        let state = self.connection_status.state();
-        if let (ConnectionState::Connecting, Some(ConnectionStep::Open(resolver))) =
-            (state.clone(), self.connection_status.connection_step())
-        {
-            self.connection_status.set_state(ConnectionState::Connected);
-            resolver.swear(Ok(connection));
-            Ok(())
-        } else {
-            error!("Invalid state: {:?}", state);
-            let error = Error::InvalidConnectionState(state);
-            self.internal_rpc.set_connection_error(error.clone());
-            Err(error)
-        }
+        let arguments = ttl_arguments(ttl_ms)?;
+        self.queue_declare(name, options, arguments).await?;
+        Ok(())
     }
 
-    fn on_connection_close_received(&self, method: protocol::connection::Close) -> Result<()> {
-        let error = AMQPError::try_from(method.clone())
-            .map(|error| {
-                error!(
-                    "Connection closed on channel {} by {}:{} => {:?} => {}",
-                    self.id, method.class_id, method.method_id, error, method.reply_text
-                );
-                Error::ProtocolError(error)
-            })
-            .unwrap_or_else(|error| {
-                error!("{}", error);
-                info!("Connection closed on channel {}: {:?}", self.id, method);
-                Error::InvalidConnectionState(ConnectionState::Closed)
-            });
-        self.internal_rpc.set_connection_closing();
-        self.frames.drop_pending(error.clone());
-        if let Some(resolver) = self.connection_status.connection_resolver() {
-            resolver.swear(Err(error.clone()));
+    /// Declare a queue whose messages expire after `ttl_ms` milliseconds and then get
+    /// dead-lettered to the `dlx` exchange.
+    ///
+    /// If `dlx_routing_key` is empty, the expired messages keep their original routing key.
+    pub async fn declare_ttl_queue_with_dlx(
+        &self,
+        name: &str,
+        ttl_ms: u64,
+        dlx: &str,
+        dlx_routing_key: &str,
+        options: QueueDeclareOptions,
+    ) -> Result<()> {
+        let mut arguments = ttl_arguments(ttl_ms)?;
+        arguments.insert(
+            "x-dead-letter-exchange".into(),
+            AMQPValue::LongString(dlx.into()),
+        );
+        if !dlx_routing_key.is_empty() {
+            arguments.insert(
+                "x-dead-letter-routing-key".into(),
+                AMQPValue::LongString(dlx_routing_key.into()),
+            );
         }
-        self.internal_rpc.send_connection_close_ok(error);
+        self.queue_declare(name, options, arguments).await?;
         Ok(())
     }
 
-    fn on_connection_blocked_received(&self, _method: protocol::connection::Blocked) -> Result<()> {
-        self.connection_status.block();
+    /// Declare a queue holding at most `max_messages` ready messages, using the `x-max-length`
+    /// argument, and behaving as told by `overflow` when full.
+    pub async fn declare_max_length_queue(
+        &self,
+        name: &str,
+        max_messages: u64,
+        overflow: OverflowBehavior,
+        options: QueueDeclareOptions,
+    ) -> Result<()> {
+        let arguments = max_length_arguments("x-max-length", max_messages, overflow)?;
+        self.queue_declare(name, options, arguments).await?;
         Ok(())
     }
 
-    fn on_connection_unblocked_received(
+    /// Declare a queue holding at most `max_bytes` bytes of ready messages bodies, using the
+    /// `x-max-length-bytes` argument, and behaving as told by `overflow` when full.
+    pub async fn declare_max_length_bytes_queue(
         &self,
-        _method: protocol::connection::Unblocked,
+        name: &str,
+        max_bytes: u64,
+        overflow: OverflowBehavior,
+        options: QueueDeclareOptions,
     ) -> Result<()> {
-        self.connection_status.unblock();
-        self.wake();
+        let arguments = max_length_arguments("x-max-length-bytes", max_bytes, overflow)?;
+        self.queue_declare(name, options, arguments).await?;
         Ok(())
     }
 
-    fn on_connection_close_ok_received(&self) -> Result<()> {
-        self.internal_rpc
-            .set_connection_closed(Error::InvalidConnectionState(ConnectionState::Closed));
+    /// Declare a priority queue supporting priorities up to `max_priority`, using the
+    /// `x-max-priority` argument.
+    ///
+    /// The messages published with a higher priority are handled as if they had the maximum
+    /// priority, see [`PriorityValidation`] to catch them.
+    ///
+    /// [`PriorityValidation`]: ./enum.PriorityValidation.html
+    pub async fn declare_priority_queue(
+        &self,
+        name: &str,
+        max_priority: ShortShortUInt,
+        options: QueueDeclareOptions,
+    ) -> Result<()> {
+        if max_priority == 0 {
+            return Err(Error::InvalidArgument(
+                "the maximum priority must be between 1 and 255".into(),
+            ));
+        }
+        let mut arguments = FieldTable::default();
+        arguments.insert(
+            "x-max-priority".into(),
+            AMQPValue::ShortShortUInt(max_priority),
+        );
+        self.queue_declare(name, options, arguments).await?;
         Ok(())
     }
 
-    fn on_channel_open_ok_received(
+    /// Bind a queue to a headers exchange.
+    ///
+    /// Messages get routed to the queue when all of the given headers match theirs if `match_all`
+    /// is set, or when any of them does otherwise.
+    pub async fn queue_bind_headers<I: IntoIterator<Item = (String, AMQPValue)>>(
         &self,
-        _method: protocol::channel::OpenOk,
-        resolver: PromiseResolver<Channel>,
-        channel: Channel,
+        queue: &str,
+        exchange: &str,
+        match_all: bool,
+        headers: I,
     ) -> Result<()> {
-        self.set_state(ChannelState::Connected);
-        resolver.swear(Ok(channel));
-        Ok(())
+        let mut arguments = FieldTable::default();
+        arguments.insert(
+            "x-match".into(),
+            AMQPValue::LongString(if match_all { "all" } else { "any" }.into()),
+        );
+        for (header, value) in headers {
+            arguments.insert(header.into(), value);
+        }
+        self.queue_bind(queue, exchange, "", QueueBindOptions::default(), arguments)
+            .await
     }
 
-    fn on_channel_flow_received(&self, method: protocol::channel::Flow) -> Result<()> {
-        self.status.set_send_flow(method.active);
-        let channel = self.clone();
-        self.internal_rpc.register_internal_future(async move {
-            channel
-                .channel_flow_ok(ChannelFlowOkOptions {
-                    active: method.active,
-                })
-                .await
-        });
+    /// Declare the `primary` exchange with `alternate` as its alternate exchange, using the
+    /// `alternate-exchange` argument, declaring `alternate` first.
+    ///
+    /// The messages which can't be routed from `primary` then get published to `alternate`,
+    /// which is usually a fanout exchange bound to a catch-all queue. Both exchanges are declared
+    /// with `options`.
+    pub async fn declare_alternate_exchange(
+        &self,
+        primary: &str,
+        primary_kind: ExchangeKind,
+        alternate: &str,
+        alternate_kind: ExchangeKind,
+        options: ExchangeDeclareOptions,
+    ) -> Result<()> {
+        self.declare_exchange_with_alternate(
+            ExchangeDeclaration::new(primary, primary_kind).with_options(options),
+            &ExchangeDeclaration::new(alternate, alternate_kind).with_options(options),
+            None,
+        )
+        .await?;
         Ok(())
     }
 
-    fn on_channel_flow_ok_received(
+    /// Declare `alternate`, bind `audit_queue` to it if any, then declare `exchange` with the
+    /// `alternate-exchange` argument.
+    async fn declare_exchange_with_alternate(
         &self,
-        method: protocol::channel::FlowOk,
-        resolver: PromiseResolver<Boolean>,
-    ) -> Result<()> {
-        // Nothing to do here, the server just confirmed that we paused/resumed the receiving flow
-        resolver.swear(Ok(method.active));
+        mut exchange: ExchangeDeclaration,
+        alternate: &ExchangeDeclaration,
+        audit_queue: Option<&Queue>,
+    ) -> Result<ExchangeDeclaration> {
+        alternate.apply(self).await?;
+        if let Some(audit_queue) = audit_queue {
+            self.queue_bind(
+                audit_queue.name().as_str(),
+                &alternate.name,
+                "#",
+                QueueBindOptions::default(),
+                FieldTable::default(),
+            )
+            .await?;
+        }
+        exchange.arguments.insert(
+            "alternate-exchange".into(),
+            AMQPValue::LongString(alternate.name.as_str().into()),
+        );
+        exchange.apply(self).await?;
+        Ok(exchange)
+    }
+
+    /// Declare `exchange` with `alternate` as its alternate exchange, along with `audit_queue`
+    /// bound to `alternate` to collect the messages which can't be routed from `exchange`.
+    ///
+    /// `alternate` is usually a fanout exchange. The audit queue is bound with the `#` routing
+    /// key, so that a topic exchange forwards it everything too. It is remembered for the whole
+    /// connection, so that [`consume_unroutable`] finds it, even when it's server-named.
+    ///
+    /// [`consume_unroutable`]: #method.consume_unroutable
+    pub async fn declare_with_alternate(
+        &self,
+        exchange: ExchangeDeclaration,
+        alternate: ExchangeDeclaration,
+        audit_queue: QueueDeclaration,
+    ) -> Result<AlternateExchange> {
+        let audit_queue = audit_queue.apply(self).await?;
+        let exchange = self
+            .declare_exchange_with_alternate(exchange, &alternate, Some(&audit_queue))
+            .await?;
+        self.declaration_cache
+            .register_audit_queue(&exchange.name, audit_queue.name().as_str());
+        Ok(AlternateExchange {
+            exchange,
+            alternate,
+            audit_queue,
+        })
+    }
+
+    /// Consume the messages which couldn't be routed from `exchange`.
+    ///
+    /// This uses the audit queue given to [`declare_with_alternate`] for `exchange` on this
+    /// connection, and fails with [`Error::InvalidArgument`] if there is none: this never
+    /// declares anything by itself.
+    ///
+    /// [`declare_with_alternate`]: #method.declare_with_alternate
+    /// [`Error::InvalidArgument`]: ./enum.Error.html#variant.InvalidArgument
+    pub async fn consume_unroutable(&self, exchange: &str) -> Result<Consumer> {
+        let audit_queue = self.declaration_cache.audit_queue(exchange).ok_or_else(|| {
+            Error::InvalidArgument(format!(
+                "no audit queue was declared for exchange {} with declare_with_alternate on this connection",
+                exchange
+            ))
+        })?;
+        self.basic_consume(
+            audit_queue.as_str(),
+            "",
+            BasicConsumeOptions::default(),
+            FieldTable::default(),
+        )
+        .await
+    }
+
+    fn skip_cached_declaration(&self, kind: &str, name: &str) -> Result<()> {
+        if !self.status.connected() {
+            return Err(self.status.state_error());
+        }
+        trace!(
+            target: targets::CHANNEL,
+            "channel {} skipping declaration of {} {}, already declared on this connection",
+            self.id,
+            kind,
+            name
+        );
         Ok(())
     }
 
-    fn on_channel_close_received(&self, method: protocol::channel::Close) -> Result<()> {
-        let error = AMQPError::try_from(method.clone())
-            .map(|error| {
-                error!(
-                    "Channel closed on channel {} by {}:{} => {:?} => {}",
-                    self.id, method.class_id, method.method_id, error, method.reply_text
-                );
-                Error::ProtocolError(error)
-            })
-            .unwrap_or_else(|error| {
-                error!("{}", error);
-                info!("Channel closed on channel {}: {:?}", self.id, method);
-                Error::InvalidChannelState(ChannelState::Closing)
-            });
-        self.set_state(ChannelState::Closing);
-        let channel = self.clone();
-        self.internal_rpc
-            .register_internal_future(async move { channel.channel_close_ok(error).await });
-        Ok(())
-    }
+    /// Nack all the deliveries received on this channel which haven't been acked, nacked or
+    /// rejected yet, using a single `basic.nack` with the `multiple` flag.
+    ///
+    /// This is a no-op if there is no outstanding delivery.
+    pub async fn nack_all_outstanding(&self, requeue: bool) -> Result<()> {
+        if !self.status.connected() {
+            return Err(self.status.state_error());
+        }
 
-    fn on_channel_close_ok_received(&self) -> Result<()> {
-        self.set_closed(Error::InvalidChannelState(ChannelState::Closed));
-        Ok(())
+        if let Some(delivery_tag) = self.outstanding_deliveries.last() {
+            trace!(
+                target: targets::CHANNEL,
+                "channel {} nacking all deliveries up to {}",
+                self.id,
+                delivery_tag
+            );
+            self.basic_nack(
+                delivery_tag,
+                BasicNackOptions::default().multiple(true).requeue(requeue),
+            )
+            .await
+        } else {
+            trace!(
+                target: targets::CHANNEL,
+                "channel {} has no outstanding delivery to nack",
+                self.id
+            );
+            Ok(())
+        }
     }
 
-    fn on_queue_delete_ok_received(
+    /// Publish a copy of `delivery` to `exchange` with `routing_key`, then nack it without
+    /// requeueing it, as an alternative to dead-lettering.
+    ///
+    /// The payload and properties of the delivery are kept. The copy is published with the
+    /// `mandatory` flag, like with [`basic_publish_mandatory`], and this fails with
+    /// [`Error::PublishReturned`] if the server couldn't route it. When publisher confirms are
+    /// enabled on this channel, this waits for the copy to be confirmed and fails with
+    /// [`Error::PublishNacked`] if the server nacks it. The delivery is only nacked once the copy
+    /// got published, so that it doesn't get lost: if the nack fails, the server redelivers it
+    /// and it may get republished twice.
+    ///
+    /// [`basic_publish_mandatory`]: #method.basic_publish_mandatory
+    /// [`Error::PublishReturned`]: ./enum.Error.html#variant.PublishReturned
+    /// [`Error::PublishNacked`]: ./enum.Error.html#variant.PublishNacked
+    pub async fn basic_nack_and_republish(
         &self,
-        method: protocol::queue::DeleteOk,
-        resolver: PromiseResolver<LongUInt>,
-        queue: ShortString,
+        delivery: &Delivery,
+        exchange: &str,
+        routing_key: &str,
     ) -> Result<()> {
-        self.queues.deregister(queue.as_str());
-        resolver.swear(Ok(method.message_count));
-        Ok(())
+        let options = BasicPublishOptions::default().mandatory(true);
+        let payload = delivery.data().to_vec();
+        let properties = delivery.properties.clone();
+        let returned = if self.status.confirm() {
+            let confirmation = self
+                .basic_publish(exchange, routing_key, options, payload, properties)
+                .await?
+                .await?;
+            if confirmation.is_nack() {
+                return Err(Error::PublishNacked);
+            }
+            confirmation.take_message()
+        } else {
+            match self
+                .basic_publish_mandatory(exchange, routing_key, options, payload, properties)
+                .await?
+            {
+                MandatoryOutcome::Routed => None,
+                MandatoryOutcome::Returned(message) => Some(message),
+            }
+        };
+        if let Some(message) = returned {
+            // The copy was dropped, keep the delivery
+            return Err(Error::PublishReturned {
+                reply_code: message.reply_code,
+                reply_text: message.reply_text.to_string(),
+            });
+        }
+        self.basic_nack(
+            delivery.delivery_tag,
+            BasicNackOptions::default().requeue(false),
+        )
+        .await
     }
 
-    fn on_queue_purge_ok_received(
-        &self,
-        method: protocol::queue::PurgeOk,
-        resolver: PromiseResolver<LongUInt>,
-    ) -> Result<()> {
-        resolver.swear(Ok(method.message_count));
-        Ok(())
+    /// Register a callback which will be called with the delivery tag of each message nacked by
+    /// the server when publisher confirms are enabled.
+    ///
+    /// The callback is called in addition to the resolution of the corresponding
+    /// [`PublisherConfirm`], and replaces any previously registered one.
+    ///
+    /// [`PublisherConfirm`]: ./publisher_confirm/struct.PublisherConfirm.html
+    pub fn set_confirm_nack_handler<F: Fn(DeliveryTag) + Send + Sync + 'static>(&self, handler: F) {
+        self.acknowledgements.set_nack_handler(Arc::new(handler));
     }
 
-    fn on_queue_declare_ok_received(
+    /// Register a callback which will be called with each message returned by the server, such
+    /// as the unroutable messages published with the `mandatory` flag.
+    ///
+    /// The returned messages are still available as usual, through their [`PublisherConfirm`]
+    /// or [`wait_for_confirms`]. The callback replaces any previously registered one, and
+    /// gets called from the io loop of the connection: it must not block.
+    ///
+    /// [`PublisherConfirm`]: ./publisher_confirm/struct.PublisherConfirm.html
+    /// [`wait_for_confirms`]: #method.wait_for_confirms
+    pub fn set_return_handler<F: Fn(&BasicReturnMessage) + Send + Sync + 'static>(
         &self,
-        method: protocol::queue::DeclareOk,
-        resolver: PromiseResolver<Queue>,
-    ) -> Result<()> {
-        let queue = Queue::new(method.queue, method.message_count, method.consumer_count);
-        self.queues.register(queue.clone().into());
-        resolver.swear(Ok(queue));
-        Ok(())
+        handler: F,
+    ) {
+        self.returned_messages.set_return_handler(Arc::new(handler));
     }
 
-    fn on_basic_get_ok_received(
+    /// Set up this freshly opened channel as told by `defaults`, closing it if that fails.
+    pub(crate) async fn apply_defaults(&self, defaults: &ChannelDefaults) -> Result<()> {
+        if let Some(callback) = defaults.publisher_returns_callback.clone() {
+            self.returned_messages.set_return_handler(callback);
+        }
+        if let Some(callback) = defaults.on_error_callback.clone() {
+            self.error_handler.set_handler(move |error| callback(error));
+        }
+        let res = async {
+            if defaults.confirm_mode {
+                self.confirm_select(ConfirmSelectOptions::default()).await?;
+            }
+            if let Some((prefetch_count, scope)) = defaults.qos {
+                self.basic_qos(prefetch_count, scope).await?;
+            }
+            Ok(())
+        }
+        .await;
+        if let Err(err) = res.as_ref() {
+            error!(
+                target: targets::CHANNEL,
+                "Failed to set up channel {} with the channel defaults: {}", self.id, err
+            );
+            if self.status.connected() {
+                let _ = self
+                    .close(protocol::constants::REPLY_SUCCESS as ShortUInt, "OK")
+                    .await;
+            }
+        }
+        res
+    }
+
+    /// Call `observer` with the new state of the flow whenever the server pauses (`false`) or
+    /// resumes (`true`) the publishes on this channel with `channel.flow`.
+    ///
+    /// The observer replaces any previously registered one.
+    pub fn set_flow_observer<F: Fn(bool) + Send + Sync + 'static>(&self, observer: F) {
+        self.status.set_flow_observer(Arc::new(observer));
+    }
+
+    /// Publish a message.
+    ///
+    /// The channel can be cloned to publish from several tasks at once: the frames of each
+    /// message are queued all at once and never interleaved with the ones of another message
+    /// of the same channel, and the delivery tags used for publisher confirms follow the order
+    /// in which the messages are queued. There is no ordering guarantee between messages
+    /// published concurrently though, only between the ones published by the same task.
+    ///
+    /// RabbitMQ 3.0 and later don't support the `immediate` flag and close the whole connection
+    /// when it's set, so such publishes fail locally with [`Error::UnsupportedByBroker`] when
+    /// the server identified itself as RabbitMQ, unless the flag was allowed with
+    /// [`ConnectionProperties::with_immediate_flag`].
+    ///
+    /// [`Error::UnsupportedByBroker`]: ./enum.Error.html#variant.UnsupportedByBroker
+    /// [`ConnectionProperties::with_immediate_flag`]: ./struct.ConnectionProperties.html#method.with_immediate_flag
+    pub async fn basic_publish(
         &self,
-        method: protocol::basic::GetOk,
-        resolver: PromiseResolver<Option<BasicGetMessage>>,
-        queue: ShortString,
-    ) -> Result<()> {
-        let class_id = method.get_amqp_class_id();
-        self.queues.start_basic_get_delivery(
-            queue.as_str(),
-            BasicGetMessage::new(
-                method.delivery_tag,
-                method.exchange,
-                method.routing_key,
-                method.redelivered,
-                method.message_count,
-            ),
-            resolver,
+        exchange: &str,
+        routing_key: &str,
+        options: BasicPublishOptions,
+        payload: Vec<u8>,
+        properties: BasicProperties,
+    ) -> Result<PublisherConfirm> {
+        let span = channel_span!(
+            self,
+            "channel.basic_publish",
+            exchange,
+            routing_key,
+            mandatory = options.mandatory,
+            immediate = options.immediate
         );
-        self.status.set_will_receive(class_id, Some(queue), None);
-        Ok(())
+        instrument(span, async move {
+            if options.immediate
+                && !self.configuration.immediate_flag()
+                && self.connection_status.is_rabbitmq_3_or_later()
+            {
+                error!(
+                    target: targets::CHANNEL,
+                    "channel {} refusing to publish with the immediate flag, the server doesn't support it",
+                    self.id
+                );
+                return Err(Error::UnsupportedByBroker("immediate flag"));
+            }
+            self.check_priority(exchange, routing_key, &properties)?;
+            self.do_basic_publish(exchange, routing_key, options, payload, properties)
+                .await
+        })
+        .await
     }
 
-    fn on_basic_get_empty_received(&self, method: protocol::basic::GetEmpty) -> Result<()> {
-        match self.frames.next_expected_reply(self.id) {
-            Some(Reply::BasicGetOk(resolver, _)) => {
-                resolver.swear(Ok(None));
+    fn check_priority(
+        &self,
+        exchange: &str,
+        routing_key: &str,
+        properties: &BasicProperties,
+    ) -> Result<()> {
+        let validation = self.configuration.priority_validation();
+        if validation == PriorityValidation::Off || !exchange.is_empty() {
+            return Ok(());
+        }
+        let priority = match properties.priority() {
+            Some(priority) => *priority,
+            None => return Ok(()),
+        };
+        match self.status.max_priority(routing_key) {
+            Some(max_priority) if priority > max_priority => {
+                let message = format!(
+                    "the priority {} is higher than the maximum priority {} of queue {}",
+                    priority, max_priority, routing_key
+                );
+                if validation == PriorityValidation::Error {
+                    return Err(Error::InvalidArgument(message));
+                }
+                warn!(target: targets::CHANNEL, "channel {}: {}", self.id, message);
                 Ok(())
             }
-            _ => self.handle_invalid_contents(
-                format!("unexpected basic get empty received on channel {}", self.id),
-                method.get_amqp_class_id(),
-                method.get_amqp_method_id(),
-            ),
+            _ => Ok(()),
         }
     }
 
-    fn on_basic_consume_ok_received(
+    /// Publish `value` encoded with the codec registered for `content_type`, see the [`codec`]
+    /// module.
+    ///
+    /// The `content_type` property is set to `content_type`, overriding the one from
+    /// `properties`.
+    ///
+    /// [`codec`]: ./codec/index.html
+    #[cfg(feature = "serde")]
+    pub async fn publish_encoded<T: Serialize>(
         &self,
-        method: protocol::basic::ConsumeOk,
-        resolver: PromiseResolver<Consumer>,
-        queue: ShortString,
-    ) -> Result<()> {
-        let consumer = Consumer::new(method.consumer_tag.clone(), self.executor.clone());
-        self.queues
-            .register_consumer(queue.as_str(), method.consumer_tag, consumer.clone());
-        resolver.swear(Ok(consumer));
-        Ok(())
-    }
-
-    fn on_basic_deliver_received(&self, method: protocol::basic::Deliver) -> Result<()> {
-        let class_id = method.get_amqp_class_id();
-        if let Some(queue_name) = self.queues.start_consumer_delivery(
-            method.consumer_tag.as_str(),
-            Delivery::new(
-                method.delivery_tag,
-                method.exchange,
-                method.routing_key,
-                method.redelivered,
-            ),
-        ) {
-            self.status
-                .set_will_receive(class_id, Some(queue_name), Some(method.consumer_tag));
-        }
-        Ok(())
+        exchange: &str,
+        routing_key: &str,
+        options: BasicPublishOptions,
+        value: &T,
+        content_type: &str,
+        properties: BasicProperties,
+    ) -> Result<PublisherConfirm> {
+        let codec = self.configuration.codecs().get(content_type)?;
+        let value =
+            serde_json::to_value(value).map_err(|e| Error::PayloadCodecError(e.to_string()))?;
+        let payload = codec.encode(&value)?;
+        self.basic_publish(
+            exchange,
+            routing_key,
+            options,
+            payload,
+            properties.with_content_type(content_type.into()),
+        )
+        .await
     }
 
-    fn on_basic_cancel_received(&self, method: protocol::basic::Cancel) -> Result<()> {
-        self.queues
-            .deregister_consumer(method.consumer_tag.as_str());
-        if !method.nowait {
-            let channel = self.clone();
-            self.internal_rpc.register_internal_future(async move {
-                channel.basic_cancel_ok(method.consumer_tag.as_str()).await
-            });
-        }
-        Ok(())
+    /// Publish a message through [`basic_publish`] with the default options and its delivery mode
+    /// set to [`DeliveryMode::Persistent`], overriding the one from `properties`.
+    ///
+    /// [`basic_publish`]: #method.basic_publish
+    /// [`DeliveryMode::Persistent`]: ./enum.DeliveryMode.html#variant.Persistent
+    pub async fn basic_publish_persistent(
+        &self,
+        exchange: &str,
+        routing_key: &str,
+        payload: Vec<u8>,
+        properties: BasicProperties,
+    ) -> Result<PublisherConfirm> {
+        self.basic_publish(
+            exchange,
+            routing_key,
+            BasicPublishOptions::default(),
+            payload,
+            properties.with_delivery_mode(DeliveryMode::Persistent.into()),
+        )
+        .await
     }
 
-    fn on_basic_cancel_ok_received(&self, method: protocol::basic::CancelOk) -> Result<()> {
-        self.queues
-            .deregister_consumer(method.consumer_tag.as_str());
-        Ok(())
+    /// Publish a [`Message`], to its own routing key if it has one, with the `mandatory` flag if
+    /// either it or `options` have it.
+    ///
+    /// [`Message`]: ./message/struct.Message.html
+    pub async fn publish_message(
+        &self,
+        exchange: &str,
+        routing_key: &str,
+        options: BasicPublishOptions,
+        message: Message,
+    ) -> Result<PublisherConfirm> {
+        let routing_key = message
+            .routing_key_override()
+            .cloned()
+            .unwrap_or_else(|| routing_key.into());
+        let options = options.mandatory(options.mandatory || message.is_mandatory());
+        let (payload, properties) = message.into_parts();
+        self.basic_publish(exchange, routing_key.as_str(), options, payload, properties)
+            .await
     }
 
-    fn on_basic_ack_received(&self, method: protocol::basic::Ack) -> Result<()> {
-        if self.status.confirm() {
-            if method.multiple {
-                if method.delivery_tag > 0 {
-                    self.acknowledgements
-                        .ack_all_before(method.delivery_tag, self.id)
-                        .or_else(|err| {
-                            self.acknowledgement_error(
-                                err,
-                                method.get_amqp_class_id(),
-                                method.get_amqp_method_id(),
-                            )
-                        })?;
-                } else {
-                    self.acknowledgements.ack_all_pending();
-                }
-            } else {
-                self.acknowledgements
-                    .ack(method.delivery_tag, self.id)
-                    .or_else(|err| {
-                        self.acknowledgement_error(
-                            err,
-                            method.get_amqp_class_id(),
-                            method.get_amqp_method_id(),
-                        )
-                    })?;
-            }
+    /// Publish several messages in order, see [`publish_message`].
+    ///
+    /// This stops at the first message which fails to be published, the previous ones having
+    /// already been sent.
+    ///
+    /// [`publish_message`]: #method.publish_message
+    pub async fn publish_batch(
+        &self,
+        exchange: &str,
+        routing_key: &str,
+        options: BasicPublishOptions,
+        messages: Vec<Message>,
+    ) -> Result<Vec<PublisherConfirm>> {
+        let mut confirms = Vec::with_capacity(messages.len());
+        for message in messages {
+            confirms.push(
+                self.publish_message(exchange, routing_key, options, message)
+                    .await?,
+            );
         }
-        Ok(())
+        Ok(confirms)
     }
 
-    fn on_basic_nack_received(&self, method: protocol::basic::Nack) -> Result<()> {
+    /// Publish a message with the `mandatory` flag set, resolving to whether the server
+    /// returned it.
+    ///
+    /// Without publisher confirms, the server only signals that it couldn't route a mandatory
+    /// message by returning it, so the message is considered routed if it didn't get returned
+    /// within the window set with [`ConnectionProperties::with_mandatory_return_window`] after
+    /// its frames were written. Returns carry nothing identifying their publish either, so they
+    /// are matched with the oldest pending mandatory publish to the same exchange and routing
+    /// key. This is a heuristic with some limits:
+    ///
+    /// - a message returned after the window is considered routed, the return is then
+    ///   available through [`wait_for_confirms`]
+    /// - a return for a message published with the `mandatory` flag through [`basic_publish`]
+    ///   can be matched with a publish made through this method to the same destination
+    ///
+    /// When publisher confirms are enabled on this channel, the outcome comes from the
+    /// confirmation of the message instead, and this waits for it.
+    ///
+    /// [`ConnectionProperties::with_mandatory_return_window`]: ./struct.ConnectionProperties.html#method.with_mandatory_return_window
+    /// [`wait_for_confirms`]: #method.wait_for_confirms
+    /// [`basic_publish`]: #method.basic_publish
+    pub async fn basic_publish_mandatory(
+        &self,
+        exchange: &str,
+        routing_key: &str,
+        options: BasicPublishOptions,
+        payload: Vec<u8>,
+        properties: BasicProperties,
+    ) -> Result<MandatoryOutcome> {
+        let options = options.mandatory(true);
         if self.status.confirm() {
-            if method.multiple {
-                if method.delivery_tag > 0 {
-                    self.acknowledgements
-                        .nack_all_before(method.delivery_tag, self.id)
-                        .or_else(|err| {
-                            self.acknowledgement_error(
-                                err,
-                                method.get_amqp_class_id(),
-                                method.get_amqp_method_id(),
-                            )
-                        })?;
-                } else {
-                    self.acknowledgements.nack_all_pending();
-                }
-            } else {
-                self.acknowledgements
-                    .nack(method.delivery_tag, self.id)
-                    .or_else(|err| {
-                        self.acknowledgement_error(
-                            err,
-                            method.get_amqp_class_id(),
-                            method.get_amqp_method_id(),
-                        )
-                    })?;
-            }
+            let confirmation = self
+                .basic_publish(exchange, routing_key, options, payload, properties)
+                .await?
+                .await?;
+            return Ok(confirmation
+                .take_message()
+                .map_or(MandatoryOutcome::Routed, MandatoryOutcome::Returned));
         }
-        Ok(())
+        let (promise, resolver) = Promise::new();
+        if level_enabled!(Level::TRACE) {
+            promise.set_marker(format!("channel.{}.basic_publish_mandatory", self.id));
+        }
+        // Forgotten when this future completes or gets dropped, whether it got published or not
+        let pending =
+            self.returned_messages
+                .register_mandatory_publish(exchange, routing_key, resolver);
+        self.basic_publish(exchange, routing_key, options, payload, properties)
+            .await?;
+        let window = self.configuration.mandatory_return_window();
+        futures_lite::future::or(promise, async {
+            Timer::after(window).await;
+            pending.routed();
+            // The promise is resolved now, one way or the other
+            futures_lite::future::pending().await
+        })
+        .await
     }
 
-    fn on_basic_return_received(&self, method: protocol::basic::Return) -> Result<()> {
-        let class_id = method.get_amqp_class_id();
-        self.returned_messages
-            .start_new_delivery(BasicReturnMessage::new(
-                method.exchange,
-                method.routing_key,
-                method.reply_code,
-                method.reply_text,
-            ));
-        self.status.set_will_receive(class_id, None, None);
-        Ok(())
+    /// Limit the number of unacknowledged deliveries the server sends, to each consumer of this
+    /// channel, to the whole channel or to the whole connection depending on `scope`.
+    ///
+    /// This fails with [`Error::UnsupportedByBroker`] if the server can't apply the limit to
+    /// `scope`, see [`QosScope`]. The prefetch count is restored by [`Consumer::resubscribe`].
+    ///
+    /// [`Error::UnsupportedByBroker`]: ./enum.Error.html#variant.UnsupportedByBroker
+    /// [`QosScope`]: ./enum.QosScope.html
+    /// [`Consumer::resubscribe`]: ./struct.Consumer.html#method.resubscribe
+    pub async fn basic_qos(&self, prefetch_count: ShortUInt, scope: QosScope) -> Result<()> {
+        let global = scope.global_flag(self.connection_status.per_consumer_qos())?;
+        self.set_qos(prefetch_count, BasicQosOptions { global })
+            .await
     }
 
-    fn on_basic_recover_ok_received(&self) -> Result<()> {
-        self.queues.drop_prefetched_messages();
-        Ok(())
+    /// Limit the number of unacknowledged deliveries the server sends, passing the `global`
+    /// flag as is, whatever it means for the server.
+    ///
+    /// The prefetch count is restored by [`Consumer::resubscribe`].
+    ///
+    /// [`Consumer::resubscribe`]: ./struct.Consumer.html#method.resubscribe
+    #[deprecated(
+        note = "the meaning of the global flag depends on the server, use basic_qos with a QosScope"
+    )]
+    pub async fn basic_qos_with_options(
+        &self,
+        prefetch_count: ShortUInt,
+        options: BasicQosOptions,
+    ) -> Result<()> {
+        self.set_qos(prefetch_count, options).await
     }
 
-    fn on_confirm_select_ok_received(&self) -> Result<()> {
-        self.status.set_confirm();
-        Ok(())
+    async fn set_qos(&self, prefetch_count: ShortUInt, options: BasicQosOptions) -> Result<()> {
+        let span = channel_span!(
+            self,
+            "channel.basic_qos",
+            prefetch_count,
+            global = options.global
+        );
+        instrument(span, async move {
+            self.do_basic_qos(prefetch_count, options).await?;
+            self.status.set_prefetch(prefetch_count, options);
+            Ok(())
+        })
+        .await
     }
 
-    fn on_access_request_ok_received(&self, _: protocol::access::RequestOk) -> Result<()> {
-        Ok(())
+    /// Change the channel-wide prefetch count without remembering it, so that
+    /// [`Consumer::resubscribe`] still restores the one set with `basic_qos`.
+    pub(crate) async fn set_transient_prefetch(&self, prefetch_count: ShortUInt) -> Result<()> {
+        self.do_basic_qos(prefetch_count, BasicQosOptions::default().global(true))
+            .await
+    }
+
+    /// The channel-wide prefetch count set with `basic_qos`, 0 meaning no limit.
+    pub(crate) fn global_prefetch(&self) -> ShortUInt {
+        match self.status.prefetch() {
+            Some((prefetch_count, options)) if options.global => prefetch_count,
+            _ => 0,
+        }
+    }
+
+    /// Start a consumer on `queue`.
+    ///
+    /// If `consumer_tag` is empty, a unique one is generated with [`id::consumer_tag`] instead
+    /// of letting the server pick it.
+    ///
+    /// If `queue` is empty, the last queue declared on this channel is consumed, like
+    /// [`queue_bind`] does.
+    ///
+    /// [`id::consumer_tag`]: ./id/fn.consumer_tag.html
+    /// [`queue_bind`]: #method.queue_bind
+    pub async fn basic_consume(
+        &self,
+        queue: &str,
+        consumer_tag: &str,
+        options: BasicConsumeOptions,
+        arguments: FieldTable,
+    ) -> Result<Consumer> {
+        let queue = self.queue_or_last_declared(queue)?;
+        let queue = queue.as_ref();
+        let span = channel_span!(
+            self,
+            "channel.basic_consume",
+            queue,
+            consumer_tag,
+            no_ack = options.no_ack
+        );
+        instrument(span, async move {
+            let subscription = Subscription {
+                queue: queue.into(),
+                options,
+                arguments: arguments.clone(),
+                prefetch: self.status.prefetch(),
+            };
+            let consumer = if consumer_tag.is_empty() {
+                let consumer_tag = id::consumer_tag();
+                self.do_basic_consume(queue, consumer_tag.as_str(), options, arguments)
+                    .await?
+            } else {
+                self.do_basic_consume(queue, consumer_tag, options, arguments)
+                    .await?
+            };
+            consumer.tag_assigned(consumer_tag);
+            consumer.set_subscription(subscription);
+            Ok(consumer)
+        })
+        .await
+    }
+
+    /// Start a consumer on the stream queue `queue` like [`basic_consume`] does, reading from
+    /// `offset`, using the `x-stream-offset` argument.
+    ///
+    /// RabbitMQ requires stream consumers to acknowledge their deliveries and their channel to
+    /// have a prefetch count, so `no_ack` is refused. Consuming a queue declared on this channel
+    /// with another `x-queue-type` than `stream` is refused too, the other queues are checked by
+    /// the server.
+    ///
+    /// [`basic_consume`]: #method.basic_consume
+    pub async fn basic_consume_offset(
+        &self,
+        queue: &str,
+        consumer_tag: &str,
+        offset: StreamOffset,
+        options: BasicConsumeOptions,
+        mut arguments: FieldTable,
+    ) -> Result<Consumer> {
+        if let Some(queue_type) = self.status.queue_type(queue) {
+            if queue_type.as_str() != "stream" {
+                return Err(Error::InvalidArgument(format!(
+                    "queue {} is a {} queue, not a stream",
+                    queue, queue_type
+                )));
+            }
+        }
+        if options.no_ack {
+            return Err(Error::InvalidArgument(
+                "stream queues can't be consumed with no_ack".into(),
+            ));
+        }
+        arguments.insert("x-stream-offset".into(), stream_offset_argument(offset)?);
+        self.basic_consume(queue, consumer_tag, options, arguments)
+            .await
+    }
+
+    /// Start a consumer on `queue` whose deliveries are handled by `delegate`.
+    ///
+    /// The consumer is created and given its delegate before `basic.consume` is sent, so the
+    /// delegate is attached before any delivery can reach it. The returned future still
+    /// resolves once the server acknowledged the consumer.
+    ///
+    /// If `consumer_tag` is empty, a unique one is generated like [`basic_consume`] does.
+    ///
+    /// [`basic_consume`]: #method.basic_consume
+    pub async fn basic_consume_lazy<D: ConsumerDelegate + 'static>(
+        &self,
+        queue: &str,
+        consumer_tag: &str,
+        options: BasicConsumeOptions,
+        arguments: FieldTable,
+        delegate: D,
+    ) -> Result<Consumer> {
+        self.basic_consume_prepared(queue, consumer_tag, options, arguments, |consumer| {
+            consumer.set_delegate(delegate)
+        })
+        .await
+    }
+
+    /// Start a consumer on `queue` like [`basic_consume`] does, calling `on_cancel` if the server
+    /// cancels it, for example because its queue got deleted.
+    ///
+    /// `on_cancel` is not called when the consumer gets canceled with [`basic_cancel`], nor
+    /// when its channel or connection gets closed. It gets called once the consumer stream
+    /// ended.
+    ///
+    /// [`basic_consume`]: #method.basic_consume
+    /// [`basic_cancel`]: #method.basic_cancel
+    pub async fn basic_consume_with_cancel_callback<F: Fn() + Send + 'static>(
+        &self,
+        queue: &str,
+        consumer_tag: &str,
+        on_cancel: F,
+        options: BasicConsumeOptions,
+        arguments: FieldTable,
+    ) -> Result<Consumer> {
+        self.basic_consume_prepared(queue, consumer_tag, options, arguments, |consumer| {
+            consumer.set_cancel_callback(on_cancel)
+        })
+        .await
+    }
+
+    /// Start a consumer on `queue` like [`basic_consume`] does, recording its activity in
+    /// `metrics`, see [`ConsumerMetrics`].
+    ///
+    /// [`basic_consume`]: #method.basic_consume
+    /// [`ConsumerMetrics`]: ./metrics/struct.ConsumerMetrics.html
+    pub async fn basic_consume_with_metrics(
+        &self,
+        queue: &str,
+        consumer_tag: &str,
+        metrics: Arc<ConsumerMetrics>,
+        options: BasicConsumeOptions,
+        arguments: FieldTable,
+    ) -> Result<Consumer> {
+        self.basic_consume_prepared(queue, consumer_tag, options, arguments, |consumer| {
+            consumer.set_metrics(metrics)
+        })
+        .await
+    }
+
+    /// Set up a consumer before sending `basic.consume`, so that it's ready before the server
+    /// can deliver to it or cancel it.
+    async fn basic_consume_prepared<F: FnOnce(&Consumer)>(
+        &self,
+        queue: &str,
+        consumer_tag: &str,
+        options: BasicConsumeOptions,
+        arguments: FieldTable,
+        prepare: F,
+    ) -> Result<Consumer> {
+        let queue = self.queue_or_last_declared(queue)?;
+        let queue = queue.as_ref();
+        let requested_tag = consumer_tag;
+        let consumer_tag = if requested_tag.is_empty() {
+            id::consumer_tag()
+        } else {
+            requested_tag.into()
+        };
+        let consumer = Consumer::new(consumer_tag.clone(), self.executor.clone());
+        prepare(&consumer);
+        let subscription = Subscription {
+            queue: queue.into(),
+            options,
+            arguments: arguments.clone(),
+            prefetch: self.status.prefetch(),
+        };
+        // Picked up when the server acknowledges the consumer, like the resubscribing ones
+        self.queues
+            .register_resubscribing(consumer_tag.clone(), consumer);
+        let res = self
+            .do_basic_consume(queue, consumer_tag.as_str(), options, arguments)
+            .await;
+        self.queues.take_resubscribing(consumer_tag.as_str());
+        let consumer = res?;
+        consumer.tag_assigned(requested_tag);
+        consumer.set_subscription(subscription);
+        Ok(consumer)
+    }
+
+    pub(crate) async fn resubscribe(&self, consumer: Consumer) -> Result<()> {
+        let subscription = consumer.subscription().ok_or_else(|| {
+            Error::InvalidArgument(format!(
+                "consumer {} wasn't started with basic_consume",
+                consumer.tag()
+            ))
+        })?;
+        if let Some((prefetch_count, options)) = subscription.prefetch {
+            self.set_qos(prefetch_count, options).await?;
+        }
+        let consumer_tag = consumer.tag();
+        self.subscribe_again(consumer, subscription, consumer_tag.as_str())
+            .await
+    }
+
+    /// Start `consumer` again on this channel, from the queue and with the tag, options and
+    /// arguments of `spec`, usually obtained from [`Consumer::detach`] once its previous
+    /// channel failed.
+    ///
+    /// The new deliveries flow to `consumer`, so that the application keeps consuming from the
+    /// same stream, or delegate, even if it already yielded the error and the end of stream
+    /// caused by the failure. The deliveries still buffered from the previous channel come
+    /// first and keep referring to it: their delivery tags are only valid there, acknowledging
+    /// them fails once it is gone, and the server redelivers them on this one anyway.
+    ///
+    /// Unlike [`Consumer::resubscribe`], the prefetch count of the previous channel isn't set on
+    /// this one.
+    ///
+    /// [`Consumer::detach`]: ./struct.Consumer.html#method.detach
+    /// [`Consumer::resubscribe`]: ./struct.Consumer.html#method.resubscribe
+    pub async fn reattach_consumer(&self, spec: ConsumerSpec, consumer: &Consumer) -> Result<()> {
+        let consumer_tag = if spec.consumer_tag.is_empty() {
+            id::consumer_tag()
+        } else {
+            spec.consumer_tag.clone()
+        };
+        consumer.set_tag(consumer_tag);
+        self.subscribe_again(
+            consumer.clone(),
+            Subscription {
+                queue: spec.queue,
+                options: spec.options,
+                arguments: spec.arguments,
+                prefetch: None,
+            },
+            spec.consumer_tag.as_str(),
+        )
+        .await
+    }
+
+    /// Consume again with the current tag of `consumer`, reporting the tag the server confirmed
+    /// unless it is `requested_tag`.
+    async fn subscribe_again(
+        &self,
+        consumer: Consumer,
+        subscription: Subscription,
+        requested_tag: &str,
+    ) -> Result<()> {
+        let consumer_tag = consumer.tag();
+        trace!(
+            target: targets::CHANNEL,
+            "channel {} resubscribing consumer {} to queue {}",
+            self.id,
+            consumer_tag,
+            subscription.queue
+        );
+        consumer.reset();
+        self.queues
+            .register_resubscribing(consumer_tag.clone(), consumer.clone());
+        let res = self
+            .do_basic_consume(
+                subscription.queue.as_str(),
+                consumer_tag.as_str(),
+                subscription.options,
+                subscription.arguments.clone(),
+            )
+            .await;
+        self.queues.take_resubscribing(consumer_tag.as_str());
+        res?;
+        consumer.tag_assigned(requested_tag);
+        consumer.set_subscription(Subscription {
+            prefetch: self.status.prefetch(),
+            ..subscription
+        });
+        Ok(())
+    }
+
+    /// Start a consumer like [`basic_consume`], giving up if the server didn't acknowledge it
+    /// within `timeout`.
+    ///
+    /// On timeout, [`Error::Timeout`] is returned and the channel stays usable: if the server
+    /// acknowledges the consumer afterwards, it gets canceled right away.
+    ///
+    /// [`basic_consume`]: #method.basic_consume
+    /// [`Error::Timeout`]: ./enum.Error.html#variant.Timeout
+    pub async fn basic_consume_with_timeout(
+        &self,
+        queue: &str,
+        consumer_tag: &str,
+        timeout: Duration,
+        options: BasicConsumeOptions,
+        arguments: FieldTable,
+    ) -> Result<Consumer> {
+        let (promise, resolver) = Promise::new();
+        if level_enabled!(Level::TRACE) {
+            promise.set_marker("basic.consume.Timeout".into());
+        }
+        let resolver = Arc::new(Mutex::new(Some(resolver)));
+        let consume = {
+            let channel = self.clone();
+            let queue = queue.to_string();
+            let consumer_tag = consumer_tag.to_string();
+            let resolver = resolver.clone();
+            async move {
+                let res = channel
+                    .basic_consume(&queue, &consumer_tag, options, arguments)
+                    .await;
+                let resolver = resolver.lock().take();
+                match (resolver, res) {
+                    (Some(resolver), res) => resolver.swear(res),
+                    (None, Ok(consumer)) => {
+                        debug!(
+                            target: targets::CONSUMER,
+                            "channel {} canceling consumer {} acknowledged after timeout",
+                            channel.id,
+                            consumer.tag()
+                        );
+                        if let Err(err) = channel
+                            .basic_cancel(consumer.tag().as_str(), BasicCancelOptions::default())
+                            .await
+                        {
+                            error!(
+                                target: targets::CONSUMER,
+                                "Failed to cancel consumer after timeout: {}",
+                                err
+                            );
+                        }
+                    }
+                    (None, Err(err)) => {
+                        trace!(
+                            target: targets::CONSUMER,
+                            "basic.consume failed after timeout: {}",
+                            err
+                        )
+                    }
+                }
+                Ok(())
+            }
+        };
+        self.internal_rpc.register_internal_future(consume);
+        futures_lite::future::or(promise, async move {
+            Timer::after(timeout).await;
+            if resolver.lock().take().is_some() {
+                Err(Error::Timeout)
+            } else {
+                // The consumer got acknowledged right in time
+                futures_lite::future::pending().await
+            }
+        })
+        .await
+    }
+
+    /// Consume `queue` in batches.
+    ///
+    /// The prefetch count of the channel is set to `prefetch`, then the deliveries are
+    /// accumulated until there are `batch_size` of them or `flush_interval` elapsed since the
+    /// first one, and handed to `handler`. If it succeeds, the whole batch is acknowledged at
+    /// once with a multiple `basic_ack`, otherwise it is rejected with a multiple `basic_nack`,
+    /// without requeueing.
+    ///
+    /// As multiple acknowledgements cover every earlier delivery of the channel, this should run
+    /// on a dedicated channel. `prefetch` should be at least `batch_size`, or batches only get
+    /// flushed by `flush_interval`.
+    ///
+    /// The returned consumer is only meant to get its tag or to cancel it: its deliveries are
+    /// already consumed. Once it gets canceled, the pending batch is flushed.
+    pub async fn basic_consume_with_prefetch_and_ack_batch<F, Fut>(
+        &self,
+        queue: &str,
+        consumer_tag: &str,
+        prefetch: ShortUInt,
+        batch_size: usize,
+        flush_interval: Duration,
+        handler: F,
+    ) -> Result<Consumer>
+    where
+        F: Fn(Vec<Delivery>) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        if batch_size == 0 {
+            return Err(Error::InvalidArgument(
+                "the batch size must be positive".into(),
+            ));
+        }
+        self.set_qos(prefetch, BasicQosOptions::default()).await?;
+        let consumer = self
+            .basic_consume(
+                queue,
+                consumer_tag,
+                BasicConsumeOptions::default(),
+                FieldTable::default(),
+            )
+            .await?;
+        self.executor.spawn(Box::pin(batch_consumer::run(
+            self.clone(),
+            consumer.clone(),
+            batch_size,
+            flush_interval,
+            handler,
+        )));
+        Ok(consumer)
+    }
+
+    /// Get the number of messages and consumers of a queue.
+    ///
+    /// This uses a passive declaration on a dedicated channel, so that an error (e.g. if the queue
+    /// doesn't exist) doesn't close this one.
+    pub async fn queue_stats(&self, queue: &str) -> Result<QueueStats> {
+        self.passive_queue_declare(queue)
+            .await
+            .map(QueueStats::from)
+    }
+
+    /// Check whether a queue exists, without risking to close this channel.
+    ///
+    /// A passive `queue_declare` of a queue which doesn't exist makes the server close the
+    /// channel. This one runs on a dedicated channel instead and returns `Ok(None)` if the server
+    /// answered that the queue wasn't found, the other errors are returned as is.
+    pub async fn queue_declare_passive_checked(&self, queue: &str) -> Result<Option<Queue>> {
+        match self.passive_queue_declare(queue).await {
+            Ok(queue) => Ok(Some(queue)),
+            Err(Error::ProtocolError(error))
+                if matches!(error.kind(), AMQPErrorKind::Soft(AMQPSoftError::NOTFOUND)) =>
+            {
+                Ok(None)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    async fn passive_queue_declare(&self, queue: &str) -> Result<Queue> {
+        let channel = self.create_internal_channel().await?;
+        let res = channel
+            .queue_declare(
+                queue,
+                QueueDeclareOptions::default().passive(true),
+                FieldTable::default(),
+            )
+            .await;
+        if channel.status().connected() {
+            let _ = channel
+                .close(protocol::constants::REPLY_SUCCESS as ShortUInt, "OK")
+                .await;
+        }
+        res
+    }
+
+    /// Periodically poll the number of messages and consumers of a queue, see [`queue_stats`].
+    ///
+    /// The first item is produced right away, the next ones after each `interval`. Errors are
+    /// produced as items and polling carries on, the stream only ends once the connection is
+    /// closed.
+    ///
+    /// [`queue_stats`]: #method.queue_stats
+    pub fn queue_stats_stream(
+        &self,
+        queue: &str,
+        interval: Duration,
+    ) -> impl Stream<Item = Result<QueueStats>> + Send {
+        let state = (self.clone(), queue.to_string(), false);
+        stream::unfold(state, move |(channel, queue, started)| async move {
+            if started {
+                Timer::after(interval).await;
+            }
+            if !channel.connection_status.connected() {
+                return None;
+            }
+            let stats = channel.queue_stats(&queue).await;
+            Some((stats, (channel, queue, true)))
+        })
+    }
+
+    /// Declare all the exchanges, queues and bindings of the topology.
+    ///
+    /// If one of the declarations fails, the exchanges and queues created by this call are deleted
+    /// before returning the error, so that the broker isn't left with a partial topology. Each one
+    /// is passively declared first, so that the ones which already existed beforehand are left
+    /// untouched. Bindings can't be looked up, so only the ones involving a created exchange or
+    /// queue are undone.
+    pub async fn declare_topology(&self, topology: &Topology) -> Result<()> {
+        let mut transaction = TopologyTransaction::default();
+        if let Err(err) = transaction.apply(topology, self).await {
+            let res = if self.status.connected() {
+                transaction.rollback(self).await
+            } else {
+                // The failure closed this channel, use another one to rollback
+                match self.create_internal_channel().await {
+                    Ok(channel) => {
+                        let res = transaction.rollback(&channel).await;
+                        let _ = channel
+                            .close(protocol::constants::REPLY_SUCCESS as ShortUInt, "OK")
+                            .await;
+                        res
+                    }
+                    Err(err) => Err(err),
+                }
+            };
+            if let Err(rollback_err) = res {
+                error!(
+                    target: targets::CHANNEL,
+                    "Failed to rollback topology declaration: {}",
+                    rollback_err
+                );
+            }
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// Check that the exchanges and queues of the topology exist, without declaring anything.
+    ///
+    /// This only relies on passive declarations, which don't need the configure permission. They
+    /// run on dedicated channels, so that the missing resources don't close this one. Only the
+    /// existence is checked, see [`VerificationReport`] for the limitations.
+    ///
+    /// [`VerificationReport`]: ./topology/struct.VerificationReport.html
+    pub async fn verify_topology(&self, topology: &Topology) -> Result<VerificationReport> {
+        topology.verify(self).await
+    }
+
+    pub async fn wait_for_confirms(&self) -> Result<Vec<BasicReturnMessage>> {
+        if self
+            .acknowledgements
+            .get_last_pending()
+            .await
+            .transpose()?
+            .is_some()
+        {
+            trace!(target: targets::CHANNEL, "Waiting for pending confirms");
+        } else {
+            trace!(target: targets::CHANNEL, "No confirms to wait for");
+        }
+        Ok(self.returned_messages.drain())
+    }
+
+    /// Wait until the broker has processed every operation issued on this channel so far.
+    ///
+    /// The returned future resolves once all the frames queued for this channel have been
+    /// written, all the replies they expect have been received and all the pending publisher
+    /// confirms have been settled. The outstanding operations are snapshotted when this method
+    /// is called, so operations issued afterwards don't delay the resolution.
+    pub fn sync(&self) -> impl Future<Output = Result<()>> + Send + 'static {
+        let id = self.id;
+        let snapshot = if self.status.connected() {
+            Ok((
+                self.acknowledgements.pending_confirms(),
+                self.frames.sync_channel(id),
+            ))
+        } else {
+            Err(self.status.state_error())
+        };
+        let frames = self.frames.clone();
+        let waker = self.waker.clone();
+        async move {
+            let (confirms, pending) = snapshot?;
+            pending.await?;
+            // Everything we were waiting for has been handed to the io loop, which writes frames
+            // in order: once this heartbeat is written, they all are.
+            let (promise, resolver) = Promise::new();
+            if level_enabled!(Level::TRACE) {
+                promise.set_marker(format!("Sync flush channel {}", id));
+            }
+            frames.push(0, AMQPFrame::Heartbeat(0), resolver, None);
+            waker.wake();
+            promise.await?;
+            trace!(
+                target: targets::CHANNEL,
+                "channel {} waiting for {} confirms",
+                id,
+                confirms.len()
+            );
+            for confirm in confirms {
+                confirm.await?;
+            }
+            Ok(())
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn register_queue(&self, queue: QueueState) {
+        self.queues.register(queue);
+    }
+
+    #[cfg(test)]
+    pub(crate) fn register_pending_confirm(&self, delivery_tag: DeliveryTag) -> PublisherConfirm {
+        self.acknowledgements
+            .register_pending(delivery_tag, self.id)
+    }
+
+    /// Check the names and field tables of `method` if strict field validation is enabled.
+    fn check_fields(&self, method: &AMQPClass) -> Result<()> {
+        if self.configuration.strict_field_validation() {
+            validation::check_method(method)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn send_method_frame(
+        &self,
+        method: AMQPClass,
+        resolver: PromiseResolver<()>,
+        expected_reply: Option<ExpectedReply>,
+    ) {
+        self.send_frame(AMQPFrame::Method(self.id, method), resolver, expected_reply);
+    }
+
+    pub(crate) fn send_frame(
+        &self,
+        frame: AMQPFrame,
+        resolver: PromiseResolver<()>,
+        expected_reply: Option<ExpectedReply>,
+    ) {
+        trace!(target: targets::CHANNEL, "channel {} send_frame", self.id);
+        self.frames.push(self.id, frame, resolver, expected_reply);
+        self.wake();
+    }
+
+    async fn send_method_frame_with_body(
+        &self,
+        method: AMQPClass,
+        payload: Vec<u8>,
+        properties: BasicProperties,
+    ) -> Result<PublisherConfirm> {
+        if self.configuration.strict_field_validation() {
+            validation::check_properties(&properties)?;
+        }
+        let class_id = method.get_amqp_class_id();
+        let header = AMQPContentHeader {
+            class_id,
+            weight: 0,
+            body_size: payload.len() as u64,
+            properties,
+        };
+        let frame_max = self.configuration.frame_max();
+        let mut frames = vec![
+            AMQPFrame::Method(self.id, method),
+            AMQPFrame::Header(self.id, class_id, Box::new(header)),
+        ];
+
+        // a content body frame 8 bytes of overhead
+        frames.extend(
+            payload
+                .as_slice()
+                .chunks(frame_max as usize - 8)
+                .map(|chunk| AMQPFrame::Body(self.id, chunk.into())),
+        );
+
+        trace!(target: targets::CHANNEL, "channel {} send_frames", self.id);
+        #[cfg(feature = "publish-timestamps")]
+        let queued_at = std::time::Instant::now();
+        let (promise, publisher_confirms_result) = {
+            // The server numbers the publishes in the order it receives them: take the delivery
+            // tag and queue all the frames at once so that concurrent publishes can't swap them.
+            let _publishing = self.publishing.lock();
+            let publisher_confirms_result = self.before_basic_publish();
+            (self.push_publish_frames(frames), publisher_confirms_result)
+        };
+        self.wake();
+        #[cfg(feature = "publish-timestamps")]
+        let (promise, written_at) = promise;
+        promise.await?;
+        self.stats.published();
+        #[allow(unused_mut)]
+        let mut publisher_confirm = publisher_confirms_result
+            .unwrap_or_else(|| PublisherConfirm::not_requested(self.returned_messages.clone()));
+        #[cfg(feature = "publish-timestamps")]
+        {
+            let written_at = written_at
+                .lock()
+                .expect("the io loop records when it writes the frames");
+            publisher_confirm.set_timestamps(queued_at, written_at);
+        }
+        Ok(publisher_confirm)
+    }
+
+    #[cfg(not(feature = "publish-timestamps"))]
+    fn push_publish_frames(&self, frames: Vec<AMQPFrame>) -> Promise<()> {
+        self.frames.push_frames(frames)
+    }
+
+    #[cfg(feature = "publish-timestamps")]
+    fn push_publish_frames(
+        &self,
+        frames: Vec<AMQPFrame>,
+    ) -> (Promise<()>, crate::frames::WrittenAt) {
+        self.frames.push_timed_frames(frames)
+    }
+
+    fn handle_invalid_contents(&self, error: String, class_id: u16, method_id: u16) -> Result<()> {
+        error!(target: targets::CHANNEL, "{}", error);
+        let error = AMQPError::new(AMQPHardError::UNEXPECTEDFRAME.into(), error.into());
+        self.internal_rpc.close_connection(
+            error.get_id(),
+            error.get_message().to_string(),
+            class_id,
+            method_id,
+        );
+        Err(Error::ProtocolError(error))
+    }
+
+    pub(crate) fn handle_content_header_frame(
+        &self,
+        class_id: u16,
+        size: u64,
+        properties: BasicProperties,
+    ) -> Result<()> {
+        let oversized = size > self.configuration.max_message_size();
+        if oversized
+            && self.configuration.oversized_message_policy()
+                == OversizedMessagePolicy::CloseConnection
+        {
+            return self.handle_oversized_message_fatal(class_id, size);
+        }
+        self.status.set_content_length(
+            self.id,
+            class_id,
+            size as usize,
+            |receiver, confirm_mode| match receiver {
+                receiver if oversized => self.handle_oversized_message(receiver, size),
+                ContentReceiver::Delivery(queue_name, request_id_or_consumer_tag) => {
+                    self.queues.handle_content_header_frame(
+                        &self,
+                        queue_name.as_str(),
+                        request_id_or_consumer_tag.as_ref().map(ShortString::as_str),
+                        size,
+                        properties,
+                    );
+                }
+                ContentReceiver::Return => {
+                    self.returned_messages.set_delivery_properties(properties);
+                    if size == 0 {
+                        self.returned_messages.new_delivery_complete(confirm_mode);
+                    }
+                }
+                ContentReceiver::UnknownConsumer(consumer_tag, delivery_tag) => {
+                    if size == 0 {
+                        self.handle_unknown_consumer_delivery(consumer_tag, delivery_tag);
+                    }
+                }
+            },
+            |msg| {
+                error!(target: targets::CHANNEL, "{}", msg);
+                let error = AMQPError::new(AMQPHardError::FRAMEERROR.into(), msg.into());
+                self.internal_rpc.close_connection(
+                    error.get_id(),
+                    error.get_message().to_string(),
+                    class_id,
+                    0,
+                );
+                let error = Error::ProtocolError(error);
+                self.set_error(error.clone());
+                Err(error)
+            },
+            |msg| self.handle_invalid_contents(msg, class_id, 0),
+            oversized,
+        )
+    }
+
+    fn handle_oversized_message(&self, receiver: ContentReceiver<'_>, size: u64) {
+        warn!(
+            target: targets::CHANNEL,
+            "message of {} bytes received on channel {} exceeds the maximum message size of {} bytes, discarding it",
+            size,
+            self.id,
+            self.configuration.max_message_size()
+        );
+        let error = Error::MessageTooLarge(size);
+        match receiver {
+            ContentReceiver::Delivery(queue_name, None) => {
+                self.queues
+                    .error_basic_get_delivery(queue_name.as_str(), error.clone());
+            }
+            ContentReceiver::Return => self.returned_messages.drop_delivery(),
+            _ => {}
+        }
+        self.error_consumers(error.clone());
+        self.error_publisher_confirms(error);
+        let error = AMQPError::new(
+            AMQPSoftError::CONTENTTOOLARGE.into(),
+            format!("message of {} bytes is too large", size).into(),
+        );
+        let channel = self.clone();
+        self.internal_rpc.register_internal_future(async move {
+            channel
+                .do_channel_close(error.get_id(), error.get_message().as_str(), 60, 0)
+                .await
+        });
+    }
+
+    fn handle_oversized_message_fatal(&self, class_id: u16, size: u64) -> Result<()> {
+        error!(
+            target: targets::CHANNEL,
+            "message of {} bytes received on channel {} exceeds the maximum message size of {} bytes, closing the connection",
+            size,
+            self.id,
+            self.configuration.max_message_size()
+        );
+        let error = AMQPError::new(
+            AMQPHardError::RESOURCEERROR.into(),
+            format!("message of {} bytes is too large", size).into(),
+        );
+        self.internal_rpc.close_connection(
+            error.get_id(),
+            error.get_message().to_string(),
+            class_id,
+            0,
+        );
+        let error = Error::MessageTooLarge(size);
+        self.set_error(error.clone());
+        Err(error)
+    }
+
+    pub(crate) fn handle_body_frame(&self, payload: Vec<u8>) -> Result<()> {
+        self.status.receive(
+            self.id,
+            payload.len(),
+            |receiver, remaining_size, confirm_mode| match receiver {
+                ContentReceiver::Delivery(queue_name, request_id_or_consumer_tag) => {
+                    self.queues.handle_body_frame(
+                        &self,
+                        queue_name.as_str(),
+                        request_id_or_consumer_tag.as_ref().map(ShortString::as_str),
+                        remaining_size,
+                        payload,
+                    );
+                }
+                ContentReceiver::Return => {
+                    self.returned_messages.receive_delivery_content(payload);
+                    if remaining_size == 0 {
+                        self.returned_messages.new_delivery_complete(confirm_mode);
+                    }
+                }
+                ContentReceiver::UnknownConsumer(consumer_tag, delivery_tag) => {
+                    if remaining_size == 0 {
+                        self.handle_unknown_consumer_delivery(consumer_tag, delivery_tag);
+                    }
+                }
+            },
+            |msg| self.handle_invalid_contents(msg, 0, 0),
+        )
+    }
+
+    fn handle_unknown_consumer_delivery(
+        &self,
+        consumer_tag: &ShortString,
+        delivery_tag: DeliveryTag,
+    ) {
+        let policy = self.configuration.unknown_consumer_policy();
+        warn!(
+            target: targets::CHANNEL,
+            "delivery {} received on channel {} for unknown consumer {}, applying {:?}",
+            delivery_tag, self.id, consumer_tag, policy
+        );
+        match policy {
+            UnknownConsumerPolicy::NackRequeue => {
+                let channel = self.clone();
+                self.internal_rpc.register_internal_future(async move {
+                    channel
+                        .basic_nack(
+                            delivery_tag,
+                            BasicNackOptions::default().multiple(false).requeue(true),
+                        )
+                        .await
+                });
+            }
+            UnknownConsumerPolicy::Drop => {}
+            UnknownConsumerPolicy::Error => {
+                let error = AMQPError::new(
+                    AMQPSoftError::NOTFOUND.into(),
+                    format!("delivery received for unknown consumer {}", consumer_tag).into(),
+                );
+                let channel = self.clone();
+                self.internal_rpc.register_internal_future(async move {
+                    // Blame basic.deliver
+                    channel
+                        .do_channel_close(error.get_id(), error.get_message().as_str(), 60, 60)
+                        .await
+                });
+            }
+        }
+    }
+
+    fn before_basic_publish(&self) -> Option<PublisherConfirm> {
+        if self.status.confirm() {
+            let delivery_tag = self.delivery_tag.next();
+            Some(
+                self.acknowledgements
+                    .register_pending(delivery_tag, self.id),
+            )
+        } else {
+            None
+        }
+    }
+
+    fn acknowledgement_error(&self, error: AMQPError, class_id: u16, method_id: u16) -> Result<()> {
+        error!(target: targets::CHANNEL, "Got a bad acknowledgement from server, closing channel");
+        let channel = self.clone();
+        let err = error.clone();
+        self.internal_rpc.register_internal_future(async move {
+            channel
+                .do_channel_close(
+                    error.get_id(),
+                    error.get_message().as_str(),
+                    class_id,
+                    method_id,
+                )
+                .await
+        });
+        Err(Error::ProtocolError(err))
+    }
+
+    fn on_connection_start_ok_sent(
+        &self,
+        resolver: PromiseResolver<Connection>,
+        connection: Connection,
+        credentials: Credentials,
+    ) {
+        self.connection_status
+            .set_connection_step(ConnectionStep::StartOk(resolver, connection, credentials));
+    }
+
+    fn on_connection_open_sent(&self, resolver: PromiseResolver<Connection>) {
+        self.connection_status
+            .set_connection_step(ConnectionStep::Open(resolver));
+    }
+
+    fn on_connection_close_sent(&self) {
+        self.internal_rpc.set_connection_closing();
+    }
+
+    fn on_connection_close_ok_sent(&self, error: Error) {
+        if let Error::ProtocolError(_) = error {
+            self.internal_rpc.set_connection_error(error);
+        } else {
+            self.internal_rpc.set_connection_closed(error);
+        }
+    }
+
+    fn before_channel_close(&self) {
+        self.set_state(ChannelState::Closing);
+    }
+
+    fn before_exchange_delete(&self, exchange: &str) {
+        self.declaration_cache.forget_exchange(exchange);
+    }
+
+    fn before_queue_delete(&self, queue: &str) {
+        self.declaration_cache.forget_queue(queue);
+        self.server_named_queues.forget(queue);
+    }
+
+    fn before_queue_unbind(&self, queue: &str, exchange: &str, routing_key: &str) {
+        self.declaration_cache
+            .forget_binding(queue, exchange, routing_key);
+        self.server_named_queues
+            .forget_binding(queue, exchange, routing_key);
+    }
+
+    fn on_channel_close_ok_sent(&self, error: Error) {
+        self.set_closed(error);
+    }
+
+    fn on_basic_recover_async_sent(&self) {
+        self.outstanding_deliveries.clear();
+        self.queues.drop_prefetched_messages();
+    }
+
+    fn on_basic_ack_sent(&self, multiple: bool, delivery_tag: DeliveryTag) {
+        self.stats.acked();
+        self.outstanding_deliveries
+            .complete(delivery_tag, multiple, Settlement::Ack);
+        if multiple && delivery_tag == 0 {
+            self.queues.drop_prefetched_messages();
+        }
+    }
+
+    fn on_basic_reject_sent(&self, delivery_tag: DeliveryTag) {
+        self.stats.rejected();
+        self.outstanding_deliveries
+            .complete(delivery_tag, false, Settlement::Reject);
+    }
+
+    fn on_basic_nack_sent(&self, multiple: bool, delivery_tag: DeliveryTag) {
+        self.stats.nacked();
+        self.outstanding_deliveries
+            .complete(delivery_tag, multiple, Settlement::Nack);
+        if multiple && delivery_tag == 0 {
+            self.queues.drop_prefetched_messages();
+        }
+    }
+
+    fn tune_connection_configuration(&self, channel_max: u16, frame_max: u32, heartbeat: u16) {
+        // If we disable the heartbeat (0) but the server don't, follow it and enable it too
+        // If both us and the server want heartbeat enabled, pick the lowest value.
+        if self.configuration.heartbeat() == 0
+            || (heartbeat != 0 && heartbeat < self.configuration.heartbeat())
+        {
+            self.configuration.set_heartbeat(heartbeat);
+        }
+
+        if channel_max != 0 {
+            // 0 means we want to take the server's value
+            // If both us and the server specified a channel_max, pick the lowest value.
+            if self.configuration.channel_max() == 0
+                || channel_max < self.configuration.channel_max()
+            {
+                self.configuration.set_channel_max(channel_max);
+            }
+        }
+        if self.configuration.channel_max() == 0 {
+            self.configuration.set_channel_max(u16::max_value());
+        }
+
+        if frame_max != 0 {
+            // 0 means we want to take the server's value
+            // If both us and the server specified a frame_max, pick the lowest value.
+            if self.configuration.frame_max() == 0 || frame_max < self.configuration.frame_max() {
+                self.configuration.set_frame_max(frame_max);
+            }
+        }
+        if self.configuration.frame_max() == 0 {
+            self.configuration.set_frame_max(u32::max_value());
+        }
+    }
+
+    fn on_connection_start_received(&self, method: protocol::connection::Start) -> Result<()> {
+        trace!(target: targets::CONNECTION, "Server sent connection::Start: {:?}", method);
+        let state = self.connection_status.state();
+        if let (
+            ConnectionState::Connecting,
+            Some(ConnectionStep::ProtocolHeader(
+                resolver,
+                connection,
+                credentials,
+                mechanism,
+                options,
+            )),
+        ) = (state.clone(), self.connection_status.connection_step())
+        {
+            let mechanism_str = mechanism.to_string();
+            let locale = options.locale.clone();
+
+            if let Some(AMQPValue::LongString(product)) =
+                method.server_properties.inner().get("product")
+            {
+                self.connection_status
+                    .set_server_product(product.to_string());
+            }
+            if let Some(AMQPValue::LongString(version)) =
+                method.server_properties.inner().get("version")
+            {
+                self.connection_status
+                    .set_server_version(version.to_string());
+            }
+            if let Some(AMQPValue::FieldTable(capabilities)) =
+                method.server_properties.inner().get("capabilities")
+            {
+                self.connection_status.set_per_consumer_qos(matches!(
+                    capabilities.inner().get("per_consumer_qos"),
+                    Some(AMQPValue::Boolean(true))
+                ));
+                self.connection_status.set_update_secret(matches!(
+                    capabilities.inner().get("connection.update_secret"),
+                    Some(AMQPValue::Boolean(true))
+                ));
+            }
+
+            if !method
+                .mechanisms
+                .split_whitespace()
+                .any(|m| m == mechanism_str)
+            {
+                error!(target: targets::CONNECTION, "unsupported mechanism: {}", mechanism);
+            }
+            if !method.locales.split_whitespace().any(|l| l == locale) {
+                error!(target: targets::CONNECTION, "unsupported locale: {}", locale);
+            }
+
+            let client_properties =
+                connection_properties::client_properties(&options.client_properties);
+            self.connection_status
+                .set_client_properties(client_properties.clone());
+
+            let channel = self.clone();
+            self.internal_rpc.register_internal_future(async move {
+                channel
+                    .connection_start_ok(
+                        client_properties,
+                        &mechanism_str,
+                        &credentials.sasl_auth_string(mechanism),
+                        &locale,
+                        resolver,
+                        connection,
+                        credentials,
+                    )
+                    .await
+            });
+            Ok(())
+        } else {
+            error!(target: targets::CONNECTION, "Invalid state: {:?}", state);
+            let error = Error::InvalidConnectionState(state);
+            self.internal_rpc.set_connection_error(error.clone());
+            Err(error)
+        }
+    }
+
+    fn on_connection_secure_received(&self, method: protocol::connection::Secure) -> Result<()> {
+        trace!(target: targets::CONNECTION, "Server sent connection::Secure: {:?}", method);
+
+        let state = self.connection_status.state();
+        if let (ConnectionState::Connecting, Some(ConnectionStep::StartOk(.., credentials))) =
+            (state.clone(), self.connection_status.connection_step())
+        {
+            let channel = self.clone();
+            self.internal_rpc.register_internal_future(async move {
+                channel
+                    .connection_secure_ok(&credentials.rabbit_cr_demo_answer())
+                    .await
+            });
+            Ok(())
+        } else {
+            error!(target: targets::CONNECTION, "Invalid state: {:?}", state);
+            let error = Error::InvalidConnectionState(state);
+            self.internal_rpc.set_connection_error(error.clone());
+            Err(error)
+        }
+    }
+
+    fn on_connection_tune_received(&self, method: protocol::connection::Tune) -> Result<()> {
+        debug!(target: targets::CONNECTION, "Server sent Connection::Tune: {:?}", method);
+
+        let state = self.connection_status.state();
+        if let (
+            ConnectionState::Connecting,
+            Some(ConnectionStep::StartOk(resolver, connection, _)),
+        ) = (state.clone(), self.connection_status.connection_step())
+        {
+            self.tune_connection_configuration(
+                method.channel_max,
+                method.frame_max,
+                method.heartbeat,
+            );
+
+            let channel = self.clone();
+            let configuration = self.configuration.clone();
+            self.internal_rpc.register_internal_future(async move {
+                channel
+                    .connection_tune_ok(
+                        configuration.channel_max(),
+                        configuration.frame_max(),
+                        configuration.heartbeat(),
+                    )
+                    .await
+            });
+            let channel = self.clone();
+            let vhost = self.connection_status.vhost();
+            self.internal_rpc.register_internal_future(async move {
+                channel.connection_open(&vhost, connection, resolver).await
+            });
+            Ok(())
+        } else {
+            error!(target: targets::CONNECTION, "Invalid state: {:?}", state);
+            let error = Error::InvalidConnectionState(state);
+            self.internal_rpc.set_connection_error(error.clone());
+            Err(error)
+        }
+    }
+
+    fn on_connection_open_ok_received(
+        &self,
+        _: protocol::connection::OpenOk,
+        connection: Connection,
+    ) -> Result<()> {
+        let state = self.connection_status.state();
+        if let (ConnectionState::Connecting, Some(ConnectionStep::Open(resolver))) =
+            (state.clone(), self.connection_status.connection_step())
+        {
+            self.connection_status.set_state(ConnectionState::Connected);
+            resolver.swear(Ok(connection));
+            Ok(())
+        } else {
+            error!(target: targets::CONNECTION, "Invalid state: {:?}", state);
+            let error = Error::InvalidConnectionState(state);
+            self.internal_rpc.set_connection_error(error.clone());
+            Err(error)
+        }
+    }
+
+    fn on_connection_close_received(&self, method: protocol::connection::Close) -> Result<()> {
+        let error = AMQPError::try_from(method.clone())
+            .map(|error| {
+                error!(
+                    target: targets::CONNECTION,
+                    "Connection closed on channel {} by {}:{} => {:?} => {}",
+                    self.id, method.class_id, method.method_id, error, method.reply_text
+                );
+                Error::ProtocolError(error)
+            })
+            .unwrap_or_else(|error| {
+                error!(target: targets::CONNECTION, "{}", error);
+                info!(
+                    target: targets::CONNECTION,
+                    "Connection closed on channel {}: {:?}",
+                    self.id,
+                    method
+                );
+                Error::InvalidConnectionState(ConnectionState::Closed)
+            });
+        self.internal_rpc.set_connection_closing();
+        self.frames.drop_pending(error.clone());
+        if let Some(resolver) = self.connection_status.connection_resolver() {
+            resolver.swear(Err(error.clone()));
+        }
+        self.internal_rpc.send_connection_close_ok(error);
+        Ok(())
+    }
+
+    fn on_connection_blocked_received(&self, _method: protocol::connection::Blocked) -> Result<()> {
+        self.connection_status.block();
+        Ok(())
+    }
+
+    fn on_connection_unblocked_received(
+        &self,
+        _method: protocol::connection::Unblocked,
+    ) -> Result<()> {
+        self.connection_status.unblock();
+        self.wake();
+        Ok(())
+    }
+
+    fn on_connection_close_ok_received(&self) -> Result<()> {
+        self.internal_rpc
+            .set_connection_closed(Error::InvalidConnectionState(ConnectionState::Closed));
+        Ok(())
+    }
+
+    fn on_channel_open_ok_received(
+        &self,
+        _method: protocol::channel::OpenOk,
+        resolver: PromiseResolver<Channel>,
+        channel: Channel,
+    ) -> Result<()> {
+        self.set_state(ChannelState::Connected);
+        resolver.swear(Ok(channel));
+        Ok(())
+    }
+
+    fn on_channel_flow_received(&self, method: protocol::channel::Flow) -> Result<()> {
+        self.status.set_send_flow(method.active);
+        let channel = self.clone();
+        self.internal_rpc.register_internal_future(async move {
+            channel
+                .channel_flow_ok(ChannelFlowOkOptions::default().active(method.active))
+                .await
+        });
+        Ok(())
+    }
+
+    fn on_channel_flow_ok_received(
+        &self,
+        method: protocol::channel::FlowOk,
+        resolver: PromiseResolver<Boolean>,
+    ) -> Result<()> {
+        // Nothing to do here, the server just confirmed that we paused/resumed the receiving flow
+        resolver.swear(Ok(method.active));
+        Ok(())
+    }
+
+    fn on_channel_close_received(&self, method: protocol::channel::Close) -> Result<()> {
+        let error = AMQPError::try_from(method.clone())
+            .map(|error| {
+                error!(
+                    target: targets::CHANNEL,
+                    "Channel closed on channel {} by {}:{} => {:?} => {}",
+                    self.id, method.class_id, method.method_id, error, method.reply_text
+                );
+                Error::ProtocolError(error)
+            })
+            .unwrap_or_else(|error| {
+                error!(target: targets::CHANNEL, "{}", error);
+                info!(
+                    target: targets::CHANNEL,
+                    "Channel closed on channel {}: {:?}",
+                    self.id,
+                    method
+                );
+                Error::InvalidChannelState(ChannelState::Closing)
+            });
+        self.status.set_error_cause(error.clone());
+        self.set_state(ChannelState::Closing);
+        // The server won't answer the pending methods anymore: fail them with its error right
+        // away, so that the caller of the method which caused it gets it.
+        self.frames.clear_expected_replies(self.id, error.clone());
+        let channel = self.clone();
+        self.internal_rpc
+            .register_internal_future(async move { channel.channel_close_ok(error).await });
+        Ok(())
+    }
+
+    fn on_channel_close_ok_received(&self) -> Result<()> {
+        self.set_closed(Error::InvalidChannelState(ChannelState::Closed));
+        Ok(())
+    }
+
+    fn on_queue_delete_ok_received(
+        &self,
+        method: protocol::queue::DeleteOk,
+        resolver: PromiseResolver<LongUInt>,
+        queue: ShortString,
+    ) -> Result<()> {
+        self.queues.deregister(queue.as_str());
+        resolver.swear(Ok(method.message_count));
+        Ok(())
+    }
+
+    fn on_queue_purge_ok_received(
+        &self,
+        method: protocol::queue::PurgeOk,
+        resolver: PromiseResolver<LongUInt>,
+    ) -> Result<()> {
+        resolver.swear(Ok(method.message_count));
+        Ok(())
+    }
+
+    fn on_queue_declare_ok_received(
+        &self,
+        method: protocol::queue::DeclareOk,
+        resolver: PromiseResolver<Queue>,
+    ) -> Result<()> {
+        let queue = Queue::new(method.queue, method.message_count, method.consumer_count);
+        self.queues.register(queue.clone().into());
+        resolver.swear(Ok(queue));
+        Ok(())
+    }
+
+    fn on_basic_get_ok_received(
+        &self,
+        method: protocol::basic::GetOk,
+        resolver: PromiseResolver<Option<BasicGetMessage>>,
+        queue: ShortString,
+        no_ack: Boolean,
+    ) -> Result<()> {
+        let class_id = method.get_amqp_class_id();
+        self.stats.delivered();
+        if !no_ack {
+            self.outstanding_deliveries
+                .register_delivery(method.delivery_tag);
+        }
+        self.queues.start_basic_get_delivery(
+            queue.as_str(),
+            BasicGetMessage::new(
+                method.delivery_tag,
+                method.exchange,
+                method.routing_key,
+                method.redelivered,
+                method.message_count,
+            ),
+            resolver,
+        );
+        self.status.set_will_receive(class_id, queue, None);
+        Ok(())
+    }
+
+    fn on_basic_get_empty_received(&self, method: protocol::basic::GetEmpty) -> Result<()> {
+        match self.frames.next_expected_reply(self.id) {
+            Some(Reply::BasicGetOk(resolver, ..)) => {
+                resolver.swear(Ok(None));
+                Ok(())
+            }
+            _ => self.handle_invalid_contents(
+                format!("unexpected basic get empty received on channel {}", self.id),
+                method.get_amqp_class_id(),
+                method.get_amqp_method_id(),
+            ),
+        }
+    }
+
+    fn on_basic_consume_ok_received(
+        &self,
+        method: protocol::basic::ConsumeOk,
+        resolver: PromiseResolver<Consumer>,
+        queue: ShortString,
+        consumer_tag: ShortString,
+        no_ack: Boolean,
+    ) -> Result<()> {
+        if no_ack {
+            self.outstanding_deliveries
+                .register_no_ack_consumer(method.consumer_tag.clone());
+        }
+        let consumer = self
+            .queues
+            .take_resubscribing(consumer_tag.as_str())
+            .unwrap_or_else(|| Consumer::new(method.consumer_tag.clone(), self.executor.clone()));
+        if method.consumer_tag != consumer_tag {
+            debug!(
+                target: targets::CHANNEL,
+                "channel {} requested consumer tag {} but the server assigned {}",
+                self.id, consumer_tag, method.consumer_tag
+            );
+        }
+        consumer.set_tag(method.consumer_tag.clone());
+        consumer.set_channel(self.id, self.internal_rpc.clone());
+        self.queues
+            .register_consumer(queue.as_str(), method.consumer_tag, consumer.clone());
+        resolver.swear(Ok(consumer));
+        Ok(())
+    }
+
+    fn on_basic_deliver_received(&self, method: protocol::basic::Deliver) -> Result<()> {
+        let class_id = method.get_amqp_class_id();
+        self.stats.delivered();
+        let started = self.queues.start_consumer_delivery(
+            method.consumer_tag.as_str(),
+            Delivery::new(
+                method.delivery_tag,
+                method.exchange,
+                method.routing_key,
+                method.redelivered,
+            ),
+        );
+        self.outstanding_deliveries.register_consumer_delivery(
+            method.consumer_tag.as_str(),
+            method.delivery_tag,
+            started.as_ref().map(|(_, stats)| stats.clone()),
+        );
+        if let Some((queue_name, _)) = started {
+            self.status
+                .set_will_receive(class_id, queue_name, Some(method.consumer_tag));
+        } else {
+            // We still need to read the content to stay in sync with the incoming frames
+            self.status
+                .set_will_discard(class_id, method.consumer_tag, method.delivery_tag);
+        }
+        Ok(())
+    }
+
+    fn on_basic_cancel_received(&self, method: protocol::basic::Cancel) -> Result<()> {
+        self.outstanding_deliveries
+            .deregister_consumer(method.consumer_tag.as_str());
+        self.queues
+            .consumer_canceled_by_server(method.consumer_tag.as_str());
+        if !method.nowait {
+            let channel = self.clone();
+            self.internal_rpc.register_internal_future(async move {
+                channel.basic_cancel_ok(method.consumer_tag.as_str()).await
+            });
+        }
+        Ok(())
+    }
+
+    fn on_basic_cancel_ok_received(&self, method: protocol::basic::CancelOk) -> Result<()> {
+        self.outstanding_deliveries
+            .deregister_consumer(method.consumer_tag.as_str());
+        self.queues
+            .deregister_consumer(method.consumer_tag.as_str());
+        Ok(())
+    }
+
+    fn on_basic_ack_received(&self, method: protocol::basic::Ack) -> Result<()> {
+        if self.status.confirm() {
+            if method.multiple {
+                if method.delivery_tag > 0 {
+                    self.acknowledgements
+                        .ack_all_before(method.delivery_tag, self.id)
+                        .or_else(|err| {
+                            self.acknowledgement_error(
+                                err,
+                                method.get_amqp_class_id(),
+                                method.get_amqp_method_id(),
+                            )
+                        })?;
+                } else {
+                    self.acknowledgements.ack_all_pending();
+                }
+            } else {
+                self.acknowledgements
+                    .ack(method.delivery_tag, self.id)
+                    .or_else(|err| {
+                        self.acknowledgement_error(
+                            err,
+                            method.get_amqp_class_id(),
+                            method.get_amqp_method_id(),
+                        )
+                    })?;
+            }
+        }
+        Ok(())
+    }
+
+    fn on_basic_nack_received(&self, method: protocol::basic::Nack) -> Result<()> {
+        if self.status.confirm() {
+            if method.multiple {
+                if method.delivery_tag > 0 {
+                    self.acknowledgements
+                        .nack_all_before(method.delivery_tag, self.id)
+                        .or_else(|err| {
+                            self.acknowledgement_error(
+                                err,
+                                method.get_amqp_class_id(),
+                                method.get_amqp_method_id(),
+                            )
+                        })?;
+                } else {
+                    self.acknowledgements.nack_all_pending();
+                }
+            } else {
+                self.acknowledgements
+                    .nack(method.delivery_tag, self.id)
+                    .or_else(|err| {
+                        self.acknowledgement_error(
+                            err,
+                            method.get_amqp_class_id(),
+                            method.get_amqp_method_id(),
+                        )
+                    })?;
+            }
+        }
+        Ok(())
+    }
+
+    fn on_basic_return_received(&self, method: protocol::basic::Return) -> Result<()> {
+        let class_id = method.get_amqp_class_id();
+        self.returned_messages
+            .start_new_delivery(BasicReturnMessage::new(
+                method.exchange,
+                method.routing_key,
+                method.reply_code,
+                method.reply_text,
+            ));
+        self.status.set_will_receive_return(class_id);
+        Ok(())
+    }
+
+    fn on_basic_recover_ok_received(&self) -> Result<()> {
+        self.outstanding_deliveries.clear();
+        self.queues.drop_prefetched_messages();
+        Ok(())
+    }
+
+    fn on_confirm_select_ok_received(&self) -> Result<()> {
+        self.status.set_confirm();
+        Ok(())
+    }
+
+    fn on_access_request_ok_received(&self, _: protocol::access::RequestOk) -> Result<()> {
+        Ok(())
     }
 }
 
-#[cfg(feature = "codegen")]
-include!(concat!(env!("OUT_DIR"), "/channel.rs"));
-#[cfg(not(feature = "codegen"))]
-include!("generated.rs");
+#[cfg(feature = "codegen")]
+include!(concat!(env!("OUT_DIR"), "/channel.rs"));
+#[cfg(not(feature = "codegen"))]
+include!("generated.rs");
+
+#[cfg(test)]
+mod tests {
+    use crate::test_helpers::*;
+
+    fn return_frames(channel: &Channel) -> Vec<AMQPFrame> {
+        content_frames(
+            channel,
+            basic::AMQPMethod::Return(basic::Return {
+                reply_code: 312,
+                reply_text: "NO_ROUTE".into(),
+                exchange: "".into(),
+                routing_key: "unroutable".into(),
+            }),
+            BasicProperties::default().with_content_type("text/plain".into()),
+            &[b"ret", b"urn"],
+        )
+    }
+
+    fn unroutable_frames(channel: &Channel, routing_key: &str) -> Vec<AMQPFrame> {
+        content_frames(
+            channel,
+            basic::AMQPMethod::Return(basic::Return {
+                reply_code: 312,
+                reply_text: "NO_ROUTE".into(),
+                exchange: "".into(),
+                routing_key: routing_key.into(),
+            }),
+            BasicProperties::default(),
+            &[b"payload"],
+        )
+    }
+
+    fn publish_mandatory(
+        channel: &Channel,
+        routing_key: &'static str,
+    ) -> async_global_executor::Task<Result<MandatoryOutcome>> {
+        use crate::options::BasicPublishOptions;
+
+        let channel = channel.clone();
+        async_global_executor::spawn(async move {
+            channel
+                .basic_publish_mandatory(
+                    "",
+                    routing_key,
+                    BasicPublishOptions::default(),
+                    b"payload".to_vec(),
+                    BasicProperties::default(),
+                )
+                .await
+        })
+    }
+
+    #[test]
+    fn basic_nack_and_republish() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        use crate::{message::Delivery, test_support::FrameCapture};
+
+        let (conn, channel, frames) = connected_channel();
+        channel.status().set_confirm();
+        let capture = FrameCapture::install(&channel);
+        let mut delivery = Delivery::new(1, "orders".into(), "order.created".into(), false);
+        delivery.properties = BasicProperties::default().with_message_id("order-1".into());
+        delivery.receive_content(b"order".to_vec());
+
+        let republish = |delivery: Delivery| {
+            let channel = channel.clone();
+            async_global_executor::spawn(async move {
+                channel
+                    .basic_nack_and_republish(&delivery, "parked", "order.created")
+                    .await
+            })
+        };
+        let confirm = |delivery_tag, ack| {
+            let method = if ack {
+                basic::AMQPMethod::Ack(basic::Ack {
+                    delivery_tag,
+                    multiple: false,
+                })
+            } else {
+                basic::AMQPMethod::Nack(basic::Nack {
+                    delivery_tag,
+                    multiple: false,
+                    requeue: false,
+                })
+            };
+            conn.channels()
+                .handle_frame(AMQPFrame::Method(channel.id(), AMQPClass::Basic(method)))
+                .unwrap();
+        };
+
+        // The server nacks the copy, the delivery must not be nacked
+        let task = republish(delivery.clone());
+        write_frames(&frames, 3);
+        confirm(1, false);
+        assert_eq!(
+            async_global_executor::block_on(task),
+            Err(Error::PublishNacked)
+        );
+        capture.assert_methods(&[(60, 40)]);
+
+        // The server can't route the copy and returns it, the delivery must not be nacked either
+        capture.clear();
+        let task = republish(delivery.clone());
+        write_frames(&frames, 3);
+        for frame in unroutable_frames(&channel, "order.created") {
+            conn.channels().handle_frame(frame).unwrap();
+        }
+        confirm(2, true);
+        assert_eq!(
+            async_global_executor::block_on(task),
+            Err(Error::PublishReturned {
+                reply_code: 312,
+                reply_text: "NO_ROUTE".into(),
+            })
+        );
+        capture.assert_methods(&[(60, 40)]);
+
+        capture.clear();
+        let task = republish(delivery);
+        write_frames(&frames, 3);
+        confirm(3, true);
+        write_frames(&frames, 1);
+        assert!(async_global_executor::block_on(task).is_ok());
+        let captured = capture.captured();
+        match (&captured[0], &captured[1], &captured[2], &captured[3]) {
+            (
+                AMQPFrame::Method(_, AMQPClass::Basic(basic::AMQPMethod::Publish(publish))),
+                AMQPFrame::Header(_, _, header),
+                AMQPFrame::Body(_, body),
+                AMQPFrame::Method(_, AMQPClass::Basic(basic::AMQPMethod::Nack(nack))),
+            ) => {
+                assert_eq!(publish.exchange.as_str(), "parked");
+                assert_eq!(publish.routing_key.as_str(), "order.created");
+                assert!(publish.mandatory);
+                assert_eq!(
+                    header.properties,
+                    BasicProperties::default().with_message_id("order-1".into())
+                );
+                assert_eq!(body, b"order");
+                assert_eq!(nack.delivery_tag, 1);
+                assert!(!nack.requeue);
+            }
+            frames => panic!("unexpected frames: {:?}", frames),
+        }
+    }
+
+    #[test]
+    fn publish_batch() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        use crate::{message::Message, options::BasicPublishOptions, test_support::FrameCapture};
+
+        let (conn, channel, frames) = connected_channel();
+        conn.configuration().set_frame_max(4096);
+        let capture = FrameCapture::install(&channel);
+        let task = {
+            let channel = channel.clone();
+            async_global_executor::spawn(async move {
+                channel
+                    .publish_batch(
+                        "orders",
+                        "created",
+                        BasicPublishOptions::default(),
+                        vec![
+                            Message::new(b"first".to_vec())
+                                .content_type("text/plain")
+                                .persistent(),
+                            Message::new(b"second".to_vec())
+                                .routing_key("audit")
+                                .mandatory(true)
+                                .header("tenant", AMQPValue::LongString("acme".into())),
+                        ],
+                    )
+                    .await
+            })
+        };
+        write_frames(&frames, 6);
+        assert_eq!(async_global_executor::block_on(task).unwrap().len(), 2);
+
+        capture.assert_publish_count(2);
+        let captured = capture.captured();
+        assert_eq!(captured.len(), 6);
+        match (&captured[0], &captured[1]) {
+            (
+                AMQPFrame::Method(_, AMQPClass::Basic(basic::AMQPMethod::Publish(publish))),
+                AMQPFrame::Header(_, _, header),
+            ) => {
+                assert_eq!(publish.exchange.as_str(), "orders");
+                assert_eq!(publish.routing_key.as_str(), "created");
+                assert!(!publish.mandatory);
+                assert_eq!(header.body_size, 5);
+                assert_eq!(
+                    header.properties,
+                    BasicProperties::default()
+                        .with_content_type("text/plain".into())
+                        .with_delivery_mode(2)
+                );
+            }
+            frames => panic!("unexpected frames: {:?}", frames),
+        }
+        match (&captured[3], &captured[4]) {
+            (
+                AMQPFrame::Method(_, AMQPClass::Basic(basic::AMQPMethod::Publish(publish))),
+                AMQPFrame::Header(_, _, header),
+            ) => {
+                let mut headers = FieldTable::default();
+                headers.insert("tenant".into(), AMQPValue::LongString("acme".into()));
+                assert_eq!(publish.routing_key.as_str(), "audit");
+                assert!(publish.mandatory);
+                assert_eq!(
+                    header.properties,
+                    BasicProperties::default().with_headers(headers)
+                );
+            }
+            frames => panic!("unexpected frames: {:?}", frames),
+        }
+    }
+
+    #[test]
+    fn basic_publish_mandatory() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        let (conn, channel, frames) = connected_channel();
+        conn.configuration().set_frame_max(4096);
+
+        // Nothing gets returned within the window
+        let routed = publish_mandatory(&channel, "routed");
+        write_frames(&frames, 3);
+        assert_eq!(
+            async_global_executor::block_on(routed),
+            Ok(MandatoryOutcome::Routed)
+        );
+
+        let returned = publish_mandatory(&channel, "unroutable");
+        write_frames(&frames, 3);
+        for frame in unroutable_frames(&channel, "unroutable") {
+            conn.channels().handle_frame(frame).unwrap();
+        }
+        match async_global_executor::block_on(returned) {
+            Ok(MandatoryOutcome::Returned(message)) => {
+                assert_eq!(message.reply_code, 312);
+                assert_eq!(message.delivery.routing_key.as_str(), "unroutable");
+                assert_eq!(message.delivery.data(), b"payload");
+            }
+            outcome => panic!("expected a returned message, got {:?}", outcome),
+        }
+    }
+
+    #[test]
+    fn interleaved_mandatory_publishes() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        let (conn, channel, frames) = connected_channel();
+        conn.configuration().set_frame_max(4096);
+
+        let first = publish_mandatory(&channel, "first");
+        let second = publish_mandatory(&channel, "second");
+        write_frames(&frames, 6);
+        for frame in unroutable_frames(&channel, "second") {
+            conn.channels().handle_frame(frame).unwrap();
+        }
+        match async_global_executor::block_on(second) {
+            Ok(MandatoryOutcome::Returned(message)) => {
+                assert_eq!(message.delivery.routing_key.as_str(), "second")
+            }
+            outcome => panic!("expected a returned message, got {:?}", outcome),
+        }
+        assert_eq!(
+            async_global_executor::block_on(first),
+            Ok(MandatoryOutcome::Routed)
+        );
+        assert!(futures_lite::future::block_on(channel.wait_for_confirms())
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn cancelled_mandatory_publish() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        let (conn, channel, frames) = connected_channel();
+        conn.configuration().set_frame_max(4096);
+        conn.configuration()
+            .set_mandatory_return_window(Duration::from_secs(60));
+
+        // Dropped while waiting for its return, it must not get matched with the next one
+        let cancelled = publish_mandatory(&channel, "unroutable");
+        write_frames(&frames, 3);
+        assert_eq!(async_global_executor::block_on(cancelled.cancel()), None);
+
+        let returned = publish_mandatory(&channel, "unroutable");
+        write_frames(&frames, 3);
+        for frame in unroutable_frames(&channel, "unroutable") {
+            conn.channels().handle_frame(frame).unwrap();
+        }
+        match async_global_executor::block_on(returned) {
+            Ok(MandatoryOutcome::Returned(message)) => {
+                assert_eq!(message.delivery.routing_key.as_str(), "unroutable")
+            }
+            outcome => panic!("expected a returned message, got {:?}", outcome),
+        }
+    }
+
+    fn check_return_and_delivery(channel: &Channel, consumer: &mut Consumer) {
+        let returned = futures_lite::future::block_on(channel.wait_for_confirms()).unwrap();
+        assert_eq!(returned.len(), 1);
+        assert_eq!(returned[0].reply_code, 312);
+        assert_eq!(returned[0].reply_text.as_str(), "NO_ROUTE");
+        assert_eq!(returned[0].delivery.routing_key.as_str(), "unroutable");
+        assert_eq!(
+            returned[0].delivery.properties.content_type(),
+            &Some("text/plain".into())
+        );
+        assert_eq!(returned[0].delivery.data(), b"return");
+
+        let waker = waker_fn(|| {});
+        let mut cx = Context::from_waker(&waker);
+        match Pin::new(consumer).poll_next(&mut cx) {
+            Poll::Ready(Some(Ok((_, delivery)))) => {
+                assert_eq!(delivery.delivery_tag, 1);
+                assert_eq!(delivery.data(), b"hello");
+            }
+            _ => panic!("expected a delivery"),
+        }
+    }
+
+    #[test]
+    fn basic_return_then_deliver() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        let consumer_tag = ShortString::from("consumer-tag");
+        let mut consumer = Consumer::new(consumer_tag.clone(), DefaultExecutor::default().unwrap());
+        let (conn, channel) = channel_with_consumer(&consumer);
+
+        let mut frames = return_frames(&channel).into_iter();
+        conn.channels()
+            .handle_frame(frames.next().unwrap())
+            .unwrap();
+        assert_eq!(
+            channel.status().receiver_state(),
+            ChannelReceiverState::WillReceiveReturn(60)
+        );
+        conn.channels()
+            .handle_frame(frames.next().unwrap())
+            .unwrap();
+        assert_eq!(
+            channel.status().receiver_state(),
+            ChannelReceiverState::ReceivingReturn(6)
+        );
+        conn.channels()
+            .handle_frame(frames.next().unwrap())
+            .unwrap();
+        assert_eq!(
+            channel.status().receiver_state(),
+            ChannelReceiverState::ReceivingReturn(3)
+        );
+        conn.channels()
+            .handle_frame(frames.next().unwrap())
+            .unwrap();
+        for frame in deliver_frames(&channel, &consumer_tag) {
+            conn.channels().handle_frame(frame).unwrap();
+        }
+
+        check_return_and_delivery(&channel, &mut consumer);
+    }
+
+    #[test]
+    fn basic_deliver_then_return() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        let consumer_tag = ShortString::from("consumer-tag");
+        let mut consumer = Consumer::new(consumer_tag.clone(), DefaultExecutor::default().unwrap());
+        let (conn, channel) = channel_with_consumer(&consumer);
+
+        for frame in deliver_frames(&channel, &consumer_tag) {
+            conn.channels().handle_frame(frame).unwrap();
+        }
+        for frame in return_frames(&channel) {
+            conn.channels().handle_frame(frame).unwrap();
+        }
+
+        check_return_and_delivery(&channel, &mut consumer);
+    }
+
+    #[test]
+    fn basic_deliver_unknown_consumer() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        use crate::queue::{Queue, QueueState};
+
+        let consumer_tag = ShortString::from("consumer-tag");
+        let consumer = Consumer::new(consumer_tag.clone(), DefaultExecutor::default().unwrap());
+        let (conn, channel, frames) = connected_channel();
+        let mut queue: QueueState = Queue::new("consumed".into(), 0, 0).into();
+        queue.register_consumer(consumer_tag.clone(), consumer);
+        channel.register_queue(queue);
+
+        // The server cancels the consumer while a delivery for it is already in flight
+        let cancel = AMQPClass::Basic(basic::AMQPMethod::Cancel(basic::Cancel {
+            consumer_tag: consumer_tag.clone(),
+            nowait: true,
+        }));
+        conn.channels()
+            .handle_frame(AMQPFrame::Method(channel.id(), cancel))
+            .unwrap();
+        let mut deliver = deliver_frames(&channel, &consumer_tag).into_iter();
+        conn.channels()
+            .handle_frame(deliver.next().unwrap())
+            .unwrap();
+        assert_eq!(
+            channel.status().receiver_state(),
+            ChannelReceiverState::WillDiscardContent(60, consumer_tag.clone(), 1)
+        );
+        conn.channels()
+            .handle_frame(deliver.next().unwrap())
+            .unwrap();
+        assert_eq!(
+            channel.status().receiver_state(),
+            ChannelReceiverState::DiscardingContent(consumer_tag, 1, 5)
+        );
+        for frame in deliver {
+            conn.channels().handle_frame(frame).unwrap();
+        }
+        assert_eq!(channel.status().state(), ChannelState::Connected);
+
+        let nack = loop {
+            if let Some((frame, resolver)) = frames.pop(true) {
+                if let Some(resolver) = resolver {
+                    resolver.swear(Ok(()));
+                }
+                break frame;
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        };
+        assert_eq!(
+            nack,
+            AMQPFrame::Method(
+                channel.id(),
+                AMQPClass::Basic(basic::AMQPMethod::Nack(basic::Nack {
+                    delivery_tag: 1,
+                    multiple: false,
+                    requeue: true,
+                }))
+            )
+        );
+    }
+
+    #[test]
+    fn basic_deliver_oversized() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        use crate::queue::{Queue, QueueState};
+        use amq_protocol::protocol::channel;
+
+        let consumer_tag = ShortString::from("consumer-tag");
+        let mut consumer = Consumer::new(consumer_tag.clone(), DefaultExecutor::default().unwrap());
+        let (conn, channel, frames) = connected_channel();
+        conn.configuration().set_max_message_size(4);
+        let mut queue: QueueState = Queue::new("consumed".into(), 0, 0).into();
+        queue.register_consumer(consumer_tag.clone(), consumer.clone());
+        channel.register_queue(queue);
+
+        let mut deliver = deliver_frames(&channel, &consumer_tag).into_iter();
+        conn.channels()
+            .handle_frame(deliver.next().unwrap())
+            .unwrap();
+        conn.channels()
+            .handle_frame(deliver.next().unwrap())
+            .unwrap();
+        assert_eq!(
+            channel.status().receiver_state(),
+            ChannelReceiverState::DroppingContent(5)
+        );
+        // The body frames get drained without reaching the consumer
+        for frame in deliver {
+            conn.channels().handle_frame(frame).unwrap();
+        }
+
+        let waker = waker_fn(|| {});
+        let mut cx = Context::from_waker(&waker);
+        match Pin::new(&mut consumer).poll_next(&mut cx) {
+            Poll::Ready(Some(Err(error))) => assert_eq!(error, Error::MessageTooLarge(5)),
+            _ => panic!("expected an error"),
+        }
+
+        let close = loop {
+            if let Some((frame, resolver)) = frames.pop(true) {
+                if let Some(resolver) = resolver {
+                    resolver.swear(Ok(()));
+                }
+                break frame;
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        };
+        match close {
+            AMQPFrame::Method(id, AMQPClass::Channel(channel::AMQPMethod::Close(close))) => {
+                assert_eq!(id, channel.id());
+                assert_eq!(close.reply_code, 311);
+            }
+            frame => panic!("expected channel.close, got {:?}", frame),
+        }
+        assert_eq!(channel.status().state(), ChannelState::Closing);
+    }
+
+    #[test]
+    fn basic_deliver_oversized_close_connection() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        use crate::{
+            queue::{Queue, QueueState},
+            OversizedMessagePolicy,
+        };
+
+        let consumer_tag = ShortString::from("consumer-tag");
+        let consumer = Consumer::new(consumer_tag.clone(), DefaultExecutor::default().unwrap());
+        let (conn, channel, _) = connected_channel();
+        conn.configuration().set_max_message_size(4);
+        conn.configuration()
+            .set_oversized_message_policy(OversizedMessagePolicy::CloseConnection);
+        let mut queue: QueueState = Queue::new("consumed".into(), 0, 0).into();
+        queue.register_consumer(consumer_tag.clone(), consumer);
+        channel.register_queue(queue);
+
+        let mut deliver = deliver_frames(&channel, &consumer_tag).into_iter();
+        conn.channels()
+            .handle_frame(deliver.next().unwrap())
+            .unwrap();
+        assert_eq!(
+            conn.channels().handle_frame(deliver.next().unwrap()),
+            Err(Error::MessageTooLarge(5))
+        );
+        assert_eq!(channel.status().state(), ChannelState::Error);
+    }
+
+    #[test]
+    fn malformed_content_header() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        let (conn, channel, _) = connected_channel();
+        let header_frame = AMQPFrame::Header(
+            channel.id(),
+            60,
+            Box::new(AMQPContentHeader {
+                class_id: 60,
+                weight: 1,
+                body_size: 0,
+                properties: BasicProperties::default(),
+            }),
+        );
+        match conn.channels().handle_frame(header_frame) {
+            Err(Error::ProtocolError(error)) => assert_eq!(error.get_id(), 501),
+            res => panic!("expected a frame error, got {:?}", res),
+        }
+
+        // A header whose class doesn't match the one of the method it follows
+        channel
+            .status()
+            .set_will_receive(60, "queue".into(), Some("consumer".into()));
+        let header_frame = AMQPFrame::Header(
+            channel.id(),
+            50,
+            Box::new(AMQPContentHeader {
+                class_id: 50,
+                weight: 0,
+                body_size: 0,
+                properties: BasicProperties::default(),
+            }),
+        );
+        match conn.channels().handle_frame(header_frame) {
+            Err(Error::ProtocolError(error)) => assert_eq!(error.get_id(), 501),
+            res => panic!("expected a frame error, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn redeclare_server_named_queues() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        use crate::options::{BasicConsumeOptions, QueueBindOptions, QueueDeclareOptions};
+        use amq_protocol::protocol::queue;
+
+        let (conn, channel, frames) = connected_channel();
+        let declare_ok = |name: &str| {
+            AMQPFrame::Method(
+                channel.id(),
+                AMQPClass::Queue(queue::AMQPMethod::DeclareOk(queue::DeclareOk {
+                    queue: name.into(),
+                    message_count: 0,
+                    consumer_count: 0,
+                })),
+            )
+        };
+        let bind_ok = || {
+            AMQPFrame::Method(
+                channel.id(),
+                AMQPClass::Queue(queue::AMQPMethod::BindOk(queue::BindOk {})),
+            )
+        };
+        let exclusive = QueueDeclareOptions::default().exclusive(true);
+
+        let task = {
+            let channel = channel.clone();
+            async_global_executor::spawn(async move {
+                channel
+                    .queue_declare("", exclusive, FieldTable::default())
+                    .await
+            })
+        };
+        answer_next_frame(&conn, &frames, declare_ok("amq.gen-1"));
+        let declared = async_global_executor::block_on(task).unwrap();
+        assert_eq!(declared.name().as_str(), "amq.gen-1");
+        assert!(declared.is_server_named());
+
+        let task = {
+            let channel = channel.clone();
+            async_global_executor::spawn(async move {
+                channel
+                    .queue_bind(
+                        "amq.gen-1",
+                        "logs",
+                        "info",
+                        QueueBindOptions::default(),
+                        FieldTable::default(),
+                    )
+                    .await
+            })
+        };
+        answer_next_frame(&conn, &frames, bind_ok());
+        assert!(async_global_executor::block_on(task).is_ok());
+
+        let task = {
+            let channel = channel.clone();
+            async_global_executor::spawn(async move {
+                channel
+                    .basic_consume(
+                        "amq.gen-1",
+                        "reply-consumer",
+                        BasicConsumeOptions::default(),
+                        FieldTable::default(),
+                    )
+                    .await
+            })
+        };
+        answer_next_frame(
+            &conn,
+            &frames,
+            AMQPFrame::Method(
+                channel.id(),
+                AMQPClass::Basic(basic::AMQPMethod::ConsumeOk(basic::ConsumeOk {
+                    consumer_tag: "reply-consumer".into(),
+                })),
+            ),
+        );
+        let consumer = async_global_executor::block_on(task).unwrap();
+
+        // The server forgot about the queue, declare it again and get a new name
+        let task = {
+            let channel = channel.clone();
+            async_global_executor::spawn(
+                async move { channel.redeclare_server_named_queues().await },
+            )
+        };
+        match answer_next_frame(&conn, &frames, declare_ok("amq.gen-2")) {
+            AMQPFrame::Method(_, AMQPClass::Queue(queue::AMQPMethod::Declare(declare))) => {
+                assert_eq!(declare.queue.as_str(), "");
+                assert!(declare.exclusive);
+            }
+            frame => panic!("unexpected frame: {:?}", frame),
+        }
+        match answer_next_frame(&conn, &frames, bind_ok()) {
+            AMQPFrame::Method(_, AMQPClass::Queue(queue::AMQPMethod::Bind(bind))) => {
+                assert_eq!(bind.queue.as_str(), "amq.gen-2");
+                assert_eq!(bind.exchange.as_str(), "logs");
+                assert_eq!(bind.routing_key.as_str(), "info");
+            }
+            frame => panic!("unexpected frame: {:?}", frame),
+        }
+        let redeclared = async_global_executor::block_on(task).unwrap();
+        assert_eq!(redeclared.len(), 1);
+        assert_eq!(redeclared[0].name().as_str(), "amq.gen-2");
+        assert!(redeclared[0].is_server_named());
+        assert_eq!(consumer.subscription().unwrap().queue.as_str(), "amq.gen-2");
+    }
+
+    #[test]
+    fn redeclare_server_named_queues_on_replacement_channel() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        use crate::options::QueueDeclareOptions;
+        use amq_protocol::protocol::queue;
+
+        let (conn, failed, frames) = connected_channel();
+        let declare_ok = |channel_id: u16, name: &str| {
+            AMQPFrame::Method(
+                channel_id,
+                AMQPClass::Queue(queue::AMQPMethod::DeclareOk(queue::DeclareOk {
+                    queue: name.into(),
+                    message_count: 0,
+                    consumer_count: 0,
+                })),
+            )
+        };
+
+        let task = {
+            let channel = failed.clone();
+            async_global_executor::spawn(async move {
+                channel
+                    .queue_declare(
+                        "",
+                        QueueDeclareOptions::default().exclusive(true),
+                        FieldTable::default(),
+                    )
+                    .await
+            })
+        };
+        answer_next_frame(&conn, &frames, declare_ok(failed.id(), "amq.gen-1"));
+        assert!(async_global_executor::block_on(task).is_ok());
+
+        failed.set_state(ChannelState::Error);
+        let replacement = conn.channels().create(conn.closer()).unwrap();
+        replacement.set_state(ChannelState::Connected);
+
+        let task = {
+            let channel = replacement.clone();
+            async_global_executor::spawn(
+                async move { channel.redeclare_server_named_queues().await },
+            )
+        };
+        match answer_next_frame(&conn, &frames, declare_ok(replacement.id(), "amq.gen-2")) {
+            AMQPFrame::Method(id, AMQPClass::Queue(queue::AMQPMethod::Declare(declare))) => {
+                assert_eq!(id, replacement.id());
+                assert_eq!(declare.queue.as_str(), "");
+                assert!(declare.exclusive);
+            }
+            frame => panic!("unexpected frame: {:?}", frame),
+        }
+        let redeclared = async_global_executor::block_on(task).unwrap();
+        assert_eq!(redeclared.len(), 1);
+        assert_eq!(redeclared[0].name().as_str(), "amq.gen-2");
+    }
+
+    #[test]
+    fn declare_ttl_queue() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        use crate::options::QueueDeclareOptions;
+        use amq_protocol::protocol::queue;
+
+        let (conn, channel, frames) = connected_channel();
+
+        assert_eq!(
+            async_global_executor::block_on(channel.declare_ttl_queue(
+                "expiring",
+                0,
+                QueueDeclareOptions::default()
+            )),
+            Err(Error::InvalidArgument(
+                "the message TTL must be greater than 0".into()
+            ))
+        );
+        assert!(frames.pop(true).is_none());
+
+        let task = {
+            let channel = channel.clone();
+            async_global_executor::spawn(async move {
+                channel
+                    .declare_ttl_queue_with_dlx(
+                        "expiring",
+                        60_000,
+                        "expired",
+                        "",
+                        QueueDeclareOptions::default(),
+                    )
+                    .await
+            })
+        };
+        let declare_ok = AMQPFrame::Method(
+            channel.id(),
+            AMQPClass::Queue(queue::AMQPMethod::DeclareOk(queue::DeclareOk {
+                queue: "expiring".into(),
+                message_count: 0,
+                consumer_count: 0,
+            })),
+        );
+        match answer_next_frame(&conn, &frames, declare_ok) {
+            AMQPFrame::Method(_, AMQPClass::Queue(queue::AMQPMethod::Declare(declare))) => {
+                let mut expected = FieldTable::default();
+                expected.insert("x-message-ttl".into(), AMQPValue::LongLongInt(60_000));
+                expected.insert(
+                    "x-dead-letter-exchange".into(),
+                    AMQPValue::LongString("expired".into()),
+                );
+                assert_eq!(declare.queue.as_str(), "expiring");
+                assert_eq!(declare.arguments, expected);
+            }
+            frame => panic!("unexpected frame: {:?}", frame),
+        }
+        assert!(async_global_executor::block_on(task).is_ok());
+    }
+
+    #[test]
+    fn declare_max_length_queue() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        use crate::{options::QueueDeclareOptions, OverflowBehavior};
+        use amq_protocol::protocol::queue;
+
+        let (conn, channel, frames) = connected_channel();
+        let task = {
+            let channel = channel.clone();
+            async_global_executor::spawn(async move {
+                channel
+                    .declare_max_length_bytes_queue(
+                        "bounded",
+                        1024,
+                        OverflowBehavior::RejectPublishDlx,
+                        QueueDeclareOptions::default(),
+                    )
+                    .await
+            })
+        };
+        let declare_ok = AMQPFrame::Method(
+            channel.id(),
+            AMQPClass::Queue(queue::AMQPMethod::DeclareOk(queue::DeclareOk {
+                queue: "bounded".into(),
+                message_count: 0,
+                consumer_count: 0,
+            })),
+        );
+        match answer_next_frame(&conn, &frames, declare_ok) {
+            AMQPFrame::Method(_, AMQPClass::Queue(queue::AMQPMethod::Declare(declare))) => {
+                let mut expected = FieldTable::default();
+                expected.insert("x-max-length-bytes".into(), AMQPValue::LongLongInt(1024));
+                expected.insert(
+                    "x-overflow".into(),
+                    AMQPValue::LongString("reject-publish-dlx".into()),
+                );
+                assert_eq!(declare.arguments, expected);
+            }
+            frame => panic!("unexpected frame: {:?}", frame),
+        }
+        assert!(async_global_executor::block_on(task).is_ok());
+
+        assert_eq!(
+            async_global_executor::block_on(channel.declare_max_length_queue(
+                "bounded",
+                u64::MAX,
+                OverflowBehavior::DropHead,
+                QueueDeclareOptions::default()
+            )),
+            Err(Error::InvalidArgument(format!(
+                "the maximum length is too large: {}",
+                u64::MAX
+            )))
+        );
+    }
+
+    #[test]
+    fn basic_consume_exclusive_conflict() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        use crate::options::BasicConsumeOptions;
+        use amq_protocol::protocol::channel;
+
+        let (conn, channel, frames) = connected_channel();
+        let task = {
+            let channel = channel.clone();
+            async_global_executor::spawn(async move {
+                channel
+                    .basic_consume(
+                        "exclusive",
+                        "contender",
+                        BasicConsumeOptions::default().exclusive(true),
+                        FieldTable::default(),
+                    )
+                    .await
+            })
+        };
+        let close = AMQPFrame::Method(
+            channel.id(),
+            AMQPClass::Channel(channel::AMQPMethod::Close(channel::Close {
+                reply_code: 403,
+                reply_text: "ACCESS_REFUSED - queue 'exclusive' in vhost '/' in exclusive use"
+                    .into(),
+                class_id: 60,
+                method_id: 20,
+            })),
+        );
+        answer_next_frame(&conn, &frames, close);
+        // Resolved by the close itself, without waiting for the channel to be torn down
+        match async_global_executor::block_on(task) {
+            Err(error) => {
+                assert!(error.is_access_refused());
+                match error {
+                    Error::ProtocolError(error) => {
+                        assert_eq!(error.get_id(), 403);
+                        assert!(error.get_message().contains("'exclusive'"));
+                    }
+                    error => panic!("expected a protocol error, got {:?}", error),
+                }
+            }
+            Ok(consumer) => panic!("expected an error, got {:?}", consumer),
+        }
+    }
+
+    #[test]
+    fn basic_consume_with_cancel_callback() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        use crate::options::{BasicCancelOptions, BasicConsumeOptions};
+        use amq_protocol::protocol::basic;
+        use futures_lite::stream::StreamExt;
+
+        let (conn, channel, frames) = connected_channel();
+        let canceled = Arc::new(Mutex::new(Vec::new()));
+        let consume = |tag: &'static str| {
+            let channel = channel.clone();
+            let canceled = canceled.clone();
+            let task = async_global_executor::spawn(async move {
+                channel
+                    .basic_consume_with_cancel_callback(
+                        "consumed",
+                        tag,
+                        move || canceled.lock().push(tag),
+                        BasicConsumeOptions::default(),
+                        FieldTable::default(),
+                    )
+                    .await
+            });
+            let consume_ok = AMQPFrame::Method(
+                channel.id(),
+                AMQPClass::Basic(basic::AMQPMethod::ConsumeOk(basic::ConsumeOk {
+                    consumer_tag: tag.into(),
+                })),
+            );
+            answer_next_frame(&conn, &frames, consume_ok);
+            async_global_executor::block_on(task).unwrap()
+        };
+        let mut by_server = consume("by-server");
+        let mut by_client = consume("by-client");
+
+        // The queue got deleted
+        conn.channels()
+            .handle_frame(AMQPFrame::Method(
+                channel.id(),
+                AMQPClass::Basic(basic::AMQPMethod::Cancel(basic::Cancel {
+                    consumer_tag: "by-server".into(),
+                    nowait: true,
+                })),
+            ))
+            .unwrap();
+        assert!(async_global_executor::block_on(by_server.next()).is_none());
+        assert_eq!(*canceled.lock(), vec!["by-server"]);
+
+        let task = {
+            let channel = channel.clone();
+            async_global_executor::spawn(async move {
+                channel
+                    .basic_cancel("by-client", BasicCancelOptions::default())
+                    .await
+            })
+        };
+        let cancel_ok = AMQPFrame::Method(
+            channel.id(),
+            AMQPClass::Basic(basic::AMQPMethod::CancelOk(basic::CancelOk {
+                consumer_tag: "by-client".into(),
+            })),
+        );
+        answer_next_frame(&conn, &frames, cancel_ok);
+        async_global_executor::block_on(task).unwrap();
+        assert!(async_global_executor::block_on(by_client.next()).is_none());
+        assert_eq!(*canceled.lock(), vec!["by-server"]);
+    }
+
+    #[test]
+    fn channel_close_gracefully() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        use crate::{
+            options::{BasicAckOptions, BasicConsumeOptions},
+            test_support::FrameCapture,
+        };
+        use amq_protocol::protocol::{basic, channel};
+        use futures_lite::stream::StreamExt;
+
+        let (conn, channel, frames) = connected_channel();
+        let consume = |consumer_tag: &'static str| {
+            let task = {
+                let channel = channel.clone();
+                async_global_executor::spawn(async move {
+                    channel
+                        .basic_consume(
+                            "queue",
+                            consumer_tag,
+                            BasicConsumeOptions::default(),
+                            FieldTable::default(),
+                        )
+                        .await
+                })
+            };
+            answer_next_frame(
+                &conn,
+                &frames,
+                AMQPFrame::Method(
+                    channel.id(),
+                    AMQPClass::Basic(basic::AMQPMethod::ConsumeOk(basic::ConsumeOk {
+                        consumer_tag: consumer_tag.into(),
+                    })),
+                ),
+            );
+            async_global_executor::block_on(task).unwrap()
+        };
+        let mut first = consume("first");
+        let mut second = consume("second");
+        for (consumer_tag, delivery_tag) in [("first", 1), ("second", 2)].iter() {
+            for frame in content_frames(
+                &channel,
+                basic::AMQPMethod::Deliver(basic::Deliver {
+                    consumer_tag: (*consumer_tag).into(),
+                    delivery_tag: *delivery_tag,
+                    redelivered: false,
+                    exchange: "".into(),
+                    routing_key: "queue".into(),
+                }),
+                BasicProperties::default(),
+                &[b"task"],
+            ) {
+                conn.channels().handle_frame(frame).unwrap();
+            }
+        }
+
+        let capture = FrameCapture::install(&channel);
+        let task = {
+            let channel = channel.clone();
+            async_global_executor::spawn(async move {
+                channel
+                    .close_gracefully(200, "Normal shutdown", Duration::from_secs(5))
+                    .await
+            })
+        };
+        for consumer_tag in &["first", "second"] {
+            answer_next_frame(
+                &conn,
+                &frames,
+                AMQPFrame::Method(
+                    channel.id(),
+                    AMQPClass::Basic(basic::AMQPMethod::CancelOk(basic::CancelOk {
+                        consumer_tag: (*consumer_tag).into(),
+                    })),
+                ),
+            );
+        }
+        // The deliveries are still there once the consumers got canceled, settle them
+        let acks = vec![&mut first, &mut second]
+            .into_iter()
+            .map(|consumer| {
+                let (channel, delivery) = async_global_executor::block_on(consumer.next())
+                    .unwrap()
+                    .unwrap();
+                async_global_executor::spawn(async move {
+                    channel
+                        .basic_ack(delivery.delivery_tag, BasicAckOptions::default())
+                        .await
+                })
+            })
+            .collect::<Vec<_>>();
+        // The acks, then the heartbeat flushing them
+        write_frames(&frames, 3);
+        for ack in acks {
+            async_global_executor::block_on(ack).unwrap();
+        }
+        answer_next_frame(
+            &conn,
+            &frames,
+            AMQPFrame::Method(
+                channel.id(),
+                AMQPClass::Channel(channel::AMQPMethod::CloseOk(channel::CloseOk {})),
+            ),
+        );
+        async_global_executor::block_on(task).unwrap();
+        // basic.cancel twice, basic.ack twice, then channel.close
+        capture.assert_methods(&[(60, 30), (60, 30), (60, 80), (60, 80), (20, 40)]);
+        assert_eq!(channel.status().state(), ChannelState::Closed);
+        assert!(async_global_executor::block_on(first.next()).is_none());
+        assert!(async_global_executor::block_on(second.next()).is_none());
+    }
+
+    #[test]
+    fn channel_close_gracefully_unanswered_cancel() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        use crate::options::BasicConsumeOptions;
+        use amq_protocol::protocol::{basic, channel};
+
+        let (conn, channel, frames) = connected_channel();
+        let task = {
+            let channel = channel.clone();
+            async_global_executor::spawn(async move {
+                channel
+                    .basic_consume(
+                        "queue",
+                        "consumer",
+                        BasicConsumeOptions::default(),
+                        FieldTable::default(),
+                    )
+                    .await
+            })
+        };
+        answer_next_frame(
+            &conn,
+            &frames,
+            AMQPFrame::Method(
+                channel.id(),
+                AMQPClass::Basic(basic::AMQPMethod::ConsumeOk(basic::ConsumeOk {
+                    consumer_tag: "consumer".into(),
+                })),
+            ),
+        );
+        let _consumer = async_global_executor::block_on(task).unwrap();
+
+        let started = std::time::Instant::now();
+        let task = {
+            let channel = channel.clone();
+            async_global_executor::spawn(async move {
+                channel
+                    .close_gracefully(200, "Normal shutdown", Duration::from_millis(100))
+                    .await
+            })
+        };
+        // The basic.cancel doesn't get answered before the timeout, the channel.close follows
+        write_frames(&frames, 1);
+        assert_eq!(
+            answer_next_frame(
+                &conn,
+                &frames,
+                AMQPFrame::Method(
+                    channel.id(),
+                    AMQPClass::Basic(basic::AMQPMethod::CancelOk(basic::CancelOk {
+                        consumer_tag: "consumer".into(),
+                    })),
+                ),
+            ),
+            AMQPFrame::Method(
+                channel.id(),
+                AMQPClass::Channel(channel::AMQPMethod::Close(channel::Close {
+                    reply_code: 200,
+                    reply_text: "Normal shutdown".into(),
+                    class_id: 0,
+                    method_id: 0,
+                })),
+            )
+        );
+        assert!(started.elapsed() < Duration::from_secs(5));
+        conn.channels()
+            .handle_frame(AMQPFrame::Method(
+                channel.id(),
+                AMQPClass::Channel(channel::AMQPMethod::CloseOk(channel::CloseOk {})),
+            ))
+            .unwrap();
+        assert!(async_global_executor::block_on(task).is_ok());
+        assert_eq!(channel.status().state(), ChannelState::Closed);
+    }
+
+    #[test]
+    fn publish_on_closed_channel() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        use crate::{
+            options::{BasicPublishOptions, QueueDeclareOptions},
+            test_support::FrameCapture,
+        };
+        use amq_protocol::protocol::channel;
+
+        let (conn, channel, _) = connected_channel();
+        assert!(channel.is_usable());
+        let capture = FrameCapture::install(&channel);
+        conn.channels()
+            .handle_frame(AMQPFrame::Method(
+                channel.id(),
+                AMQPClass::Channel(channel::AMQPMethod::Close(channel::Close {
+                    reply_code: 406,
+                    reply_text: "PRECONDITION_FAILED - unknown delivery tag 42".into(),
+                    class_id: 60,
+                    method_id: 80,
+                })),
+            ))
+            .unwrap();
+        assert!(!channel.is_usable());
+
+        let check = |error: Error| match error {
+            Error::ProtocolError(error) => assert_eq!(error.get_id(), 406),
+            error => panic!("expected the error closing the channel, got {:?}", error),
+        };
+        check(
+            async_global_executor::block_on(channel.basic_publish(
+                "",
+                "queue",
+                BasicPublishOptions::default(),
+                b"late".to_vec(),
+                BasicProperties::default(),
+            ))
+            .unwrap_err(),
+        );
+        check(
+            async_global_executor::block_on(channel.queue_declare(
+                "queue",
+                QueueDeclareOptions::default(),
+                FieldTable::default(),
+            ))
+            .unwrap_err(),
+        );
+        check(channel.status().error_cause().unwrap());
+        capture.assert_publish_count(0);
+        assert!(!capture.methods().contains(&(50, 10)));
+    }
+
+    #[test]
+    fn strict_field_validation() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        use crate::{
+            options::{BasicPublishOptions, QueueDeclareOptions},
+            test_support::FrameCapture,
+        };
+
+        let (conn, channel, _) = connected_channel();
+        assert_eq!(
+            conn.configuration().strict_field_validation(),
+            cfg!(debug_assertions)
+        );
+        assert_eq!(
+            ConnectionProperties::default().strict_field_validation,
+            cfg!(debug_assertions)
+        );
+        conn.configuration().set_strict_field_validation(true);
+        let capture = FrameCapture::install(&channel);
+
+        let queue = "q".repeat(256);
+        assert_eq!(
+            async_global_executor::block_on(channel.queue_declare(
+                &queue,
+                QueueDeclareOptions::default(),
+                FieldTable::default(),
+            ))
+            .unwrap_err(),
+            Error::InvalidName {
+                field: "queue name",
+                value: queue,
+                reason: "longer than 255 bytes",
+            }
+        );
+        let mut headers = FieldTable::default();
+        headers.insert("1st".into(), AMQPValue::Boolean(true));
+        assert_eq!(
+            async_global_executor::block_on(channel.basic_publish(
+                "",
+                "queue",
+                BasicPublishOptions::default(),
+                b"headers".to_vec(),
+                BasicProperties::default().with_headers(headers),
+            ))
+            .unwrap_err(),
+            Error::InvalidName {
+                field: "header",
+                value: "1st".into(),
+                reason: "doesn't start with a letter, '$' or '#'",
+            }
+        );
+        // Nothing reached the server
+        capture.assert_methods(&[]);
+        assert!(channel.is_usable());
+    }
+
+    #[test]
+    fn channel_error_handler() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        use crate::protocol::{AMQPError, AMQPHardError};
+
+        let (conn, first, _) = connected_channel();
+        let second = conn.channels().create(conn.closer()).unwrap();
+        second.set_state(ChannelState::Connected);
+        let errors = Arc::new(Mutex::new(Vec::new()));
+        let handler = |id: u16| {
+            let errors = errors.clone();
+            move |error: Error| errors.lock().push((id, error))
+        };
+        let (first_id, second_id) = (first.id(), second.id());
+        let first = first.with_error_handler(handler(first_id));
+        let _second = second.with_error_handler(handler(second_id));
+
+        // A content header without a method fails the first channel right away
+        let header_frame = AMQPFrame::Header(
+            first.id(),
+            60,
+            Box::new(AMQPContentHeader {
+                class_id: 60,
+                weight: 1,
+                body_size: 0,
+                properties: BasicProperties::default(),
+            }),
+        );
+        let frame_error = match conn.channels().handle_frame(header_frame) {
+            Err(error) => error,
+            res => panic!("expected a frame error, got {:?}", res),
+        };
+        assert_eq!(first.status().state(), ChannelState::Error);
+        assert_eq!(*errors.lock(), vec![(first_id, frame_error)]);
+
+        // The connection failing notifies the second channel, the first one already failed
+        let error = Error::ProtocolError(AMQPError::new(
+            AMQPHardError::CONNECTIONFORCED.into(),
+            "CONNECTION_FORCED - broker forced connection closure".into(),
+        ));
+        conn.channels().set_connection_error(error.clone());
+        assert_eq!(errors.lock().len(), 2);
+        assert_eq!(errors.lock()[1], (second_id, error));
+    }
+
+    #[test]
+    fn flow_observer() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        use amq_protocol::protocol::channel;
+
+        let (conn, channel, _) = connected_channel();
+        let observed = Arc::new(Mutex::new(Vec::new()));
+        {
+            let observed = observed.clone();
+            channel.set_flow_observer(move |active| observed.lock().push(active));
+        }
+        let flow = |active| {
+            AMQPFrame::Method(
+                channel.id(),
+                AMQPClass::Channel(channel::AMQPMethod::Flow(channel::Flow { active })),
+            )
+        };
+
+        for active in &[false, false, true, true] {
+            conn.channels().handle_frame(flow(*active)).unwrap();
+        }
+        // Only the changes get observed
+        assert_eq!(*observed.lock(), vec![false, true]);
+        assert!(channel.status().flow());
+    }
+
+    #[test]
+    fn declare_priority_queue() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        use crate::{
+            options::{BasicPublishOptions, QueueDeclareOptions},
+            tracing_integration::tests::Captured,
+            PriorityValidation,
+        };
+        use amq_protocol::protocol::queue;
+
+        let (conn, channel, frames) = connected_channel();
+        conn.configuration()
+            .set_priority_validation(PriorityValidation::Error);
+        let task = {
+            let channel = channel.clone();
+            async_global_executor::spawn(async move {
+                channel
+                    .declare_priority_queue("prioritized", 5, QueueDeclareOptions::default())
+                    .await
+            })
+        };
+        let declare_ok = AMQPFrame::Method(
+            channel.id(),
+            AMQPClass::Queue(queue::AMQPMethod::DeclareOk(queue::DeclareOk {
+                queue: "prioritized".into(),
+                message_count: 0,
+                consumer_count: 0,
+            })),
+        );
+        match answer_next_frame(&conn, &frames, declare_ok) {
+            AMQPFrame::Method(_, AMQPClass::Queue(queue::AMQPMethod::Declare(declare))) => {
+                let mut expected = FieldTable::default();
+                expected.insert("x-max-priority".into(), AMQPValue::ShortShortUInt(5));
+                assert_eq!(declare.arguments, expected);
+            }
+            frame => panic!("unexpected frame: {:?}", frame),
+        }
+        assert!(async_global_executor::block_on(task).is_ok());
+
+        assert_eq!(
+            async_global_executor::block_on(channel.basic_publish(
+                "",
+                "prioritized",
+                BasicPublishOptions::default(),
+                b"urgent".to_vec(),
+                BasicProperties::default().with_priority(9),
+            ))
+            .err(),
+            Some(Error::InvalidArgument(
+                "the priority 9 is higher than the maximum priority 5 of queue prioritized".into()
+            ))
+        );
+
+        // With Warn, the message is published anyway and the warning gets logged
+        conn.configuration()
+            .set_priority_validation(PriorityValidation::Warn);
+        let writer = {
+            let frames = frames.clone();
+            std::thread::spawn(move || write_frames(&frames, 3))
+        };
+        let captured = Captured::default();
+        let subscriber = tracing_subscriber::fmt()
+            .with_ansi(false)
+            .with_writer(captured.clone())
+            .finish();
+        let published = tracing::subscriber::with_default(subscriber, || {
+            async_global_executor::block_on(channel.basic_publish(
+                "",
+                "prioritized",
+                BasicPublishOptions::default(),
+                b"urgent".to_vec(),
+                BasicProperties::default().with_priority(9),
+            ))
+        });
+        writer.join().unwrap();
+        assert!(published.is_ok());
+        let output = captured.output();
+        assert!(
+            output.contains(&format!(
+                "WARN lapin::channel: channel {}: the priority 9 is higher than the maximum priority 5 of queue prioritized",
+                channel.id()
+            )),
+            "{}",
+            output
+        );
+        assert_eq!(
+            async_global_executor::block_on(channel.declare_priority_queue(
+                "prioritized",
+                0,
+                QueueDeclareOptions::default()
+            )),
+            Err(Error::InvalidArgument(
+                "the maximum priority must be between 1 and 255".into()
+            ))
+        );
+    }
+
+    #[test]
+    fn basic_consume_offset() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        use crate::{
+            options::{BasicConsumeOptions, QueueDeclareOptions},
+            StreamOffset,
+        };
+        use amq_protocol::protocol::queue;
+        use std::time::UNIX_EPOCH;
+
+        let (conn, channel, frames) = connected_channel();
+        let consume = |offset: StreamOffset| {
+            let channel = channel.clone();
+            async_global_executor::spawn(async move {
+                channel
+                    .basic_consume_offset(
+                        "events",
+                        "events-reader",
+                        offset,
+                        BasicConsumeOptions::default(),
+                        FieldTable::default(),
+                    )
+                    .await
+            })
+        };
+        let consume_ok = || {
+            AMQPFrame::Method(
+                channel.id(),
+                AMQPClass::Basic(basic::AMQPMethod::ConsumeOk(basic::ConsumeOk {
+                    consumer_tag: "events-reader".into(),
+                })),
+            )
+        };
+        let time = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        for (offset, expected) in vec![
+            (StreamOffset::First, AMQPValue::LongString("first".into())),
+            (StreamOffset::Next, AMQPValue::LongString("next".into())),
+            (StreamOffset::Absolute(42), AMQPValue::LongLongInt(42)),
+            (
+                StreamOffset::Timestamp(time),
+                AMQPValue::Timestamp(1_600_000_000),
+            ),
+        ] {
+            let task = consume(offset);
+            match answer_next_frame(&conn, &frames, consume_ok()) {
+                AMQPFrame::Method(_, AMQPClass::Basic(basic::AMQPMethod::Consume(consume))) => {
+                    let mut arguments = FieldTable::default();
+                    arguments.insert("x-stream-offset".into(), expected);
+                    assert_eq!(consume.queue.as_str(), "events");
+                    assert_eq!(consume.arguments, arguments);
+                }
+                frame => panic!("unexpected frame: {:?}", frame),
+            }
+            assert!(async_global_executor::block_on(task).is_ok());
+        }
+
+        assert_eq!(
+            async_global_executor::block_on(consume(StreamOffset::Absolute(u64::MAX))).err(),
+            Some(Error::InvalidArgument(format!(
+                "the stream offset is too large: {}",
+                u64::MAX
+            )))
+        );
+        assert_eq!(
+            async_global_executor::block_on(channel.basic_consume_offset(
+                "events",
+                "",
+                StreamOffset::Last,
+                BasicConsumeOptions::default().no_ack(true),
+                FieldTable::default(),
+            ))
+            .err(),
+            Some(Error::InvalidArgument(
+                "stream queues can't be consumed with no_ack".into()
+            ))
+        );
+
+        // A queue declared as a quorum queue on this channel isn't a stream, whichever string
+        // type its x-queue-type was sent as
+        let task = {
+            let channel = channel.clone();
+            async_global_executor::spawn(async move {
+                let mut arguments = FieldTable::default();
+                arguments.insert(
+                    "x-queue-type".into(),
+                    AMQPValue::ShortString("quorum".into()),
+                );
+                channel
+                    .queue_declare(
+                        "quorum-events",
+                        QueueDeclareOptions::default().durable(true),
+                        arguments,
+                    )
+                    .await
+            })
+        };
+        answer_next_frame(
+            &conn,
+            &frames,
+            AMQPFrame::Method(
+                channel.id(),
+                AMQPClass::Queue(queue::AMQPMethod::DeclareOk(queue::DeclareOk {
+                    queue: "quorum-events".into(),
+                    message_count: 0,
+                    consumer_count: 0,
+                })),
+            ),
+        );
+        assert!(async_global_executor::block_on(task).is_ok());
+        assert_eq!(
+            async_global_executor::block_on(channel.basic_consume_offset(
+                "quorum-events",
+                "",
+                StreamOffset::First,
+                BasicConsumeOptions::default(),
+                FieldTable::default(),
+            ))
+            .err(),
+            Some(Error::InvalidArgument(
+                "queue quorum-events is a quorum queue, not a stream".into()
+            ))
+        );
+    }
+
+    #[test]
+    fn empty_queue_name() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        use crate::options::{
+            BasicConsumeOptions, QueueBindOptions, QueueDeclareOptions, QueueDeleteOptions,
+            QueuePurgeOptions,
+        };
+        use amq_protocol::protocol::queue;
+
+        let (conn, channel, frames) = connected_channel();
+
+        // Nothing was declared yet, fail before the server closes the channel
+        assert_eq!(
+            async_global_executor::block_on(channel.basic_consume(
+                "",
+                "",
+                BasicConsumeOptions::default(),
+                FieldTable::default(),
+            ))
+            .err(),
+            Some(Error::NoPreviouslyDeclaredQueue)
+        );
+        assert_eq!(
+            async_global_executor::block_on(channel.queue_bind(
+                "",
+                "amq.direct",
+                "key",
+                QueueBindOptions::default(),
+                FieldTable::default(),
+            )),
+            Err(Error::NoPreviouslyDeclaredQueue)
+        );
+        assert_eq!(
+            async_global_executor::block_on(channel.queue_purge("", QueuePurgeOptions::default())),
+            Err(Error::NoPreviouslyDeclaredQueue)
+        );
+        assert_eq!(
+            async_global_executor::block_on(
+                channel.queue_delete("", QueueDeleteOptions::default())
+            ),
+            Err(Error::NoPreviouslyDeclaredQueue)
+        );
+        assert!(frames.pop(true).is_none());
+        assert_eq!(channel.status().state(), ChannelState::Connected);
+
+        let task = {
+            let channel = channel.clone();
+            async_global_executor::spawn(async move {
+                channel
+                    .queue_declare(
+                        "",
+                        QueueDeclareOptions::default().exclusive(true),
+                        FieldTable::default(),
+                    )
+                    .await
+            })
+        };
+        answer_next_frame(
+            &conn,
+            &frames,
+            AMQPFrame::Method(
+                channel.id(),
+                AMQPClass::Queue(queue::AMQPMethod::DeclareOk(queue::DeclareOk {
+                    queue: "amq.gen-1".into(),
+                    message_count: 0,
+                    consumer_count: 0,
+                })),
+            ),
+        );
+        assert!(async_global_executor::block_on(task).is_ok());
+
+        // The actual name is sent instead of the empty one
+        let task = {
+            let channel = channel.clone();
+            async_global_executor::spawn(async move {
+                channel.queue_purge("", QueuePurgeOptions::default()).await
+            })
+        };
+        match answer_next_frame(
+            &conn,
+            &frames,
+            AMQPFrame::Method(
+                channel.id(),
+                AMQPClass::Queue(queue::AMQPMethod::PurgeOk(queue::PurgeOk {
+                    message_count: 3,
+                })),
+            ),
+        ) {
+            AMQPFrame::Method(_, AMQPClass::Queue(queue::AMQPMethod::Purge(purge))) => {
+                assert_eq!(purge.queue.as_str(), "amq.gen-1");
+            }
+            frame => panic!("unexpected frame: {:?}", frame),
+        }
+        assert_eq!(async_global_executor::block_on(task), Ok(3));
+
+        let task = {
+            let channel = channel.clone();
+            async_global_executor::spawn(async move {
+                channel
+                    .basic_consume(
+                        "",
+                        "reader",
+                        BasicConsumeOptions::default(),
+                        FieldTable::default(),
+                    )
+                    .await
+            })
+        };
+        match answer_next_frame(
+            &conn,
+            &frames,
+            AMQPFrame::Method(
+                channel.id(),
+                AMQPClass::Basic(basic::AMQPMethod::ConsumeOk(basic::ConsumeOk {
+                    consumer_tag: "reader".into(),
+                })),
+            ),
+        ) {
+            AMQPFrame::Method(_, AMQPClass::Basic(basic::AMQPMethod::Consume(consume))) => {
+                assert_eq!(consume.queue.as_str(), "amq.gen-1");
+            }
+            frame => panic!("unexpected frame: {:?}", frame),
+        }
+        assert!(async_global_executor::block_on(task).is_ok());
+    }
+
+    #[test]
+    fn channel_defaults() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        use crate::{ChannelDefaults, QosScope};
+        use amq_protocol::protocol::{channel, confirm};
+
+        let (conn, _, frames) = connected_channel();
+        let (returned, returns) = flume::unbounded();
+        conn.configuration().set_channel_defaults(
+            ChannelDefaults::default()
+                .with_confirm_mode(true)
+                .with_qos(50, QosScope::PerChannel)
+                .with_publisher_returns_callback(move |message| {
+                    let _ = returned.send(message.delivery.routing_key.to_string());
+                }),
+        );
+        let conn = Arc::new(conn);
+
+        for id in 2..4 {
+            let task = {
+                let conn = conn.clone();
+                async_global_executor::spawn(async move { conn.create_channel().await })
+            };
+            assert_eq!(
+                answer_next_frame(
+                    &conn,
+                    &frames,
+                    AMQPFrame::Method(
+                        id,
+                        AMQPClass::Channel(channel::AMQPMethod::OpenOk(channel::OpenOk {})),
+                    ),
+                ),
+                AMQPFrame::Method(
+                    id,
+                    AMQPClass::Channel(channel::AMQPMethod::Open(channel::Open {}))
+                )
+            );
+            assert_eq!(
+                answer_next_frame(
+                    &conn,
+                    &frames,
+                    AMQPFrame::Method(
+                        id,
+                        AMQPClass::Confirm(confirm::AMQPMethod::SelectOk(confirm::SelectOk {})),
+                    ),
+                ),
+                AMQPFrame::Method(
+                    id,
+                    AMQPClass::Confirm(confirm::AMQPMethod::Select(confirm::Select {
+                        nowait: false
+                    }))
+                )
+            );
+            // The channel is only handed over once the prefetch count is set too
+            assert_eq!(
+                answer_next_frame(
+                    &conn,
+                    &frames,
+                    AMQPFrame::Method(
+                        id,
+                        AMQPClass::Basic(basic::AMQPMethod::QosOk(basic::QosOk {}))
+                    ),
+                ),
+                AMQPFrame::Method(
+                    id,
+                    AMQPClass::Basic(basic::AMQPMethod::Qos(basic::Qos {
+                        prefetch_count: 50,
+                        global: false,
+                    }))
+                )
+            );
+            let channel = async_global_executor::block_on(task).unwrap();
+            assert_eq!(channel.id(), id);
+            assert!(channel.status().confirm());
+
+            for frame in unroutable_frames(&channel, "missing") {
+                conn.channels().handle_frame(frame).unwrap();
+            }
+            assert_eq!(
+                returns.recv_timeout(Duration::from_secs(1)),
+                Ok("missing".to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn close_normally() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        use amq_protocol::protocol::channel;
+
+        let (conn, channel, frames) = connected_channel();
+        let task = {
+            let channel = channel.clone();
+            async_global_executor::spawn(async move { channel.close_normally().await })
+        };
+        let close_ok = AMQPFrame::Method(
+            channel.id(),
+            AMQPClass::Channel(channel::AMQPMethod::CloseOk(channel::CloseOk {})),
+        );
+        assert_eq!(
+            answer_next_frame(&conn, &frames, close_ok),
+            AMQPFrame::Method(
+                channel.id(),
+                AMQPClass::Channel(channel::AMQPMethod::Close(channel::Close {
+                    reply_code: 200,
+                    reply_text: "Normal shutdown".into(),
+                    class_id: 0,
+                    method_id: 0,
+                })),
+            )
+        );
+        assert!(async_global_executor::block_on(task).is_ok());
+        assert_eq!(channel.status().state(), ChannelState::Closed);
+    }
+
+    #[test]
+    fn basic_consume_ack_batch() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        let (conn, channel, frames) = connected_channel();
+        let handled = Arc::new(Mutex::new(Vec::new()));
+        let task = {
+            let channel = channel.clone();
+            let handled = handled.clone();
+            async_global_executor::spawn(async move {
+                channel
+                    .basic_consume_with_prefetch_and_ack_batch(
+                        "queue",
+                        "batch",
+                        10,
+                        2,
+                        Duration::from_millis(50),
+                        move |deliveries: Vec<Delivery>| {
+                            let tags = deliveries
+                                .iter()
+                                .map(|delivery| delivery.delivery_tag)
+                                .collect::<Vec<_>>();
+                            let failed = tags.contains(&3);
+                            handled.lock().push(tags);
+                            async move {
+                                if failed {
+                                    Err(Error::Cancelled)
+                                } else {
+                                    Ok(())
+                                }
+                            }
+                        },
+                    )
+                    .await
+            })
+        };
+        answer_next_frame(
+            &conn,
+            &frames,
+            AMQPFrame::Method(
+                channel.id(),
+                AMQPClass::Basic(basic::AMQPMethod::QosOk(basic::QosOk {})),
+            ),
+        );
+        answer_next_frame(
+            &conn,
+            &frames,
+            AMQPFrame::Method(
+                channel.id(),
+                AMQPClass::Basic(basic::AMQPMethod::ConsumeOk(basic::ConsumeOk {
+                    consumer_tag: "batch".into(),
+                })),
+            ),
+        );
+        async_global_executor::block_on(task).unwrap();
+
+        for delivery_tag in 1..=3 {
+            let deliver = content_frames(
+                &channel,
+                basic::AMQPMethod::Deliver(basic::Deliver {
+                    consumer_tag: "batch".into(),
+                    delivery_tag,
+                    redelivered: false,
+                    exchange: "".into(),
+                    routing_key: "queue".into(),
+                }),
+                BasicProperties::default(),
+                &[b"batched"],
+            );
+            for frame in deliver {
+                conn.channels().handle_frame(frame).unwrap();
+            }
+        }
+        let next_frame = || loop {
+            if let Some((frame, resolver)) = frames.pop(true) {
+                if let Some(resolver) = resolver {
+                    resolver.swear(Ok(()));
+                }
+                return frame;
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        };
+
+        // A full batch gets handled right away
+        match next_frame() {
+            AMQPFrame::Method(_, AMQPClass::Basic(basic::AMQPMethod::Ack(ack))) => {
+                assert_eq!(ack.delivery_tag, 2);
+                assert!(ack.multiple);
+            }
+            frame => panic!("unexpected frame: {:?}", frame),
+        }
+        // The last one once the flush interval elapsed, the handler fails
+        match next_frame() {
+            AMQPFrame::Method(_, AMQPClass::Basic(basic::AMQPMethod::Nack(nack))) => {
+                assert_eq!(nack.delivery_tag, 3);
+                assert!(nack.multiple);
+                assert!(!nack.requeue);
+            }
+            frame => panic!("unexpected frame: {:?}", frame),
+        }
+        assert_eq!(*handled.lock(), vec![vec![1, 2], vec![3]]);
+    }
+
+    #[test]
+    fn basic_consume_lazy() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        use crate::options::BasicConsumeOptions;
+
+        let (conn, channel, frames) = connected_channel();
+        let (delivered_sender, delivered) = flume::unbounded();
+        let task = {
+            let channel = channel.clone();
+            async_global_executor::spawn(async move {
+                channel
+                    .basic_consume_lazy(
+                        "consumed",
+                        "lazy",
+                        BasicConsumeOptions::default(),
+                        FieldTable::default(),
+                        move |delivery: DeliveryResult| {
+                            let delivered_sender = delivered_sender.clone();
+                            async move {
+                                if let Ok(Some((_, delivery))) = delivery {
+                                    let _ = delivered_sender.send(delivery.delivery_tag);
+                                }
+                            }
+                        },
+                    )
+                    .await
+            })
+        };
+        answer_next_frame(
+            &conn,
+            &frames,
+            AMQPFrame::Method(
+                channel.id(),
+                AMQPClass::Basic(basic::AMQPMethod::ConsumeOk(basic::ConsumeOk {
+                    consumer_tag: "lazy".into(),
+                })),
+            ),
+        );
+        // Delivered before the caller got the consumer back
+        for frame in deliver_frames(&channel, &"lazy".into()) {
+            conn.channels().handle_frame(frame).unwrap();
+        }
+        assert_eq!(delivered.recv_timeout(Duration::from_secs(5)), Ok(1));
+
+        let consumer = async_global_executor::block_on(task).unwrap();
+        assert_eq!(consumer.tag().as_str(), "lazy");
+        assert!(consumer.subscription().is_some());
+    }
+
+    #[test]
+    fn basic_consume_with_metrics() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        use crate::{
+            metrics::{ConsumerMetrics, Histogram},
+            options::BasicConsumeOptions,
+        };
+        use std::sync::atomic::{AtomicU64, Ordering};
+
+        #[derive(Default)]
+        struct Observed(Mutex<Vec<f64>>);
+
+        impl Histogram for Observed {
+            fn observe(&self, value: f64) {
+                self.0.lock().push(value);
+            }
+        }
+
+        let (conn, channel, frames) = connected_channel();
+        let received = Arc::new(AtomicU64::new(0));
+        let processing_time = Arc::new(Observed::default());
+        let errors = Arc::new(AtomicU64::new(0));
+        let metrics = Arc::new(ConsumerMetrics::new(
+            received.clone(),
+            processing_time.clone(),
+            errors.clone(),
+        ));
+        let task = {
+            let channel = channel.clone();
+            async_global_executor::spawn(async move {
+                channel
+                    .basic_consume_with_metrics(
+                        "consumed",
+                        "measured",
+                        metrics,
+                        BasicConsumeOptions::default(),
+                        FieldTable::default(),
+                    )
+                    .await
+            })
+        };
+        answer_next_frame(
+            &conn,
+            &frames,
+            AMQPFrame::Method(
+                channel.id(),
+                AMQPClass::Basic(basic::AMQPMethod::ConsumeOk(basic::ConsumeOk {
+                    consumer_tag: "measured".into(),
+                })),
+            ),
+        );
+        let consumer = async_global_executor::block_on(task).unwrap();
+        consumer.set_delegate(|delivery: DeliveryResult| async move {
+            if let Ok(Some(_)) = delivery {
+                async_io::Timer::after(Duration::from_millis(20)).await;
+            }
+        });
+
+        for frame in deliver_frames(&channel, &consumer.tag()) {
+            conn.channels().handle_frame(frame).unwrap();
+        }
+        async_global_executor::block_on(consumer.join());
+        assert_eq!(received.load(Ordering::SeqCst), 1);
+        let observed = processing_time.0.lock().clone();
+        assert_eq!(observed.len(), 1);
+        assert!(observed[0] >= 0.02, "{:?}", observed);
+        assert_eq!(errors.load(Ordering::SeqCst), 0);
+
+        consumer.set_error(Error::ChannelsLimitReached);
+        async_global_executor::block_on(consumer.join());
+        assert_eq!(errors.load(Ordering::SeqCst), 1);
+        // Only the deliveries get timed
+        assert_eq!(processing_time.0.lock().len(), 1);
+    }
+
+    #[test]
+    fn qos_scope() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        use crate::{test_support::FrameCapture, QosScope};
+        use amq_protocol::protocol::basic;
+
+        let (conn, channel, frames) = connected_channel();
+        let qos = |scope| {
+            let task = {
+                let channel = channel.clone();
+                async_global_executor::spawn(async move { channel.basic_qos(10, scope).await })
+            };
+            let sent = answer_next_frame(
+                &conn,
+                &frames,
+                AMQPFrame::Method(
+                    channel.id(),
+                    AMQPClass::Basic(basic::AMQPMethod::QosOk(basic::QosOk {})),
+                ),
+            );
+            async_global_executor::block_on(task).unwrap();
+            match sent {
+                AMQPFrame::Method(_, AMQPClass::Basic(basic::AMQPMethod::Qos(qos))) => {
+                    assert_eq!(qos.prefetch_count, 10);
+                    qos.global
+                }
+                frame => panic!("expected a basic.qos, got {:?}", frame),
+            }
+        };
+        let unsupported = |scope| {
+            let capture = FrameCapture::install(&channel);
+            let res = async_global_executor::block_on(channel.basic_qos(10, scope));
+            capture.assert_methods(&[]);
+            res.unwrap_err()
+        };
+
+        // As the specification defines it
+        assert!(!qos(QosScope::PerChannel));
+        assert!(qos(QosScope::PerConnectionSpec));
+        assert_eq!(
+            unsupported(QosScope::PerConsumer),
+            Error::UnsupportedByBroker("per-consumer prefetch")
+        );
+
+        // As RabbitMQ defines it
+        conn.status().set_per_consumer_qos(true);
+        assert!(!qos(QosScope::PerConsumer));
+        assert!(qos(QosScope::PerChannel));
+        assert_eq!(
+            unsupported(QosScope::PerConnectionSpec),
+            Error::UnsupportedByBroker("per-connection prefetch")
+        );
+    }
+
+    #[test]
+    fn basic_consume_timeout() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        use crate::options::BasicConsumeOptions;
+
+        let (conn, channel, frames) = connected_channel();
+
+        let res = async_global_executor::block_on(channel.basic_consume_with_timeout(
+            "consumed",
+            "consumer-tag",
+            Duration::from_millis(50),
+            BasicConsumeOptions::default(),
+            FieldTable::default(),
+        ));
+        assert_eq!(res.err(), Some(Error::Timeout));
+        assert_eq!(channel.status().state(), ChannelState::Connected);
+
+        // The late consume-ok leads to the consumer being canceled
+        let frame = answer_next_frame(
+            &conn,
+            &frames,
+            AMQPFrame::Method(
+                channel.id(),
+                AMQPClass::Basic(basic::AMQPMethod::ConsumeOk(basic::ConsumeOk {
+                    consumer_tag: "consumer-tag".into(),
+                })),
+            ),
+        );
+        assert!(matches!(
+            frame,
+            AMQPFrame::Method(_, AMQPClass::Basic(basic::AMQPMethod::Consume(_)))
+        ));
+        let frame = answer_next_frame(
+            &conn,
+            &frames,
+            AMQPFrame::Method(
+                channel.id(),
+                AMQPClass::Basic(basic::AMQPMethod::CancelOk(basic::CancelOk {
+                    consumer_tag: "consumer-tag".into(),
+                })),
+            ),
+        );
+        match frame {
+            AMQPFrame::Method(_, AMQPClass::Basic(basic::AMQPMethod::Cancel(cancel))) => {
+                assert_eq!(cancel.consumer_tag.as_str(), "consumer-tag")
+            }
+            frame => panic!("expected basic.cancel, got {:?}", frame),
+        }
+        assert_eq!(channel.status().state(), ChannelState::Connected);
+    }
+
+    #[cfg(feature = "publish-timestamps")]
+    #[test]
+    fn publish_timestamps() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        use crate::options::BasicPublishOptions;
+
+        let (conn, channel, frames) = connected_channel();
+
+        let before = std::time::Instant::now();
+        let publish = {
+            let channel = channel.clone();
+            async_global_executor::spawn(async move {
+                channel
+                    .basic_publish(
+                        "",
+                        "queue",
+                        BasicPublishOptions::default(),
+                        b"payload".to_vec(),
+                        BasicProperties::default(),
+                    )
+                    .await
+            })
+        };
+        // Pretend the io loop wrote the method, header and body frames
+        let mut written = 0;
+        while written < 3 {
+            if let Some((_, resolver)) = frames.pop(true) {
+                if let Some(resolver) = resolver {
+                    resolver.swear(Ok(()));
+                }
+                written += 1;
+            } else {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+        }
+        let written = std::time::Instant::now();
+        // Recorded when the last frame got written, not when the publish got polled again
+        std::thread::sleep(Duration::from_millis(10));
+        let confirm = async_global_executor::block_on(publish).unwrap();
+
+        let queued_at = confirm.queued_at().unwrap();
+        let written_at = confirm.written_at().unwrap();
+        assert!(before <= queued_at);
+        assert!(queued_at <= written_at);
+        assert!(written_at <= written);
+    }
+
+    #[test]
+    fn publisher_confirms() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        use crate::publisher_confirm::Confirmation;
+
+        let (conn, channel, _frames) = connected_channel();
+        channel.status().set_confirm();
+        let confirms = (1..=3)
+            .map(|delivery_tag| channel.register_pending_confirm(delivery_tag))
+            .collect::<Vec<_>>();
+
+        conn.channels()
+            .handle_frame(AMQPFrame::Method(
+                channel.id(),
+                AMQPClass::Basic(basic::AMQPMethod::Ack(basic::Ack {
+                    delivery_tag: 2,
+                    multiple: true,
+                })),
+            ))
+            .unwrap();
+        conn.channels()
+            .handle_frame(AMQPFrame::Method(
+                channel.id(),
+                AMQPClass::Basic(basic::AMQPMethod::Nack(basic::Nack {
+                    delivery_tag: 3,
+                    multiple: false,
+                    requeue: false,
+                })),
+            ))
+            .unwrap();
+
+        let outcomes = confirms
+            .into_iter()
+            .map(|confirm| async_global_executor::block_on(confirm).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            outcomes,
+            vec![
+                Confirmation::Ack(None),
+                Confirmation::Ack(None),
+                Confirmation::Nack(None),
+            ]
+        );
+        assert_eq!(channel.status().state(), ChannelState::Connected);
+    }
+
+    #[test]
+    fn confirm_nack_handler() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        use crate::options::BasicPublishOptions;
+
+        let (conn, channel, frames) = connected_channel();
+        channel.status().set_confirm();
+        let nacked = Arc::new(Mutex::new(Vec::new()));
+        {
+            let nacked = nacked.clone();
+            channel.set_confirm_nack_handler(move |delivery_tag| nacked.lock().push(delivery_tag));
+        }
+
+        let publish = |payload: &[u8]| {
+            let channel = channel.clone();
+            let payload = payload.to_vec();
+            async_global_executor::spawn(async move {
+                channel
+                    .basic_publish(
+                        "",
+                        "queue",
+                        BasicPublishOptions::default(),
+                        payload,
+                        BasicProperties::default(),
+                    )
+                    .await
+            })
+        };
+        let first = publish(b"first");
+        write_frames(&frames, 3);
+        let first = async_global_executor::block_on(first).unwrap();
+        let second = publish(b"second");
+        write_frames(&frames, 3);
+        let second = async_global_executor::block_on(second).unwrap();
+
+        // The server acks the first message and nacks the second one
+        conn.channels()
+            .handle_frame(AMQPFrame::Method(
+                channel.id(),
+                AMQPClass::Basic(basic::AMQPMethod::Ack(basic::Ack {
+                    delivery_tag: 1,
+                    multiple: false,
+                })),
+            ))
+            .unwrap();
+        conn.channels()
+            .handle_frame(AMQPFrame::Method(
+                channel.id(),
+                AMQPClass::Basic(basic::AMQPMethod::Nack(basic::Nack {
+                    delivery_tag: 2,
+                    multiple: false,
+                    requeue: false,
+                })),
+            ))
+            .unwrap();
+
+        assert!(async_global_executor::block_on(first).unwrap().is_ack());
+        assert!(async_global_executor::block_on(second).unwrap().is_nack());
+        assert_eq!(*nacked.lock(), vec![2]);
+    }
+
+    #[test]
+    fn concurrent_publishes() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        use crate::{
+            frame_ordering::FrameOrderingValidator, options::BasicPublishOptions,
+            publisher_confirm::Confirmation,
+        };
+
+        const TASKS: usize = 16;
+        const MESSAGES: usize = 10_000;
+
+        let (conn, channel, frames) = connected_channel();
+        // Split every payload into several body frames
+        conn.configuration().set_frame_max(24);
+        channel.status().set_confirm();
+
+        // Acts as the server: check the frames, reassemble the messages and nack every 7th
+        // message of each task using the delivery tag the server would give them.
+        let server = {
+            let channel_id = channel.id();
+            std::thread::spawn(move || {
+                let mut validator = FrameOrderingValidator::default();
+                let mut next_seq = [0; TASKS];
+                let mut delivery_tag = 0;
+                let mut content = Vec::new();
+                while delivery_tag < (TASKS * MESSAGES) as u64 {
+                    let (frame, resolver) = match frames.pop(true) {
+                        Some(frame) => frame,
+                        None => {
+                            std::thread::yield_now();
+                            continue;
+                        }
+                    };
+                    if let Some(resolver) = resolver {
+                        resolver.swear(Ok(()));
+                    }
+                    if let Err(violation) = validator.check(&frame) {
+                        panic!("{}", violation);
+                    }
+                    let payload = match frame {
+                        AMQPFrame::Body(_, payload) => payload,
+                        _ => continue,
+                    };
+                    content.extend(payload);
+                    if content.len() < 32 {
+                        continue;
+                    }
+                    let message = String::from_utf8(std::mem::take(&mut content)).unwrap();
+                    let mut parts = message.trim_end_matches('.').split('-');
+                    let task = parts.next().unwrap().parse::<usize>().unwrap();
+                    let seq = parts.next().unwrap().parse::<usize>().unwrap();
+                    assert_eq!(seq, next_seq[task], "task {} lost a message", task);
+                    next_seq[task] += 1;
+                    delivery_tag += 1;
+                    let method = if seq % 7 == 0 {
+                        basic::AMQPMethod::Nack(basic::Nack {
+                            delivery_tag,
+                            multiple: false,
+                            requeue: false,
+                        })
+                    } else {
+                        basic::AMQPMethod::Ack(basic::Ack {
+                            delivery_tag,
+                            multiple: false,
+                        })
+                    };
+                    conn.channels()
+                        .handle_frame(AMQPFrame::Method(channel_id, AMQPClass::Basic(method)))
+                        .unwrap();
+                }
+                conn
+            })
+        };
+
+        let tasks = (0..TASKS)
+            .map(|task| {
+                let channel = channel.clone();
+                async_global_executor::spawn(async move {
+                    let mut confirms = Vec::with_capacity(MESSAGES);
+                    for seq in 0..MESSAGES {
+                        let payload = format!("{:02}-{:05}-{:.<23}", task, seq, "");
+                        confirms.push(
+                            channel
+                                .basic_publish(
+                                    "",
+                                    "queue",
+                                    BasicPublishOptions::default(),
+                                    payload.into_bytes(),
+                                    BasicProperties::default(),
+                                )
+                                .await
+                                .unwrap(),
+                        );
+                    }
+                    for (seq, confirm) in confirms.into_iter().enumerate() {
+                        let expected = if seq % 7 == 0 {
+                            Confirmation::Nack(None)
+                        } else {
+                            Confirmation::Ack(None)
+                        };
+                        assert_eq!(confirm.await.unwrap(), expected);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for task in tasks {
+            async_global_executor::block_on(task);
+        }
+        let conn = server.join().unwrap();
+        assert_eq!(conn.stats().channels.published, (TASKS * MESSAGES) as u64);
+    }
+
+    #[test]
+    fn publish_immediate_flag() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        use crate::options::BasicPublishOptions;
+
+        let (conn, channel, frames) = connected_channel();
+        conn.status().set_server_product("RabbitMQ".into());
+        conn.status().set_server_version("3.8.9".into());
+        let publish = |channel: &Channel| {
+            let channel = channel.clone();
+            async_global_executor::spawn(async move {
+                channel
+                    .basic_publish(
+                        "",
+                        "queue",
+                        BasicPublishOptions::default()
+                            .mandatory(false)
+                            .immediate(true),
+                        b"payload".to_vec(),
+                        BasicProperties::default(),
+                    )
+                    .await
+            })
+        };
+
+        // Rejected locally, nothing gets sent
+        assert_eq!(
+            async_global_executor::block_on(publish(&channel)).err(),
+            Some(Error::UnsupportedByBroker("immediate flag"))
+        );
+        assert!(frames.pop(true).is_none());
+        assert_eq!(channel.status().state(), ChannelState::Connected);
+
+        conn.configuration().set_immediate_flag(true);
+        let task = publish(&channel);
+        let mut written = Vec::new();
+        while written.len() < 3 {
+            if let Some((frame, resolver)) = frames.pop(true) {
+                if let Some(resolver) = resolver {
+                    resolver.swear(Ok(()));
+                }
+                written.push(frame);
+            } else {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+        }
+        assert!(async_global_executor::block_on(task).is_ok());
+        match &written[0] {
+            AMQPFrame::Method(_, AMQPClass::Basic(basic::AMQPMethod::Publish(publish))) => {
+                assert!(publish.immediate)
+            }
+            frame => panic!("unexpected frame: {:?}", frame),
+        }
+    }
+
+    #[test]
+    fn queue_bind_headers() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        use amq_protocol::protocol::queue;
+
+        let (conn, channel, frames) = connected_channel();
+        let bind = {
+            let channel = channel.clone();
+            async_global_executor::spawn(async move {
+                channel
+                    .queue_bind_headers(
+                        "queue",
+                        "amq.headers",
+                        false,
+                        vec![("format".to_string(), AMQPValue::LongString("pdf".into()))],
+                    )
+                    .await
+            })
+        };
+        let frame = answer_next_frame(
+            &conn,
+            &frames,
+            AMQPFrame::Method(
+                channel.id(),
+                AMQPClass::Queue(queue::AMQPMethod::BindOk(queue::BindOk {})),
+            ),
+        );
+        async_global_executor::block_on(bind).unwrap();
+
+        let mut arguments = FieldTable::default();
+        arguments.insert("x-match".into(), AMQPValue::LongString("any".into()));
+        arguments.insert("format".into(), AMQPValue::LongString("pdf".into()));
+        match frame {
+            AMQPFrame::Method(_, AMQPClass::Queue(queue::AMQPMethod::Bind(bind))) => {
+                assert_eq!(bind.exchange.as_str(), "amq.headers");
+                assert_eq!(bind.routing_key.as_str(), "");
+                assert_eq!(bind.arguments, arguments);
+            }
+            frame => panic!("unexpected frame: {:?}", frame),
+        }
+    }
+
+    #[test]
+    fn declare_alternate_exchange() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        use crate::{options::ExchangeDeclareOptions, ExchangeKind};
+        use amq_protocol::protocol::exchange;
+
+        let (conn, channel, frames) = connected_channel();
+        let task = {
+            let channel = channel.clone();
+            async_global_executor::spawn(async move {
+                channel
+                    .declare_alternate_exchange(
+                        "orders",
+                        ExchangeKind::Topic,
+                        "unrouted",
+                        ExchangeKind::Fanout,
+                        ExchangeDeclareOptions::default().durable(true),
+                    )
+                    .await
+            })
+        };
+        let declare_ok = || {
+            AMQPFrame::Method(
+                channel.id(),
+                AMQPClass::Exchange(exchange::AMQPMethod::DeclareOk(exchange::DeclareOk {})),
+            )
+        };
+        match answer_next_frame(&conn, &frames, declare_ok()) {
+            AMQPFrame::Method(_, AMQPClass::Exchange(exchange::AMQPMethod::Declare(declare))) => {
+                assert_eq!(declare.exchange.as_str(), "unrouted");
+                assert_eq!(declare.kind.as_str(), "fanout");
+                assert!(declare.durable);
+                assert_eq!(declare.arguments, FieldTable::default());
+            }
+            frame => panic!("unexpected frame: {:?}", frame),
+        }
+        match answer_next_frame(&conn, &frames, declare_ok()) {
+            AMQPFrame::Method(_, AMQPClass::Exchange(exchange::AMQPMethod::Declare(declare))) => {
+                let mut arguments = FieldTable::default();
+                arguments.insert(
+                    "alternate-exchange".into(),
+                    AMQPValue::LongString("unrouted".into()),
+                );
+                assert_eq!(declare.exchange.as_str(), "orders");
+                assert_eq!(declare.kind.as_str(), "topic");
+                assert!(declare.durable);
+                assert_eq!(declare.arguments, arguments);
+            }
+            frame => panic!("unexpected frame: {:?}", frame),
+        }
+        assert!(async_global_executor::block_on(task).is_ok());
+    }
+
+    #[test]
+    fn consume_unroutable_without_audit_queue() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        let (_conn, channel, frames) = connected_channel();
+        match async_global_executor::block_on(channel.consume_unroutable("orders")) {
+            Err(Error::InvalidArgument(reason)) => assert!(reason.contains("orders"), "{}", reason),
+            res => panic!(
+                "expected an invalid argument error, got {:?}",
+                res.map(|c| c.tag())
+            ),
+        }
+        // Nothing got declared behind our back
+        assert!(frames.pop(true).is_none());
+    }
+}
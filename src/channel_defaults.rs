@@ -0,0 +1,84 @@
+use crate::{message::BasicReturnMessage, types::ShortUInt, Error, QosScope};
+use std::{fmt, sync::Arc};
+
+pub(crate) type ReturnCallback = Arc<dyn Fn(&BasicReturnMessage) + Send + Sync>;
+pub(crate) type ErrorCallback = Arc<dyn Fn(Error) + Send + Sync>;
+
+/// What gets set up on every channel created with [`Connection::create_channel`], see
+/// [`ConnectionProperties::with_channel_defaults`].
+///
+/// The callbacks are shared by all the channels, each of them getting a clone.
+///
+/// [`Connection::create_channel`]: ./struct.Connection.html#method.create_channel
+/// [`ConnectionProperties::with_channel_defaults`]: ./struct.ConnectionProperties.html#method.with_channel_defaults
+#[derive(Clone, Default)]
+#[non_exhaustive]
+pub struct ChannelDefaults {
+    /// Enable publisher confirms with `confirm.select`.
+    pub confirm_mode: bool,
+    /// Set the prefetch count with `basic.qos`.
+    pub qos: Option<(ShortUInt, QosScope)>,
+    /// Called with each message returned by the server, see
+    /// [`Channel::set_return_handler`].
+    ///
+    /// [`Channel::set_return_handler`]: ./struct.Channel.html#method.set_return_handler
+    pub publisher_returns_callback: Option<ReturnCallback>,
+    /// Called when the channel fails, see [`Channel::with_error_handler`].
+    ///
+    /// [`Channel::with_error_handler`]: ./struct.Channel.html#method.with_error_handler
+    pub on_error_callback: Option<ErrorCallback>,
+}
+
+impl ChannelDefaults {
+    /// Whether to enable publisher confirms on every channel.
+    pub fn with_confirm_mode(mut self, confirm_mode: bool) -> Self {
+        self.confirm_mode = confirm_mode;
+        self
+    }
+
+    /// Set the prefetch count of every channel, applied as `scope` defines it.
+    pub fn with_qos(mut self, prefetch_count: ShortUInt, scope: QosScope) -> Self {
+        self.qos = Some((prefetch_count, scope));
+        self
+    }
+
+    /// Call `callback` with each message the server returns on any of the channels.
+    pub fn with_publisher_returns_callback<F: Fn(&BasicReturnMessage) + Send + Sync + 'static>(
+        mut self,
+        callback: F,
+    ) -> Self {
+        self.publisher_returns_callback = Some(Arc::new(callback));
+        self
+    }
+
+    /// Call `callback` with the error of any channel which fails.
+    pub fn with_on_error_callback<F: Fn(Error) + Send + Sync + 'static>(
+        mut self,
+        callback: F,
+    ) -> Self {
+        self.on_error_callback = Some(Arc::new(callback));
+        self
+    }
+
+    /// Whether there is nothing to set up.
+    pub(crate) fn is_empty(&self) -> bool {
+        !self.confirm_mode
+            && self.qos.is_none()
+            && self.publisher_returns_callback.is_none()
+            && self.on_error_callback.is_none()
+    }
+}
+
+impl fmt::Debug for ChannelDefaults {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ChannelDefaults")
+            .field("confirm_mode", &self.confirm_mode)
+            .field("qos", &self.qos)
+            .field(
+                "publisher_returns_callback",
+                &self.publisher_returns_callback.is_some(),
+            )
+            .field("on_error_callback", &self.on_error_callback.is_some())
+            .finish()
+    }
+}
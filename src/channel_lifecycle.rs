@@ -0,0 +1,81 @@
+use crate::{protocol::AMQPError, types::ShortUInt, Error};
+use parking_lot::Mutex;
+use std::{fmt, sync::Arc};
+
+/// Gets notified when the channels of a connection get opened, closed or fail, see
+/// [`Connection::register_channel_lifecycle_observer`].
+///
+/// The methods are called from the io loop of the connection, they must not block.
+///
+/// [`Connection::register_channel_lifecycle_observer`]: ./struct.Connection.html#method.register_channel_lifecycle_observer
+pub trait ChannelLifecycleObserver: Send + Sync {
+    /// The server acknowledged the opening of the channel `id`.
+    fn on_channel_opened(&self, id: u16);
+
+    /// The channel `id` got closed, with the reason given by the server if it closed it.
+    ///
+    /// `reason` is `None` when we closed the channel, or when it went away with its connection.
+    fn on_channel_closed(&self, id: u16, reason: Option<CloseReason>);
+
+    /// The channel `id` got closed because of `error`, or because its connection failed.
+    fn on_channel_error(&self, id: u16, error: &Error);
+}
+
+/// Why the server closed a channel.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CloseReason {
+    pub reply_code: ShortUInt,
+    pub reply_text: String,
+}
+
+impl From<&AMQPError> for CloseReason {
+    fn from(error: &AMQPError) -> Self {
+        Self {
+            reply_code: error.get_id(),
+            reply_text: error.get_message().to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub(crate) struct ChannelLifecycleObservers(Arc<Mutex<Vec<Arc<dyn ChannelLifecycleObserver>>>>);
+
+impl ChannelLifecycleObservers {
+    pub(crate) fn register(&self, observer: Arc<dyn ChannelLifecycleObserver>) {
+        self.0.lock().push(observer);
+    }
+
+    pub(crate) fn opened(&self, id: u16) {
+        for observer in self.observers() {
+            observer.on_channel_opened(id);
+        }
+    }
+
+    pub(crate) fn closed(&self, id: u16, reason: Option<CloseReason>) {
+        for observer in self.observers() {
+            observer.on_channel_closed(id, reason.clone());
+        }
+    }
+
+    pub(crate) fn error(&self, id: u16, error: &Error) {
+        for observer in self.observers() {
+            observer.on_channel_error(id, error);
+        }
+    }
+
+    /// The registered observers, to call them without holding the lock so that they can
+    /// register other ones.
+    fn observers(&self) -> Vec<Arc<dyn ChannelLifecycleObserver>> {
+        self.0.lock().clone()
+    }
+}
+
+impl fmt::Debug for ChannelLifecycleObservers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("ChannelLifecycleObservers");
+        if let Some(observers) = self.0.try_lock() {
+            debug.field("observers", &observers.len());
+        }
+        debug.finish()
+    }
+}
@@ -1,4 +1,5 @@
 use crate::{
+    acknowledgement::DeliveryTag,
     types::{ShortString, ShortUInt},
     Result,
 };
@@ -16,7 +17,7 @@ impl ChannelReceiverStates {
     pub(crate) fn set_will_receive(
         &mut self,
         class_id: ShortUInt,
-        queue_name: Option<ShortString>,
+        queue_name: ShortString,
         request_id_or_consumer_tag: Option<ShortString>,
     ) {
         self.0.push_back(ChannelReceiverState::WillReceiveContent(
@@ -26,9 +27,27 @@ impl ChannelReceiverStates {
         ));
     }
 
+    pub(crate) fn set_will_receive_return(&mut self, class_id: ShortUInt) {
+        self.0
+            .push_back(ChannelReceiverState::WillReceiveReturn(class_id));
+    }
+
+    pub(crate) fn set_will_discard(
+        &mut self,
+        class_id: ShortUInt,
+        consumer_tag: ShortString,
+        delivery_tag: DeliveryTag,
+    ) {
+        self.0.push_back(ChannelReceiverState::WillDiscardContent(
+            class_id,
+            consumer_tag,
+            delivery_tag,
+        ));
+    }
+
     #[allow(clippy::too_many_arguments)]
     pub(crate) fn set_content_length<
-        Handler: FnOnce(&Option<ShortString>, &Option<ShortString>, bool),
+        Handler: FnOnce(ContentReceiver<'_>, bool),
         OnInvalidClass: FnOnce(String) -> Result<()>,
         OnError: FnOnce(String) -> Result<()>,
     >(
@@ -40,39 +59,57 @@ impl ChannelReceiverStates {
         invalid_class_hanlder: OnInvalidClass,
         error_handler: OnError,
         confirm_mode: bool,
+        drop_content: bool,
     ) -> Result<()> {
-        if let Some(ChannelReceiverState::WillReceiveContent(
-            expected_class_id,
-            queue_name,
-            request_id_or_consumer_tag,
-        )) = self.0.pop_front()
-        {
-            if expected_class_id == class_id {
-                handler(&queue_name, &request_id_or_consumer_tag, confirm_mode);
-                if length > 0 {
-                    self.0.push_front(ChannelReceiverState::ReceivingContent(
-                        queue_name,
-                        request_id_or_consumer_tag,
-                        length,
-                    ));
-                }
-                Ok(())
-            } else {
-                invalid_class_hanlder(format!(
-                    "content header frame with class id {} instead of {} received on channel {}",
-                    class_id, expected_class_id, channel_id
+        let (expected_class_id, content) = match self.0.pop_front() {
+            Some(ChannelReceiverState::WillReceiveContent(
+                expected_class_id,
+                queue_name,
+                request_id_or_consumer_tag,
+            )) => (
+                expected_class_id,
+                Content::Delivery(queue_name, request_id_or_consumer_tag),
+            ),
+            Some(ChannelReceiverState::WillReceiveReturn(expected_class_id)) => {
+                (expected_class_id, Content::Return)
+            }
+            Some(ChannelReceiverState::WillDiscardContent(
+                expected_class_id,
+                consumer_tag,
+                delivery_tag,
+            )) => (
+                expected_class_id,
+                Content::UnknownConsumer(consumer_tag, delivery_tag),
+            ),
+            _ => {
+                return error_handler(format!(
+                    "unexpected content header frame received on channel {}",
+                    channel_id
                 ))
             }
+        };
+        if expected_class_id != class_id {
+            return invalid_class_hanlder(format!(
+                "content header frame with class id {} instead of {} received on channel {}",
+                class_id, expected_class_id, channel_id
+            ));
+        }
+        if let Some(receiver) = content.receiver() {
+            handler(receiver, confirm_mode);
+        }
+        let content = if drop_content {
+            Content::Dropped
         } else {
-            error_handler(format!(
-                "unexpected content header frame received on channel {}",
-                channel_id
-            ))
+            content
+        };
+        if length > 0 {
+            self.0.push_front(content.receiving(length));
         }
+        Ok(())
     }
 
     pub(crate) fn receive<
-        Handler: FnOnce(&Option<ShortString>, &Option<ShortString>, usize, bool),
+        Handler: FnOnce(ContentReceiver<'_>, usize, bool),
         OnError: FnOnce(String) -> Result<()>,
     >(
         &mut self,
@@ -82,41 +119,99 @@ impl ChannelReceiverStates {
         error_handler: OnError,
         confirm_mode: bool,
     ) -> Result<()> {
-        if let Some(ChannelReceiverState::ReceivingContent(
-            queue_name,
-            request_id_or_consumer_tag,
-            len,
-        )) = self.0.pop_front()
-        {
-            if let Some(remaining) = len.checked_sub(length) {
-                handler(
-                    &queue_name,
-                    &request_id_or_consumer_tag,
-                    remaining,
-                    confirm_mode,
-                );
-                if remaining > 0 {
-                    self.0.push_front(ChannelReceiverState::ReceivingContent(
-                        queue_name,
-                        request_id_or_consumer_tag,
-                        remaining,
-                    ));
-                }
-                Ok(())
-            } else {
-                error_handler(format!("unexpectedly large content body frame received on channel {} ({} ybtes, expected {} bytes)", channel_id, length, len))
+        let (content, len) = match self.0.pop_front() {
+            Some(ChannelReceiverState::ReceivingContent(
+                queue_name,
+                request_id_or_consumer_tag,
+                len,
+            )) => (
+                Content::Delivery(queue_name, request_id_or_consumer_tag),
+                len,
+            ),
+            Some(ChannelReceiverState::ReceivingReturn(len)) => (Content::Return, len),
+            Some(ChannelReceiverState::DiscardingContent(consumer_tag, delivery_tag, len)) => {
+                (Content::UnknownConsumer(consumer_tag, delivery_tag), len)
             }
+            Some(ChannelReceiverState::DroppingContent(len)) => (Content::Dropped, len),
+            _ => {
+                return error_handler(format!(
+                    "unexpected content body frame received on channel {}",
+                    channel_id
+                ))
+            }
+        };
+        if let Some(remaining) = len.checked_sub(length) {
+            if let Some(receiver) = content.receiver() {
+                handler(receiver, remaining, confirm_mode);
+            }
+            if remaining > 0 {
+                self.0.push_front(content.receiving(remaining));
+            }
+            Ok(())
         } else {
-            error_handler(format!(
-                "unexpected content body frame received on channel {}",
-                channel_id
-            ))
+            error_handler(format!("unexpectedly large content body frame received on channel {} ({} ybtes, expected {} bytes)", channel_id, length, len))
+        }
+    }
+}
+
+/// The content announced by the state we just popped.
+enum Content {
+    Delivery(ShortString, Option<ShortString>),
+    Return,
+    UnknownConsumer(ShortString, DeliveryTag),
+    /// Content we don't hand to anyone, because it's too large
+    Dropped,
+}
+
+impl Content {
+    fn receiver(&self) -> Option<ContentReceiver<'_>> {
+        match self {
+            Content::Delivery(queue_name, request_id_or_consumer_tag) => Some(
+                ContentReceiver::Delivery(queue_name, request_id_or_consumer_tag),
+            ),
+            Content::Return => Some(ContentReceiver::Return),
+            Content::UnknownConsumer(consumer_tag, delivery_tag) => Some(
+                ContentReceiver::UnknownConsumer(consumer_tag, *delivery_tag),
+            ),
+            Content::Dropped => None,
+        }
+    }
+
+    fn receiving(self, remaining: usize) -> ChannelReceiverState {
+        match self {
+            Content::Delivery(queue_name, request_id_or_consumer_tag) => {
+                ChannelReceiverState::ReceivingContent(
+                    queue_name,
+                    request_id_or_consumer_tag,
+                    remaining,
+                )
+            }
+            Content::Return => ChannelReceiverState::ReceivingReturn(remaining),
+            Content::UnknownConsumer(consumer_tag, delivery_tag) => {
+                ChannelReceiverState::DiscardingContent(consumer_tag, delivery_tag, remaining)
+            }
+            Content::Dropped => ChannelReceiverState::DroppingContent(remaining),
         }
     }
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub(crate) enum ChannelReceiverState {
-    WillReceiveContent(ShortUInt, Option<ShortString>, Option<ShortString>),
-    ReceivingContent(Option<ShortString>, Option<ShortString>, usize),
+    WillReceiveContent(ShortUInt, ShortString, Option<ShortString>),
+    ReceivingContent(ShortString, Option<ShortString>, usize),
+    WillReceiveReturn(ShortUInt),
+    ReceivingReturn(usize),
+    WillDiscardContent(ShortUInt, ShortString, DeliveryTag),
+    DiscardingContent(ShortString, DeliveryTag, usize),
+    DroppingContent(usize),
+}
+
+/// What the content being received belongs to.
+pub(crate) enum ContentReceiver<'a> {
+    /// A delivery for the given queue, from basic.deliver or basic.get-ok
+    Delivery(&'a ShortString, &'a Option<ShortString>),
+    /// A message returned by basic.return
+    Return,
+    /// A delivery from basic.deliver for a consumer tag we don't know about
+    UnknownConsumer(&'a ShortString, DeliveryTag),
 }
@@ -0,0 +1,172 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+/// A snapshot of the message operations performed on a channel since it was opened.
+///
+/// The counters never reset and wrap around on overflow, use [`since`] to get the operations
+/// performed between two snapshots.
+///
+/// [`since`]: #method.since
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ChannelStats {
+    /// The number of messages published
+    pub published: u64,
+    /// The number of messages delivered to consumers or through `basic.get`
+    pub delivered: u64,
+    /// The number of `basic.ack` sent
+    pub acked: u64,
+    /// The number of `basic.nack` sent
+    pub nacked: u64,
+    /// The number of `basic.reject` sent
+    pub rejected: u64,
+}
+
+impl ChannelStats {
+    /// The operations performed since the `baseline` snapshot was taken.
+    pub fn since(&self, baseline: ChannelStats) -> ChannelStats {
+        ChannelStats {
+            published: self.published.wrapping_sub(baseline.published),
+            delivered: self.delivered.wrapping_sub(baseline.delivered),
+            acked: self.acked.wrapping_sub(baseline.acked),
+            nacked: self.nacked.wrapping_sub(baseline.nacked),
+            rejected: self.rejected.wrapping_sub(baseline.rejected),
+        }
+    }
+
+    pub(crate) fn add(&mut self, other: ChannelStats) {
+        self.published = self.published.wrapping_add(other.published);
+        self.delivered = self.delivered.wrapping_add(other.delivered);
+        self.acked = self.acked.wrapping_add(other.acked);
+        self.nacked = self.nacked.wrapping_add(other.nacked);
+        self.rejected = self.rejected.wrapping_add(other.rejected);
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ChannelStatsCounters(Arc<Counters>);
+
+#[derive(Debug, Default)]
+struct Counters {
+    published: AtomicU64,
+    delivered: AtomicU64,
+    acked: AtomicU64,
+    nacked: AtomicU64,
+    rejected: AtomicU64,
+}
+
+impl ChannelStatsCounters {
+    pub(crate) fn published(&self) {
+        self.0.published.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn delivered(&self) {
+        self.0.delivered.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn acked(&self) {
+        self.0.acked.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn nacked(&self) {
+        self.0.nacked.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn rejected(&self) {
+        self.0.rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> ChannelStats {
+        ChannelStats {
+            published: self.0.published.load(Ordering::Relaxed),
+            delivered: self.0.delivered.load(Ordering::Relaxed),
+            acked: self.0.acked.load(Ordering::Relaxed),
+            nacked: self.0.nacked.load(Ordering::Relaxed),
+            rejected: self.0.rejected.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::*;
+
+    #[test]
+    fn since_wraps() {
+        let baseline = ChannelStats {
+            published: u64::MAX,
+            acked: 3,
+            ..ChannelStats::default()
+        };
+        let stats = ChannelStats {
+            published: 1,
+            delivered: 2,
+            acked: 5,
+            ..ChannelStats::default()
+        };
+        assert_eq!(
+            stats.since(baseline),
+            ChannelStats {
+                published: 2,
+                delivered: 2,
+                acked: 2,
+                nacked: 0,
+                rejected: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn channel_stats() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        use crate::options::{BasicAckOptions, BasicNackOptions, BasicRejectOptions};
+        use crate::queue::{Queue, QueueState};
+
+        let consumer_tag = ShortString::from("consumer-tag");
+        let consumer = Consumer::new(consumer_tag.clone(), DefaultExecutor::default().unwrap());
+        let (conn, channel, frames) = connected_channel();
+        let mut queue: QueueState = Queue::new("consumed".into(), 0, 0).into();
+        queue.register_consumer(consumer_tag.clone(), consumer);
+        channel.register_queue(queue);
+
+        for delivery_tag in 1..=3 {
+            deliver_with_delivery_count(&conn, &channel, &consumer_tag, delivery_tag, 0);
+        }
+        let baseline = channel.stats();
+        assert_eq!(baseline.delivered, 3);
+
+        let acks = {
+            let channel = channel.clone();
+            async_global_executor::spawn(async move {
+                channel.basic_ack(1, BasicAckOptions::default()).await?;
+                channel.basic_nack(2, BasicNackOptions::default()).await?;
+                channel.basic_reject(3, BasicRejectOptions::default()).await
+            })
+        };
+        // Pretend the io loop wrote the frames
+        let mut written = 0;
+        while written < 3 {
+            if let Some((_, Some(resolver))) = frames.pop(true) {
+                resolver.swear(Ok(()));
+                written += 1;
+            } else {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+        }
+        async_global_executor::block_on(acks).unwrap();
+
+        assert_eq!(
+            channel.stats_since(baseline),
+            crate::ChannelStats {
+                published: 0,
+                delivered: 0,
+                acked: 1,
+                nacked: 1,
+                rejected: 1,
+            }
+        );
+    }
+}
@@ -1,16 +1,26 @@
 use crate::{
-    channel_receiver_state::ChannelReceiverStates,
-    types::{ShortString, ShortUInt},
-    Result,
+    acknowledgement::DeliveryTag,
+    channel_receiver_state::{ChannelReceiverStates, ContentReceiver},
+    options::BasicQosOptions,
+    tracing_integration::targets,
+    types::{ShortShortUInt, ShortString, ShortUInt},
+    Error, Result,
 };
 use parking_lot::Mutex;
-use std::{fmt, sync::Arc};
+use std::{collections::HashMap, fmt, sync::Arc};
 use tracing::trace;
 
+type FlowObserver = Arc<dyn Fn(bool) + Send + Sync>;
+
 #[derive(Clone, Default)]
 pub struct ChannelStatus(Arc<Mutex<Inner>>);
 
 impl ChannelStatus {
+    /// Whether both are the status of the same channel, as the ids get reused.
+    pub(crate) fn is_same(&self, other: &ChannelStatus) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+
     pub fn initializing(&self) -> bool {
         self.0.lock().state == ChannelState::Initial
     }
@@ -33,7 +43,43 @@ impl ChannelStatus {
 
     pub(crate) fn set_confirm(&self) {
         self.0.lock().confirm = true;
-        trace!("Publisher confirms activated");
+        trace!(target: targets::CHANNEL, "Publisher confirms activated");
+    }
+
+    /// The prefetch count and options of the last successful `basic.qos`.
+    pub(crate) fn prefetch(&self) -> Option<(ShortUInt, BasicQosOptions)> {
+        self.0.lock().prefetch
+    }
+
+    pub(crate) fn set_prefetch(&self, prefetch_count: ShortUInt, options: BasicQosOptions) {
+        self.0.lock().prefetch = Some((prefetch_count, options));
+    }
+
+    /// The `x-max-priority` of a queue declared on this channel.
+    pub(crate) fn max_priority(&self, queue: &str) -> Option<ShortShortUInt> {
+        self.0.lock().max_priorities.get(queue).copied()
+    }
+
+    pub(crate) fn set_max_priority(&self, queue: ShortString, max_priority: ShortShortUInt) {
+        self.0.lock().max_priorities.insert(queue, max_priority);
+    }
+
+    /// The name of the last queue declared on this channel.
+    pub(crate) fn last_declared_queue(&self) -> Option<ShortString> {
+        self.0.lock().last_declared_queue.clone()
+    }
+
+    pub(crate) fn set_last_declared_queue(&self, queue: ShortString) {
+        self.0.lock().last_declared_queue = Some(queue);
+    }
+
+    /// The `x-queue-type` of a queue declared on this channel.
+    pub(crate) fn queue_type(&self, queue: &str) -> Option<ShortString> {
+        self.0.lock().queue_types.get(queue).cloned()
+    }
+
+    pub(crate) fn set_queue_type(&self, queue: ShortString, queue_type: ShortString) {
+        self.0.lock().queue_types.insert(queue, queue_type);
     }
 
     pub fn state(&self) -> ChannelState {
@@ -41,7 +87,31 @@ impl ChannelStatus {
     }
 
     pub(crate) fn set_state(&self, state: ChannelState) {
-        self.0.lock().state = state;
+        let mut inner = self.0.lock();
+        if let ChannelState::Initial | ChannelState::Connected = state {
+            inner.error_cause = None;
+        }
+        inner.state = state;
+    }
+
+    /// The error which closed the channel, such as the error the server closed it with.
+    pub fn error_cause(&self) -> Option<Error> {
+        self.0.lock().error_cause.clone()
+    }
+
+    /// Remember why the channel got closed, unless it already got closed for another reason.
+    pub(crate) fn set_error_cause(&self, error: Error) {
+        self.0.lock().error_cause.get_or_insert(error);
+    }
+
+    /// The error for the methods sent in the wrong state: the error which closed the channel
+    /// if it got closed.
+    pub(crate) fn state_error(&self) -> Error {
+        let inner = self.0.lock();
+        inner
+            .error_cause
+            .clone()
+            .unwrap_or_else(|| Error::InvalidChannelState(inner.state.clone()))
     }
 
     pub(crate) fn auto_close(&self, id: u16) -> bool {
@@ -56,7 +126,7 @@ impl ChannelStatus {
     pub(crate) fn set_will_receive(
         &self,
         class_id: ShortUInt,
-        queue_name: Option<ShortString>,
+        queue_name: ShortString,
         request_id_or_consumer_tag: Option<ShortString>,
     ) {
         self.0.lock().receiver_state.set_will_receive(
@@ -66,8 +136,28 @@ impl ChannelStatus {
         );
     }
 
+    pub(crate) fn set_will_receive_return(&self, class_id: ShortUInt) {
+        self.0
+            .lock()
+            .receiver_state
+            .set_will_receive_return(class_id);
+    }
+
+    pub(crate) fn set_will_discard(
+        &self,
+        class_id: ShortUInt,
+        consumer_tag: ShortString,
+        delivery_tag: DeliveryTag,
+    ) {
+        self.0
+            .lock()
+            .receiver_state
+            .set_will_discard(class_id, consumer_tag, delivery_tag);
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn set_content_length<
-        Handler: FnOnce(&Option<ShortString>, &Option<ShortString>, bool),
+        Handler: FnOnce(ContentReceiver<'_>, bool),
         OnInvalidClass: FnOnce(String) -> Result<()>,
         OnError: FnOnce(String) -> Result<()>,
     >(
@@ -78,6 +168,7 @@ impl ChannelStatus {
         handler: Handler,
         invalid_class_hanlder: OnInvalidClass,
         error_handler: OnError,
+        drop_content: bool,
     ) -> Result<()> {
         let mut inner = self.0.lock();
         let confirm_mode = inner.confirm;
@@ -89,11 +180,12 @@ impl ChannelStatus {
             invalid_class_hanlder,
             error_handler,
             confirm_mode,
+            drop_content,
         )
     }
 
     pub(crate) fn receive<
-        Handler: FnOnce(&Option<ShortString>, &Option<ShortString>, usize, bool),
+        Handler: FnOnce(ContentReceiver<'_>, usize, bool),
         OnError: FnOnce(String) -> Result<()>,
     >(
         &self,
@@ -110,7 +202,20 @@ impl ChannelStatus {
     }
 
     pub(crate) fn set_send_flow(&self, flow: bool) {
-        self.0.lock().send_flow = flow;
+        // Call the observer without holding the lock so that it can safely use the channel
+        let observer = {
+            let mut inner = self.0.lock();
+            let changed = inner.send_flow != flow;
+            inner.send_flow = flow;
+            inner.flow_observer.clone().filter(|_| changed)
+        };
+        if let Some(observer) = observer {
+            observer(flow);
+        }
+    }
+
+    pub(crate) fn set_flow_observer(&self, observer: FlowObserver) {
+        self.0.lock().flow_observer = Some(observer);
     }
 
     pub(crate) fn flow(&self) -> bool {
@@ -139,8 +244,13 @@ impl fmt::Debug for ChannelStatus {
         if let Some(inner) = self.0.try_lock() {
             debug
                 .field("state", &inner.state)
+                .field("error_cause", &inner.error_cause)
                 .field("receiver_state", &inner.receiver_state)
                 .field("confirm", &inner.confirm)
+                .field("prefetch", &inner.prefetch)
+                .field("max_priorities", &inner.max_priorities)
+                .field("queue_types", &inner.queue_types)
+                .field("last_declared_queue", &inner.last_declared_queue)
                 .field("send_flow", &inner.send_flow);
         }
         debug.finish()
@@ -149,8 +259,14 @@ impl fmt::Debug for ChannelStatus {
 
 struct Inner {
     confirm: bool,
+    prefetch: Option<(ShortUInt, BasicQosOptions)>,
+    max_priorities: HashMap<ShortString, ShortShortUInt>,
+    queue_types: HashMap<ShortString, ShortString>,
+    last_declared_queue: Option<ShortString>,
     send_flow: bool,
+    flow_observer: Option<FlowObserver>,
     state: ChannelState,
+    error_cause: Option<Error>,
     receiver_state: ChannelReceiverStates,
 }
 
@@ -158,8 +274,14 @@ impl Default for Inner {
     fn default() -> Self {
         Self {
             confirm: false,
+            prefetch: None,
+            max_priorities: HashMap::default(),
+            queue_types: HashMap::default(),
+            last_declared_queue: None,
             send_flow: true,
+            flow_observer: None,
             state: ChannelState::default(),
+            error_cause: None,
             receiver_state: ChannelReceiverStates::default(),
         }
     }
@@ -1,19 +1,29 @@
 use crate::{
+    channel_lifecycle::{ChannelLifecycleObserver, ChannelLifecycleObservers, CloseReason},
+    channel_stats::ChannelStats,
     connection_closer::ConnectionCloser,
+    connection_stats::{ConnectionStats, ConnectionStatsCounters},
+    declaration_cache::DeclarationCache,
     error_handler::ErrorHandler,
     executor::Executor,
     frames::Frames,
     id_sequence::IdSequence,
     internal_rpc::InternalRPCHandle,
-    protocol::{AMQPClass, AMQPError, AMQPHardError},
+    protocol::{self, AMQPClass, AMQPError, AMQPHardError},
+    server_named_queues::ServerNamedQueues,
     socket_state::SocketStateHandle,
+    tracing_integration::targets,
     BasicProperties, Channel, ChannelState, Configuration, ConnectionState, ConnectionStatus,
     Error, Promise, Result,
 };
 use amq_protocol::frame::{AMQPFrame, ProtocolVersion};
 use parking_lot::Mutex;
-use std::{collections::HashMap, fmt, sync::Arc};
-use tracing::{debug, error, level_enabled, trace, Level};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    sync::Arc,
+};
+use tracing::{debug, error, level_enabled, trace, warn, Level};
 
 #[derive(Clone)]
 pub(crate) struct Channels {
@@ -23,6 +33,8 @@ pub(crate) struct Channels {
     executor: Arc<dyn Executor>,
     frames: Frames,
     error_handler: ErrorHandler,
+    lifecycle_observers: ChannelLifecycleObservers,
+    stats: ConnectionStatsCounters,
 }
 
 impl Channels {
@@ -41,17 +53,21 @@ impl Channels {
             executor,
             frames,
             error_handler: ErrorHandler::default(),
+            lifecycle_observers: ChannelLifecycleObservers::default(),
+            stats: ConnectionStatsCounters::default(),
         }
     }
 
     pub(crate) fn create(&self, connection_closer: Arc<ConnectionCloser>) -> Result<Channel> {
-        self.inner.lock().create(
+        let channel = self.inner.lock().create(
             self.connection_status.clone(),
             self.internal_rpc.clone(),
             self.frames.clone(),
             self.executor.clone(),
             connection_closer,
-        )
+        )?;
+        self.stats.channel_opened();
+        Ok(channel)
     }
 
     pub(crate) fn create_zero(&self) {
@@ -68,13 +84,100 @@ impl Channels {
             .set_state(ChannelState::Connected);
     }
 
+    pub(crate) fn declaration_cache(&self) -> DeclarationCache {
+        self.inner.lock().declaration_cache.clone()
+    }
+
+    pub(crate) fn stats_counters(&self) -> ConnectionStatsCounters {
+        self.stats.clone()
+    }
+
+    pub(crate) fn stats(&self) -> ConnectionStats {
+        let mut open_channels = ChannelStats::default();
+        for (id, channel) in self.inner.lock().channels.iter() {
+            if *id != 0 {
+                open_channels.add(channel.stats());
+            }
+        }
+        self.stats.snapshot(open_channels)
+    }
+
+    fn channel_closed(&self, id: u16, channel: &Channel) {
+        if id != 0 {
+            self.stats.channel_closed(channel.stats());
+        }
+    }
+
     pub(crate) fn get(&self, id: u16) -> Option<Channel> {
         self.inner.lock().channels.get(&id).cloned()
     }
 
+    /// The channels currently connected, excluding channel 0.
+    pub(crate) fn connected(&self) -> Vec<Channel> {
+        self.inner
+            .lock()
+            .channels
+            .iter()
+            .filter(|(id, channel)| **id != 0 && channel.status().connected())
+            .map(|(_, channel)| channel.clone())
+            .collect()
+    }
+
+    pub(crate) fn try_count(&self) -> Option<usize> {
+        self.inner
+            .try_lock()
+            .map(|inner| inner.channels.keys().filter(|id| **id != 0).count())
+    }
+
+    /// The number of connected and errored channels, excluding channel 0.
+    pub(crate) fn try_channel_states(&self) -> Option<(usize, usize)> {
+        self.inner.try_lock().map(|inner| {
+            inner.channels.iter().filter(|(id, _)| **id != 0).fold(
+                (0, 0),
+                |(open, errored), (_, channel)| match channel.status().state() {
+                    ChannelState::Connected => (open + 1, errored),
+                    ChannelState::Error => (open, errored + 1),
+                    _ => (open, errored),
+                },
+            )
+        })
+    }
+
+    pub(crate) fn try_pending_frames(&self) -> Option<usize> {
+        self.frames.try_pending_count()
+    }
+
+    fn channel_ids(&self) -> HashSet<u16> {
+        self.inner.lock().channels.keys().cloned().collect()
+    }
+
     pub(crate) fn remove(&self, id: u16, error: Error) -> Result<()> {
-        self.frames.clear_expected_replies(id, error);
-        if self.inner.lock().channels.remove(&id).is_some() {
+        let channel = self.inner.lock().channels.remove(&id);
+        if let Some(ChannelState::Error) = channel.as_ref().map(|channel| channel.status().state())
+        {
+            // A failed channel takes its connection down, its queued frames won't get through
+            let dropped = self.frames.cancel_channel_frames(id, error.clone());
+            if dropped > 0 {
+                warn!(
+                    target: targets::CHANNEL,
+                    "dropped {} frames queued on failed channel {}", dropped, id
+                );
+            }
+            self.stats.frames_dropped(dropped);
+        } else {
+            self.frames.clear_expected_replies(id, error.clone());
+        }
+        if let Some(channel) = channel {
+            self.channel_closed(id, &channel);
+            if let ChannelState::Error = channel.status().state() {
+                self.lifecycle_observers.error(id, &error);
+            } else {
+                let reason = match channel.status().error_cause() {
+                    Some(Error::ProtocolError(error)) => Some(CloseReason::from(&error)),
+                    _ => None,
+                };
+                self.lifecycle_observers.closed(id, reason);
+            }
             Ok(())
         } else {
             Err(Error::InvalidChannel(id))
@@ -82,9 +185,17 @@ impl Channels {
     }
 
     pub(crate) fn receive_method(&self, id: u16, method: AMQPClass) -> Result<()> {
+        let opened = matches!(
+            method,
+            AMQPClass::Channel(protocol::channel::AMQPMethod::OpenOk(_))
+        );
         self.get(id)
             .map(|channel| channel.receive_method(method))
-            .unwrap_or_else(|| Err(Error::InvalidChannel(id)))
+            .unwrap_or_else(|| Err(Error::InvalidChannel(id)))?;
+        if opened {
+            self.lifecycle_observers.opened(id);
+        }
+        Ok(())
     }
 
     pub(crate) fn handle_content_header_frame(
@@ -105,6 +216,24 @@ impl Channels {
             .unwrap_or_else(|| Err(Error::InvalidChannel(id)))
     }
 
+    pub(crate) fn cancel(&self) {
+        let channels = self
+            .inner
+            .lock()
+            .channels
+            .iter()
+            .filter(|(id, _)| **id != 0)
+            .map(|(_, channel)| channel.clone())
+            .collect::<Vec<_>>();
+        // The consumers' delegates may use the connection, don't hold its lock while they run
+        for channel in channels {
+            self.frames
+                .clear_expected_replies(channel.id(), Error::Cancelled);
+            channel.error_publisher_confirms(Error::Cancelled);
+            channel.cancel_consumers();
+        }
+    }
+
     pub(crate) fn set_connection_closing(&self) {
         self.connection_status.set_state(ConnectionState::Closing);
         for channel in self.inner.lock().channels.values() {
@@ -114,11 +243,23 @@ impl Channels {
 
     pub(crate) fn set_connection_closed(&self, error: Error) {
         self.connection_status.set_state(ConnectionState::Closed);
-        for (id, channel) in self.inner.lock().channels.drain() {
-            self.frames.clear_expected_replies(id, error.clone());
-            channel.set_state(ChannelState::Closed);
-            channel.error_publisher_confirms(error.clone());
+        let mut closed = Vec::new();
+        {
+            let mut inner = self.inner.lock();
+            inner.declaration_cache.clear();
+            for (id, channel) in inner.channels.drain() {
+                self.frames.clear_expected_replies(id, error.clone());
+                self.channel_closed(id, &channel);
+                channel.set_state(ChannelState::Closed);
+                channel.error_publisher_confirms(error.clone());
+                closed.push(channel);
+            }
+        }
+        for channel in closed {
             channel.cancel_consumers();
+            if channel.id() != 0 {
+                self.lifecycle_observers.closed(channel.id(), None);
+            }
         }
     }
 
@@ -127,15 +268,31 @@ impl Channels {
             return;
         }
 
-        error!("Connection error: {}", error);
+        error!(target: targets::CONNECTION, "Connection error: {}", error);
         self.connection_status.set_state(ConnectionState::Error);
         self.frames.drop_pending(error.clone());
         self.error_handler.on_error(error.clone());
-        for (id, channel) in self.inner.lock().channels.drain() {
-            self.frames.clear_expected_replies(id, error.clone());
-            channel.set_state(ChannelState::Error);
-            channel.error_publisher_confirms(error.clone());
+        let mut errored = Vec::new();
+        {
+            let mut inner = self.inner.lock();
+            inner.declaration_cache.clear();
+            for (id, channel) in inner.channels.drain() {
+                self.frames.clear_expected_replies(id, error.clone());
+                self.channel_closed(id, &channel);
+                let notify = channel.set_errored();
+                channel.error_publisher_confirms(error.clone());
+                errored.push((channel, notify));
+            }
+        }
+        for (channel, notify) in errored {
             channel.error_consumers(error.clone());
+            if channel.id() == 0 {
+                continue;
+            }
+            if notify {
+                channel.on_error(error.clone());
+            }
+            self.lifecycle_observers.error(channel.id(), &error);
         }
     }
 
@@ -148,7 +305,7 @@ impl Channels {
     }
 
     pub(crate) fn send_heartbeat(&self) {
-        debug!("send heartbeat");
+        debug!(target: targets::HEARTBEAT, "send heartbeat");
 
         if let Some(channel0) = self.get(0) {
             let (promise, resolver) = Promise::new();
@@ -163,7 +320,9 @@ impl Channels {
     }
 
     pub(crate) fn handle_frame(&self, f: AMQPFrame) -> Result<()> {
+        self.stats.frame_received();
         if let Err(err) = self.do_handle_frame(f) {
+            self.frames.cleanup_closed_channels(&self.channel_ids());
             self.set_connection_error(err.clone());
             Err(err)
         } else {
@@ -172,10 +331,11 @@ impl Channels {
     }
 
     fn do_handle_frame(&self, f: AMQPFrame) -> Result<()> {
-        trace!("will handle frame: {:?}", f);
+        trace!(target: targets::IO, "will handle frame: {:?}", f);
         match f {
             AMQPFrame::ProtocolHeader(version) => {
                 error!(
+                    target: targets::CONNECTION,
                     "we asked for AMQP {} but the server only supports AMQP {}",
                     ProtocolVersion::amqp_0_9_1(),
                     version
@@ -191,9 +351,13 @@ impl Channels {
             }
             AMQPFrame::Heartbeat(channel_id) => {
                 if channel_id == 0 {
-                    debug!("received heartbeat from server");
+                    debug!(target: targets::HEARTBEAT, "received heartbeat from server");
                 } else {
-                    error!("received invalid heartbeat on channel {}", channel_id);
+                    error!(
+                        target: targets::HEARTBEAT,
+                        "received invalid heartbeat on channel {}",
+                        channel_id
+                    );
                     let error = AMQPError::new(
                         AMQPHardError::FRAMEERROR.into(),
                         format!("heartbeat frame received on channel {}", channel_id).into(),
@@ -216,7 +380,11 @@ impl Channels {
             }
             AMQPFrame::Header(channel_id, class_id, header) => {
                 if channel_id == 0 {
-                    error!("received content header on channel {}", channel_id);
+                    error!(
+                        target: targets::CHANNEL,
+                        "received content header on channel {}",
+                        channel_id
+                    );
                     let error = AMQPError::new(
                         AMQPHardError::CHANNELERROR.into(),
                         format!("content header frame received on channel {}", channel_id).into(),
@@ -235,6 +403,36 @@ impl Channels {
                         });
                     }
                     return Err(Error::ProtocolError(error));
+                } else if header.weight != 0 {
+                    // The class id gets checked against the one of the method the content
+                    // belongs to once the channel handles the header
+                    error!(
+                        target: targets::CHANNEL,
+                        "received malformed content header on channel {}: {:?}",
+                        channel_id, header
+                    );
+                    let error = AMQPError::new(
+                        AMQPHardError::FRAMEERROR.into(),
+                        format!(
+                            "malformed content header frame received on channel {} (weight {})",
+                            channel_id, header.weight
+                        )
+                        .into(),
+                    );
+                    if let Some(channel0) = self.get(0) {
+                        let error = error.clone();
+                        self.internal_rpc.register_internal_future(async move {
+                            channel0
+                                .connection_close(
+                                    error.get_id(),
+                                    error.get_message().as_str(),
+                                    class_id,
+                                    0,
+                                )
+                                .await
+                        });
+                    }
+                    return Err(Error::ProtocolError(error));
                 } else {
                     self.handle_content_header_frame(
                         channel_id,
@@ -254,6 +452,10 @@ impl Channels {
     pub(crate) fn set_error_handler<E: FnMut(Error) + Send + 'static>(&self, handler: E) {
         self.error_handler.set_handler(handler);
     }
+
+    pub(crate) fn register_lifecycle_observer(&self, observer: Arc<dyn ChannelLifecycleObserver>) {
+        self.lifecycle_observers.register(observer);
+    }
 }
 
 impl fmt::Debug for Channels {
@@ -270,6 +472,7 @@ impl fmt::Debug for Channels {
             .field("executor", &self.executor)
             .field("connection_status", &self.connection_status)
             .field("error_handler", &self.error_handler)
+            .field("lifecycle_observers", &self.lifecycle_observers)
             .finish()
     }
 }
@@ -278,6 +481,10 @@ struct Inner {
     channels: HashMap<u16, Channel>,
     channel_id: IdSequence<u16>,
     configuration: Configuration,
+    declaration_cache: DeclarationCache,
+    /* Kept across the failures of the channels and not cleared with the connection, as
+    recovery is when it's needed */
+    server_named_queues: ServerNamedQueues,
     waker: SocketStateHandle,
 }
 
@@ -287,6 +494,8 @@ impl Inner {
             channels: HashMap::default(),
             channel_id: IdSequence::new(false),
             configuration,
+            declaration_cache: DeclarationCache::default(),
+            server_named_queues: ServerNamedQueues::default(),
             waker,
         }
     }
@@ -300,11 +509,13 @@ impl Inner {
         executor: Arc<dyn Executor>,
         connection_closer: Option<Arc<ConnectionCloser>>,
     ) -> Channel {
-        debug!("create channel with id {}", id);
+        debug!(target: targets::CHANNEL, "create channel with id {}", id);
         let channel = Channel::new(
             id,
             self.configuration.clone(),
             connection_status,
+            self.declaration_cache.clone(),
+            self.server_named_queues.clone(),
             self.waker.clone(),
             internal_rpc,
             frames,
@@ -323,7 +534,7 @@ impl Inner {
         executor: Arc<dyn Executor>,
         connection_closer: Arc<ConnectionCloser>,
     ) -> Result<Channel> {
-        debug!("create channel");
+        debug!(target: targets::CHANNEL, "create channel");
         self.channel_id.set_max(self.configuration.channel_max());
         let first_id = self.channel_id.next();
         let mut id = first_id;
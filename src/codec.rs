@@ -0,0 +1,200 @@
+//! Payload codecs picked according to the `content_type` property of the messages.
+//!
+//! A [`PayloadCodec`] converts payloads from and to a [`DecodedPayload`], a dynamically typed
+//! value. The codecs are registered by content type on the [`CodecRegistry`] of the connection,
+//! using [`ConnectionProperties::with_codec`]. A JSON codec is always registered for
+//! `application/json`, and a MessagePack one for `application/msgpack` and
+//! `application/x-msgpack` with the `msgpack` feature.
+//!
+//! [`Channel::publish_encoded`] encodes a value with the codec of the content type it's given,
+//! and [`Delivery::decode_with`] decodes a payload with the codec of its content type:
+//!
+//! ```rust,no_run
+//! use lapin::{options::*, BasicProperties, Connection, ConnectionProperties, Result};
+//! use serde::{Deserialize, Serialize};
+//!
+//! #[derive(Deserialize, Serialize)]
+//! struct Order {
+//!     id: u64,
+//! }
+//!
+//! async fn publish(conn: &Connection) -> Result<()> {
+//!     let channel = conn.create_channel().await?;
+//!     channel
+//!         .publish_encoded(
+//!             "",
+//!             "orders",
+//!             BasicPublishOptions::default(),
+//!             &Order { id: 42 },
+//!             "application/json",
+//!             BasicProperties::default(),
+//!         )
+//!         .await?;
+//!     Ok(())
+//! }
+//! ```
+//!
+//! [`PayloadCodec`]: ./trait.PayloadCodec.html
+//! [`DecodedPayload`]: ./type.DecodedPayload.html
+//! [`CodecRegistry`]: ./struct.CodecRegistry.html
+//! [`ConnectionProperties::with_codec`]: ../struct.ConnectionProperties.html#method.with_codec
+//! [`Channel::publish_encoded`]: ../struct.Channel.html#method.publish_encoded
+//! [`Delivery::decode_with`]: ../message/struct.Delivery.html#method.decode_with
+
+use crate::{Error, Result};
+use std::{collections::HashMap, fmt, sync::Arc};
+
+/// A payload decoded without knowing its type, which can then be converted to one with
+/// `serde_json::from_value`.
+pub type DecodedPayload = serde_json::Value;
+
+/// Converts payloads from and to a [`DecodedPayload`].
+///
+/// [`DecodedPayload`]: ./type.DecodedPayload.html
+pub trait PayloadCodec: Send + Sync {
+    fn decode(&self, bytes: &[u8]) -> Result<DecodedPayload>;
+    fn encode(&self, value: &DecodedPayload) -> Result<Vec<u8>>;
+}
+
+/// The codec of `application/json`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct JsonCodec;
+
+impl PayloadCodec for JsonCodec {
+    fn decode(&self, bytes: &[u8]) -> Result<DecodedPayload> {
+        serde_json::from_slice(bytes).map_err(|e| Error::PayloadCodecError(e.to_string()))
+    }
+
+    fn encode(&self, value: &DecodedPayload) -> Result<Vec<u8>> {
+        serde_json::to_vec(value).map_err(|e| Error::PayloadCodecError(e.to_string()))
+    }
+}
+
+/// The codec of `application/msgpack` and `application/x-msgpack`.
+#[cfg(feature = "msgpack")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MsgpackCodec;
+
+#[cfg(feature = "msgpack")]
+impl PayloadCodec for MsgpackCodec {
+    fn decode(&self, bytes: &[u8]) -> Result<DecodedPayload> {
+        rmp_serde::from_read_ref(bytes).map_err(|e| Error::PayloadCodecError(e.to_string()))
+    }
+
+    fn encode(&self, value: &DecodedPayload) -> Result<Vec<u8>> {
+        rmp_serde::to_vec_named(value).map_err(|e| Error::PayloadCodecError(e.to_string()))
+    }
+}
+
+/// The codecs of a connection, by content type.
+///
+/// Content types are matched without their parameters and regardless of the case, so that
+/// `application/json; charset=utf-8` uses the codec of `application/json`.
+#[derive(Clone)]
+pub struct CodecRegistry {
+    codecs: HashMap<String, Arc<dyn PayloadCodec>>,
+}
+
+impl Default for CodecRegistry {
+    fn default() -> Self {
+        let mut registry = Self {
+            codecs: HashMap::default(),
+        };
+        registry.register("application/json", Arc::new(JsonCodec));
+        #[cfg(feature = "msgpack")]
+        {
+            registry.register("application/msgpack", Arc::new(MsgpackCodec));
+            registry.register("application/x-msgpack", Arc::new(MsgpackCodec));
+        }
+        registry
+    }
+}
+
+impl CodecRegistry {
+    /// Use `codec` for `content_type`, replacing the codec previously registered for it.
+    pub fn register(&mut self, content_type: &str, codec: Arc<dyn PayloadCodec>) {
+        self.codecs.insert(normalize(content_type), codec);
+    }
+
+    /// The codec registered for `content_type`.
+    pub fn get(&self, content_type: &str) -> Result<Arc<dyn PayloadCodec>> {
+        self.codecs
+            .get(&normalize(content_type))
+            .cloned()
+            .ok_or_else(|| Error::UnknownContentType(content_type.to_string()))
+    }
+}
+
+impl fmt::Debug for CodecRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut content_types = self.codecs.keys().collect::<Vec<_>>();
+        content_types.sort();
+        f.debug_struct("CodecRegistry")
+            .field("content_types", &content_types)
+            .finish()
+    }
+}
+
+fn normalize(content_type: &str) -> String {
+    content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Upper;
+
+    impl PayloadCodec for Upper {
+        fn decode(&self, bytes: &[u8]) -> Result<DecodedPayload> {
+            Ok(String::from_utf8_lossy(bytes).to_lowercase().into())
+        }
+
+        fn encode(&self, value: &DecodedPayload) -> Result<Vec<u8>> {
+            Ok(value
+                .as_str()
+                .unwrap_or_default()
+                .to_uppercase()
+                .into_bytes())
+        }
+    }
+
+    #[test]
+    fn codec_by_content_type() {
+        let mut registry = CodecRegistry::default();
+        registry.register("text/x-upper", Arc::new(Upper));
+
+        let json = registry.get("Application/JSON; charset=utf-8").unwrap();
+        assert_eq!(
+            json.decode(br#"{"id":42}"#).unwrap(),
+            serde_json::json!({ "id": 42 })
+        );
+        let upper = registry.get("text/x-upper").unwrap();
+        assert_eq!(upper.encode(&"hello".into()).unwrap(), b"HELLO");
+
+        assert_eq!(
+            registry.get("application/x-protobuf").err(),
+            Some(Error::UnknownContentType("application/x-protobuf".into()))
+        );
+        assert!(matches!(
+            json.decode(b"not json"),
+            Err(Error::PayloadCodecError(_))
+        ));
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[test]
+    fn msgpack() {
+        let registry = CodecRegistry::default();
+        let codec = registry.get("application/msgpack").unwrap();
+        let value = serde_json::json!({ "id": 42, "tags": ["a", "b"] });
+        let encoded = codec.encode(&value).unwrap();
+        assert_eq!(codec.decode(&encoded).unwrap(), value);
+        assert!(registry.get("application/x-msgpack").is_ok());
+    }
+}
@@ -1,6 +1,9 @@
-use crate::protocol;
+#[cfg(feature = "serde")]
+use crate::codec::CodecRegistry;
+use crate::{protocol, ChannelDefaults, UnknownConsumerPolicy};
 use parking_lot::RwLock;
-use std::{fmt, sync::Arc};
+use std::{fmt, sync::Arc, time::Duration};
+use tracing::Level;
 
 #[derive(Clone, Default)]
 pub struct Configuration {
@@ -32,22 +35,228 @@ impl Configuration {
     pub(crate) fn set_heartbeat(&self, heartbeat: u16) {
         self.inner.write().heartbeat = heartbeat;
     }
+
+    pub fn unknown_consumer_policy(&self) -> UnknownConsumerPolicy {
+        self.inner.read().unknown_consumer_policy
+    }
+
+    pub(crate) fn set_unknown_consumer_policy(&self, policy: UnknownConsumerPolicy) {
+        self.inner.write().unknown_consumer_policy = policy;
+    }
+
+    pub fn max_message_size(&self) -> u64 {
+        self.inner.read().max_message_size
+    }
+
+    pub(crate) fn set_max_message_size(&self, max_message_size: u64) {
+        self.inner.write().max_message_size = max_message_size;
+    }
+
+    pub fn oversized_message_policy(&self) -> OversizedMessagePolicy {
+        self.inner.read().oversized_message_policy
+    }
+
+    pub(crate) fn set_oversized_message_policy(&self, policy: OversizedMessagePolicy) {
+        self.inner.write().oversized_message_policy = policy;
+    }
+
+    pub fn immediate_flag(&self) -> bool {
+        self.inner.read().immediate_flag
+    }
+
+    pub(crate) fn set_immediate_flag(&self, immediate_flag: bool) {
+        self.inner.write().immediate_flag = immediate_flag;
+    }
+
+    pub fn strict_frame_ordering(&self) -> bool {
+        self.inner.read().strict_frame_ordering
+    }
+
+    pub(crate) fn set_strict_frame_ordering(&self, strict_frame_ordering: bool) {
+        self.inner.write().strict_frame_ordering = strict_frame_ordering;
+    }
+
+    pub fn instrumentation_level(&self) -> Level {
+        self.inner.read().instrumentation_level
+    }
+
+    pub(crate) fn set_instrumentation_level(&self, level: Level) {
+        self.inner.write().instrumentation_level = level;
+    }
+
+    pub fn channel_close_timeout(&self) -> Duration {
+        self.inner.read().channel_close_timeout
+    }
+
+    pub(crate) fn set_channel_close_timeout(&self, timeout: Duration) {
+        self.inner.write().channel_close_timeout = timeout;
+    }
+
+    pub fn mandatory_return_window(&self) -> Duration {
+        self.inner.read().mandatory_return_window
+    }
+
+    pub(crate) fn set_mandatory_return_window(&self, window: Duration) {
+        self.inner.write().mandatory_return_window = window;
+    }
+
+    pub fn priority_validation(&self) -> PriorityValidation {
+        self.inner.read().priority_validation
+    }
+
+    pub(crate) fn set_priority_validation(&self, validation: PriorityValidation) {
+        self.inner.write().priority_validation = validation;
+    }
+
+    pub fn strict_field_validation(&self) -> bool {
+        self.inner.read().strict_field_validation
+    }
+
+    pub(crate) fn set_strict_field_validation(&self, strict: bool) {
+        self.inner.write().strict_field_validation = strict;
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn codecs(&self) -> Arc<CodecRegistry> {
+        self.inner.read().codecs.clone()
+    }
+
+    #[cfg(feature = "serde")]
+    pub(crate) fn set_codecs(&self, codecs: CodecRegistry) {
+        self.inner.write().codecs = Arc::new(codecs);
+    }
+
+    /// What gets set up on every channel created on this connection, see
+    /// [`ConnectionProperties::with_channel_defaults`].
+    ///
+    /// [`ConnectionProperties::with_channel_defaults`]: ./struct.ConnectionProperties.html#method.with_channel_defaults
+    pub fn channel_defaults(&self) -> ChannelDefaults {
+        self.inner.read().channel_defaults.clone()
+    }
+
+    pub(crate) fn set_channel_defaults(&self, channel_defaults: ChannelDefaults) {
+        self.inner.write().channel_defaults = channel_defaults;
+    }
+}
+
+/// How long `Connection::close_all` waits for the channels to be closed by default.
+pub(crate) const DEFAULT_CHANNEL_CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long `Channel::basic_publish_mandatory` waits for a return by default.
+pub(crate) const DEFAULT_MANDATORY_RETURN_WINDOW: Duration = Duration::from_millis(200);
+
+/// The default maximum size of the messages we accept to receive: 128MiB.
+pub(crate) const DEFAULT_MAX_MESSAGE_SIZE: u64 = 128 * 1024 * 1024;
+
+/// What to do when receiving a message larger than the maximum message size.
+///
+/// This is configured using [`ConnectionProperties::with_oversized_message_policy`].
+///
+/// [`ConnectionProperties::with_oversized_message_policy`]: ./struct.ConnectionProperties.html#method.with_oversized_message_policy
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OversizedMessagePolicy {
+    /// Discard the content of the message without buffering it, error the consumer and close
+    /// the channel. The connection stays usable.
+    Discard,
+    /// Close the connection.
+    CloseConnection,
+}
+
+impl Default for OversizedMessagePolicy {
+    fn default() -> Self {
+        OversizedMessagePolicy::Discard
+    }
+}
+
+/// What to do when publishing a message through the default exchange with a priority higher
+/// than the `x-max-priority` of its queue, as declared on the same channel. The server handles
+/// such messages as if they had the maximum priority.
+///
+/// This is configured using [`ConnectionProperties::with_priority_validation`].
+///
+/// [`ConnectionProperties::with_priority_validation`]: ./struct.ConnectionProperties.html#method.with_priority_validation
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PriorityValidation {
+    /// Don't check the priorities.
+    Off,
+    /// Log a warning and publish the message anyway.
+    Warn,
+    /// Fail the publish with [`Error::InvalidArgument`].
+    ///
+    /// [`Error::InvalidArgument`]: ./enum.Error.html#variant.InvalidArgument
+    Error,
+}
+
+impl Default for PriorityValidation {
+    fn default() -> Self {
+        PriorityValidation::Off
+    }
 }
 
-#[derive(Default)]
 struct Inner {
     channel_max: u16,
     frame_max: u32,
     heartbeat: u16,
+    unknown_consumer_policy: UnknownConsumerPolicy,
+    max_message_size: u64,
+    oversized_message_policy: OversizedMessagePolicy,
+    immediate_flag: bool,
+    strict_frame_ordering: bool,
+    instrumentation_level: Level,
+    channel_close_timeout: Duration,
+    mandatory_return_window: Duration,
+    priority_validation: PriorityValidation,
+    strict_field_validation: bool,
+    #[cfg(feature = "serde")]
+    codecs: Arc<CodecRegistry>,
+    channel_defaults: ChannelDefaults,
+}
+
+impl Default for Inner {
+    fn default() -> Self {
+        Self {
+            channel_max: 0,
+            frame_max: 0,
+            heartbeat: 0,
+            unknown_consumer_policy: UnknownConsumerPolicy::default(),
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            oversized_message_policy: OversizedMessagePolicy::default(),
+            immediate_flag: false,
+            strict_frame_ordering: false,
+            instrumentation_level: Level::DEBUG,
+            channel_close_timeout: DEFAULT_CHANNEL_CLOSE_TIMEOUT,
+            mandatory_return_window: DEFAULT_MANDATORY_RETURN_WINDOW,
+            priority_validation: PriorityValidation::default(),
+            strict_field_validation: cfg!(debug_assertions),
+            #[cfg(feature = "serde")]
+            codecs: Arc::new(CodecRegistry::default()),
+            channel_defaults: ChannelDefaults::default(),
+        }
+    }
 }
 
 impl fmt::Debug for Configuration {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let inner = self.inner.read();
-        f.debug_struct("Configuration")
+        let mut debug = f.debug_struct("Configuration");
+        debug
             .field("channel_max", &inner.channel_max)
             .field("frame_max", &inner.frame_max)
             .field("heartbeat", &inner.heartbeat)
+            .field("unknown_consumer_policy", &inner.unknown_consumer_policy)
+            .field("max_message_size", &inner.max_message_size)
+            .field("oversized_message_policy", &inner.oversized_message_policy)
+            .field("immediate_flag", &inner.immediate_flag)
+            .field("strict_frame_ordering", &inner.strict_frame_ordering)
+            .field("instrumentation_level", &inner.instrumentation_level)
+            .field("channel_close_timeout", &inner.channel_close_timeout)
+            .field("mandatory_return_window", &inner.mandatory_return_window)
+            .field("priority_validation", &inner.priority_validation)
+            .field("strict_field_validation", &inner.strict_field_validation);
+        #[cfg(feature = "serde")]
+        debug.field("codecs", &inner.codecs);
+        debug
+            .field("channel_defaults", &inner.channel_defaults)
             .finish()
     }
 }
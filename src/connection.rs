@@ -1,26 +1,46 @@
 use crate::{
     channel::Channel,
+    channel_lifecycle::ChannelLifecycleObserver,
     channels::Channels,
     configuration::Configuration,
     connection_closer::ConnectionCloser,
     connection_properties::ConnectionProperties,
+    connection_stats::ConnectionStats,
     connection_status::{ConnectionState, ConnectionStatus, ConnectionStep},
+    connector::{Connector, SocketAddrConnector},
     executor::{DefaultExecutor, Executor},
     frames::Frames,
+    health::HealthStatus,
     internal_rpc::{InternalRPC, InternalRPCHandle},
     io_loop::IoLoop,
+    protocol,
     reactor::DefaultReactorBuilder,
     socket_state::{SocketState, SocketStateHandle},
     tcp::{AMQPUriTcpExt, HandshakeResult, OwnedTLSConfig},
     thread::ThreadHandle,
-    types::ShortUInt,
+    topology::TopologyChannel,
+    tracing_integration::targets,
+    types::{FieldTable, ShortUInt},
     uri::AMQPUri,
     Error, Promise, Result,
 };
 use amq_protocol::frame::{AMQPFrame, ProtocolVersion};
+use async_io::Timer;
 use async_trait::async_trait;
-use std::{fmt, io, sync::Arc};
-use tracing::{level_enabled, Level};
+use futures_lite::future;
+use parking_lot::Mutex;
+use std::{
+    fmt,
+    future::Future,
+    io,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use tracing::{debug, level_enabled, warn, Level};
 
 /// A TCP connection to the AMQP server.
 ///
@@ -34,14 +54,32 @@ use tracing::{level_enabled, Level};
 /// [`Channel`]: ./struct.Channel.html
 /// [`create_channel`]: ./struct.Connection.html#method.create_channel
 pub struct Connection {
+    id: u64,
     configuration: Configuration,
     status: ConnectionStatus,
     channels: Channels,
     io_loop: ThreadHandle,
     closer: Arc<ConnectionCloser>,
+    topology_channels: Arc<Mutex<Vec<TopologyChannel>>>,
 }
 
 impl Connection {
+    /// The reply code and text of a normal shutdown
+    pub const NORMAL_CLOSE: (ShortUInt, &'static str) = (
+        protocol::constants::REPLY_SUCCESS as ShortUInt,
+        "Normal shutdown",
+    );
+    /// The reply code and text of a shutdown caused by an error on a channel
+    pub const CHANNEL_ERROR: (ShortUInt, &'static str) = (
+        protocol::constants::CHANNEL_ERROR as ShortUInt,
+        "Channel error",
+    );
+    /// The reply code and text of a shutdown caused by an internal error
+    pub const INTERNAL_ERROR: (ShortUInt, &'static str) = (
+        protocol::constants::INTERNAL_ERROR as ShortUInt,
+        "Internal error",
+    );
+
     fn new(
         waker: SocketStateHandle,
         internal_rpc: InternalRPCHandle,
@@ -60,11 +98,13 @@ impl Connection {
         );
         let closer = Arc::new(ConnectionCloser::new(status.clone(), internal_rpc));
         let connection = Self {
+            id: CONNECTION_ID.fetch_add(1, Ordering::Relaxed),
             configuration,
             status,
             channels,
             io_loop: ThreadHandle::default(),
             closer,
+            topology_channels: Arc::default(),
         };
 
         connection.channels.create_zero();
@@ -108,19 +148,92 @@ impl Connection {
         Connect::connect(uri, options, config).await
     }
 
+    /// Connect to an AMQP Server by dialing the given address.
+    ///
+    /// The URI is still used for everything else, such as the TLS handshake and the virtual host.
+    pub async fn connect_to_addr(
+        addr: SocketAddr,
+        uri: &str,
+        options: ConnectionProperties,
+    ) -> Result<Connection> {
+        Connect::connect(
+            uri,
+            options.with_connector(Arc::new(SocketAddrConnector::new(addr))),
+            OwnedTLSConfig::default(),
+        )
+        .await
+    }
+
     /// Creates a new [`Channel`] on this connection.
     ///
     /// This method is only successful if the client is connected.
     /// Otherwise, [`InvalidConnectionState`] error is returned.
     ///
+    /// The [`ChannelDefaults`] of the connection are applied before the channel is returned.
+    ///
     /// [`Channel`]: ./struct.Channel.html
     /// [`InvalidConnectionState`]: ./enum.Error.html#variant.InvalidConnectionState
+    /// [`ChannelDefaults`]: ./struct.ChannelDefaults.html
     pub async fn create_channel(&self) -> Result<Channel> {
         if !self.status.connected() {
             return Err(Error::InvalidConnectionState(self.status.state()));
         }
         let channel = self.channels.create(self.closer.clone())?;
-        channel.clone().channel_open(channel).await
+        let channel = channel.clone().channel_open(channel).await?;
+        let defaults = self.configuration.channel_defaults();
+        if !defaults.is_empty() {
+            channel.apply_defaults(&defaults).await?;
+        }
+        Ok(channel)
+    }
+
+    /// The channel with the given id, if it was created on this connection and wasn't closed
+    /// since.
+    ///
+    /// Channel 0, used by the connection itself, is never returned.
+    pub fn channel_by_id(&self, id: u16) -> Option<Channel> {
+        if id == 0 {
+            return None;
+        }
+        self.channels.get(id)
+    }
+
+    /// Creates a new [`TopologyChannel`] on this connection, to declare exchanges, queues and
+    /// bindings and be able to declare them again later on.
+    ///
+    /// [`TopologyChannel`]: ./topology/struct.TopologyChannel.html
+    pub async fn create_topology_channel(&self) -> Result<TopologyChannel> {
+        let channel = TopologyChannel::new(self.create_channel().await?);
+        self.topology_channels.lock().push(channel.clone());
+        Ok(channel)
+    }
+
+    /// Declare again the topologies of all the [`TopologyChannel`]s of this connection, for
+    /// example after the server lost the non-durable resources.
+    ///
+    /// The topology channels whose channel failed, or got closed by the server because of an
+    /// error, get a new channel to declare everything again. The ones closed on purpose are
+    /// forgotten. Stops at the first failure.
+    ///
+    /// The topology channels belong to this connection: after it failed, declare their
+    /// [`topology_snapshot`] again on the new connection.
+    ///
+    /// [`TopologyChannel`]: ./topology/struct.TopologyChannel.html
+    /// [`topology_snapshot`]: ./topology/struct.TopologyChannel.html#method.topology_snapshot
+    pub async fn reapply_all_topologies(&self) -> Result<()> {
+        let channels = {
+            let mut channels = self.topology_channels.lock();
+            channels.retain(|channel| !channel.is_closed_by_client());
+            channels.clone()
+        };
+        for channel in channels {
+            if channel.is_open() {
+                channel.reapply_topology().await?;
+            } else {
+                channel.recover(self.create_channel().await?).await?;
+            }
+        }
+        Ok(())
     }
 
     /// Block current thread while the connection is still active.
@@ -136,6 +249,13 @@ impl Connection {
         self.channels.set_error_handler(handler);
     }
 
+    /// Notify `observer` whenever a channel of this connection gets opened, closed or fails.
+    ///
+    /// Several observers can be registered, they are called in the order of registration.
+    pub fn register_channel_lifecycle_observer(&self, observer: Arc<dyn ChannelLifecycleObserver>) {
+        self.channels.register_lifecycle_observer(observer);
+    }
+
     pub fn configuration(&self) -> &Configuration {
         &self.configuration
     }
@@ -144,6 +264,34 @@ impl Connection {
         &self.status
     }
 
+    /// The client properties sent to the server when the connection was established, including
+    /// the ones lapin sends by default, see [`ConnectionProperties::client_properties`].
+    ///
+    /// This is empty until the server started the handshake.
+    ///
+    /// [`ConnectionProperties::client_properties`]: ./struct.ConnectionProperties.html#structfield.client_properties
+    pub fn client_properties(&self) -> FieldTable {
+        self.status.client_properties()
+    }
+
+    /// Whether the connection is currently connected.
+    ///
+    /// This is only a snapshot, the connection may get closed right after this returned `true`.
+    pub fn is_open(&self) -> bool {
+        self.status.connected()
+    }
+
+    /// Close the connection with the given reply code and text, which show up in the server
+    /// logs.
+    ///
+    /// The close isn't caused by a failing method, so the class and method ids sent to the
+    /// server are 0. See [`NORMAL_CLOSE`], [`CHANNEL_ERROR`] and [`INTERNAL_ERROR`] for the
+    /// usual reasons and [`close_normally`] for the usual shutdown.
+    ///
+    /// [`NORMAL_CLOSE`]: #associatedconstant.NORMAL_CLOSE
+    /// [`CHANNEL_ERROR`]: #associatedconstant.CHANNEL_ERROR
+    /// [`INTERNAL_ERROR`]: #associatedconstant.INTERNAL_ERROR
+    /// [`close_normally`]: #method.close_normally
     pub async fn close(&self, reply_code: ShortUInt, reply_text: &str) -> Result<()> {
         if let Some(channel0) = self.channels.get(0) {
             channel0
@@ -154,6 +302,165 @@ impl Connection {
         }
     }
 
+    /// Close the connection with the [`NORMAL_CLOSE`] reply code and text.
+    ///
+    /// [`NORMAL_CLOSE`]: #associatedconstant.NORMAL_CLOSE
+    pub async fn close_normally(&self) -> Result<()> {
+        let (reply_code, reply_text) = Self::NORMAL_CLOSE;
+        self.close(reply_code, reply_text).await
+    }
+
+    /// Close all the open channels, then the connection.
+    ///
+    /// A `channel.close` is sent on each connected channel at once and the connection gets closed
+    /// once they're all acknowledged. Channels which are already closed, closing or in error are
+    /// skipped. The timeout set with [`ConnectionProperties::with_channel_close_timeout`] is a
+    /// single deadline shared by all the channels, starting when this gets called: the channels
+    /// whose `channel.close-ok` didn't arrive by then are considered closed anyway.
+    ///
+    /// [`ConnectionProperties::with_channel_close_timeout`]: ./struct.ConnectionProperties.html#method.with_channel_close_timeout
+    pub async fn close_all(&self, reply_code: ShortUInt, reply_text: &str) -> Result<()> {
+        let timeout = self.configuration.channel_close_timeout();
+        let deadline = Instant::now() + timeout;
+        let closing = self
+            .channels
+            .connected()
+            .into_iter()
+            .map(|channel| {
+                let promise = channel.start_closing(reply_code, reply_text);
+                (channel, promise)
+            })
+            .collect::<Vec<_>>();
+        for (channel, promise) in closing {
+            let closed = future::or(async { Some(promise.await) }, async {
+                Timer::at(deadline).await;
+                None
+            })
+            .await;
+            match closed {
+                Some(Ok(())) => {}
+                Some(Err(err)) => {
+                    debug!(
+                        target: targets::CONNECTION,
+                        "channel {} failed to close: {}",
+                        channel.id(),
+                        err
+                    )
+                }
+                None => {
+                    warn!(
+                        target: targets::CONNECTION,
+                        "channel {} didn't acknowledge its closing within {:?}, considering it closed",
+                        channel.id(),
+                        timeout
+                    );
+                    channel.force_closed();
+                }
+            }
+        }
+        self.close(reply_code, reply_text).await
+    }
+
+    /// Shut the connection down once the consumers are done with their deliveries.
+    ///
+    /// The consumers of all the channels get canceled first, then their delegates get to finish
+    /// handling the deliveries they already got, and the frames they queued, such as their
+    /// acknowledgements, get written. The channels and the connection then get closed with
+    /// [`NORMAL_CLOSE`] like [`close_all`] does. Waiting for the cancellations, for the delegates
+    /// and for the frames stops after `timeout`, the closing goes on anyway.
+    ///
+    /// [`NORMAL_CLOSE`]: #associatedconstant.NORMAL_CLOSE
+    /// [`close_all`]: #method.close_all
+    pub async fn close_gracefully(&self, timeout: Duration) -> Result<()> {
+        let deadline = Instant::now() + timeout;
+        let channels = self.channels.connected();
+        let mut consumers = Vec::new();
+        let mut canceled = true;
+        for channel in &channels {
+            let (channel_consumers, channel_canceled) =
+                channel.cancel_consumers_before(deadline).await;
+            consumers.extend(channel_consumers);
+            canceled &= channel_canceled;
+        }
+        if !canceled {
+            warn!(
+                target: targets::CONNECTION,
+                "consumers didn't get canceled within {:?}, closing anyway",
+                timeout
+            );
+        }
+        let joined = future::or(
+            async {
+                for consumer in &consumers {
+                    consumer.join().await;
+                }
+                true
+            },
+            async {
+                Timer::at(deadline).await;
+                false
+            },
+        )
+        .await;
+        if !joined {
+            warn!(
+                target: targets::CONNECTION,
+                "consumers didn't finish handling their deliveries within {:?}, closing anyway",
+                timeout
+            );
+        }
+        for channel in &channels {
+            channel.sync_before(deadline).await;
+        }
+        let (reply_code, reply_text) = Self::NORMAL_CLOSE;
+        self.close_all(reply_code, reply_text).await
+    }
+
+    /// The activity of this connection so far, including the message operations of all its
+    /// channels.
+    pub fn stats(&self) -> ConnectionStats {
+        self.channels.stats()
+    }
+
+    /// A snapshot of the health of this connection.
+    ///
+    /// This never waits for the server nor for the frames and channels to be available: the
+    /// pending frames count and the channels counts are `None` if they were being modified by
+    /// another thread. See [`ping`] to check that the server actually answers.
+    ///
+    /// [`ping`]: #method.ping
+    pub fn health_check(&self) -> HealthStatus {
+        let stats = self.channels.stats_counters();
+        let channel_states = self.channels.try_channel_states();
+        HealthStatus {
+            state: self.status.state(),
+            last_heartbeat_sent: stats.last_heartbeat_sent(),
+            last_frame_received: stats.last_frame_received(),
+            pending_frames: self.channels.try_pending_frames(),
+            channels_open: channel_states.map(|(open, _)| open),
+            channels_errored: channel_states.map(|(_, errored)| errored),
+        }
+    }
+
+    /// Check that the server answers, returning the round trip duration.
+    ///
+    /// This opens a dedicated channel and passively declares the `amq.direct` exchange on it.
+    /// If the server doesn't answer within `timeout`, `Error::Timeout` is returned, the channel
+    /// still gets closed if the server answers later on.
+    pub async fn ping(&self, timeout: Duration) -> Result<Duration> {
+        if !self.status.connected() {
+            return Err(Error::InvalidConnectionState(self.status.state()));
+        }
+        // The round trip runs in the background so that the channel still gets closed when the
+        // server answers after the timeout
+        let round_trip = self.channels.create(self.closer.clone())?.start_ping();
+        future::or(round_trip, async {
+            Timer::after(timeout).await;
+            Err(Error::Timeout)
+        })
+        .await
+    }
+
     /// Block all consumers and publishers on this connection
     pub async fn block(&self, reason: &str) -> Result<()> {
         if let Some(channel0) = self.channels.get(0) {
@@ -173,7 +480,17 @@ impl Connection {
     }
 
     /// Update the secret used by some authentication module such as OAuth2
+    ///
+    /// This rotates the credentials of the connection without reconnecting. It fails with
+    /// [`Error::UnsupportedByBroker`] if the server doesn't support it, see
+    /// [`ConnectionStatus::supports_update_secret`].
+    ///
+    /// [`Error::UnsupportedByBroker`]: ./enum.Error.html#variant.UnsupportedByBroker
+    /// [`ConnectionStatus::supports_update_secret`]: ./struct.ConnectionStatus.html#method.supports_update_secret
     pub async fn update_secret(&self, new_secret: &str, reason: &str) -> Result<()> {
+        if !self.status.supports_update_secret() {
+            return Err(Error::UnsupportedByBroker("connection.update_secret"));
+        }
         if let Some(channel0) = self.channels.get(0) {
             channel0.connection_update_secret(new_secret, reason).await
         } else {
@@ -198,6 +515,32 @@ impl Connection {
             resolver.swear(connect(&connect_uri));
         }));
 
+        Self::handshake(uri, connect_promise, executor, options).await
+    }
+
+    async fn connect_with_connector(
+        uri: AMQPUri,
+        connector: Arc<dyn Connector>,
+        config: OwnedTLSConfig,
+        mut options: ConnectionProperties,
+    ) -> Result<Connection> {
+        let executor = options
+            .executor
+            .take()
+            .map(Ok)
+            .unwrap_or_else(DefaultExecutor::default)?;
+        let connect_uri = uri.clone();
+        let transport = async move { connector.connect(connect_uri, config).await };
+
+        Self::handshake(uri, transport, executor, options).await
+    }
+
+    async fn handshake(
+        uri: AMQPUri,
+        transport: impl Future<Output = HandshakeResult>,
+        executor: Arc<dyn Executor>,
+        mut options: ConnectionProperties,
+    ) -> Result<Connection> {
         let reactor_builder = options
             .reactor_builder
             .take()
@@ -205,6 +548,11 @@ impl Connection {
         let socket_state = SocketState::default();
         let waker = socket_state.handle();
         let internal_rpc = InternalRPC::new(executor.clone(), waker.clone());
+        let cancellation_token = options.cancellation_token.clone();
+        if let Some(cancellation_token) = cancellation_token.as_ref() {
+            let internal_rpc = internal_rpc.handle();
+            cancellation_token.on_cancel(move || internal_rpc.cancel());
+        }
         let frames = Frames::default();
         let conn = Connection::new(
             waker,
@@ -212,8 +560,24 @@ impl Connection {
             frames.clone(),
             executor.clone(),
         );
+        if options.declaration_cache {
+            conn.channels.declaration_cache().enable();
+        }
         let status = conn.status.clone();
         let configuration = conn.configuration.clone();
+        configuration.set_unknown_consumer_policy(options.unknown_consumer_policy);
+        configuration.set_max_message_size(options.max_message_size);
+        configuration.set_oversized_message_policy(options.oversized_message_policy);
+        configuration.set_immediate_flag(options.immediate_flag);
+        configuration.set_strict_frame_ordering(options.strict_frame_ordering);
+        configuration.set_instrumentation_level(options.instrumentation_level);
+        configuration.set_channel_close_timeout(options.channel_close_timeout);
+        configuration.set_mandatory_return_window(options.mandatory_return_window);
+        configuration.set_priority_validation(options.priority_validation);
+        configuration.set_strict_field_validation(options.strict_field_validation);
+        #[cfg(feature = "serde")]
+        configuration.set_codecs(options.codecs.clone());
+        configuration.set_channel_defaults(options.channel_defaults.clone());
         status.set_vhost(&uri.vhost);
         status.set_username(&uri.authority.userinfo.username);
         if let Some(frame_max) = uri.query.frame_max {
@@ -250,35 +614,63 @@ impl Connection {
             uri.query.auth_mechanism.unwrap_or_default(),
             options,
         ));
-        let handshake_result = connect_promise.await;
-        IoLoop::new(
-            status,
-            configuration,
-            channels,
-            internal_rpc,
-            frames,
-            socket_state,
-            io_loop_handle,
-            handshake_result,
-            &*reactor_builder,
-            executor,
-        )
-        .and_then(IoLoop::start)?;
-        promise_out.await?;
-        promise_in.await
+        let handshake = async move {
+            let handshake_result = transport.await;
+            IoLoop::new(
+                status,
+                configuration,
+                channels,
+                internal_rpc,
+                frames,
+                socket_state,
+                io_loop_handle,
+                handshake_result,
+                &*reactor_builder,
+                executor,
+            )
+            .and_then(IoLoop::start)?;
+            promise_out.await?;
+            promise_in.await
+        };
+        match cancellation_token {
+            Some(cancellation_token) => {
+                futures_lite::future::or(handshake, async move {
+                    cancellation_token.cancelled().await;
+                    Err(Error::Cancelled)
+                })
+                .await
+            }
+            None => handshake.await,
+        }
     }
 }
 
 impl fmt::Debug for Connection {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_struct("Connection")
-            .field("configuration", &self.configuration)
-            .field("status", &self.status)
-            .field("channels", &self.channels)
-            .finish()
+        // Only use try_lock here, we may be formatting while holding one of those locks
+        let mut debug = f.debug_struct("Connection");
+        let summary = self.status.try_summary();
+        debug.field("id", &self.id);
+        if let Some((state, ..)) = summary.as_ref() {
+            debug.field("status", state);
+        }
+        if let Some(channel_count) = self.channels.try_count() {
+            debug.field("channel_count", &channel_count);
+        }
+        if let Some(pending_frames) = self.channels.try_pending_frames() {
+            debug.field("pending_frames", &pending_frames);
+        }
+        if let Some((_, vhost, server_version)) = summary.as_ref() {
+            debug
+                .field("server_version", server_version)
+                .field("vhost", vhost);
+        }
+        debug.finish()
     }
 }
 
+static CONNECTION_ID: AtomicU64 = AtomicU64::new(0);
+
 /// Trait providing a method to connect to an AMQP server
 #[async_trait]
 pub trait Connect {
@@ -294,9 +686,12 @@ pub trait Connect {
 impl Connect for AMQPUri {
     async fn connect(
         self,
-        options: ConnectionProperties,
+        mut options: ConnectionProperties,
         config: OwnedTLSConfig,
     ) -> Result<Connection> {
+        if let Some(connector) = options.connector.take() {
+            return Connection::connect_with_connector(self, connector, config, options).await;
+        }
         Connection::connector(
             self,
             Box::new(move |uri| AMQPUriTcpExt::connect_with_config(uri, config.as_ref())),
@@ -320,21 +715,38 @@ impl Connect for &str {
     }
 }
 
+#[cfg(test)]
+impl Connection {
+    /// A connected connection without socket nor io loop, whose frames are left in `frames`.
+    pub(crate) fn connected_for_tests(frames: Frames) -> Self {
+        let socket_state = SocketState::default();
+        let waker = socket_state.handle();
+        let executor = DefaultExecutor::default().unwrap();
+        let internal_rpc = InternalRPC::new(executor.clone(), waker.clone());
+        let conn = Connection::new(waker, internal_rpc.handle(), frames, executor);
+        conn.status.set_state(ConnectionState::Connected);
+        conn.configuration.set_channel_max(2047);
+        conn
+    }
+
+    pub(crate) fn channels(&self) -> &Channels {
+        &self.channels
+    }
+
+    pub(crate) fn closer(&self) -> Arc<ConnectionCloser> {
+        self.closer.clone()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::channel_receiver_state::ChannelReceiverState;
-    use crate::channel_status::ChannelState;
-    use crate::types::ShortString;
-    use crate::BasicProperties;
-    use amq_protocol::frame::AMQPContentHeader;
-    use amq_protocol::protocol::{basic, AMQPClass};
+    use crate::test_helpers::*;
 
     #[test]
     fn basic_consume_small_payload() {
         let _ = tracing_subscriber::fmt::try_init();
 
-        use crate::consumer::Consumer;
         use crate::queue::{Queue, QueueState};
 
         // Bootstrap connection state to a consuming state
@@ -375,7 +787,7 @@ mod tests {
             let channel_state = channel.status().receiver_state();
             let expected_state = ChannelReceiverState::WillReceiveContent(
                 class_id,
-                Some(queue_name.clone()),
+                queue_name.clone(),
                 Some(consumer_tag.clone()),
             );
             assert_eq!(channel_state, expected_state);
@@ -394,7 +806,7 @@ mod tests {
             conn.channels.handle_frame(header_frame).unwrap();
             let channel_state = channel.status().receiver_state();
             let expected_state = ChannelReceiverState::ReceivingContent(
-                Some(queue_name.clone()),
+                queue_name.clone(),
                 Some(consumer_tag.clone()),
                 2,
             );
@@ -413,7 +825,6 @@ mod tests {
     fn basic_consume_empty_payload() {
         let _ = tracing_subscriber::fmt::try_init();
 
-        use crate::consumer::Consumer;
         use crate::queue::{Queue, QueueState};
 
         // Bootstrap connection state to a consuming state
@@ -454,7 +865,7 @@ mod tests {
             let channel_state = channel.status().receiver_state();
             let expected_state = ChannelReceiverState::WillReceiveContent(
                 class_id,
-                Some(queue_name.clone()),
+                queue_name.clone(),
                 Some(consumer_tag.clone()),
             );
             assert_eq!(channel_state, expected_state);
@@ -476,4 +887,595 @@ mod tests {
             assert_eq!(channel_state, expected_state);
         }
     }
+
+    #[test]
+    fn reapply_all_topologies_after_channel_failure() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        use crate::options::QueueDeclareOptions;
+        use amq_protocol::protocol::{channel, queue};
+
+        let (conn, _, frames) = connected_channel();
+        let conn = Arc::new(conn);
+        // Answer the next frame on the channel it was sent on
+        let answer = |reply: &dyn Fn(u16) -> AMQPClass| loop {
+            if let Some((frame, resolver)) = frames.pop(true) {
+                if let Some(resolver) = resolver {
+                    resolver.swear(Ok(()));
+                }
+                let id = match frame {
+                    AMQPFrame::Method(id, _) => id,
+                    ref frame => panic!("unexpected frame: {:?}", frame),
+                };
+                conn.channels
+                    .handle_frame(AMQPFrame::Method(id, reply(id)))
+                    .unwrap();
+                return frame;
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        };
+        let open_ok = |_: u16| AMQPClass::Channel(channel::AMQPMethod::OpenOk(channel::OpenOk {}));
+        let declare_ok = |_: u16| {
+            AMQPClass::Queue(queue::AMQPMethod::DeclareOk(queue::DeclareOk {
+                queue: "jobs".into(),
+                message_count: 0,
+                consumer_count: 0,
+            }))
+        };
+
+        let task = {
+            let conn = conn.clone();
+            async_global_executor::spawn(async move { conn.create_topology_channel().await })
+        };
+        answer(&open_ok);
+        let topology = async_global_executor::block_on(task).unwrap();
+        let task = {
+            let topology = topology.clone();
+            async_global_executor::spawn(async move {
+                topology
+                    .queue_declare(
+                        "jobs",
+                        QueueDeclareOptions::default(),
+                        FieldTable::default(),
+                    )
+                    .await
+            })
+        };
+        answer(&declare_ok);
+        async_global_executor::block_on(task).unwrap();
+        let failed = topology.channel();
+
+        // The server closes the channel because of an error
+        conn.channels
+            .handle_frame(AMQPFrame::Method(
+                failed.id(),
+                AMQPClass::Channel(channel::AMQPMethod::Close(channel::Close {
+                    reply_code: 404,
+                    reply_text: "NOT_FOUND - no exchange 'missing'".into(),
+                    class_id: 50,
+                    method_id: 20,
+                })),
+            ))
+            .unwrap();
+        write_frames(&frames, 1);
+        while failed.status().state() != ChannelState::Closed {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert!(!topology.is_open());
+
+        // A new channel gets opened to declare everything again
+        let task = {
+            let conn = conn.clone();
+            async_global_executor::spawn(async move { conn.reapply_all_topologies().await })
+        };
+        match answer(&open_ok) {
+            AMQPFrame::Method(_, AMQPClass::Channel(channel::AMQPMethod::Open(_))) => {}
+            frame => panic!("unexpected frame: {:?}", frame),
+        }
+        match answer(&declare_ok) {
+            AMQPFrame::Method(_, AMQPClass::Queue(queue::AMQPMethod::Declare(declare))) => {
+                assert_eq!(declare.queue.as_str(), "jobs")
+            }
+            frame => panic!("unexpected frame: {:?}", frame),
+        }
+        async_global_executor::block_on(task).unwrap();
+        assert!(topology.is_open());
+        assert_ne!(topology.channel().id(), failed.id());
+    }
+
+    #[test]
+    fn channel_lifecycle_observer() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        use crate::{
+            protocol::{AMQPError, AMQPHardError},
+            ChannelLifecycleObserver, CloseReason,
+        };
+        use amq_protocol::protocol::channel;
+
+        #[derive(Debug, PartialEq)]
+        enum Event {
+            Opened(u16),
+            Closed(u16, Option<CloseReason>),
+            Error(u16, Error),
+        }
+
+        #[derive(Default)]
+        struct Recorder(Mutex<Vec<Event>>);
+
+        impl ChannelLifecycleObserver for Recorder {
+            fn on_channel_opened(&self, id: u16) {
+                self.0.lock().push(Event::Opened(id));
+            }
+
+            fn on_channel_closed(&self, id: u16, reason: Option<CloseReason>) {
+                self.0.lock().push(Event::Closed(id, reason));
+            }
+
+            fn on_channel_error(&self, id: u16, error: &Error) {
+                self.0.lock().push(Event::Error(id, error.clone()));
+            }
+        }
+
+        let (conn, first, frames) = connected_channel();
+        let recorders = [Arc::new(Recorder::default()), Arc::new(Recorder::default())];
+        for recorder in recorders.iter() {
+            conn.register_channel_lifecycle_observer(recorder.clone());
+        }
+
+        let second = conn.channels.create(conn.closer.clone()).unwrap();
+        let task = async_global_executor::spawn(second.clone().channel_open(second.clone()));
+        answer_next_frame(
+            &conn,
+            &frames,
+            AMQPFrame::Method(
+                second.id(),
+                AMQPClass::Channel(channel::AMQPMethod::OpenOk(channel::OpenOk {})),
+            ),
+        );
+        async_global_executor::block_on(task).unwrap();
+
+        // The server closes the second channel, which gets removed once we answered
+        conn.channels
+            .handle_frame(AMQPFrame::Method(
+                second.id(),
+                AMQPClass::Channel(channel::AMQPMethod::Close(channel::Close {
+                    reply_code: 404,
+                    reply_text: "NOT_FOUND - no queue 'missing'".into(),
+                    class_id: 50,
+                    method_id: 10,
+                })),
+            ))
+            .unwrap();
+        write_frames(&frames, 1);
+        while second.status().state() != ChannelState::Closed {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        conn.channels
+            .remove(
+                second.id(),
+                Error::InvalidChannelState(ChannelState::Closed),
+            )
+            .unwrap();
+
+        let error = Error::ProtocolError(AMQPError::new(
+            AMQPHardError::CONNECTIONFORCED.into(),
+            "CONNECTION_FORCED - broker forced connection closure".into(),
+        ));
+        conn.channels.set_connection_error(error.clone());
+
+        for recorder in recorders.iter() {
+            assert_eq!(
+                *recorder.0.lock(),
+                vec![
+                    Event::Opened(second.id()),
+                    Event::Closed(
+                        second.id(),
+                        Some(CloseReason {
+                            reply_code: 404,
+                            reply_text: "NOT_FOUND - no queue 'missing'".into(),
+                        })
+                    ),
+                    Event::Error(first.id(), error.clone()),
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn channel_by_id() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        let (conn, channel, _) = connected_channel();
+        let found = conn.channel_by_id(channel.id()).expect("channel not found");
+        assert_eq!(found.id(), channel.id());
+        assert!(found.status().connected());
+        assert!(conn.channel_by_id(0).is_none());
+        assert!(conn.channel_by_id(channel.id() + 1).is_none());
+    }
+
+    #[test]
+    fn connection_close_normally() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        use amq_protocol::protocol::connection;
+
+        let (conn, _, frames) = connected_channel();
+        let conn = Arc::new(conn);
+        let task = {
+            let conn = conn.clone();
+            async_global_executor::spawn(async move { conn.close_normally().await })
+        };
+        let close_ok = AMQPFrame::Method(
+            0,
+            AMQPClass::Connection(connection::AMQPMethod::CloseOk(connection::CloseOk {})),
+        );
+        assert_eq!(
+            answer_next_frame(&conn, &frames, close_ok),
+            AMQPFrame::Method(
+                0,
+                AMQPClass::Connection(connection::AMQPMethod::Close(connection::Close {
+                    reply_code: 200,
+                    reply_text: "Normal shutdown".into(),
+                    class_id: 0,
+                    method_id: 0,
+                })),
+            )
+        );
+        assert!(async_global_executor::block_on(task).is_ok());
+        assert_eq!(Connection::CHANNEL_ERROR.0, 504);
+        assert_eq!(Connection::INTERNAL_ERROR.0, 541);
+    }
+
+    #[test]
+    fn connection_close_gracefully_unanswered_cancel() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        use crate::options::BasicConsumeOptions;
+        use amq_protocol::protocol::{basic, connection};
+
+        let (conn, channel, frames) = connected_channel();
+        conn.configuration
+            .set_channel_close_timeout(Duration::from_millis(100));
+        let conn = Arc::new(conn);
+        let task = {
+            let channel = channel.clone();
+            async_global_executor::spawn(async move {
+                channel
+                    .basic_consume(
+                        "queue",
+                        "consumer",
+                        BasicConsumeOptions::default(),
+                        FieldTable::default(),
+                    )
+                    .await
+            })
+        };
+        answer_next_frame(
+            &conn,
+            &frames,
+            AMQPFrame::Method(
+                channel.id(),
+                AMQPClass::Basic(basic::AMQPMethod::ConsumeOk(basic::ConsumeOk {
+                    consumer_tag: "consumer".into(),
+                })),
+            ),
+        );
+        let _consumer = async_global_executor::block_on(task).unwrap();
+
+        let started = std::time::Instant::now();
+        let task = {
+            let conn = conn.clone();
+            async_global_executor::spawn(async move {
+                conn.close_gracefully(Duration::from_millis(100)).await
+            })
+        };
+        // The server never answers the basic.cancel nor the channel.close, the timeouts have to
+        // end the waits anyway
+        write_frames(&frames, 2);
+        answer_next_frame(
+            &conn,
+            &frames,
+            AMQPFrame::Method(
+                0,
+                AMQPClass::Connection(connection::AMQPMethod::CloseOk(connection::CloseOk {})),
+            ),
+        );
+        assert!(async_global_executor::block_on(task).is_ok());
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn update_secret() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        use amq_protocol::protocol::connection;
+
+        let (conn, _, frames) = connected_channel();
+        assert_eq!(
+            async_global_executor::block_on(conn.update_secret("new-token", "expiring")),
+            Err(Error::UnsupportedByBroker("connection.update_secret"))
+        );
+        assert!(frames.pop(true).is_none());
+
+        // RabbitMQ supports it from 3.8 on, without advertising it
+        conn.status.set_server_product("RabbitMQ".into());
+        conn.status.set_server_version("3.7.28".into());
+        assert!(!conn.status.supports_update_secret());
+        conn.status.set_server_version("3.8.9".into());
+        assert!(conn.status.supports_update_secret());
+
+        let conn = Arc::new(conn);
+        let task = {
+            let conn = conn.clone();
+            async_global_executor::spawn(async move {
+                conn.update_secret("new-token", "expiring").await
+            })
+        };
+        let update_secret_ok = AMQPFrame::Method(
+            0,
+            AMQPClass::Connection(connection::AMQPMethod::UpdateSecretOk(
+                connection::UpdateSecretOk {},
+            )),
+        );
+        assert_eq!(
+            answer_next_frame(&conn, &frames, update_secret_ok),
+            AMQPFrame::Method(
+                0,
+                AMQPClass::Connection(connection::AMQPMethod::UpdateSecret(
+                    connection::UpdateSecret {
+                        new_secret: "new-token".into(),
+                        reason: "expiring".into(),
+                    }
+                )),
+            )
+        );
+        assert!(async_global_executor::block_on(task).is_ok());
+    }
+
+    #[test]
+    fn close_all() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        use amq_protocol::protocol::{channel, connection};
+
+        let (conn, healthy, frames) = connected_channel();
+        conn.configuration
+            .set_channel_close_timeout(Duration::from_millis(100));
+        let closed = conn.channels.create(conn.closer.clone()).unwrap();
+        closed.set_state(ChannelState::Closed);
+        let unresponsive = conn.channels.create(conn.closer.clone()).unwrap();
+        unresponsive.set_state(ChannelState::Connected);
+
+        // Play the server: acknowledge the healthy channel and the connection, ignore the rest
+        let server = {
+            let channels = conn.channels.clone();
+            let healthy = healthy.id();
+            std::thread::spawn(move || {
+                let mut closed_channels = Vec::new();
+                loop {
+                    let (frame, resolver) = match frames.pop(true) {
+                        Some(frame) => frame,
+                        None => {
+                            std::thread::sleep(Duration::from_millis(1));
+                            continue;
+                        }
+                    };
+                    if let Some(resolver) = resolver {
+                        resolver.swear(Ok(()));
+                    }
+                    match frame {
+                        AMQPFrame::Method(
+                            id,
+                            AMQPClass::Channel(channel::AMQPMethod::Close(_)),
+                        ) => {
+                            closed_channels.push(id);
+                            if id == healthy {
+                                channels
+                                    .handle_frame(AMQPFrame::Method(
+                                        id,
+                                        AMQPClass::Channel(channel::AMQPMethod::CloseOk(
+                                            channel::CloseOk {},
+                                        )),
+                                    ))
+                                    .unwrap();
+                            }
+                        }
+                        AMQPFrame::Method(
+                            0,
+                            AMQPClass::Connection(connection::AMQPMethod::Close(_)),
+                        ) => {
+                            channels
+                                .handle_frame(AMQPFrame::Method(
+                                    0,
+                                    AMQPClass::Connection(connection::AMQPMethod::CloseOk(
+                                        connection::CloseOk {},
+                                    )),
+                                ))
+                                .unwrap();
+                            return closed_channels;
+                        }
+                        frame => panic!("unexpected frame: {:?}", frame),
+                    }
+                }
+            })
+        };
+
+        assert!(async_global_executor::block_on(conn.close_all(200, "bye")).is_ok());
+        let mut closed_channels = server.join().unwrap();
+        closed_channels.sort_unstable();
+        assert_eq!(closed_channels, vec![healthy.id(), unresponsive.id()]);
+        assert_eq!(healthy.status().state(), ChannelState::Closed);
+        assert_eq!(unresponsive.status().state(), ChannelState::Closed);
+        assert_eq!(conn.status.state(), ConnectionState::Closed);
+    }
+
+    #[test]
+    fn connection_stats() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        use crate::queue::{Queue, QueueState};
+
+        let consumer_tag = ShortString::from("consumer-tag");
+        let consumer = Consumer::new(consumer_tag.clone(), DefaultExecutor::default().unwrap());
+        let (conn, channel, _) = connected_channel();
+        let mut queue: QueueState = Queue::new("consumed".into(), 0, 0).into();
+        queue.register_consumer(consumer_tag.clone(), consumer);
+        channel.register_queue(queue);
+
+        deliver_with_delivery_count(&conn, &channel, &consumer_tag, 1, 0);
+        let stats = conn.stats();
+        assert_eq!(stats.channels_opened, 1);
+        assert_eq!(stats.channels_closed, 0);
+        // basic.deliver and the content header of the empty message
+        assert_eq!(stats.frames_received, 2);
+        assert_eq!(stats.channels.delivered, 1);
+
+        conn.channels
+            .remove(channel.id(), Error::InvalidChannel(channel.id()))
+            .unwrap();
+        let stats = conn.stats();
+        assert_eq!(stats.channels_closed, 1);
+        // The stats of closed channels are kept
+        assert_eq!(stats.channels.delivered, 1);
+    }
+
+    #[test]
+    fn cancel_pending_operations() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        let consumer_tag = ShortString::from("consumer-tag");
+        let mut consumer = Consumer::new(consumer_tag, DefaultExecutor::default().unwrap());
+        let (conn, channel) = channel_with_consumer(&consumer);
+        let confirm = channel.register_pending_confirm(1);
+
+        conn.channels.cancel();
+
+        assert_eq!(
+            futures_lite::future::block_on(channel.wait_for_confirms()),
+            Err(Error::Cancelled)
+        );
+        assert_eq!(
+            futures_lite::future::block_on(confirm),
+            Err(Error::Cancelled)
+        );
+        let waker = waker_fn(|| {});
+        let mut cx = Context::from_waker(&waker);
+        assert!(matches!(
+            Pin::new(&mut consumer).poll_next(&mut cx),
+            Poll::Ready(None)
+        ));
+    }
+
+    #[test]
+    fn health_check() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        let (conn, channel, _frames) = connected_channel();
+        let health = conn.health_check();
+        assert!(health.is_healthy());
+        assert_eq!(health.state, ConnectionState::Connected);
+        assert_eq!(health.pending_frames, Some(0));
+        assert_eq!(health.channels_open, Some(1));
+        assert_eq!(health.channels_errored, Some(0));
+        assert_eq!(health.last_frame_received, None);
+
+        conn.channels.handle_frame(AMQPFrame::Heartbeat(0)).unwrap();
+        assert!(conn.health_check().last_frame_received.is_some());
+
+        // Nothing writes the frames out, they pile up
+        conn.channels.send_heartbeat();
+        assert_eq!(conn.health_check().pending_frames, Some(1));
+        conn.channels.send_heartbeat();
+        assert_eq!(conn.health_check().pending_frames, Some(2));
+        assert_eq!(conn.health_check().last_heartbeat_sent, None);
+
+        channel.set_state(ChannelState::Error);
+        let health = conn.health_check();
+        assert!(!health.is_healthy());
+        assert_eq!(health.channels_open, Some(0));
+        assert_eq!(health.channels_errored, Some(1));
+
+        conn.channels
+            .set_connection_error(Error::InvalidConnectionState(ConnectionState::Error));
+        let health = conn.health_check();
+        assert!(!health.is_healthy());
+        assert_eq!(health.state, ConnectionState::Error);
+        assert_eq!(health.pending_frames, Some(0));
+        assert_eq!(health.channels_open, Some(0));
+        assert_eq!(health.channels_errored, Some(0));
+    }
+
+    #[test]
+    fn ping() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        use amq_protocol::protocol::{channel, exchange};
+
+        let (conn, _, frames) = connected_channel();
+
+        // Play the server: answer the channel opening, the declaration and the channel closing
+        let serve = || {
+            let channels = conn.channels.clone();
+            let frames = frames.clone();
+            std::thread::spawn(move || loop {
+                let (frame, resolver) = match frames.pop(true) {
+                    Some(frame) => frame,
+                    None => {
+                        std::thread::sleep(Duration::from_millis(1));
+                        continue;
+                    }
+                };
+                if let Some(resolver) = resolver {
+                    resolver.swear(Ok(()));
+                }
+                let (id, reply) = match frame {
+                    AMQPFrame::Method(id, AMQPClass::Channel(channel::AMQPMethod::Open(_))) => (
+                        id,
+                        AMQPClass::Channel(channel::AMQPMethod::OpenOk(channel::OpenOk {})),
+                    ),
+                    AMQPFrame::Method(
+                        id,
+                        AMQPClass::Exchange(exchange::AMQPMethod::Declare(declare)),
+                    ) => {
+                        assert_eq!(declare.exchange.as_str(), "amq.direct");
+                        assert!(declare.passive);
+                        (
+                            id,
+                            AMQPClass::Exchange(exchange::AMQPMethod::DeclareOk(
+                                exchange::DeclareOk {},
+                            )),
+                        )
+                    }
+                    AMQPFrame::Method(id, AMQPClass::Channel(channel::AMQPMethod::Close(_))) => {
+                        channels
+                            .handle_frame(AMQPFrame::Method(
+                                id,
+                                AMQPClass::Channel(channel::AMQPMethod::CloseOk(
+                                    channel::CloseOk {},
+                                )),
+                            ))
+                            .unwrap();
+                        return;
+                    }
+                    frame => panic!("unexpected frame: {:?}", frame),
+                };
+                channels.handle_frame(AMQPFrame::Method(id, reply)).unwrap();
+            })
+        };
+        let server = serve();
+        assert!(async_global_executor::block_on(conn.ping(Duration::from_secs(5))).is_ok());
+        server.join().unwrap();
+        assert_eq!(conn.health_check().channels_open, Some(1));
+
+        // Nobody answers in time
+        assert_eq!(
+            async_global_executor::block_on(conn.ping(Duration::from_millis(50))),
+            Err(Error::Timeout)
+        );
+
+        // The channel still gets closed once the server answers: the server stops on the closing
+        serve().join().unwrap();
+        assert_eq!(conn.health_check().channels_open, Some(1));
+    }
 }
@@ -1,4 +1,5 @@
 use crate::{connection_status::ConnectionStatus, internal_rpc::InternalRPCHandle, protocol};
+use std::fmt;
 
 pub(crate) struct ConnectionCloser {
     status: ConnectionStatus,
@@ -14,6 +15,12 @@ impl ConnectionCloser {
     }
 }
 
+impl fmt::Debug for ConnectionCloser {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConnectionCloser").finish()
+    }
+}
+
 impl Drop for ConnectionCloser {
     fn drop(&mut self) {
         if self.status.auto_close() {
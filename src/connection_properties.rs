@@ -1,12 +1,56 @@
-use crate::{executor::Executor, reactor::ReactorBuilder, types::FieldTable};
-use std::sync::Arc;
+#[cfg(feature = "serde")]
+use crate::codec::{CodecRegistry, PayloadCodec};
+use crate::{
+    cancellation::CancellationToken,
+    configuration::{
+        DEFAULT_CHANNEL_CLOSE_TIMEOUT, DEFAULT_MANDATORY_RETURN_WINDOW, DEFAULT_MAX_MESSAGE_SIZE,
+    },
+    connector::Connector,
+    executor::Executor,
+    reactor::ReactorBuilder,
+    types::{AMQPValue, FieldTable},
+    ChannelDefaults, OversizedMessagePolicy, PriorityValidation, UnknownConsumerPolicy,
+};
+use std::{sync::Arc, time::Duration};
+use tracing::Level;
 
 #[derive(Clone, Debug)]
 pub struct ConnectionProperties {
     pub locale: String,
+    /// The client properties to send to the server, on top of the ones lapin sends by default:
+    ///
+    /// - `product`: `lapin`
+    /// - `version`: the version of lapin
+    /// - `platform`: `rust`
+    /// - `capabilities`: the protocol extensions lapin supports, `publisher_confirms`,
+    ///   `exchange_exchange_bindings`, `basic.nack`, `consumer_cancel_notify`,
+    ///   `connection.blocked`, `consumer_priorities`, `authentication_failure_close`,
+    ///   `per_consumer_qos` and `direct_reply_to`
+    ///
+    /// The properties set here take precedence over the default ones, and the entries of a
+    /// `capabilities` table over the default capabilities. See
+    /// [`Connection::client_properties`] for what actually got sent.
+    ///
+    /// [`Connection::client_properties`]: ./struct.Connection.html#method.client_properties
     pub client_properties: FieldTable,
     pub executor: Option<Arc<dyn Executor>>,
     pub reactor_builder: Option<Arc<dyn ReactorBuilder>>,
+    pub connector: Option<Arc<dyn Connector>>,
+    pub cancellation_token: Option<CancellationToken>,
+    pub declaration_cache: bool,
+    pub unknown_consumer_policy: UnknownConsumerPolicy,
+    pub max_message_size: u64,
+    pub oversized_message_policy: OversizedMessagePolicy,
+    pub immediate_flag: bool,
+    pub strict_frame_ordering: bool,
+    pub instrumentation_level: Level,
+    pub channel_close_timeout: Duration,
+    pub mandatory_return_window: Duration,
+    pub priority_validation: PriorityValidation,
+    pub strict_field_validation: bool,
+    #[cfg(feature = "serde")]
+    pub codecs: CodecRegistry,
+    pub channel_defaults: ChannelDefaults,
 }
 
 impl Default for ConnectionProperties {
@@ -16,11 +60,71 @@ impl Default for ConnectionProperties {
             client_properties: FieldTable::default(),
             executor: None,
             reactor_builder: None,
+            connector: None,
+            cancellation_token: None,
+            declaration_cache: false,
+            unknown_consumer_policy: UnknownConsumerPolicy::default(),
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            oversized_message_policy: OversizedMessagePolicy::default(),
+            immediate_flag: false,
+            strict_frame_ordering: false,
+            instrumentation_level: Level::DEBUG,
+            channel_close_timeout: DEFAULT_CHANNEL_CLOSE_TIMEOUT,
+            mandatory_return_window: DEFAULT_MANDATORY_RETURN_WINDOW,
+            priority_validation: PriorityValidation::default(),
+            strict_field_validation: cfg!(debug_assertions),
+            #[cfg(feature = "serde")]
+            codecs: CodecRegistry::default(),
+            channel_defaults: ChannelDefaults::default(),
         }
     }
 }
 
 impl ConnectionProperties {
+    /// The locale to ask the server to use for its error messages, `en_US` by default.
+    ///
+    /// It has to be one of the locales the server advertises.
+    pub fn with_locale<L: Into<String>>(mut self, locale: L) -> Self {
+        self.locale = locale.into();
+        self
+    }
+
+    /// The name of the connection, shown by the server in its management tools.
+    pub fn with_connection_name<N: Into<String>>(self, connection_name: N) -> Self {
+        self.with_client_property("connection_name", connection_name.into())
+    }
+
+    /// The name of the client library, `lapin` by default.
+    ///
+    /// The version of lapin isn't sent along with another product, unless set with
+    /// [`with_version`].
+    ///
+    /// [`with_version`]: #method.with_version
+    pub fn with_product<P: Into<String>>(self, product: P) -> Self {
+        self.with_client_property("product", product.into())
+    }
+
+    /// The version of the client library, the version of lapin by default.
+    pub fn with_version<V: Into<String>>(self, version: V) -> Self {
+        self.with_client_property("version", version.into())
+    }
+
+    /// The platform of the client, `rust` by default.
+    pub fn with_platform<P: Into<String>>(self, platform: P) -> Self {
+        self.with_client_property("platform", platform.into())
+    }
+
+    /// A free-form description of the client.
+    pub fn with_information<I: Into<String>>(self, information: I) -> Self {
+        self.with_client_property("information", information.into())
+    }
+
+    fn with_client_property(mut self, key: &str, value: String) -> Self {
+        self.client_properties
+            .insert(key.into(), AMQPValue::LongString(value.into()));
+        self
+    }
+
     pub fn with_executor<E: Executor + 'static>(mut self, executor: E) -> Self {
         self.executor = Some(Arc::new(executor));
         self
@@ -30,4 +134,242 @@ impl ConnectionProperties {
         self.reactor_builder = Some(Arc::new(reactor_builder));
         self
     }
+
+    pub fn with_connector(mut self, connector: Arc<dyn Connector>) -> Self {
+        self.connector = Some(connector);
+        self
+    }
+
+    pub fn with_cancellation_token(mut self, cancellation_token: CancellationToken) -> Self {
+        self.cancellation_token = Some(cancellation_token);
+        self
+    }
+
+    /// Skip the declarations of queues, exchanges and bindings already declared with the same
+    /// options and arguments on this connection.
+    ///
+    /// The cache is reset when the connection errors out and forgets about deleted or unbound
+    /// entities. Server-named, auto-delete and passive declarations always go to the server.
+    ///
+    /// The queues declared from the cache report 0 messages and consumers; use a passive
+    /// declaration to get their actual counts.
+    pub fn with_declaration_cache(mut self, declaration_cache: bool) -> Self {
+        self.declaration_cache = declaration_cache;
+        self
+    }
+
+    /// What to do with deliveries received for consumer tags we don't know about.
+    pub fn with_unknown_consumer_policy(mut self, policy: UnknownConsumerPolicy) -> Self {
+        self.unknown_consumer_policy = policy;
+        self
+    }
+
+    /// The maximum size of the messages we accept to receive, 128MiB by default.
+    ///
+    /// Larger messages are rejected as soon as their content header is received, before
+    /// buffering anything, according to the [`OversizedMessagePolicy`].
+    ///
+    /// [`OversizedMessagePolicy`]: ./enum.OversizedMessagePolicy.html
+    pub fn with_max_message_size(mut self, max_message_size: u64) -> Self {
+        self.max_message_size = max_message_size;
+        self
+    }
+
+    /// What to do with messages larger than the maximum message size.
+    pub fn with_oversized_message_policy(mut self, policy: OversizedMessagePolicy) -> Self {
+        self.oversized_message_policy = policy;
+        self
+    }
+
+    /// Allow publishing with the `immediate` flag even if the server identified itself as
+    /// RabbitMQ 3.0 or later, which doesn't support it.
+    pub fn with_immediate_flag(mut self, immediate_flag: bool) -> Self {
+        self.immediate_flag = immediate_flag;
+        self
+    }
+
+    /// Check that the content frames of each publish are written directly after their
+    /// basic.publish frame, to diagnose UNEXPECTED_FRAME errors from the server.
+    ///
+    /// The last 32 written frames are kept for the diagnostic. On violation, debug builds panic
+    /// while release builds error the connection with [`Error::FrameOrderingViolation`].
+    ///
+    /// [`Error::FrameOrderingViolation`]: ./enum.Error.html#variant.FrameOrderingViolation
+    pub fn with_strict_frame_ordering(mut self, strict_frame_ordering: bool) -> Self {
+        self.strict_frame_ordering = strict_frame_ordering;
+        self
+    }
+
+    /// The level of the spans around the [`Channel`] methods, `DEBUG` by default.
+    ///
+    /// See the [`tracing_integration`] module for the spans themselves.
+    ///
+    /// [`Channel`]: ./struct.Channel.html
+    /// [`tracing_integration`]: ./tracing_integration/index.html
+    pub fn with_instrumentation_level(mut self, level: Level) -> Self {
+        self.instrumentation_level = level;
+        self
+    }
+
+    /// How long [`Connection::close_all`] waits for all the channels to be closed before
+    /// considering the remaining ones closed anyway, 5 seconds by default.
+    ///
+    /// [`Connection::close_all`]: ./struct.Connection.html#method.close_all
+    pub fn with_channel_close_timeout(mut self, timeout: Duration) -> Self {
+        self.channel_close_timeout = timeout;
+        self
+    }
+
+    /// How long [`Channel::basic_publish_mandatory`] waits for the server to return a message
+    /// before considering it routed, 200 milliseconds by default.
+    ///
+    /// [`Channel::basic_publish_mandatory`]: ./struct.Channel.html#method.basic_publish_mandatory
+    pub fn with_mandatory_return_window(mut self, window: Duration) -> Self {
+        self.mandatory_return_window = window;
+        self
+    }
+
+    /// Check the priority of the messages published through the default exchange against the
+    /// `x-max-priority` of the queues declared on the same channel, see
+    /// [`PriorityValidation`]. Off by default.
+    ///
+    /// [`PriorityValidation`]: ./enum.PriorityValidation.html
+    pub fn with_priority_validation(mut self, validation: PriorityValidation) -> Self {
+        self.priority_validation = validation;
+        self
+    }
+
+    /// Check the names and field table keys of the methods we send against the limits of the
+    /// AMQP specification, failing them with [`Error::InvalidName`] instead of letting the
+    /// serialisation or the server reject them. On by default in debug builds only.
+    ///
+    /// The short strings (queue and exchange names, consumer tags, routing keys, field table
+    /// keys) can be at most 255 bytes long, and the field table keys at most 128 characters
+    /// long, starting with a letter, `$` or `#`.
+    ///
+    /// [`Error::InvalidName`]: ./enum.Error.html#variant.InvalidName
+    pub fn with_strict_field_validation(mut self, strict: bool) -> Self {
+        self.strict_field_validation = strict;
+        self
+    }
+
+    /// Use `codec` for the payloads of type `content_type`, see the [`codec`] module.
+    ///
+    /// [`codec`]: ./codec/index.html
+    #[cfg(feature = "serde")]
+    pub fn with_codec(mut self, content_type: &str, codec: Arc<dyn PayloadCodec>) -> Self {
+        self.codecs.register(content_type, codec);
+        self
+    }
+
+    /// Set up every channel created with [`Connection::create_channel`] as told by `defaults`
+    /// before handing it over, see [`ChannelDefaults`]. Nothing is set up by default.
+    ///
+    /// [`Connection::create_channel`]: ./struct.Connection.html#method.create_channel
+    /// [`ChannelDefaults`]: ./struct.ChannelDefaults.html
+    pub fn with_channel_defaults(mut self, defaults: ChannelDefaults) -> Self {
+        self.channel_defaults = defaults;
+        self
+    }
+}
+
+/// The client properties to send in `connection.start-ok`, with the default ones merged under
+/// `overrides`.
+pub(crate) fn client_properties(overrides: &FieldTable) -> FieldTable {
+    let mut capabilities = FieldTable::default();
+    capabilities.insert("publisher_confirms".into(), true.into());
+    capabilities.insert("exchange_exchange_bindings".into(), true.into());
+    capabilities.insert("basic.nack".into(), true.into());
+    capabilities.insert("consumer_cancel_notify".into(), true.into());
+    capabilities.insert("connection.blocked".into(), true.into());
+    capabilities.insert("consumer_priorities".into(), true.into());
+    capabilities.insert("authentication_failure_close".into(), true.into());
+    capabilities.insert("per_consumer_qos".into(), true.into());
+    capabilities.insert("direct_reply_to".into(), true.into());
+
+    let mut client_properties = FieldTable::default();
+    client_properties.insert(
+        "product".into(),
+        AMQPValue::LongString(env!("CARGO_PKG_NAME").into()),
+    );
+    // Our version means nothing for another product
+    if !overrides.inner().contains_key("product") {
+        client_properties.insert(
+            "version".into(),
+            AMQPValue::LongString(env!("CARGO_PKG_VERSION").into()),
+        );
+    }
+    client_properties.insert("platform".into(), AMQPValue::LongString("rust".into()));
+
+    for (key, value) in overrides.inner() {
+        match (key.as_str(), value) {
+            ("capabilities", AMQPValue::FieldTable(overrides)) => {
+                for (capability, value) in overrides.inner() {
+                    capabilities.insert(capability.clone(), value.clone());
+                }
+            }
+            _ => {
+                client_properties.insert(key.clone(), value.clone());
+            }
+        }
+    }
+    client_properties.insert("capabilities".into(), AMQPValue::FieldTable(capabilities));
+    client_properties
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn client_properties_precedence() {
+        let mut capabilities = FieldTable::default();
+        capabilities.insert("consumer_cancel_notify".into(), false.into());
+        let mut overrides = ConnectionProperties::default()
+            .with_connection_name("billing")
+            .with_product("billing-service")
+            .client_properties;
+        overrides.insert("capabilities".into(), AMQPValue::FieldTable(capabilities));
+
+        let client_properties = client_properties(&overrides);
+        let properties = client_properties.inner();
+        assert_eq!(
+            properties.get("connection_name"),
+            Some(&AMQPValue::LongString("billing".into()))
+        );
+        // The user-provided values win, the others are kept
+        assert_eq!(
+            properties.get("product"),
+            Some(&AMQPValue::LongString("billing-service".into()))
+        );
+        // Our version doesn't go with another product
+        assert_eq!(properties.get("version"), None);
+        assert_eq!(
+            properties.get("platform"),
+            Some(&AMQPValue::LongString("rust".into()))
+        );
+        match properties.get("capabilities") {
+            Some(AMQPValue::FieldTable(capabilities)) => {
+                let capabilities = capabilities.inner();
+                assert_eq!(
+                    capabilities.get("consumer_cancel_notify"),
+                    Some(&AMQPValue::Boolean(false))
+                );
+                assert_eq!(
+                    capabilities.get("publisher_confirms"),
+                    Some(&AMQPValue::Boolean(true))
+                );
+            }
+            capabilities => panic!("expected a capabilities table, got {:?}", capabilities),
+        }
+
+        let overrides = ConnectionProperties::default()
+            .with_product("billing-service")
+            .with_version("1.2.0")
+            .client_properties;
+        assert_eq!(
+            client_properties(&overrides).inner().get("version"),
+            Some(&AMQPValue::LongString("1.2.0".into()))
+        );
+    }
 }
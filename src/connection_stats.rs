@@ -0,0 +1,120 @@
+use crate::channel_stats::ChannelStats;
+use parking_lot::Mutex;
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+
+/// A snapshot of the activity of a connection since it was opened.
+///
+/// Like [`ChannelStats`], the counters never reset and wrap around on overflow.
+///
+/// [`ChannelStats`]: ./struct.ChannelStats.html
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ConnectionStats {
+    /// The number of channels opened
+    pub channels_opened: u64,
+    /// The number of channels closed
+    pub channels_closed: u64,
+    /// The number of bytes written to the socket
+    pub bytes_sent: u64,
+    /// The number of bytes read from the socket
+    pub bytes_received: u64,
+    /// The number of frames sent
+    pub frames_sent: u64,
+    /// The number of frames received
+    pub frames_received: u64,
+    /// The number of heartbeats sent
+    pub heartbeats_sent: u64,
+    /// The number of frames queued on channels which failed before they could be sent
+    pub frames_dropped: u64,
+    /// The message operations performed on all the channels of this connection, including the
+    /// closed ones
+    pub channels: ChannelStats,
+}
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ConnectionStatsCounters(Arc<Counters>);
+
+#[derive(Debug, Default)]
+struct Counters {
+    channels_opened: AtomicU64,
+    channels_closed: AtomicU64,
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+    frames_sent: AtomicU64,
+    frames_received: AtomicU64,
+    heartbeats_sent: AtomicU64,
+    frames_dropped: AtomicU64,
+    closed_channels: Mutex<ChannelStats>,
+    last_frame_received: Mutex<Option<Instant>>,
+    last_heartbeat_sent: Mutex<Option<Instant>>,
+}
+
+impl ConnectionStatsCounters {
+    pub(crate) fn channel_opened(&self) {
+        self.0.channels_opened.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn channel_closed(&self, stats: ChannelStats) {
+        self.0.channels_closed.fetch_add(1, Ordering::Relaxed);
+        self.0.closed_channels.lock().add(stats);
+    }
+
+    pub(crate) fn sent(&self, bytes: usize) {
+        self.0.bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn received(&self, bytes: usize) {
+        self.0
+            .bytes_received
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn frame_sent(&self) {
+        self.0.frames_sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn frame_received(&self) {
+        self.0.frames_received.fetch_add(1, Ordering::Relaxed);
+        *self.0.last_frame_received.lock() = Some(Instant::now());
+    }
+
+    pub(crate) fn heartbeat_sent(&self) {
+        self.0.heartbeats_sent.fetch_add(1, Ordering::Relaxed);
+        *self.0.last_heartbeat_sent.lock() = Some(Instant::now());
+    }
+
+    pub(crate) fn frames_dropped(&self, count: usize) {
+        self.0
+            .frames_dropped
+            .fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn last_frame_received(&self) -> Option<Instant> {
+        *self.0.last_frame_received.lock()
+    }
+
+    pub(crate) fn last_heartbeat_sent(&self) -> Option<Instant> {
+        *self.0.last_heartbeat_sent.lock()
+    }
+
+    pub(crate) fn snapshot(&self, open_channels: ChannelStats) -> ConnectionStats {
+        let mut channels = *self.0.closed_channels.lock();
+        channels.add(open_channels);
+        ConnectionStats {
+            channels_opened: self.0.channels_opened.load(Ordering::Relaxed),
+            channels_closed: self.0.channels_closed.load(Ordering::Relaxed),
+            bytes_sent: self.0.bytes_sent.load(Ordering::Relaxed),
+            bytes_received: self.0.bytes_received.load(Ordering::Relaxed),
+            frames_sent: self.0.frames_sent.load(Ordering::Relaxed),
+            frames_received: self.0.frames_received.load(Ordering::Relaxed),
+            heartbeats_sent: self.0.heartbeats_sent.load(Ordering::Relaxed),
+            frames_dropped: self.0.frames_dropped.load(Ordering::Relaxed),
+            channels,
+        }
+    }
+}
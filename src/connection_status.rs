@@ -1,5 +1,6 @@
 use crate::{
     auth::{Credentials, SASLMechanism},
+    types::FieldTable,
     Connection, ConnectionProperties, PromiseResolver,
 };
 use parking_lot::Mutex;
@@ -47,6 +48,96 @@ impl ConnectionStatus {
         self.0.lock().username = username.into();
     }
 
+    pub(crate) fn client_properties(&self) -> FieldTable {
+        self.0.lock().client_properties.clone()
+    }
+
+    pub(crate) fn set_client_properties(&self, client_properties: FieldTable) {
+        self.0.lock().client_properties = client_properties;
+    }
+
+    /// The version the server reported when the connection was established
+    pub fn server_version(&self) -> Option<String> {
+        self.0.lock().server_version.clone()
+    }
+
+    pub(crate) fn set_server_version(&self, server_version: String) {
+        self.0.lock().server_version = Some(server_version);
+    }
+
+    /// The product name the server reported when the connection was established, such as
+    /// "RabbitMQ"
+    pub fn server_product(&self) -> Option<String> {
+        self.0.lock().server_product.clone()
+    }
+
+    pub(crate) fn set_server_product(&self, server_product: String) {
+        self.0.lock().server_product = Some(server_product);
+    }
+
+    /// Whether the server advertised the `per_consumer_qos` capability, meaning that it applies
+    /// the prefetch count per consumer or per channel rather than per channel or per connection,
+    /// see [`QosScope`].
+    ///
+    /// [`QosScope`]: ./enum.QosScope.html
+    pub fn per_consumer_qos(&self) -> bool {
+        self.0.lock().per_consumer_qos
+    }
+
+    pub(crate) fn set_per_consumer_qos(&self, per_consumer_qos: bool) {
+        self.0.lock().per_consumer_qos = per_consumer_qos;
+    }
+
+    /// Whether the server supports `connection.update-secret`, see
+    /// [`Connection::update_secret`].
+    ///
+    /// This is the case when it advertised the `connection.update_secret` capability, or when it
+    /// identified itself as RabbitMQ 3.8 or later, which supports it without advertising it.
+    ///
+    /// [`Connection::update_secret`]: ./struct.Connection.html#method.update_secret
+    pub fn supports_update_secret(&self) -> bool {
+        let advertised = self.0.lock().update_secret;
+        advertised || self.is_rabbitmq_at_least(3, 8)
+    }
+
+    pub(crate) fn set_update_secret(&self, update_secret: bool) {
+        self.0.lock().update_secret = update_secret;
+    }
+
+    /// Whether the server identified itself as RabbitMQ 3.0 or later.
+    pub(crate) fn is_rabbitmq_3_or_later(&self) -> bool {
+        self.is_rabbitmq_at_least(3, 0)
+    }
+
+    fn is_rabbitmq_at_least(&self, major: u32, minor: u32) -> bool {
+        let inner = self.0.lock();
+        if inner.server_product.as_deref() != Some("RabbitMQ") {
+            return false;
+        }
+        let mut version = inner
+            .server_version
+            .as_deref()
+            .unwrap_or_default()
+            .split('.')
+            .map(|number| number.parse::<u32>().ok());
+        match (version.next().flatten(), version.next().flatten()) {
+            (Some(server_major), server_minor) => {
+                (server_major, server_minor.unwrap_or(0)) >= (major, minor)
+            }
+            (None, _) => false,
+        }
+    }
+
+    pub(crate) fn try_summary(&self) -> Option<(ConnectionState, String, Option<String>)> {
+        self.0.try_lock().map(|inner| {
+            (
+                inner.state.clone(),
+                inner.vhost.clone(),
+                inner.server_version.clone(),
+            )
+        })
+    }
+
     pub(crate) fn block(&self) {
         self.0.lock().blocked = true;
     }
@@ -117,6 +208,10 @@ impl fmt::Debug for ConnectionStatus {
                 .field("state", &inner.state)
                 .field("vhost", &inner.vhost)
                 .field("username", &inner.username)
+                .field("server_product", &inner.server_product)
+                .field("server_version", &inner.server_version)
+                .field("per_consumer_qos", &inner.per_consumer_qos)
+                .field("update_secret", &inner.update_secret)
                 .field("blocked", &inner.blocked);
         }
         debug.finish()
@@ -128,6 +223,11 @@ struct Inner {
     state: ConnectionState,
     vhost: String,
     username: String,
+    server_product: Option<String>,
+    server_version: Option<String>,
+    client_properties: FieldTable,
+    per_consumer_qos: bool,
+    update_secret: bool,
     blocked: bool,
 }
 
@@ -138,6 +238,11 @@ impl Default for Inner {
             state: ConnectionState::default(),
             vhost: "/".into(),
             username: "guest".into(),
+            server_product: None,
+            server_version: None,
+            client_properties: FieldTable::default(),
+            per_consumer_qos: false,
+            update_secret: false,
             blocked: false,
         }
     }
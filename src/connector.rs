@@ -0,0 +1,52 @@
+use crate::{
+    tcp::{HandshakeResult, OwnedTLSConfig, TcpStream},
+    uri::{AMQPScheme, AMQPUri},
+};
+use async_trait::async_trait;
+use std::{fmt, net::SocketAddr};
+
+/// Establishes the transport used by a [`Connection`].
+///
+/// When no connector is set in the [`ConnectionProperties`], the host and port from the URI are
+/// resolved and dialed, with TLS negotiated for `amqps` URIs.
+///
+/// [`Connection`]: ./struct.Connection.html
+/// [`ConnectionProperties`]: ./struct.ConnectionProperties.html
+#[async_trait]
+pub trait Connector: fmt::Debug + Send + Sync {
+    /// Open the transport for the given URI.
+    async fn connect(&self, uri: AMQPUri, config: OwnedTLSConfig) -> HandshakeResult;
+}
+
+/// A [`Connector`] dialing a fixed address instead of the host from the URI.
+///
+/// The host from the URI is still used for the TLS handshake (SNI and certificate validation).
+///
+/// [`Connector`]: ./trait.Connector.html
+#[derive(Clone, Debug)]
+pub struct SocketAddrConnector {
+    addr: SocketAddr,
+}
+
+impl SocketAddrConnector {
+    pub fn new(addr: SocketAddr) -> Self {
+        Self { addr }
+    }
+}
+
+#[async_trait]
+impl Connector for SocketAddrConnector {
+    async fn connect(&self, uri: AMQPUri, config: OwnedTLSConfig) -> HandshakeResult {
+        let addr = self.addr;
+        blocking::unblock(move || {
+            let stream = TcpStream::connect(addr)?;
+            let stream = match uri.scheme {
+                AMQPScheme::AMQP => stream,
+                AMQPScheme::AMQPS => stream.into_tls(&uri.authority.host, config.as_ref())?,
+            };
+            stream.set_nonblocking(true)?;
+            Ok(stream)
+        })
+        .await
+    }
+}
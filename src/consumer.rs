@@ -1,20 +1,33 @@
 use crate::{
+    backlog::{BacklogCheck, BacklogEvent, BacklogMonitor, BacklogPolicy},
+    cancellation::CancellationToken,
+    consumer_stats::{ConsumerStats, ConsumerStatsCounters, ConsumerStatsSample},
     executor::Executor,
+    internal_rpc::InternalRPCHandle,
     message::{Delivery, DeliveryResult},
-    types::ShortString,
+    metrics::ConsumerMetrics,
+    options::{
+        BasicAckOptions, BasicConsumeOptions, BasicNackOptions, BasicQosOptions, BasicRejectOptions,
+    },
+    tracing_integration::targets,
+    types::{FieldTable, ShortString, ShortUInt},
     BasicProperties, Channel, Error, Result,
 };
+use async_io::Timer;
 use flume::{Receiver, Sender};
-use futures_lite::Stream;
+use futures_lite::{future, stream, FutureExt, Stream};
 use parking_lot::Mutex;
 use std::{
+    collections::HashMap,
     fmt,
     future::Future,
+    panic::{self, AssertUnwindSafe},
     pin::Pin,
     sync::Arc,
     task::{Context, Poll, Waker},
+    time::{Duration, Instant, SystemTime},
 };
-use tracing::trace;
+use tracing::{error, trace};
 
 pub trait ConsumerDelegate: Send + Sync {
     fn on_new_delivery(&self, delivery: DeliveryResult)
@@ -37,6 +50,209 @@ impl<
     }
 }
 
+/// How to settle a delivery which has been successfully handled by a [`TypedConsumerDelegate`].
+///
+/// [`TypedConsumerDelegate`]: ./trait.TypedConsumerDelegate.html
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AckKind {
+    /// Acknowledge this delivery
+    Ack,
+    /// Acknowledge this delivery and all the previous unacknowledged ones on its channel
+    AckMultiple,
+}
+
+/// How to settle a delivery whose handling by a [`TypedConsumerDelegate`] failed.
+///
+/// [`TypedConsumerDelegate`]: ./trait.TypedConsumerDelegate.html
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NackKind {
+    /// Negatively acknowledge this delivery
+    Nack { requeue: bool },
+    /// Reject this delivery
+    Reject { requeue: bool },
+}
+
+/// A delegate whose result decides how each delivery gets settled.
+///
+/// Set it with [`Consumer::set_typed_delegate`]: the delivery gets acknowledged, nacked or
+/// rejected on its channel once the returned future resolves. If the delegate panics, the delivery
+/// is nacked and requeued.
+///
+/// [`Consumer::set_typed_delegate`]: ./struct.Consumer.html#method.set_typed_delegate
+pub trait TypedConsumerDelegate: Send + Sync {
+    fn on_new_delivery(
+        &self,
+        delivery: Delivery,
+    ) -> Pin<Box<dyn Future<Output = std::result::Result<AckKind, NackKind>> + Send>>;
+}
+
+impl<
+        F: Future<Output = std::result::Result<AckKind, NackKind>> + Send + 'static,
+        DeliveryHandler: Fn(Delivery) -> F + Send + Sync + 'static,
+    > TypedConsumerDelegate for DeliveryHandler
+{
+    fn on_new_delivery(
+        &self,
+        delivery: Delivery,
+    ) -> Pin<Box<dyn Future<Output = std::result::Result<AckKind, NackKind>> + Send>> {
+        Box::pin(self(delivery))
+    }
+}
+
+struct SettlingDelegate<D>(Arc<D>);
+
+impl<D: TypedConsumerDelegate + 'static> ConsumerDelegate for SettlingDelegate<D> {
+    fn on_new_delivery(
+        &self,
+        delivery: DeliveryResult,
+    ) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        let delegate = self.0.clone();
+        Box::pin(async move {
+            let (channel, delivery) = match delivery {
+                Ok(Some(delivery)) => delivery,
+                Ok(None) => return,
+                Err(err) => {
+                    error!(target: targets::CONSUMER, "Consumer error: {}", err);
+                    return;
+                }
+            };
+            let delivery_tag = delivery.delivery_tag;
+            let handled = match panic::catch_unwind(AssertUnwindSafe(|| {
+                delegate.on_new_delivery(delivery)
+            })) {
+                Ok(handler) => AssertUnwindSafe(handler).catch_unwind().await,
+                Err(panic) => Err(panic),
+            };
+            let res = match handled {
+                Ok(Ok(AckKind::Ack)) => {
+                    channel
+                        .basic_ack(delivery_tag, BasicAckOptions::default().multiple(false))
+                        .await
+                }
+                Ok(Ok(AckKind::AckMultiple)) => {
+                    channel
+                        .basic_ack(delivery_tag, BasicAckOptions::default().multiple(true))
+                        .await
+                }
+                Ok(Err(NackKind::Nack { requeue })) => {
+                    channel
+                        .basic_nack(
+                            delivery_tag,
+                            BasicNackOptions::default().multiple(false).requeue(requeue),
+                        )
+                        .await
+                }
+                Ok(Err(NackKind::Reject { requeue })) => {
+                    channel
+                        .basic_reject(delivery_tag, BasicRejectOptions::default().requeue(requeue))
+                        .await
+                }
+                Err(_) => {
+                    error!(
+                        target: targets::CONSUMER,
+                        "Consumer delegate panicked, requeueing delivery; delivery_tag={}",
+                        delivery_tag
+                    );
+                    channel
+                        .basic_nack(
+                            delivery_tag,
+                            BasicNackOptions::default().multiple(false).requeue(true),
+                        )
+                        .await
+                }
+            };
+            if let Err(err) = res {
+                error!(
+                    target: targets::CONSUMER,
+                    "Failed to settle delivery; delivery_tag={}, error={}",
+                    delivery_tag, err
+                );
+            }
+        })
+    }
+}
+
+/// What to do with a message which has been delivered too many times.
+///
+/// See [`Consumer::set_poison_threshold`].
+///
+/// [`Consumer::set_poison_threshold`]: ./struct.Consumer.html#method.set_poison_threshold
+#[derive(Clone)]
+pub enum PoisonAction {
+    /// Reject the message without requeueing it, letting the server dead-letter it if the queue
+    /// has a dead letter exchange configured.
+    DeadLetter,
+    /// Acknowledge the message so that it gets discarded, even if the queue has a dead letter
+    /// exchange configured.
+    Drop,
+    /// Call the given function with the message, and then reject it without requeueing it.
+    Callback(Arc<dyn Fn(&Delivery) + Send + Sync>),
+}
+
+/// What to do with a delivery received for a consumer tag which isn't known on its channel,
+/// for example because it arrived right after the consumer got canceled.
+///
+/// The content of the delivery is always read before the policy gets applied. See
+/// [`ConnectionProperties::with_unknown_consumer_policy`].
+///
+/// [`ConnectionProperties::with_unknown_consumer_policy`]: ./struct.ConnectionProperties.html#method.with_unknown_consumer_policy
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnknownConsumerPolicy {
+    /// Nack the delivery and requeue it so that another consumer can get it.
+    NackRequeue,
+    /// Ignore the delivery. It stays unacknowledged until the channel gets closed.
+    Drop,
+    /// Close the channel with an error.
+    Error,
+}
+
+impl Default for UnknownConsumerPolicy {
+    fn default() -> Self {
+        UnknownConsumerPolicy::NackRequeue
+    }
+}
+
+impl PoisonAction {
+    async fn handle(self, channel: Channel, delivery: Delivery) {
+        let delivery_tag = delivery.delivery_tag;
+        let res = match self {
+            PoisonAction::DeadLetter => {
+                channel
+                    .basic_reject(delivery_tag, BasicRejectOptions::default().requeue(false))
+                    .await
+            }
+            PoisonAction::Drop => {
+                channel
+                    .basic_ack(delivery_tag, BasicAckOptions::default())
+                    .await
+            }
+            PoisonAction::Callback(callback) => {
+                callback(&delivery);
+                channel
+                    .basic_reject(delivery_tag, BasicRejectOptions::default().requeue(false))
+                    .await
+            }
+        };
+        if let Err(err) = res {
+            error!(
+                target: targets::CONSUMER,
+                "Failed to discard poison message; delivery_tag={}, error={}",
+                delivery_tag, err
+            );
+        }
+    }
+}
+
+impl fmt::Debug for PoisonAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PoisonAction::DeadLetter => f.write_str("DeadLetter"),
+            PoisonAction::Drop => f.write_str("Drop"),
+            PoisonAction::Callback(_) => f.write_str("Callback"),
+        }
+    }
+}
+
 /// Continuously consumes message from a Queue.
 ///
 /// A consumer represents a stream of messages created from
@@ -128,35 +344,306 @@ impl<
 #[derive(Clone)]
 pub struct Consumer {
     inner: Arc<Mutex<ConsumerInner>>,
+    stats: ConsumerStatsCounters,
 }
 
 impl Consumer {
     pub(crate) fn new(consumer_tag: ShortString, executor: Arc<dyn Executor>) -> Consumer {
         Consumer {
             inner: Arc::new(Mutex::new(ConsumerInner::new(consumer_tag, executor))),
+            stats: ConsumerStatsCounters::default(),
         }
     }
 
     /// Gets the consumer tag.
     ///
     /// If no consumer tag was specified when obtaining the consumer from the channel,
-    /// this contains the server generated consumer tag.
+    /// this contains the generated consumer tag.
+    ///
+    /// The tag can be changed by the server, so this returns a copy of it, which allocates:
+    /// keep it around rather than calling this in a loop.
     pub fn tag(&self) -> ShortString {
         self.inner.lock().tag.clone()
     }
 
+    /// Call `callback` once with the tag the server confirmed for this consumer in its
+    /// `basic.consume-ok`, if it isn't the requested one.
+    ///
+    /// This is the tag lapin generated when [`Channel::basic_consume`] got an empty one, or the
+    /// one the server assigned when it didn't honor the requested one. Resubscribing with the
+    /// same tag doesn't report it again. If the server already confirmed the consumer,
+    /// `callback` is called right away.
+    ///
+    /// [`Channel::basic_consume`]: ./struct.Channel.html#method.basic_consume
+    pub fn on_tag_assigned<F: Fn(ShortString) + Send + 'static>(&self, callback: F) {
+        let mut inner = self.inner.lock();
+        if let Some(tag) = inner.assigned_tag.take() {
+            drop(inner);
+            callback(tag);
+        } else {
+            inner.tag_assigned_callback = Some(Box::new(callback));
+        }
+    }
+
+    /// Report the tag the server confirmed if lapin generated it, as `requested` was empty, or if
+    /// the server didn't honor `requested`.
+    pub(crate) fn tag_assigned(&self, requested: &str) {
+        let mut inner = self.inner.lock();
+        if !requested.is_empty() && inner.tag.as_str() == requested {
+            return;
+        }
+        let tag = inner.tag.clone();
+        if let Some(callback) = inner.tag_assigned_callback.take() {
+            drop(inner);
+            callback(tag);
+        } else {
+            inner.assigned_tag = Some(tag);
+        }
+    }
+
+    /// The deliveries received by this consumer and how they were settled, since it was created
+    /// or since the last call to [`reset_stats`].
+    ///
+    /// [`reset_stats`]: #method.reset_stats
+    pub fn stats(&self) -> ConsumerStats {
+        self.stats.snapshot()
+    }
+
+    /// Reset the counters of [`stats`] to zero. The deliveries still waiting for their
+    /// acknowledgement are kept to compute their age.
+    ///
+    /// [`stats`]: #method.stats
+    pub fn reset_stats(&self) {
+        self.stats.reset();
+    }
+
+    /// Sample [`stats`] every `interval`, until the consumer gets canceled or fails, for example
+    /// because its channel or connection failed.
+    ///
+    /// The rate is the number of deliveries received per second since the previous sample, or
+    /// since this was called for the first one.
+    ///
+    /// [`stats`]: #method.stats
+    pub fn statistics_interval(
+        &self,
+        interval: Duration,
+    ) -> impl Stream<Item = ConsumerStatsSample> + Send + 'static {
+        let state = (self.clone(), self.stats(), Instant::now());
+        stream::unfold(state, move |(consumer, previous, sampled_at)| async move {
+            Timer::after(interval).await;
+            if consumer.is_canceled() {
+                return None;
+            }
+            let stats = consumer.stats();
+            let now = Instant::now();
+            let elapsed = now.duration_since(sampled_at).as_secs_f64();
+            // The counters go back down when they get reset
+            let delivered = stats.delivered.saturating_sub(previous.delivered);
+            let sample = ConsumerStatsSample {
+                timestamp: SystemTime::now(),
+                stats,
+                rate_per_second: if elapsed > 0.0 {
+                    delivered as f64 / elapsed
+                } else {
+                    0.0
+                },
+            };
+            Some((sample, (consumer, stats, now)))
+        })
+    }
+
+    pub(crate) fn stats_counters(&self) -> ConsumerStatsCounters {
+        self.stats.clone()
+    }
+
     /// Automatically spawns the delegate on the executor for each message.
     ///
     /// Enables parallel handling of the messages.
+    ///
+    /// The delegate is called, and its future spawned, with no lock of the consumer, channel or
+    /// connection held, so it can use them. The io loop of the connection runs on its own thread:
+    /// the delegate can await the methods it calls, such as a publish and its confirmation, even
+    /// with a single-threaded executor, as long as it doesn't block it.
     pub fn set_delegate<D: ConsumerDelegate + 'static>(&self, delegate: D) {
+        let delegate: Arc<Box<dyn ConsumerDelegate>> = Arc::new(Box::new(delegate));
+        self.with_inner(|inner| {
+            while let Some(delivery) = inner.next_delivery() {
+                inner.dispatch(delegate.clone(), delivery);
+            }
+            inner.delegate = Some(delegate);
+        });
+    }
+
+    /// Automatically spawns the delegate on the executor for each message, and settles the message
+    /// according to its result.
+    ///
+    /// See [`TypedConsumerDelegate`].
+    ///
+    /// [`TypedConsumerDelegate`]: ./trait.TypedConsumerDelegate.html
+    pub fn set_typed_delegate<D: TypedConsumerDelegate + 'static>(&self, delegate: D) {
+        self.set_delegate(SettlingDelegate(Arc::new(delegate)));
+    }
+
+    /// Wait for the delegate to finish handling the deliveries it got so far.
+    ///
+    /// Once the consumer got canceled, this resolves once all of its deliveries have been
+    /// handled. This resolves right away for consumers without a delegate.
+    pub fn join(&self) -> impl Future<Output = ()> + Send + 'static {
+        self.inner.lock().in_flight.idle()
+    }
+
+    /// Automatically discard the messages which have already been delivered more than `threshold`
+    /// times, according to [`Delivery::delivery_count`], instead of handing them to the
+    /// application.
+    ///
+    /// This only makes sense for consumers with manual acknowledgement, on queues which set the
+    /// `x-delivery-count` header (such as RabbitMQ quorum queues).
+    ///
+    /// [`Delivery::delivery_count`]: ./message/struct.Delivery.html#method.delivery_count
+    pub fn set_poison_threshold(&self, threshold: u64, action: PoisonAction) {
+        self.inner.lock().poison_threshold = Some((threshold, action));
+    }
+
+    /// Call `callback` when the deliveries waiting to be read from this consumer pile up, and
+    /// shed the load on the server as configured by `policy` until they get read.
+    ///
+    /// Only the deliveries buffered for the stream or the iterator of this consumer are counted:
+    /// the ones handed to a delegate are never considered waiting.
+    pub fn on_backlog<F: Fn(BacklogEvent) + Send + Sync + 'static>(
+        &self,
+        policy: BacklogPolicy,
+        callback: F,
+    ) {
+        self.inner.lock().backlog = Some(BacklogMonitor::new(policy, Arc::new(callback)));
+    }
+
+    async fn watch_backlog(self, mut wait: Duration) {
+        loop {
+            Timer::after(wait).await;
+            let (report, action, next) = {
+                let mut inner = self.inner.lock();
+                let buffered = inner.deliveries_out.len();
+                match inner.backlog.as_mut() {
+                    Some(backlog) => backlog.check(buffered),
+                    None => return,
+                }
+            };
+            if let Some((callback, event)) = report {
+                callback(event);
+            }
+            if let Some(action) = action {
+                if let Err(err) = action.run().await {
+                    error!(
+                        target: targets::CONSUMER,
+                        "Failed to shed the backlog; consumer_tag={}, error={}",
+                        self.tag(),
+                        err
+                    );
+                }
+            }
+            match next {
+                BacklogCheck::Wait(next) => wait = next,
+                BacklogCheck::Stop => return,
+            }
+        }
+    }
+
+    /// Cancel this consumer once the token gets cancelled, ending the stream of deliveries.
+    pub fn set_cancellation_token(&self, token: CancellationToken) {
+        // The token can outlive the consumer, don't keep it alive
+        let inner = Arc::downgrade(&self.inner);
+        let stats = self.stats.clone();
+        token.on_cancel(move || {
+            if let Some(inner) = inner.upgrade() {
+                Consumer { inner, stats }.with_inner(ConsumerInner::cancel_on_server);
+            }
+        });
+    }
+
+    /// Consume again from the same queue with the same consumer tag, options and arguments, on
+    /// `channel`, for example after the connection was lost and a new one got opened.
+    ///
+    /// If a prefetch count was set with [`Channel::basic_qos`] on the original channel, it gets
+    /// set on `channel` first. Once this resolves, the new deliveries keep flowing to this
+    /// consumer, even if it already yielded the error and the end of stream caused by the
+    /// connection loss.
+    ///
+    /// This fails with [`Error::InvalidArgument`] for consumers which weren't started with one of
+    /// the `basic_consume` methods of [`Channel`].
+    ///
+    /// [`Channel::basic_qos`]: ./struct.Channel.html#method.basic_qos
+    /// [`Error::InvalidArgument`]: ./enum.Error.html#variant.InvalidArgument
+    /// [`Channel`]: ./struct.Channel.html
+    pub fn resubscribe(
+        &self,
+        channel: Channel,
+    ) -> impl Future<Output = Result<()>> + Send + 'static {
+        let consumer = self.clone();
+        async move { channel.resubscribe(consumer).await }
+    }
+
+    /// What this consumer was started with, to start it again on another channel with
+    /// [`Channel::reattach_consumer`], for example once the one it consumed on failed.
+    ///
+    /// The consumer gets unhooked from its current channel: canceling it afterwards only ends
+    /// its stream, until it gets reattached. This fails for consumers which weren't started with
+    /// one of the `basic_consume` methods of [`Channel`], and for the ones still consuming, as
+    /// the server would keep delivering to them on their current channel: cancel them first.
+    ///
+    /// [`Channel::reattach_consumer`]: ./struct.Channel.html#method.reattach_consumer
+    /// [`Channel`]: ./struct.Channel.html
+    pub fn detach(&self) -> Result<ConsumerSpec> {
         let mut inner = self.inner.lock();
-        while let Some(delivery) = inner.next_delivery() {
-            inner.executor.spawn(delegate.on_new_delivery(delivery));
+        let subscription = inner.subscription.clone().ok_or_else(|| {
+            Error::InvalidArgument(format!(
+                "consumer {} wasn't started with basic_consume",
+                inner.tag
+            ))
+        })?;
+        if !inner.canceled {
+            return Err(Error::InvalidArgument(format!(
+                "consumer {} is still consuming, cancel it or wait for its channel to fail first",
+                inner.tag
+            )));
         }
-        inner.delegate = Some(Arc::new(Box::new(delegate)));
+        trace!(target: targets::CONSUMER, "detach; consumer_tag={}", inner.tag);
+        inner.channel = None;
+        Ok(ConsumerSpec {
+            queue: subscription.queue,
+            consumer_tag: inner.tag.clone(),
+            options: subscription.options,
+            arguments: subscription.arguments,
+        })
+    }
+
+    pub(crate) fn set_tag(&self, tag: ShortString) {
+        self.inner.lock().tag = tag;
+    }
+
+    pub(crate) fn subscription(&self) -> Option<Subscription> {
+        self.inner.lock().subscription.clone()
+    }
+
+    pub(crate) fn set_subscription(&self, subscription: Subscription) {
+        self.inner.lock().subscription = Some(subscription);
+    }
+
+    pub(crate) fn set_subscription_queue(&self, queue: ShortString) {
+        if let Some(subscription) = self.inner.lock().subscription.as_mut() {
+            subscription.queue = queue;
+        }
+    }
+
+    pub(crate) fn reset(&self) {
+        self.inner.lock().reset();
+    }
+
+    pub(crate) fn set_channel(&self, channel_id: u16, internal_rpc: InternalRPCHandle) {
+        self.inner.lock().channel = Some((channel_id, internal_rpc));
     }
 
     pub(crate) fn start_new_delivery(&mut self, delivery: Delivery) {
+        self.stats.delivered(delivery.redelivered);
         self.inner.lock().current_message = Some(delivery)
     }
 
@@ -167,31 +654,89 @@ impl Consumer {
     }
 
     pub(crate) fn receive_delivery_content(&mut self, payload: Vec<u8>) {
+        self.stats.received(payload.len());
         if let Some(delivery) = self.inner.lock().current_message.as_mut() {
             delivery.receive_content(payload);
         }
     }
 
     pub(crate) fn new_delivery_complete(&mut self, channel: Channel) {
-        let mut inner = self.inner.lock();
-        if let Some(delivery) = inner.current_message.take() {
-            inner.new_delivery(channel, delivery);
-        }
+        self.with_inner(|inner| {
+            if let Some(mut delivery) = inner.current_message.take() {
+                delivery.complete();
+                let backlog_channel = inner.backlog.as_ref().map(|_| channel.clone());
+                inner.new_delivery(channel, delivery);
+                if let Some(channel) = backlog_channel {
+                    let buffered = inner.deliveries_out.len();
+                    let watch = inner
+                        .backlog
+                        .as_mut()
+                        .and_then(|backlog| backlog.grew(buffered, &channel));
+                    if let Some(wait) = watch {
+                        inner.spawn(Box::pin(self.clone().watch_backlog(wait)));
+                    }
+                }
+            }
+        });
     }
 
     pub(crate) fn drop_prefetched_messages(&self) {
-        self.inner.lock().drop_prefetched_messages();
+        self.with_inner(ConsumerInner::drop_prefetched_messages);
     }
 
     pub(crate) fn cancel(&self) {
-        self.inner.lock().cancel();
+        self.with_inner(ConsumerInner::cancel);
+    }
+
+    /// Whether the consumer got canceled, and not subscribed again since.
+    pub(crate) fn is_canceled(&self) -> bool {
+        self.inner.lock().canceled
+    }
+
+    pub(crate) fn set_metrics(&self, metrics: Arc<ConsumerMetrics>) {
+        self.inner.lock().metrics = Some(metrics);
+    }
+
+    pub(crate) fn set_cancel_callback<F: Fn() + Send + 'static>(&self, callback: F) {
+        self.inner.lock().cancel_callback = Some(Box::new(callback));
+    }
+
+    pub(crate) fn canceled_by_server(&self) {
+        let callback = self.with_inner(|inner| {
+            inner.cancel();
+            inner.cancel_callback.take()
+        });
+        if let Some(callback) = callback {
+            callback();
+        }
     }
 
     pub(crate) fn set_error(&self, error: Error) {
-        self.inner.lock().set_error(error);
+        self.with_inner(|inner| inner.set_error(error));
+    }
+
+    /// Run `f` with the consumer locked, then call the delegate and spawn the futures it queued.
+    ///
+    /// Neither the delegate nor the executor may run with the consumer locked: the delegate
+    /// could use the consumer or its channel, and an executor could poll the futures right away,
+    /// which would deadlock.
+    fn with_inner<T, F: FnOnce(&mut ConsumerInner) -> T>(&self, f: F) -> T {
+        let (res, executor, dispatches) = {
+            let mut inner = self.inner.lock();
+            let res = f(&mut inner);
+            let dispatches = std::mem::take(&mut inner.dispatches);
+            (res, inner.executor.clone(), dispatches)
+        };
+        for dispatch in dispatches {
+            executor.spawn(dispatch());
+        }
+        res
     }
 }
 
+/// Calls the delegate, or builds the future to spawn, once the consumer got unlocked.
+type Dispatch = Box<dyn FnOnce() -> Pin<Box<dyn Future<Output = ()> + Send>> + Send>;
+
 struct ConsumerInner {
     current_message: Option<Delivery>,
     deliveries_in: Sender<DeliveryResult>,
@@ -200,6 +745,141 @@ struct ConsumerInner {
     tag: ShortString,
     delegate: Option<Arc<Box<dyn ConsumerDelegate>>>,
     executor: Arc<dyn Executor>,
+    poison_threshold: Option<(u64, PoisonAction)>,
+    channel: Option<(u16, InternalRPCHandle)>,
+    subscription: Option<Subscription>,
+    /* The tag the server assigned instead of the requested one, until it gets reported */
+    assigned_tag: Option<ShortString>,
+    tag_assigned_callback: Option<Box<dyn Fn(ShortString) + Send>>,
+    /* Called when the server cancels the consumer */
+    cancel_callback: Option<Box<dyn Fn() + Send>>,
+    backlog: Option<BacklogMonitor>,
+    in_flight: InFlight,
+    metrics: Option<Arc<ConsumerMetrics>>,
+    /* What to run once the consumer gets unlocked, see Consumer::with_inner */
+    dispatches: Vec<Dispatch>,
+    canceled: bool,
+}
+
+/// Counts the futures spawned to handle deliveries which haven't completed yet.
+#[derive(Clone, Default)]
+struct InFlight(Arc<Mutex<InFlightState>>);
+
+#[derive(Default)]
+struct InFlightState {
+    count: usize,
+    idle_wakers: HashMap<usize, Waker>,
+    next_idle_waiter: usize,
+}
+
+impl InFlight {
+    fn track(
+        &self,
+        future: Pin<Box<dyn Future<Output = ()> + Send>>,
+    ) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        let completion = self.start();
+        Box::pin(async move {
+            future.await;
+            drop(completion);
+        })
+    }
+
+    /// Count a future to come, until the returned guard gets dropped along with it, so that
+    /// futures panicking or dropped by the executor count too.
+    fn start(&self) -> InFlightCompletion {
+        self.0.lock().count += 1;
+        InFlightCompletion(self.clone())
+    }
+
+    fn idle(&self) -> impl Future<Output = ()> + Send + 'static {
+        Idle {
+            in_flight: self.clone(),
+            waiter: None,
+        }
+    }
+}
+
+/// Resolves once no future is in flight anymore, keeping a single waker registered however many
+/// times it gets polled, until it gets dropped.
+struct Idle {
+    in_flight: InFlight,
+    waiter: Option<usize>,
+}
+
+impl Future for Idle {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let this = &mut *self;
+        let mut state = this.in_flight.0.lock();
+        if state.count == 0 {
+            return Poll::Ready(());
+        }
+        let waiter = match this.waiter {
+            Some(waiter) => waiter,
+            None => {
+                let waiter = state.next_idle_waiter;
+                state.next_idle_waiter = state.next_idle_waiter.wrapping_add(1);
+                this.waiter = Some(waiter);
+                waiter
+            }
+        };
+        let waker = state
+            .idle_wakers
+            .entry(waiter)
+            .or_insert_with(|| cx.waker().clone());
+        if !waker.will_wake(cx.waker()) {
+            *waker = cx.waker().clone();
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Idle {
+    fn drop(&mut self) {
+        if let Some(waiter) = self.waiter {
+            self.in_flight.0.lock().idle_wakers.remove(&waiter);
+        }
+    }
+}
+
+struct InFlightCompletion(InFlight);
+
+impl Drop for InFlightCompletion {
+    fn drop(&mut self) {
+        let wakers = {
+            let mut state = (self.0).0.lock();
+            state.count -= 1;
+            if state.count == 0 {
+                std::mem::take(&mut state.idle_wakers)
+            } else {
+                HashMap::new()
+            }
+        };
+        for waker in wakers.into_iter().map(|(_, waker)| waker) {
+            waker.wake();
+        }
+    }
+}
+
+/// What a consumer was started with, to be able to start it again.
+#[derive(Clone, Debug)]
+pub(crate) struct Subscription {
+    pub(crate) queue: ShortString,
+    pub(crate) options: BasicConsumeOptions,
+    pub(crate) arguments: FieldTable,
+    pub(crate) prefetch: Option<(ShortUInt, BasicQosOptions)>,
+}
+
+/// What a consumer consumes and how, see [`Consumer::detach`].
+///
+/// [`Consumer::detach`]: ./struct.Consumer.html#method.detach
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConsumerSpec {
+    pub queue: ShortString,
+    pub consumer_tag: ShortString,
+    pub options: BasicConsumeOptions,
+    pub arguments: FieldTable,
 }
 
 pub struct ConsumerIterator {
@@ -232,7 +912,10 @@ impl fmt::Debug for Consumer {
             debug
                 .field("tag", &inner.tag)
                 .field("executor", &inner.executor)
-                .field("task", &inner.task);
+                .field("task", &inner.task)
+                .field("poison_threshold", &inner.poison_threshold)
+                .field("backlog", &inner.backlog)
+                .field("canceled", &inner.canceled);
         }
         debug.finish()
     }
@@ -249,6 +932,33 @@ impl ConsumerInner {
             tag: consumer_tag,
             delegate: None,
             executor,
+            poison_threshold: None,
+            channel: None,
+            subscription: None,
+            assigned_tag: None,
+            tag_assigned_callback: None,
+            cancel_callback: None,
+            backlog: None,
+            in_flight: InFlight::default(),
+            metrics: None,
+            dispatches: Vec::new(),
+            canceled: false,
+        }
+    }
+
+    fn reset(&mut self) {
+        trace!(target: targets::CONSUMER, "reset; consumer_tag={}", self.tag);
+        // Forget about the error and cancellation of the previous subscription
+        self.canceled = false;
+        let deliveries = self
+            .deliveries_out
+            .try_iter()
+            .filter(|delivery| matches!(delivery, Ok(Some(_))))
+            .collect::<Vec<_>>();
+        for delivery in deliveries {
+            self.deliveries_in
+                .send(delivery)
+                .expect("failed to send delivery to consumer");
         }
     }
 
@@ -257,11 +967,25 @@ impl ConsumerInner {
     }
 
     fn new_delivery(&mut self, channel: Channel, delivery: Delivery) {
-        trace!("new_delivery; consumer_tag={}", self.tag);
-        if let Some(delegate) = self.delegate.as_ref() {
-            let delegate = delegate.clone();
-            self.executor
-                .spawn(delegate.on_new_delivery(Ok(Some((channel, delivery)))));
+        trace!(target: targets::CONSUMER, "new_delivery; consumer_tag={}", self.tag);
+        if let Some(metrics) = self.metrics.as_ref() {
+            metrics.received();
+        }
+        if let Some(action) = self.poison_action(&delivery) {
+            trace!(
+                target: targets::CONSUMER,
+                "discarding poison message; consumer_tag={}, delivery_tag={}",
+                self.tag,
+                delivery.delivery_tag
+            );
+            let handled = self
+                .in_flight
+                .track(Box::pin(action.handle(channel, delivery)));
+            self.spawn(handled);
+            return;
+        }
+        if let Some(delegate) = self.delegate.clone() {
+            self.dispatch(delegate, Ok(Some((channel, delivery))));
         } else {
             self.deliveries_in
                 .send(Ok(Some((channel, delivery))))
@@ -272,20 +996,55 @@ impl ConsumerInner {
         }
     }
 
+    /// Hand `delivery` to `delegate` once the consumer got unlocked, recording how long it takes
+    /// to handle it in the metrics, if any.
+    fn dispatch(&mut self, delegate: Arc<Box<dyn ConsumerDelegate>>, delivery: DeliveryResult) {
+        let metrics = match delivery {
+            Ok(Some(_)) => self.metrics.clone(),
+            _ => None,
+        };
+        let completion = self.in_flight.start();
+        self.dispatches.push(Box::new(move || {
+            let handled = delegate.on_new_delivery(delivery);
+            Box::pin(async move {
+                let started = Instant::now();
+                handled.await;
+                if let Some(metrics) = metrics {
+                    metrics.processed(started.elapsed());
+                }
+                drop(completion);
+            })
+        }));
+    }
+
+    /// Spawn `future` once the consumer got unlocked.
+    fn spawn(&mut self, future: Pin<Box<dyn Future<Output = ()> + Send>>) {
+        self.dispatches.push(Box::new(move || future));
+    }
+
+    fn poison_action(&self, delivery: &Delivery) -> Option<PoisonAction> {
+        let (threshold, action) = self.poison_threshold.as_ref()?;
+        if delivery.delivery_count()? > *threshold {
+            Some(action.clone())
+        } else {
+            None
+        }
+    }
+
     fn drop_prefetched_messages(&mut self) {
-        trace!("drop_prefetched_messages; consumer_tag={}", self.tag);
-        if let Some(delegate) = self.delegate.as_ref() {
-            let delegate = delegate.clone();
-            self.executor.spawn(delegate.drop_prefetched_messages());
+        trace!(target: targets::CONSUMER, "drop_prefetched_messages; consumer_tag={}", self.tag);
+        if let Some(delegate) = self.delegate.clone() {
+            self.dispatches
+                .push(Box::new(move || delegate.drop_prefetched_messages()));
         }
         while self.next_delivery().is_some() {}
     }
 
     fn cancel(&mut self) {
-        trace!("cancel; consumer_tag={}", self.tag);
-        if let Some(delegate) = self.delegate.as_ref() {
-            let delegate = delegate.clone();
-            self.executor.spawn(delegate.on_new_delivery(Ok(None)));
+        trace!(target: targets::CONSUMER, "cancel; consumer_tag={}", self.tag);
+        self.canceled = true;
+        if let Some(delegate) = self.delegate.clone() {
+            self.dispatch(delegate, Ok(None));
         } else {
             self.deliveries_in
                 .send(Ok(None))
@@ -296,16 +1055,29 @@ impl ConsumerInner {
         }
     }
 
+    fn cancel_on_server(&mut self) {
+        trace!(target: targets::CONSUMER, "cancel_on_server; consumer_tag={}", self.tag);
+        if let Some((channel_id, internal_rpc)) = self.channel.as_ref() {
+            // The consumer gets canceled locally once the server acknowledged it
+            internal_rpc.cancel_consumer(*channel_id, self.tag.clone());
+        } else {
+            self.cancel();
+        }
+    }
+
     fn set_error(&mut self, error: Error) {
-        trace!("set_error; consumer_tag={}", self.tag);
-        if let Some(delegate) = self.delegate.as_ref() {
-            let delegate = delegate.clone();
-            self.executor.spawn(delegate.on_new_delivery(Err(error)));
+        trace!(target: targets::CONSUMER, "set_error; consumer_tag={}", self.tag);
+        if let Some(metrics) = self.metrics.as_ref() {
+            metrics.errored();
+        }
+        if let Some(delegate) = self.delegate.clone() {
+            self.dispatch(delegate, Err(error));
         } else {
             self.deliveries_in
                 .send(Err(error))
                 .expect("failed to send error to consumer");
         }
+        // Marks the consumer as canceled too, which ends statistics_interval
         self.cancel();
     }
 }
@@ -314,9 +1086,10 @@ impl Stream for Consumer {
     type Item = Result<(Channel, Delivery)>;
 
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        trace!("consumer poll_next");
+        trace!(target: targets::CONSUMER, "consumer poll_next");
         let mut inner = self.inner.lock();
         trace!(
+            target: targets::CONSUMER,
             "consumer poll; acquired inner lock, consumer_tag={}",
             inner.tag
         );
@@ -325,6 +1098,7 @@ impl Stream for Consumer {
             match delivery {
                 Ok(Some((channel, delivery))) => {
                     trace!(
+                        target: targets::CONSUMER,
                         "delivery; channel={}, consumer_tag={}, delivery_tag={:?}",
                         channel.id(),
                         inner.tag,
@@ -333,13 +1107,21 @@ impl Stream for Consumer {
                     Poll::Ready(Some(Ok((channel, delivery))))
                 }
                 Ok(None) => {
-                    trace!("consumer canceled; consumer_tag={}", inner.tag);
+                    trace!(
+                        target: targets::CONSUMER,
+                        "consumer canceled; consumer_tag={}",
+                        inner.tag
+                    );
                     Poll::Ready(None)
                 }
                 Err(error) => Poll::Ready(Some(Err(error))),
             }
         } else {
-            trace!("delivery; status=NotReady, consumer_tag={}", inner.tag);
+            trace!(
+                target: targets::CONSUMER,
+                "delivery; status=NotReady, consumer_tag={}",
+                inner.tag
+            );
             Poll::Pending
         }
     }
@@ -425,4 +1207,743 @@ mod futures_tests {
             );
         }
     }
+
+    #[test]
+    fn join_waits_for_delegate() {
+        let (release, released) = flume::bounded::<()>(1);
+        let consumer = Consumer::new(
+            ShortString::from("test-consumer"),
+            DefaultExecutor::default().unwrap(),
+        );
+        consumer.set_delegate(move |_delivery: DeliveryResult| {
+            let released = released.clone();
+            async move {
+                let _ = released.recv_async().await;
+            }
+        });
+        consumer.cancel();
+
+        let waker = waker_fn(|| {});
+        let mut cx = Context::from_waker(&waker);
+        let mut join = Box::pin(consumer.join());
+        assert_eq!(join.as_mut().poll(&mut cx), Poll::Pending);
+
+        release.send(()).unwrap();
+        async_global_executor::block_on(join);
+        async_global_executor::block_on(
+            Consumer::new(
+                ShortString::from("no-delegate"),
+                DefaultExecutor::default().unwrap(),
+            )
+            .join(),
+        );
+    }
+
+    #[test]
+    fn statistics_interval() {
+        let mut consumer = Consumer::new(
+            ShortString::from("test-consumer"),
+            DefaultExecutor::default().unwrap(),
+        );
+        let mut samples = Box::pin(consumer.statistics_interval(Duration::from_millis(10)));
+
+        for _ in 0..3 {
+            consumer.start_new_delivery(Delivery::new(1, "".into(), "".into(), false));
+        }
+        let sample = async_global_executor::block_on(samples.next()).unwrap();
+        assert_eq!(sample.stats.delivered, 3);
+        assert!(sample.rate_per_second > 0.0);
+        let sample = async_global_executor::block_on(samples.next()).unwrap();
+        assert_eq!(sample.stats.delivered, 3);
+        assert_eq!(sample.rate_per_second, 0.0);
+
+        consumer.cancel();
+        assert_eq!(async_global_executor::block_on(samples.next()), None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::*;
+
+    #[test]
+    fn in_flight_idle_wakers() {
+        let in_flight = InFlight::default();
+        let completion = in_flight.start();
+
+        // Polling a waiter over and over keeps a single waker around
+        let mut idle = Box::pin(in_flight.idle());
+        for _ in 0..100 {
+            assert_eq!(
+                async_global_executor::block_on(future::poll_once(&mut idle)),
+                None
+            );
+        }
+        assert_eq!(in_flight.0.lock().idle_wakers.len(), 1);
+
+        // Dropped waiters don't leave their waker behind
+        for _ in 0..100 {
+            assert_eq!(
+                async_global_executor::block_on(future::poll_once(in_flight.idle())),
+                None
+            );
+        }
+        assert_eq!(in_flight.0.lock().idle_wakers.len(), 1);
+
+        drop(completion);
+        async_global_executor::block_on(idle);
+        assert!(in_flight.0.lock().idle_wakers.is_empty());
+    }
+
+    #[test]
+    fn basic_consume_poison_threshold_stream() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        let consumer_tag = ShortString::from("consumer-tag");
+        let mut consumer = Consumer::new(consumer_tag.clone(), DefaultExecutor::default().unwrap());
+        consumer.set_poison_threshold(3, PoisonAction::DeadLetter);
+        let (conn, channel) = channel_with_consumer(&consumer);
+
+        deliver_with_delivery_count(&conn, &channel, &consumer_tag, 1, 3);
+        deliver_with_delivery_count(&conn, &channel, &consumer_tag, 2, 4);
+
+        let waker = waker_fn(|| {});
+        let mut cx = Context::from_waker(&waker);
+        match Pin::new(&mut consumer).poll_next(&mut cx) {
+            Poll::Ready(Some(Ok((_, delivery)))) => {
+                assert_eq!(delivery.delivery_tag, 1);
+                assert_eq!(delivery.delivery_count(), Some(3));
+                assert!(delivery.is_redelivered());
+            }
+            _ => panic!("expected a delivery"),
+        }
+        assert!(Pin::new(&mut consumer).poll_next(&mut cx).is_pending());
+    }
+
+    #[test]
+    fn basic_consume_poison_threshold_delegate() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        let consumer_tag = ShortString::from("consumer-tag");
+        let consumer = Consumer::new(consumer_tag.clone(), DefaultExecutor::default().unwrap());
+        let (delivered_sender, delivered) = flume::unbounded();
+        let (poisoned_sender, poisoned) = flume::unbounded();
+        consumer.set_delegate(move |delivery: DeliveryResult| {
+            let delivered_sender = delivered_sender.clone();
+            async move {
+                if let Ok(Some((_, delivery))) = delivery {
+                    let _ = delivered_sender.send(delivery.delivery_tag);
+                }
+            }
+        });
+        consumer.set_poison_threshold(
+            3,
+            PoisonAction::Callback(Arc::new(move |delivery: &Delivery| {
+                let _ = poisoned_sender.send(delivery.delivery_tag);
+            })),
+        );
+        let (conn, channel) = channel_with_consumer(&consumer);
+
+        deliver_with_delivery_count(&conn, &channel, &consumer_tag, 1, 3);
+        deliver_with_delivery_count(&conn, &channel, &consumer_tag, 2, 4);
+
+        let timeout = Duration::from_secs(5);
+        assert_eq!(delivered.recv_timeout(timeout), Ok(1));
+        assert_eq!(poisoned.recv_timeout(timeout), Ok(2));
+        assert!(delivered.recv_timeout(Duration::from_millis(100)).is_err());
+    }
+
+    #[test]
+    fn statistics_interval_consumer_error() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        use crate::options::BasicConsumeOptions;
+        use futures_lite::stream::StreamExt;
+
+        let (conn, channel, frames) = connected_channel();
+        let task = {
+            let channel = channel.clone();
+            async_global_executor::spawn(async move {
+                channel
+                    .basic_consume(
+                        "queue",
+                        "consumer",
+                        BasicConsumeOptions::default(),
+                        FieldTable::default(),
+                    )
+                    .await
+            })
+        };
+        answer_next_frame(
+            &conn,
+            &frames,
+            AMQPFrame::Method(
+                channel.id(),
+                AMQPClass::Basic(basic::AMQPMethod::ConsumeOk(basic::ConsumeOk {
+                    consumer_tag: "consumer".into(),
+                })),
+            ),
+        );
+        let consumer = async_global_executor::block_on(task).unwrap();
+        let mut samples = Box::pin(consumer.statistics_interval(Duration::from_millis(10)));
+        assert!(async_global_executor::block_on(samples.next()).is_some());
+
+        // The connection fails, the consumer fails with it
+        conn.channels()
+            .set_connection_error(Error::from(std::io::Error::from(
+                std::io::ErrorKind::ConnectionReset,
+            )));
+        drop(consumer);
+        assert_eq!(async_global_executor::block_on(samples.next()), None);
+    }
+
+    #[test]
+    fn consumer_tag_assigned() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        use crate::options::BasicConsumeOptions;
+
+        let (conn, channel, frames) = connected_channel();
+        let consume = |consumer_tag: &'static str| {
+            let channel = channel.clone();
+            async_global_executor::spawn(async move {
+                channel
+                    .basic_consume(
+                        "queue",
+                        consumer_tag,
+                        BasicConsumeOptions::default(),
+                        FieldTable::default(),
+                    )
+                    .await
+            })
+        };
+        let consume_ok = |consumer_tag: &str| {
+            AMQPFrame::Method(
+                channel.id(),
+                AMQPClass::Basic(basic::AMQPMethod::ConsumeOk(basic::ConsumeOk {
+                    consumer_tag: consumer_tag.into(),
+                })),
+            )
+        };
+        let assigned = Arc::new(Mutex::new(Vec::new()));
+        let record = |consumer: &Consumer| {
+            let assigned = assigned.clone();
+            consumer.on_tag_assigned(move |tag| assigned.lock().push(tag));
+        };
+
+        // The honored tags aren't reported, even when resubscribing
+        let task = consume("honored");
+        answer_next_frame(&conn, &frames, consume_ok("honored"));
+        let consumer = async_global_executor::block_on(task).unwrap();
+        record(&consumer);
+        assert_eq!(consumer.tag().as_str(), "honored");
+        let task = async_global_executor::spawn(consumer.resubscribe(channel.clone()));
+        answer_next_frame(&conn, &frames, consume_ok("honored"));
+        assert!(async_global_executor::block_on(task).is_ok());
+        assert!(assigned.lock().is_empty());
+
+        // The tag generated for an empty one gets reported once confirmed
+        let task = consume("");
+        let generated = loop {
+            if let Some((frame, resolver)) = frames.pop(true) {
+                if let Some(resolver) = resolver {
+                    resolver.swear(Ok(()));
+                }
+                match frame {
+                    AMQPFrame::Method(_, AMQPClass::Basic(basic::AMQPMethod::Consume(consume))) => {
+                        break consume.consumer_tag
+                    }
+                    frame => panic!("unexpected frame: {:?}", frame),
+                }
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        };
+        assert!(!generated.as_str().is_empty());
+        conn.channels()
+            .handle_frame(consume_ok(generated.as_str()))
+            .unwrap();
+        let consumer = async_global_executor::block_on(task).unwrap();
+        record(&consumer);
+        assert_eq!(*assigned.lock(), vec![generated]);
+
+        assigned.lock().clear();
+        let task = consume("requested");
+        answer_next_frame(&conn, &frames, consume_ok("amq.ctag-assigned"));
+        let consumer = async_global_executor::block_on(task).unwrap();
+        assert_eq!(consumer.tag().as_str(), "amq.ctag-assigned");
+        record(&consumer);
+        record(&consumer);
+        assert_eq!(
+            *assigned.lock(),
+            vec![ShortString::from("amq.ctag-assigned")]
+        );
+    }
+
+    #[test]
+    fn resubscribe_without_subscription() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        let (_conn, channel, frames) = connected_channel();
+        let consumer = Consumer::new("manual".into(), DefaultExecutor::default().unwrap());
+        assert!(matches!(
+            async_global_executor::block_on(consumer.resubscribe(channel)),
+            Err(Error::InvalidArgument(_))
+        ));
+        assert!(frames.pop(true).is_none());
+    }
+
+    #[test]
+    fn consumer_backlog_shedding() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        use crate::queue::{Queue, QueueState};
+        use crate::{BacklogEvent, BacklogPolicy, Shedding};
+        use amq_protocol::protocol::channel;
+
+        let (conn, channel, frames) = connected_channel();
+        let consumer_tag = ShortString::from("stalled");
+        let mut consumer = Consumer::new(consumer_tag.clone(), DefaultExecutor::default().unwrap());
+        let mut queue: QueueState = Queue::new("consumed".into(), 0, 0).into();
+        queue.register_consumer(consumer.tag(), consumer.clone());
+        conn.channels()
+            .get(channel.id())
+            .map(|c| c.register_queue(queue));
+        let events = Arc::new(Mutex::new(Vec::new()));
+        {
+            let events = events.clone();
+            consumer.on_backlog(
+                BacklogPolicy::new(2, Duration::from_millis(50))
+                    .with_low_watermark(0)
+                    .with_shedding(Shedding::ChannelFlow),
+                move |event| events.lock().push(event),
+            );
+        }
+        let flow_ok = |active| {
+            AMQPFrame::Method(
+                channel.id(),
+                AMQPClass::Channel(channel::AMQPMethod::FlowOk(channel::FlowOk { active })),
+            )
+        };
+
+        // Nobody reads the deliveries
+        for _ in 0..3 {
+            for frame in deliver_frames(&channel, &consumer_tag) {
+                conn.channels().handle_frame(frame).unwrap();
+            }
+        }
+        match answer_next_frame(&conn, &frames, flow_ok(false)) {
+            AMQPFrame::Method(_, AMQPClass::Channel(channel::AMQPMethod::Flow(flow))) => {
+                assert!(!flow.active)
+            }
+            frame => panic!("unexpected frame: {:?}", frame),
+        }
+        assert!(matches!(
+            events.lock().as_slice(),
+            [BacklogEvent::Lagging { buffered: 3, .. }]
+        ));
+
+        // The deliveries get read, the channel gets resumed
+        let waker = waker_fn(|| {});
+        let mut cx = Context::from_waker(&waker);
+        for _ in 0..3 {
+            assert!(matches!(
+                Pin::new(&mut consumer).poll_next(&mut cx),
+                Poll::Ready(Some(Ok(_)))
+            ));
+        }
+        match answer_next_frame(&conn, &frames, flow_ok(true)) {
+            AMQPFrame::Method(_, AMQPClass::Channel(channel::AMQPMethod::Flow(flow))) => {
+                assert!(flow.active)
+            }
+            frame => panic!("unexpected frame: {:?}", frame),
+        }
+        assert!(matches!(
+            events.lock().as_slice(),
+            [
+                BacklogEvent::Lagging { .. },
+                BacklogEvent::Drained { buffered: 0 }
+            ]
+        ));
+    }
+
+    #[test]
+    fn consumer_resubscribe() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        use crate::{options::BasicConsumeOptions, QosScope};
+
+        let (conn, channel, frames) = connected_channel();
+        let qos_ok = |channel: &Channel| {
+            AMQPFrame::Method(
+                channel.id(),
+                AMQPClass::Basic(basic::AMQPMethod::QosOk(basic::QosOk {})),
+            )
+        };
+        let consume_ok = |channel: &Channel| {
+            AMQPFrame::Method(
+                channel.id(),
+                AMQPClass::Basic(basic::AMQPMethod::ConsumeOk(basic::ConsumeOk {
+                    consumer_tag: "resubscribed".into(),
+                })),
+            )
+        };
+
+        let task = {
+            let channel = channel.clone();
+            async_global_executor::spawn(async move {
+                channel.basic_qos(10, QosScope::PerChannel).await
+            })
+        };
+        answer_next_frame(&conn, &frames, qos_ok(&channel));
+        assert!(async_global_executor::block_on(task).is_ok());
+        let task = {
+            let channel = channel.clone();
+            async_global_executor::spawn(async move {
+                channel
+                    .basic_consume(
+                        "consumed",
+                        "resubscribed",
+                        BasicConsumeOptions::default(),
+                        FieldTable::default(),
+                    )
+                    .await
+            })
+        };
+        answer_next_frame(&conn, &frames, consume_ok(&channel));
+        let mut consumer = async_global_executor::block_on(task).unwrap();
+
+        // The connection got lost, consume again on a new channel
+        consumer.set_error(Error::InvalidConnectionState(ConnectionState::Error));
+        let new_channel = conn.channels().create(conn.closer()).unwrap();
+        new_channel.set_state(ChannelState::Connected);
+        let task = async_global_executor::spawn(consumer.resubscribe(new_channel.clone()));
+        assert_eq!(
+            answer_next_frame(&conn, &frames, qos_ok(&new_channel)),
+            AMQPFrame::Method(
+                new_channel.id(),
+                AMQPClass::Basic(basic::AMQPMethod::Qos(basic::Qos {
+                    prefetch_count: 10,
+                    global: false,
+                }))
+            )
+        );
+        match answer_next_frame(&conn, &frames, consume_ok(&new_channel)) {
+            AMQPFrame::Method(
+                channel_id,
+                AMQPClass::Basic(basic::AMQPMethod::Consume(basic::Consume {
+                    queue,
+                    consumer_tag,
+                    ..
+                })),
+            ) => {
+                assert_eq!(channel_id, new_channel.id());
+                assert_eq!(queue.as_str(), "consumed");
+                assert_eq!(consumer_tag.as_str(), "resubscribed");
+            }
+            frame => panic!("unexpected frame: {:?}", frame),
+        }
+        assert!(async_global_executor::block_on(task).is_ok());
+
+        for frame in deliver_frames(&new_channel, &consumer.tag()) {
+            conn.channels().handle_frame(frame).unwrap();
+        }
+        let waker = waker_fn(|| {});
+        let mut cx = Context::from_waker(&waker);
+        match Pin::new(&mut consumer).poll_next(&mut cx) {
+            Poll::Ready(Some(Ok((channel, delivery)))) => {
+                assert_eq!(channel.id(), new_channel.id());
+                assert_eq!(delivery.data(), b"hello");
+            }
+            _ => panic!("expected a delivery"),
+        }
+    }
+
+    #[test]
+    fn delegate_uses_channel() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        use crate::options::BasicConsumeOptions;
+
+        let (conn, channel, frames) = connected_channel();
+        let task = {
+            let channel = channel.clone();
+            async_global_executor::spawn(async move {
+                channel
+                    .basic_consume(
+                        "consumed",
+                        "delegated",
+                        BasicConsumeOptions::default(),
+                        FieldTable::default(),
+                    )
+                    .await
+            })
+        };
+        answer_next_frame(
+            &conn,
+            &frames,
+            AMQPFrame::Method(
+                channel.id(),
+                AMQPClass::Basic(basic::AMQPMethod::ConsumeOk(basic::ConsumeOk {
+                    consumer_tag: "delegated".into(),
+                })),
+            ),
+        );
+        let consumer = async_global_executor::block_on(task).unwrap();
+        let (sender, receiver) = flume::unbounded();
+        {
+            let channel = channel.clone();
+            let delegated = consumer.clone();
+            consumer.set_delegate(move |_delivery: DeliveryResult| {
+                // Called while the delivery gets dispatched, which used to hold the locks of the
+                // consumer and of the queues of the channel
+                let tags = channel
+                    .consumers()
+                    .iter()
+                    .map(Consumer::tag)
+                    .collect::<Vec<_>>();
+                let _ = sender.send((delegated.tag(), tags));
+                async {}
+            });
+        }
+
+        for frame in deliver_frames(&channel, &consumer.tag()) {
+            conn.channels().handle_frame(frame).unwrap();
+        }
+        assert_eq!(
+            receiver.recv_timeout(Duration::from_secs(5)),
+            Ok((
+                ShortString::from("delegated"),
+                vec![ShortString::from("delegated")]
+            ))
+        );
+    }
+
+    #[test]
+    fn consumer_reattach() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        use crate::{options::BasicConsumeOptions, ConsumerSpec};
+        use futures_lite::stream::StreamExt;
+
+        let (conn, channel, frames) = connected_channel();
+        let consume_ok = |channel: &Channel| {
+            AMQPFrame::Method(
+                channel.id(),
+                AMQPClass::Basic(basic::AMQPMethod::ConsumeOk(basic::ConsumeOk {
+                    consumer_tag: "reattached".into(),
+                })),
+            )
+        };
+        let task = {
+            let channel = channel.clone();
+            async_global_executor::spawn(async move {
+                channel
+                    .basic_consume(
+                        "consumed",
+                        "reattached",
+                        BasicConsumeOptions::default(),
+                        FieldTable::default(),
+                    )
+                    .await
+            })
+        };
+        answer_next_frame(&conn, &frames, consume_ok(&channel));
+        let mut consumer = async_global_executor::block_on(task).unwrap();
+        for frame in deliver_frames(&channel, &consumer.tag()) {
+            conn.channels().handle_frame(frame).unwrap();
+        }
+        let (delivered_on, delivery) = async_global_executor::block_on(consumer.next())
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            (delivered_on.id(), delivery.delivery_tag),
+            (channel.id(), 1)
+        );
+        // The server still delivers to it on this channel
+        assert!(consumer.detach().is_err());
+
+        // A content header without a method kills the channel
+        let header_frame = AMQPFrame::Header(
+            channel.id(),
+            60,
+            Box::new(AMQPContentHeader {
+                class_id: 60,
+                weight: 1,
+                body_size: 0,
+                properties: BasicProperties::default(),
+            }),
+        );
+        assert!(conn.channels().handle_frame(header_frame).is_err());
+        assert!(async_global_executor::block_on(consumer.next())
+            .unwrap()
+            .is_err());
+
+        let spec = consumer.detach().unwrap();
+        assert_eq!(
+            spec,
+            ConsumerSpec {
+                queue: "consumed".into(),
+                consumer_tag: "reattached".into(),
+                options: BasicConsumeOptions::default(),
+                arguments: FieldTable::default(),
+            }
+        );
+        let new_channel = conn.channels().create(conn.closer()).unwrap();
+        new_channel.set_state(ChannelState::Connected);
+        let task = {
+            let new_channel = new_channel.clone();
+            let consumer = consumer.clone();
+            async_global_executor::spawn(async move {
+                new_channel.reattach_consumer(spec, &consumer).await
+            })
+        };
+        match answer_next_frame(&conn, &frames, consume_ok(&new_channel)) {
+            AMQPFrame::Method(
+                channel_id,
+                AMQPClass::Basic(basic::AMQPMethod::Consume(basic::Consume {
+                    queue,
+                    consumer_tag,
+                    ..
+                })),
+            ) => {
+                assert_eq!(channel_id, new_channel.id());
+                assert_eq!(queue.as_str(), "consumed");
+                assert_eq!(consumer_tag.as_str(), "reattached");
+            }
+            frame => panic!("unexpected frame: {:?}", frame),
+        }
+        assert!(async_global_executor::block_on(task).is_ok());
+
+        // The delivery tags restart on the new channel, the same stream yields the deliveries
+        for frame in deliver_frames(&new_channel, &consumer.tag()) {
+            conn.channels().handle_frame(frame).unwrap();
+        }
+        let (delivered_on, delivery) = async_global_executor::block_on(consumer.next())
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            (delivered_on.id(), delivery.delivery_tag),
+            (new_channel.id(), 1)
+        );
+        assert_eq!(delivery.data(), b"hello");
+    }
+
+    fn settle_with_typed_delegate<D: crate::TypedConsumerDelegate + 'static>(
+        delegate: D,
+    ) -> AMQPClass {
+        use crate::queue::{Queue, QueueState};
+
+        let consumer_tag = ShortString::from("consumer-tag");
+        let consumer = Consumer::new(consumer_tag.clone(), DefaultExecutor::default().unwrap());
+        consumer.set_typed_delegate(delegate);
+        let (conn, channel, frames) = connected_channel();
+        let mut queue: QueueState = Queue::new("consumed".into(), 0, 0).into();
+        queue.register_consumer(consumer_tag.clone(), consumer);
+        channel.register_queue(queue);
+
+        deliver_with_delivery_count(&conn, &channel, &consumer_tag, 42, 0);
+        loop {
+            if let Some((frame, resolver)) = frames.pop(true) {
+                if let Some(resolver) = resolver {
+                    resolver.swear(Ok(()));
+                }
+                match frame {
+                    AMQPFrame::Method(_, method) => return method,
+                    frame => panic!("unexpected frame: {:?}", frame),
+                }
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    fn settle_with_result(
+        result: std::result::Result<crate::AckKind, crate::NackKind>,
+    ) -> AMQPClass {
+        settle_with_typed_delegate(move |_: Delivery| async move { result })
+    }
+
+    #[test]
+    fn typed_delegate_settlement() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        use crate::{AckKind, NackKind};
+
+        assert_eq!(
+            settle_with_result(Ok(AckKind::Ack)),
+            AMQPClass::Basic(basic::AMQPMethod::Ack(basic::Ack {
+                delivery_tag: 42,
+                multiple: false,
+            }))
+        );
+        assert_eq!(
+            settle_with_result(Ok(AckKind::AckMultiple)),
+            AMQPClass::Basic(basic::AMQPMethod::Ack(basic::Ack {
+                delivery_tag: 42,
+                multiple: true,
+            }))
+        );
+        assert_eq!(
+            settle_with_result(Err(NackKind::Nack { requeue: false })),
+            AMQPClass::Basic(basic::AMQPMethod::Nack(basic::Nack {
+                delivery_tag: 42,
+                multiple: false,
+                requeue: false,
+            }))
+        );
+        assert_eq!(
+            settle_with_result(Err(NackKind::Reject { requeue: true })),
+            AMQPClass::Basic(basic::AMQPMethod::Reject(basic::Reject {
+                delivery_tag: 42,
+                requeue: true,
+            }))
+        );
+    }
+
+    #[test]
+    fn typed_delegate_panic() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        assert_eq!(
+            settle_with_typed_delegate(|delivery: Delivery| async move {
+                if delivery.delivery_tag == 42 {
+                    panic!("failed to handle delivery");
+                }
+                Ok(crate::AckKind::Ack)
+            }),
+            AMQPClass::Basic(basic::AMQPMethod::Nack(basic::Nack {
+                delivery_tag: 42,
+                multiple: false,
+                requeue: true,
+            }))
+        );
+    }
+
+    #[test]
+    fn consumer_cancellation_token() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        let token = CancellationToken::new();
+        let mut consumer =
+            Consumer::new("consumer-tag".into(), DefaultExecutor::default().unwrap());
+        consumer.set_cancellation_token(token.clone());
+        let waker = waker_fn(|| {});
+        let mut cx = Context::from_waker(&waker);
+        assert!(Pin::new(&mut consumer).poll_next(&mut cx).is_pending());
+
+        token.cancel();
+        assert!(matches!(
+            Pin::new(&mut consumer).poll_next(&mut cx),
+            Poll::Ready(None)
+        ));
+
+        // A long-lived token doesn't keep the consumers it got set on alive
+        let token = CancellationToken::new();
+        let consumer = Consumer::new("dropped".into(), DefaultExecutor::default().unwrap());
+        consumer.set_cancellation_token(token.clone());
+        let inner = Arc::downgrade(&consumer.inner);
+        drop(consumer);
+        assert!(inner.upgrade().is_none());
+        token.cancel();
+    }
 }
@@ -0,0 +1,359 @@
+use crate::{
+    message::Delivery,
+    options::{BasicCancelOptions, BasicConsumeOptions, BasicNackOptions},
+    tracing_integration::targets,
+    types::FieldTable,
+    Channel, Consumer, Result,
+};
+use futures_lite::{Stream, StreamExt};
+use parking_lot::Mutex;
+use std::{
+    fmt,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll, Waker},
+};
+use tracing::{trace, warn};
+
+/// Consumes from a changing set of queues, merging their deliveries into a single stream.
+///
+/// One [`Consumer`] is started per queue with [`add_queue`], and canceled with
+/// [`remove_queue`]. The stream yields the name of the queue along with each delivery, taking
+/// turns between the queues which have deliveries pending, so that a busy queue doesn't starve
+/// the others.
+///
+/// The stream never ends on its own, even when no queue is left: clone the group to add and
+/// remove queues from another task while this one consumes the stream. When a consumer fails,
+/// for example because the connection dropped, its error is yielded and the queue stays in the
+/// group: call [`recover`] with a new channel to consume from all the queues again.
+///
+/// [`Consumer`]: ./struct.Consumer.html
+/// [`add_queue`]: #method.add_queue
+/// [`remove_queue`]: #method.remove_queue
+/// [`recover`]: #method.recover
+#[derive(Clone)]
+pub struct ConsumerGroup {
+    inner: Arc<Mutex<Inner>>,
+    options: BasicConsumeOptions,
+}
+
+struct Inner {
+    channel: Channel,
+    members: Vec<Member>,
+    /// Where the next poll starts, right after the member which yielded last.
+    next: usize,
+    task: Option<Waker>,
+}
+
+struct Member {
+    queue: String,
+    consumer: Consumer,
+    /// Whether the consumer yielded the end of its stream, until it gets resubscribed.
+    ended: bool,
+}
+
+impl ConsumerGroup {
+    /// Create an empty group consuming on `channel` with `options`.
+    pub fn new(channel: Channel, options: BasicConsumeOptions) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                channel,
+                members: Vec::new(),
+                next: 0,
+                task: None,
+            })),
+            options,
+        }
+    }
+
+    /// The channel the consumers currently use.
+    pub fn channel(&self) -> Channel {
+        self.inner.lock().channel.clone()
+    }
+
+    /// The queues currently consumed, in the order they were added.
+    pub fn queues(&self) -> Vec<String> {
+        self.inner
+            .lock()
+            .members
+            .iter()
+            .map(|member| member.queue.clone())
+            .collect()
+    }
+
+    /// Start consuming from `queue`, unless it's already part of the group.
+    pub async fn add_queue(&self, queue: &str) -> Result<()> {
+        if self.position(queue).is_some() {
+            return Ok(());
+        }
+        let consumer = self
+            .channel()
+            .basic_consume(queue, "", self.options, FieldTable::default())
+            .await?;
+        trace!(
+            target: targets::CONSUMER,
+            "consumer group added queue {}; consumer_tag={}",
+            queue,
+            consumer.tag()
+        );
+        let mut inner = self.inner.lock();
+        inner.members.push(Member {
+            queue: queue.into(),
+            consumer,
+            ended: false,
+        });
+        inner.wake();
+        Ok(())
+    }
+
+    /// Stop consuming from `queue`.
+    ///
+    /// The consumer is canceled on the server and removed from the stream right away. The
+    /// deliveries it had received but the stream didn't yield yet are rejected and requeued,
+    /// unless consuming with `no_ack`.
+    pub async fn remove_queue(&self, queue: &str) -> Result<()> {
+        let (channel, mut consumer) = {
+            let mut inner = self.inner.lock();
+            let position = match inner.position(queue) {
+                Some(position) => position,
+                None => return Ok(()),
+            };
+            let member = inner.members.remove(position);
+            if inner.next > position {
+                inner.next -= 1;
+            }
+            (inner.channel.clone(), member.consumer)
+        };
+        trace!(
+            target: targets::CONSUMER,
+            "consumer group removing queue {}; consumer_tag={}",
+            queue,
+            consumer.tag()
+        );
+        channel
+            .basic_cancel(consumer.tag().as_str(), BasicCancelOptions::default())
+            .await?;
+        // The consumer got canceled locally once the server acknowledged it, this ends once
+        // we went through what it received before that
+        while let Some(delivery) = consumer.next().await {
+            let (channel, delivery) = delivery?;
+            if !self.options.no_ack {
+                channel
+                    .basic_nack(
+                        delivery.delivery_tag,
+                        BasicNackOptions::default().requeue(true),
+                    )
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Consume again from all the queues of the group on `channel`, for example after the
+    /// connection was lost and a new one got opened, see [`Consumer::resubscribe`].
+    ///
+    /// [`Consumer::resubscribe`]: ./struct.Consumer.html#method.resubscribe
+    pub async fn recover(&self, channel: Channel) -> Result<()> {
+        let consumers = {
+            let mut inner = self.inner.lock();
+            inner.channel = channel.clone();
+            inner
+                .members
+                .iter()
+                .map(|member| member.consumer.clone())
+                .collect::<Vec<_>>()
+        };
+        for consumer in consumers {
+            consumer.resubscribe(channel.clone()).await?;
+            let mut inner = self.inner.lock();
+            if let Some(member) = inner
+                .members
+                .iter_mut()
+                .find(|member| member.consumer.tag() == consumer.tag())
+            {
+                member.ended = false;
+            }
+            inner.wake();
+        }
+        Ok(())
+    }
+
+    fn position(&self, queue: &str) -> Option<usize> {
+        self.inner.lock().position(queue)
+    }
+}
+
+impl Inner {
+    fn position(&self, queue: &str) -> Option<usize> {
+        self.members.iter().position(|member| member.queue == queue)
+    }
+
+    fn wake(&mut self) {
+        if let Some(task) = self.task.take() {
+            task.wake();
+        }
+    }
+}
+
+impl Stream for ConsumerGroup {
+    type Item = Result<(String, Delivery)>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut inner = self.inner.lock();
+        inner.task = Some(cx.waker().clone());
+        let count = inner.members.len();
+        for offset in 0..count {
+            let index = (inner.next + offset) % count;
+            let member = &mut inner.members[index];
+            if member.ended {
+                continue;
+            }
+            match Pin::new(&mut member.consumer).poll_next(cx) {
+                Poll::Ready(Some(delivery)) => {
+                    let queue = member.queue.clone();
+                    inner.next = index + 1;
+                    return Poll::Ready(Some(delivery.map(|(_, delivery)| (queue, delivery))));
+                }
+                Poll::Ready(None) => {
+                    warn!(
+                        target: targets::CONSUMER,
+                        "consumer group stopped consuming from queue {}",
+                        member.queue
+                    );
+                    member.ended = true;
+                }
+                Poll::Pending => {}
+            }
+        }
+        Poll::Pending
+    }
+}
+
+impl fmt::Debug for ConsumerGroup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("ConsumerGroup");
+        if let Some(inner) = self.inner.try_lock() {
+            debug
+                .field("channel", &inner.channel.id())
+                .field("queues", &inner.members.len());
+        }
+        debug.field("options", &self.options).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_helpers::*;
+
+    #[test]
+    fn consumer_group() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        use crate::{options::BasicConsumeOptions, test_support::FrameCapture, ConsumerGroup};
+        use amq_protocol::protocol::basic;
+        use futures_lite::stream::StreamExt;
+
+        let (conn, channel, frames) = connected_channel();
+        let mut group = ConsumerGroup::new(channel.clone(), BasicConsumeOptions::default());
+        let add_queue = |queue: &'static str| {
+            let group = group.clone();
+            let task = async_global_executor::spawn(async move { group.add_queue(queue).await });
+            let consume = loop {
+                if let Some((frame, resolver)) = frames.pop(true) {
+                    if let Some(resolver) = resolver {
+                        resolver.swear(Ok(()));
+                    }
+                    break frame;
+                }
+                std::thread::sleep(Duration::from_millis(1));
+            };
+            let consumer_tag = match consume {
+                AMQPFrame::Method(_, AMQPClass::Basic(basic::AMQPMethod::Consume(consume))) => {
+                    consume.consumer_tag
+                }
+                frame => panic!("expected a basic.consume, got {:?}", frame),
+            };
+            conn.channels()
+                .handle_frame(AMQPFrame::Method(
+                    channel.id(),
+                    AMQPClass::Basic(basic::AMQPMethod::ConsumeOk(basic::ConsumeOk {
+                        consumer_tag: consumer_tag.clone(),
+                    })),
+                ))
+                .unwrap();
+            async_global_executor::block_on(task).unwrap();
+            consumer_tag
+        };
+        let tenant_a = add_queue("tenant-a");
+        let tenant_b = add_queue("tenant-b");
+        assert_eq!(group.queues(), vec!["tenant-a", "tenant-b"]);
+
+        let deliver = |consumer_tag: &ShortString, delivery_tag| {
+            for frame in content_frames(
+                &channel,
+                basic::AMQPMethod::Deliver(basic::Deliver {
+                    consumer_tag: consumer_tag.clone(),
+                    delivery_tag,
+                    redelivered: false,
+                    exchange: "".into(),
+                    routing_key: "tenant".into(),
+                }),
+                BasicProperties::default(),
+                &[b"task"],
+            ) {
+                conn.channels().handle_frame(frame).unwrap();
+            }
+        };
+        // All the deliveries of the first queue arrive before the ones of the second
+        deliver(&tenant_a, 1);
+        deliver(&tenant_a, 2);
+        deliver(&tenant_b, 3);
+        deliver(&tenant_b, 4);
+        let received = (0..4)
+            .map(|_| {
+                let (queue, delivery) = async_global_executor::block_on(group.next())
+                    .unwrap()
+                    .unwrap();
+                (queue, delivery.delivery_tag)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            received,
+            vec![
+                ("tenant-a".to_string(), 1),
+                ("tenant-b".to_string(), 3),
+                ("tenant-a".to_string(), 2),
+                ("tenant-b".to_string(), 4),
+            ]
+        );
+
+        // This one is still pending when the queue gets removed
+        deliver(&tenant_b, 5);
+        let capture = FrameCapture::install(&channel);
+        let task = {
+            let group = group.clone();
+            async_global_executor::spawn(async move { group.remove_queue("tenant-b").await })
+        };
+        answer_next_frame(
+            &conn,
+            &frames,
+            AMQPFrame::Method(
+                channel.id(),
+                AMQPClass::Basic(basic::AMQPMethod::CancelOk(basic::CancelOk {
+                    consumer_tag: tenant_b,
+                })),
+            ),
+        );
+        write_frames(&frames, 1);
+        async_global_executor::block_on(task).unwrap();
+        // basic.cancel, then basic.nack to requeue the pending delivery
+        capture.assert_methods(&[(60, 30), (60, 120)]);
+        assert_eq!(group.queues(), vec!["tenant-a"]);
+
+        deliver(&tenant_a, 6);
+        let (queue, delivery) = async_global_executor::block_on(group.next())
+            .unwrap()
+            .unwrap();
+        assert_eq!((queue.as_str(), delivery.delivery_tag), ("tenant-a", 6));
+    }
+}
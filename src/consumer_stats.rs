@@ -0,0 +1,207 @@
+use crate::acknowledgement::DeliveryTag;
+use parking_lot::Mutex;
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+
+/// A snapshot of the deliveries received by a consumer and of how they were settled.
+///
+/// Unlike [`ChannelStats`] which counts the methods sent, the settlement counters count
+/// deliveries: acking several deliveries at once with the `multiple` flag counts each of them.
+///
+/// [`ChannelStats`]: ./struct.ChannelStats.html
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ConsumerStats {
+    /// The number of deliveries received
+    pub delivered: u64,
+    /// The number of payload bytes received
+    pub bytes_received: u64,
+    /// The number of deliveries acknowledged
+    pub acked: u64,
+    /// The number of deliveries negatively acknowledged
+    pub nacked: u64,
+    /// The number of deliveries rejected
+    pub rejected: u64,
+    /// The number of deliveries the server flagged as redelivered
+    pub redelivered: u64,
+    /// How long the oldest delivery still waiting for its acknowledgement has been waiting
+    pub oldest_outstanding: Option<Duration>,
+}
+
+/// A [`ConsumerStats`] taken at regular intervals by [`Consumer::statistics_interval`].
+///
+/// [`ConsumerStats`]: ./struct.ConsumerStats.html
+/// [`Consumer::statistics_interval`]: ./struct.Consumer.html#method.statistics_interval
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ConsumerStatsSample {
+    /// When the sample was taken
+    pub timestamp: SystemTime,
+    /// The statistics of the consumer at that time
+    pub stats: ConsumerStats,
+    /// The number of deliveries received per second since the previous sample
+    pub rate_per_second: f64,
+}
+
+/// How a delivery got settled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Settlement {
+    Ack,
+    Nack,
+    Reject,
+}
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ConsumerStatsCounters(Arc<Counters>);
+
+#[derive(Debug, Default)]
+struct Counters {
+    delivered: AtomicU64,
+    bytes_received: AtomicU64,
+    acked: AtomicU64,
+    nacked: AtomicU64,
+    rejected: AtomicU64,
+    redelivered: AtomicU64,
+    /* When the deliveries waiting for their acknowledgement were received */
+    outstanding: Mutex<BTreeMap<DeliveryTag, Instant>>,
+}
+
+impl ConsumerStatsCounters {
+    pub(crate) fn delivered(&self, redelivered: bool) {
+        self.0.delivered.fetch_add(1, Ordering::Relaxed);
+        if redelivered {
+            self.0.redelivered.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn received(&self, bytes: usize) {
+        self.0
+            .bytes_received
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn outstanding(&self, delivery_tag: DeliveryTag) {
+        self.0
+            .outstanding
+            .lock()
+            .insert(delivery_tag, Instant::now());
+    }
+
+    pub(crate) fn settled(&self, delivery_tag: DeliveryTag, settlement: Settlement) {
+        let counter = match settlement {
+            Settlement::Ack => &self.0.acked,
+            Settlement::Nack => &self.0.nacked,
+            Settlement::Reject => &self.0.rejected,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        self.forget(delivery_tag);
+    }
+
+    pub(crate) fn forget(&self, delivery_tag: DeliveryTag) {
+        self.0.outstanding.lock().remove(&delivery_tag);
+    }
+
+    pub(crate) fn reset(&self) {
+        self.0.delivered.store(0, Ordering::Relaxed);
+        self.0.bytes_received.store(0, Ordering::Relaxed);
+        self.0.acked.store(0, Ordering::Relaxed);
+        self.0.nacked.store(0, Ordering::Relaxed);
+        self.0.rejected.store(0, Ordering::Relaxed);
+        self.0.redelivered.store(0, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> ConsumerStats {
+        ConsumerStats {
+            delivered: self.0.delivered.load(Ordering::Relaxed),
+            bytes_received: self.0.bytes_received.load(Ordering::Relaxed),
+            acked: self.0.acked.load(Ordering::Relaxed),
+            nacked: self.0.nacked.load(Ordering::Relaxed),
+            rejected: self.0.rejected.load(Ordering::Relaxed),
+            redelivered: self.0.redelivered.load(Ordering::Relaxed),
+            // Delivery tags increase on a channel, so the first one is the oldest
+            oldest_outstanding: self
+                .0
+                .outstanding
+                .lock()
+                .values()
+                .next()
+                .map(Instant::elapsed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_helpers::*;
+
+    #[test]
+    fn consumer_stats() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        use crate::{
+            options::{BasicAckOptions, BasicNackOptions},
+            ConsumerStats,
+        };
+
+        let consumer_tag = ShortString::from("consumer-tag");
+        let consumer = Consumer::new(consumer_tag.clone(), DefaultExecutor::default().unwrap());
+        let (conn, channel) = channel_with_consumer(&consumer);
+        for delivery_tag in 1..=10 {
+            let frames = content_frames(
+                &channel,
+                basic::AMQPMethod::Deliver(basic::Deliver {
+                    consumer_tag: consumer_tag.clone(),
+                    delivery_tag,
+                    redelivered: delivery_tag <= 3,
+                    exchange: "".into(),
+                    routing_key: "consumed".into(),
+                }),
+                BasicProperties::default(),
+                &[b"hel", b"lo"],
+            );
+            for frame in frames {
+                conn.channels().handle_frame(frame).unwrap();
+            }
+        }
+
+        // The stats are updated as soon as the methods are queued, don't wait for them to be sent
+        let _ = futures_lite::future::block_on(futures_lite::future::poll_once(
+            channel.basic_ack(7, BasicAckOptions::default().multiple(true)),
+        ));
+        for delivery_tag in 8..=9 {
+            let _ = futures_lite::future::block_on(futures_lite::future::poll_once(
+                channel.basic_nack(delivery_tag, BasicNackOptions::default()),
+            ));
+        }
+
+        let stats = consumer.stats();
+        assert!(stats.oldest_outstanding.is_some());
+        assert_eq!(
+            stats,
+            ConsumerStats {
+                delivered: 10,
+                bytes_received: 50,
+                acked: 7,
+                nacked: 2,
+                rejected: 0,
+                redelivered: 3,
+                oldest_outstanding: stats.oldest_outstanding,
+            }
+        );
+
+        consumer.reset_stats();
+        let stats = consumer.stats();
+        assert!(stats.oldest_outstanding.is_some());
+        assert_eq!(
+            stats,
+            ConsumerStats {
+                oldest_outstanding: stats.oldest_outstanding,
+                ..ConsumerStats::default()
+            }
+        );
+    }
+}
@@ -0,0 +1,270 @@
+use crate::{
+    options::{ExchangeDeclareOptions, QueueDeclareOptions},
+    queue::Queue,
+    tracing_integration::targets,
+    types::{FieldTable, ShortString},
+};
+use parking_lot::Mutex;
+use std::{collections::HashMap, fmt, sync::Arc};
+use tracing::trace;
+
+/// Remembers the entities successfully declared on a connection to skip redeclaring them.
+///
+/// Server-named, auto-delete and passive declarations are never cached as the server may have
+/// changed them behind our back.
+///
+/// It also keeps track of the audit queues of the exchanges declared through
+/// `Channel::declare_with_alternate`, whether caching is enabled or not.
+#[derive(Clone, Default)]
+pub(crate) struct DeclarationCache(Arc<Mutex<Inner>>);
+
+#[derive(Default)]
+struct Inner {
+    enabled: bool,
+    exchanges: HashMap<ShortString, (ShortString, ExchangeDeclareOptions, FieldTable)>,
+    queues: HashMap<ShortString, (QueueDeclareOptions, FieldTable)>,
+    bindings: HashMap<(ShortString, ShortString, ShortString), Vec<FieldTable>>,
+    audit_queues: HashMap<ShortString, ShortString>,
+}
+
+impl DeclarationCache {
+    pub(crate) fn enable(&self) {
+        self.0.lock().enabled = true;
+    }
+
+    pub(crate) fn clear(&self) {
+        let mut inner = self.0.lock();
+        inner.exchanges.clear();
+        inner.queues.clear();
+        inner.bindings.clear();
+    }
+
+    pub(crate) fn has_exchange(
+        &self,
+        exchange: &str,
+        kind: &str,
+        options: &ExchangeDeclareOptions,
+        arguments: &FieldTable,
+    ) -> bool {
+        let inner = self.0.lock();
+        inner.enabled
+            && inner.exchanges.get(exchange).map_or(
+                false,
+                |(cached_kind, cached_options, cached_arguments)| {
+                    cached_kind.as_str() == kind
+                        && cached_options == options
+                        && cached_arguments == arguments
+                },
+            )
+    }
+
+    pub(crate) fn register_exchange(
+        &self,
+        exchange: &str,
+        kind: &str,
+        options: ExchangeDeclareOptions,
+        arguments: FieldTable,
+    ) {
+        let mut inner = self.0.lock();
+        if inner.enabled && !exchange.is_empty() && !options.passive && !options.auto_delete {
+            trace!(target: targets::CHANNEL, "caching exchange declaration; exchange={}", exchange);
+            inner
+                .exchanges
+                .insert(exchange.into(), (kind.into(), options, arguments));
+        }
+    }
+
+    pub(crate) fn forget_exchange(&self, exchange: &str) {
+        let mut inner = self.0.lock();
+        inner.exchanges.remove(exchange);
+        inner
+            .bindings
+            .retain(|(_, cached_exchange, _), _| cached_exchange.as_str() != exchange);
+    }
+
+    /// The counts of the queues answered from the cache are unknown and reported as 0, as the
+    /// ones we got when declaring them are long stale.
+    pub(crate) fn get_queue(
+        &self,
+        queue: &str,
+        options: &QueueDeclareOptions,
+        arguments: &FieldTable,
+    ) -> Option<Queue> {
+        let inner = self.0.lock();
+        if !inner.enabled {
+            return None;
+        }
+        inner
+            .queues
+            .get_key_value(queue)
+            .filter(|(_, (cached_options, cached_arguments))| {
+                cached_options == options && cached_arguments == arguments
+            })
+            .map(|(queue, _)| Queue::new(queue.clone(), 0, 0))
+    }
+
+    pub(crate) fn register_queue(
+        &self,
+        queue: &Queue,
+        options: QueueDeclareOptions,
+        arguments: FieldTable,
+    ) {
+        let mut inner = self.0.lock();
+        if inner.enabled
+            && !queue.name().as_str().is_empty()
+            && !options.passive
+            && !options.auto_delete
+        {
+            trace!(target: targets::CHANNEL, "caching queue declaration; queue={}", queue.name());
+            inner
+                .queues
+                .insert(queue.name().clone(), (options, arguments));
+        }
+    }
+
+    pub(crate) fn forget_queue(&self, queue: &str) {
+        let mut inner = self.0.lock();
+        inner.queues.remove(queue);
+        inner
+            .bindings
+            .retain(|(cached_queue, ..), _| cached_queue.as_str() != queue);
+    }
+
+    pub(crate) fn has_binding(
+        &self,
+        queue: &str,
+        exchange: &str,
+        routing_key: &str,
+        arguments: &FieldTable,
+    ) -> bool {
+        let inner = self.0.lock();
+        inner.enabled
+            && inner
+                .bindings
+                .get(&(queue.into(), exchange.into(), routing_key.into()))
+                .map_or(false, |cached| cached.contains(arguments))
+    }
+
+    pub(crate) fn register_binding(
+        &self,
+        queue: &str,
+        exchange: &str,
+        routing_key: &str,
+        arguments: FieldTable,
+    ) {
+        let mut inner = self.0.lock();
+        if inner.enabled && !queue.is_empty() {
+            trace!(
+                target: targets::CHANNEL,
+                "caching queue binding; queue={}, exchange={}, routing_key={}",
+                queue,
+                exchange,
+                routing_key
+            );
+            let bindings = inner
+                .bindings
+                .entry((queue.into(), exchange.into(), routing_key.into()))
+                .or_default();
+            if !bindings.contains(&arguments) {
+                bindings.push(arguments);
+            }
+        }
+    }
+
+    pub(crate) fn forget_binding(&self, queue: &str, exchange: &str, routing_key: &str) {
+        self.0
+            .lock()
+            .bindings
+            .remove(&(queue.into(), exchange.into(), routing_key.into()));
+    }
+
+    pub(crate) fn register_audit_queue(&self, exchange: &str, queue: &str) {
+        self.0
+            .lock()
+            .audit_queues
+            .insert(exchange.into(), queue.into());
+    }
+
+    pub(crate) fn audit_queue(&self, exchange: &str) -> Option<ShortString> {
+        self.0.lock().audit_queues.get(exchange).cloned()
+    }
+}
+
+impl fmt::Debug for DeclarationCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("DeclarationCache");
+        if let Some(inner) = self.0.try_lock() {
+            debug
+                .field("enabled", &inner.enabled)
+                .field("exchanges", &inner.exchanges.keys())
+                .field("queues", &inner.queues.keys())
+                .field("bindings", &inner.bindings.keys())
+                .field("audit_queues", &inner.audit_queues);
+        }
+        debug.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_helpers::*;
+
+    #[test]
+    fn declaration_cache() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        use crate::options::QueueDeclareOptions;
+        use amq_protocol::protocol::queue;
+
+        let (conn, channel, frames) = connected_channel();
+        conn.channels().declaration_cache().enable();
+
+        let declare = |arguments: FieldTable| {
+            let channel = channel.clone();
+            async_global_executor::spawn(async move {
+                channel
+                    .queue_declare("cached", QueueDeclareOptions::default(), arguments)
+                    .await
+            })
+        };
+        let declare_ok = || {
+            AMQPFrame::Method(
+                channel.id(),
+                AMQPClass::Queue(queue::AMQPMethod::DeclareOk(queue::DeclareOk {
+                    queue: "cached".into(),
+                    message_count: 5,
+                    consumer_count: 1,
+                })),
+            )
+        };
+
+        let task = declare(FieldTable::default());
+        let frame = answer_next_frame(&conn, &frames, declare_ok());
+        assert!(matches!(
+            frame,
+            AMQPFrame::Method(_, AMQPClass::Queue(queue::AMQPMethod::Declare(_)))
+        ));
+        let queue = async_global_executor::block_on(task).unwrap();
+        assert_eq!(queue.message_count(), 5);
+
+        // Identical declaration, answered from the cache without the stale counts
+        let queue = async_global_executor::block_on(declare(FieldTable::default())).unwrap();
+        assert_eq!(queue.name().as_str(), "cached");
+        assert_eq!(queue.message_count(), 0);
+        assert_eq!(queue.consumer_count(), 0);
+        assert!(frames.pop(true).is_none());
+
+        // Different arguments go to the server
+        let mut arguments = FieldTable::default();
+        arguments.insert("x-max-length".into(), AMQPValue::LongUInt(10));
+        let task = declare(arguments.clone());
+        let frame = answer_next_frame(&conn, &frames, declare_ok());
+        assert!(matches!(
+            frame,
+            AMQPFrame::Method(_, AMQPClass::Queue(queue::AMQPMethod::Declare(_)))
+        ));
+        assert!(async_global_executor::block_on(task).is_ok());
+        assert!(async_global_executor::block_on(declare(arguments)).is_ok());
+        assert!(frames.pop(true).is_none());
+    }
+}
@@ -1,5 +1,8 @@
 use crate::{
-    channel_status::ChannelState, connection_status::ConnectionState, protocol::AMQPError,
+    channel_status::ChannelState,
+    connection_status::ConnectionState,
+    protocol::{AMQPError, AMQPErrorKind, AMQPHardError, AMQPSoftError},
+    types::ShortUInt,
 };
 use amq_protocol::frame::{GenError, ParserError, ProtocolVersion};
 use std::{error, fmt, io, sync::Arc};
@@ -14,17 +17,40 @@ pub type Result<T> = std::result::Result<T, Error>;
 #[derive(Clone, Debug)]
 #[non_exhaustive]
 pub enum Error {
+    Cancelled,
     ChannelsLimitReached,
+    FrameOrderingViolation(String),
     InvalidProtocolVersion(ProtocolVersion),
+    NoPreviouslyDeclaredQueue,
+    NoReplyTo,
+    NotAnAmqpServer(Vec<u8>),
+    PublishNacked,
+    Timeout,
 
+    InvalidArgument(String),
     InvalidChannel(u16),
     InvalidChannelState(ChannelState),
     InvalidConnectionState(ConnectionState),
+    InvalidName {
+        field: &'static str,
+        value: String,
+        reason: &'static str,
+    },
+    MessageTooLarge(u64),
+    PublishReturned {
+        reply_code: ShortUInt,
+        reply_text: String,
+    },
+    UnknownContentType(String),
+    UnsupportedByBroker(&'static str),
 
     IOError(Arc<io::Error>),
     ParsingError(ParserError),
+    PayloadCodecError(String),
     ProtocolError(AMQPError),
     SerialisationError(Arc<GenError>),
+    #[cfg(feature = "serde")]
+    TopologyParsingError(Arc<serde_json::Error>),
 }
 
 impl Error {
@@ -43,29 +69,154 @@ impl Error {
             false
         }
     }
+
+    /// Whether the server refused the operation with `ACCESS_REFUSED`, for example because
+    /// another consumer already consumes exclusively from the queue.
+    pub fn is_access_refused(&self) -> bool {
+        matches!(
+            self,
+            Error::ProtocolError(e)
+                if matches!(e.kind(), AMQPErrorKind::Soft(AMQPSoftError::ACCESSREFUSED))
+        )
+    }
+}
+
+/// Tells apart the errors worth retrying from the fatal ones.
+pub trait RetryableError {
+    /// Whether the failed operation can succeed if retried, usually after reconnecting or
+    /// opening a new channel.
+    fn is_retryable(&self) -> bool;
+}
+
+impl RetryableError for Error {
+    fn is_retryable(&self) -> bool {
+        match self {
+            Error::InvalidChannel(_)
+            | Error::InvalidChannelState(_)
+            | Error::InvalidConnectionState(_)
+            | Error::PublishNacked
+            | Error::Timeout => true,
+            Error::IOError(e) => matches!(
+                e.kind(),
+                io::ErrorKind::ConnectionRefused
+                    | io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+                    | io::ErrorKind::NotConnected
+                    | io::ErrorKind::BrokenPipe
+                    | io::ErrorKind::TimedOut
+                    | io::ErrorKind::Interrupted
+                    | io::ErrorKind::WouldBlock
+                    | io::ErrorKind::UnexpectedEof
+            ),
+            Error::ProtocolError(e) => matches!(
+                e.kind(),
+                AMQPErrorKind::Soft(AMQPSoftError::RESOURCELOCKED)
+                    | AMQPErrorKind::Hard(AMQPHardError::CONNECTIONFORCED)
+                    | AMQPErrorKind::Hard(AMQPHardError::RESOURCEERROR)
+                    | AMQPErrorKind::Hard(AMQPHardError::INTERNALERROR)
+            ),
+            Error::Cancelled
+            | Error::ChannelsLimitReached
+            | Error::FrameOrderingViolation(_)
+            | Error::InvalidArgument(_)
+            | Error::InvalidName { .. }
+            | Error::InvalidProtocolVersion(_)
+            | Error::NoPreviouslyDeclaredQueue
+            | Error::NoReplyTo
+            | Error::NotAnAmqpServer(_)
+            | Error::MessageTooLarge(_)
+            | Error::PublishReturned { .. }
+            | Error::UnknownContentType(_)
+            | Error::UnsupportedByBroker(_)
+            | Error::ParsingError(_)
+            | Error::PayloadCodecError(_)
+            | Error::SerialisationError(_) => false,
+            #[cfg(feature = "serde")]
+            Error::TopologyParsingError(_) => false,
+        }
+    }
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
+            Error::Cancelled => write!(f, "the operation was cancelled"),
             Error::ChannelsLimitReached => write!(
                 f,
                 "the maximum number of channels for this connection has been reached"
             ),
+            Error::FrameOrderingViolation(dump) => {
+                write!(f, "content frames were written out of order: {}", dump)
+            }
             Error::InvalidProtocolVersion(version) => {
-                write!(f, "the server only supports AMQP {}", version)
+                write!(f, "the server only supports AMQP {}", version)?;
+                if version.major == 1 {
+                    write!(
+                        f,
+                        " (AMQP 1.0 is a different protocol, lapin only supports AMQP 0.9.1)"
+                    )?;
+                }
+                Ok(())
+            }
+            Error::NoPreviouslyDeclaredQueue => write!(
+                f,
+                "an empty queue name stands for the last queue declared on the channel, but none was declared"
+            ),
+            Error::NoReplyTo => write!(f, "the message to reply to has no reply_to address"),
+            Error::NotAnAmqpServer(greeting) => {
+                write!(f, "the server doesn't speak AMQP, it answered:")?;
+                for byte in greeting {
+                    write!(f, " {:02x}", byte)?;
+                }
+                if greeting.starts_with(b"HTTP/") {
+                    write!(
+                        f,
+                        " (this looks like an HTTP server, is this the management port?)"
+                    )?;
+                }
+                Ok(())
             }
+            Error::PublishNacked => write!(f, "the server nacked the published message"),
+            Error::Timeout => write!(f, "the operation timed out"),
 
+            Error::InvalidArgument(reason) => write!(f, "invalid argument: {}", reason),
             Error::InvalidChannel(channel) => write!(f, "invalid channel: {}", channel),
             Error::InvalidChannelState(state) => write!(f, "invalid channel state: {:?}", state),
             Error::InvalidConnectionState(state) => {
                 write!(f, "invalid connection state: {:?}", state)
             }
+            Error::InvalidName {
+                field,
+                value,
+                reason,
+            } => write!(f, "invalid {} {:?}: {}", field, value, reason),
+            Error::MessageTooLarge(size) => write!(
+                f,
+                "received a message of {} bytes, which exceeds the maximum message size",
+                size
+            ),
+            Error::PublishReturned {
+                reply_code,
+                reply_text,
+            } => write!(
+                f,
+                "the server returned the published message: {} {}",
+                reply_code, reply_text
+            ),
+            Error::UnknownContentType(content_type) => {
+                write!(f, "no payload codec for content type: {:?}", content_type)
+            }
+            Error::UnsupportedByBroker(feature) => {
+                write!(f, "the server doesn't support the {}", feature)
+            }
 
             Error::IOError(e) => write!(f, "IO error: {}", e),
             Error::ParsingError(e) => write!(f, "failed to parse: {}", e),
+            Error::PayloadCodecError(e) => write!(f, "failed to convert payload: {}", e),
             Error::ProtocolError(e) => write!(f, "protocol error: {}", e),
             Error::SerialisationError(e) => write!(f, "failed to serialise: {}", e),
+            #[cfg(feature = "serde")]
+            Error::TopologyParsingError(e) => write!(f, "failed to parse topology: {}", e),
         }
     }
 }
@@ -77,6 +228,8 @@ impl error::Error for Error {
             Error::ParsingError(e) => Some(&*e),
             Error::ProtocolError(e) => Some(&*e),
             Error::SerialisationError(e) => Some(&**e),
+            #[cfg(feature = "serde")]
+            Error::TopologyParsingError(e) => Some(&**e),
             _ => None,
         }
     }
@@ -94,11 +247,25 @@ impl PartialEq for Error {
         use Error::*;
 
         match (self, other) {
+            (Cancelled, Cancelled) => true,
             (ChannelsLimitReached, ChannelsLimitReached) => true,
+            (FrameOrderingViolation(left_inner), FrameOrderingViolation(right_inner)) => {
+                left_inner == right_inner
+            }
             (InvalidProtocolVersion(left_inner), InvalidProtocolVersion(right_version)) => {
                 left_inner == right_version
             }
+            (NoPreviouslyDeclaredQueue, NoPreviouslyDeclaredQueue) => true,
+            (NoReplyTo, NoReplyTo) => true,
+            (NotAnAmqpServer(left_inner), NotAnAmqpServer(right_inner)) => {
+                left_inner == right_inner
+            }
+            (PublishNacked, PublishNacked) => true,
+            (Timeout, Timeout) => true,
 
+            (InvalidArgument(left_inner), InvalidArgument(right_inner)) => {
+                left_inner == right_inner
+            }
             (InvalidChannel(left_inner), InvalidChannel(right_inner)) => left_inner == right_inner,
             (InvalidChannelState(left_inner), InvalidChannelState(right_inner)) => {
                 left_inner == right_inner
@@ -106,19 +273,92 @@ impl PartialEq for Error {
             (InvalidConnectionState(left_inner), InvalidConnectionState(right_inner)) => {
                 left_inner == right_inner
             }
+            (
+                InvalidName {
+                    field: left_field,
+                    value: left_value,
+                    reason: left_reason,
+                },
+                InvalidName {
+                    field: right_field,
+                    value: right_value,
+                    reason: right_reason,
+                },
+            ) => {
+                left_field == right_field
+                    && left_value == right_value
+                    && left_reason == right_reason
+            }
+            (MessageTooLarge(left_inner), MessageTooLarge(right_inner)) => {
+                left_inner == right_inner
+            }
+            (
+                PublishReturned {
+                    reply_code: left_code,
+                    reply_text: left_text,
+                },
+                PublishReturned {
+                    reply_code: right_code,
+                    reply_text: right_text,
+                },
+            ) => left_code == right_code && left_text == right_text,
+            (UnknownContentType(left_inner), UnknownContentType(right_inner)) => {
+                left_inner == right_inner
+            }
+            (UnsupportedByBroker(left_inner), UnsupportedByBroker(right_inner)) => {
+                left_inner == right_inner
+            }
 
             (IOError(_), IOError(_)) => {
                 error!("Unable to compare lapin::Error::IOError");
                 false
             }
             (ParsingError(left_inner), ParsingError(right_inner)) => left_inner == right_inner,
+            (PayloadCodecError(left_inner), PayloadCodecError(right_inner)) => {
+                left_inner == right_inner
+            }
             (ProtocolError(left_inner), ProtocolError(right_inner)) => left_inner == right_inner,
             (SerialisationError(_), SerialisationError(_)) => {
                 error!("Unable to compare lapin::Error::SerialisationError");
                 false
             }
+            #[cfg(feature = "serde")]
+            (TopologyParsingError(_), TopologyParsingError(_)) => {
+                error!("Unable to compare lapin::Error::TopologyParsingError");
+                false
+            }
 
             _ => false,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retryable() {
+        assert!(Error::Timeout.is_retryable());
+        assert!(Error::InvalidChannelState(ChannelState::Closed).is_retryable());
+        assert!(Error::from(io::Error::from(io::ErrorKind::ConnectionReset)).is_retryable());
+        assert!(Error::ProtocolError(AMQPError::new(
+            AMQPHardError::CONNECTIONFORCED.into(),
+            "broker shutdown".into()
+        ))
+        .is_retryable());
+
+        assert!(!Error::Cancelled.is_retryable());
+        assert!(!Error::from(io::Error::from(io::ErrorKind::InvalidData)).is_retryable());
+        assert!(!Error::ProtocolError(AMQPError::new(
+            AMQPSoftError::ACCESSREFUSED.into(),
+            "access refused".into()
+        ))
+        .is_retryable());
+        assert!(!Error::ProtocolError(AMQPError::new(
+            AMQPHardError::FRAMEERROR.into(),
+            "malformed frame".into()
+        ))
+        .is_retryable());
+    }
+}
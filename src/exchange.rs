@@ -1,3 +1,6 @@
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum ExchangeKind {
     Custom(String),
@@ -24,3 +27,29 @@ impl ExchangeKind {
         }
     }
 }
+
+impl From<String> for ExchangeKind {
+    fn from(kind: String) -> Self {
+        match kind.as_str() {
+            "direct" => Self::Direct,
+            "fanout" => Self::Fanout,
+            "headers" => Self::Headers,
+            "topic" => Self::Topic,
+            _ => Self::Custom(kind),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Serialize for ExchangeKind {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.kind())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for ExchangeKind {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Self::from)
+    }
+}
@@ -0,0 +1,274 @@
+use crate::protocol::{basic, AMQPClass};
+use amq_protocol::frame::AMQPFrame;
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+};
+
+/// How many of the last written frames are kept to diagnose an ordering violation.
+const HISTORY_SIZE: usize = 32;
+
+/// Checks that the content frames we write follow their method directly.
+///
+/// Content frames of a publish can be interleaved with frames of other channels, but on their
+/// own channel the Header frame must come right after the basic.publish frame and the Body
+/// frames right after it until the announced size has been sent, otherwise the server closes
+/// the connection with UNEXPECTED_FRAME.
+#[derive(Debug, Default)]
+pub(crate) struct FrameOrderingValidator {
+    channels: HashMap<u16, Expecting>,
+    history: VecDeque<FrameDescriptor>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Expecting {
+    Header,
+    Body(u64),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct FrameDescriptor {
+    channel_id: Option<u16>,
+    kind: FrameKind,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum FrameKind {
+    ProtocolHeader,
+    Method(u16, u16),
+    Header(u64),
+    Body(usize),
+    Heartbeat,
+}
+
+/// A frame written out of order, along with the frames written before it.
+#[derive(Debug)]
+pub(crate) struct FrameOrderingViolation {
+    reason: String,
+    history: Vec<FrameDescriptor>,
+}
+
+impl FrameOrderingValidator {
+    /// Record a frame about to be written, failing if it breaks the content frames ordering.
+    pub(crate) fn check(&mut self, frame: &AMQPFrame) -> Result<(), FrameOrderingViolation> {
+        let descriptor = FrameDescriptor::from(frame);
+        if self.history.len() == HISTORY_SIZE {
+            self.history.pop_front();
+        }
+        self.history.push_back(descriptor.clone());
+        let channel_id = match descriptor.channel_id {
+            Some(channel_id) => channel_id,
+            None => return Ok(()),
+        };
+        let expecting = self.channels.remove(&channel_id);
+        match (expecting, &descriptor.kind) {
+            (None, FrameKind::Method(..)) if is_content_method(frame) => {
+                self.channels.insert(channel_id, Expecting::Header);
+            }
+            (None, FrameKind::Method(..)) | (None, FrameKind::Heartbeat) => {}
+            (Some(Expecting::Header), FrameKind::Header(size)) => {
+                if *size > 0 {
+                    self.channels.insert(channel_id, Expecting::Body(*size));
+                }
+            }
+            (Some(Expecting::Body(remaining)), FrameKind::Body(size))
+                if *size as u64 <= remaining =>
+            {
+                if remaining > *size as u64 {
+                    self.channels
+                        .insert(channel_id, Expecting::Body(remaining - *size as u64));
+                }
+            }
+            (expecting, kind) => {
+                let expected = match expecting {
+                    Some(Expecting::Header) => "a content header".to_string(),
+                    Some(Expecting::Body(remaining)) => {
+                        format!("a body frame of at most {} bytes", remaining)
+                    }
+                    None => "a method or a heartbeat".to_string(),
+                };
+                return Err(FrameOrderingViolation {
+                    reason: format!(
+                        "wrote {} on channel {} while expecting {}",
+                        kind, channel_id, expected
+                    ),
+                    history: self.history.iter().cloned().collect(),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_content_method(frame: &AMQPFrame) -> bool {
+    matches!(
+        frame,
+        AMQPFrame::Method(_, AMQPClass::Basic(basic::AMQPMethod::Publish(_)))
+    )
+}
+
+impl From<&AMQPFrame> for FrameDescriptor {
+    fn from(frame: &AMQPFrame) -> Self {
+        let (channel_id, kind) = match frame {
+            AMQPFrame::ProtocolHeader(_) => (None, FrameKind::ProtocolHeader),
+            AMQPFrame::Method(channel_id, method) => (
+                Some(*channel_id),
+                FrameKind::Method(method.get_amqp_class_id(), method.get_amqp_method_id()),
+            ),
+            AMQPFrame::Header(channel_id, _, header) => {
+                (Some(*channel_id), FrameKind::Header(header.body_size))
+            }
+            AMQPFrame::Body(channel_id, payload) => {
+                (Some(*channel_id), FrameKind::Body(payload.len()))
+            }
+            AMQPFrame::Heartbeat(channel_id) => (Some(*channel_id), FrameKind::Heartbeat),
+        };
+        Self { channel_id, kind }
+    }
+}
+
+impl fmt::Display for FrameKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameKind::ProtocolHeader => write!(f, "the protocol header"),
+            FrameKind::Method(class_id, method_id) => {
+                write!(f, "method {}.{}", class_id, method_id)
+            }
+            FrameKind::Header(body_size) => {
+                write!(f, "a content header announcing {} bytes", body_size)
+            }
+            FrameKind::Body(size) => write!(f, "a body frame of {} bytes", size),
+            FrameKind::Heartbeat => write!(f, "a heartbeat"),
+        }
+    }
+}
+
+impl fmt::Display for FrameDescriptor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.channel_id {
+            Some(channel_id) => write!(f, "[{}] {}", channel_id, self.kind),
+            None => write!(f, "{}", self.kind),
+        }
+    }
+}
+
+impl fmt::Display for FrameOrderingViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}; last written frames:", self.reason)?;
+        for descriptor in &self.history {
+            write!(f, "\n  {}", descriptor)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{frames::Frames, protocol::channel, BasicProperties, Promise};
+    use amq_protocol::frame::AMQPContentHeader;
+
+    fn publish(channel_id: u16) -> AMQPFrame {
+        AMQPFrame::Method(
+            channel_id,
+            AMQPClass::Basic(basic::AMQPMethod::Publish(basic::Publish {
+                exchange: "".into(),
+                routing_key: "queue".into(),
+                mandatory: false,
+                immediate: false,
+            })),
+        )
+    }
+
+    fn header(channel_id: u16, body_size: u64) -> AMQPFrame {
+        AMQPFrame::Header(
+            channel_id,
+            60,
+            Box::new(AMQPContentHeader {
+                class_id: 60,
+                weight: 0,
+                body_size,
+                properties: BasicProperties::default(),
+            }),
+        )
+    }
+
+    fn flow(channel_id: u16) -> AMQPFrame {
+        AMQPFrame::Method(
+            channel_id,
+            AMQPClass::Channel(channel::AMQPMethod::Flow(channel::Flow { active: true })),
+        )
+    }
+
+    fn validate(frames: &Frames) -> Result<(), FrameOrderingViolation> {
+        let mut validator = FrameOrderingValidator::default();
+        while let Some((frame, _)) = frames.pop(true) {
+            validator.check(&frame)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn interleaved_channels() {
+        let frames = Frames::default();
+        let _ = frames.push_frames(vec![
+            publish(1),
+            header(1, 6),
+            AMQPFrame::Body(1, b"hel".to_vec()),
+            AMQPFrame::Body(1, b"lo!".to_vec()),
+        ]);
+        frames.push(2, flow(2), Promise::new().1, None);
+        let _ = frames.push_frames(vec![publish(2), header(2, 0)]);
+        assert!(validate(&frames).is_ok());
+    }
+
+    #[test]
+    fn method_before_header() {
+        let frames = Frames::default();
+        frames.push(1, publish(1), Promise::new().1, None);
+        frames.push(1, flow(1), Promise::new().1, None);
+        frames.push(1, header(1, 3), Promise::new().1, None);
+        let violation = validate(&frames).unwrap_err();
+        assert_eq!(
+            violation.reason,
+            "wrote method 20.20 on channel 1 while expecting a content header"
+        );
+        assert_eq!(violation.history.len(), 2);
+    }
+
+    #[test]
+    fn body_without_header() {
+        let frames = Frames::default();
+        frames.push(1, AMQPFrame::Heartbeat(0), Promise::new().1, None);
+        frames.retry((AMQPFrame::Body(1, b"lost".to_vec()), None));
+        let violation = validate(&frames).unwrap_err();
+        assert_eq!(
+            violation.to_string(),
+            "wrote a body frame of 4 bytes on channel 1 while expecting a method or a heartbeat; last written frames:\n  [1] a body frame of 4 bytes"
+        );
+    }
+
+    #[test]
+    fn body_too_large() {
+        let mut validator = FrameOrderingValidator::default();
+        assert!(validator.check(&publish(1)).is_ok());
+        assert!(validator.check(&header(1, 2)).is_ok());
+        assert!(validator
+            .check(&AMQPFrame::Body(1, b"hello".to_vec()))
+            .is_err());
+    }
+
+    #[test]
+    fn history_size() {
+        let mut validator = FrameOrderingValidator::default();
+        for _ in 0..40 {
+            assert!(validator.check(&AMQPFrame::Heartbeat(0)).is_ok());
+        }
+        let violation = validator.check(&header(1, 0)).unwrap_err();
+        assert_eq!(violation.history.len(), HISTORY_SIZE);
+        assert_eq!(
+            violation.history.last(),
+            Some(&FrameDescriptor::from(&header(1, 0)))
+        );
+    }
+}
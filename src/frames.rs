@@ -1,14 +1,51 @@
-use crate::{channel::Reply, Error, Promise, PromiseResolver, Result};
+use crate::{
+    channel::Reply, tracing_integration::targets, Error, Promise, PromiseResolver, Result,
+};
 use amq_protocol::frame::AMQPFrame;
 use parking_lot::Mutex;
 use pinky_swear::Cancellable;
+#[cfg(feature = "publish-timestamps")]
+use std::time::Instant;
 use std::{
-    collections::{HashMap, VecDeque},
+    collections::{HashMap, HashSet, VecDeque},
     fmt,
-    sync::Arc,
+    sync::{Arc, Weak},
 };
 use tracing::{level_enabled, trace, Level};
 
+/// When the last frame of a message was written to the socket, filled by the io loop.
+#[cfg(feature = "publish-timestamps")]
+pub(crate) type WrittenAt = Arc<Mutex<Option<Instant>>>;
+
+/// Resolves the promise of a frame once the io loop wrote it to the socket, or failed to.
+pub(crate) struct FrameResolver {
+    resolver: PromiseResolver<()>,
+    #[cfg(feature = "publish-timestamps")]
+    written_at: Option<WrittenAt>,
+}
+
+impl FrameResolver {
+    pub(crate) fn swear(&self, res: Result<()>) {
+        #[cfg(feature = "publish-timestamps")]
+        {
+            if let (Ok(()), Some(written_at)) = (&res, self.written_at.as_ref()) {
+                *written_at.lock() = Some(Instant::now());
+            }
+        }
+        self.resolver.swear(res);
+    }
+}
+
+impl From<PromiseResolver<()>> for FrameResolver {
+    fn from(resolver: PromiseResolver<()>) -> Self {
+        Self {
+            resolver,
+            #[cfg(feature = "publish-timestamps")]
+            written_at: None,
+        }
+    }
+}
+
 pub(crate) struct ExpectedReply(
     pub(crate) Reply,
     pub(crate) Box<dyn Cancellable<Error> + Send>,
@@ -38,32 +75,66 @@ impl Frames {
             .push(channel_id, frame, resolver, expected_reply);
     }
 
-    pub(crate) async fn push_frames(&self, frames: Vec<AMQPFrame>) -> Result<()> {
-        let promise = self.inner.lock().push_frames(frames);
-        promise.await
+    /// Queue `frames` right away, the returned promise resolves once they're all written.
+    pub(crate) fn push_frames(&self, frames: Vec<AMQPFrame>) -> Promise<()> {
+        let (promise, resolver) = Self::frames_promise();
+        self.inner.lock().push_frames(frames, resolver.into());
+        promise
     }
 
-    pub(crate) fn retry(&self, frame: (AMQPFrame, Option<PromiseResolver<()>>)) {
-        self.inner.lock().retry_frames.push_back(frame);
+    /// Queue `frames` like `push_frames`, also recording when the last one got written.
+    #[cfg(feature = "publish-timestamps")]
+    pub(crate) fn push_timed_frames(&self, frames: Vec<AMQPFrame>) -> (Promise<()>, WrittenAt) {
+        let (promise, resolver) = Self::frames_promise();
+        let written_at = WrittenAt::default();
+        self.inner.lock().push_frames(
+            frames,
+            FrameResolver {
+                resolver,
+                written_at: Some(written_at.clone()),
+            },
+        );
+        (promise, written_at)
     }
 
-    pub(crate) fn pop(&self, flow: bool) -> Option<(AMQPFrame, Option<PromiseResolver<()>>)> {
+    fn frames_promise() -> (Promise<()>, PromiseResolver<()>) {
+        let (promise, resolver) = Promise::new();
+        if level_enabled!(Level::TRACE) {
+            promise.set_marker("Frames".into());
+        }
+        (promise, resolver)
+    }
+
+    pub(crate) fn retry(&self, frame: SendFrame) {
+        self.inner.lock().retry(frame);
+    }
+
+    pub(crate) fn pop(&self, flow: bool) -> Option<SendFrame> {
         self.inner.lock().pop(flow)
     }
 
+    pub(crate) fn prioritize_channel(&self, channel_id: u16) {
+        self.inner.lock().prioritize_channel(channel_id);
+    }
+
     pub(crate) fn next_expected_reply(&self, channel_id: u16) -> Option<Reply> {
-        self.inner
-            .lock()
-            .expected_replies
-            .get_mut(&channel_id)
-            .and_then(|replies| replies.pop_front())
-            .map(|t| t.0)
+        self.inner.lock().next_expected_reply(channel_id)
+    }
+
+    /// Resolves once every frame currently queued for this channel has been popped and every
+    /// reply it currently expects has been received. Frames pushed afterwards are ignored.
+    pub(crate) fn sync_channel(&self, channel_id: u16) -> Promise<()> {
+        self.inner.lock().sync_channel(channel_id)
     }
 
     pub(crate) fn has_pending(&self) -> bool {
         self.inner.lock().has_pending()
     }
 
+    pub(crate) fn try_pending_count(&self) -> Option<usize> {
+        self.inner.try_lock().map(|inner| inner.pending_count())
+    }
+
     pub(crate) fn drop_pending(&self, error: Error) {
         self.inner.lock().drop_pending(error);
     }
@@ -71,16 +142,53 @@ impl Frames {
     pub(crate) fn clear_expected_replies(&self, channel_id: u16, error: Error) {
         self.inner.lock().clear_expected_replies(channel_id, error);
     }
+
+    /// Drop the frames queued for this channel which didn't start being sent, failing them and
+    /// the replies it expects with `error`. Returns how many of the dropped frames were awaited.
+    pub(crate) fn cancel_channel_frames(&self, channel_id: u16, error: Error) -> usize {
+        self.inner.lock().cancel_channel_frames(channel_id, error)
+    }
+
+    pub(crate) fn cleanup_closed_channels(&self, open_channels: &HashSet<u16>) {
+        self.inner.lock().cleanup_closed_channels(open_channels);
+    }
+
+    /// Copy the frames queued for this channel from now on into `capture`, until it's dropped.
+    #[cfg(any(test, feature = "test-support"))]
+    pub(crate) fn capture(&self, channel_id: u16, capture: &Arc<Mutex<Vec<AMQPFrame>>>) {
+        self.inner
+            .lock()
+            .captures
+            .push((channel_id, Arc::downgrade(capture)));
+    }
+}
+
+pub(crate) type SendFrame = (AMQPFrame, Option<FrameResolver>);
+type Capture = (u16, Weak<Mutex<Vec<AMQPFrame>>>);
+/* Frames are numbered in push order so that a sync can tell them apart from the ones pushed after it */
+type QueuedFrame = (u64, SendFrame);
+
+struct SyncWaiter {
+    channel_id: u16,
+    /* Only the frames numbered before this one are waited for */
+    until_seq: u64,
+    frames: usize,
+    replies: usize,
+    resolver: PromiseResolver<()>,
 }
 
 struct Inner {
     /* Header frames must follow basic.publish frames directly, otherwise RabbitMQ-server send us an UNEXPECTED_FRAME */
     /* After sending the Header frame, we need to send the associated Body frames before anything else for the same reason */
-    publish_frames: VecDeque<(AMQPFrame, Option<PromiseResolver<()>>)>,
-    retry_frames: VecDeque<(AMQPFrame, Option<PromiseResolver<()>>)>,
-    frames: VecDeque<(AMQPFrame, Option<PromiseResolver<()>>)>,
-    low_prio_frames: VecDeque<(AMQPFrame, Option<PromiseResolver<()>>)>,
+    publish_frames: VecDeque<QueuedFrame>,
+    retry_frames: VecDeque<QueuedFrame>,
+    frames: VecDeque<QueuedFrame>,
+    low_prio_frames: VecDeque<QueuedFrame>,
     expected_replies: HashMap<u16, VecDeque<ExpectedReply>>,
+    next_seq: u64,
+    popped_seq: u64,
+    sync_waiters: Vec<SyncWaiter>,
+    captures: Vec<Capture>,
 }
 
 impl Default for Inner {
@@ -91,6 +199,10 @@ impl Default for Inner {
             frames: VecDeque::default(),
             low_prio_frames: VecDeque::default(),
             expected_replies: HashMap::default(),
+            next_seq: 0,
+            popped_seq: 0,
+            sync_waiters: Vec::default(),
+            captures: Vec::default(),
         }
     }
 }
@@ -113,9 +225,12 @@ impl Inner {
         resolver: PromiseResolver<()>,
         expected_reply: Option<ExpectedReply>,
     ) {
-        self.frames.push_back((frame, Some(resolver)));
+        let seq = self.next_seq();
+        self.capture(&frame);
+        self.frames.push_back((seq, (frame, Some(resolver.into()))));
         if let Some(reply) = expected_reply {
             trace!(
+                target: targets::FRAMES,
                 "channel {} state is now waiting for {:?}",
                 channel_id,
                 reply
@@ -127,26 +242,70 @@ impl Inner {
         }
     }
 
-    fn push_frames(&mut self, mut frames: Vec<AMQPFrame>) -> Promise<()> {
-        let (promise, resolver) = Promise::new();
+    fn push_frames(&mut self, mut frames: Vec<AMQPFrame>, resolver: FrameResolver) {
         let last_frame = frames.pop();
-
-        if level_enabled!(Level::TRACE) {
-            promise.set_marker("Frames".into());
-        }
-
         for frame in frames {
-            self.low_prio_frames.push_back((frame, None));
+            let seq = self.next_seq();
+            self.capture(&frame);
+            self.low_prio_frames.push_back((seq, (frame, None)));
         }
         if let Some(last_frame) = last_frame {
-            self.low_prio_frames.push_back((last_frame, Some(resolver)));
+            let seq = self.next_seq();
+            self.capture(&last_frame);
+            self.low_prio_frames
+                .push_back((seq, (last_frame, Some(resolver))));
         } else {
             resolver.swear(Ok(()));
         }
-        promise
     }
 
-    fn pop(&mut self, flow: bool) -> Option<(AMQPFrame, Option<PromiseResolver<()>>)> {
+    fn capture(&mut self, frame: &AMQPFrame) {
+        if self.captures.is_empty() {
+            return;
+        }
+        let channel_id = frame_channel_id(frame);
+        self.captures.retain(|(capture_channel_id, capture)| {
+            if let Some(capture) = capture.upgrade() {
+                if channel_id == Some(*capture_channel_id) {
+                    capture.lock().push(frame.clone());
+                }
+                true
+            } else {
+                false
+            }
+        });
+    }
+
+    fn next_seq(&mut self) -> u64 {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        seq
+    }
+
+    fn retry(&mut self, frame: SendFrame) {
+        // This is the frame we just popped, requeue it with its original number
+        let seq = self.popped_seq;
+        if let Some(channel_id) = frame_channel_id(&frame.0) {
+            for waiter in self.sync_waiters_for(channel_id, seq) {
+                waiter.frames += 1;
+            }
+        }
+        self.retry_frames.push_back((seq, frame));
+    }
+
+    fn pop(&mut self, flow: bool) -> Option<SendFrame> {
+        let (seq, frame) = self.next_frame(flow)?;
+        self.popped_seq = seq;
+        if let Some(channel_id) = frame_channel_id(&frame.0) {
+            for waiter in self.sync_waiters_for(channel_id, seq) {
+                waiter.frames = waiter.frames.saturating_sub(1);
+            }
+            self.resolve_sync_waiters();
+        }
+        Some(frame)
+    }
+
+    fn next_frame(&mut self, flow: bool) -> Option<QueuedFrame> {
         if let Some(frame) = self
             .retry_frames
             .pop_front()
@@ -164,7 +323,7 @@ impl Inner {
                 if self
                     .low_prio_frames
                     .front()
-                    .map(|(frame, _)| frame.is_header())
+                    .map(|(_, (frame, _))| frame.is_header())
                     .unwrap_or(false)
                 {
                     // Yes, this will always be Some() with a Header frame, but let's keep our unwrap() count low
@@ -172,7 +331,7 @@ impl Inner {
                         self.publish_frames.push_back(next_frame);
                     }
                     while let Some(next_frame) = self.low_prio_frames.pop_front() {
-                        match next_frame.0 {
+                        match (next_frame.1).0 {
                             AMQPFrame::Body(..) => {
                                 self.publish_frames.push_back(next_frame);
                             }
@@ -190,6 +349,141 @@ impl Inner {
         None
     }
 
+    fn prioritize_channel(&mut self, channel_id: u16) {
+        // A publish already halfway sent stays in publish_frames, its remaining Header and Body
+        // frames go first anyway. The publishes stay in low_prio_frames so that they still wait
+        // for the server to let us send them.
+        let frames = Self::take_channel_frames(&mut self.frames, channel_id);
+        let publishes = Self::take_channel_frames(&mut self.low_prio_frames, channel_id);
+        trace!(
+            target: targets::FRAMES,
+            "prioritizing {} frames for channel {}",
+            frames.len() + publishes.len(),
+            channel_id
+        );
+        Self::push_front(&mut self.frames, frames);
+        Self::push_front(&mut self.low_prio_frames, publishes);
+    }
+
+    fn push_front(frames: &mut VecDeque<QueuedFrame>, mut prioritized: VecDeque<QueuedFrame>) {
+        prioritized.append(frames);
+        *frames = prioritized;
+    }
+
+    fn take_channel_frames(
+        frames: &mut VecDeque<QueuedFrame>,
+        channel_id: u16,
+    ) -> VecDeque<QueuedFrame> {
+        let (taken, kept) = std::mem::take(frames)
+            .into_iter()
+            .partition(|(_, (frame, _))| frame_channel_id(frame) == Some(channel_id));
+        *frames = kept;
+        taken
+    }
+
+    fn next_expected_reply(&mut self, channel_id: u16) -> Option<Reply> {
+        let reply = self
+            .expected_replies
+            .get_mut(&channel_id)
+            .and_then(|replies| replies.pop_front())
+            .map(|t| t.0)?;
+        // Replies come back in order, so this is always the oldest one a sync may be waiting for
+        for waiter in self
+            .sync_waiters
+            .iter_mut()
+            .filter(|waiter| waiter.channel_id == channel_id)
+        {
+            waiter.replies = waiter.replies.saturating_sub(1);
+        }
+        self.resolve_sync_waiters();
+        Some(reply)
+    }
+
+    fn sync_channel(&mut self, channel_id: u16) -> Promise<()> {
+        let (promise, resolver) = Promise::new();
+
+        if level_enabled!(Level::TRACE) {
+            promise.set_marker(format!("Sync channel {}", channel_id));
+        }
+
+        let frames = self
+            .retry_frames
+            .iter()
+            .chain(self.publish_frames.iter())
+            .chain(self.frames.iter())
+            .chain(self.low_prio_frames.iter())
+            .filter(|(_, (frame, _))| frame_channel_id(frame) == Some(channel_id))
+            .count();
+        let replies = self
+            .expected_replies
+            .get(&channel_id)
+            .map_or(0, VecDeque::len);
+        trace!(
+            target: targets::FRAMES,
+            "channel {} sync waiting for {} frames and {} replies",
+            channel_id,
+            frames,
+            replies
+        );
+        if frames == 0 && replies == 0 {
+            resolver.swear(Ok(()));
+        } else {
+            self.sync_waiters.push(SyncWaiter {
+                channel_id,
+                until_seq: self.next_seq,
+                frames,
+                replies,
+                resolver,
+            });
+        }
+        promise
+    }
+
+    fn sync_waiters_for(
+        &mut self,
+        channel_id: u16,
+        seq: u64,
+    ) -> impl Iterator<Item = &mut SyncWaiter> {
+        self.sync_waiters
+            .iter_mut()
+            .filter(move |waiter| waiter.channel_id == channel_id && seq < waiter.until_seq)
+    }
+
+    fn resolve_sync_waiters(&mut self) {
+        if self
+            .sync_waiters
+            .iter()
+            .any(|waiter| waiter.frames == 0 && waiter.replies == 0)
+        {
+            let (done, waiting): (Vec<SyncWaiter>, Vec<SyncWaiter>) =
+                std::mem::take(&mut self.sync_waiters)
+                    .into_iter()
+                    .partition(|waiter| waiter.frames == 0 && waiter.replies == 0);
+            self.sync_waiters = waiting;
+            for waiter in done {
+                waiter.resolver.swear(Ok(()));
+            }
+        }
+    }
+
+    fn cancel_sync_waiters(&mut self, channel_id: Option<u16>, error: Error) {
+        let (cancelled, waiting): (Vec<SyncWaiter>, Vec<SyncWaiter>) =
+            std::mem::take(&mut self.sync_waiters)
+                .into_iter()
+                .partition(|waiter| channel_id.map_or(true, |id| id == waiter.channel_id));
+        self.sync_waiters = waiting;
+        for waiter in cancelled {
+            waiter.resolver.swear(Err(error.clone()));
+        }
+    }
+
+    fn pending_count(&self) -> usize {
+        self.retry_frames.len()
+            + self.publish_frames.len()
+            + self.frames.len()
+            + self.low_prio_frames.len()
+    }
+
     fn has_pending(&self) -> bool {
         !(self.retry_frames.is_empty()
             && self.publish_frames.is_empty()
@@ -205,13 +499,11 @@ impl Inner {
         for (_, replies) in self.expected_replies.drain() {
             Self::cancel_expected_replies(replies, error.clone());
         }
+        self.cancel_sync_waiters(None, error);
     }
 
-    fn drop_pending_frames(
-        frames: &mut VecDeque<(AMQPFrame, Option<PromiseResolver<()>>)>,
-        error: Error,
-    ) {
-        for (_, resolver) in std::mem::take(frames) {
+    fn drop_pending_frames(frames: &mut VecDeque<QueuedFrame>, error: Error) {
+        for (_, (_, resolver)) in std::mem::take(frames) {
             if let Some(resolver) = resolver {
                 resolver.swear(Err(error.clone()));
             }
@@ -220,7 +512,47 @@ impl Inner {
 
     fn clear_expected_replies(&mut self, channel_id: u16, error: Error) {
         if let Some(replies) = self.expected_replies.remove(&channel_id) {
-            Self::cancel_expected_replies(replies, error);
+            Self::cancel_expected_replies(replies, error.clone());
+        }
+        self.cancel_sync_waiters(Some(channel_id), error);
+    }
+
+    fn cancel_channel_frames(&mut self, channel_id: u16, error: Error) -> usize {
+        // Frames being retried or following a basic.publish which was sent already have to go
+        let mut cancelled = Self::take_channel_frames(&mut self.frames, channel_id);
+        cancelled.extend(Self::take_channel_frames(
+            &mut self.low_prio_frames,
+            channel_id,
+        ));
+        let count = cancelled
+            .iter()
+            .filter(|(_, (_, resolver))| resolver.is_some())
+            .count();
+        trace!(
+            target: targets::FRAMES,
+            "cancelling {} frames for channel {}",
+            cancelled.len(),
+            channel_id
+        );
+        Self::drop_pending_frames(&mut cancelled, error.clone());
+        self.clear_expected_replies(channel_id, error);
+        count
+    }
+
+    fn cleanup_closed_channels(&mut self, open_channels: &HashSet<u16>) {
+        let closed_channels = self
+            .expected_replies
+            .keys()
+            .filter(|channel_id| !open_channels.contains(channel_id))
+            .cloned()
+            .collect::<Vec<u16>>();
+        for channel_id in closed_channels {
+            trace!(
+                target: targets::FRAMES,
+                "dropping expected replies for closed channel {}",
+                channel_id
+            );
+            self.clear_expected_replies(channel_id, Error::InvalidChannel(channel_id));
         }
     }
 
@@ -230,3 +562,165 @@ impl Inner {
         }
     }
 }
+
+fn frame_channel_id(frame: &AMQPFrame) -> Option<u16> {
+    match frame {
+        AMQPFrame::ProtocolHeader(_) => None,
+        AMQPFrame::Method(channel_id, _)
+        | AMQPFrame::Header(channel_id, ..)
+        | AMQPFrame::Body(channel_id, _)
+        | AMQPFrame::Heartbeat(channel_id) => Some(*channel_id),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn push_publish(frames: &Frames, channel_id: u16, body: &[u8]) {
+        let _ = frames.push_frames(vec![
+            AMQPFrame::Body(channel_id, body.to_vec()),
+            AMQPFrame::Body(channel_id, body.to_vec()),
+        ]);
+    }
+
+    #[test]
+    fn prioritize_channel() {
+        let frames = Frames::default();
+        push_publish(&frames, 1, b"one");
+        push_publish(&frames, 2, b"two");
+        frames.push(2, AMQPFrame::Heartbeat(2), Promise::new().1, None);
+        frames.push(1, AMQPFrame::Heartbeat(1), Promise::new().1, None);
+        frames.retry((AMQPFrame::Heartbeat(0), None));
+
+        frames.prioritize_channel(2);
+
+        let mut sent = Vec::new();
+        while let Some((frame, _)) = frames.pop(false) {
+            sent.push(frame);
+        }
+        // The publishes still wait for the flow to be active
+        assert_eq!(
+            sent,
+            vec![
+                AMQPFrame::Heartbeat(0),
+                AMQPFrame::Heartbeat(2),
+                AMQPFrame::Heartbeat(1),
+            ]
+        );
+        sent.clear();
+        while let Some((frame, _)) = frames.pop(true) {
+            sent.push(frame);
+        }
+        assert_eq!(
+            sent,
+            vec![
+                AMQPFrame::Body(2, b"two".to_vec()),
+                AMQPFrame::Body(2, b"two".to_vec()),
+                AMQPFrame::Body(1, b"one".to_vec()),
+                AMQPFrame::Body(1, b"one".to_vec()),
+            ]
+        );
+
+        // This was a one-shot boost
+        push_publish(&frames, 1, b"one");
+        push_publish(&frames, 2, b"two");
+        assert_eq!(
+            frames.pop(true).map(|(frame, _)| frame),
+            Some(AMQPFrame::Body(1, b"one".to_vec()))
+        );
+    }
+
+    fn push_declare(frames: &Frames, channel_id: u16) -> Promise<()> {
+        let (promise, resolver) = Promise::new();
+        frames.push(
+            channel_id,
+            AMQPFrame::Heartbeat(channel_id),
+            Promise::new().1,
+            Some(ExpectedReply(
+                Reply::ExchangeDeclareOk(resolver.clone()),
+                Box::new(resolver),
+            )),
+        );
+        promise
+    }
+
+    #[test]
+    fn sync_channel() {
+        let frames = Frames::default();
+        assert!(frames.sync_channel(1).try_wait().is_some());
+
+        push_publish(&frames, 1, b"one");
+        let _declare = push_declare(&frames, 1);
+        push_publish(&frames, 2, b"two");
+        let sync = frames.sync_channel(1);
+
+        // Issued after the sync, must not delay it
+        push_publish(&frames, 1, b"late");
+        let _late_declare = push_declare(&frames, 1);
+
+        // Both declares, then the publish of channel 1 queued before the sync
+        for _ in 0..4 {
+            assert!(sync.try_wait().is_none());
+            assert!(frames.pop(true).is_some());
+        }
+        // The declare still expects its reply
+        assert!(sync.try_wait().is_none());
+        assert!(frames.next_expected_reply(1).is_some());
+        assert!(matches!(sync.try_wait(), Some(Ok(()))));
+
+        // The late operations are still pending
+        assert!(frames.has_pending());
+        assert!(frames.next_expected_reply(1).is_some());
+    }
+
+    #[test]
+    fn sync_channel_error() {
+        let frames = Frames::default();
+        let _declare = push_declare(&frames, 1);
+        let sync = frames.sync_channel(1);
+        let other = frames.sync_channel(2);
+        assert!(matches!(other.try_wait(), Some(Ok(()))));
+
+        frames.clear_expected_replies(1, Error::InvalidChannel(1));
+        assert!(matches!(
+            sync.try_wait(),
+            Some(Err(Error::InvalidChannel(1)))
+        ));
+    }
+
+    #[test]
+    fn cancel_channel_frames() {
+        let frames = Frames::default();
+        push_publish(&frames, 1, b"one");
+        let declare = push_declare(&frames, 1);
+        push_publish(&frames, 2, b"two");
+        let (sent, resolver) = Promise::new();
+        frames.push(1, AMQPFrame::Heartbeat(1), resolver, None);
+
+        // The end of the publish, the declare and the heartbeat were awaited, not the first body
+        // frame of the publish
+        assert_eq!(frames.cancel_channel_frames(1, Error::InvalidChannel(1)), 3);
+        assert!(matches!(
+            sent.try_wait(),
+            Some(Err(Error::InvalidChannel(1)))
+        ));
+        assert!(matches!(
+            declare.try_wait(),
+            Some(Err(Error::InvalidChannel(1)))
+        ));
+        assert!(frames.next_expected_reply(1).is_none());
+
+        let mut sent = Vec::new();
+        while let Some((frame, _)) = frames.pop(true) {
+            sent.push(frame);
+        }
+        assert_eq!(
+            sent,
+            vec![
+                AMQPFrame::Body(2, b"two".to_vec()),
+                AMQPFrame::Body(2, b"two".to_vec()),
+            ]
+        );
+    }
+}
@@ -1,17 +1,73 @@
 pub mod options {
+    //! The options of the AMQP methods.
+    //!
+    //! New flags may get added to the options, so they're built from their default value with
+    //! one method per flag:
+    //!
+    //! ```
+    //! use lapin::options::QueueDeclareOptions;
+    //!
+    //! let options = QueueDeclareOptions::default().durable(true).auto_delete(false);
+    //! assert!(options.durable);
+    //! ```
+    //!
+    //! The flags can also be set on a mutable value:
+    //!
+    //! ```
+    //! use lapin::options::QueueDeclareOptions;
+    //!
+    //! let mut options = QueueDeclareOptions::default();
+    //! options.durable = true;
+    //! ```
+    //!
+    //! Struct expressions, including the struct update syntax, aren't available outside of lapin:
+    //!
+    //! ```compile_fail
+    //! use lapin::options::QueueDeclareOptions;
+    //!
+    //! let options = QueueDeclareOptions {
+    //!     durable: true,
+    //!     ..QueueDeclareOptions::default()
+    //! };
+    //! ```
+
     use super::*;
+    #[cfg(feature = "serde")]
+    use serde::{Deserialize, Serialize};
 
     #[derive(Copy, Clone, Debug, Default, PartialEq)]
+    #[cfg_attr(feature = "serde", derive(Deserialize, Serialize), serde(default))]
+    #[non_exhaustive]
     pub struct ChannelFlowOptions {
         pub active: Boolean,
     }
 
+    impl ChannelFlowOptions {
+        /// Set the active flag
+        pub fn active(mut self, active: Boolean) -> Self {
+            self.active = active;
+            self
+        }
+    }
+
     #[derive(Copy, Clone, Debug, Default, PartialEq)]
+    #[cfg_attr(feature = "serde", derive(Deserialize, Serialize), serde(default))]
+    #[non_exhaustive]
     pub struct ChannelFlowOkOptions {
         pub active: Boolean,
     }
 
+    impl ChannelFlowOkOptions {
+        /// Set the active flag
+        pub fn active(mut self, active: Boolean) -> Self {
+            self.active = active;
+            self
+        }
+    }
+
     #[derive(Copy, Clone, Debug, Default, PartialEq)]
+    #[cfg_attr(feature = "serde", derive(Deserialize, Serialize), serde(default))]
+    #[non_exhaustive]
     pub struct AccessRequestOptions {
         pub exclusive: Boolean,
         pub passive: Boolean,
@@ -20,7 +76,41 @@ pub mod options {
         pub read: Boolean,
     }
 
+    impl AccessRequestOptions {
+        /// Set the exclusive flag
+        pub fn exclusive(mut self, exclusive: Boolean) -> Self {
+            self.exclusive = exclusive;
+            self
+        }
+
+        /// Set the passive flag
+        pub fn passive(mut self, passive: Boolean) -> Self {
+            self.passive = passive;
+            self
+        }
+
+        /// Set the active flag
+        pub fn active(mut self, active: Boolean) -> Self {
+            self.active = active;
+            self
+        }
+
+        /// Set the write flag
+        pub fn write(mut self, write: Boolean) -> Self {
+            self.write = write;
+            self
+        }
+
+        /// Set the read flag
+        pub fn read(mut self, read: Boolean) -> Self {
+            self.read = read;
+            self
+        }
+    }
+
     #[derive(Copy, Clone, Debug, Default, PartialEq)]
+    #[cfg_attr(feature = "serde", derive(Deserialize, Serialize), serde(default))]
+    #[non_exhaustive]
     pub struct ExchangeDeclareOptions {
         pub passive: Boolean,
         pub durable: Boolean,
@@ -29,23 +119,93 @@ pub mod options {
         pub nowait: Boolean,
     }
 
+    impl ExchangeDeclareOptions {
+        /// Set the passive flag
+        pub fn passive(mut self, passive: Boolean) -> Self {
+            self.passive = passive;
+            self
+        }
+
+        /// Set the durable flag
+        pub fn durable(mut self, durable: Boolean) -> Self {
+            self.durable = durable;
+            self
+        }
+
+        /// Set the auto_delete flag
+        pub fn auto_delete(mut self, auto_delete: Boolean) -> Self {
+            self.auto_delete = auto_delete;
+            self
+        }
+
+        /// Set the internal flag
+        pub fn internal(mut self, internal: Boolean) -> Self {
+            self.internal = internal;
+            self
+        }
+
+        /// Set the nowait flag
+        pub fn nowait(mut self, nowait: Boolean) -> Self {
+            self.nowait = nowait;
+            self
+        }
+    }
+
     #[derive(Copy, Clone, Debug, Default, PartialEq)]
+    #[cfg_attr(feature = "serde", derive(Deserialize, Serialize), serde(default))]
+    #[non_exhaustive]
     pub struct ExchangeDeleteOptions {
         pub if_unused: Boolean,
         pub nowait: Boolean,
     }
 
+    impl ExchangeDeleteOptions {
+        /// Set the if_unused flag
+        pub fn if_unused(mut self, if_unused: Boolean) -> Self {
+            self.if_unused = if_unused;
+            self
+        }
+
+        /// Set the nowait flag
+        pub fn nowait(mut self, nowait: Boolean) -> Self {
+            self.nowait = nowait;
+            self
+        }
+    }
+
     #[derive(Copy, Clone, Debug, Default, PartialEq)]
+    #[cfg_attr(feature = "serde", derive(Deserialize, Serialize), serde(default))]
+    #[non_exhaustive]
     pub struct ExchangeBindOptions {
         pub nowait: Boolean,
     }
 
+    impl ExchangeBindOptions {
+        /// Set the nowait flag
+        pub fn nowait(mut self, nowait: Boolean) -> Self {
+            self.nowait = nowait;
+            self
+        }
+    }
+
     #[derive(Copy, Clone, Debug, Default, PartialEq)]
+    #[cfg_attr(feature = "serde", derive(Deserialize, Serialize), serde(default))]
+    #[non_exhaustive]
     pub struct ExchangeUnbindOptions {
         pub nowait: Boolean,
     }
 
+    impl ExchangeUnbindOptions {
+        /// Set the nowait flag
+        pub fn nowait(mut self, nowait: Boolean) -> Self {
+            self.nowait = nowait;
+            self
+        }
+    }
+
     #[derive(Copy, Clone, Debug, Default, PartialEq)]
+    #[cfg_attr(feature = "serde", derive(Deserialize, Serialize), serde(default))]
+    #[non_exhaustive]
     pub struct QueueDeclareOptions {
         pub passive: Boolean,
         pub durable: Boolean,
@@ -54,29 +214,115 @@ pub mod options {
         pub nowait: Boolean,
     }
 
+    impl QueueDeclareOptions {
+        /// Set the passive flag
+        pub fn passive(mut self, passive: Boolean) -> Self {
+            self.passive = passive;
+            self
+        }
+
+        /// Set the durable flag
+        pub fn durable(mut self, durable: Boolean) -> Self {
+            self.durable = durable;
+            self
+        }
+
+        /// Set the exclusive flag
+        pub fn exclusive(mut self, exclusive: Boolean) -> Self {
+            self.exclusive = exclusive;
+            self
+        }
+
+        /// Set the auto_delete flag
+        pub fn auto_delete(mut self, auto_delete: Boolean) -> Self {
+            self.auto_delete = auto_delete;
+            self
+        }
+
+        /// Set the nowait flag
+        pub fn nowait(mut self, nowait: Boolean) -> Self {
+            self.nowait = nowait;
+            self
+        }
+    }
+
     #[derive(Copy, Clone, Debug, Default, PartialEq)]
+    #[cfg_attr(feature = "serde", derive(Deserialize, Serialize), serde(default))]
+    #[non_exhaustive]
     pub struct QueueBindOptions {
         pub nowait: Boolean,
     }
 
+    impl QueueBindOptions {
+        /// Set the nowait flag
+        pub fn nowait(mut self, nowait: Boolean) -> Self {
+            self.nowait = nowait;
+            self
+        }
+    }
+
     #[derive(Copy, Clone, Debug, Default, PartialEq)]
+    #[cfg_attr(feature = "serde", derive(Deserialize, Serialize), serde(default))]
+    #[non_exhaustive]
     pub struct QueuePurgeOptions {
         pub nowait: Boolean,
     }
 
+    impl QueuePurgeOptions {
+        /// Set the nowait flag
+        pub fn nowait(mut self, nowait: Boolean) -> Self {
+            self.nowait = nowait;
+            self
+        }
+    }
+
     #[derive(Copy, Clone, Debug, Default, PartialEq)]
+    #[cfg_attr(feature = "serde", derive(Deserialize, Serialize), serde(default))]
+    #[non_exhaustive]
     pub struct QueueDeleteOptions {
         pub if_unused: Boolean,
         pub if_empty: Boolean,
         pub nowait: Boolean,
     }
 
+    impl QueueDeleteOptions {
+        /// Set the if_unused flag
+        pub fn if_unused(mut self, if_unused: Boolean) -> Self {
+            self.if_unused = if_unused;
+            self
+        }
+
+        /// Set the if_empty flag
+        pub fn if_empty(mut self, if_empty: Boolean) -> Self {
+            self.if_empty = if_empty;
+            self
+        }
+
+        /// Set the nowait flag
+        pub fn nowait(mut self, nowait: Boolean) -> Self {
+            self.nowait = nowait;
+            self
+        }
+    }
+
     #[derive(Copy, Clone, Debug, Default, PartialEq)]
+    #[cfg_attr(feature = "serde", derive(Deserialize, Serialize), serde(default))]
+    #[non_exhaustive]
     pub struct BasicQosOptions {
         pub global: Boolean,
     }
 
+    impl BasicQosOptions {
+        /// Set the global flag
+        pub fn global(mut self, global: Boolean) -> Self {
+            self.global = global;
+            self
+        }
+    }
+
     #[derive(Copy, Clone, Debug, Default, PartialEq)]
+    #[cfg_attr(feature = "serde", derive(Deserialize, Serialize), serde(default))]
+    #[non_exhaustive]
     pub struct BasicConsumeOptions {
         pub no_local: Boolean,
         pub no_ack: Boolean,
@@ -84,62 +330,210 @@ pub mod options {
         pub nowait: Boolean,
     }
 
+    impl BasicConsumeOptions {
+        /// Set the no_local flag
+        pub fn no_local(mut self, no_local: Boolean) -> Self {
+            self.no_local = no_local;
+            self
+        }
+
+        /// Set the no_ack flag
+        pub fn no_ack(mut self, no_ack: Boolean) -> Self {
+            self.no_ack = no_ack;
+            self
+        }
+
+        /// Set the exclusive flag
+        pub fn exclusive(mut self, exclusive: Boolean) -> Self {
+            self.exclusive = exclusive;
+            self
+        }
+
+        /// Set the nowait flag
+        pub fn nowait(mut self, nowait: Boolean) -> Self {
+            self.nowait = nowait;
+            self
+        }
+    }
+
     #[derive(Copy, Clone, Debug, Default, PartialEq)]
+    #[cfg_attr(feature = "serde", derive(Deserialize, Serialize), serde(default))]
+    #[non_exhaustive]
     pub struct BasicCancelOptions {
         pub nowait: Boolean,
     }
 
+    impl BasicCancelOptions {
+        /// Set the nowait flag
+        pub fn nowait(mut self, nowait: Boolean) -> Self {
+            self.nowait = nowait;
+            self
+        }
+    }
+
     #[derive(Copy, Clone, Debug, Default, PartialEq)]
+    #[cfg_attr(feature = "serde", derive(Deserialize, Serialize), serde(default))]
+    #[non_exhaustive]
     pub struct BasicPublishOptions {
         pub mandatory: Boolean,
         pub immediate: Boolean,
     }
 
+    impl BasicPublishOptions {
+        /// Set the mandatory flag
+        pub fn mandatory(mut self, mandatory: Boolean) -> Self {
+            self.mandatory = mandatory;
+            self
+        }
+
+        /// Set the immediate flag
+        pub fn immediate(mut self, immediate: Boolean) -> Self {
+            self.immediate = immediate;
+            self
+        }
+    }
+
     #[derive(Copy, Clone, Debug, Default, PartialEq)]
+    #[cfg_attr(feature = "serde", derive(Deserialize, Serialize), serde(default))]
+    #[non_exhaustive]
     pub struct BasicDeliverOptions {
         pub redelivered: Boolean,
     }
 
+    impl BasicDeliverOptions {
+        /// Set the redelivered flag
+        pub fn redelivered(mut self, redelivered: Boolean) -> Self {
+            self.redelivered = redelivered;
+            self
+        }
+    }
+
     #[derive(Copy, Clone, Debug, Default, PartialEq)]
+    #[cfg_attr(feature = "serde", derive(Deserialize, Serialize), serde(default))]
+    #[non_exhaustive]
     pub struct BasicGetOptions {
         pub no_ack: Boolean,
     }
 
+    impl BasicGetOptions {
+        /// Set the no_ack flag
+        pub fn no_ack(mut self, no_ack: Boolean) -> Self {
+            self.no_ack = no_ack;
+            self
+        }
+    }
+
     #[derive(Copy, Clone, Debug, Default, PartialEq)]
+    #[cfg_attr(feature = "serde", derive(Deserialize, Serialize), serde(default))]
+    #[non_exhaustive]
     pub struct BasicGetOkOptions {
         pub redelivered: Boolean,
     }
 
+    impl BasicGetOkOptions {
+        /// Set the redelivered flag
+        pub fn redelivered(mut self, redelivered: Boolean) -> Self {
+            self.redelivered = redelivered;
+            self
+        }
+    }
+
     #[derive(Copy, Clone, Debug, Default, PartialEq)]
+    #[cfg_attr(feature = "serde", derive(Deserialize, Serialize), serde(default))]
+    #[non_exhaustive]
     pub struct BasicAckOptions {
         pub multiple: Boolean,
     }
 
+    impl BasicAckOptions {
+        /// Set the multiple flag
+        pub fn multiple(mut self, multiple: Boolean) -> Self {
+            self.multiple = multiple;
+            self
+        }
+    }
+
     #[derive(Copy, Clone, Debug, Default, PartialEq)]
+    #[cfg_attr(feature = "serde", derive(Deserialize, Serialize), serde(default))]
+    #[non_exhaustive]
     pub struct BasicRejectOptions {
         pub requeue: Boolean,
     }
 
+    impl BasicRejectOptions {
+        /// Set the requeue flag
+        pub fn requeue(mut self, requeue: Boolean) -> Self {
+            self.requeue = requeue;
+            self
+        }
+    }
+
     #[derive(Copy, Clone, Debug, Default, PartialEq)]
+    #[cfg_attr(feature = "serde", derive(Deserialize, Serialize), serde(default))]
+    #[non_exhaustive]
     pub struct BasicRecoverAsyncOptions {
         pub requeue: Boolean,
     }
 
+    impl BasicRecoverAsyncOptions {
+        /// Set the requeue flag
+        pub fn requeue(mut self, requeue: Boolean) -> Self {
+            self.requeue = requeue;
+            self
+        }
+    }
+
     #[derive(Copy, Clone, Debug, Default, PartialEq)]
+    #[cfg_attr(feature = "serde", derive(Deserialize, Serialize), serde(default))]
+    #[non_exhaustive]
     pub struct BasicRecoverOptions {
         pub requeue: Boolean,
     }
 
+    impl BasicRecoverOptions {
+        /// Set the requeue flag
+        pub fn requeue(mut self, requeue: Boolean) -> Self {
+            self.requeue = requeue;
+            self
+        }
+    }
+
     #[derive(Copy, Clone, Debug, Default, PartialEq)]
+    #[cfg_attr(feature = "serde", derive(Deserialize, Serialize), serde(default))]
+    #[non_exhaustive]
     pub struct BasicNackOptions {
         pub multiple: Boolean,
         pub requeue: Boolean,
     }
 
+    impl BasicNackOptions {
+        /// Set the multiple flag
+        pub fn multiple(mut self, multiple: Boolean) -> Self {
+            self.multiple = multiple;
+            self
+        }
+
+        /// Set the requeue flag
+        pub fn requeue(mut self, requeue: Boolean) -> Self {
+            self.requeue = requeue;
+            self
+        }
+    }
+
     #[derive(Copy, Clone, Debug, Default, PartialEq)]
+    #[cfg_attr(feature = "serde", derive(Deserialize, Serialize), serde(default))]
+    #[non_exhaustive]
     pub struct ConfirmSelectOptions {
         pub nowait: Boolean,
     }
+
+    impl ConfirmSelectOptions {
+        /// Set the nowait flag
+        pub fn nowait(mut self, nowait: Boolean) -> Self {
+            self.nowait = nowait;
+            self
+        }
+    }
 }
 
 use options::*;
@@ -164,9 +558,9 @@ pub(crate) enum Reply {
     QueueDeleteOk(PromiseResolver<LongUInt>, ShortString),
     QueueUnbindOk(PromiseResolver<()>),
     BasicQosOk(PromiseResolver<()>),
-    BasicConsumeOk(PromiseResolver<Consumer>, ShortString),
+    BasicConsumeOk(PromiseResolver<Consumer>, ShortString, ShortString, Boolean),
     BasicCancelOk(PromiseResolver<()>),
-    BasicGetOk(PromiseResolver<Option<BasicGetMessage>>, ShortString),
+    BasicGetOk(PromiseResolver<Option<BasicGetMessage>>, ShortString, Boolean),
     BasicRecoverOk(PromiseResolver<()>),
     TxSelectOk(PromiseResolver<()>),
     TxCommitOk(PromiseResolver<()>),
@@ -283,7 +677,11 @@ impl Channel {
                 self.receive_confirm_select_ok(m)
             }
             m => {
-                error!("the client should not receive this method: {:?}", m);
+                error!(
+                    target: targets::CHANNEL,
+                    "the client should not receive this method: {:?}",
+                    m
+                );
                 self.handle_invalid_contents(
                     format!("unexepcted method received on channel {}", self.id),
                     m.get_amqp_class_id(),
@@ -311,26 +709,31 @@ impl Channel {
         connection: Connection,
         credentials: Credentials,
     ) -> Result<()> {
-        if !self.status.connected() {
-            return Err(Error::InvalidChannelState(self.status.state()));
-        }
-
-        let method = AMQPClass::Connection(protocol::connection::AMQPMethod::StartOk(
-            protocol::connection::StartOk {
-                client_properties,
-                mechanism: mechanism.into(),
-                response: response.into(),
-                locale: locale.into(),
-            },
-        ));
+        let span = channel_span!(self, "channel.connection_start_ok");
+        instrument(span, async move {
+            if !self.status.connected() {
+                return Err(self.status.state_error());
+            }
 
-        let (promise, send_resolver) = Promise::new();
-        if level_enabled!(Level::TRACE) {
-            promise.set_marker("connection.start-ok".into());
-        }
-        self.send_method_frame(method, send_resolver, None);
-        self.on_connection_start_ok_sent(resolver, connection, credentials);
-        promise.await
+            let method = AMQPClass::Connection(protocol::connection::AMQPMethod::StartOk(
+                protocol::connection::StartOk {
+                    client_properties,
+                    mechanism: mechanism.into(),
+                    response: response.into(),
+                    locale: locale.into(),
+                },
+            ));
+            self.check_fields(&method)?;
+
+            let (promise, send_resolver) = Promise::new();
+            if level_enabled!(Level::TRACE) {
+                promise.set_marker("connection.start-ok".into());
+            }
+            self.send_method_frame(method, send_resolver, None);
+            self.on_connection_start_ok_sent(resolver, connection, credentials);
+            promise.await
+        })
+        .await
     }
 
     fn receive_connection_secure(&self, method: protocol::connection::Secure) -> Result<()> {
@@ -342,22 +745,27 @@ impl Channel {
     }
     #[allow(clippy::too_many_arguments)]
     async fn connection_secure_ok(&self, response: &str) -> Result<()> {
-        if !self.status.connected() {
-            return Err(Error::InvalidChannelState(self.status.state()));
-        }
+        let span = channel_span!(self, "channel.connection_secure_ok");
+        instrument(span, async move {
+            if !self.status.connected() {
+                return Err(self.status.state_error());
+            }
 
-        let method = AMQPClass::Connection(protocol::connection::AMQPMethod::SecureOk(
-            protocol::connection::SecureOk {
-                response: response.into(),
-            },
-        ));
+            let method = AMQPClass::Connection(protocol::connection::AMQPMethod::SecureOk(
+                protocol::connection::SecureOk {
+                    response: response.into(),
+                },
+            ));
+            self.check_fields(&method)?;
 
-        let (promise, send_resolver) = Promise::new();
-        if level_enabled!(Level::TRACE) {
-            promise.set_marker("connection.secure-ok".into());
-        }
-        self.send_method_frame(method, send_resolver, None);
-        promise.await
+            let (promise, send_resolver) = Promise::new();
+            if level_enabled!(Level::TRACE) {
+                promise.set_marker("connection.secure-ok".into());
+            }
+            self.send_method_frame(method, send_resolver, None);
+            promise.await
+        })
+        .await
     }
 
     fn receive_connection_tune(&self, method: protocol::connection::Tune) -> Result<()> {
@@ -374,24 +782,29 @@ impl Channel {
         frame_max: LongUInt,
         heartbeat: ShortUInt,
     ) -> Result<()> {
-        if !self.status.connected() {
-            return Err(Error::InvalidChannelState(self.status.state()));
-        }
-
-        let method = AMQPClass::Connection(protocol::connection::AMQPMethod::TuneOk(
-            protocol::connection::TuneOk {
-                channel_max,
-                frame_max,
-                heartbeat,
-            },
-        ));
+        let span = channel_span!(self, "channel.connection_tune_ok");
+        instrument(span, async move {
+            if !self.status.connected() {
+                return Err(self.status.state_error());
+            }
 
-        let (promise, send_resolver) = Promise::new();
-        if level_enabled!(Level::TRACE) {
-            promise.set_marker("connection.tune-ok".into());
-        }
-        self.send_method_frame(method, send_resolver, None);
-        promise.await
+            let method = AMQPClass::Connection(protocol::connection::AMQPMethod::TuneOk(
+                protocol::connection::TuneOk {
+                    channel_max,
+                    frame_max,
+                    heartbeat,
+                },
+            ));
+            self.check_fields(&method)?;
+
+            let (promise, send_resolver) = Promise::new();
+            if level_enabled!(Level::TRACE) {
+                promise.set_marker("connection.tune-ok".into());
+            }
+            self.send_method_frame(method, send_resolver, None);
+            promise.await
+        })
+        .await
     }
     #[allow(clippy::too_many_arguments)]
     pub(crate) async fn connection_open(
@@ -400,35 +813,40 @@ impl Channel {
         connection: Connection,
         conn_resolver: PromiseResolver<Connection>,
     ) -> Result<()> {
-        if !self.status.connected() {
-            return Err(Error::InvalidChannelState(self.status.state()));
-        }
+        let span = channel_span!(self, "channel.connection_open");
+        instrument(span, async move {
+            if !self.status.connected() {
+                return Err(self.status.state_error());
+            }
 
-        let method = AMQPClass::Connection(protocol::connection::AMQPMethod::Open(
-            protocol::connection::Open {
-                virtual_host: virtual_host.into(),
-            },
-        ));
+            let method = AMQPClass::Connection(protocol::connection::AMQPMethod::Open(
+                protocol::connection::Open {
+                    virtual_host: virtual_host.into(),
+                },
+            ));
+            self.check_fields(&method)?;
 
-        let (promise, send_resolver) = Promise::new();
-        if level_enabled!(Level::TRACE) {
-            promise.set_marker("connection.open".into());
-        }
-        let ((promise, resolver), promise_out) = (Promise::new(), promise);
-        if level_enabled!(Level::TRACE) {
-            promise.set_marker("connection.open.Ok".into());
-        }
-        self.send_method_frame(
-            method,
-            send_resolver,
-            Some(ExpectedReply(
-                Reply::ConnectionOpenOk(resolver.clone(), connection),
-                Box::new(resolver),
-            )),
-        );
-        self.on_connection_open_sent(conn_resolver);
-        promise_out.await?;
-        promise.await
+            let (promise, send_resolver) = Promise::new();
+            if level_enabled!(Level::TRACE) {
+                promise.set_marker("connection.open".into());
+            }
+            let ((promise, resolver), promise_out) = (Promise::new(), promise);
+            if level_enabled!(Level::TRACE) {
+                promise.set_marker("connection.open.Ok".into());
+            }
+            self.send_method_frame(
+                method,
+                send_resolver,
+                Some(ExpectedReply(
+                    Reply::ConnectionOpenOk(resolver.clone(), connection),
+                    Box::new(resolver),
+                )),
+            );
+            self.on_connection_open_sent(conn_resolver);
+            promise_out.await?;
+            promise.await
+        })
+        .await
     }
     fn receive_connection_open_ok(&self, method: protocol::connection::OpenOk) -> Result<()> {
         self.assert_channel0(method.get_amqp_class_id(), method.get_amqp_method_id())?;
@@ -461,38 +879,43 @@ impl Channel {
         class_id: ShortUInt,
         method_id: ShortUInt,
     ) -> Result<()> {
-        if !self.status.connected() {
-            return Err(Error::InvalidChannelState(self.status.state()));
-        }
-
-        let method = AMQPClass::Connection(protocol::connection::AMQPMethod::Close(
-            protocol::connection::Close {
-                reply_code,
-                reply_text: reply_text.into(),
-                class_id,
-                method_id,
-            },
-        ));
+        let span = channel_span!(self, "channel.connection_close");
+        instrument(span, async move {
+            if !self.status.connected() {
+                return Err(self.status.state_error());
+            }
 
-        let (promise, send_resolver) = Promise::new();
-        if level_enabled!(Level::TRACE) {
-            promise.set_marker("connection.close".into());
-        }
-        let ((promise, resolver), promise_out) = (Promise::new(), promise);
-        if level_enabled!(Level::TRACE) {
-            promise.set_marker("connection.close.Ok".into());
-        }
-        self.send_method_frame(
-            method,
-            send_resolver,
-            Some(ExpectedReply(
-                Reply::ConnectionCloseOk(resolver.clone()),
-                Box::new(resolver),
-            )),
-        );
-        self.on_connection_close_sent();
-        promise_out.await?;
-        promise.await
+            let method = AMQPClass::Connection(protocol::connection::AMQPMethod::Close(
+                protocol::connection::Close {
+                    reply_code,
+                    reply_text: reply_text.into(),
+                    class_id,
+                    method_id,
+                },
+            ));
+            self.check_fields(&method)?;
+
+            let (promise, send_resolver) = Promise::new();
+            if level_enabled!(Level::TRACE) {
+                promise.set_marker("connection.close".into());
+            }
+            let ((promise, resolver), promise_out) = (Promise::new(), promise);
+            if level_enabled!(Level::TRACE) {
+                promise.set_marker("connection.close.Ok".into());
+            }
+            self.send_method_frame(
+                method,
+                send_resolver,
+                Some(ExpectedReply(
+                    Reply::ConnectionCloseOk(resolver.clone()),
+                    Box::new(resolver),
+                )),
+            );
+            self.on_connection_close_sent();
+            promise_out.await?;
+            promise.await
+        })
+        .await
     }
 
     fn receive_connection_close(&self, method: protocol::connection::Close) -> Result<()> {
@@ -504,21 +927,26 @@ impl Channel {
     }
     #[allow(clippy::too_many_arguments)]
     pub(crate) async fn connection_close_ok(&self, error: Error) -> Result<()> {
-        if !self.status.closing() {
-            return Err(Error::InvalidChannelState(self.status.state()));
-        }
+        let span = channel_span!(self, "channel.connection_close_ok");
+        instrument(span, async move {
+            if !self.status.closing() {
+                return Err(self.status.state_error());
+            }
 
-        let method = AMQPClass::Connection(protocol::connection::AMQPMethod::CloseOk(
-            protocol::connection::CloseOk {},
-        ));
+            let method = AMQPClass::Connection(protocol::connection::AMQPMethod::CloseOk(
+                protocol::connection::CloseOk {},
+            ));
+            self.check_fields(&method)?;
 
-        let (promise, send_resolver) = Promise::new();
-        if level_enabled!(Level::TRACE) {
-            promise.set_marker("connection.close-ok".into());
-        }
-        self.send_method_frame(method, send_resolver, None);
-        self.on_connection_close_ok_sent(error);
-        promise.await
+            let (promise, send_resolver) = Promise::new();
+            if level_enabled!(Level::TRACE) {
+                promise.set_marker("connection.close-ok".into());
+            }
+            self.send_method_frame(method, send_resolver, None);
+            self.on_connection_close_ok_sent(error);
+            promise.await
+        })
+        .await
     }
     fn receive_connection_close_ok(&self, method: protocol::connection::CloseOk) -> Result<()> {
         self.assert_channel0(method.get_amqp_class_id(), method.get_amqp_method_id())?;
@@ -545,22 +973,27 @@ impl Channel {
     }
     #[allow(clippy::too_many_arguments)]
     pub(crate) async fn connection_blocked(&self, reason: &str) -> Result<()> {
-        if !self.status.connected() {
-            return Err(Error::InvalidChannelState(self.status.state()));
-        }
+        let span = channel_span!(self, "channel.connection_blocked");
+        instrument(span, async move {
+            if !self.status.connected() {
+                return Err(self.status.state_error());
+            }
 
-        let method = AMQPClass::Connection(protocol::connection::AMQPMethod::Blocked(
-            protocol::connection::Blocked {
-                reason: reason.into(),
-            },
-        ));
+            let method = AMQPClass::Connection(protocol::connection::AMQPMethod::Blocked(
+                protocol::connection::Blocked {
+                    reason: reason.into(),
+                },
+            ));
+            self.check_fields(&method)?;
 
-        let (promise, send_resolver) = Promise::new();
-        if level_enabled!(Level::TRACE) {
-            promise.set_marker("connection.blocked".into());
-        }
-        self.send_method_frame(method, send_resolver, None);
-        promise.await
+            let (promise, send_resolver) = Promise::new();
+            if level_enabled!(Level::TRACE) {
+                promise.set_marker("connection.blocked".into());
+            }
+            self.send_method_frame(method, send_resolver, None);
+            promise.await
+        })
+        .await
     }
 
     fn receive_connection_blocked(&self, method: protocol::connection::Blocked) -> Result<()> {
@@ -572,20 +1005,25 @@ impl Channel {
     }
     #[allow(clippy::too_many_arguments)]
     pub(crate) async fn connection_unblocked(&self) -> Result<()> {
-        if !self.status.connected() {
-            return Err(Error::InvalidChannelState(self.status.state()));
-        }
+        let span = channel_span!(self, "channel.connection_unblocked");
+        instrument(span, async move {
+            if !self.status.connected() {
+                return Err(self.status.state_error());
+            }
 
-        let method = AMQPClass::Connection(protocol::connection::AMQPMethod::Unblocked(
-            protocol::connection::Unblocked {},
-        ));
+            let method = AMQPClass::Connection(protocol::connection::AMQPMethod::Unblocked(
+                protocol::connection::Unblocked {},
+            ));
+            self.check_fields(&method)?;
 
-        let (promise, send_resolver) = Promise::new();
-        if level_enabled!(Level::TRACE) {
-            promise.set_marker("connection.unblocked".into());
-        }
-        self.send_method_frame(method, send_resolver, None);
-        promise.await
+            let (promise, send_resolver) = Promise::new();
+            if level_enabled!(Level::TRACE) {
+                promise.set_marker("connection.unblocked".into());
+            }
+            self.send_method_frame(method, send_resolver, None);
+            promise.await
+        })
+        .await
     }
 
     fn receive_connection_unblocked(&self, method: protocol::connection::Unblocked) -> Result<()> {
@@ -601,35 +1039,40 @@ impl Channel {
         new_secret: &str,
         reason: &str,
     ) -> Result<()> {
-        if !self.status.connected() {
-            return Err(Error::InvalidChannelState(self.status.state()));
-        }
-
-        let method = AMQPClass::Connection(protocol::connection::AMQPMethod::UpdateSecret(
-            protocol::connection::UpdateSecret {
-                new_secret: new_secret.into(),
-                reason: reason.into(),
-            },
-        ));
+        let span = channel_span!(self, "channel.connection_update_secret");
+        instrument(span, async move {
+            if !self.status.connected() {
+                return Err(self.status.state_error());
+            }
 
-        let (promise, send_resolver) = Promise::new();
-        if level_enabled!(Level::TRACE) {
-            promise.set_marker("connection.update-secret".into());
-        }
-        let ((promise, resolver), promise_out) = (Promise::new(), promise);
-        if level_enabled!(Level::TRACE) {
-            promise.set_marker("connection.update-secret.Ok".into());
-        }
-        self.send_method_frame(
-            method,
-            send_resolver,
-            Some(ExpectedReply(
-                Reply::ConnectionUpdateSecretOk(resolver.clone()),
-                Box::new(resolver),
-            )),
-        );
-        promise_out.await?;
-        promise.await
+            let method = AMQPClass::Connection(protocol::connection::AMQPMethod::UpdateSecret(
+                protocol::connection::UpdateSecret {
+                    new_secret: new_secret.into(),
+                    reason: reason.into(),
+                },
+            ));
+            self.check_fields(&method)?;
+
+            let (promise, send_resolver) = Promise::new();
+            if level_enabled!(Level::TRACE) {
+                promise.set_marker("connection.update-secret".into());
+            }
+            let ((promise, resolver), promise_out) = (Promise::new(), promise);
+            if level_enabled!(Level::TRACE) {
+                promise.set_marker("connection.update-secret.Ok".into());
+            }
+            self.send_method_frame(
+                method,
+                send_resolver,
+                Some(ExpectedReply(
+                    Reply::ConnectionUpdateSecretOk(resolver.clone()),
+                    Box::new(resolver),
+                )),
+            );
+            promise_out.await?;
+            promise.await
+        })
+        .await
     }
     fn receive_connection_update_secret_ok(
         &self,
@@ -659,36 +1102,41 @@ impl Channel {
     }
     #[allow(clippy::too_many_arguments)]
     pub(crate) async fn channel_open(&self, channel: Channel) -> Result<Channel> {
-        if !self.status.initializing() {
-            return Err(Error::InvalidChannelState(self.status.state()));
-        }
+        let span = channel_span!(self, "channel.channel_open");
+        instrument(span, async move {
+            if !self.status.initializing() {
+                return Err(self.status.state_error());
+            }
 
-        let method = AMQPClass::Channel(protocol::channel::AMQPMethod::Open(
-            protocol::channel::Open {},
-        ));
+            let method = AMQPClass::Channel(protocol::channel::AMQPMethod::Open(
+                protocol::channel::Open {},
+            ));
+            self.check_fields(&method)?;
 
-        let (promise, send_resolver) = Promise::new();
-        if level_enabled!(Level::TRACE) {
-            promise.set_marker("channel.open".into());
-        }
-        let ((promise, resolver), promise_out) = (Promise::new(), promise);
-        if level_enabled!(Level::TRACE) {
-            promise.set_marker("channel.open.Ok".into());
-        }
-        self.send_method_frame(
-            method,
-            send_resolver,
-            Some(ExpectedReply(
-                Reply::ChannelOpenOk(resolver.clone(), channel),
-                Box::new(resolver),
-            )),
-        );
-        promise_out.await?;
-        promise.await
+            let (promise, send_resolver) = Promise::new();
+            if level_enabled!(Level::TRACE) {
+                promise.set_marker("channel.open".into());
+            }
+            let ((promise, resolver), promise_out) = (Promise::new(), promise);
+            if level_enabled!(Level::TRACE) {
+                promise.set_marker("channel.open.Ok".into());
+            }
+            self.send_method_frame(
+                method,
+                send_resolver,
+                Some(ExpectedReply(
+                    Reply::ChannelOpenOk(resolver.clone(), channel),
+                    Box::new(resolver),
+                )),
+            );
+            promise_out.await?;
+            promise.await
+        })
+        .await
     }
     fn receive_channel_open_ok(&self, method: protocol::channel::OpenOk) -> Result<()> {
         if !self.status.initializing() {
-            return Err(Error::InvalidChannelState(self.status.state()));
+            return Err(self.status.state_error());
         }
 
         match self.frames.next_expected_reply(self.id) {
@@ -704,33 +1152,38 @@ impl Channel {
     }
     #[allow(clippy::too_many_arguments)]
     pub async fn channel_flow(&self, options: ChannelFlowOptions) -> Result<Boolean> {
-        if !self.status.connected() {
-            return Err(Error::InvalidChannelState(self.status.state()));
-        }
+        let span = channel_span!(self, "channel.channel_flow");
+        instrument(span, async move {
+            if !self.status.connected() {
+                return Err(self.status.state_error());
+            }
 
-        let ChannelFlowOptions { active } = options;
-        let method = AMQPClass::Channel(protocol::channel::AMQPMethod::Flow(
-            protocol::channel::Flow { active },
-        ));
+            let ChannelFlowOptions { active } = options;
+            let method = AMQPClass::Channel(protocol::channel::AMQPMethod::Flow(
+                protocol::channel::Flow { active },
+            ));
+            self.check_fields(&method)?;
 
-        let (promise, send_resolver) = Promise::new();
-        if level_enabled!(Level::TRACE) {
-            promise.set_marker("channel.flow".into());
-        }
-        let ((promise, resolver), promise_out) = (Promise::new(), promise);
-        if level_enabled!(Level::TRACE) {
-            promise.set_marker("channel.flow.Ok".into());
-        }
-        self.send_method_frame(
-            method,
-            send_resolver,
-            Some(ExpectedReply(
-                Reply::ChannelFlowOk(resolver.clone()),
-                Box::new(resolver),
-            )),
-        );
-        promise_out.await?;
-        promise.await
+            let (promise, send_resolver) = Promise::new();
+            if level_enabled!(Level::TRACE) {
+                promise.set_marker("channel.flow".into());
+            }
+            let ((promise, resolver), promise_out) = (Promise::new(), promise);
+            if level_enabled!(Level::TRACE) {
+                promise.set_marker("channel.flow.Ok".into());
+            }
+            self.send_method_frame(
+                method,
+                send_resolver,
+                Some(ExpectedReply(
+                    Reply::ChannelFlowOk(resolver.clone()),
+                    Box::new(resolver),
+                )),
+            );
+            promise_out.await?;
+            promise.await
+        })
+        .await
     }
 
     fn receive_channel_flow(&self, method: protocol::channel::Flow) -> Result<()> {
@@ -741,21 +1194,26 @@ impl Channel {
     }
     #[allow(clippy::too_many_arguments)]
     async fn channel_flow_ok(&self, options: ChannelFlowOkOptions) -> Result<()> {
-        if !self.status.connected() {
-            return Err(Error::InvalidChannelState(self.status.state()));
-        }
+        let span = channel_span!(self, "channel.channel_flow_ok");
+        instrument(span, async move {
+            if !self.status.connected() {
+                return Err(self.status.state_error());
+            }
 
-        let ChannelFlowOkOptions { active } = options;
-        let method = AMQPClass::Channel(protocol::channel::AMQPMethod::FlowOk(
-            protocol::channel::FlowOk { active },
-        ));
+            let ChannelFlowOkOptions { active } = options;
+            let method = AMQPClass::Channel(protocol::channel::AMQPMethod::FlowOk(
+                protocol::channel::FlowOk { active },
+            ));
+            self.check_fields(&method)?;
 
-        let (promise, send_resolver) = Promise::new();
-        if level_enabled!(Level::TRACE) {
-            promise.set_marker("channel.flow-ok".into());
-        }
-        self.send_method_frame(method, send_resolver, None);
-        promise.await
+            let (promise, send_resolver) = Promise::new();
+            if level_enabled!(Level::TRACE) {
+                promise.set_marker("channel.flow-ok".into());
+            }
+            self.send_method_frame(method, send_resolver, None);
+            promise.await
+        })
+        .await
     }
     fn receive_channel_flow_ok(&self, method: protocol::channel::FlowOk) -> Result<()> {
         if !self.status.can_receive_messages() {
@@ -782,7 +1240,7 @@ impl Channel {
         method_id: ShortUInt,
     ) -> Result<()> {
         if !self.status.connected() {
-            return Err(Error::InvalidChannelState(self.status.state()));
+            return Err(self.status.state_error());
         }
 
         self.before_channel_close();
@@ -794,6 +1252,7 @@ impl Channel {
                 method_id,
             },
         ));
+        self.check_fields(&method)?;
 
         let (promise, send_resolver) = Promise::new();
         if level_enabled!(Level::TRACE) {
@@ -823,21 +1282,26 @@ impl Channel {
     }
     #[allow(clippy::too_many_arguments)]
     async fn channel_close_ok(&self, error: Error) -> Result<()> {
-        if !self.status.closing() {
-            return Err(Error::InvalidChannelState(self.status.state()));
-        }
+        let span = channel_span!(self, "channel.channel_close_ok");
+        instrument(span, async move {
+            if !self.status.closing() {
+                return Err(self.status.state_error());
+            }
 
-        let method = AMQPClass::Channel(protocol::channel::AMQPMethod::CloseOk(
-            protocol::channel::CloseOk {},
-        ));
+            let method = AMQPClass::Channel(protocol::channel::AMQPMethod::CloseOk(
+                protocol::channel::CloseOk {},
+            ));
+            self.check_fields(&method)?;
 
-        let (promise, send_resolver) = Promise::new();
-        if level_enabled!(Level::TRACE) {
-            promise.set_marker("channel.close-ok".into());
-        }
-        self.send_method_frame(method, send_resolver, None);
-        self.on_channel_close_ok_sent(error);
-        promise.await
+            let (promise, send_resolver) = Promise::new();
+            if level_enabled!(Level::TRACE) {
+                promise.set_marker("channel.close-ok".into());
+            }
+            self.send_method_frame(method, send_resolver, None);
+            self.on_channel_close_ok_sent(error);
+            promise.await
+        })
+        .await
     }
     fn receive_channel_close_ok(&self, method: protocol::channel::CloseOk) -> Result<()> {
         if !self.status.can_receive_messages() {
@@ -862,47 +1326,52 @@ impl Channel {
     }
     #[allow(clippy::too_many_arguments)]
     pub async fn access_request(&self, realm: &str, options: AccessRequestOptions) -> Result<()> {
-        if !self.status.connected() {
-            return Err(Error::InvalidChannelState(self.status.state()));
-        }
-
-        let AccessRequestOptions {
-            exclusive,
-            passive,
-            active,
-            write,
-            read,
-        } = options;
-        let method = AMQPClass::Access(protocol::access::AMQPMethod::Request(
-            protocol::access::Request {
-                realm: realm.into(),
+        let span = channel_span!(self, "channel.access_request");
+        instrument(span, async move {
+            if !self.status.connected() {
+                return Err(self.status.state_error());
+            }
 
+            let AccessRequestOptions {
                 exclusive,
                 passive,
                 active,
                 write,
                 read,
-            },
-        ));
-
-        let (promise, send_resolver) = Promise::new();
-        if level_enabled!(Level::TRACE) {
-            promise.set_marker("access.request".into());
-        }
-        let ((promise, resolver), promise_out) = (Promise::new(), promise);
-        if level_enabled!(Level::TRACE) {
-            promise.set_marker("access.request.Ok".into());
-        }
-        self.send_method_frame(
-            method,
-            send_resolver,
-            Some(ExpectedReply(
-                Reply::AccessRequestOk(resolver.clone()),
-                Box::new(resolver),
-            )),
-        );
-        promise_out.await?;
-        promise.await
+            } = options;
+            let method = AMQPClass::Access(protocol::access::AMQPMethod::Request(
+                protocol::access::Request {
+                    realm: realm.into(),
+
+                    exclusive,
+                    passive,
+                    active,
+                    write,
+                    read,
+                },
+            ));
+            self.check_fields(&method)?;
+
+            let (promise, send_resolver) = Promise::new();
+            if level_enabled!(Level::TRACE) {
+                promise.set_marker("access.request".into());
+            }
+            let ((promise, resolver), promise_out) = (Promise::new(), promise);
+            if level_enabled!(Level::TRACE) {
+                promise.set_marker("access.request.Ok".into());
+            }
+            self.send_method_frame(
+                method,
+                send_resolver,
+                Some(ExpectedReply(
+                    Reply::AccessRequestOk(resolver.clone()),
+                    Box::new(resolver),
+                )),
+            );
+            promise_out.await?;
+            promise.await
+        })
+        .await
     }
     fn receive_access_request_ok(&self, method: protocol::access::RequestOk) -> Result<()> {
         if !self.status.can_receive_messages() {
@@ -934,7 +1403,7 @@ impl Channel {
         arguments: FieldTable,
     ) -> Result<()> {
         if !self.status.connected() {
-            return Err(Error::InvalidChannelState(self.status.state()));
+            return Err(self.status.state_error());
         }
 
         let ExchangeDeclareOptions {
@@ -957,6 +1426,7 @@ impl Channel {
                 arguments,
             },
         ));
+        self.check_fields(&method)?;
 
         let (promise, send_resolver) = Promise::new();
         if level_enabled!(Level::TRACE) {
@@ -1005,38 +1475,44 @@ impl Channel {
         exchange: &str,
         options: ExchangeDeleteOptions,
     ) -> Result<()> {
-        if !self.status.connected() {
-            return Err(Error::InvalidChannelState(self.status.state()));
-        }
-
-        let ExchangeDeleteOptions { if_unused, nowait } = options;
-        let method = AMQPClass::Exchange(protocol::exchange::AMQPMethod::Delete(
-            protocol::exchange::Delete {
-                exchange: exchange.into(),
-
-                if_unused,
-                nowait,
-            },
-        ));
+        let span = channel_span!(self, "channel.exchange_delete");
+        instrument(span, async move {
+            if !self.status.connected() {
+                return Err(self.status.state_error());
+            }
 
-        let (promise, send_resolver) = Promise::new();
-        if level_enabled!(Level::TRACE) {
-            promise.set_marker("exchange.delete".into());
-        }
-        let ((promise, resolver), promise_out) = (Promise::new(), promise);
-        if level_enabled!(Level::TRACE) {
-            promise.set_marker("exchange.delete.Ok".into());
-        }
-        self.send_method_frame(
-            method,
-            send_resolver,
-            Some(ExpectedReply(
-                Reply::ExchangeDeleteOk(resolver.clone()),
-                Box::new(resolver),
-            )),
-        );
-        promise_out.await?;
-        promise.await
+            self.before_exchange_delete(exchange);
+            let ExchangeDeleteOptions { if_unused, nowait } = options;
+            let method = AMQPClass::Exchange(protocol::exchange::AMQPMethod::Delete(
+                protocol::exchange::Delete {
+                    exchange: exchange.into(),
+
+                    if_unused,
+                    nowait,
+                },
+            ));
+            self.check_fields(&method)?;
+
+            let (promise, send_resolver) = Promise::new();
+            if level_enabled!(Level::TRACE) {
+                promise.set_marker("exchange.delete".into());
+            }
+            let ((promise, resolver), promise_out) = (Promise::new(), promise);
+            if level_enabled!(Level::TRACE) {
+                promise.set_marker("exchange.delete.Ok".into());
+            }
+            self.send_method_frame(
+                method,
+                send_resolver,
+                Some(ExpectedReply(
+                    Reply::ExchangeDeleteOk(resolver.clone()),
+                    Box::new(resolver),
+                )),
+            );
+            promise_out.await?;
+            promise.await
+        })
+        .await
     }
     fn receive_exchange_delete_ok(&self, method: protocol::exchange::DeleteOk) -> Result<()> {
         if !self.status.can_receive_messages() {
@@ -1068,40 +1544,45 @@ impl Channel {
         options: ExchangeBindOptions,
         arguments: FieldTable,
     ) -> Result<()> {
-        if !self.status.connected() {
-            return Err(Error::InvalidChannelState(self.status.state()));
-        }
-
-        let ExchangeBindOptions { nowait } = options;
-        let method = AMQPClass::Exchange(protocol::exchange::AMQPMethod::Bind(
-            protocol::exchange::Bind {
-                destination: destination.into(),
-                source: source.into(),
-                routing_key: routing_key.into(),
-
-                nowait,
-                arguments,
-            },
-        ));
+        let span = channel_span!(self, "channel.exchange_bind");
+        instrument(span, async move {
+            if !self.status.connected() {
+                return Err(self.status.state_error());
+            }
 
-        let (promise, send_resolver) = Promise::new();
-        if level_enabled!(Level::TRACE) {
-            promise.set_marker("exchange.bind".into());
-        }
-        let ((promise, resolver), promise_out) = (Promise::new(), promise);
-        if level_enabled!(Level::TRACE) {
-            promise.set_marker("exchange.bind.Ok".into());
-        }
-        self.send_method_frame(
-            method,
-            send_resolver,
-            Some(ExpectedReply(
-                Reply::ExchangeBindOk(resolver.clone()),
-                Box::new(resolver),
-            )),
-        );
-        promise_out.await?;
-        promise.await
+            let ExchangeBindOptions { nowait } = options;
+            let method = AMQPClass::Exchange(protocol::exchange::AMQPMethod::Bind(
+                protocol::exchange::Bind {
+                    destination: destination.into(),
+                    source: source.into(),
+                    routing_key: routing_key.into(),
+
+                    nowait,
+                    arguments,
+                },
+            ));
+            self.check_fields(&method)?;
+
+            let (promise, send_resolver) = Promise::new();
+            if level_enabled!(Level::TRACE) {
+                promise.set_marker("exchange.bind".into());
+            }
+            let ((promise, resolver), promise_out) = (Promise::new(), promise);
+            if level_enabled!(Level::TRACE) {
+                promise.set_marker("exchange.bind.Ok".into());
+            }
+            self.send_method_frame(
+                method,
+                send_resolver,
+                Some(ExpectedReply(
+                    Reply::ExchangeBindOk(resolver.clone()),
+                    Box::new(resolver),
+                )),
+            );
+            promise_out.await?;
+            promise.await
+        })
+        .await
     }
     fn receive_exchange_bind_ok(&self, method: protocol::exchange::BindOk) -> Result<()> {
         if !self.status.can_receive_messages() {
@@ -1133,40 +1614,45 @@ impl Channel {
         options: ExchangeUnbindOptions,
         arguments: FieldTable,
     ) -> Result<()> {
-        if !self.status.connected() {
-            return Err(Error::InvalidChannelState(self.status.state()));
-        }
-
-        let ExchangeUnbindOptions { nowait } = options;
-        let method = AMQPClass::Exchange(protocol::exchange::AMQPMethod::Unbind(
-            protocol::exchange::Unbind {
-                destination: destination.into(),
-                source: source.into(),
-                routing_key: routing_key.into(),
-
-                nowait,
-                arguments,
-            },
-        ));
+        let span = channel_span!(self, "channel.exchange_unbind");
+        instrument(span, async move {
+            if !self.status.connected() {
+                return Err(self.status.state_error());
+            }
 
-        let (promise, send_resolver) = Promise::new();
-        if level_enabled!(Level::TRACE) {
-            promise.set_marker("exchange.unbind".into());
-        }
-        let ((promise, resolver), promise_out) = (Promise::new(), promise);
-        if level_enabled!(Level::TRACE) {
-            promise.set_marker("exchange.unbind.Ok".into());
-        }
-        self.send_method_frame(
-            method,
-            send_resolver,
-            Some(ExpectedReply(
-                Reply::ExchangeUnbindOk(resolver.clone()),
-                Box::new(resolver),
-            )),
-        );
-        promise_out.await?;
-        promise.await
+            let ExchangeUnbindOptions { nowait } = options;
+            let method = AMQPClass::Exchange(protocol::exchange::AMQPMethod::Unbind(
+                protocol::exchange::Unbind {
+                    destination: destination.into(),
+                    source: source.into(),
+                    routing_key: routing_key.into(),
+
+                    nowait,
+                    arguments,
+                },
+            ));
+            self.check_fields(&method)?;
+
+            let (promise, send_resolver) = Promise::new();
+            if level_enabled!(Level::TRACE) {
+                promise.set_marker("exchange.unbind".into());
+            }
+            let ((promise, resolver), promise_out) = (Promise::new(), promise);
+            if level_enabled!(Level::TRACE) {
+                promise.set_marker("exchange.unbind.Ok".into());
+            }
+            self.send_method_frame(
+                method,
+                send_resolver,
+                Some(ExpectedReply(
+                    Reply::ExchangeUnbindOk(resolver.clone()),
+                    Box::new(resolver),
+                )),
+            );
+            promise_out.await?;
+            promise.await
+        })
+        .await
     }
     fn receive_exchange_unbind_ok(&self, method: protocol::exchange::UnbindOk) -> Result<()> {
         if !self.status.can_receive_messages() {
@@ -1190,14 +1676,14 @@ impl Channel {
         }
     }
     #[allow(clippy::too_many_arguments)]
-    pub async fn queue_declare(
+    async fn do_queue_declare(
         &self,
         queue: &str,
         options: QueueDeclareOptions,
         arguments: FieldTable,
     ) -> Result<Queue> {
         if !self.status.connected() {
-            return Err(Error::InvalidChannelState(self.status.state()));
+            return Err(self.status.state_error());
         }
 
         let QueueDeclareOptions {
@@ -1219,6 +1705,7 @@ impl Channel {
                 arguments,
             },
         ));
+        self.check_fields(&method)?;
 
         let (promise, send_resolver) = Promise::new();
         if level_enabled!(Level::TRACE) {
@@ -1265,7 +1752,7 @@ impl Channel {
         }
     }
     #[allow(clippy::too_many_arguments)]
-    pub async fn queue_bind(
+    async fn do_queue_bind(
         &self,
         queue: &str,
         exchange: &str,
@@ -1274,7 +1761,7 @@ impl Channel {
         arguments: FieldTable,
     ) -> Result<()> {
         if !self.status.connected() {
-            return Err(Error::InvalidChannelState(self.status.state()));
+            return Err(self.status.state_error());
         }
 
         let QueueBindOptions { nowait } = options;
@@ -1286,6 +1773,7 @@ impl Channel {
             nowait,
             arguments,
         }));
+        self.check_fields(&method)?;
 
         let (promise, send_resolver) = Promise::new();
         if level_enabled!(Level::TRACE) {
@@ -1325,9 +1813,9 @@ impl Channel {
         }
     }
     #[allow(clippy::too_many_arguments)]
-    pub async fn queue_purge(&self, queue: &str, options: QueuePurgeOptions) -> Result<LongUInt> {
+    async fn do_queue_purge(&self, queue: &str, options: QueuePurgeOptions) -> Result<LongUInt> {
         if !self.status.connected() {
-            return Err(Error::InvalidChannelState(self.status.state()));
+            return Err(self.status.state_error());
         }
 
         let QueuePurgeOptions { nowait } = options;
@@ -1336,6 +1824,7 @@ impl Channel {
 
             nowait,
         }));
+        self.check_fields(&method)?;
 
         let (promise, send_resolver) = Promise::new();
         if level_enabled!(Level::TRACE) {
@@ -1373,11 +1862,12 @@ impl Channel {
         }
     }
     #[allow(clippy::too_many_arguments)]
-    pub async fn queue_delete(&self, queue: &str, options: QueueDeleteOptions) -> Result<LongUInt> {
+    async fn do_queue_delete(&self, queue: &str, options: QueueDeleteOptions) -> Result<LongUInt> {
         if !self.status.connected() {
-            return Err(Error::InvalidChannelState(self.status.state()));
+            return Err(self.status.state_error());
         }
 
+        self.before_queue_delete(queue);
         let QueueDeleteOptions {
             if_unused,
             if_empty,
@@ -1392,6 +1882,7 @@ impl Channel {
                 nowait,
             },
         ));
+        self.check_fields(&method)?;
 
         let (promise, send_resolver) = Promise::new();
         if level_enabled!(Level::TRACE) {
@@ -1441,37 +1932,43 @@ impl Channel {
         routing_key: &str,
         arguments: FieldTable,
     ) -> Result<()> {
-        if !self.status.connected() {
-            return Err(Error::InvalidChannelState(self.status.state()));
-        }
-
-        let method = AMQPClass::Queue(protocol::queue::AMQPMethod::Unbind(
-            protocol::queue::Unbind {
-                queue: queue.into(),
-                exchange: exchange.into(),
-                routing_key: routing_key.into(),
-                arguments,
-            },
-        ));
+        let span = channel_span!(self, "channel.queue_unbind");
+        instrument(span, async move {
+            if !self.status.connected() {
+                return Err(self.status.state_error());
+            }
 
-        let (promise, send_resolver) = Promise::new();
-        if level_enabled!(Level::TRACE) {
-            promise.set_marker("queue.unbind".into());
-        }
-        let ((promise, resolver), promise_out) = (Promise::new(), promise);
-        if level_enabled!(Level::TRACE) {
-            promise.set_marker("queue.unbind.Ok".into());
-        }
-        self.send_method_frame(
-            method,
-            send_resolver,
-            Some(ExpectedReply(
-                Reply::QueueUnbindOk(resolver.clone()),
-                Box::new(resolver),
-            )),
-        );
-        promise_out.await?;
-        promise.await
+            self.before_queue_unbind(queue, exchange, routing_key);
+            let method = AMQPClass::Queue(protocol::queue::AMQPMethod::Unbind(
+                protocol::queue::Unbind {
+                    queue: queue.into(),
+                    exchange: exchange.into(),
+                    routing_key: routing_key.into(),
+                    arguments,
+                },
+            ));
+            self.check_fields(&method)?;
+
+            let (promise, send_resolver) = Promise::new();
+            if level_enabled!(Level::TRACE) {
+                promise.set_marker("queue.unbind".into());
+            }
+            let ((promise, resolver), promise_out) = (Promise::new(), promise);
+            if level_enabled!(Level::TRACE) {
+                promise.set_marker("queue.unbind.Ok".into());
+            }
+            self.send_method_frame(
+                method,
+                send_resolver,
+                Some(ExpectedReply(
+                    Reply::QueueUnbindOk(resolver.clone()),
+                    Box::new(resolver),
+                )),
+            );
+            promise_out.await?;
+            promise.await
+        })
+        .await
     }
     fn receive_queue_unbind_ok(&self, method: protocol::queue::UnbindOk) -> Result<()> {
         if !self.status.can_receive_messages() {
@@ -1492,13 +1989,13 @@ impl Channel {
         }
     }
     #[allow(clippy::too_many_arguments)]
-    pub async fn basic_qos(
+    async fn do_basic_qos(
         &self,
         prefetch_count: ShortUInt,
         options: BasicQosOptions,
     ) -> Result<()> {
         if !self.status.connected() {
-            return Err(Error::InvalidChannelState(self.status.state()));
+            return Err(self.status.state_error());
         }
 
         let BasicQosOptions { global } = options;
@@ -1507,6 +2004,7 @@ impl Channel {
 
             global,
         }));
+        self.check_fields(&method)?;
 
         let (promise, send_resolver) = Promise::new();
         if level_enabled!(Level::TRACE) {
@@ -1546,7 +2044,7 @@ impl Channel {
         }
     }
     #[allow(clippy::too_many_arguments)]
-    pub async fn basic_consume(
+    async fn do_basic_consume(
         &self,
         queue: &str,
         consumer_tag: &str,
@@ -1554,7 +2052,7 @@ impl Channel {
         arguments: FieldTable,
     ) -> Result<Consumer> {
         if !self.status.connected() {
-            return Err(Error::InvalidChannelState(self.status.state()));
+            return Err(self.status.state_error());
         }
 
         let BasicConsumeOptions {
@@ -1575,6 +2073,7 @@ impl Channel {
                 arguments,
             },
         ));
+        self.check_fields(&method)?;
 
         let (promise, send_resolver) = Promise::new();
         if level_enabled!(Level::TRACE) {
@@ -1588,7 +2087,7 @@ impl Channel {
             method,
             send_resolver,
             Some(ExpectedReply(
-                Reply::BasicConsumeOk(resolver.clone(), queue.into()),
+                Reply::BasicConsumeOk(resolver.clone(), queue.into(), consumer_tag.into(), no_ack),
                 Box::new(resolver),
             )),
         );
@@ -1606,8 +2105,8 @@ impl Channel {
         }
 
         match self.frames.next_expected_reply(self.id) {
-            Some(Reply::BasicConsumeOk(resolver, queue)) => {
-                self.on_basic_consume_ok_received(method, resolver, queue)
+            Some(Reply::BasicConsumeOk(resolver, queue, consumer_tag, no_ack)) => {
+                self.on_basic_consume_ok_received(method, resolver, queue, consumer_tag, no_ack)
             }
             _ => self.handle_invalid_contents(
                 format!(
@@ -1625,42 +2124,47 @@ impl Channel {
         consumer_tag: &str,
         options: BasicCancelOptions,
     ) -> Result<()> {
-        if !self.status.connected() {
-            return Err(Error::InvalidChannelState(self.status.state()));
-        }
+        let span = channel_span!(self, "channel.basic_cancel");
+        instrument(span, async move {
+            if !self.status.connected() {
+                return Err(self.status.state_error());
+            }
 
-        let BasicCancelOptions { nowait } = options;
-        let method = AMQPClass::Basic(protocol::basic::AMQPMethod::Cancel(
-            protocol::basic::Cancel {
-                consumer_tag: consumer_tag.into(),
+            let BasicCancelOptions { nowait } = options;
+            let method = AMQPClass::Basic(protocol::basic::AMQPMethod::Cancel(
+                protocol::basic::Cancel {
+                    consumer_tag: consumer_tag.into(),
 
-                nowait,
-            },
-        ));
+                    nowait,
+                },
+            ));
+            self.check_fields(&method)?;
 
-        let (promise, send_resolver) = Promise::new();
-        if level_enabled!(Level::TRACE) {
-            promise.set_marker("basic.cancel".into());
-        }
-        let ((promise, resolver), promise_out) = (Promise::new(), promise);
-        if level_enabled!(Level::TRACE) {
-            promise.set_marker("basic.cancel.Ok".into());
-        }
-        self.send_method_frame(
-            method,
-            send_resolver,
-            Some(ExpectedReply(
-                Reply::BasicCancelOk(resolver.clone()),
-                Box::new(resolver),
-            )),
-        );
-        if nowait {
-            self.receive_basic_cancel_ok(protocol::basic::CancelOk {
-                consumer_tag: consumer_tag.into(),
-            })?;
-        }
-        promise_out.await?;
-        promise.await
+            let (promise, send_resolver) = Promise::new();
+            if level_enabled!(Level::TRACE) {
+                promise.set_marker("basic.cancel".into());
+            }
+            let ((promise, resolver), promise_out) = (Promise::new(), promise);
+            if level_enabled!(Level::TRACE) {
+                promise.set_marker("basic.cancel.Ok".into());
+            }
+            self.send_method_frame(
+                method,
+                send_resolver,
+                Some(ExpectedReply(
+                    Reply::BasicCancelOk(resolver.clone()),
+                    Box::new(resolver),
+                )),
+            );
+            if nowait {
+                self.receive_basic_cancel_ok(protocol::basic::CancelOk {
+                    consumer_tag: consumer_tag.into(),
+                })?;
+            }
+            promise_out.await?;
+            promise.await
+        })
+        .await
     }
 
     fn receive_basic_cancel(&self, method: protocol::basic::Cancel) -> Result<()> {
@@ -1671,22 +2175,27 @@ impl Channel {
     }
     #[allow(clippy::too_many_arguments)]
     async fn basic_cancel_ok(&self, consumer_tag: &str) -> Result<()> {
-        if !self.status.connected() {
-            return Err(Error::InvalidChannelState(self.status.state()));
-        }
+        let span = channel_span!(self, "channel.basic_cancel_ok");
+        instrument(span, async move {
+            if !self.status.connected() {
+                return Err(self.status.state_error());
+            }
 
-        let method = AMQPClass::Basic(protocol::basic::AMQPMethod::CancelOk(
-            protocol::basic::CancelOk {
-                consumer_tag: consumer_tag.into(),
-            },
-        ));
+            let method = AMQPClass::Basic(protocol::basic::AMQPMethod::CancelOk(
+                protocol::basic::CancelOk {
+                    consumer_tag: consumer_tag.into(),
+                },
+            ));
+            self.check_fields(&method)?;
 
-        let (promise, send_resolver) = Promise::new();
-        if level_enabled!(Level::TRACE) {
-            promise.set_marker("basic.cancel-ok".into());
-        }
-        self.send_method_frame(method, send_resolver, None);
-        promise.await
+            let (promise, send_resolver) = Promise::new();
+            if level_enabled!(Level::TRACE) {
+                promise.set_marker("basic.cancel-ok".into());
+            }
+            self.send_method_frame(method, send_resolver, None);
+            promise.await
+        })
+        .await
     }
     fn receive_basic_cancel_ok(&self, method: protocol::basic::CancelOk) -> Result<()> {
         if !self.status.can_receive_messages() {
@@ -1707,7 +2216,7 @@ impl Channel {
         }
     }
     #[allow(clippy::too_many_arguments)]
-    pub async fn basic_publish(
+    async fn do_basic_publish(
         &self,
         exchange: &str,
         routing_key: &str,
@@ -1716,10 +2225,9 @@ impl Channel {
         properties: BasicProperties,
     ) -> Result<PublisherConfirm> {
         if !self.status.connected() {
-            return Err(Error::InvalidChannelState(self.status.state()));
+            return Err(self.status.state_error());
         }
 
-        let start_hook_res = self.before_basic_publish();
         let BasicPublishOptions {
             mandatory,
             immediate,
@@ -1733,8 +2241,9 @@ impl Channel {
                 immediate,
             },
         ));
+        self.check_fields(&method)?;
 
-        self.send_method_frame_with_body(method, payload, properties, start_hook_res)
+        self.send_method_frame_with_body(method, payload, properties)
             .await
     }
 
@@ -1757,35 +2266,40 @@ impl Channel {
         queue: &str,
         options: BasicGetOptions,
     ) -> Result<Option<BasicGetMessage>> {
-        if !self.status.connected() {
-            return Err(Error::InvalidChannelState(self.status.state()));
-        }
+        let span = channel_span!(self, "channel.basic_get");
+        instrument(span, async move {
+            if !self.status.connected() {
+                return Err(self.status.state_error());
+            }
 
-        let BasicGetOptions { no_ack } = options;
-        let method = AMQPClass::Basic(protocol::basic::AMQPMethod::Get(protocol::basic::Get {
-            queue: queue.into(),
+            let BasicGetOptions { no_ack } = options;
+            let method = AMQPClass::Basic(protocol::basic::AMQPMethod::Get(protocol::basic::Get {
+                queue: queue.into(),
 
-            no_ack,
-        }));
+                no_ack,
+            }));
+            self.check_fields(&method)?;
 
-        let (promise, send_resolver) = Promise::new();
-        if level_enabled!(Level::TRACE) {
-            promise.set_marker("basic.get".into());
-        }
-        let ((promise, resolver), promise_out) = (Promise::new(), promise);
-        if level_enabled!(Level::TRACE) {
-            promise.set_marker("basic.get.Ok".into());
-        }
-        self.send_method_frame(
-            method,
-            send_resolver,
-            Some(ExpectedReply(
-                Reply::BasicGetOk(resolver.clone(), queue.into()),
-                Box::new(resolver),
-            )),
-        );
-        promise_out.await?;
-        promise.await
+            let (promise, send_resolver) = Promise::new();
+            if level_enabled!(Level::TRACE) {
+                promise.set_marker("basic.get".into());
+            }
+            let ((promise, resolver), promise_out) = (Promise::new(), promise);
+            if level_enabled!(Level::TRACE) {
+                promise.set_marker("basic.get.Ok".into());
+            }
+            self.send_method_frame(
+                method,
+                send_resolver,
+                Some(ExpectedReply(
+                    Reply::BasicGetOk(resolver.clone(), queue.into(), no_ack),
+                    Box::new(resolver),
+                )),
+            );
+            promise_out.await?;
+            promise.await
+        })
+        .await
     }
     fn receive_basic_get_ok(&self, method: protocol::basic::GetOk) -> Result<()> {
         if !self.status.can_receive_messages() {
@@ -1793,8 +2307,8 @@ impl Channel {
         }
 
         match self.frames.next_expected_reply(self.id) {
-            Some(Reply::BasicGetOk(resolver, queue)) => {
-                self.on_basic_get_ok_received(method, resolver, queue)
+            Some(Reply::BasicGetOk(resolver, queue, no_ack)) => {
+                self.on_basic_get_ok_received(method, resolver, queue, no_ack)
             }
             _ => self.handle_invalid_contents(
                 format!("unexepcted basic get-ok received on channel {}", self.id),
@@ -1816,24 +2330,29 @@ impl Channel {
         delivery_tag: LongLongUInt,
         options: BasicAckOptions,
     ) -> Result<()> {
-        if !self.status.connected() {
-            return Err(Error::InvalidChannelState(self.status.state()));
-        }
+        let span = channel_span!(self, "channel.basic_ack");
+        instrument(span, async move {
+            if !self.status.connected() {
+                return Err(self.status.state_error());
+            }
 
-        let BasicAckOptions { multiple } = options;
-        let method = AMQPClass::Basic(protocol::basic::AMQPMethod::Ack(protocol::basic::Ack {
-            delivery_tag,
+            let BasicAckOptions { multiple } = options;
+            let method = AMQPClass::Basic(protocol::basic::AMQPMethod::Ack(protocol::basic::Ack {
+                delivery_tag,
 
-            multiple,
-        }));
+                multiple,
+            }));
+            self.check_fields(&method)?;
 
-        let (promise, send_resolver) = Promise::new();
-        if level_enabled!(Level::TRACE) {
-            promise.set_marker("basic.ack".into());
-        }
-        self.send_method_frame(method, send_resolver, None);
-        self.on_basic_ack_sent(multiple, delivery_tag);
-        promise.await
+            let (promise, send_resolver) = Promise::new();
+            if level_enabled!(Level::TRACE) {
+                promise.set_marker("basic.ack".into());
+            }
+            self.send_method_frame(method, send_resolver, None);
+            self.on_basic_ack_sent(multiple, delivery_tag);
+            promise.await
+        })
+        .await
     }
 
     fn receive_basic_ack(&self, method: protocol::basic::Ack) -> Result<()> {
@@ -1848,74 +2367,90 @@ impl Channel {
         delivery_tag: LongLongUInt,
         options: BasicRejectOptions,
     ) -> Result<()> {
-        if !self.status.connected() {
-            return Err(Error::InvalidChannelState(self.status.state()));
-        }
+        let span = channel_span!(self, "channel.basic_reject");
+        instrument(span, async move {
+            if !self.status.connected() {
+                return Err(self.status.state_error());
+            }
 
-        let BasicRejectOptions { requeue } = options;
-        let method = AMQPClass::Basic(protocol::basic::AMQPMethod::Reject(
-            protocol::basic::Reject {
-                delivery_tag,
+            let BasicRejectOptions { requeue } = options;
+            let method = AMQPClass::Basic(protocol::basic::AMQPMethod::Reject(
+                protocol::basic::Reject {
+                    delivery_tag,
 
-                requeue,
-            },
-        ));
+                    requeue,
+                },
+            ));
+            self.check_fields(&method)?;
 
-        let (promise, send_resolver) = Promise::new();
-        if level_enabled!(Level::TRACE) {
-            promise.set_marker("basic.reject".into());
-        }
-        self.send_method_frame(method, send_resolver, None);
-        promise.await
+            let (promise, send_resolver) = Promise::new();
+            if level_enabled!(Level::TRACE) {
+                promise.set_marker("basic.reject".into());
+            }
+            self.send_method_frame(method, send_resolver, None);
+            self.on_basic_reject_sent(delivery_tag);
+            promise.await
+        })
+        .await
     }
     #[allow(clippy::too_many_arguments)]
     pub async fn basic_recover_async(&self, options: BasicRecoverAsyncOptions) -> Result<()> {
-        if !self.status.connected() {
-            return Err(Error::InvalidChannelState(self.status.state()));
-        }
+        let span = channel_span!(self, "channel.basic_recover_async");
+        instrument(span, async move {
+            if !self.status.connected() {
+                return Err(self.status.state_error());
+            }
 
-        let BasicRecoverAsyncOptions { requeue } = options;
-        let method = AMQPClass::Basic(protocol::basic::AMQPMethod::RecoverAsync(
-            protocol::basic::RecoverAsync { requeue },
-        ));
+            let BasicRecoverAsyncOptions { requeue } = options;
+            let method = AMQPClass::Basic(protocol::basic::AMQPMethod::RecoverAsync(
+                protocol::basic::RecoverAsync { requeue },
+            ));
+            self.check_fields(&method)?;
 
-        let (promise, send_resolver) = Promise::new();
-        if level_enabled!(Level::TRACE) {
-            promise.set_marker("basic.recover-async".into());
-        }
-        self.send_method_frame(method, send_resolver, None);
-        self.on_basic_recover_async_sent();
-        promise.await
+            let (promise, send_resolver) = Promise::new();
+            if level_enabled!(Level::TRACE) {
+                promise.set_marker("basic.recover-async".into());
+            }
+            self.send_method_frame(method, send_resolver, None);
+            self.on_basic_recover_async_sent();
+            promise.await
+        })
+        .await
     }
     #[allow(clippy::too_many_arguments)]
     pub async fn basic_recover(&self, options: BasicRecoverOptions) -> Result<()> {
-        if !self.status.connected() {
-            return Err(Error::InvalidChannelState(self.status.state()));
-        }
+        let span = channel_span!(self, "channel.basic_recover");
+        instrument(span, async move {
+            if !self.status.connected() {
+                return Err(self.status.state_error());
+            }
 
-        let BasicRecoverOptions { requeue } = options;
-        let method = AMQPClass::Basic(protocol::basic::AMQPMethod::Recover(
-            protocol::basic::Recover { requeue },
-        ));
+            let BasicRecoverOptions { requeue } = options;
+            let method = AMQPClass::Basic(protocol::basic::AMQPMethod::Recover(
+                protocol::basic::Recover { requeue },
+            ));
+            self.check_fields(&method)?;
 
-        let (promise, send_resolver) = Promise::new();
-        if level_enabled!(Level::TRACE) {
-            promise.set_marker("basic.recover".into());
-        }
-        let ((promise, resolver), promise_out) = (Promise::new(), promise);
-        if level_enabled!(Level::TRACE) {
-            promise.set_marker("basic.recover.Ok".into());
-        }
-        self.send_method_frame(
-            method,
-            send_resolver,
-            Some(ExpectedReply(
-                Reply::BasicRecoverOk(resolver.clone()),
-                Box::new(resolver),
-            )),
-        );
-        promise_out.await?;
-        promise.await
+            let (promise, send_resolver) = Promise::new();
+            if level_enabled!(Level::TRACE) {
+                promise.set_marker("basic.recover".into());
+            }
+            let ((promise, resolver), promise_out) = (Promise::new(), promise);
+            if level_enabled!(Level::TRACE) {
+                promise.set_marker("basic.recover.Ok".into());
+            }
+            self.send_method_frame(
+                method,
+                send_resolver,
+                Some(ExpectedReply(
+                    Reply::BasicRecoverOk(resolver.clone()),
+                    Box::new(resolver),
+                )),
+            );
+            promise_out.await?;
+            promise.await
+        })
+        .await
     }
     fn receive_basic_recover_ok(&self, method: protocol::basic::RecoverOk) -> Result<()> {
         if !self.status.can_receive_messages() {
@@ -1944,25 +2479,31 @@ impl Channel {
         delivery_tag: LongLongUInt,
         options: BasicNackOptions,
     ) -> Result<()> {
-        if !self.status.connected() {
-            return Err(Error::InvalidChannelState(self.status.state()));
-        }
+        let span = channel_span!(self, "channel.basic_nack");
+        instrument(span, async move {
+            if !self.status.connected() {
+                return Err(self.status.state_error());
+            }
 
-        let BasicNackOptions { multiple, requeue } = options;
-        let method = AMQPClass::Basic(protocol::basic::AMQPMethod::Nack(protocol::basic::Nack {
-            delivery_tag,
+            let BasicNackOptions { multiple, requeue } = options;
+            let method =
+                AMQPClass::Basic(protocol::basic::AMQPMethod::Nack(protocol::basic::Nack {
+                    delivery_tag,
 
-            multiple,
-            requeue,
-        }));
+                    multiple,
+                    requeue,
+                }));
+            self.check_fields(&method)?;
 
-        let (promise, send_resolver) = Promise::new();
-        if level_enabled!(Level::TRACE) {
-            promise.set_marker("basic.nack".into());
-        }
-        self.send_method_frame(method, send_resolver, None);
-        self.on_basic_nack_sent(multiple, delivery_tag);
-        promise.await
+            let (promise, send_resolver) = Promise::new();
+            if level_enabled!(Level::TRACE) {
+                promise.set_marker("basic.nack".into());
+            }
+            self.send_method_frame(method, send_resolver, None);
+            self.on_basic_nack_sent(multiple, delivery_tag);
+            promise.await
+        })
+        .await
     }
 
     fn receive_basic_nack(&self, method: protocol::basic::Nack) -> Result<()> {
@@ -1973,30 +2514,35 @@ impl Channel {
     }
     #[allow(clippy::too_many_arguments)]
     pub async fn tx_select(&self) -> Result<()> {
-        if !self.status.connected() {
-            return Err(Error::InvalidChannelState(self.status.state()));
-        }
+        let span = channel_span!(self, "channel.tx_select");
+        instrument(span, async move {
+            if !self.status.connected() {
+                return Err(self.status.state_error());
+            }
 
-        let method = AMQPClass::Tx(protocol::tx::AMQPMethod::Select(protocol::tx::Select {}));
+            let method = AMQPClass::Tx(protocol::tx::AMQPMethod::Select(protocol::tx::Select {}));
+            self.check_fields(&method)?;
 
-        let (promise, send_resolver) = Promise::new();
-        if level_enabled!(Level::TRACE) {
-            promise.set_marker("tx.select".into());
-        }
-        let ((promise, resolver), promise_out) = (Promise::new(), promise);
-        if level_enabled!(Level::TRACE) {
-            promise.set_marker("tx.select.Ok".into());
-        }
-        self.send_method_frame(
-            method,
-            send_resolver,
-            Some(ExpectedReply(
-                Reply::TxSelectOk(resolver.clone()),
-                Box::new(resolver),
-            )),
-        );
-        promise_out.await?;
-        promise.await
+            let (promise, send_resolver) = Promise::new();
+            if level_enabled!(Level::TRACE) {
+                promise.set_marker("tx.select".into());
+            }
+            let ((promise, resolver), promise_out) = (Promise::new(), promise);
+            if level_enabled!(Level::TRACE) {
+                promise.set_marker("tx.select.Ok".into());
+            }
+            self.send_method_frame(
+                method,
+                send_resolver,
+                Some(ExpectedReply(
+                    Reply::TxSelectOk(resolver.clone()),
+                    Box::new(resolver),
+                )),
+            );
+            promise_out.await?;
+            promise.await
+        })
+        .await
     }
     fn receive_tx_select_ok(&self, method: protocol::tx::SelectOk) -> Result<()> {
         if !self.status.can_receive_messages() {
@@ -2018,30 +2564,35 @@ impl Channel {
     }
     #[allow(clippy::too_many_arguments)]
     pub async fn tx_commit(&self) -> Result<()> {
-        if !self.status.connected() {
-            return Err(Error::InvalidChannelState(self.status.state()));
-        }
+        let span = channel_span!(self, "channel.tx_commit");
+        instrument(span, async move {
+            if !self.status.connected() {
+                return Err(self.status.state_error());
+            }
 
-        let method = AMQPClass::Tx(protocol::tx::AMQPMethod::Commit(protocol::tx::Commit {}));
+            let method = AMQPClass::Tx(protocol::tx::AMQPMethod::Commit(protocol::tx::Commit {}));
+            self.check_fields(&method)?;
 
-        let (promise, send_resolver) = Promise::new();
-        if level_enabled!(Level::TRACE) {
-            promise.set_marker("tx.commit".into());
-        }
-        let ((promise, resolver), promise_out) = (Promise::new(), promise);
-        if level_enabled!(Level::TRACE) {
-            promise.set_marker("tx.commit.Ok".into());
-        }
-        self.send_method_frame(
-            method,
-            send_resolver,
-            Some(ExpectedReply(
-                Reply::TxCommitOk(resolver.clone()),
-                Box::new(resolver),
-            )),
-        );
-        promise_out.await?;
-        promise.await
+            let (promise, send_resolver) = Promise::new();
+            if level_enabled!(Level::TRACE) {
+                promise.set_marker("tx.commit".into());
+            }
+            let ((promise, resolver), promise_out) = (Promise::new(), promise);
+            if level_enabled!(Level::TRACE) {
+                promise.set_marker("tx.commit.Ok".into());
+            }
+            self.send_method_frame(
+                method,
+                send_resolver,
+                Some(ExpectedReply(
+                    Reply::TxCommitOk(resolver.clone()),
+                    Box::new(resolver),
+                )),
+            );
+            promise_out.await?;
+            promise.await
+        })
+        .await
     }
     fn receive_tx_commit_ok(&self, method: protocol::tx::CommitOk) -> Result<()> {
         if !self.status.can_receive_messages() {
@@ -2063,32 +2614,37 @@ impl Channel {
     }
     #[allow(clippy::too_many_arguments)]
     pub async fn tx_rollback(&self) -> Result<()> {
-        if !self.status.connected() {
-            return Err(Error::InvalidChannelState(self.status.state()));
-        }
+        let span = channel_span!(self, "channel.tx_rollback");
+        instrument(span, async move {
+            if !self.status.connected() {
+                return Err(self.status.state_error());
+            }
 
-        let method = AMQPClass::Tx(protocol::tx::AMQPMethod::Rollback(
-            protocol::tx::Rollback {},
-        ));
+            let method = AMQPClass::Tx(protocol::tx::AMQPMethod::Rollback(
+                protocol::tx::Rollback {},
+            ));
+            self.check_fields(&method)?;
 
-        let (promise, send_resolver) = Promise::new();
-        if level_enabled!(Level::TRACE) {
-            promise.set_marker("tx.rollback".into());
-        }
-        let ((promise, resolver), promise_out) = (Promise::new(), promise);
-        if level_enabled!(Level::TRACE) {
-            promise.set_marker("tx.rollback.Ok".into());
-        }
-        self.send_method_frame(
-            method,
-            send_resolver,
-            Some(ExpectedReply(
-                Reply::TxRollbackOk(resolver.clone()),
-                Box::new(resolver),
-            )),
-        );
-        promise_out.await?;
-        promise.await
+            let (promise, send_resolver) = Promise::new();
+            if level_enabled!(Level::TRACE) {
+                promise.set_marker("tx.rollback".into());
+            }
+            let ((promise, resolver), promise_out) = (Promise::new(), promise);
+            if level_enabled!(Level::TRACE) {
+                promise.set_marker("tx.rollback.Ok".into());
+            }
+            self.send_method_frame(
+                method,
+                send_resolver,
+                Some(ExpectedReply(
+                    Reply::TxRollbackOk(resolver.clone()),
+                    Box::new(resolver),
+                )),
+            );
+            promise_out.await?;
+            promise.await
+        })
+        .await
     }
     fn receive_tx_rollback_ok(&self, method: protocol::tx::RollbackOk) -> Result<()> {
         if !self.status.can_receive_messages() {
@@ -2110,33 +2666,38 @@ impl Channel {
     }
     #[allow(clippy::too_many_arguments)]
     pub async fn confirm_select(&self, options: ConfirmSelectOptions) -> Result<()> {
-        if !self.status.connected() {
-            return Err(Error::InvalidChannelState(self.status.state()));
-        }
+        let span = channel_span!(self, "channel.confirm_select");
+        instrument(span, async move {
+            if !self.status.connected() {
+                return Err(self.status.state_error());
+            }
 
-        let ConfirmSelectOptions { nowait } = options;
-        let method = AMQPClass::Confirm(protocol::confirm::AMQPMethod::Select(
-            protocol::confirm::Select { nowait },
-        ));
+            let ConfirmSelectOptions { nowait } = options;
+            let method = AMQPClass::Confirm(protocol::confirm::AMQPMethod::Select(
+                protocol::confirm::Select { nowait },
+            ));
+            self.check_fields(&method)?;
 
-        let (promise, send_resolver) = Promise::new();
-        if level_enabled!(Level::TRACE) {
-            promise.set_marker("confirm.select".into());
-        }
-        let ((promise, resolver), promise_out) = (Promise::new(), promise);
-        if level_enabled!(Level::TRACE) {
-            promise.set_marker("confirm.select.Ok".into());
-        }
-        self.send_method_frame(
-            method,
-            send_resolver,
-            Some(ExpectedReply(
-                Reply::ConfirmSelectOk(resolver.clone()),
-                Box::new(resolver),
-            )),
-        );
-        promise_out.await?;
-        promise.await
+            let (promise, send_resolver) = Promise::new();
+            if level_enabled!(Level::TRACE) {
+                promise.set_marker("confirm.select".into());
+            }
+            let ((promise, resolver), promise_out) = (Promise::new(), promise);
+            if level_enabled!(Level::TRACE) {
+                promise.set_marker("confirm.select.Ok".into());
+            }
+            self.send_method_frame(
+                method,
+                send_resolver,
+                Some(ExpectedReply(
+                    Reply::ConfirmSelectOk(resolver.clone()),
+                    Box::new(resolver),
+                )),
+            );
+            promise_out.await?;
+            promise.await
+        })
+        .await
     }
     fn receive_confirm_select_ok(&self, method: protocol::confirm::SelectOk) -> Result<()> {
         if !self.status.can_receive_messages() {
@@ -0,0 +1,52 @@
+use crate::ConnectionState;
+use std::time::Instant;
+
+/// A snapshot of the health of a connection, see [`Connection::health_check`].
+///
+/// [`Connection::health_check`]: ./struct.Connection.html#method.health_check
+#[derive(Clone, Debug, PartialEq)]
+pub struct HealthStatus {
+    /// The state of the connection
+    pub state: ConnectionState,
+    /// When we last wrote a heartbeat to the socket
+    pub last_heartbeat_sent: Option<Instant>,
+    /// When we last received a frame from the server, heartbeats included
+    pub last_frame_received: Option<Instant>,
+    /// The number of frames waiting to be written, `None` if they were being modified
+    pub pending_frames: Option<usize>,
+    /// The number of open channels, `None` if they were being modified
+    pub channels_open: Option<usize>,
+    /// The number of channels in error, `None` if they were being modified
+    pub channels_errored: Option<usize>,
+}
+
+impl HealthStatus {
+    /// Whether the connection is connected and none of its channels is known to be in error.
+    ///
+    /// When the channels couldn't be counted, their health is unknown and the connection isn't
+    /// reported as healthy.
+    pub fn is_healthy(&self) -> bool {
+        self.state == ConnectionState::Connected && self.channels_errored == Some(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_channels_are_not_healthy() {
+        let mut health = HealthStatus {
+            state: ConnectionState::Connected,
+            last_heartbeat_sent: None,
+            last_frame_received: None,
+            pending_frames: None,
+            channels_open: Some(1),
+            channels_errored: Some(0),
+        };
+        assert!(health.is_healthy());
+        health.channels_open = None;
+        health.channels_errored = None;
+        assert!(!health.is_healthy());
+    }
+}
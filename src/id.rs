@@ -0,0 +1,105 @@
+//! Unique identifiers generation.
+//!
+//! The generated ids look like `ctag-5d1f0e9a33c2b871-42`:
+//!
+//! * a short prefix telling what the id is used for (`ctag` for consumer tags, `corr` for
+//!   correlation ids)
+//! * 16 lowercase hexadecimal digits, randomly chosen once per process, so that ids coming
+//!   from different processes don't collide
+//! * a decimal sequence number, shared by all the ids of the process, so that ids coming from
+//!   the same process don't collide
+//!
+//! This format is stable and can be relied upon to search the logs.
+
+use crate::types::ShortString;
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+static PROCESS_ID: AtomicU64 = AtomicU64::new(0);
+static SEQUENCE: AtomicU64 = AtomicU64::new(0);
+
+/// Generate a unique id starting with `prefix`.
+pub fn generate(prefix: &str) -> ShortString {
+    format!(
+        "{}-{:016x}-{}",
+        prefix,
+        process_id(),
+        SEQUENCE.fetch_add(1, Ordering::Relaxed)
+    )
+    .into()
+}
+
+/// Generate a unique consumer tag.
+pub fn consumer_tag() -> ShortString {
+    generate("ctag")
+}
+
+/// Generate a unique correlation id.
+pub fn correlation_id() -> ShortString {
+    generate("corr")
+}
+
+fn process_id() -> u64 {
+    let id = PROCESS_ID.load(Ordering::Relaxed);
+    if id != 0 {
+        return id;
+    }
+    // 0 means not initialized yet, make sure we never pick it
+    let id = random_u64().max(1);
+    match PROCESS_ID.compare_exchange(0, id, Ordering::Relaxed, Ordering::Relaxed) {
+        Ok(_) => id,
+        // Another thread was faster, keep its value
+        Err(id) => id,
+    }
+}
+
+fn random_u64() -> u64 {
+    // RandomState gets seeded from the OS random source
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u32(std::process::id());
+    if let Ok(now) = SystemTime::now().duration_since(UNIX_EPOCH) {
+        hasher.write_u128(now.as_nanos());
+    }
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{collections::HashSet, thread};
+
+    #[test]
+    fn format() {
+        let id = consumer_tag();
+        let parts = id.as_str().split('-').collect::<Vec<_>>();
+        assert_eq!(parts.len(), 3);
+        assert_eq!(parts[0], "ctag");
+        assert_eq!(parts[1].len(), 16);
+        assert!(parts[1]
+            .chars()
+            .all(|c| c.is_ascii_hexdigit() && !c.is_ascii_uppercase()));
+        assert!(parts[2].parse::<u64>().is_ok());
+
+        let other = correlation_id();
+        assert!(other.as_str().starts_with("corr-"));
+        assert_eq!(other.as_str().split('-').nth(1), Some(parts[1]));
+    }
+
+    #[test]
+    fn unique_across_threads() {
+        let threads = (0..8)
+            .map(|_| thread::spawn(|| (0..10_000).map(|_| consumer_tag()).collect::<Vec<_>>()))
+            .collect::<Vec<_>>();
+        let mut ids = HashSet::new();
+        for thread in threads {
+            for id in thread.join().unwrap() {
+                assert!(ids.insert(id));
+            }
+        }
+        assert_eq!(ids.len(), 80_000);
+    }
+}
@@ -1,6 +1,13 @@
 use crate::{
-    channels::Channels, executor::Executor, socket_state::SocketStateHandle, types::ShortUInt,
-    Error, Result,
+    channels::Channels,
+    connection_closer::ConnectionCloser,
+    executor::Executor,
+    options::BasicCancelOptions,
+    protocol,
+    socket_state::SocketStateHandle,
+    tracing_integration::targets,
+    types::{ShortString, ShortUInt},
+    Channel, Error, PromiseResolver, Result,
 };
 use flume::{Receiver, Sender};
 use std::{future::Future, sync::Arc};
@@ -19,6 +26,22 @@ pub(crate) struct InternalRPCHandle {
 }
 
 impl InternalRPCHandle {
+    pub(crate) fn create_channel(
+        &self,
+        connection_closer: Arc<ConnectionCloser>,
+        resolver: PromiseResolver<Channel>,
+    ) {
+        self.send(InternalCommand::CreateChannel(connection_closer, resolver));
+    }
+
+    pub(crate) fn cancel(&self) {
+        self.send(InternalCommand::Cancel);
+    }
+
+    pub(crate) fn cancel_consumer(&self, channel_id: u16, consumer_tag: ShortString) {
+        self.send(InternalCommand::CancelConsumer(channel_id, consumer_tag));
+    }
+
     pub(crate) fn close_channel(&self, channel_id: u16, reply_code: ShortUInt, reply_text: String) {
         self.send(InternalCommand::CloseChannel(
             channel_id, reply_code, reply_text,
@@ -58,7 +81,7 @@ impl InternalRPCHandle {
     }
 
     fn send(&self, command: InternalCommand) {
-        trace!("Queuing internal RPC command: {:?}", command);
+        trace!(target: targets::CONNECTION, "Queuing internal RPC command: {:?}", command);
         // The only scenario where this can fail if this is the IoLoop already exited
         let _ = self.sender.send(command);
         self.waker.wake();
@@ -79,6 +102,9 @@ impl InternalRPCHandle {
 
 #[derive(Debug)]
 enum InternalCommand {
+    Cancel,
+    CancelConsumer(u16, ShortString),
+    CreateChannel(Arc<ConnectionCloser>, PromiseResolver<Channel>),
     CloseChannel(u16, ShortUInt, String),
     CloseConnection(ShortUInt, String, ShortUInt, ShortUInt),
     SendConnectionCloseOk(Error),
@@ -113,8 +139,39 @@ impl InternalRPC {
     fn run(&self, command: InternalCommand, channels: &Channels) -> Result<()> {
         use InternalCommand::*;
 
-        trace!("Handling internal RPC command: {:?}", command);
+        trace!(target: targets::CONNECTION, "Handling internal RPC command: {:?}", command);
         match command {
+            Cancel => {
+                channels.cancel();
+                self.handle.close_connection(
+                    protocol::constants::REPLY_SUCCESS as ShortUInt,
+                    "OK".to_string(),
+                    0,
+                    0,
+                );
+            }
+            CancelConsumer(channel_id, consumer_tag) => channels
+                .get(channel_id)
+                .map(|channel| {
+                    self.handle.register_internal_future(async move {
+                        if channel.status().connected() {
+                            channel
+                                .basic_cancel(consumer_tag.as_str(), BasicCancelOptions::default())
+                                .await
+                        } else {
+                            Ok(())
+                        }
+                    })
+                })
+                .unwrap_or_default(),
+            CreateChannel(connection_closer, resolver) => {
+                match channels.create(connection_closer) {
+                    Ok(channel) => self.handle.executor.spawn(Box::pin(async move {
+                        resolver.swear(channel.clone().channel_open(channel).await);
+                    })),
+                    Err(err) => resolver.swear(Err(err)),
+                }
+            }
             CloseChannel(channel_id, reply_code, reply_text) => channels
                 .get(channel_id)
                 .map(|channel| {
@@ -1,19 +1,23 @@
 use crate::{
     buffer::Buffer,
     channels::Channels,
+    connection_stats::ConnectionStatsCounters,
     connection_status::ConnectionState,
     executor::Executor,
-    frames::Frames,
+    frame_ordering::FrameOrderingValidator,
+    frames::{FrameResolver, Frames},
     heartbeat::Heartbeat,
     internal_rpc::InternalRPC,
     protocol::{self, AMQPError, AMQPHardError},
-    reactor::{ReactorBuilder, ReactorHandle, Slot},
+    reactor::{ReactorBuilder, ReactorHandle, ReactorSocket, Slot},
     socket_state::SocketState,
     tcp::HandshakeResult,
     thread::ThreadHandle,
-    Configuration, ConnectionStatus, Error, PromiseResolver, Result, TcpStream,
+    tracing_integration::targets,
+    Configuration, ConnectionStatus, Error, Result, TcpStream,
 };
-use amq_protocol::frame::{gen_frame, parse_frame, AMQPFrame, GenError};
+use amq_protocol::frame::{gen_frame, parse_frame, AMQPFrame, GenError, ProtocolVersion};
+use parking_lot::Mutex;
 use std::{
     collections::VecDeque,
     convert::TryFrom,
@@ -25,6 +29,7 @@ use std::{
 use tracing::{debug, error, trace};
 
 const FRAMES_STORAGE: usize = 32;
+const GREETING_DUMP_SIZE: usize = 16;
 
 #[derive(Debug, PartialEq)]
 enum Status {
@@ -43,13 +48,16 @@ pub struct IoLoop {
     socket_state: SocketState,
     reactor: Box<dyn ReactorHandle + Send>,
     connection_io_loop_handle: ThreadHandle,
-    stream: TcpStream,
+    stream: Arc<Mutex<TcpStream>>,
     slot: Slot,
     status: Status,
     frame_size: usize,
     receive_buffer: Buffer,
     send_buffer: Buffer,
-    serialized_frames: VecDeque<(u64, Option<PromiseResolver<()>>)>,
+    serialized_frames: VecDeque<(u64, Option<FrameResolver>)>,
+    stats: ConnectionStatsCounters,
+    received_first_frame: bool,
+    frame_ordering: Option<FrameOrderingValidator>,
 }
 
 impl IoLoop {
@@ -66,7 +74,7 @@ impl IoLoop {
         reactor_builder: &dyn ReactorBuilder,
         executor: Arc<dyn Executor>,
     ) -> Result<Self> {
-        let mut stream = TcpStream::try_from(stream)?;
+        let stream = Arc::new(Mutex::new(TcpStream::try_from(stream)?));
         let heartbeat = Heartbeat::new(channels.clone());
         let mut reactor = reactor_builder.build(heartbeat.clone(), executor);
         let reactor_handle = reactor.handle();
@@ -74,7 +82,13 @@ impl IoLoop {
             protocol::constants::FRAME_MIN_SIZE as usize,
             configuration.frame_max() as usize,
         );
-        let slot = reactor.register(stream.inner_mut(), socket_state.handle())?;
+        let stats = channels.stats_counters();
+        let slot = reactor.register(ReactorSocket::new(stream.clone()), socket_state.handle())?;
+        let frame_ordering = if configuration.strict_frame_ordering() {
+            Some(FrameOrderingValidator::default())
+        } else {
+            None
+        };
 
         Ok(Self {
             connection_status,
@@ -93,6 +107,9 @@ impl IoLoop {
             receive_buffer: Buffer::with_capacity(FRAMES_STORAGE * frame_size),
             send_buffer: Buffer::with_capacity(FRAMES_STORAGE * frame_size),
             serialized_frames: VecDeque::default(),
+            stats,
+            received_first_frame: false,
+            frame_ordering,
         })
     }
 
@@ -108,8 +125,8 @@ impl IoLoop {
                 self.heartbeat.set_timeout(heartbeat);
                 self.reactor.start_heartbeat();
             }
-            let peer = self.stream.inner().peer_addr()?;
-            debug!("Connected to {}", peer);
+            let peer = self.stream.lock().inner().peer_addr()?;
+            debug!(target: targets::CONNECTION, "Connected to {}", peer);
             self.status = Status::Connected;
         }
         Ok(true)
@@ -156,19 +173,25 @@ impl IoLoop {
             ThreadBuilder::new()
                 .name("lapin-io-loop".to_owned())
                 .spawn(move || {
-                    while self.should_continue() {
-                        if let Err(err) = self.run() {
-                            self.critical_error(err)?;
-                        }
-                    }
+                    let res = self.run_until_stopped();
                     self.heartbeat.cancel();
-                    Ok(())
+                    self.reactor.unregister(self.slot);
+                    res
                 })?,
         );
         waker.wake();
         Ok(())
     }
 
+    fn run_until_stopped(&mut self) -> Result<()> {
+        while self.should_continue() {
+            if let Err(err) = self.run() {
+                self.critical_error(err)?;
+            }
+        }
+        Ok(())
+    }
+
     fn poll_internal_rpc(&self) -> Result<()> {
         self.internal_rpc.poll(&self.channels)
     }
@@ -179,12 +202,13 @@ impl IoLoop {
     }
 
     fn run(&mut self) -> Result<()> {
-        trace!("io_loop run");
+        trace!(target: targets::IO, "io_loop run");
         self.poll_socket_events()?;
         if !self.ensure_setup()? {
             return Ok(());
         }
         trace!(
+            target: targets::IO,
             "io_loop do_run; can_read={}, can_write={}, has_data={}",
             self.socket_state.readable(),
             self.socket_state.writable(),
@@ -194,11 +218,14 @@ impl IoLoop {
             self.socket_state.wait();
         }
         self.poll_socket_events()?;
-        if self.stream.is_handshaking() {
-            self.stream.handshake()?;
-            if self.stream.is_handshaking() {
-                // We hit WOULDBLOCK while handshaking, wait for the next socket event
-                return Ok(());
+        {
+            let mut stream = self.stream.lock();
+            if stream.is_handshaking() {
+                stream.handshake()?;
+                if stream.is_handshaking() {
+                    // We hit WOULDBLOCK while handshaking, wait for the next socket event
+                    return Ok(());
+                }
             }
         }
         self.write()?;
@@ -210,6 +237,7 @@ impl IoLoop {
         }
         self.handle_frames()?;
         trace!(
+            target: targets::IO,
             "io_loop do_run done; can_read={}, can_write={}, has_data={}, status={:?}",
             self.socket_state.readable(),
             self.socket_state.writable(),
@@ -238,7 +266,7 @@ impl IoLoop {
             .socket_state
             .handle_read_result(result, &*self.reactor, self.slot)
         {
-            error!("error reading: {:?}", e);
+            error!(target: targets::IO, "error reading: {:?}", e);
             self.critical_error(e)?;
         }
         self.poll_internal_rpc()
@@ -249,14 +277,14 @@ impl IoLoop {
             .socket_state
             .handle_write_result(result, &*self.reactor, self.slot)
         {
-            error!("error writing: {:?}", e);
+            error!(target: targets::IO, "error writing: {:?}", e);
             self.critical_error(e)?;
         }
         self.poll_internal_rpc()
     }
 
     fn flush(&mut self) -> Result<()> {
-        self.stream.flush()?;
+        self.stream.lock().flush()?;
         self.poll_internal_rpc()
     }
 
@@ -284,12 +312,13 @@ impl IoLoop {
         self.flush()?;
         self.serialize()?;
 
-        let sz = self.send_buffer.write_to(&mut self.stream)?;
+        let sz = self.send_buffer.write_to(&mut *self.stream.lock())?;
 
         if sz > 0 {
             self.heartbeat.update_last_write();
 
-            trace!("wrote {} bytes", sz);
+            trace!(target: targets::IO, "wrote {} bytes", sz);
+            self.stats.sent(sz);
             self.send_buffer.consume(sz);
 
             let mut written = sz as u64;
@@ -298,7 +327,11 @@ impl IoLoop {
                     if written < to_write {
                         self.serialized_frames
                             .push_front((to_write - written, resolver));
-                        trace!("{} to write to complete this frame", to_write - written);
+                        trace!(
+                            target: targets::IO,
+                            "{} to write to complete this frame",
+                            to_write - written
+                        );
                         written = 0;
                     } else {
                         if let Some(resolver) = resolver {
@@ -308,6 +341,7 @@ impl IoLoop {
                     }
                 } else {
                     error!(
+                        target: targets::IO,
                         "We've written {} but didn't expect to write anything",
                         written
                     );
@@ -317,12 +351,15 @@ impl IoLoop {
 
             if self.send_buffer.available_data() > 0 {
                 // We didn't write all the data yet
-                trace!("Still {} to write", self.send_buffer.available_data());
+                trace!(target: targets::IO, "Still {} to write", self.send_buffer.available_data());
             }
 
             self.flush()?;
         } else {
-            error!("Socket was writable but we wrote 0, marking as wouldblock");
+            error!(
+                target: targets::IO,
+                "Socket was writable but we wrote 0, marking as wouldblock"
+            );
             self.handle_write_result(Err(io::Error::from(io::ErrorKind::WouldBlock).into()))?;
         }
         self.poll_internal_rpc()
@@ -333,13 +370,17 @@ impl IoLoop {
             ConnectionState::Closed => Ok(()),
             ConnectionState::Error => Err(Error::InvalidConnectionState(ConnectionState::Error)),
             _ => {
-                let sz = self.receive_buffer.read_from(&mut self.stream)?;
+                let sz = self.receive_buffer.read_from(&mut *self.stream.lock())?;
 
                 if sz > 0 {
-                    trace!("read {} bytes", sz);
+                    trace!(target: targets::IO, "read {} bytes", sz);
+                    self.stats.received(sz);
                     self.receive_buffer.fill(sz);
                 } else {
-                    error!("Socket was readable but we read 0, marking as wouldblock");
+                    error!(
+                        target: targets::IO,
+                        "Socket was readable but we read 0, marking as wouldblock"
+                    );
                     self.handle_read_result(
                         Err(io::Error::from(io::ErrorKind::WouldBlock).into()),
                     )?;
@@ -351,11 +392,33 @@ impl IoLoop {
 
     fn serialize(&mut self) -> Result<()> {
         while let Some((next_msg, resolver)) = self.frames.pop(self.channels.flow()) {
-            trace!("will write to buffer: {}", next_msg);
+            trace!(target: targets::IO, "will write to buffer: {}", next_msg);
             let checkpoint = self.send_buffer.checkpoint();
             let res = gen_frame(&next_msg)((&mut self.send_buffer).into());
             match res.map(|w| w.into_inner().1) {
-                Ok(sz) => self.serialized_frames.push_back((sz, resolver)),
+                Ok(sz) => {
+                    if let Some(Err(violation)) = self
+                        .frame_ordering
+                        .as_mut()
+                        .map(|validator| validator.check(&next_msg))
+                    {
+                        self.send_buffer.rollback(checkpoint);
+                        if cfg!(debug_assertions) {
+                            panic!("{}", violation);
+                        }
+                        error!(target: targets::IO, "{}", violation);
+                        let error = Error::FrameOrderingViolation(violation.to_string());
+                        if let Some(resolver) = resolver {
+                            resolver.swear(Err(error.clone()));
+                        }
+                        return self.critical_error(error);
+                    }
+                    self.stats.frame_sent();
+                    if let AMQPFrame::Heartbeat(_) = next_msg {
+                        self.stats.heartbeat_sent();
+                    }
+                    self.serialized_frames.push_back((sz, resolver));
+                }
                 Err(e) => {
                     self.send_buffer.rollback(checkpoint);
                     match e {
@@ -365,7 +428,7 @@ impl IoLoop {
                             break;
                         }
                         e => {
-                            error!("error generating frame: {:?}", e);
+                            error!(target: targets::IO, "error generating frame: {:?}", e);
                             self.critical_error(Error::SerialisationError(Arc::new(e)))?;
                         }
                     }
@@ -387,12 +450,28 @@ impl IoLoop {
     }
 
     fn parse(&mut self) -> Result<Option<AMQPFrame>> {
+        if !self.received_first_frame {
+            let greeting = self.receive_buffer.peek(GREETING_DUMP_SIZE);
+            if !is_amqp_greeting(&greeting) {
+                error!(target: targets::CONNECTION, "the server doesn't speak AMQP");
+                self.critical_error(Error::NotAnAmqpServer(greeting))?;
+            }
+            if let Some(version) = foreign_protocol_header(&greeting) {
+                error!(
+                    target: targets::CONNECTION,
+                    "we asked for AMQP {} but the server only supports AMQP {}",
+                    ProtocolVersion::amqp_0_9_1(),
+                    version
+                );
+                self.critical_error(Error::InvalidProtocolVersion(version))?;
+            }
+        }
         match parse_frame(self.receive_buffer.parsing_context()) {
             Ok((i, f)) => {
                 let consumed = self.receive_buffer.offset(i);
                 let frame_max = self.configuration.frame_max() as usize;
                 if frame_max > 0 && consumed > frame_max {
-                    error!("received large ({} bytes) frame", consumed);
+                    error!(target: targets::IO, "received large ({} bytes) frame", consumed);
                     let error = AMQPError::new(
                         AMQPHardError::FRAMEERROR.into(),
                         format!("frame too large: {} bytes", consumed).into(),
@@ -407,11 +486,12 @@ impl IoLoop {
                     self.critical_error(Error::ProtocolError(error))?;
                 }
                 self.receive_buffer.consume(consumed);
+                self.received_first_frame = true;
                 Ok(Some(f))
             }
             Err(e) => {
                 if !e.is_incomplete() {
-                    error!("parse error: {:?}", e);
+                    error!(target: targets::IO, "parse error: {:?}", e);
                     self.critical_error(Error::ParsingError(e))?;
                 }
                 Ok(None)
@@ -419,3 +499,87 @@ impl IoLoop {
         }
     }
 }
+
+/// Whether the (possibly partial) first bytes we received can be the beginning of either a
+/// protocol header rejecting our version, or a connection.start method frame.
+fn is_amqp_greeting(data: &[u8]) -> bool {
+    // frame type, channel id, payload size, class id, method id
+    const CONNECTION_START: [Option<u8>; 11] = [
+        Some(1),
+        Some(0),
+        Some(0),
+        None,
+        None,
+        None,
+        None,
+        Some(0),
+        Some(10),
+        Some(0),
+        Some(10),
+    ];
+
+    data.iter()
+        .zip(b"AMQP")
+        .all(|(byte, expected)| byte == expected)
+        || data
+            .iter()
+            .zip(CONNECTION_START.iter())
+            .all(|(byte, expected)| expected.map_or(true, |expected| *byte == expected))
+}
+
+/// Protocol headers the frame parser doesn't know about, as they don't have the `AMQP 0` prefix
+/// of the 0-9-1 one.
+fn foreign_protocol_header(data: &[u8]) -> Option<ProtocolVersion> {
+    if data.len() < 8 || !data.starts_with(b"AMQP") || data[4] == 0 {
+        return None;
+    }
+    let version = if data[4..6] == [1, 1] {
+        // AMQP 0-8: "AMQP", class, instance, major, minor
+        ProtocolVersion {
+            major: 0,
+            minor: data[6],
+            revision: data[7],
+        }
+    } else {
+        // AMQP 1.0: "AMQP", protocol id (TLS, SASL), major, minor, revision
+        ProtocolVersion {
+            major: data[5],
+            minor: data[6],
+            revision: data[7],
+        }
+    };
+    Some(version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn amqp_greeting() {
+        assert!(is_amqp_greeting(b""));
+        assert!(is_amqp_greeting(b"AMQP\x00\x00\x09\x01"));
+        assert!(is_amqp_greeting(
+            b"\x01\x00\x00\x00\x00\x01\x2c\x00\x0a\x00\x0a\x00\x09"
+        ));
+        assert!(is_amqp_greeting(b"\x01\x00"));
+        assert!(!is_amqp_greeting(b"HTTP/1.1 400 Bad Request\r\n"));
+        assert!(!is_amqp_greeting(
+            b"\x01\x00\x00\x00\x00\x00\x04\x00\x0a\x00\x32"
+        ));
+    }
+
+    #[test]
+    fn foreign_header() {
+        assert_eq!(foreign_protocol_header(b"AMQP\x00\x00\x09\x01"), None);
+        assert_eq!(foreign_protocol_header(b"AMQP\x03"), None);
+        assert_eq!(
+            foreign_protocol_header(b"AMQP\x03\x01\x00\x00").map(|version| version.to_string()),
+            Some("1.0.0".to_string())
+        );
+        assert_eq!(
+            foreign_protocol_header(b"AMQP\x01\x01\x08\x00").map(|version| version.to_string()),
+            Some("0.8.0".to_string())
+        );
+    }
+}
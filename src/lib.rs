@@ -16,6 +16,9 @@
 //! * `rustls`: enable amqps support through rustls (preferred over openssl when set, uses rustls-native-certs by default)
 //! * `rustls-native-certs`: same as rustls, be ensure we'll still use rustls-native-certs even if the default for rustls changes
 //! * `rustls-webpki-roots-certs`: same as rustls but using webkit-roots instead of rustls-native-certs
+//! * `serde`: (de)serialize the topologies and options, and encode and decode payloads with the
+//!   `codec` module, the JSON helpers and the work queue pattern
+//! * `test-support`: the `test_support` module, to check what lapin sends to the server in tests
 //!
 //! ## Example
 //!
@@ -104,51 +107,99 @@ pub use amq_protocol::{
     tcp, types, uri,
 };
 
+pub use backlog::{BacklogEvent, BacklogPolicy, Shedding};
+pub use cancellation::CancellationToken;
 pub use channel::{options, Channel};
+pub use channel_defaults::ChannelDefaults;
+pub use channel_lifecycle::{ChannelLifecycleObserver, CloseReason};
+pub use channel_stats::ChannelStats;
 pub use channel_status::{ChannelState, ChannelStatus};
-pub use configuration::Configuration;
+pub use configuration::{Configuration, OversizedMessagePolicy, PriorityValidation};
 pub use connection::{Connect, Connection};
 pub use connection_properties::ConnectionProperties;
+pub use connection_stats::ConnectionStats;
 pub use connection_status::{ConnectionState, ConnectionStatus};
-pub use consumer::{Consumer, ConsumerDelegate, ConsumerIterator};
+pub use connector::{Connector, SocketAddrConnector};
+pub use consumer::{
+    AckKind, Consumer, ConsumerDelegate, ConsumerIterator, ConsumerSpec, NackKind, PoisonAction,
+    TypedConsumerDelegate, UnknownConsumerPolicy,
+};
+pub use consumer_group::ConsumerGroup;
+pub use consumer_stats::{ConsumerStats, ConsumerStatsSample};
 pub use error::{Error, Result};
 pub use exchange::ExchangeKind;
-pub use queue::Queue;
+pub use health::HealthStatus;
+pub use message::DeliveryMode;
+pub use qos::QosScope;
+pub use queue::{OverflowBehavior, Queue, QueueStats, StreamOffset};
 pub use stream::TcpStream;
 
+#[cfg(feature = "serde")]
+pub mod codec;
+pub mod error;
 pub mod executor;
 pub mod heartbeat;
+pub mod id;
 pub mod message;
+pub mod metrics;
+pub mod patterns;
+pub mod pool;
 pub mod publisher_confirm;
 pub mod reactor;
+pub mod reconnect;
+#[cfg(feature = "serde")]
+pub mod serde_amqp;
 pub mod socket_state;
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_support;
+pub mod timestamp;
+pub mod topology;
+pub mod tracing_integration;
 
 type Promise<T> = pinky_swear::PinkySwear<Result<T>>;
 type PromiseResolver<T> = pinky_swear::Pinky<Result<T>>;
 
 mod acknowledgement;
+mod backlog;
+mod batch_consumer;
 mod buffer;
+mod cancellation;
 mod channel;
 mod channel_closer;
+mod channel_defaults;
+mod channel_lifecycle;
 mod channel_receiver_state;
+mod channel_stats;
 mod channel_status;
 mod channels;
 mod configuration;
 mod connection;
 mod connection_closer;
 mod connection_properties;
+mod connection_stats;
 mod connection_status;
+mod connector;
 mod consumer;
-mod error;
+mod consumer_group;
+mod consumer_stats;
+mod declaration_cache;
 mod error_handler;
 mod exchange;
+mod frame_ordering;
 mod frames;
+mod health;
 mod id_sequence;
 mod internal_rpc;
 mod io_loop;
+mod outstanding_deliveries;
 mod parsing;
+mod qos;
 mod queue;
 mod queues;
 mod returned_messages;
+mod server_named_queues;
 mod stream;
+#[cfg(test)]
+mod test_helpers;
 mod thread;
+mod validation;
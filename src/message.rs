@@ -1,8 +1,221 @@
+#[cfg(feature = "serde")]
+use crate::codec::CodecRegistry;
 use crate::{
+    options::BasicPublishOptions,
     protocol::AMQPError,
-    types::{LongLongUInt, LongUInt, ShortString, ShortUInt},
-    BasicProperties, Channel, Result,
+    timestamp::Timestamp,
+    tracing_integration::targets,
+    types::{
+        AMQPValue, FieldTable, LongLongUInt, LongUInt, ShortShortUInt, ShortString, ShortUInt,
+    },
+    BasicProperties, Channel, Error, Result,
 };
+#[cfg(feature = "serde")]
+use serde::de::DeserializeOwned;
+use std::{
+    convert::TryFrom,
+    future::Future,
+    mem,
+    str::Utf8Error,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+use tracing::warn;
+
+/// The delivery mode of a message, set through [`BasicProperties::with_delivery_mode`].
+///
+/// [`BasicProperties::with_delivery_mode`]: ../struct.BasicProperties.html#method.with_delivery_mode
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum DeliveryMode {
+    /// The message may be lost if the server restarts
+    Transient = 1,
+    /// The message is written to disk when it reaches a durable queue
+    Persistent = 2,
+}
+
+impl From<DeliveryMode> for ShortShortUInt {
+    fn from(mode: DeliveryMode) -> Self {
+        mode as ShortShortUInt
+    }
+}
+
+/// A message to publish, gathering its payload, its properties and how to publish it.
+///
+/// ```rust
+/// use lapin::{message::Message, types::AMQPValue};
+/// use std::time::Duration;
+///
+/// let message = Message::new(br#"{"id": 42}"#.to_vec())
+///     .content_type("application/json")
+///     .persistent()
+///     .header("tenant", AMQPValue::LongString("acme".into()))
+///     .priority(3)
+///     .expiration(Duration::from_secs(60));
+/// let (_payload, properties) = message.into_parts();
+/// assert_eq!(properties.expiration(), &Some("60000".into()));
+/// ```
+///
+/// Publish it with [`Channel::publish_message`] or, along with others, with
+/// [`Channel::publish_batch`].
+///
+/// [`Channel::publish_message`]: ../struct.Channel.html#method.publish_message
+/// [`Channel::publish_batch`]: ../struct.Channel.html#method.publish_batch
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Message {
+    payload: Vec<u8>,
+    properties: BasicProperties,
+    routing_key: Option<ShortString>,
+    mandatory: bool,
+}
+
+impl Message {
+    pub fn new(payload: Vec<u8>) -> Self {
+        Self {
+            payload,
+            ..Self::default()
+        }
+    }
+
+    /// Replace all the properties set so far.
+    pub fn properties(mut self, properties: BasicProperties) -> Self {
+        self.properties = properties;
+        self
+    }
+
+    pub fn content_type(mut self, content_type: &str) -> Self {
+        self.properties = self.properties.with_content_type(content_type.into());
+        self
+    }
+
+    pub fn content_encoding(mut self, content_encoding: &str) -> Self {
+        self.properties = self
+            .properties
+            .with_content_encoding(content_encoding.into());
+        self
+    }
+
+    /// Add a header, replacing the previous one with the same name.
+    pub fn header<V: Into<AMQPValue>>(mut self, name: &str, value: V) -> Self {
+        let mut headers = self.properties.headers().clone().unwrap_or_default();
+        headers.insert(name.into(), value.into());
+        self.properties = self.properties.with_headers(headers);
+        self
+    }
+
+    /// Replace all the headers set so far.
+    pub fn headers(mut self, headers: FieldTable) -> Self {
+        self.properties = self.properties.with_headers(headers);
+        self
+    }
+
+    pub fn delivery_mode(mut self, delivery_mode: DeliveryMode) -> Self {
+        self.properties = self.properties.with_delivery_mode(delivery_mode.into());
+        self
+    }
+
+    /// Have the server write the message to disk when it reaches a durable queue.
+    pub fn persistent(self) -> Self {
+        self.delivery_mode(DeliveryMode::Persistent)
+    }
+
+    pub fn transient(self) -> Self {
+        self.delivery_mode(DeliveryMode::Transient)
+    }
+
+    pub fn priority(mut self, priority: ShortShortUInt) -> Self {
+        self.properties = self.properties.with_priority(priority);
+        self
+    }
+
+    pub fn correlation_id(mut self, correlation_id: &str) -> Self {
+        self.properties = self.properties.with_correlation_id(correlation_id.into());
+        self
+    }
+
+    pub fn reply_to(mut self, reply_to: &str) -> Self {
+        self.properties = self.properties.with_reply_to(reply_to.into());
+        self
+    }
+
+    /// Have the message expire once it stayed in a queue for `ttl`, truncated to the
+    /// millisecond.
+    pub fn expiration(mut self, ttl: Duration) -> Self {
+        self.properties = self
+            .properties
+            .with_expiration(ttl.as_millis().to_string().into());
+        self
+    }
+
+    pub fn message_id(mut self, message_id: &str) -> Self {
+        self.properties = self.properties.with_message_id(message_id.into());
+        self
+    }
+
+    /// Set the `timestamp` property, truncated to the second.
+    pub fn timestamp(mut self, time: SystemTime) -> Self {
+        self.properties = self.properties.with_timestamp(Timestamp::from(time).0);
+        self
+    }
+
+    /// Set the `type` property.
+    pub fn kind(mut self, kind: &str) -> Self {
+        self.properties = self.properties.with_kind(kind.into());
+        self
+    }
+
+    pub fn user_id(mut self, user_id: &str) -> Self {
+        self.properties = self.properties.with_user_id(user_id.into());
+        self
+    }
+
+    pub fn app_id(mut self, app_id: &str) -> Self {
+        self.properties = self.properties.with_app_id(app_id.into());
+        self
+    }
+
+    /// Publish the message with this routing key rather than the one given when publishing.
+    pub fn routing_key(mut self, routing_key: &str) -> Self {
+        self.routing_key = Some(routing_key.into());
+        self
+    }
+
+    /// Publish the message with the `mandatory` flag, whatever the options given when
+    /// publishing.
+    pub fn mandatory(mut self, mandatory: bool) -> Self {
+        self.mandatory = mandatory;
+        self
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+
+    pub fn basic_properties(&self) -> &BasicProperties {
+        &self.properties
+    }
+
+    pub fn routing_key_override(&self) -> Option<&ShortString> {
+        self.routing_key.as_ref()
+    }
+
+    pub fn is_mandatory(&self) -> bool {
+        self.mandatory
+    }
+
+    /// The payload and the properties to give to [`Channel::basic_publish`].
+    ///
+    /// [`Channel::basic_publish`]: ../struct.Channel.html#method.basic_publish
+    pub fn into_parts(self) -> (Vec<u8>, BasicProperties) {
+        (self.payload, self.properties)
+    }
+}
+
+impl From<Message> for (Vec<u8>, BasicProperties) {
+    fn from(message: Message) -> Self {
+        message.into_parts()
+    }
+}
 
 /// Type wrapping the output of a consumer
 ///
@@ -22,7 +235,9 @@ pub type DeliveryResult = Result<Option<(Channel, Delivery)>>;
 /// [`Channel::basic_ack`]: ../struct.Channel.html#method.basic_ack
 /// [`Channel::basic_reject`]: ../struct.Channel.html#method.basic_reject
 /// [`Channel::basic_nack`]: ../struct.Channel.html#method.basic_nack
-#[derive(Clone, Debug, PartialEq)]
+///
+/// The payload is shared between clones, which makes cloning cheap regardless of its size.
+#[derive(Clone, Debug)]
 pub struct Delivery {
     /// The delivery tag of the message. Use this for
     /// acknowledging the message.
@@ -39,12 +254,31 @@ pub struct Delivery {
     /// Whether this message was redelivered
     pub redelivered: bool,
 
+    /// When the io loop handled the method frame starting this delivery, right after reading it
+    /// off the socket. This isn't taken into account when comparing deliveries.
+    pub received_at: Instant,
+
     /// Contains the properties and the headers of the
     /// message.
     pub properties: BasicProperties,
 
     /// The payload of the message in binary format.
-    pub data: Vec<u8>,
+    data: Arc<Vec<u8>>,
+
+    /// The payload being received, frozen into data once complete.
+    content: Vec<u8>,
+}
+
+impl PartialEq for Delivery {
+    fn eq(&self, other: &Self) -> bool {
+        self.delivery_tag == other.delivery_tag
+            && self.exchange == other.exchange
+            && self.routing_key == other.routing_key
+            && self.redelivered == other.redelivered
+            && self.properties == other.properties
+            && self.data == other.data
+            && self.content == other.content
+    }
 }
 
 impl Delivery {
@@ -59,14 +293,159 @@ impl Delivery {
             exchange,
             routing_key,
             redelivered,
+            received_at: Instant::now(),
             properties: BasicProperties::default(),
-            data: Vec::default(),
+            data: Arc::default(),
+            content: Vec::default(),
         }
     }
 
     pub(crate) fn receive_content(&mut self, data: Vec<u8>) {
-        self.data.extend(data);
+        self.content.extend(data);
+    }
+
+    pub(crate) fn complete(&mut self) {
+        self.data = Arc::new(mem::take(&mut self.content));
+    }
+
+    /// The payload of the message in binary format.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Get an owned copy of the payload.
+    ///
+    /// The payload is shared with the clones of this delivery: it only gets copied if one of
+    /// them is still around.
+    pub fn into_vec(self) -> Vec<u8> {
+        Arc::try_unwrap(self.data).unwrap_or_else(|data| data.to_vec())
     }
+
+    /// The payload of the message as text, for JSON or plain text messages.
+    ///
+    /// A warning is logged if the `content_type` property says the payload is binary.
+    pub fn body_as_str(&self) -> std::result::Result<&str, Utf8Error> {
+        if let Some(content_type) = self.properties.content_type() {
+            if is_binary_content_type(content_type.as_str()) {
+                warn!(
+                    target: targets::CONSUMER,
+                    "reading a {} payload as UTF-8; delivery_tag={}",
+                    content_type, self.delivery_tag
+                );
+            }
+        }
+        std::str::from_utf8(&self.data)
+    }
+
+    /// Get an owned copy of the payload as text, see [`body_as_str`].
+    ///
+    /// [`body_as_str`]: #method.body_as_str
+    pub fn body_as_string(&self) -> std::result::Result<String, Utf8Error> {
+        self.body_as_str().map(str::to_owned)
+    }
+
+    /// Decode the payload with the codec registered in `codecs` for its `content_type`.
+    ///
+    /// The codecs of a connection are available through [`Configuration::codecs`].
+    ///
+    /// [`Configuration::codecs`]: ../struct.Configuration.html#method.codecs
+    #[cfg(feature = "serde")]
+    pub fn decode_with<T: DeserializeOwned>(&self, codecs: &CodecRegistry) -> Result<T> {
+        let content_type = self
+            .properties
+            .content_type()
+            .as_ref()
+            .map_or("", |content_type| content_type.as_str());
+        let value = codecs.get(content_type)?.decode(&self.data)?;
+        serde_json::from_value(value).map_err(|e| Error::PayloadCodecError(e.to_string()))
+    }
+
+    /// Whether this message was redelivered
+    pub fn is_redelivered(&self) -> bool {
+        self.redelivered
+    }
+
+    /// The number of previous deliveries of this message, as reported by the `x-delivery-count`
+    /// header.
+    ///
+    /// RabbitMQ only sets this header for quorum queues, classic queues only provide the
+    /// `redelivered` flag.
+    pub fn delivery_count(&self) -> Option<u64> {
+        match self
+            .properties
+            .headers()
+            .as_ref()?
+            .inner()
+            .get("x-delivery-count")?
+        {
+            AMQPValue::ShortShortUInt(count) => Some((*count).into()),
+            AMQPValue::ShortUInt(count) => Some((*count).into()),
+            AMQPValue::LongUInt(count) => Some((*count).into()),
+            AMQPValue::ShortShortInt(count) => u64::try_from(*count).ok(),
+            AMQPValue::ShortInt(count) => u64::try_from(*count).ok(),
+            AMQPValue::LongInt(count) => u64::try_from(*count).ok(),
+            AMQPValue::LongLongInt(count) => u64::try_from(*count).ok(),
+            _ => None,
+        }
+    }
+
+    /// Answer this message, for the request-reply pattern.
+    ///
+    /// The reply is published on `channel` through the default exchange to the `reply_to`
+    /// address of this message, with its `correlation_id` if it has one. This fails with
+    /// [`Error::NoReplyTo`] if this message has no `reply_to` address. The publisher confirm of
+    /// the reply, if any, isn't waited for.
+    ///
+    /// [`Error::NoReplyTo`]: ../enum.Error.html#variant.NoReplyTo
+    pub fn reply(
+        &self,
+        channel: &Channel,
+        payload: &[u8],
+        properties: BasicProperties,
+    ) -> impl Future<Output = Result<()>> + Send + 'static {
+        let reply_to = self.properties.reply_to().clone();
+        let properties = match self.properties.correlation_id() {
+            Some(correlation_id) => properties.with_correlation_id(correlation_id.clone()),
+            None => properties,
+        };
+        let channel = channel.clone();
+        let payload = payload.to_vec();
+        async move {
+            let reply_to = reply_to.ok_or(Error::NoReplyTo)?;
+            channel
+                .basic_publish(
+                    "",
+                    reply_to.as_str(),
+                    BasicPublishOptions::default(),
+                    payload,
+                    properties,
+                )
+                .await?;
+            Ok(())
+        }
+    }
+}
+
+fn is_binary_content_type(content_type: &str) -> bool {
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    ["image/", "audio/", "video/"]
+        .iter()
+        .any(|prefix| mime.starts_with(prefix))
+        || [
+            "application/octet-stream",
+            "application/gzip",
+            "application/zip",
+            "application/protobuf",
+            "application/x-protobuf",
+            "application/msgpack",
+            "application/x-msgpack",
+        ]
+        .contains(&mime.as_str())
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -115,3 +494,255 @@ impl BasicReturnMessage {
         AMQPError::from_id(self.reply_code, self.reply_text.clone())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::*;
+
+    #[test]
+    fn equality_ignores_received_at() {
+        let mut delivery = Delivery::new(1, "".into(), "queue".into(), false);
+        delivery.receive_content(b"payload".to_vec());
+        delivery.complete();
+        let mut later = delivery.clone();
+        later.received_at += Duration::from_secs(1);
+        assert_eq!(delivery, later);
+
+        later.redelivered = true;
+        assert_ne!(delivery, later);
+    }
+
+    #[test]
+    fn body_as_str() {
+        let mut delivery = Delivery::new(1, "".into(), "queue".into(), false);
+        delivery.receive_content(b"{\"id\": 42}".to_vec());
+        delivery.complete();
+        assert_eq!(delivery.body_as_str(), Ok(r#"{"id": 42}"#));
+        assert_eq!(delivery.body_as_string(), Ok(r#"{"id": 42}"#.to_string()));
+
+        let mut delivery = Delivery::new(2, "".into(), "queue".into(), false);
+        delivery.properties = BasicProperties::default().with_content_type("image/png".into());
+        delivery.receive_content(vec![0x89, b'P', b'N', b'G', 0xff]);
+        delivery.complete();
+        assert!(delivery.body_as_str().is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn decode_with() {
+        #[derive(Debug, PartialEq, serde::Deserialize)]
+        struct Order {
+            id: u64,
+        }
+
+        let codecs = CodecRegistry::default();
+        let mut delivery = Delivery::new(1, "".into(), "queue".into(), false);
+        delivery.properties =
+            BasicProperties::default().with_content_type("application/json".into());
+        delivery.receive_content(b"{\"id\": 42}".to_vec());
+        delivery.complete();
+        assert_eq!(delivery.decode_with::<Order>(&codecs), Ok(Order { id: 42 }));
+
+        delivery.properties =
+            BasicProperties::default().with_content_type("application/x-protobuf".into());
+        assert_eq!(
+            delivery.decode_with::<Order>(&codecs),
+            Err(Error::UnknownContentType("application/x-protobuf".into()))
+        );
+        delivery.properties = BasicProperties::default();
+        assert_eq!(
+            delivery.decode_with::<Order>(&codecs),
+            Err(Error::UnknownContentType("".into()))
+        );
+    }
+
+    #[test]
+    fn message_builder() {
+        use std::time::UNIX_EPOCH;
+
+        let (payload, properties) = Message::new(b"payload".to_vec())
+            .content_type("application/json")
+            .content_encoding("gzip")
+            .header("tenant", AMQPValue::LongString("acme".into()))
+            .header("attempt", AMQPValue::LongUInt(1))
+            .header("tenant", AMQPValue::LongString("globex".into()))
+            .persistent()
+            .priority(3)
+            .correlation_id("correlation")
+            .reply_to("replies")
+            .expiration(Duration::from_millis(1500))
+            .message_id("message")
+            .timestamp(UNIX_EPOCH + Duration::from_millis(1_600_000_000_750))
+            .kind("order.created")
+            .user_id("guest")
+            .app_id("shop")
+            .into_parts();
+
+        let mut headers = FieldTable::default();
+        headers.insert("tenant".into(), AMQPValue::LongString("globex".into()));
+        headers.insert("attempt".into(), AMQPValue::LongUInt(1));
+        assert_eq!(payload, b"payload");
+        assert_eq!(
+            properties,
+            BasicProperties::default()
+                .with_content_type("application/json".into())
+                .with_content_encoding("gzip".into())
+                .with_headers(headers)
+                .with_delivery_mode(2)
+                .with_priority(3)
+                .with_correlation_id("correlation".into())
+                .with_reply_to("replies".into())
+                .with_expiration("1500".into())
+                .with_message_id("message".into())
+                .with_timestamp(1_600_000_000)
+                .with_kind("order.created".into())
+                .with_user_id("guest".into())
+                .with_app_id("shop".into())
+        );
+        // Nothing else gets set
+        assert_eq!(properties.cluster_id(), &None);
+
+        let message = Message::new(Vec::new())
+            .persistent()
+            .transient()
+            .routing_key("override")
+            .mandatory(true);
+        assert_eq!(message.basic_properties().delivery_mode(), &Some(1));
+        assert_eq!(
+            message.routing_key_override().map(ShortString::as_str),
+            Some("override")
+        );
+        assert!(message.is_mandatory());
+        assert_eq!(
+            Message::new(b"raw".to_vec()).into_parts(),
+            (b"raw".to_vec(), BasicProperties::default())
+        );
+    }
+
+    #[test]
+    fn delivery_count_types() {
+        let delivery_count = |count: AMQPValue| {
+            let mut headers = FieldTable::default();
+            headers.insert("x-delivery-count".into(), count);
+            let mut delivery = Delivery::new(1, "".into(), "queue".into(), false);
+            delivery.properties = BasicProperties::default().with_headers(headers);
+            delivery.delivery_count()
+        };
+        assert_eq!(delivery_count(AMQPValue::LongUInt(3)), Some(3));
+        assert_eq!(delivery_count(AMQPValue::LongLongInt(3)), Some(3));
+        assert_eq!(delivery_count(AMQPValue::LongInt(-1)), None);
+        // Only integers are counts
+        assert_eq!(delivery_count(AMQPValue::Timestamp(3)), None);
+    }
+
+    #[test]
+    fn binary_content_types() {
+        assert!(is_binary_content_type("application/octet-stream"));
+        assert!(is_binary_content_type("Image/PNG"));
+        assert!(is_binary_content_type(
+            "application/x-protobuf; proto=orders.Order"
+        ));
+        assert!(!is_binary_content_type("application/json"));
+        assert!(!is_binary_content_type("text/plain; charset=utf-8"));
+    }
+
+    #[test]
+    fn clone_shares_payload() {
+        let mut delivery = Delivery::new(1, "".into(), "queue".into(), false);
+        delivery.receive_content(vec![42; 512 * 1024]);
+        delivery.receive_content(vec![42; 512 * 1024]);
+        delivery.complete();
+        assert_eq!(delivery.data().len(), 1024 * 1024);
+
+        let clones = (0..1000).map(|_| delivery.clone()).collect::<Vec<_>>();
+        for clone in &clones {
+            assert!(Arc::ptr_eq(&clone.data, &delivery.data));
+            assert_eq!(clone.data().as_ptr(), delivery.data().as_ptr());
+        }
+        assert_eq!(clones[0].clone().into_vec(), vec![42; 1024 * 1024]);
+
+        // The last one gets the payload without a copy
+        drop(clones);
+        let payload = delivery.data().as_ptr();
+        assert_eq!(delivery.into_vec().as_ptr(), payload);
+    }
+
+    #[test]
+    fn delivery_reply() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        use crate::{message::Delivery, test_support::FrameCapture};
+
+        let (_conn, channel, frames) = connected_channel();
+        let capture = FrameCapture::install(&channel);
+        let mut request = Delivery::new(1, "".into(), "rpc".into(), false);
+        request.properties = BasicProperties::default()
+            .with_reply_to("amq.rabbitmq.reply-to".into())
+            .with_correlation_id("request-1".into());
+
+        let task = async_global_executor::spawn(request.reply(
+            &channel,
+            b"pong",
+            BasicProperties::default().with_content_type("text/plain".into()),
+        ));
+        write_frames(&frames, 3);
+        async_global_executor::block_on(task).unwrap();
+
+        let captured = capture.captured();
+        match (&captured[0], &captured[1]) {
+            (
+                AMQPFrame::Method(_, AMQPClass::Basic(basic::AMQPMethod::Publish(publish))),
+                AMQPFrame::Header(_, _, header),
+            ) => {
+                assert_eq!(publish.exchange.as_str(), "");
+                assert_eq!(publish.routing_key.as_str(), "amq.rabbitmq.reply-to");
+                assert_eq!(
+                    header.properties,
+                    BasicProperties::default()
+                        .with_content_type("text/plain".into())
+                        .with_correlation_id("request-1".into())
+                );
+            }
+            frames => panic!("unexpected frames: {:?}", frames),
+        }
+
+        capture.clear();
+        let notification = Delivery::new(2, "".into(), "rpc".into(), false);
+        assert_eq!(
+            async_global_executor::block_on(notification.reply(
+                &channel,
+                b"pong",
+                BasicProperties::default()
+            )),
+            Err(Error::NoReplyTo)
+        );
+        capture.assert_publish_count(0);
+    }
+
+    #[test]
+    fn delivery_received_at() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        let consumer_tag = ShortString::from("consumer-tag");
+        let mut consumer = Consumer::new(consumer_tag.clone(), DefaultExecutor::default().unwrap());
+        let (conn, channel) = channel_with_consumer(&consumer);
+
+        let before = std::time::Instant::now();
+        deliver_with_delivery_count(&conn, &channel, &consumer_tag, 1, 0);
+        deliver_with_delivery_count(&conn, &channel, &consumer_tag, 2, 0);
+        let after = std::time::Instant::now();
+
+        let waker = waker_fn(|| {});
+        let mut cx = Context::from_waker(&waker);
+        let mut next_delivery = || match Pin::new(&mut consumer).poll_next(&mut cx) {
+            Poll::Ready(Some(Ok((_, delivery)))) => delivery,
+            _ => panic!("expected a delivery"),
+        };
+        let first = next_delivery();
+        let second = next_delivery();
+        assert!(before <= first.received_at);
+        assert!(first.received_at <= second.received_at);
+        assert!(second.received_at <= after);
+    }
+}
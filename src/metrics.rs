@@ -0,0 +1,108 @@
+//! Metrics recorded by the consumers started with [`Channel::basic_consume_with_metrics`].
+//!
+//! The metrics go to the counters and histograms given in [`ConsumerMetrics`], which can be any
+//! type implementing [`IntCounter`] and [`Histogram`]. The `prometheus-client` feature provides
+//! these implementations for the `Counter` and `Histogram` of the `prometheus-client` crate.
+//!
+//! [`Channel::basic_consume_with_metrics`]: ../struct.Channel.html#method.basic_consume_with_metrics
+//! [`ConsumerMetrics`]: ./struct.ConsumerMetrics.html
+//! [`IntCounter`]: ./trait.IntCounter.html
+//! [`Histogram`]: ./trait.Histogram.html
+
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+/// A monotonic counter.
+pub trait IntCounter: Send + Sync {
+    /// Add one to the counter.
+    fn inc(&self);
+}
+
+/// A distribution of observed values.
+pub trait Histogram: Send + Sync {
+    /// Record a value.
+    fn observe(&self, value: f64);
+}
+
+impl IntCounter for AtomicU64 {
+    fn inc(&self) {
+        self.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl<C: IntCounter + ?Sized> IntCounter for Arc<C> {
+    fn inc(&self) {
+        (**self).inc();
+    }
+}
+
+impl<H: Histogram + ?Sized> Histogram for Arc<H> {
+    fn observe(&self, value: f64) {
+        (**self).observe(value);
+    }
+}
+
+#[cfg(feature = "prometheus-client")]
+impl IntCounter for prometheus_client::metrics::counter::Counter {
+    fn inc(&self) {
+        prometheus_client::metrics::counter::Counter::inc(self);
+    }
+}
+
+#[cfg(feature = "prometheus-client")]
+impl Histogram for prometheus_client::metrics::histogram::Histogram {
+    fn observe(&self, value: f64) {
+        prometheus_client::metrics::histogram::Histogram::observe(self, value);
+    }
+}
+
+/// Where a consumer records its activity.
+///
+/// Each consumer records to its own metrics, without any lock shared with the other consumers.
+pub struct ConsumerMetrics {
+    /// Incremented for each delivery the consumer receives
+    pub messages_received: Box<dyn IntCounter>,
+    /// How long the delegate of the consumer took to handle each delivery, in seconds. Nothing
+    /// is recorded for the consumers used as streams.
+    pub processing_time: Box<dyn Histogram>,
+    /// Incremented when the consumer gets an error, usually because its channel got closed
+    pub error_count: Box<dyn IntCounter>,
+}
+
+impl ConsumerMetrics {
+    pub fn new<R: IntCounter + 'static, P: Histogram + 'static, E: IntCounter + 'static>(
+        messages_received: R,
+        processing_time: P,
+        error_count: E,
+    ) -> Self {
+        Self {
+            messages_received: Box::new(messages_received),
+            processing_time: Box::new(processing_time),
+            error_count: Box::new(error_count),
+        }
+    }
+
+    pub(crate) fn received(&self) {
+        self.messages_received.inc();
+    }
+
+    pub(crate) fn processed(&self, elapsed: Duration) {
+        self.processing_time.observe(elapsed.as_secs_f64());
+    }
+
+    pub(crate) fn errored(&self) {
+        self.error_count.inc();
+    }
+}
+
+impl fmt::Debug for ConsumerMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConsumerMetrics").finish()
+    }
+}
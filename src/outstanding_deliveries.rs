@@ -0,0 +1,143 @@
+use crate::{
+    acknowledgement::DeliveryTag,
+    consumer_stats::{ConsumerStatsCounters, Settlement},
+    types::ShortString,
+    Promise, PromiseResolver,
+};
+use parking_lot::Mutex;
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt,
+    sync::Arc,
+};
+
+/// Tracks the deliveries received on a channel which haven't been acked, nacked or rejected yet.
+///
+/// Each delivery remembers the stats of the consumer it was delivered to, if any, to account
+/// for its settlement.
+#[derive(Clone, Default)]
+pub(crate) struct OutstandingDeliveries(Arc<Mutex<Inner>>);
+
+impl OutstandingDeliveries {
+    pub(crate) fn register_no_ack_consumer(&self, consumer_tag: ShortString) {
+        self.0.lock().no_ack_consumers.insert(consumer_tag);
+    }
+
+    pub(crate) fn deregister_consumer(&self, consumer_tag: &str) {
+        self.0.lock().no_ack_consumers.remove(consumer_tag);
+    }
+
+    pub(crate) fn register_consumer_delivery(
+        &self,
+        consumer_tag: &str,
+        delivery_tag: DeliveryTag,
+        stats: Option<ConsumerStatsCounters>,
+    ) {
+        let mut inner = self.0.lock();
+        if !inner.no_ack_consumers.contains(consumer_tag) {
+            if let Some(stats) = stats.as_ref() {
+                stats.outstanding(delivery_tag);
+            }
+            inner.delivery_tags.insert(delivery_tag, stats);
+        }
+    }
+
+    pub(crate) fn register_delivery(&self, delivery_tag: DeliveryTag) {
+        self.0.lock().delivery_tags.insert(delivery_tag, None);
+    }
+
+    pub(crate) fn complete(
+        &self,
+        delivery_tag: DeliveryTag,
+        multiple: bool,
+        settlement: Settlement,
+    ) {
+        let mut inner = self.0.lock();
+        let completed = if multiple && delivery_tag == 0 {
+            std::mem::take(&mut inner.delivery_tags)
+        } else if multiple {
+            let remaining = inner
+                .delivery_tags
+                .split_off(&delivery_tag.saturating_add(1));
+            std::mem::replace(&mut inner.delivery_tags, remaining)
+        } else {
+            inner
+                .delivery_tags
+                .remove_entry(&delivery_tag)
+                .into_iter()
+                .collect()
+        };
+        let waiters = inner.take_settled_waiters();
+        drop(inner);
+        for (delivery_tag, stats) in completed {
+            if let Some(stats) = stats {
+                stats.settled(delivery_tag, settlement);
+            }
+        }
+        for waiter in waiters {
+            waiter.swear(Ok(()));
+        }
+    }
+
+    /// Resolves once no delivery is left to settle, or once they got cleared.
+    pub(crate) fn settled(&self) -> Promise<()> {
+        let mut inner = self.0.lock();
+        if inner.delivery_tags.is_empty() {
+            return Promise::new_with_data(Ok(()));
+        }
+        let (promise, resolver) = Promise::new();
+        inner.settled_waiters.push(resolver);
+        promise
+    }
+
+    pub(crate) fn last(&self) -> Option<DeliveryTag> {
+        self.0.lock().delivery_tags.keys().next_back().cloned()
+    }
+
+    pub(crate) fn clear(&self) {
+        let (delivery_tags, waiters) = {
+            let mut inner = self.0.lock();
+            (
+                std::mem::take(&mut inner.delivery_tags),
+                std::mem::take(&mut inner.settled_waiters),
+            )
+        };
+        for (delivery_tag, stats) in delivery_tags {
+            if let Some(stats) = stats {
+                stats.forget(delivery_tag);
+            }
+        }
+        for waiter in waiters {
+            waiter.swear(Ok(()));
+        }
+    }
+}
+
+impl fmt::Debug for OutstandingDeliveries {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("OutstandingDeliveries");
+        if let Some(inner) = self.0.try_lock() {
+            debug
+                .field("delivery_tags", &inner.delivery_tags.keys())
+                .field("no_ack_consumers", &inner.no_ack_consumers);
+        }
+        debug.finish()
+    }
+}
+
+#[derive(Default)]
+struct Inner {
+    delivery_tags: BTreeMap<DeliveryTag, Option<ConsumerStatsCounters>>,
+    no_ack_consumers: HashSet<ShortString>,
+    settled_waiters: Vec<PromiseResolver<()>>,
+}
+
+impl Inner {
+    fn take_settled_waiters(&mut self) -> Vec<PromiseResolver<()>> {
+        if self.delivery_tags.is_empty() {
+            std::mem::take(&mut self.settled_waiters)
+        } else {
+            Vec::new()
+        }
+    }
+}
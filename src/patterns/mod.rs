@@ -0,0 +1,8 @@
+//! Ready-made implementations of common messaging patterns on top of [`Channel`].
+//!
+//! [`Channel`]: ../struct.Channel.html
+
+pub mod pubsub;
+pub mod rpc;
+#[cfg(feature = "serde")]
+pub mod work_queue;
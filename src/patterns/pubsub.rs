@@ -0,0 +1,171 @@
+//! Publish/subscribe through an exchange.
+//!
+//! A [`Publisher`] publishes to an exchange, and each [`Subscriber`] gets its own exclusive queue
+//! bound to that exchange, so that all the subscribers whose pattern matches the routing key of
+//! a message receive it. The exchange has to be declared beforehand, usually as a `topic` or
+//! `fanout` exchange.
+//!
+//! [`Publisher`]: ./struct.Publisher.html
+//! [`Subscriber`]: ./struct.Subscriber.html
+
+use crate::{
+    options::{BasicConsumeOptions, BasicPublishOptions, QueueBindOptions, QueueDeclareOptions},
+    publisher_confirm::Confirmation,
+    types::FieldTable,
+    BasicProperties, Channel, Consumer, Error, Result,
+};
+use futures_lite::Stream;
+#[cfg(feature = "serde")]
+use serde::Serialize;
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// Publishes messages to an exchange.
+#[derive(Clone, Debug)]
+pub struct Publisher {
+    channel: Channel,
+    exchange: String,
+}
+
+impl Publisher {
+    pub fn new(channel: Channel, exchange: &str) -> Self {
+        Self {
+            channel,
+            exchange: exchange.into(),
+        }
+    }
+
+    pub fn channel(&self) -> &Channel {
+        &self.channel
+    }
+
+    pub fn exchange(&self) -> &str {
+        &self.exchange
+    }
+
+    /// Publish `payload` with `routing_key`.
+    ///
+    /// If publisher confirms are enabled on the channel, this waits for the server to confirm
+    /// the message, and fails with [`Error::PublishNacked`] if the server nacks it.
+    ///
+    /// [`Error::PublishNacked`]: ../../enum.Error.html#variant.PublishNacked
+    pub async fn publish(&self, routing_key: &str, payload: &[u8]) -> Result<()> {
+        let confirmation = self
+            .channel
+            .basic_publish(
+                &self.exchange,
+                routing_key,
+                BasicPublishOptions::default(),
+                payload.to_vec(),
+                BasicProperties::default(),
+            )
+            .await?
+            .await?;
+        Self::check(confirmation)
+    }
+
+    /// Publish `value` encoded as JSON, with the `application/json` content type.
+    ///
+    /// Publisher confirms are handled like with [`publish`].
+    ///
+    /// [`publish`]: #method.publish
+    #[cfg(feature = "serde")]
+    pub async fn publish_json<T: Serialize>(&self, routing_key: &str, value: &T) -> Result<()> {
+        let confirmation = self
+            .channel
+            .publish_encoded(
+                &self.exchange,
+                routing_key,
+                BasicPublishOptions::default(),
+                value,
+                "application/json",
+                BasicProperties::default(),
+            )
+            .await?
+            .await?;
+        Self::check(confirmation)
+    }
+
+    fn check(confirmation: Confirmation) -> Result<()> {
+        if confirmation.is_nack() {
+            return Err(Error::PublishNacked);
+        }
+        Ok(())
+    }
+}
+
+/// Receives the messages published to an exchange with a routing key matching a pattern.
+///
+/// This is a stream of the routing keys and payloads of the messages. The messages are
+/// acknowledged as soon as the server delivers them.
+#[derive(Debug)]
+pub struct Subscriber {
+    consumer: Consumer,
+    exchange: String,
+    queue: String,
+}
+
+impl Subscriber {
+    /// Declare an exclusive, server-named queue on `channel`, bind it to `exchange` with
+    /// `routing_key_pattern` and start consuming from it.
+    pub async fn new(channel: &Channel, exchange: &str, routing_key_pattern: &str) -> Result<Self> {
+        let queue = channel
+            .queue_declare(
+                "",
+                QueueDeclareOptions::default().exclusive(true),
+                FieldTable::default(),
+            )
+            .await?;
+        let queue = queue.name().to_string();
+        channel
+            .queue_bind(
+                &queue,
+                exchange,
+                routing_key_pattern,
+                QueueBindOptions::default(),
+                FieldTable::default(),
+            )
+            .await?;
+        let consumer = channel
+            .basic_consume(
+                &queue,
+                "",
+                BasicConsumeOptions::default().no_ack(true),
+                FieldTable::default(),
+            )
+            .await?;
+        Ok(Self {
+            consumer,
+            exchange: exchange.into(),
+            queue,
+        })
+    }
+
+    pub fn consumer(&self) -> &Consumer {
+        &self.consumer
+    }
+
+    pub fn exchange(&self) -> &str {
+        &self.exchange
+    }
+
+    /// The name the server gave to the queue of this subscriber.
+    pub fn queue(&self) -> &str {
+        &self.queue
+    }
+}
+
+impl Stream for Subscriber {
+    type Item = Result<(String, Vec<u8>)>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.consumer).poll_next(cx).map(|delivery| {
+            delivery.map(|delivery| {
+                delivery
+                    .map(|(_, delivery)| (delivery.routing_key.to_string(), delivery.into_vec()))
+            })
+        })
+    }
+}
@@ -0,0 +1,241 @@
+//! Request-reply over queues.
+//!
+//! An [`RpcClient`] publishes its requests to the queue of an [`RpcServer`] with a unique
+//! correlation id and the name of its own, exclusive, reply queue. The server handles each
+//! request and publishes the reply to that queue, with the same correlation id, which the
+//! client uses to hand the reply to the matching call.
+//!
+//! [`RpcClient`]: ./struct.RpcClient.html
+//! [`RpcServer`]: ./struct.RpcServer.html
+
+use crate::{
+    id,
+    message::{Delivery, DeliveryResult},
+    options::{
+        BasicAckOptions, BasicConsumeOptions, BasicPublishOptions, BasicRejectOptions,
+        QueueDeclareOptions,
+    },
+    tracing_integration::targets,
+    types::{FieldTable, ShortString},
+    BasicProperties, Channel, Consumer, Error, Promise, PromiseResolver, Result,
+};
+use futures_lite::StreamExt;
+use parking_lot::Mutex;
+use std::{collections::HashMap, fmt, future::Future, sync::Arc};
+use tracing::{trace, warn};
+
+type Pending = Arc<Mutex<HashMap<ShortString, PromiseResolver<Delivery>>>>;
+
+/// Sends requests and waits for their replies.
+///
+/// The calls can be made concurrently: the replies are matched to them by correlation id.
+#[derive(Clone)]
+pub struct RpcClient {
+    channel: Channel,
+    reply_queue: String,
+    pending: Pending,
+}
+
+impl RpcClient {
+    /// Declare an exclusive, server-named reply queue on `channel` and start consuming from it.
+    pub async fn new(channel: Channel) -> Result<Self> {
+        let queue = channel
+            .queue_declare(
+                "",
+                QueueDeclareOptions::default().exclusive(true),
+                FieldTable::default(),
+            )
+            .await?;
+        let reply_queue = queue.name().to_string();
+        let consumer = channel
+            .basic_consume(
+                &reply_queue,
+                "",
+                BasicConsumeOptions::default().no_ack(true),
+                FieldTable::default(),
+            )
+            .await?;
+        let pending = Pending::default();
+        {
+            let pending = pending.clone();
+            consumer.set_delegate(move |delivery: DeliveryResult| {
+                dispatch(&pending, delivery);
+                async {}
+            });
+        }
+        Ok(Self {
+            channel,
+            reply_queue,
+            pending,
+        })
+    }
+
+    pub fn channel(&self) -> &Channel {
+        &self.channel
+    }
+
+    /// The name the server gave to the reply queue.
+    pub fn reply_queue(&self) -> &str {
+        &self.reply_queue
+    }
+
+    /// Publish `payload` to `request_queue` and wait for the reply.
+    ///
+    /// There is no timeout: a request nobody answers waits until the reply consumer gets
+    /// canceled, in which case it fails with [`Error::Cancelled`], or the channel fails.
+    ///
+    /// [`Error::Cancelled`]: ../../enum.Error.html#variant.Cancelled
+    pub fn call(
+        &self,
+        request_queue: &str,
+        payload: &[u8],
+    ) -> impl Future<Output = Result<Delivery>> + Send + 'static {
+        let correlation_id = id::correlation_id();
+        let channel = self.channel.clone();
+        let pending = self.pending.clone();
+        let request_queue = request_queue.to_string();
+        let properties = BasicProperties::default()
+            .with_correlation_id(correlation_id.clone())
+            .with_reply_to(self.reply_queue.as_str().into());
+        let payload = payload.to_vec();
+        async move {
+            trace!(
+                target: targets::CHANNEL,
+                "rpc call to {}; correlation_id={}",
+                request_queue,
+                correlation_id
+            );
+            let (promise, resolver) = Promise::new();
+            pending.lock().insert(correlation_id.clone(), resolver);
+            if let Err(error) = channel
+                .basic_publish(
+                    "",
+                    &request_queue,
+                    BasicPublishOptions::default(),
+                    payload,
+                    properties,
+                )
+                .await
+            {
+                pending.lock().remove(&correlation_id);
+                return Err(error);
+            }
+            promise.await
+        }
+    }
+}
+
+/// Hand a reply to the call waiting for it, or fail all the calls once the reply consumer is
+/// gone.
+fn dispatch(pending: &Pending, delivery: DeliveryResult) {
+    match delivery {
+        Ok(Some((_, delivery))) => {
+            let resolver = delivery
+                .properties
+                .correlation_id()
+                .as_ref()
+                .and_then(|correlation_id| pending.lock().remove(correlation_id));
+            match resolver {
+                Some(resolver) => resolver.swear(Ok(delivery)),
+                None => warn!(
+                    target: targets::CONSUMER,
+                    "dropping a reply matching no pending call; correlation_id={:?}",
+                    delivery.properties.correlation_id()
+                ),
+            }
+        }
+        Ok(None) => fail_all(pending, Error::Cancelled),
+        Err(error) => fail_all(pending, error),
+    }
+}
+
+fn fail_all(pending: &Pending, error: Error) {
+    for (_, resolver) in pending.lock().drain() {
+        resolver.swear(Err(error.clone()));
+    }
+}
+
+impl fmt::Debug for RpcClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("RpcClient");
+        debug
+            .field("channel", &self.channel)
+            .field("reply_queue", &self.reply_queue);
+        if let Some(pending) = self.pending.try_lock() {
+            debug.field("pending", &pending.len());
+        }
+        debug.finish()
+    }
+}
+
+/// Handles requests and publishes their replies.
+#[derive(Debug)]
+pub struct RpcServer {
+    channel: Channel,
+    consumer: Consumer,
+}
+
+impl RpcServer {
+    /// Declare `request_queue` on `channel` and start consuming the requests from it.
+    pub async fn new(channel: Channel, request_queue: &str) -> Result<Self> {
+        channel
+            .queue_declare(
+                request_queue,
+                QueueDeclareOptions::default(),
+                FieldTable::default(),
+            )
+            .await?;
+        let consumer = channel
+            .basic_consume(
+                request_queue,
+                "",
+                BasicConsumeOptions::default(),
+                FieldTable::default(),
+            )
+            .await?;
+        Ok(Self { channel, consumer })
+    }
+
+    pub fn channel(&self) -> &Channel {
+        &self.channel
+    }
+
+    pub fn consumer(&self) -> &Consumer {
+        &self.consumer
+    }
+
+    /// Call `handler` for each request, one at a time, and reply with the payload it returns.
+    ///
+    /// The requests are acknowledged once replied to. The ones without a `reply_to` address
+    /// are rejected without calling `handler`. This resolves once the consumer gets canceled,
+    /// or fails with the error of the channel.
+    pub async fn serve<F, Fut>(mut self, handler: F) -> Result<()>
+    where
+        F: Fn(Delivery) -> Fut,
+        Fut: Future<Output = Vec<u8>>,
+    {
+        while let Some(delivery) = self.consumer.next().await {
+            let (channel, delivery) = delivery?;
+            let delivery_tag = delivery.delivery_tag;
+            if delivery.properties.reply_to().is_none() {
+                warn!(
+                    target: targets::CONSUMER,
+                    "rejecting a request without reply_to; delivery_tag={}",
+                    delivery_tag
+                );
+                channel
+                    .basic_reject(delivery_tag, BasicRejectOptions::default())
+                    .await?;
+                continue;
+            }
+            let payload = handler(delivery.clone()).await;
+            delivery
+                .reply(&channel, &payload, BasicProperties::default())
+                .await?;
+            channel
+                .basic_ack(delivery_tag, BasicAckOptions::default())
+                .await?;
+        }
+        Ok(())
+    }
+}
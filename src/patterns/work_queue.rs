@@ -0,0 +1,239 @@
+//! Distributing tasks among workers through a queue.
+//!
+//! [`WorkQueue::setup`] declares a durable queue and gives a [`WorkSender`] to publish tasks to
+//! it and a [`WorkReceiver`] to consume them. Each task goes to a single receiver, and since a
+//! receiver only gets one unacknowledged task at a time, a busy worker doesn't hold back tasks
+//! that another one could handle.
+//!
+//! ```rust,no_run
+//! use lapin::{
+//!     options::BasicAckOptions, patterns::work_queue::WorkQueue, Connection, ConnectionProperties,
+//! };
+//! use serde::{Deserialize, Serialize};
+//!
+//! #[derive(Deserialize, Serialize)]
+//! struct Resize {
+//!     image: String,
+//!     width: u32,
+//! }
+//!
+//! async_global_executor::block_on(async {
+//!     let conn = Connection::connect("amqp://127.0.0.1:5672/%2f", ConnectionProperties::default())
+//!         .await?;
+//!     let (sender, mut receiver) = WorkQueue::setup(&conn, "resize").await?;
+//!     sender
+//!         .send(&Resize { image: "cat.png".into(), width: 320 })
+//!         .await?;
+//!
+//!     let (task, delivery): (Resize, _) = receiver.recv().await?;
+//!     // ... resize task.image ...
+//!     receiver
+//!         .channel()
+//!         .basic_ack(delivery.delivery_tag, BasicAckOptions::default())
+//!         .await?;
+//!     Ok::<(), lapin::Error>(())
+//! });
+//! ```
+//!
+//! [`WorkQueue::setup`]: ./struct.WorkQueue.html#method.setup
+//! [`WorkSender`]: ./struct.WorkSender.html
+//! [`WorkReceiver`]: ./struct.WorkReceiver.html
+
+use crate::{
+    codec::CodecRegistry,
+    message::Delivery,
+    options::{
+        BasicConsumeOptions, BasicPublishOptions, BasicRejectOptions, ConfirmSelectOptions,
+        QueueDeclareOptions,
+    },
+    types::{FieldTable, ShortUInt},
+    BasicProperties, Channel, Connection, Consumer, DeliveryMode, Error, QosScope, Result,
+};
+use futures_lite::StreamExt;
+use serde::{de::DeserializeOwned, Serialize};
+use std::sync::Arc;
+
+/// How many unacknowledged tasks a receiver gets at once.
+const PREFETCH_COUNT: ShortUInt = 1;
+
+/// Sets up a work queue, see the [module documentation](./index.html).
+#[derive(Debug)]
+pub struct WorkQueue;
+
+impl WorkQueue {
+    /// Declare the durable queue `queue_name` and open a channel to send tasks to it and
+    /// another one to receive them.
+    pub async fn setup(conn: &Connection, queue_name: &str) -> Result<(WorkSender, WorkReceiver)> {
+        let channel = conn.create_channel().await?;
+        channel
+            .queue_declare(
+                queue_name,
+                QueueDeclareOptions::default().durable(true),
+                FieldTable::default(),
+            )
+            .await?;
+        channel
+            .confirm_select(ConfirmSelectOptions::default())
+            .await?;
+        let sender = WorkSender::new(channel, queue_name);
+        let receiver = WorkReceiver::new(conn, queue_name).await?;
+        Ok((sender, receiver))
+    }
+}
+
+/// Sends tasks to a work queue.
+#[derive(Clone, Debug)]
+pub struct WorkSender {
+    channel: Channel,
+    queue: String,
+}
+
+impl WorkSender {
+    pub(crate) fn new(channel: Channel, queue: &str) -> Self {
+        Self {
+            channel,
+            queue: queue.into(),
+        }
+    }
+
+    pub fn channel(&self) -> &Channel {
+        &self.channel
+    }
+
+    pub fn queue(&self) -> &str {
+        &self.queue
+    }
+
+    /// Send `task` encoded as JSON, as a persistent message, and wait for the server to
+    /// confirm it.
+    ///
+    /// This fails with [`Error::PublishNacked`] if the server nacks the task.
+    ///
+    /// [`Error::PublishNacked`]: ../../enum.Error.html#variant.PublishNacked
+    pub async fn send<T: Serialize>(&self, task: &T) -> Result<()> {
+        let confirmation = self
+            .channel
+            .publish_encoded(
+                "",
+                &self.queue,
+                BasicPublishOptions::default(),
+                task,
+                "application/json",
+                BasicProperties::default().with_delivery_mode(DeliveryMode::Persistent.into()),
+            )
+            .await?
+            .await?;
+        if confirmation.is_nack() {
+            return Err(Error::PublishNacked);
+        }
+        Ok(())
+    }
+}
+
+/// Receives the tasks of a work queue.
+///
+/// The tasks have to be acknowledged through [`channel`] once handled, the next one is only
+/// delivered then.
+///
+/// [`channel`]: #method.channel
+#[derive(Debug)]
+pub struct WorkReceiver {
+    channel: Channel,
+    consumer: Consumer,
+    codecs: Arc<CodecRegistry>,
+}
+
+impl WorkReceiver {
+    async fn new(conn: &Connection, queue_name: &str) -> Result<Self> {
+        let channel = conn.create_channel().await?;
+        channel
+            .basic_qos(PREFETCH_COUNT, QosScope::PerChannel)
+            .await?;
+        let consumer = channel
+            .basic_consume(
+                queue_name,
+                "",
+                BasicConsumeOptions::default(),
+                FieldTable::default(),
+            )
+            .await?;
+        Ok(Self {
+            channel,
+            consumer,
+            codecs: conn.configuration().codecs(),
+        })
+    }
+
+    pub fn channel(&self) -> &Channel {
+        &self.channel
+    }
+
+    pub fn consumer(&self) -> &Consumer {
+        &self.consumer
+    }
+
+    /// Wait for the next task and decode it with the codec of its content type.
+    ///
+    /// A task which can't be decoded is rejected without being requeued, and its decoding
+    /// error returned.
+    pub async fn recv<T: DeserializeOwned>(&mut self) -> Result<(T, Delivery)> {
+        let (_, delivery) = match self.consumer.next().await {
+            Some(delivery) => delivery?,
+            None => return Err(self.channel.status().state_error()),
+        };
+        match delivery.decode_with(&self.codecs) {
+            Ok(task) => Ok((task, delivery)),
+            Err(error) => {
+                self.channel
+                    .basic_reject(delivery.delivery_tag, BasicRejectOptions::default())
+                    .await?;
+                Err(error)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_helpers::*;
+
+    #[test]
+    fn work_sender_nacked() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        use crate::patterns::work_queue::WorkSender;
+
+        let (conn, channel, frames) = connected_channel();
+        channel.status().set_confirm();
+        let sender = WorkSender::new(channel.clone(), "tasks");
+        let send = || {
+            let sender = sender.clone();
+            async_global_executor::spawn(async move { sender.send(&"resize cat.png").await })
+        };
+        let confirm = |method| {
+            conn.channels()
+                .handle_frame(AMQPFrame::Method(channel.id(), AMQPClass::Basic(method)))
+                .unwrap();
+        };
+
+        let task = send();
+        write_frames(&frames, 3);
+        confirm(basic::AMQPMethod::Nack(basic::Nack {
+            delivery_tag: 1,
+            multiple: false,
+            requeue: false,
+        }));
+        assert_eq!(
+            async_global_executor::block_on(task),
+            Err(Error::PublishNacked)
+        );
+
+        let task = send();
+        write_frames(&frames, 3);
+        confirm(basic::AMQPMethod::Ack(basic::Ack {
+            delivery_tag: 2,
+            multiple: false,
+        }));
+        assert!(async_global_executor::block_on(task).is_ok());
+    }
+}
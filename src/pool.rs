@@ -0,0 +1,190 @@
+//! Process deliveries on a pool of threads, for CPU-bound handlers.
+
+use crate::{
+    message::Delivery,
+    options::{BasicAckOptions, BasicCancelOptions, BasicConsumeOptions, BasicRejectOptions},
+    protocol,
+    tracing_integration::targets,
+    types::{FieldTable, ShortString, ShortUInt},
+    Channel, Connection, Consumer, Error, QosScope, Result,
+};
+use futures_lite::{future, stream};
+use std::{
+    convert::TryFrom,
+    fmt,
+    panic::{self, AssertUnwindSafe},
+    sync::Arc,
+    thread::{self, JoinHandle},
+};
+use tracing::{error, trace, warn};
+
+/// Consumes a queue on a dedicated channel and hands its deliveries to a fixed number of
+/// worker threads.
+///
+/// Each worker runs the handler on one delivery at a time, then acknowledges it from the worker
+/// thread if the handler succeeded, or rejects it without requeueing otherwise, including when
+/// the handler panics: the worker then goes on with the next delivery. The deliveries
+/// are dispatched through a bounded queue holding twice as many deliveries as there are workers,
+/// so that slow handlers make the consumer stop pulling messages.
+pub struct WorkerPool {
+    channel: Channel,
+    consumer: Consumer,
+    dispatcher: JoinHandle<()>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl WorkerPool {
+    /// Start consuming `queue` with `workers` threads running `handler`.
+    ///
+    /// The prefetch count of the channel is `prefetch`, or twice the number of workers if
+    /// `None`.
+    pub async fn new<H>(
+        conn: &Connection,
+        queue: &str,
+        workers: usize,
+        handler: H,
+        prefetch: Option<ShortUInt>,
+    ) -> Result<Self>
+    where
+        H: Fn(&Delivery) -> Result<()> + Send + Sync + 'static,
+    {
+        if workers == 0 {
+            return Err(Error::InvalidArgument(
+                "a worker pool needs at least one worker".into(),
+            ));
+        }
+        let prefetch = match prefetch {
+            Some(prefetch) => prefetch,
+            None => ShortUInt::try_from(workers * 2).map_err(|_| {
+                Error::InvalidArgument(format!("too many workers for a prefetch: {}", workers))
+            })?,
+        };
+        let channel = conn.create_channel().await?;
+        channel.basic_qos(prefetch, QosScope::PerChannel).await?;
+        let consumer = channel
+            .basic_consume(
+                queue,
+                "",
+                BasicConsumeOptions::default(),
+                FieldTable::default(),
+            )
+            .await?;
+
+        let (sender, receiver) = flume::bounded::<(Channel, Delivery)>(workers * 2);
+        let handler = Arc::new(handler);
+        let workers = (0..workers)
+            .map(|index| {
+                let receiver = receiver.clone();
+                let handler = handler.clone();
+                thread::Builder::new()
+                    .name(format!("lapin-worker-{}", index))
+                    .spawn(move || {
+                        for (channel, delivery) in receiver.iter() {
+                            process(&channel, &delivery, &*handler);
+                        }
+                    })
+            })
+            .collect::<std::io::Result<Vec<_>>>()?;
+        let dispatcher = {
+            let consumer = consumer.clone();
+            thread::Builder::new()
+                .name("lapin-worker-dispatcher".into())
+                .spawn(move || {
+                    let tag = consumer.tag();
+                    for delivery in stream::block_on(consumer) {
+                        match delivery {
+                            Ok(delivery) => {
+                                if sender.send(delivery).is_err() {
+                                    break;
+                                }
+                            }
+                            Err(err) => {
+                                error!(
+                                    target: targets::CONSUMER,
+                                    "worker pool consumer {} failed: {}",
+                                    tag,
+                                    err
+                                );
+                                break;
+                            }
+                        }
+                    }
+                    trace!(target: targets::CONSUMER, "worker pool consumer {} stopped", tag);
+                })?
+        };
+
+        Ok(Self {
+            channel,
+            consumer,
+            dispatcher,
+            workers,
+        })
+    }
+
+    /// The channel the deliveries are consumed on.
+    pub fn channel(&self) -> &Channel {
+        &self.channel
+    }
+
+    /// The tag of the consumer.
+    pub fn consumer_tag(&self) -> ShortString {
+        self.consumer.tag()
+    }
+
+    /// Stop consuming, wait for the workers to process the deliveries already dispatched, then
+    /// close the channel.
+    pub async fn shutdown(self) -> Result<()> {
+        if self.channel.status().connected() {
+            self.channel
+                .basic_cancel(self.consumer.tag().as_str(), BasicCancelOptions::default())
+                .await?;
+        }
+        let dispatcher = self.dispatcher;
+        let workers = self.workers;
+        blocking::unblock(move || {
+            let _ = dispatcher.join();
+            for worker in workers {
+                let _ = worker.join();
+            }
+        })
+        .await;
+        if self.channel.status().connected() {
+            self.channel
+                .close(protocol::constants::REPLY_SUCCESS as ShortUInt, "OK")
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+fn process<H: Fn(&Delivery) -> Result<()>>(channel: &Channel, delivery: &Delivery, handler: &H) {
+    let tag = delivery.delivery_tag;
+    let res = match panic::catch_unwind(AssertUnwindSafe(|| handler(delivery))) {
+        Ok(Ok(())) => future::block_on(channel.basic_ack(tag, BasicAckOptions::default())),
+        Ok(Err(err)) => {
+            warn!(target: targets::CONSUMER, "failed to handle delivery {}: {}", tag, err);
+            future::block_on(channel.basic_reject(tag, BasicRejectOptions::default()))
+        }
+        Err(_) => {
+            error!(
+                target: targets::CONSUMER,
+                "worker pool handler panicked, rejecting delivery; delivery_tag={}",
+                tag
+            );
+            future::block_on(channel.basic_reject(tag, BasicRejectOptions::default()))
+        }
+    };
+    if let Err(err) = res {
+        error!(target: targets::CONSUMER, "failed to settle delivery {}: {}", tag, err);
+    }
+}
+
+impl fmt::Debug for WorkerPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WorkerPool")
+            .field("channel", &self.channel)
+            .field("consumer", &self.consumer)
+            .field("workers", &self.workers.len())
+            .finish()
+    }
+}
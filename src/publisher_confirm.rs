@@ -1,4 +1,9 @@
-use crate::{message::BasicReturnMessage, returned_messages::ReturnedMessages, Promise, Result};
+use crate::{
+    message::BasicReturnMessage, returned_messages::ReturnedMessages, tracing_integration::targets,
+    Promise, Result,
+};
+#[cfg(feature = "publish-timestamps")]
+use std::time::Instant;
 use std::{
     fmt,
     future::Future,
@@ -10,6 +15,8 @@ use tracing::trace;
 pub struct PublisherConfirm {
     inner: Option<Promise<Confirmation>>,
     returned_messages: ReturnedMessages,
+    #[cfg(feature = "publish-timestamps")]
+    timestamps: Option<(Instant, Instant)>,
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -19,6 +26,17 @@ pub enum Confirmation {
     NotRequested,
 }
 
+/// The outcome of [`Channel::basic_publish_mandatory`].
+///
+/// [`Channel::basic_publish_mandatory`]: ../struct.Channel.html#method.basic_publish_mandatory
+#[derive(Clone, Debug, PartialEq)]
+pub enum MandatoryOutcome {
+    /// The message was not returned, so it was routed to at least one queue
+    Routed,
+    /// The server couldn't route the message and returned it
+    Returned(BasicReturnMessage),
+}
+
 impl Confirmation {
     pub fn take_message(self) -> Option<BasicReturnMessage> {
         if let Confirmation::Ack(Some(msg)) | Confirmation::Nack(Some(msg)) = self {
@@ -50,6 +68,8 @@ impl PublisherConfirm {
         Self {
             inner: Some(inner),
             returned_messages,
+            #[cfg(feature = "publish-timestamps")]
+            timestamps: None,
         }
     }
 
@@ -57,8 +77,27 @@ impl PublisherConfirm {
         Self {
             inner: Some(Promise::new_with_data(Ok(Confirmation::NotRequested))),
             returned_messages,
+            #[cfg(feature = "publish-timestamps")]
+            timestamps: None,
         }
     }
+
+    #[cfg(feature = "publish-timestamps")]
+    pub(crate) fn set_timestamps(&mut self, queued_at: Instant, written_at: Instant) {
+        self.timestamps = Some((queued_at, written_at));
+    }
+
+    /// When the frames of the message were queued for sending by `basic_publish`.
+    #[cfg(feature = "publish-timestamps")]
+    pub fn queued_at(&self) -> Option<Instant> {
+        self.timestamps.map(|(queued_at, _)| queued_at)
+    }
+
+    /// When the last frame of the message was written to the socket.
+    #[cfg(feature = "publish-timestamps")]
+    pub fn written_at(&self) -> Option<Instant> {
+        self.timestamps.map(|(_, written_at)| written_at)
+    }
 }
 
 impl fmt::Debug for PublisherConfirm {
@@ -89,7 +128,10 @@ impl Future for PublisherConfirm {
 impl Drop for PublisherConfirm {
     fn drop(&mut self) {
         if let Some(promise) = self.inner.take() {
-            trace!("PublisherConfirm dropped without use, registering it for wait_for_confirms");
+            trace!(
+                target: targets::CHANNEL,
+                "PublisherConfirm dropped without use, registering it for wait_for_confirms"
+            );
             self.returned_messages.register_dropped_confirm(promise);
         }
     }
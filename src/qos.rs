@@ -0,0 +1,38 @@
+use crate::{Error, Result};
+
+/// What the prefetch count set with [`Channel::basic_qos`] applies to.
+///
+/// The AMQP specification and RabbitMQ give different meanings to the `global` flag of
+/// `basic.qos`: for the specification, it switches between a limit per channel and a limit
+/// shared by the whole connection, while for RabbitMQ it switches between a limit per consumer
+/// and a limit shared by all the consumers of the channel. The flag is picked depending on
+/// whether the server advertised the `per_consumer_qos` capability, as RabbitMQ does.
+///
+/// [`Channel::basic_qos`]: ./struct.Channel.html#method.basic_qos
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QosScope {
+    /// Each consumer started afterwards on the channel gets its own limit. Only supported by
+    /// servers with per-consumer prefetch, such as RabbitMQ.
+    PerConsumer,
+    /// The limit is shared by all the consumers of the channel.
+    PerChannel,
+    /// The limit is shared by all the channels of the connection, as the specification
+    /// defines it. Not supported by servers with per-consumer prefetch, such as RabbitMQ.
+    PerConnectionSpec,
+}
+
+impl QosScope {
+    /// The `global` flag to send for this scope.
+    pub(crate) fn global_flag(self, per_consumer_qos: bool) -> Result<bool> {
+        match (self, per_consumer_qos) {
+            (Self::PerConsumer, true) => Ok(false),
+            (Self::PerConsumer, false) => Err(Error::UnsupportedByBroker("per-consumer prefetch")),
+            (Self::PerChannel, true) => Ok(true),
+            (Self::PerChannel, false) => Ok(false),
+            (Self::PerConnectionSpec, true) => {
+                Err(Error::UnsupportedByBroker("per-connection prefetch"))
+            }
+            (Self::PerConnectionSpec, false) => Ok(true),
+        }
+    }
+}
@@ -2,13 +2,14 @@ use crate::{
     consumer::Consumer, message::BasicGetMessage, types::ShortString, BasicProperties, Error,
     PromiseResolver,
 };
-use std::{borrow::Borrow, collections::HashMap, fmt, hash::Hash};
+use std::{borrow::Borrow, collections::HashMap, fmt, hash::Hash, time::SystemTime};
 
 #[derive(Clone, Debug)]
 pub struct Queue {
     name: ShortString,
     message_count: u32,
     consumer_count: u32,
+    server_named: bool,
 }
 
 impl Queue {
@@ -16,6 +17,12 @@ impl Queue {
         &self.name
     }
 
+    /// Whether the name of the queue was picked by the server, as it was declared with an empty
+    /// name.
+    pub fn is_server_named(&self) -> bool {
+        self.server_named
+    }
+
     pub fn message_count(&self) -> u32 {
         self.message_count
     }
@@ -25,6 +32,71 @@ impl Queue {
     }
 }
 
+/// What happens when a queue with a maximum length is full, see
+/// [`Channel::declare_max_length_queue`].
+///
+/// [`Channel::declare_max_length_queue`]: ./struct.Channel.html#method.declare_max_length_queue
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OverflowBehavior {
+    /// Drop the oldest messages to make room for the new ones.
+    DropHead,
+    /// Reject the new messages.
+    RejectPublish,
+    /// Reject the new messages and dead-letter them.
+    RejectPublishDlx,
+}
+
+impl Default for OverflowBehavior {
+    fn default() -> Self {
+        Self::DropHead
+    }
+}
+
+impl OverflowBehavior {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            Self::DropHead => "drop-head",
+            Self::RejectPublish => "reject-publish",
+            Self::RejectPublishDlx => "reject-publish-dlx",
+        }
+    }
+}
+
+/// Where a consumer of a stream queue starts reading, see [`Channel::basic_consume_offset`].
+///
+/// [`Channel::basic_consume_offset`]: ./struct.Channel.html#method.basic_consume_offset
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StreamOffset {
+    /// The first message still available in the stream.
+    First,
+    /// The last chunk of messages written to the stream.
+    Last,
+    /// The messages written after the consumer started.
+    Next,
+    /// The message at this offset.
+    Absolute(u64),
+    /// The first chunk of messages written at this time or later, to the second.
+    Timestamp(SystemTime),
+}
+
+/// A snapshot of the depth of a queue, see [`Channel::queue_stats`].
+///
+/// [`Channel::queue_stats`]: ./struct.Channel.html#method.queue_stats
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QueueStats {
+    pub messages: u32,
+    pub consumers: u32,
+}
+
+impl From<Queue> for QueueStats {
+    fn from(queue: Queue) -> Self {
+        Self {
+            messages: queue.message_count,
+            consumers: queue.consumer_count,
+        }
+    }
+}
+
 pub(crate) struct QueueState {
     name: ShortString,
     consumers: HashMap<ShortString, Consumer>,
@@ -46,8 +118,13 @@ impl Queue {
             name,
             message_count,
             consumer_count,
+            server_named: false,
         }
     }
+
+    pub(crate) fn set_server_named(&mut self) {
+        self.server_named = true;
+    }
 }
 
 impl Borrow<str> for Queue {
@@ -61,13 +138,14 @@ impl QueueState {
         self.consumers.insert(consumer_tag, consumer);
     }
 
-    pub(crate) fn deregister_consumer<S: Hash + Eq + ?Sized>(&mut self, consumer_tag: &S)
+    pub(crate) fn deregister_consumer<S: Hash + Eq + ?Sized>(
+        &mut self,
+        consumer_tag: &S,
+    ) -> Option<Consumer>
     where
         ShortString: Borrow<S>,
     {
-        if let Some(consumer) = self.consumers.remove(consumer_tag) {
-            consumer.cancel();
-        }
+        self.consumers.remove(consumer_tag)
     }
 
     pub(crate) fn get_consumer<S: Hash + Eq + ?Sized>(
@@ -80,26 +158,19 @@ impl QueueState {
         self.consumers.get_mut(consumer_tag.borrow())
     }
 
-    pub(crate) fn cancel_consumers(&self) {
-        for consumer in self.consumers.values() {
-            consumer.cancel();
-        }
-    }
-
-    pub(crate) fn error_consumers(&self, error: Error) {
-        for consumer in self.consumers.values() {
-            consumer.set_error(error.clone());
-        }
+    pub(crate) fn consumers(&self) -> impl Iterator<Item = &Consumer> {
+        self.consumers.values()
     }
 
     pub(crate) fn name(&self) -> ShortString {
         self.name.clone()
     }
 
-    pub(crate) fn drop_prefetched_messages(&self) {
+    pub(crate) fn rename(&mut self, name: ShortString) {
         for consumer in self.consumers.values() {
-            consumer.drop_prefetched_messages();
+            consumer.set_subscription_queue(name.clone());
         }
+        self.name = name;
     }
 
     pub(crate) fn start_new_delivery(
@@ -122,8 +193,15 @@ impl QueueState {
         }
     }
 
+    pub(crate) fn error_delivery(&mut self, error: Error) {
+        if let Some((_, resolver)) = self.current_get_message.take() {
+            resolver.swear(Err(error));
+        }
+    }
+
     pub(crate) fn new_delivery_complete(&mut self) {
-        if let Some((message, resolver)) = self.current_get_message.take() {
+        if let Some((mut message, resolver)) = self.current_get_message.take() {
+            message.delivery.complete();
             resolver.swear(Ok(Some(message)));
         }
     }
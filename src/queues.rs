@@ -1,5 +1,6 @@
 use crate::{
     consumer::Consumer,
+    consumer_stats::ConsumerStatsCounters,
     message::{BasicGetMessage, Delivery},
     queue::{Queue, QueueState},
     types::ShortString,
@@ -11,6 +12,8 @@ use std::{collections::HashMap, fmt, sync::Arc};
 #[derive(Clone, Default)]
 pub(crate) struct Queues {
     queues: Arc<Mutex<HashMap<ShortString, QueueState>>>,
+    /* Existing consumers waiting for their basic.consume-ok, by consumer tag */
+    resubscribing: Arc<Mutex<HashMap<ShortString, Consumer>>>,
 }
 
 impl Queues {
@@ -30,7 +33,15 @@ impl Queues {
         self.queues.lock().remove(queue);
     }
 
-    fn with_queue<F: FnOnce(&mut QueueState)>(&self, queue: &str, f: F) {
+    pub(crate) fn rename(&self, queue: &str, name: ShortString) {
+        let mut queues = self.queues.lock();
+        if let Some(mut state) = queues.remove(queue) {
+            state.rename(name.clone());
+            queues.insert(name, state);
+        }
+    }
+
+    fn with_queue<T, F: FnOnce(&mut QueueState) -> T>(&self, queue: &str, f: F) -> T {
         f(self
             .queues
             .lock()
@@ -49,27 +60,60 @@ impl Queues {
         });
     }
 
+    pub(crate) fn register_resubscribing(&self, consumer_tag: ShortString, consumer: Consumer) {
+        self.resubscribing.lock().insert(consumer_tag, consumer);
+    }
+
+    pub(crate) fn take_resubscribing(&self, consumer_tag: &str) -> Option<Consumer> {
+        self.resubscribing.lock().remove(consumer_tag)
+    }
+
     pub(crate) fn deregister_consumer(&self, consumer_tag: &str) {
-        for queue in self.queues.lock().values_mut() {
-            queue.deregister_consumer(consumer_tag);
+        for consumer in self.take_consumer(consumer_tag) {
+            consumer.cancel();
+        }
+    }
+
+    pub(crate) fn consumer_canceled_by_server(&self, consumer_tag: &str) {
+        for consumer in self.take_consumer(consumer_tag) {
+            consumer.canceled_by_server();
         }
     }
 
+    // The consumers get notified once the queues are unlocked: their delegates may use the
+    // channel, and with it its queues.
+    fn take_consumer(&self, consumer_tag: &str) -> Vec<Consumer> {
+        self.queues
+            .lock()
+            .values_mut()
+            .filter_map(|queue| queue.deregister_consumer(consumer_tag))
+            .collect()
+    }
+
     pub(crate) fn drop_prefetched_messages(&self) {
-        for queue in self.queues.lock().values() {
-            queue.drop_prefetched_messages();
+        for consumer in self.consumers() {
+            consumer.drop_prefetched_messages();
         }
     }
 
+    pub(crate) fn consumers(&self) -> Vec<Consumer> {
+        self.queues
+            .lock()
+            .values()
+            .flat_map(|queue| queue.consumers())
+            .cloned()
+            .collect()
+    }
+
     pub(crate) fn cancel_consumers(&self) {
-        for queue in self.queues.lock().values() {
-            queue.cancel_consumers();
+        for consumer in self.consumers() {
+            consumer.cancel();
         }
     }
 
     pub(crate) fn error_consumers(&self, error: Error) {
-        for queue in self.queues.lock().values() {
-            queue.error_consumers(error.clone());
+        for consumer in self.consumers() {
+            consumer.set_error(error.clone());
         }
     }
 
@@ -77,11 +121,12 @@ impl Queues {
         &self,
         consumer_tag: &str,
         message: Delivery,
-    ) -> Option<ShortString> {
+    ) -> Option<(ShortString, ConsumerStatsCounters)> {
         for queue in self.queues.lock().values_mut() {
             if let Some(consumer) = queue.get_consumer(consumer_tag) {
                 consumer.start_new_delivery(message);
-                return Some(queue.name());
+                let stats = consumer.stats_counters();
+                return Some((queue.name(), stats));
             }
         }
         None
@@ -98,21 +143,26 @@ impl Queues {
         });
     }
 
+    pub(crate) fn error_basic_get_delivery(&self, queue: &str, error: Error) {
+        self.with_queue(queue, |queue| queue.error_delivery(error));
+    }
+
     pub(crate) fn handle_content_header_frame(
         &self,
         channel: &Channel,
         queue: &str,
-        consumer_tag: Option<ShortString>,
+        consumer_tag: Option<&str>,
         size: u64,
         properties: BasicProperties,
     ) {
-        self.with_queue(queue, |queue| match consumer_tag {
+        let complete = self.with_queue(queue, |queue| match consumer_tag {
             Some(consumer_tag) => {
-                if let Some(consumer) = queue.get_consumer(&consumer_tag) {
-                    consumer.set_delivery_properties(properties);
-                    if size == 0 {
-                        consumer.new_delivery_complete(channel.clone());
-                    }
+                let consumer = queue.get_consumer(consumer_tag)?;
+                consumer.set_delivery_properties(properties);
+                if size == 0 {
+                    Some(consumer.clone())
+                } else {
+                    None
                 }
             }
             None => {
@@ -120,25 +170,31 @@ impl Queues {
                 if size == 0 {
                     queue.new_delivery_complete();
                 }
+                None
             }
-        })
+        });
+        // Handed to the consumer once the queues are unlocked, see take_consumer
+        if let Some(mut consumer) = complete {
+            consumer.new_delivery_complete(channel.clone());
+        }
     }
 
     pub(crate) fn handle_body_frame(
         &self,
         channel: &Channel,
         queue: &str,
-        consumer_tag: Option<ShortString>,
+        consumer_tag: Option<&str>,
         remaining_size: usize,
         payload: Vec<u8>,
     ) {
-        self.with_queue(queue, |queue| match consumer_tag {
+        let complete = self.with_queue(queue, |queue| match consumer_tag {
             Some(consumer_tag) => {
-                if let Some(consumer) = queue.get_consumer(&consumer_tag) {
-                    consumer.receive_delivery_content(payload);
-                    if remaining_size == 0 {
-                        consumer.new_delivery_complete(channel.clone());
-                    }
+                let consumer = queue.get_consumer(consumer_tag)?;
+                consumer.receive_delivery_content(payload);
+                if remaining_size == 0 {
+                    Some(consumer.clone())
+                } else {
+                    None
                 }
             }
             None => {
@@ -146,8 +202,13 @@ impl Queues {
                 if remaining_size == 0 {
                     queue.new_delivery_complete();
                 }
+                None
             }
         });
+        // Handed to the consumer once the queues are unlocked, see take_consumer
+        if let Some(mut consumer) = complete {
+            consumer.new_delivery_complete(channel.clone());
+        }
     }
 }
 
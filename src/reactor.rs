@@ -1,13 +1,16 @@
+pub use crate::stream::ReactorSocket;
+
 use crate::{
+    cancellation::CancellationToken,
     executor::Executor,
     heartbeat::Heartbeat,
     socket_state::{SocketEvent, SocketStateHandle},
-    tcp::{TcpStream, TcpStreamWrapper},
     Result,
 };
 use async_io::{Async, Timer};
+use futures_lite::future;
 use parking_lot::Mutex;
-use std::{collections::HashMap, fmt, sync::Arc};
+use std::{collections::HashMap, fmt, future::Future, io, sync::Arc};
 
 pub type Slot = usize;
 
@@ -16,8 +19,8 @@ pub trait ReactorBuilder: fmt::Debug + Send + Sync {
 }
 
 pub trait Reactor: fmt::Debug + Send {
-    fn register(&mut self, socket: &mut TcpStream, socket_state: SocketStateHandle)
-        -> Result<Slot>;
+    /// Take ownership of the socket and notify socket_state of its readiness when polled.
+    fn register(&mut self, socket: ReactorSocket, socket_state: SocketStateHandle) -> Result<Slot>;
     fn handle(&self) -> Box<dyn ReactorHandle + Send> {
         Box::new(DummyHandle)
     }
@@ -27,6 +30,8 @@ pub trait ReactorHandle {
     fn start_heartbeat(&self) {}
     fn poll_read(&self, _slot: Slot) {}
     fn poll_write(&self, _slot: Slot) {}
+    /// Release the socket once the connection is over.
+    fn unregister(&self, _slot: Slot) {}
 }
 
 #[derive(Clone)]
@@ -46,16 +51,53 @@ impl ReactorBuilder for DefaultReactorBuilder {
     }
 }
 
+/// A `ReactorBuilder` whose reactors all share the same sockets registry.
+///
+/// Clone it and pass it to [`ConnectionProperties::with_reactor`] for each connection which
+/// should use this reactor.
+///
+/// [`ConnectionProperties::with_reactor`]: ../struct.ConnectionProperties.html#method.with_reactor
+#[derive(Clone, Default)]
+pub struct SharedReactorBuilder(Arc<Mutex<Inner>>);
+
+impl SharedReactorBuilder {
+    /// A new reactor, with no socket registered yet.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of sockets currently registered in this reactor.
+    pub fn registered_sockets(&self) -> usize {
+        self.0.lock().slots.len()
+    }
+}
+
+impl ReactorBuilder for SharedReactorBuilder {
+    fn build(&self, heartbeat: Heartbeat, executor: Arc<dyn Executor>) -> Box<dyn Reactor + Send> {
+        Box::new(DefaultReactor(DefaultReactorHandle {
+            heartbeat,
+            executor,
+            inner: self.0.clone(),
+        }))
+    }
+}
+
+impl fmt::Debug for SharedReactorBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("SharedReactorBuilder");
+        if let Some(inner) = self.0.try_lock() {
+            debug.field("registered_sockets", &inner.slots.len());
+        }
+        debug.finish()
+    }
+}
+
 #[derive(Debug)]
 pub(crate) struct DefaultReactor(DefaultReactorHandle);
 
 impl Reactor for DefaultReactor {
-    fn register(
-        &mut self,
-        socket: &mut TcpStream,
-        socket_state: SocketStateHandle,
-    ) -> Result<Slot> {
-        let socket = Arc::new(Async::new(unsafe { TcpStreamWrapper::new(socket) })?);
+    fn register(&mut self, socket: ReactorSocket, socket_state: SocketStateHandle) -> Result<Slot> {
+        let socket = Arc::new(Async::new(socket)?);
         let slot = self.0.inner.lock().register(socket, socket_state)?;
         self.0.poll_read(slot);
         self.0.poll_write(slot);
@@ -87,16 +129,23 @@ impl ReactorHandle for DefaultReactorHandle {
     }
 
     fn poll_read(&self, slot: usize) {
-        if let Some((socket, socket_state)) = self.inner.lock().slots.get(&slot) {
+        if let Some(registration) = self.inner.lock().slots.get(&slot) {
             self.executor
-                .spawn(Box::pin(poll_read(socket.clone(), socket_state.clone())));
+                .spawn(Box::pin(poll_read(registration.clone())));
         }
     }
 
     fn poll_write(&self, slot: usize) {
-        if let Some((socket, socket_state)) = self.inner.lock().slots.get(&slot) {
+        if let Some(registration) = self.inner.lock().slots.get(&slot) {
             self.executor
-                .spawn(Box::pin(poll_write(socket.clone(), socket_state.clone())));
+                .spawn(Box::pin(poll_write(registration.clone())));
+        }
+    }
+
+    fn unregister(&self, slot: usize) {
+        let registration = self.inner.lock().slots.remove(&slot);
+        if let Some(registration) = registration {
+            registration.released.cancel();
         }
     }
 }
@@ -104,36 +153,69 @@ impl ReactorHandle for DefaultReactorHandle {
 #[derive(Default)]
 struct Inner {
     slot: Slot,
-    slots: HashMap<usize, (Arc<Async<TcpStreamWrapper>>, SocketStateHandle)>,
+    slots: HashMap<usize, Registration>,
 }
 
 impl Inner {
     fn register(
         &mut self,
-        socket: Arc<Async<TcpStreamWrapper>>,
+        socket: Arc<Async<ReactorSocket>>,
         socket_state: SocketStateHandle,
     ) -> Result<usize> {
         let slot = self.slot;
         self.slot += 1;
-        self.slots.insert(slot, (socket, socket_state));
+        self.slots.insert(
+            slot,
+            Registration {
+                socket,
+                socket_state,
+                released: CancellationToken::default(),
+            },
+        );
         Ok(slot)
     }
 }
 
+#[derive(Clone)]
+struct Registration {
+    socket: Arc<Async<ReactorSocket>>,
+    socket_state: SocketStateHandle,
+    // Cancelled once the socket gets unregistered, to end the pending readiness polls which
+    // would otherwise keep it open
+    released: CancellationToken,
+}
+
+impl Registration {
+    /// Wait for the socket to be `ready`, unless it gets unregistered first.
+    async fn ready<F: Future<Output = io::Result<()>>>(&self, ready: F) -> bool {
+        let ready = async {
+            ready.await.unwrap();
+            true
+        };
+        let released = async {
+            self.released.cancelled().await;
+            false
+        };
+        future::or(ready, released).await
+    }
+}
+
 async fn heartbeat(heartbeat: Heartbeat) {
     while let Some(timeout) = heartbeat.poll_timeout() {
         Timer::after(timeout).await;
     }
 }
 
-async fn poll_read(socket: Arc<Async<TcpStreamWrapper>>, socket_state: SocketStateHandle) {
-    socket.readable().await.unwrap();
-    socket_state.send(SocketEvent::Readable);
+async fn poll_read(registration: Registration) {
+    if registration.ready(registration.socket.readable()).await {
+        registration.socket_state.send(SocketEvent::Readable);
+    }
 }
 
-async fn poll_write(socket: Arc<Async<TcpStreamWrapper>>, socket_state: SocketStateHandle) {
-    socket.writable().await.unwrap();
-    socket_state.send(SocketEvent::Writable);
+async fn poll_write(registration: Registration) {
+    if registration.ready(registration.socket.writable()).await {
+        registration.socket_state.send(SocketEvent::Writable);
+    }
 }
 
 impl fmt::Debug for DefaultReactorBuilder {
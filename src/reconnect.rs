@@ -0,0 +1,289 @@
+use crate::{
+    error::RetryableError,
+    message::Delivery,
+    options::{BasicPublishOptions, ConfirmSelectOptions},
+    tracing_integration::targets,
+    BasicProperties, Channel, Connection, Consumer, Result,
+};
+use futures_lite::Stream;
+use parking_lot::Mutex;
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+use tracing::{trace, warn};
+
+type ConsumerFuture = Pin<Box<dyn Future<Output = Result<Consumer>> + Send>>;
+
+/// A [`Consumer`] which gets recreated when it fails, for example because its connection dropped.
+///
+/// When the inner consumer yields an error, the factory is called to get a new one (usually
+/// by reconnecting, opening a new channel and calling [`Channel::basic_consume`] again) and
+/// the stream transparently resumes with it. The failing delivery is not retried, so messages
+/// may be missed while reconnecting.
+///
+/// If the factory itself fails, its error is yielded and it will be called again on the next
+/// poll: it is up to the factory to wait between attempts.
+///
+/// The stream ends when the current consumer is canceled.
+///
+/// [`Consumer`]: ../struct.Consumer.html
+/// [`Channel::basic_consume`]: ../struct.Channel.html#method.basic_consume
+pub struct ReconnectingConsumer<F> {
+    factory: F,
+    state: State,
+}
+
+enum State {
+    Consuming(Consumer),
+    Reconnecting(ConsumerFuture),
+}
+
+impl<F, Fut> ReconnectingConsumer<F>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<Consumer>> + Send + 'static,
+{
+    pub fn new(consumer: Consumer, factory: F) -> Self {
+        Self {
+            factory,
+            state: State::Consuming(consumer),
+        }
+    }
+
+    /// The consumer currently in use, if we're not reconnecting.
+    pub fn consumer(&self) -> Option<&Consumer> {
+        match &self.state {
+            State::Consuming(consumer) => Some(consumer),
+            State::Reconnecting(_) => None,
+        }
+    }
+
+    fn reconnect(&mut self) {
+        self.state = State::Reconnecting(Box::pin((self.factory)()));
+    }
+}
+
+impl<F, Fut> Stream for ReconnectingConsumer<F>
+where
+    F: Fn() -> Fut + Unpin,
+    Fut: Future<Output = Result<Consumer>> + Send + 'static,
+{
+    type Item = Result<(Channel, Delivery)>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            match &mut this.state {
+                State::Consuming(consumer) => match Pin::new(consumer).poll_next(cx) {
+                    Poll::Ready(Some(Err(error))) => {
+                        warn!(
+                            target: targets::CONSUMER,
+                            "consumer failed, recreating it: {}",
+                            error
+                        );
+                        this.reconnect();
+                    }
+                    res => return res,
+                },
+                State::Reconnecting(consumer) => match consumer.as_mut().poll(cx) {
+                    Poll::Ready(Ok(consumer)) => {
+                        trace!(
+                            target: targets::CONSUMER,
+                            "consumer recreated; consumer_tag={}",
+                            consumer.tag()
+                        );
+                        this.state = State::Consuming(consumer);
+                    }
+                    Poll::Ready(Err(error)) => {
+                        this.reconnect();
+                        return Poll::Ready(Some(Err(error)));
+                    }
+                    Poll::Pending => return Poll::Pending,
+                },
+            }
+        }
+    }
+}
+
+impl<F> fmt::Debug for ReconnectingConsumer<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("ReconnectingConsumer");
+        match &self.state {
+            State::Consuming(consumer) => debug.field("consumer", consumer),
+            State::Reconnecting(_) => debug.field("reconnecting", &true),
+        };
+        debug.finish()
+    }
+}
+
+/// Publishes on a [`Channel`] which gets recreated when it fails.
+///
+/// When a publish fails with a retryable error, for example because the channel got closed by
+/// the server, a new channel is created on the same connection, with confirm mode enabled if it
+/// was on the failing channel, and the publish is retried once on it.
+///
+/// The publisher confirms are not awaited: use [`channel`] to get the current channel and call
+/// [`Channel::wait_for_confirms`] on it.
+///
+/// [`Channel`]: ../struct.Channel.html
+/// [`Channel::wait_for_confirms`]: ../struct.Channel.html#method.wait_for_confirms
+/// [`channel`]: #method.channel
+pub struct ReconnectingPublisher {
+    connection: Arc<Connection>,
+    channel: Mutex<Channel>,
+}
+
+impl ReconnectingPublisher {
+    pub fn new(connection: Arc<Connection>, channel: Channel) -> Self {
+        Self {
+            connection,
+            channel: Mutex::new(channel),
+        }
+    }
+
+    /// The channel currently used for publishing.
+    pub fn channel(&self) -> Channel {
+        self.channel.lock().clone()
+    }
+
+    pub async fn publish(
+        &self,
+        exchange: &str,
+        routing_key: &str,
+        payload: Vec<u8>,
+        properties: BasicProperties,
+        options: BasicPublishOptions,
+    ) -> Result<()> {
+        let channel = self.channel();
+        match channel
+            .basic_publish(
+                exchange,
+                routing_key,
+                options,
+                payload.clone(),
+                properties.clone(),
+            )
+            .await
+        {
+            Ok(_) => return Ok(()),
+            Err(error) if !error.is_retryable() => return Err(error),
+            Err(error) => warn!(
+                target: targets::CONNECTION,
+                "publish failed on channel {}, recreating it: {}",
+                channel.id(),
+                error
+            ),
+        }
+        self.recreate_channel(&channel)
+            .await?
+            .basic_publish(exchange, routing_key, options, payload, properties)
+            .await?;
+        Ok(())
+    }
+
+    async fn recreate_channel(&self, failed: &Channel) -> Result<Channel> {
+        let current = self.channel();
+        if current.is_open() && !current.status().is_same(failed.status()) {
+            // A concurrent publish already replaced it
+            return Ok(current);
+        }
+        let channel = self.connection.create_channel().await?;
+        if failed.status().confirm() {
+            channel
+                .confirm_select(ConfirmSelectOptions::default())
+                .await?;
+        }
+        trace!(
+            target: targets::CONNECTION,
+            "channel {} replaced by channel {}",
+            failed.id(),
+            channel.id()
+        );
+        *self.channel.lock() = channel.clone();
+        Ok(channel)
+    }
+}
+
+impl fmt::Debug for ReconnectingPublisher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("ReconnectingPublisher");
+        if let Some(channel) = self.channel.try_lock() {
+            debug.field("channel", &channel.id());
+        }
+        debug.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::*;
+    use futures_lite::{future, stream::StreamExt};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn consumer() -> Consumer {
+        Consumer::new(
+            ShortString::from("test-consumer"),
+            DefaultExecutor::default().unwrap(),
+        )
+    }
+
+    #[test]
+    fn recreate_on_error() {
+        let first = consumer();
+        let second = consumer();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut stream = {
+            let second = second.clone();
+            let calls = calls.clone();
+            ReconnectingConsumer::new(first.clone(), move || {
+                calls.fetch_add(1, Ordering::SeqCst);
+                future::ready(Ok(second.clone()))
+            })
+        };
+
+        first.set_error(Error::ChannelsLimitReached);
+        second.cancel();
+
+        assert!(future::block_on(stream.next()).is_none());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn factory_error() {
+        let first = consumer();
+        let mut stream = ReconnectingConsumer::new(first.clone(), || {
+            future::ready(Err(Error::ChannelsLimitReached))
+        });
+
+        first.set_error(Error::InvalidChannel(1));
+
+        assert_eq!(
+            future::block_on(stream.next()),
+            Some(Err(Error::ChannelsLimitReached))
+        );
+        assert!(stream.consumer().is_none());
+    }
+
+    #[test]
+    fn replaced_channel_with_reused_id() {
+        let (conn, failed, _) = connected_channel();
+        // Only one channel can be open at a time, the replacement reuses the id
+        conn.configuration().set_channel_max(1);
+        conn.channels()
+            .remove(failed.id(), Error::InvalidChannel(failed.id()))
+            .unwrap();
+        failed.set_state(ChannelState::Error);
+        let replacement = conn.channels().create(conn.closer()).unwrap();
+        replacement.set_state(ChannelState::Connected);
+        assert_eq!(replacement.id(), failed.id());
+
+        let publisher = ReconnectingPublisher::new(Arc::new(conn), replacement.clone());
+        let current = future::block_on(publisher.recreate_channel(&failed)).unwrap();
+        assert!(current.status().is_same(replacement.status()));
+    }
+}
@@ -1,5 +1,10 @@
 use crate::{
-    message::BasicReturnMessage, publisher_confirm::Confirmation, BasicProperties, Promise,
+    channel_defaults::ReturnCallback,
+    message::BasicReturnMessage,
+    publisher_confirm::{Confirmation, MandatoryOutcome},
+    tracing_integration::targets,
+    types::ShortString,
+    BasicProperties, Promise, PromiseResolver,
 };
 use parking_lot::Mutex;
 use std::{collections::VecDeque, fmt, sync::Arc};
@@ -22,12 +27,24 @@ impl ReturnedMessages {
     }
 
     pub(crate) fn new_delivery_complete(&self, confirm_mode: bool) {
-        self.inner.lock().new_delivery_complete(confirm_mode);
+        let returned = self.inner.lock().new_delivery_complete(confirm_mode);
+        // Called without the lock so that it can publish again
+        if let Some((handler, message)) = returned {
+            handler(&message);
+        }
+    }
+
+    pub(crate) fn set_return_handler(&self, handler: ReturnCallback) {
+        self.inner.lock().return_handler = Some(handler);
+    }
+
+    pub(crate) fn drop_delivery(&self) {
+        self.inner.lock().current_message = None;
     }
 
     pub(crate) fn receive_delivery_content(&self, data: Vec<u8>) {
         if let Some(message) = self.inner.lock().current_message.as_mut() {
-            message.delivery.data.extend(data);
+            message.delivery.receive_content(data);
         }
     }
 
@@ -42,6 +59,64 @@ impl ReturnedMessages {
     pub(crate) fn get_waiting_message(&self) -> Option<BasicReturnMessage> {
         self.inner.lock().waiting_messages.pop_front()
     }
+
+    /// Wait for the return of a mandatory publish made without publisher confirms.
+    ///
+    /// The publish stays pending until it gets returned or considered routed, or until the
+    /// returned guard is dropped.
+    pub(crate) fn register_mandatory_publish(
+        &self,
+        exchange: &str,
+        routing_key: &str,
+        resolver: PromiseResolver<MandatoryOutcome>,
+    ) -> PendingMandatoryPublish {
+        let mut inner = self.inner.lock();
+        let id = inner.next_mandatory_publish_id;
+        inner.next_mandatory_publish_id += 1;
+        inner.mandatory_publishes.push_back(MandatoryPublish {
+            id,
+            exchange: exchange.into(),
+            routing_key: routing_key.into(),
+            resolver,
+        });
+        PendingMandatoryPublish {
+            returned_messages: self.clone(),
+            id,
+        }
+    }
+
+    /// Consider a mandatory publish routed if it didn't get returned yet.
+    fn mandatory_publish_routed(&self, id: u64) {
+        let publish = self.inner.lock().take_mandatory_publish(id);
+        if let Some(publish) = publish {
+            publish.resolver.swear(Ok(MandatoryOutcome::Routed));
+        }
+    }
+
+    fn forget_mandatory_publish(&self, id: u64) {
+        let publish = self.inner.lock().take_mandatory_publish(id);
+        drop(publish);
+    }
+}
+
+/// A mandatory publish waiting for its return, forgotten once dropped so that a failed or
+/// cancelled publish doesn't get matched with the return of another one.
+pub(crate) struct PendingMandatoryPublish {
+    returned_messages: ReturnedMessages,
+    id: u64,
+}
+
+impl PendingMandatoryPublish {
+    /// Consider the publish routed if it didn't get returned yet.
+    pub(crate) fn routed(&self) {
+        self.returned_messages.mandatory_publish_routed(self.id);
+    }
+}
+
+impl Drop for PendingMandatoryPublish {
+    fn drop(&mut self) {
+        self.returned_messages.forget_mandatory_publish(self.id);
+    }
 }
 
 impl fmt::Debug for ReturnedMessages {
@@ -51,7 +126,9 @@ impl fmt::Debug for ReturnedMessages {
             debug
                 .field("waiting_messages", &inner.waiting_messages)
                 .field("messages", &inner.messages)
-                .field("non_confirm_messages", &inner.non_confirm_messages);
+                .field("non_confirm_messages", &inner.non_confirm_messages)
+                .field("mandatory_publishes", &inner.mandatory_publishes.len())
+                .field("return_handler", &inner.return_handler.is_some());
         }
         debug.finish()
     }
@@ -64,18 +141,62 @@ pub struct Inner {
     waiting_messages: VecDeque<BasicReturnMessage>,
     messages: Vec<BasicReturnMessage>,
     dropped_confirms: Vec<Promise<Confirmation>>,
+    mandatory_publishes: VecDeque<MandatoryPublish>,
+    next_mandatory_publish_id: u64,
+    return_handler: Option<ReturnCallback>,
+}
+
+struct MandatoryPublish {
+    id: u64,
+    exchange: ShortString,
+    routing_key: ShortString,
+    resolver: PromiseResolver<MandatoryOutcome>,
 }
 
 impl Inner {
-    fn new_delivery_complete(&mut self, confirm_mode: bool) {
-        if let Some(message) = self.current_message.take() {
-            warn!("Server returned us a message: {:?}", message);
-            if confirm_mode {
-                self.waiting_messages.push_back(message);
-            } else {
-                self.non_confirm_messages.push(message);
-            }
+    /// Store the message returned by the server, returning it along with the return handler if
+    /// there is one to call.
+    fn new_delivery_complete(
+        &mut self,
+        confirm_mode: bool,
+    ) -> Option<(ReturnCallback, BasicReturnMessage)> {
+        let mut message = self.current_message.take()?;
+        message.delivery.complete();
+        warn!(target: targets::CHANNEL, "Server returned us a message: {:?}", message);
+        let returned = self
+            .return_handler
+            .clone()
+            .map(|handler| (handler, message.clone()));
+        if confirm_mode {
+            self.waiting_messages.push_back(message);
+        } else if let Some(publish) = self.take_returned_mandatory_publish(&message) {
+            publish
+                .resolver
+                .swear(Ok(MandatoryOutcome::Returned(message)));
+        } else {
+            self.non_confirm_messages.push(message);
         }
+        returned
+    }
+
+    /* Returns don't identify their publish: match the oldest one sent to the same destination */
+    fn take_returned_mandatory_publish(
+        &mut self,
+        message: &BasicReturnMessage,
+    ) -> Option<MandatoryPublish> {
+        let position = self.mandatory_publishes.iter().position(|publish| {
+            publish.exchange == message.delivery.exchange
+                && publish.routing_key == message.delivery.routing_key
+        })?;
+        self.mandatory_publishes.remove(position)
+    }
+
+    fn take_mandatory_publish(&mut self, id: u64) -> Option<MandatoryPublish> {
+        let position = self
+            .mandatory_publishes
+            .iter()
+            .position(|publish| publish.id == id)?;
+        self.mandatory_publishes.remove(position)
     }
 
     fn register_dropped_confirm(&mut self, promise: Promise<Confirmation>) {
@@ -83,13 +204,19 @@ impl Inner {
             if let Ok(Confirmation::Nack(Some(message))) | Ok(Confirmation::Ack(Some(message))) =
                 confirmation
             {
-                trace!("Dropped PublisherConfirm was carrying a message, storing it");
+                trace!(
+                    target: targets::CHANNEL,
+                    "Dropped PublisherConfirm was carrying a message, storing it"
+                );
                 self.messages.push(*message);
             } else {
-                trace!("Dropped PublisherConfirm was ready but didn't carry a message, discarding");
+                trace!(
+                    target: targets::CHANNEL,
+                    "Dropped PublisherConfirm was ready but didn't carry a message, discarding"
+                );
             }
         } else {
-            trace!("Storing dropped PublisherConfirm for further use");
+            trace!(target: targets::CHANNEL, "Storing dropped PublisherConfirm for further use");
             self.dropped_confirms.push(promise);
         }
     }
@@ -108,17 +235,27 @@ impl Inner {
                     if let Ok(Confirmation::Nack(Some(message)))
                     | Ok(Confirmation::Ack(Some(message))) = confirmation
                     {
-                        trace!("PublisherConfirm was carrying a message, storing it");
+                        trace!(
+                            target: targets::CHANNEL,
+                            "PublisherConfirm was carrying a message, storing it"
+                        );
                         messages.push(*message);
                     } else {
-                        trace!("PublisherConfirm was ready but didn't carry a message, discarding");
+                        trace!(
+                            target: targets::CHANNEL,
+                            "PublisherConfirm was ready but didn't carry a message, discarding"
+                        );
                     }
                 } else {
-                    trace!("PublisherConfirm wasn't ready yet, storing it back");
+                    trace!(
+                        target: targets::CHANNEL,
+                        "PublisherConfirm wasn't ready yet, storing it back"
+                    );
                     self.dropped_confirms.push(promise);
                 }
             }
             trace!(
+                target: targets::CHANNEL,
                 "{} PublisherConfirms left (was {})",
                 self.dropped_confirms.len(),
                 before
@@ -0,0 +1,309 @@
+//! Idiomatic serde support for [`AMQPValue`] and [`FieldTable`].
+//!
+//! The serde implementations provided by amq-protocol keep the exact AMQP type of each value,
+//! which gives JSON such as `{"LongString": "pdf"}`. This module maps them to the natural JSON
+//! types instead, to store arguments in databases or configuration files:
+//!
+//! * strings become JSON strings
+//! * integers, floats, decimals and timestamps become JSON numbers
+//! * tables become JSON objects
+//! * arrays and byte arrays become JSON arrays
+//! * void becomes `null`
+//!
+//! As JSON doesn't know about the AMQP types, loading the values back picks a canonical one:
+//! integers become `LongLongInt` (or `Timestamp` if they don't fit), other numbers become
+//! `Double` and strings become `LongString`.
+//!
+//! `AMQPValue` and `FieldTable` come from amq-protocol, so the serde traits are implemented on
+//! the [`Value`] and [`Table`] wrappers. A `FieldTable` field can also use this representation
+//! with `#[serde(with = "lapin::serde_amqp")]`.
+//!
+//! [`AMQPValue`]: ../types/enum.AMQPValue.html
+//! [`FieldTable`]: ../types/struct.FieldTable.html
+//! [`Value`]: ./struct.Value.html
+//! [`Table`]: ./struct.Table.html
+
+use crate::types::{AMQPValue, FieldArray, FieldTable};
+use serde::{
+    de::{self, Deserializer, MapAccess, SeqAccess, Visitor},
+    ser::{SerializeMap, SerializeSeq, Serializer},
+    Deserialize, Serialize,
+};
+use std::{convert::TryFrom, fmt};
+
+/// An [`AMQPValue`] (de)serialized as the matching JSON type.
+///
+/// [`AMQPValue`]: ../types/enum.AMQPValue.html
+#[derive(Clone, Debug, PartialEq)]
+pub struct Value(pub AMQPValue);
+
+/// A [`FieldTable`] (de)serialized as a JSON object.
+///
+/// [`FieldTable`]: ../types/struct.FieldTable.html
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Table(pub FieldTable);
+
+/// Serialize a `FieldTable` as a JSON object, for use with `#[serde(with)]`.
+pub fn serialize<S: Serializer>(table: &FieldTable, serializer: S) -> Result<S::Ok, S::Error> {
+    TableRef(table).serialize(serializer)
+}
+
+/// Deserialize a `FieldTable` from a JSON object, for use with `#[serde(with)]`.
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<FieldTable, D::Error> {
+    Table::deserialize(deserializer).map(|table| table.0)
+}
+
+struct ValueRef<'a>(&'a AMQPValue);
+
+struct TableRef<'a>(&'a FieldTable);
+
+impl Serialize for ValueRef<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.0 {
+            AMQPValue::Boolean(value) => serializer.serialize_bool(*value),
+            AMQPValue::ShortShortInt(value) => serializer.serialize_i8(*value),
+            AMQPValue::ShortShortUInt(value) => serializer.serialize_u8(*value),
+            AMQPValue::ShortInt(value) => serializer.serialize_i16(*value),
+            AMQPValue::ShortUInt(value) => serializer.serialize_u16(*value),
+            AMQPValue::LongInt(value) => serializer.serialize_i32(*value),
+            AMQPValue::LongUInt(value) => serializer.serialize_u32(*value),
+            AMQPValue::LongLongInt(value) => serializer.serialize_i64(*value),
+            AMQPValue::Float(value) => serializer.serialize_f32(*value),
+            AMQPValue::Double(value) => serializer.serialize_f64(*value),
+            AMQPValue::DecimalValue(decimal) => serializer
+                .serialize_f64(f64::from(decimal.value) / 10f64.powi(decimal.scale.into())),
+            AMQPValue::ShortString(value) => serializer.serialize_str(value.as_str()),
+            AMQPValue::LongString(value) => serializer.serialize_str(value.as_str()),
+            AMQPValue::FieldArray(array) => {
+                let mut seq = serializer.serialize_seq(Some(array.as_slice().len()))?;
+                for value in array.as_slice() {
+                    seq.serialize_element(&ValueRef(value))?;
+                }
+                seq.end()
+            }
+            AMQPValue::Timestamp(value) => serializer.serialize_u64(*value),
+            AMQPValue::FieldTable(table) => TableRef(table).serialize(serializer),
+            AMQPValue::ByteArray(bytes) => {
+                let mut seq = serializer.serialize_seq(Some(bytes.as_slice().len()))?;
+                for byte in bytes.as_slice() {
+                    seq.serialize_element(byte)?;
+                }
+                seq.end()
+            }
+            AMQPValue::Void => serializer.serialize_unit(),
+        }
+    }
+}
+
+impl Serialize for TableRef<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let table = self.0.inner();
+        let mut map = serializer.serialize_map(Some(table.len()))?;
+        for (key, value) in table {
+            map.serialize_entry(key.as_str(), &ValueRef(value))?;
+        }
+        map.end()
+    }
+}
+
+impl Serialize for Value {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        ValueRef(&self.0).serialize(serializer)
+    }
+}
+
+impl Serialize for Table {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        TableRef(&self.0).serialize(serializer)
+    }
+}
+
+struct ValueVisitor;
+
+impl<'de> Visitor<'de> for ValueVisitor {
+    type Value = AMQPValue;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a JSON value")
+    }
+
+    fn visit_bool<E: de::Error>(self, value: bool) -> Result<AMQPValue, E> {
+        Ok(AMQPValue::Boolean(value))
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<AMQPValue, E> {
+        Ok(AMQPValue::LongLongInt(value))
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<AMQPValue, E> {
+        Ok(i64::try_from(value).map_or(AMQPValue::Timestamp(value), AMQPValue::LongLongInt))
+    }
+
+    fn visit_f64<E: de::Error>(self, value: f64) -> Result<AMQPValue, E> {
+        Ok(AMQPValue::Double(value))
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<AMQPValue, E> {
+        Ok(AMQPValue::LongString(value.into()))
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<AMQPValue, E> {
+        Ok(AMQPValue::Void)
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<AMQPValue, E> {
+        Ok(AMQPValue::Void)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<AMQPValue, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<AMQPValue, A::Error> {
+        let mut array = FieldArray::default();
+        while let Some(Value(value)) = seq.next_element()? {
+            array.push(value);
+        }
+        Ok(AMQPValue::FieldArray(array))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<AMQPValue, A::Error> {
+        TableVisitor.visit_map(map).map(AMQPValue::FieldTable)
+    }
+}
+
+struct TableVisitor;
+
+impl<'de> Visitor<'de> for TableVisitor {
+    type Value = FieldTable;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a JSON object")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<FieldTable, A::Error> {
+        let mut table = FieldTable::default();
+        while let Some((key, Value(value))) = map.next_entry::<String, Value>()? {
+            table.insert(key.into(), value);
+        }
+        Ok(table)
+    }
+}
+
+impl<'de> Deserialize<'de> for Value {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(ValueVisitor).map(Value)
+    }
+}
+
+impl<'de> Deserialize<'de> for Table {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_map(TableVisitor).map(Table)
+    }
+}
+
+impl From<AMQPValue> for Value {
+    fn from(value: AMQPValue) -> Self {
+        Value(value)
+    }
+}
+
+impl From<FieldTable> for Table {
+    fn from(table: FieldTable) -> Self {
+        Table(table)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::DecimalValue;
+
+    #[test]
+    fn table_to_json() {
+        let mut nested = FieldTable::default();
+        nested.insert("enabled".into(), AMQPValue::Boolean(true));
+        let mut table = FieldTable::default();
+        table.insert("x-max-length".into(), AMQPValue::LongUInt(1000));
+        table.insert(
+            "x-queue-type".into(),
+            AMQPValue::LongString("quorum".into()),
+        );
+        table.insert(
+            "price".into(),
+            AMQPValue::DecimalValue(DecimalValue {
+                scale: 2,
+                value: 1250,
+            }),
+        );
+        table.insert(
+            "formats".into(),
+            AMQPValue::FieldArray(vec![AMQPValue::ShortString("pdf".into())].into()),
+        );
+        table.insert("nested".into(), AMQPValue::FieldTable(nested));
+        table.insert("nothing".into(), AMQPValue::Void);
+
+        assert_eq!(
+            serde_json::to_value(Table(table)).unwrap(),
+            serde_json::json!({
+                "x-max-length": 1000,
+                "x-queue-type": "quorum",
+                "price": 12.5,
+                "formats": ["pdf"],
+                "nested": { "enabled": true },
+                "nothing": null,
+            })
+        );
+    }
+
+    #[test]
+    fn table_from_json() {
+        let table: Table = serde_json::from_str(
+            r#"{"x-max-length": 1000, "x-message-ttl": -1, "ratio": 0.5, "name": "orders", "tags": ["a", 1], "nested": {"big": 18446744073709551615}, "nothing": null}"#,
+        )
+        .unwrap();
+
+        let mut nested = FieldTable::default();
+        nested.insert("big".into(), AMQPValue::Timestamp(u64::MAX));
+        let mut expected = FieldTable::default();
+        expected.insert("x-max-length".into(), AMQPValue::LongLongInt(1000));
+        expected.insert("x-message-ttl".into(), AMQPValue::LongLongInt(-1));
+        expected.insert("ratio".into(), AMQPValue::Double(0.5));
+        expected.insert("name".into(), AMQPValue::LongString("orders".into()));
+        expected.insert(
+            "tags".into(),
+            AMQPValue::FieldArray(
+                vec![AMQPValue::LongString("a".into()), AMQPValue::LongLongInt(1)].into(),
+            ),
+        );
+        expected.insert("nested".into(), AMQPValue::FieldTable(nested));
+        expected.insert("nothing".into(), AMQPValue::Void);
+        assert_eq!(table, Table(expected));
+    }
+
+    #[test]
+    fn with_attribute() {
+        #[derive(Debug, PartialEq, Deserialize, Serialize)]
+        struct Declaration {
+            queue: String,
+            #[serde(with = "crate::serde_amqp")]
+            arguments: FieldTable,
+        }
+
+        let mut arguments = FieldTable::default();
+        arguments.insert("x-expires".into(), AMQPValue::LongLongInt(60_000));
+        let declaration = Declaration {
+            queue: "orders".into(),
+            arguments,
+        };
+        let json = serde_json::to_string(&declaration).unwrap();
+        assert_eq!(
+            json,
+            r#"{"queue":"orders","arguments":{"x-expires":60000}}"#
+        );
+        assert_eq!(
+            serde_json::from_str::<Declaration>(&json).unwrap(),
+            declaration
+        );
+    }
+}
@@ -0,0 +1,125 @@
+use crate::{
+    options::{QueueBindOptions, QueueDeclareOptions},
+    tracing_integration::targets,
+    types::{FieldTable, ShortString},
+};
+use parking_lot::Mutex;
+use std::{fmt, sync::Arc};
+use tracing::trace;
+
+/// Remembers the server-named queues declared on a connection along with their bindings, to be
+/// able to declare them again and rewrite everything that refers to their new name.
+#[derive(Clone, Default)]
+pub(crate) struct ServerNamedQueues(Arc<Mutex<Vec<ServerNamedQueue>>>);
+
+#[derive(Clone, Debug)]
+pub(crate) struct ServerNamedQueue {
+    /* The name the server gave to the queue last time we declared it */
+    pub(crate) name: ShortString,
+    pub(crate) options: QueueDeclareOptions,
+    pub(crate) arguments: FieldTable,
+    pub(crate) bindings: Vec<ServerNamedQueueBinding>,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct ServerNamedQueueBinding {
+    pub(crate) exchange: ShortString,
+    pub(crate) routing_key: ShortString,
+    pub(crate) options: QueueBindOptions,
+    pub(crate) arguments: FieldTable,
+}
+
+impl ServerNamedQueues {
+    pub(crate) fn register(
+        &self,
+        name: ShortString,
+        options: QueueDeclareOptions,
+        arguments: FieldTable,
+    ) {
+        trace!(target: targets::CHANNEL, "tracking server-named queue; queue={}", name);
+        self.0.lock().push(ServerNamedQueue {
+            name,
+            options,
+            arguments,
+            bindings: Vec::new(),
+        });
+    }
+
+    pub(crate) fn is_server_named(&self, queue: &str) -> bool {
+        self.0
+            .lock()
+            .iter()
+            .any(|declared| declared.name.as_str() == queue)
+    }
+
+    pub(crate) fn forget(&self, queue: &str) {
+        self.0
+            .lock()
+            .retain(|declared| declared.name.as_str() != queue);
+    }
+
+    pub(crate) fn register_binding(
+        &self,
+        queue: &str,
+        exchange: &str,
+        routing_key: &str,
+        options: QueueBindOptions,
+        arguments: FieldTable,
+    ) {
+        self.with_queue(queue, |declared| {
+            trace!(
+                target: targets::CHANNEL,
+                "tracking server-named queue binding; queue={}, exchange={}, routing_key={}",
+                queue,
+                exchange,
+                routing_key
+            );
+            declared.bindings.push(ServerNamedQueueBinding {
+                exchange: exchange.into(),
+                routing_key: routing_key.into(),
+                options,
+                arguments,
+            });
+        });
+    }
+
+    pub(crate) fn forget_binding(&self, queue: &str, exchange: &str, routing_key: &str) {
+        self.with_queue(queue, |declared| {
+            declared.bindings.retain(|binding| {
+                binding.exchange.as_str() != exchange || binding.routing_key.as_str() != routing_key
+            });
+        });
+    }
+
+    pub(crate) fn declarations(&self) -> Vec<ServerNamedQueue> {
+        self.0.lock().clone()
+    }
+
+    pub(crate) fn rename(&self, queue: &str, name: ShortString) {
+        self.with_queue(queue, |declared| {
+            trace!(target: targets::CHANNEL, "server-named queue {} is now {}", queue, name);
+            declared.name = name;
+        });
+    }
+
+    fn with_queue<F: FnOnce(&mut ServerNamedQueue)>(&self, queue: &str, f: F) {
+        if let Some(declared) = self
+            .0
+            .lock()
+            .iter_mut()
+            .find(|declared| declared.name.as_str() == queue)
+        {
+            f(declared);
+        }
+    }
+}
+
+impl fmt::Debug for ServerNamedQueues {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_list();
+        if let Some(inner) = self.0.try_lock() {
+            debug.entries(inner.iter().map(|declared| &declared.name));
+        }
+        debug.finish()
+    }
+}
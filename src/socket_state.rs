@@ -1,5 +1,6 @@
 use crate::{
     reactor::{ReactorHandle, Slot},
+    tracing_integration::targets,
     Result,
 };
 use flume::{Receiver, Sender};
@@ -101,7 +102,7 @@ impl SocketState {
     }
 
     fn handle_event(&mut self, event: SocketEvent) {
-        trace!("Got event for socket: {:?}", event);
+        trace!(target: targets::IO, "Got event for socket: {:?}", event);
         match event {
             SocketEvent::Readable => self.readable = true,
             SocketEvent::Writable => self.writable = true,
@@ -1,10 +1,17 @@
 use crate::{tcp, Error, Result};
+use parking_lot::Mutex;
 use std::{
     convert::TryFrom,
     fmt,
     io::{self, IoSlice, IoSliceMut, Read, Write},
+    sync::Arc,
 };
 
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, RawFd};
+#[cfg(windows)]
+use std::os::windows::io::{AsRawSocket, RawSocket};
+
 pub struct TcpStream(Inner);
 
 enum Inner {
@@ -34,13 +41,6 @@ impl TcpStream {
         }
     }
 
-    pub(crate) fn inner_mut(&mut self) -> &mut tcp::TcpStream {
-        match self.0 {
-            Inner::Connected(ref mut stream) => stream,
-            Inner::Handshaking(ref mut handshaker) => handshaker.as_mut().unwrap().get_mut(),
-        }
-    }
-
     pub(crate) fn is_handshaking(&self) -> bool {
         if let Inner::Handshaking(_) = self.0 {
             true
@@ -114,3 +114,110 @@ impl Write for TcpStream {
         fwd_impl!(self, write_fmt, fmt)
     }
 }
+
+/// A handle on the socket of a connection, to be registered into a [`Reactor`].
+///
+/// It shares the ownership of the connection's stream, so the socket remains open for as long
+/// as the reactor keeps it around, even once the connection is gone, and gets closed once both
+/// of them released it.
+///
+/// [`Reactor`]: ./reactor/trait.Reactor.html
+pub struct ReactorSocket {
+    // Only held to keep the socket open
+    _stream: Arc<Mutex<TcpStream>>,
+    #[cfg(unix)]
+    fd: RawFd,
+    #[cfg(windows)]
+    socket: RawSocket,
+}
+
+impl ReactorSocket {
+    pub(crate) fn new(stream: Arc<Mutex<TcpStream>>) -> Self {
+        // The TLS handshake doesn't change the underlying socket, it's safe to remember it
+        #[cfg(unix)]
+        let fd = stream.lock().inner().as_raw_fd();
+        #[cfg(windows)]
+        let socket = stream.lock().inner().as_raw_socket();
+        Self {
+            _stream: stream,
+            #[cfg(unix)]
+            fd,
+            #[cfg(windows)]
+            socket,
+        }
+    }
+}
+
+#[cfg(unix)]
+impl AsRawFd for ReactorSocket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+#[cfg(windows)]
+impl AsRawSocket for ReactorSocket {
+    fn as_raw_socket(&self) -> RawSocket {
+        self.socket
+    }
+}
+
+impl fmt::Debug for ReactorSocket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("ReactorSocket");
+        #[cfg(unix)]
+        debug.field("fd", &self.fd);
+        #[cfg(windows)]
+        debug.field("socket", &self.socket);
+        debug.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use async_io::Async;
+    use std::net::{self, TcpListener};
+
+    fn stream_pair() -> (Arc<Mutex<TcpStream>>, net::TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = tcp::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        let client = TcpStream::try_from(Ok(client)).unwrap();
+        (Arc::new(Mutex::new(client)), server)
+    }
+
+    #[test]
+    fn reactor_socket_outlives_stream() {
+        let (client, mut server) = stream_pair();
+        let socket = ReactorSocket::new(client.clone());
+        drop(client);
+        let socket = Async::new(socket).unwrap();
+        async_global_executor::block_on(socket.writable()).unwrap();
+        server.write_all(b"ping").unwrap();
+        async_global_executor::block_on(socket.readable()).unwrap();
+    }
+
+    #[test]
+    fn dropping_reactor_socket_keeps_stream_open() {
+        let (client, mut server) = stream_pair();
+        let socket = Async::new(ReactorSocket::new(client.clone())).unwrap();
+        async_global_executor::block_on(socket.writable()).unwrap();
+        drop(socket);
+        client.lock().write_all(b"ping").unwrap();
+        let mut buf = [0; 4];
+        server.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ping");
+    }
+
+    #[test]
+    fn socket_closed_once_released() {
+        let (client, mut server) = stream_pair();
+        let socket = Async::new(ReactorSocket::new(client.clone())).unwrap();
+        drop(client);
+        drop(socket);
+        let mut buf = [0; 4];
+        assert_eq!(server.read(&mut buf).unwrap(), 0);
+    }
+}
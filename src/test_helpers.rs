@@ -0,0 +1,155 @@
+//! Helpers shared by the unit tests, which play the server and the io loop of a connection
+//! that has no socket.
+
+pub(crate) use crate::{
+    cancellation::CancellationToken,
+    channel_receiver_state::ChannelReceiverState,
+    channel_status::ChannelState,
+    consumer::{Consumer, PoisonAction},
+    executor::DefaultExecutor,
+    frames::Frames,
+    message::{Delivery, DeliveryResult},
+    publisher_confirm::MandatoryOutcome,
+    types::{AMQPValue, FieldTable, ShortString},
+    BasicProperties, Channel, Connection, ConnectionProperties, ConnectionState, Error,
+};
+pub(crate) use amq_protocol::{
+    frame::{AMQPContentHeader, AMQPFrame},
+    protocol::{basic, AMQPClass},
+};
+pub(crate) use futures_lite::Stream;
+pub(crate) use parking_lot::Mutex;
+pub(crate) use std::{
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+pub(crate) use waker_fn::waker_fn;
+
+pub(crate) fn deliver_with_delivery_count(
+    conn: &Connection,
+    channel: &Channel,
+    consumer_tag: &ShortString,
+    delivery_tag: u64,
+    delivery_count: i64,
+) {
+    let method = AMQPClass::Basic(basic::AMQPMethod::Deliver(basic::Deliver {
+        consumer_tag: consumer_tag.clone(),
+        delivery_tag,
+        redelivered: true,
+        exchange: "".into(),
+        routing_key: "consumed".into(),
+    }));
+    conn.channels()
+        .handle_frame(AMQPFrame::Method(channel.id(), method))
+        .unwrap();
+    let mut headers = FieldTable::default();
+    headers.insert(
+        "x-delivery-count".into(),
+        AMQPValue::LongLongInt(delivery_count),
+    );
+    let header_frame = AMQPFrame::Header(
+        channel.id(),
+        60,
+        Box::new(AMQPContentHeader {
+            class_id: 60,
+            weight: 0,
+            body_size: 0,
+            properties: BasicProperties::default().with_headers(headers),
+        }),
+    );
+    conn.channels().handle_frame(header_frame).unwrap();
+}
+
+/// A connected channel on a connection without socket, whose frames are left in the returned
+/// `Frames` for the tests to play the io loop.
+pub(crate) fn connected_channel() -> (Connection, Channel, Frames) {
+    let frames = Frames::default();
+    let conn = Connection::connected_for_tests(frames.clone());
+    let channel = conn.channels().create(conn.closer()).unwrap();
+    channel.set_state(ChannelState::Connected);
+    (conn, channel, frames)
+}
+
+/// A connected channel with `consumer` consuming from the `consumed` queue.
+pub(crate) fn channel_with_consumer(consumer: &Consumer) -> (Connection, Channel) {
+    use crate::queue::{Queue, QueueState};
+
+    let (conn, channel, _) = connected_channel();
+    let mut queue: QueueState = Queue::new("consumed".into(), 0, 0).into();
+    queue.register_consumer(consumer.tag(), consumer.clone());
+    conn.channels()
+        .get(channel.id())
+        .map(|c| c.register_queue(queue));
+    (conn, channel)
+}
+
+pub(crate) fn content_frames(
+    channel: &Channel,
+    method: basic::AMQPMethod,
+    properties: BasicProperties,
+    bodies: &[&[u8]],
+) -> Vec<AMQPFrame> {
+    let mut frames = vec![
+        AMQPFrame::Method(channel.id(), AMQPClass::Basic(method)),
+        AMQPFrame::Header(
+            channel.id(),
+            60,
+            Box::new(AMQPContentHeader {
+                class_id: 60,
+                weight: 0,
+                body_size: bodies.iter().map(|body| body.len() as u64).sum(),
+                properties,
+            }),
+        ),
+    ];
+    frames.extend(
+        bodies
+            .iter()
+            .map(|body| AMQPFrame::Body(channel.id(), body.to_vec())),
+    );
+    frames
+}
+
+/* Play the socket: write out the given number of frames */
+pub(crate) fn write_frames(frames: &Frames, mut count: usize) {
+    while count > 0 {
+        if let Some((_, resolver)) = frames.pop(true) {
+            if let Some(resolver) = resolver {
+                resolver.swear(Ok(()));
+            }
+            count -= 1;
+        } else {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
+
+pub(crate) fn deliver_frames(channel: &Channel, consumer_tag: &ShortString) -> Vec<AMQPFrame> {
+    content_frames(
+        channel,
+        basic::AMQPMethod::Deliver(basic::Deliver {
+            consumer_tag: consumer_tag.clone(),
+            delivery_tag: 1,
+            redelivered: false,
+            exchange: "".into(),
+            routing_key: "consumed".into(),
+        }),
+        BasicProperties::default(),
+        &[b"hel", b"lo"],
+    )
+}
+
+pub(crate) fn answer_next_frame(conn: &Connection, frames: &Frames, reply: AMQPFrame) -> AMQPFrame {
+    loop {
+        if let Some((frame, resolver)) = frames.pop(true) {
+            if let Some(resolver) = resolver {
+                resolver.swear(Ok(()));
+            }
+            conn.channels().handle_frame(reply).unwrap();
+            return frame;
+        }
+        std::thread::sleep(Duration::from_millis(1));
+    }
+}
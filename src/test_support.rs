@@ -0,0 +1,150 @@
+//! Helpers to check what lapin sends to the server in tests.
+//!
+//! This module is only available with the `test-support` feature.
+
+use crate::{protocol::AMQPClass, types::ShortUInt, Channel};
+use parking_lot::Mutex;
+use std::{fmt, sync::Arc};
+
+pub use amq_protocol::frame::AMQPFrame;
+
+/// The class and method ids of `basic.publish`.
+const BASIC_PUBLISH: (ShortUInt, ShortUInt) = (60, 40);
+
+/// Records the frames queued for a channel, from its installation until it's dropped.
+///
+/// The frames are recorded in the order they were queued, which is the order in which the frames
+/// of this channel are sent.
+#[derive(Clone)]
+pub struct FrameCapture {
+    frames: Arc<Mutex<Vec<AMQPFrame>>>,
+}
+
+impl FrameCapture {
+    /// Start recording the frames of `channel`.
+    pub fn install(channel: &Channel) -> Self {
+        let frames = Arc::new(Mutex::new(Vec::new()));
+        channel.capture_frames(&frames);
+        Self { frames }
+    }
+
+    /// The frames recorded so far.
+    pub fn captured(&self) -> Vec<AMQPFrame> {
+        self.frames.lock().clone()
+    }
+
+    /// Forget about the frames recorded so far.
+    pub fn clear(&self) {
+        self.frames.lock().clear();
+    }
+
+    /// The class and method ids of the method frames recorded so far.
+    pub fn methods(&self) -> Vec<(ShortUInt, ShortUInt)> {
+        self.frames
+            .lock()
+            .iter()
+            .filter_map(|frame| match frame {
+                AMQPFrame::Method(_, method) => Some(method_ids(method)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Panic unless a method with these class and method ids has been recorded.
+    pub fn assert_method(&self, class_id: ShortUInt, method_id: ShortUInt) {
+        let methods = self.methods();
+        assert!(
+            methods.contains(&(class_id, method_id)),
+            "no method {}.{} was sent, got {:?}",
+            class_id,
+            method_id,
+            methods
+        );
+    }
+
+    /// Panic unless exactly these methods have been recorded, in this order.
+    pub fn assert_methods(&self, expected: &[(ShortUInt, ShortUInt)]) {
+        assert_eq!(self.methods(), expected);
+    }
+
+    /// Panic unless exactly `count` `basic.publish` have been recorded.
+    pub fn assert_publish_count(&self, count: usize) {
+        let published = self
+            .methods()
+            .into_iter()
+            .filter(|method| *method == BASIC_PUBLISH)
+            .count();
+        assert_eq!(
+            published, count,
+            "expected {} basic.publish, got {}",
+            count, published
+        );
+    }
+}
+
+fn method_ids(method: &AMQPClass) -> (ShortUInt, ShortUInt) {
+    (method.get_amqp_class_id(), method.get_amqp_method_id())
+}
+
+impl fmt::Debug for FrameCapture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("FrameCapture");
+        if let Some(frames) = self.frames.try_lock() {
+            debug.field("frames", &frames.len());
+        }
+        debug.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_helpers::*;
+
+    #[test]
+    fn frame_capture() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        use crate::{options::BasicPublishOptions, test_support::FrameCapture};
+
+        let (conn, channel, frames) = connected_channel();
+        let other = conn.channels().create(conn.closer()).unwrap();
+        other.set_state(ChannelState::Connected);
+        conn.configuration().set_frame_max(4096);
+        let capture = FrameCapture::install(&channel);
+
+        let publish = |channel: &Channel| {
+            let channel = channel.clone();
+            async_global_executor::spawn(async move {
+                channel
+                    .basic_publish(
+                        "",
+                        "captured",
+                        BasicPublishOptions::default(),
+                        b"payload".to_vec(),
+                        BasicProperties::default(),
+                    )
+                    .await
+            })
+        };
+        let published = publish(&channel);
+        let ignored = publish(&other);
+        write_frames(&frames, 6);
+        assert!(async_global_executor::block_on(published).is_ok());
+        assert!(async_global_executor::block_on(ignored).is_ok());
+
+        capture.assert_publish_count(1);
+        capture.assert_methods(&[(60, 40)]);
+        let captured = capture.captured();
+        assert_eq!(captured.len(), 3);
+        match &captured[2] {
+            AMQPFrame::Body(channel_id, body) => {
+                assert_eq!(*channel_id, channel.id());
+                assert_eq!(body.as_slice(), b"payload");
+            }
+            frame => panic!("unexpected frame: {:?}", frame),
+        }
+
+        capture.clear();
+        assert!(capture.captured().is_empty());
+    }
+}
@@ -0,0 +1,116 @@
+//! Conversions between AMQP timestamps and [`SystemTime`].
+//!
+//! AMQP timestamps are the number of seconds since the UNIX epoch. `lapin::types::Timestamp`
+//! is a plain `u64` alias coming from amq-protocol, the [`Timestamp`] newtype of this module
+//! converts from and, when representable, to [`SystemTime`] and into an [`AMQPValue`] to be used
+//! in headers.
+//!
+//! [`SystemTime`]: https://doc.rust-lang.org/std/time/struct.SystemTime.html
+//! [`Timestamp`]: ./struct.Timestamp.html
+//! [`AMQPValue`]: ../types/enum.AMQPValue.html
+
+use crate::{types::AMQPValue, BasicProperties};
+use std::{
+    mem,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// A number of seconds since the UNIX epoch.
+///
+/// Converting from a [`SystemTime`] truncates to the second, times before the UNIX epoch
+/// become 0.
+///
+/// [`SystemTime`]: https://doc.rust-lang.org/std/time/struct.SystemTime.html
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Timestamp(pub u64);
+
+impl Timestamp {
+    /// The current time.
+    pub fn now() -> Self {
+        SystemTime::now().into()
+    }
+
+    /// This timestamp as a [`SystemTime`], or `None` if it's too far in the future to be
+    /// represented on this platform.
+    ///
+    /// [`SystemTime`]: https://doc.rust-lang.org/std/time/struct.SystemTime.html
+    pub fn to_system_time(self) -> Option<SystemTime> {
+        UNIX_EPOCH.checked_add(Duration::from_secs(self.0))
+    }
+}
+
+impl From<SystemTime> for Timestamp {
+    fn from(time: SystemTime) -> Self {
+        Timestamp(
+            time.duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_secs()),
+        )
+    }
+}
+
+impl From<Timestamp> for AMQPValue {
+    fn from(timestamp: Timestamp) -> Self {
+        AMQPValue::Timestamp(timestamp.0)
+    }
+}
+
+/// Read and write the `timestamp` property as a [`SystemTime`].
+///
+/// [`SystemTime`]: https://doc.rust-lang.org/std/time/struct.SystemTime.html
+pub trait TimestampProperty {
+    /// Set the `timestamp` property, truncated to the second.
+    fn set_timestamp(&mut self, time: SystemTime);
+
+    /// The `timestamp` property, if set and representable as a [`SystemTime`].
+    ///
+    /// [`SystemTime`]: https://doc.rust-lang.org/std/time/struct.SystemTime.html
+    fn timestamp_as_system_time(&self) -> Option<SystemTime>;
+}
+
+impl TimestampProperty for BasicProperties {
+    fn set_timestamp(&mut self, time: SystemTime) {
+        *self = mem::take(self).with_timestamp(Timestamp::from(time).0);
+    }
+
+    fn timestamp_as_system_time(&self) -> Option<SystemTime> {
+        (*self.timestamp()).and_then(|timestamp| Timestamp(timestamp).to_system_time())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conversions() {
+        let time = UNIX_EPOCH + Duration::from_millis(1_600_000_000_750);
+        let timestamp = Timestamp::from(time);
+        assert_eq!(timestamp, Timestamp(1_600_000_000));
+        assert_eq!(
+            timestamp.to_system_time(),
+            Some(UNIX_EPOCH + Duration::from_secs(1_600_000_000))
+        );
+        assert_eq!(Timestamp(u64::MAX).to_system_time(), None);
+        assert_eq!(
+            AMQPValue::from(timestamp),
+            AMQPValue::Timestamp(1_600_000_000)
+        );
+        assert_eq!(
+            Timestamp::from(UNIX_EPOCH - Duration::from_secs(1)),
+            Timestamp(0)
+        );
+    }
+
+    #[test]
+    fn properties() {
+        let mut properties = BasicProperties::default();
+        assert_eq!(properties.timestamp_as_system_time(), None);
+        let time = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        properties.set_timestamp(time);
+        assert_eq!(properties.timestamp(), &Some(1_600_000_000));
+        assert_eq!(properties.timestamp_as_system_time(), Some(time));
+        // An untrusted value from the wire mustn't make the conversion panic
+        let properties = BasicProperties::default().with_timestamp(u64::MAX);
+        assert_eq!(properties.timestamp_as_system_time(), None);
+    }
+}
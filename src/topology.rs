@@ -0,0 +1,885 @@
+use crate::{
+    options::{
+        ExchangeBindOptions, ExchangeDeclareOptions, ExchangeDeleteOptions, ExchangeUnbindOptions,
+        QueueBindOptions, QueueDeclareOptions, QueueDeleteOptions,
+    },
+    protocol::{self, AMQPErrorKind, AMQPSoftError},
+    tracing_integration::targets,
+    types::{FieldTable, ShortUInt},
+    Channel, ChannelState, Error, ExchangeKind, Queue, Result,
+};
+use parking_lot::Mutex;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::{fmt, sync::Arc};
+use tracing::error;
+
+/// A declarative description of a set of exchanges, queues and bindings.
+///
+/// A `Topology` can be built programmatically or, with the `serde` feature, parsed from JSON,
+/// and then applied to a [`Channel`]. As it can be cloned and applied again, it can be kept
+/// around to restore the topology after a reconnection.
+///
+/// Use [`Channel::declare_topology`] instead of [`apply`] to get rid of the already declared
+/// resources when one of the declarations fails.
+///
+/// [`Channel`]: ../struct.Channel.html
+/// [`Channel::declare_topology`]: ../struct.Channel.html#method.declare_topology
+/// [`apply`]: #method.apply
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize), serde(default))]
+pub struct Topology {
+    exchanges: Vec<ExchangeDeclaration>,
+    queues: Vec<QueueDeclaration>,
+    bindings: Vec<BindingDeclaration>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize), serde(default))]
+pub struct ExchangeDeclaration {
+    pub name: String,
+    pub kind: ExchangeKind,
+    pub options: ExchangeDeclareOptions,
+    pub arguments: FieldTable,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize), serde(default))]
+pub struct QueueDeclaration {
+    pub name: String,
+    pub options: QueueDeclareOptions,
+    pub arguments: FieldTable,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize), serde(default))]
+pub struct BindingDeclaration {
+    pub source: String,
+    pub destination: String,
+    pub destination_kind: DestinationKind,
+    pub routing_key: String,
+    pub nowait: bool,
+    pub arguments: FieldTable,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Deserialize, Serialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum DestinationKind {
+    Queue,
+    Exchange,
+}
+
+impl Default for DestinationKind {
+    fn default() -> Self {
+        Self::Queue
+    }
+}
+
+impl Topology {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn from_json(json: &str) -> Result<Self> {
+        serde_json::from_str(json).map_err(|e| Error::TopologyParsingError(Arc::new(e)))
+    }
+
+    pub fn exchange(mut self, exchange: ExchangeDeclaration) -> Self {
+        self.exchanges.push(exchange);
+        self
+    }
+
+    pub fn queue(mut self, queue: QueueDeclaration) -> Self {
+        self.queues.push(queue);
+        self
+    }
+
+    pub fn binding(mut self, binding: BindingDeclaration) -> Self {
+        self.bindings.push(binding);
+        self
+    }
+
+    pub fn exchanges(&self) -> &[ExchangeDeclaration] {
+        &self.exchanges
+    }
+
+    pub fn queues(&self) -> &[QueueDeclaration] {
+        &self.queues
+    }
+
+    pub fn bindings(&self) -> &[BindingDeclaration] {
+        &self.bindings
+    }
+
+    /// Declare all the exchanges, then all the queues and finally all the bindings on the channel.
+    pub async fn apply(&self, channel: &Channel) -> Result<()> {
+        for exchange in &self.exchanges {
+            exchange.apply(channel).await?;
+        }
+        for queue in &self.queues {
+            queue.apply(channel).await?;
+        }
+        for binding in &self.bindings {
+            binding.apply(channel).await?;
+        }
+        Ok(())
+    }
+}
+
+impl ExchangeDeclaration {
+    pub fn new(name: &str, kind: ExchangeKind) -> Self {
+        Self {
+            name: name.into(),
+            kind,
+            ..Self::default()
+        }
+    }
+
+    pub fn with_options(mut self, options: ExchangeDeclareOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn with_arguments(mut self, arguments: FieldTable) -> Self {
+        self.arguments = arguments;
+        self
+    }
+
+    pub(crate) async fn apply(&self, channel: &Channel) -> Result<()> {
+        channel
+            .exchange_declare(
+                &self.name,
+                self.kind.clone(),
+                self.options,
+                self.arguments.clone(),
+            )
+            .await
+    }
+}
+
+impl QueueDeclaration {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.into(),
+            ..Self::default()
+        }
+    }
+
+    pub fn with_options(mut self, options: QueueDeclareOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn with_arguments(mut self, arguments: FieldTable) -> Self {
+        self.arguments = arguments;
+        self
+    }
+
+    pub(crate) async fn apply(&self, channel: &Channel) -> Result<Queue> {
+        channel
+            .queue_declare(&self.name, self.options, self.arguments.clone())
+            .await
+    }
+}
+
+impl BindingDeclaration {
+    pub fn queue(queue: &str, exchange: &str, routing_key: &str) -> Self {
+        Self {
+            source: exchange.into(),
+            destination: queue.into(),
+            destination_kind: DestinationKind::Queue,
+            routing_key: routing_key.into(),
+            ..Self::default()
+        }
+    }
+
+    pub fn exchange(destination: &str, source: &str, routing_key: &str) -> Self {
+        Self {
+            source: source.into(),
+            destination: destination.into(),
+            destination_kind: DestinationKind::Exchange,
+            routing_key: routing_key.into(),
+            ..Self::default()
+        }
+    }
+
+    pub fn with_arguments(mut self, arguments: FieldTable) -> Self {
+        self.arguments = arguments;
+        self
+    }
+
+    pub(crate) async fn apply(&self, channel: &Channel) -> Result<()> {
+        match self.destination_kind {
+            DestinationKind::Queue => {
+                channel
+                    .queue_bind(
+                        &self.destination,
+                        &self.source,
+                        &self.routing_key,
+                        QueueBindOptions::default().nowait(self.nowait),
+                        self.arguments.clone(),
+                    )
+                    .await
+            }
+            DestinationKind::Exchange => {
+                channel
+                    .exchange_bind(
+                        &self.destination,
+                        &self.source,
+                        &self.routing_key,
+                        ExchangeBindOptions::default().nowait(self.nowait),
+                        self.arguments.clone(),
+                    )
+                    .await
+            }
+        }
+    }
+}
+
+/// The exchanges and the audit queue declared by [`Channel::declare_with_alternate`].
+///
+/// [`Channel::declare_with_alternate`]: ../struct.Channel.html#method.declare_with_alternate
+#[derive(Clone, Debug)]
+pub struct AlternateExchange {
+    /// The main exchange, including its `alternate-exchange` argument.
+    pub exchange: ExchangeDeclaration,
+    /// The exchange getting the messages which can't be routed from the main one.
+    pub alternate: ExchangeDeclaration,
+    /// The queue bound to the alternate exchange, collecting the unroutable messages.
+    pub audit_queue: Queue,
+}
+
+impl Topology {
+    fn record_exchange(&mut self, exchange: ExchangeDeclaration) {
+        self.forget_exchange(&exchange.name, false);
+        self.exchanges.push(exchange);
+    }
+
+    fn record_queue(&mut self, queue: QueueDeclaration) {
+        self.forget_queue(&queue.name, false);
+        self.queues.push(queue);
+    }
+
+    fn record_binding(&mut self, binding: BindingDeclaration) {
+        self.forget_binding(&binding);
+        self.bindings.push(binding);
+    }
+
+    fn forget_exchange(&mut self, name: &str, with_bindings: bool) {
+        self.exchanges.retain(|exchange| exchange.name != name);
+        if with_bindings {
+            self.bindings.retain(|binding| {
+                binding.source != name
+                    && (binding.destination_kind != DestinationKind::Exchange
+                        || binding.destination != name)
+            });
+        }
+    }
+
+    fn forget_queue(&mut self, name: &str, with_bindings: bool) {
+        self.queues.retain(|queue| queue.name != name);
+        if with_bindings {
+            self.bindings.retain(|binding| {
+                binding.destination_kind != DestinationKind::Queue || binding.destination != name
+            });
+        }
+    }
+
+    fn forget_binding(&mut self, binding: &BindingDeclaration) {
+        self.bindings.retain(|known| {
+            known.destination_kind != binding.destination_kind
+                || known.source != binding.source
+                || known.destination != binding.destination
+                || known.routing_key != binding.routing_key
+                || known.arguments != binding.arguments
+        });
+    }
+}
+
+/// A [`Channel`] dedicated to declarations, which remembers everything declared through it.
+///
+/// Create one with [`Connection::create_topology_channel`]. The exchanges, queues and bindings
+/// declared (and deleted) through it are tracked in a [`Topology`], which can be retrieved with
+/// [`topology_snapshot`] and declared again with [`reapply_topology`], for example after the
+/// server lost non-durable resources. Passive declarations are only checks and aren't tracked.
+///
+/// Topology channels are meant to be long-lived and shouldn't be used to publish or consume
+/// messages. Declarations made directly on the underlying [`channel`] aren't tracked.
+///
+/// [`Channel`]: ../struct.Channel.html
+/// [`Connection::create_topology_channel`]: ../struct.Connection.html#method.create_topology_channel
+/// [`Topology`]: ./struct.Topology.html
+/// [`topology_snapshot`]: #method.topology_snapshot
+/// [`reapply_topology`]: #method.reapply_topology
+/// [`channel`]: #method.channel
+#[derive(Clone)]
+pub struct TopologyChannel {
+    channel: Arc<Mutex<Channel>>,
+    topology: Arc<Mutex<Topology>>,
+}
+
+impl TopologyChannel {
+    pub(crate) fn new(channel: Channel) -> Self {
+        Self {
+            channel: Arc::new(Mutex::new(channel)),
+            topology: Arc::default(),
+        }
+    }
+
+    /// The underlying channel, which gets replaced when the topology is recovered after it
+    /// failed, see [`Connection::reapply_all_topologies`].
+    ///
+    /// [`Connection::reapply_all_topologies`]: ../struct.Connection.html#method.reapply_all_topologies
+    pub fn channel(&self) -> Channel {
+        self.channel.lock().clone()
+    }
+
+    /// Whether the underlying channel is still usable.
+    pub fn is_open(&self) -> bool {
+        self.channel().status().connected()
+    }
+
+    /// Whether the underlying channel got closed on purpose, rather than because of an error.
+    pub(crate) fn is_closed_by_client(&self) -> bool {
+        let channel = self.channel();
+        channel.status().state() == ChannelState::Closed
+            && !matches!(
+                channel.status().error_cause(),
+                Some(Error::ProtocolError(_))
+            )
+    }
+
+    /// Switch to `channel` and declare everything again on it.
+    pub(crate) async fn recover(&self, channel: Channel) -> Result<()> {
+        *self.channel.lock() = channel;
+        self.reapply_topology().await
+    }
+
+    /// Everything currently declared through this channel.
+    pub fn topology_snapshot(&self) -> Topology {
+        self.topology.lock().clone()
+    }
+
+    /// Declare again everything which has been declared through this channel.
+    pub async fn reapply_topology(&self) -> Result<()> {
+        let topology = self.topology_snapshot();
+        topology.apply(&self.channel()).await
+    }
+
+    pub async fn exchange_declare(
+        &self,
+        exchange: &str,
+        kind: ExchangeKind,
+        options: ExchangeDeclareOptions,
+        arguments: FieldTable,
+    ) -> Result<()> {
+        let declaration = ExchangeDeclaration::new(exchange, kind)
+            .with_options(options)
+            .with_arguments(arguments);
+        declaration.apply(&self.channel()).await?;
+        if !options.passive {
+            self.topology.lock().record_exchange(declaration);
+        }
+        Ok(())
+    }
+
+    pub async fn exchange_delete(
+        &self,
+        exchange: &str,
+        options: ExchangeDeleteOptions,
+    ) -> Result<()> {
+        self.channel().exchange_delete(exchange, options).await?;
+        self.topology.lock().forget_exchange(exchange, true);
+        Ok(())
+    }
+
+    pub async fn queue_declare(
+        &self,
+        queue: &str,
+        options: QueueDeclareOptions,
+        arguments: FieldTable,
+    ) -> Result<Queue> {
+        let declaration = QueueDeclaration::new(queue)
+            .with_options(options)
+            .with_arguments(arguments);
+        let declared = declaration.apply(&self.channel()).await?;
+        if !options.passive {
+            self.topology.lock().record_queue(declaration);
+        }
+        Ok(declared)
+    }
+
+    pub async fn queue_delete(&self, queue: &str, options: QueueDeleteOptions) -> Result<u32> {
+        let message_count = self.channel().queue_delete(queue, options).await?;
+        self.topology.lock().forget_queue(queue, true);
+        Ok(message_count)
+    }
+
+    pub async fn queue_bind(
+        &self,
+        queue: &str,
+        exchange: &str,
+        routing_key: &str,
+        options: QueueBindOptions,
+        arguments: FieldTable,
+    ) -> Result<()> {
+        let mut binding =
+            BindingDeclaration::queue(queue, exchange, routing_key).with_arguments(arguments);
+        binding.nowait = options.nowait;
+        binding.apply(&self.channel()).await?;
+        self.topology.lock().record_binding(binding);
+        Ok(())
+    }
+
+    pub async fn queue_unbind(
+        &self,
+        queue: &str,
+        exchange: &str,
+        routing_key: &str,
+        arguments: FieldTable,
+    ) -> Result<()> {
+        let binding = BindingDeclaration::queue(queue, exchange, routing_key)
+            .with_arguments(arguments.clone());
+        self.channel()
+            .queue_unbind(queue, exchange, routing_key, arguments)
+            .await?;
+        self.topology.lock().forget_binding(&binding);
+        Ok(())
+    }
+
+    pub async fn exchange_bind(
+        &self,
+        destination: &str,
+        source: &str,
+        routing_key: &str,
+        options: ExchangeBindOptions,
+        arguments: FieldTable,
+    ) -> Result<()> {
+        let mut binding = BindingDeclaration::exchange(destination, source, routing_key)
+            .with_arguments(arguments);
+        binding.nowait = options.nowait;
+        binding.apply(&self.channel()).await?;
+        self.topology.lock().record_binding(binding);
+        Ok(())
+    }
+
+    pub async fn exchange_unbind(
+        &self,
+        destination: &str,
+        source: &str,
+        routing_key: &str,
+        options: ExchangeUnbindOptions,
+        arguments: FieldTable,
+    ) -> Result<()> {
+        let binding = BindingDeclaration::exchange(destination, source, routing_key)
+            .with_arguments(arguments.clone());
+        self.channel()
+            .exchange_unbind(destination, source, routing_key, options, arguments)
+            .await?;
+        self.topology.lock().forget_binding(&binding);
+        Ok(())
+    }
+}
+
+impl fmt::Debug for TopologyChannel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("TopologyChannel");
+        if let Some(channel) = self.channel.try_lock() {
+            debug.field("channel", &channel.id());
+        }
+        if let Some(topology) = self.topology.try_lock() {
+            debug.field("topology", &*topology);
+        }
+        debug.finish()
+    }
+}
+
+/// The resources a [`Topology`] declaration created so far, used to delete them again if one of
+/// the next declarations fails.
+///
+/// Exchanges and queues are passively declared first to know whether they already existed, and
+/// those are never deleted. Bindings can't be looked up, so only the ones involving a resource
+/// created by the transaction are undone.
+///
+/// [`Topology`]: ./struct.Topology.html
+#[derive(Clone, Debug, Default)]
+pub(crate) struct TopologyTransaction {
+    exchanges: Vec<String>,
+    queues: Vec<String>,
+    bindings: Vec<BindingDeclaration>,
+}
+
+impl TopologyTransaction {
+    pub(crate) async fn apply(&mut self, topology: &Topology, channel: &Channel) -> Result<()> {
+        let mut probe = Probe::default();
+        let res = self.do_apply(topology, channel, &mut probe).await;
+        probe.close().await;
+        res
+    }
+
+    async fn do_apply(
+        &mut self,
+        topology: &Topology,
+        channel: &Channel,
+        probe: &mut Probe,
+    ) -> Result<()> {
+        for exchange in &topology.exchanges {
+            // The default exchange always exists
+            let created = !exchange.options.passive
+                && !exchange.name.is_empty()
+                && probe.exchange(channel, exchange).await? == Presence::Missing;
+            exchange.apply(channel).await?;
+            if created {
+                self.exchanges.push(exchange.name.clone());
+            }
+        }
+        for queue in &topology.queues {
+            // Server-named queues are always new
+            let created = !queue.options.passive
+                && (queue.name.is_empty()
+                    || probe.queue(channel, &queue.name).await? == Presence::Missing);
+            let declared = queue.apply(channel).await?;
+            if created {
+                self.queues.push(declared.name().to_string());
+            }
+        }
+        for binding in &topology.bindings {
+            binding.apply(channel).await?;
+            if self.created(binding) {
+                self.bindings.push(binding.clone());
+            }
+        }
+        Ok(())
+    }
+
+    fn created(&self, binding: &BindingDeclaration) -> bool {
+        let destinations = match binding.destination_kind {
+            DestinationKind::Queue => &self.queues,
+            DestinationKind::Exchange => &self.exchanges,
+        };
+        self.exchanges.contains(&binding.source) || destinations.contains(&binding.destination)
+    }
+
+    /// Undo everything in reverse order, carrying on after failures and returning the first one.
+    pub(crate) async fn rollback(self, channel: &Channel) -> Result<()> {
+        let mut res = Ok(());
+        for binding in self.bindings.iter().rev() {
+            let step = match binding.destination_kind {
+                DestinationKind::Queue => {
+                    channel
+                        .queue_unbind(
+                            &binding.destination,
+                            &binding.source,
+                            &binding.routing_key,
+                            binding.arguments.clone(),
+                        )
+                        .await
+                }
+                DestinationKind::Exchange => {
+                    channel
+                        .exchange_unbind(
+                            &binding.destination,
+                            &binding.source,
+                            &binding.routing_key,
+                            ExchangeUnbindOptions::default(),
+                            binding.arguments.clone(),
+                        )
+                        .await
+                }
+            };
+            Self::record(&mut res, step);
+        }
+        for queue in self.queues.iter().rev() {
+            let step = channel
+                .queue_delete(queue, QueueDeleteOptions::default())
+                .await
+                .map(|_| ());
+            Self::record(&mut res, step);
+        }
+        for exchange in self.exchanges.iter().rev() {
+            let step = channel
+                .exchange_delete(exchange, ExchangeDeleteOptions::default())
+                .await;
+            Self::record(&mut res, step);
+        }
+        res
+    }
+
+    fn record(res: &mut Result<()>, step: Result<()>) {
+        if let Err(err) = step {
+            error!(target: targets::CHANNEL, "Topology rollback step failed: {}", err);
+            if res.is_ok() {
+                *res = Err(err);
+            }
+        }
+    }
+}
+
+/// The outcome of [`Channel::verify_topology`].
+///
+/// Passive declarations only tell whether an exchange or a queue exists: their kind, options
+/// and arguments aren't compared, and bindings can't be checked at all.
+///
+/// [`Channel::verify_topology`]: ../struct.Channel.html#method.verify_topology
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct VerificationReport {
+    /// The exchanges which don't exist
+    pub missing_exchanges: Vec<String>,
+    /// The queues which don't exist
+    pub missing_queues: Vec<String>,
+    /// The exchanges we aren't allowed to access
+    pub inaccessible_exchanges: Vec<String>,
+    /// The queues we aren't allowed to access, including exclusive queues of other connections
+    pub inaccessible_queues: Vec<String>,
+}
+
+impl VerificationReport {
+    /// Whether all the exchanges and queues exist and are accessible.
+    pub fn is_ok(&self) -> bool {
+        self.missing_exchanges.is_empty()
+            && self.missing_queues.is_empty()
+            && self.inaccessible_exchanges.is_empty()
+            && self.inaccessible_queues.is_empty()
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Presence {
+    Present,
+    Missing,
+    Inaccessible,
+}
+
+impl Presence {
+    fn from_passive_declaration(res: Result<()>) -> Result<Self> {
+        match res {
+            Ok(()) => Ok(Presence::Present),
+            Err(Error::ProtocolError(error)) => match error.kind() {
+                AMQPErrorKind::Soft(AMQPSoftError::NOTFOUND) => Ok(Presence::Missing),
+                AMQPErrorKind::Soft(AMQPSoftError::ACCESSREFUSED)
+                | AMQPErrorKind::Soft(AMQPSoftError::RESOURCELOCKED) => Ok(Presence::Inaccessible),
+                _ => Err(Error::ProtocolError(error)),
+            },
+            Err(err) => Err(err),
+        }
+    }
+}
+
+impl Topology {
+    /// Passively declare the exchanges and queues on throwaway channels, as each failure closes
+    /// the channel it happened on.
+    pub(crate) async fn verify(&self, channel: &Channel) -> Result<VerificationReport> {
+        let mut report = VerificationReport::default();
+        let mut probe = Probe::default();
+        let res = self.do_verify(channel, &mut probe, &mut report).await;
+        probe.close().await;
+        res.map(|_| report)
+    }
+
+    async fn do_verify(
+        &self,
+        channel: &Channel,
+        probe: &mut Probe,
+        report: &mut VerificationReport,
+    ) -> Result<()> {
+        // The default exchange always exists and server-named queues can't be looked up
+        for exchange in self.exchanges.iter().filter(|e| !e.name.is_empty()) {
+            match probe.exchange(channel, exchange).await? {
+                Presence::Present => {}
+                Presence::Missing => report.missing_exchanges.push(exchange.name.clone()),
+                Presence::Inaccessible => report.inaccessible_exchanges.push(exchange.name.clone()),
+            }
+        }
+        for queue in self.queues.iter().filter(|q| !q.name.is_empty()) {
+            match probe.queue(channel, &queue.name).await? {
+                Presence::Present => {}
+                Presence::Missing => report.missing_queues.push(queue.name.clone()),
+                Presence::Inaccessible => report.inaccessible_queues.push(queue.name.clone()),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Passive declarations on a throwaway channel, as each failure closes the channel it happened
+/// on.
+#[derive(Default)]
+struct Probe {
+    channel: Option<Channel>,
+}
+
+impl Probe {
+    async fn exchange(
+        &mut self,
+        channel: &Channel,
+        exchange: &ExchangeDeclaration,
+    ) -> Result<Presence> {
+        let res = self
+            .channel(channel)
+            .await?
+            .exchange_declare(
+                &exchange.name,
+                exchange.kind.clone(),
+                ExchangeDeclareOptions::default().passive(true),
+                FieldTable::default(),
+            )
+            .await;
+        self.check(res)
+    }
+
+    async fn queue(&mut self, channel: &Channel, queue: &str) -> Result<Presence> {
+        let res = self
+            .channel(channel)
+            .await?
+            .queue_declare(
+                queue,
+                QueueDeclareOptions::default().passive(true),
+                FieldTable::default(),
+            )
+            .await
+            .map(|_| ());
+        self.check(res)
+    }
+
+    async fn channel(&mut self, channel: &Channel) -> Result<&Channel> {
+        if self.channel.is_none() {
+            self.channel = Some(channel.create_internal_channel().await?);
+        }
+        Ok(self.channel.as_ref().expect("probe channel"))
+    }
+
+    fn check(&mut self, res: Result<()>) -> Result<Presence> {
+        if self
+            .channel
+            .as_ref()
+            .map_or(false, |probe| !probe.status().connected())
+        {
+            // The failure closed the channel, the next check needs a new one
+            self.channel = None;
+        }
+        Presence::from_passive_declaration(res)
+    }
+
+    async fn close(self) {
+        if let Some(probe) = self.channel {
+            let _ = probe
+                .close(protocol::constants::REPLY_SUCCESS as ShortUInt, "OK")
+                .await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::AMQPError;
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn from_json() {
+        let topology = Topology::from_json(
+            r##"{
+                "exchanges": [
+                    { "name": "events", "kind": "topic", "options": { "durable": true } },
+                    { "name": "delayed", "kind": "x-delayed-message" }
+                ],
+                "queues": [
+                    { "name": "audit", "options": { "durable": true } }
+                ],
+                "bindings": [
+                    { "source": "events", "destination": "audit", "routing_key": "#" },
+                    { "source": "delayed", "destination": "events", "destination_kind": "exchange" }
+                ]
+            }"##,
+        )
+        .unwrap();
+        let expected = Topology::new()
+            .exchange(
+                ExchangeDeclaration::new("events", ExchangeKind::Topic)
+                    .with_options(ExchangeDeclareOptions::default().durable(true)),
+            )
+            .exchange(ExchangeDeclaration::new(
+                "delayed",
+                ExchangeKind::Custom("x-delayed-message".into()),
+            ))
+            .queue(
+                QueueDeclaration::new("audit")
+                    .with_options(QueueDeclareOptions::default().durable(true)),
+            )
+            .binding(BindingDeclaration::queue("audit", "events", "#"))
+            .binding(BindingDeclaration::exchange("events", "delayed", ""));
+        assert_eq!(topology, expected);
+    }
+
+    #[test]
+    fn tracking() {
+        let mut topology = Topology::new();
+        topology.record_exchange(ExchangeDeclaration::new("events", ExchangeKind::Topic));
+        topology.record_exchange(ExchangeDeclaration::new("events", ExchangeKind::Fanout));
+        topology.record_queue(QueueDeclaration::new("audit"));
+        topology.record_queue(QueueDeclaration::new("orders"));
+        topology.record_binding(BindingDeclaration::queue("audit", "events", "#"));
+        topology.record_binding(BindingDeclaration::queue("audit", "events", "#"));
+        topology.record_binding(BindingDeclaration::queue("orders", "events", "order.*"));
+        topology.record_binding(BindingDeclaration::exchange("events", "amq.topic", "#"));
+        assert_eq!(
+            topology.exchanges(),
+            &[ExchangeDeclaration::new("events", ExchangeKind::Fanout)]
+        );
+        assert_eq!(topology.bindings().len(), 3);
+
+        topology.forget_queue("audit", true);
+        assert_eq!(topology.queues(), &[QueueDeclaration::new("orders")]);
+        assert_eq!(topology.bindings().len(), 2);
+
+        topology.forget_binding(&BindingDeclaration::queue("orders", "events", "order.*"));
+        assert_eq!(
+            topology.bindings(),
+            &[BindingDeclaration::exchange("events", "amq.topic", "#")]
+        );
+
+        topology.forget_exchange("events", true);
+        assert!(topology.exchanges().is_empty());
+        assert!(topology.bindings().is_empty());
+    }
+
+    #[test]
+    fn passive_declaration_outcomes() {
+        let error = |kind: AMQPSoftError| {
+            Err(Error::ProtocolError(AMQPError::new(
+                kind.into(),
+                "passive".into(),
+            )))
+        };
+        assert_eq!(
+            Presence::from_passive_declaration(Ok(())),
+            Ok(Presence::Present)
+        );
+        assert_eq!(
+            Presence::from_passive_declaration(error(AMQPSoftError::NOTFOUND)),
+            Ok(Presence::Missing)
+        );
+        assert_eq!(
+            Presence::from_passive_declaration(error(AMQPSoftError::ACCESSREFUSED)),
+            Ok(Presence::Inaccessible)
+        );
+        assert!(
+            Presence::from_passive_declaration(error(AMQPSoftError::PRECONDITIONFAILED)).is_err()
+        );
+        assert_eq!(
+            Presence::from_passive_declaration(Err(Error::Timeout)),
+            Err(Error::Timeout)
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn invalid_json() {
+        assert!(Topology::from_json(r#"{ "queues": 42 }"#).is_err());
+    }
+}
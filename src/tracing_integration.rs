@@ -0,0 +1,204 @@
+//! Tracing spans around the [`Channel`] methods.
+//!
+//! Every AMQP method of [`Channel`] runs inside a span named after it, such as
+//! `channel.basic_publish` or `channel.queue_declare`. The spans have the channel id, a
+//! `request_id` unique to each call and, for the main methods, their main arguments as fields,
+//! along with a `result` field recorded once the call completed: either `ok` or the error.
+//!
+//! The spans are at the `DEBUG` level unless changed with
+//! [`ConnectionProperties::with_instrumentation_level`].
+//!
+//! The events are logged under one target per subsystem, listed in the [`targets`] module, so
+//! that each of them can be enabled on its own. For example, to only see what the consumers do:
+//!
+//! ```rust
+//! use tracing_subscriber::EnvFilter;
+//!
+//! let filter = EnvFilter::new("lapin=warn")
+//!     .add_directive("lapin::consumer=trace".parse().unwrap());
+//! tracing_subscriber::fmt().with_env_filter(filter).init();
+//! ```
+//!
+//! [`Channel`]: ../struct.Channel.html
+//! [`ConnectionProperties::with_instrumentation_level`]: ../struct.ConnectionProperties.html#method.with_instrumentation_level
+//! [`targets`]: ./targets/index.html
+
+use crate::Result;
+use std::{
+    future::Future,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use tracing::{field, Instrument, Span};
+
+/// The tracing targets of the events lapin logs.
+pub mod targets {
+    /// Reading from and writing to the socket, including a trace of each frame.
+    pub const IO: &str = "lapin::io";
+    /// The frames queued for sending and the replies they wait for.
+    pub const FRAMES: &str = "lapin::frames";
+    /// The channels, their methods and the contents they receive.
+    pub const CHANNEL: &str = "lapin::channel";
+    /// The consumers and their deliveries.
+    pub const CONSUMER: &str = "lapin::consumer";
+    /// The heartbeats sent and received.
+    pub const HEARTBEAT: &str = "lapin::heartbeat";
+    /// Establishing, recovering and closing the connection.
+    pub const CONNECTION: &str = "lapin::connection";
+}
+
+static REQUEST_ID: AtomicU64 = AtomicU64::new(0);
+
+pub(crate) fn next_request_id() -> u64 {
+    REQUEST_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Run `future` inside `span`, recording its result in the `result` field.
+pub(crate) async fn instrument<T, F: Future<Output = Result<T>>>(
+    span: Span,
+    future: F,
+) -> Result<T> {
+    let res = future.instrument(span.clone()).await;
+    match &res {
+        Ok(_) => span.record("result", &"ok"),
+        Err(err) => span.record("result", &field::display(err)),
+    };
+    res
+}
+
+/// Create the span of a channel method at the configured instrumentation level, with optional
+/// extra fields.
+///
+/// The span levels have to be known at compile time, hence one branch per level.
+macro_rules! channel_span {
+    (@fields $channel:expr, $name:literal, $($fields:tt)*) => {{
+        let channel_id = $channel.id();
+        let request_id = $crate::tracing_integration::next_request_id();
+        let level = $channel.configuration.instrumentation_level();
+        if level == tracing::Level::ERROR {
+            tracing::error_span!($name, channel_id, request_id, $($fields)*)
+        } else if level == tracing::Level::WARN {
+            tracing::warn_span!($name, channel_id, request_id, $($fields)*)
+        } else if level == tracing::Level::INFO {
+            tracing::info_span!($name, channel_id, request_id, $($fields)*)
+        } else if level == tracing::Level::DEBUG {
+            tracing::debug_span!($name, channel_id, request_id, $($fields)*)
+        } else {
+            tracing::trace_span!($name, channel_id, request_id, $($fields)*)
+        }
+    }};
+    ($channel:expr, $name:literal) => {
+        $crate::tracing_integration::channel_span!(@fields $channel, $name, result = tracing::field::Empty)
+    };
+    ($channel:expr, $name:literal, $($fields:tt)*) => {
+        $crate::tracing_integration::channel_span!(@fields $channel, $name, $($fields)*, result = tracing::field::Empty)
+    };
+}
+
+pub(crate) use channel_span;
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::test_helpers::*;
+    use crate::{consumer::Consumer, executor::DefaultExecutor, socket_state::SocketState};
+    use parking_lot::Mutex;
+    use std::{io, sync::Arc};
+    use tracing_subscriber::fmt::MakeWriter;
+
+    /// A writer keeping what the `fmt` subscriber logs, to check it in the tests.
+    #[derive(Clone, Default)]
+    pub(crate) struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl Captured {
+        pub(crate) fn output(&self) -> String {
+            String::from_utf8(self.0.lock().clone()).unwrap()
+        }
+    }
+
+    impl io::Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl MakeWriter for Captured {
+        type Writer = Self;
+
+        fn make_writer(&self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    #[test]
+    fn targets() {
+        let captured = Captured::default();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::TRACE)
+            .with_ansi(false)
+            .with_writer(captured.clone())
+            .finish();
+
+        tracing::subscriber::with_default(subscriber, || {
+            let mut socket_state = SocketState::default();
+            socket_state.handle().wake();
+            socket_state.poll_events();
+
+            Consumer::new("traced".into(), DefaultExecutor::default().unwrap()).cancel();
+        });
+
+        let output = captured.output();
+        assert!(
+            output.contains("lapin::io: Got event for socket: Wake"),
+            "{}",
+            output
+        );
+        assert!(
+            output.contains("lapin::consumer: cancel; consumer_tag=traced"),
+            "{}",
+            output
+        );
+    }
+
+    #[test]
+    fn generated_methods_spans() {
+        use crate::options::BasicAckOptions;
+        use tracing_subscriber::fmt::format::FmtSpan;
+
+        let (_conn, channel, frames) = connected_channel();
+        let writer = {
+            let frames = frames.clone();
+            std::thread::spawn(move || write_frames(&frames, 1))
+        };
+        let captured = Captured::default();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::DEBUG)
+            .with_span_events(FmtSpan::CLOSE)
+            .with_ansi(false)
+            .with_writer(captured.clone())
+            .finish();
+        let acked = tracing::subscriber::with_default(subscriber, || {
+            async_global_executor::block_on(channel.basic_ack(1, BasicAckOptions::default()))
+        });
+        writer.join().unwrap();
+        assert!(acked.is_ok());
+        let output = captured.output();
+        assert!(
+            output.contains(&format!(
+                "channel.basic_ack{{channel_id={} request_id=",
+                channel.id()
+            )),
+            "{}",
+            output
+        );
+        assert!(
+            output.contains("result=\"ok\"}: lapin::channel: close"),
+            "{}",
+            output
+        );
+    }
+}
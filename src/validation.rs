@@ -0,0 +1,239 @@
+//! Checks of the names and field tables we send against the limits of the AMQP specification.
+//!
+//! A short string longer than 255 bytes can't be serialised at all, which would fail the whole
+//! connection, and the server closes the channel when a field table key is malformed: catching
+//! those before sending lets us fail the method alone, with an error saying what is wrong.
+
+use crate::{
+    protocol::{basic, exchange, queue, AMQPClass},
+    types::{AMQPValue, FieldTable},
+    BasicProperties, Error, Result,
+};
+
+/// The maximum length of a short string, in bytes.
+const SHORT_STRING_MAX_BYTES: usize = 255;
+
+/// The maximum length of a field table key, in characters.
+const FIELD_NAME_MAX_CHARS: usize = 128;
+
+/// Check the short strings and the field tables of a method we're about to send.
+pub(crate) fn check_method(method: &AMQPClass) -> Result<()> {
+    match method {
+        AMQPClass::Exchange(exchange::AMQPMethod::Declare(method)) => {
+            short_string("exchange name", method.exchange.as_str())?;
+            short_string("exchange type", method.kind.as_str())?;
+            field_table("argument", &method.arguments)
+        }
+        AMQPClass::Exchange(exchange::AMQPMethod::Delete(method)) => {
+            short_string("exchange name", method.exchange.as_str())
+        }
+        AMQPClass::Exchange(exchange::AMQPMethod::Bind(method)) => {
+            short_string("exchange name", method.destination.as_str())?;
+            short_string("exchange name", method.source.as_str())?;
+            short_string("routing key", method.routing_key.as_str())?;
+            field_table("argument", &method.arguments)
+        }
+        AMQPClass::Exchange(exchange::AMQPMethod::Unbind(method)) => {
+            short_string("exchange name", method.destination.as_str())?;
+            short_string("exchange name", method.source.as_str())?;
+            short_string("routing key", method.routing_key.as_str())?;
+            field_table("argument", &method.arguments)
+        }
+        AMQPClass::Queue(queue::AMQPMethod::Declare(method)) => {
+            short_string("queue name", method.queue.as_str())?;
+            field_table("argument", &method.arguments)
+        }
+        AMQPClass::Queue(queue::AMQPMethod::Bind(method)) => {
+            short_string("queue name", method.queue.as_str())?;
+            short_string("exchange name", method.exchange.as_str())?;
+            short_string("routing key", method.routing_key.as_str())?;
+            field_table("argument", &method.arguments)
+        }
+        AMQPClass::Queue(queue::AMQPMethod::Unbind(method)) => {
+            short_string("queue name", method.queue.as_str())?;
+            short_string("exchange name", method.exchange.as_str())?;
+            short_string("routing key", method.routing_key.as_str())?;
+            field_table("argument", &method.arguments)
+        }
+        AMQPClass::Queue(queue::AMQPMethod::Purge(method)) => {
+            short_string("queue name", method.queue.as_str())
+        }
+        AMQPClass::Queue(queue::AMQPMethod::Delete(method)) => {
+            short_string("queue name", method.queue.as_str())
+        }
+        AMQPClass::Basic(basic::AMQPMethod::Consume(method)) => {
+            short_string("queue name", method.queue.as_str())?;
+            short_string("consumer tag", method.consumer_tag.as_str())?;
+            field_table("argument", &method.arguments)
+        }
+        AMQPClass::Basic(basic::AMQPMethod::Cancel(method)) => {
+            short_string("consumer tag", method.consumer_tag.as_str())
+        }
+        AMQPClass::Basic(basic::AMQPMethod::Publish(method)) => {
+            short_string("exchange name", method.exchange.as_str())?;
+            short_string("routing key", method.routing_key.as_str())
+        }
+        AMQPClass::Basic(basic::AMQPMethod::Get(method)) => {
+            short_string("queue name", method.queue.as_str())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Check the headers of a message we're about to publish.
+pub(crate) fn check_properties(properties: &BasicProperties) -> Result<()> {
+    match properties.headers() {
+        Some(headers) => field_table("header", headers),
+        None => Ok(()),
+    }
+}
+
+fn short_string(field: &'static str, value: &str) -> Result<()> {
+    if value.len() > SHORT_STRING_MAX_BYTES {
+        return Err(invalid(field, value, "longer than 255 bytes"));
+    }
+    Ok(())
+}
+
+fn field_table(field: &'static str, table: &FieldTable) -> Result<()> {
+    for (key, value) in table.inner() {
+        field_name(field, key.as_str())?;
+        field_value(field, value)?;
+    }
+    Ok(())
+}
+
+fn field_name(field: &'static str, name: &str) -> Result<()> {
+    short_string(field, name)?;
+    if name.chars().count() > FIELD_NAME_MAX_CHARS {
+        return Err(invalid(field, name, "longer than 128 characters"));
+    }
+    match name.chars().next() {
+        Some(first) if first.is_ascii_alphabetic() || first == '$' || first == '#' => Ok(()),
+        _ => Err(invalid(
+            field,
+            name,
+            "doesn't start with a letter, '$' or '#'",
+        )),
+    }
+}
+
+fn field_value(field: &'static str, value: &AMQPValue) -> Result<()> {
+    match value {
+        AMQPValue::FieldTable(table) => field_table(field, table),
+        AMQPValue::FieldArray(array) => array
+            .as_slice()
+            .iter()
+            .try_for_each(|value| field_value(field, value)),
+        _ => Ok(()),
+    }
+}
+
+fn invalid(field: &'static str, value: &str, reason: &'static str) -> Error {
+    Error::InvalidName {
+        field,
+        value: value.into(),
+        reason,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{FieldArray, ShortString};
+
+    fn queue_declare(queue: &str, arguments: FieldTable) -> AMQPClass {
+        AMQPClass::Queue(queue::AMQPMethod::Declare(queue::Declare {
+            queue: queue.into(),
+            arguments,
+            ..Default::default()
+        }))
+    }
+
+    fn table(key: &str) -> FieldTable {
+        let mut table = FieldTable::default();
+        table.insert(ShortString::from(key), AMQPValue::Boolean(true));
+        table
+    }
+
+    #[test]
+    fn short_string_length() {
+        assert_eq!(
+            check_method(&queue_declare(&"q".repeat(255), FieldTable::default())),
+            Ok(())
+        );
+        assert_eq!(
+            check_method(&queue_declare(&"q".repeat(256), FieldTable::default())),
+            Err(invalid(
+                "queue name",
+                &"q".repeat(256),
+                "longer than 255 bytes"
+            ))
+        );
+    }
+
+    #[test]
+    fn short_string_length_in_bytes() {
+        // 128 characters, but 256 bytes
+        let name = "é".repeat(128);
+        assert_eq!(
+            check_method(&AMQPClass::Basic(basic::AMQPMethod::Cancel(
+                basic::Cancel {
+                    consumer_tag: name.as_str().into(),
+                    ..Default::default()
+                }
+            ))),
+            Err(invalid("consumer tag", &name, "longer than 255 bytes"))
+        );
+    }
+
+    #[test]
+    fn field_name_length() {
+        assert_eq!(
+            check_method(&queue_declare("queue", table(&"k".repeat(128)))),
+            Ok(())
+        );
+        assert_eq!(
+            check_method(&queue_declare("queue", table(&"k".repeat(129)))),
+            Err(invalid(
+                "argument",
+                &"k".repeat(129),
+                "longer than 128 characters"
+            ))
+        );
+    }
+
+    #[test]
+    fn field_name_first_character() {
+        for name in &["x-max-priority", "$key", "#key"] {
+            assert_eq!(check_method(&queue_declare("queue", table(name))), Ok(()));
+        }
+        for name in &["", "1key", "_key", "-key"] {
+            assert_eq!(
+                check_method(&queue_declare("queue", table(name))),
+                Err(invalid(
+                    "argument",
+                    name,
+                    "doesn't start with a letter, '$' or '#'"
+                ))
+            );
+        }
+    }
+
+    #[test]
+    fn nested_field_names() {
+        let mut headers = FieldTable::default();
+        headers.insert(
+            "nested".into(),
+            AMQPValue::FieldArray(FieldArray::from(vec![AMQPValue::FieldTable(table("_key"))])),
+        );
+        assert_eq!(
+            check_properties(&BasicProperties::default().with_headers(headers)),
+            Err(invalid(
+                "header",
+                "_key",
+                "doesn't start with a letter, '$' or '#'"
+            ))
+        );
+    }
+}
@@ -1,5 +1,39 @@
 pub mod options {
+  //! The options of the AMQP methods.
+  //!
+  //! New flags may get added to the options, so they're built from their default value with
+  //! one method per flag:
+  //!
+  //! ```
+  //! use lapin::options::QueueDeclareOptions;
+  //!
+  //! let options = QueueDeclareOptions::default().durable(true).auto_delete(false);
+  //! assert!(options.durable);
+  //! ```
+  //!
+  //! The flags can also be set on a mutable value:
+  //!
+  //! ```
+  //! use lapin::options::QueueDeclareOptions;
+  //!
+  //! let mut options = QueueDeclareOptions::default();
+  //! options.durable = true;
+  //! ```
+  //!
+  //! Struct expressions, including the struct update syntax, aren't available outside of lapin:
+  //!
+  //! ```compile_fail
+  //! use lapin::options::QueueDeclareOptions;
+  //!
+  //! let options = QueueDeclareOptions {
+  //!     durable: true,
+  //!     ..QueueDeclareOptions::default()
+  //! };
+  //! ```
+
   use super::*;
+  #[cfg(feature = "serde")]
+  use serde::{Deserialize, Serialize};
 
   {{#each protocol.classes as |class| ~}}
   {{#each class.methods as |method| ~}}
@@ -8,12 +42,24 @@ pub mod options {
   {{#unless @argument_is_value ~}}
   {{#unless argument.ignore_flags ~}}
   #[derive(Copy, Clone, Debug, Default, PartialEq)]
+  #[cfg_attr(feature = "serde", derive(Deserialize, Serialize), serde(default))]
+  #[non_exhaustive]
   pub struct {{camel class.name}}{{camel method.name}}Options {
     {{#each argument.flags as |flag| ~}}
     pub {{snake flag.name}}: Boolean,
     {{/each ~}}
   }
 
+  impl {{camel class.name}}{{camel method.name}}Options {
+    {{#each argument.flags as |flag| ~}}
+    /// Set the {{snake flag.name}} flag
+    pub fn {{snake flag.name}}(mut self, {{snake flag.name}}: Boolean) -> Self {
+      self.{{snake flag.name}} = {{snake flag.name}};
+      self
+    }
+    {{/each ~}}
+  }
+
   {{/unless ~}}
   {{/unless ~}}
   {{/each_argument ~}}
@@ -51,7 +97,7 @@ impl Channel {
       {{/each ~}}
       {{/each ~}}
       m => {
-        error!("the client should not receive this method: {:?}", m);
+        error!(target: targets::CHANNEL, "the client should not receive this method: {:?}", m);
         self.handle_invalid_contents(format!("unexepcted method received on channel {}", self.id), m.get_amqp_class_id(), m.get_amqp_method_id())
       }
     }
@@ -63,6 +109,10 @@ impl Channel {
   {{#if method.c2s ~}}
 #[allow(clippy::too_many_arguments)]
 {{include_more class.name method.name}}{{#unless method.metadata.require_wrapper ~}}{{#if method.is_reply ~}}{{#if method.metadata.internal ~}}pub(crate) {{/if ~}}{{else}}pub {{#if method.metadata.internal ~}}(crate) {{/if ~}}{{/if ~}}async fn {{else}}async fn do_{{/unless ~}}{{snake class.name false}}_{{snake method.name false}}(&self{{#unless method.ignore_args ~}}{{#each_argument method.arguments as |argument| ~}}{{#if @argument_is_value ~}}{{#unless argument.force_default ~}}, {{snake argument.name}}: {{#if (use_str_ref argument.type) ~}}&str{{else}}{{argument.type}}{{/if ~}}{{/unless ~}}{{else}}{{#unless argument.ignore_flags ~}}, options: {{camel class.name}}{{camel method.name}}Options{{/unless ~}}{{/if ~}}{{/each_argument ~}}{{/unless ~}}{{#each method.metadata.extra_args as |arg| ~}}, {{arg.name}}: {{arg.type}}{{/each ~}}) -> Result<{{#if method.metadata.confirmation.type ~}}{{method.metadata.confirmation.type}}{{else}}(){{/if ~}}> {
+    {{#unless method.metadata.require_wrapper ~}}
+    let span = channel_span!(self, "channel.{{snake class.name false}}_{{snake method.name false}}");
+    instrument(span, async move {
+    {{/unless ~}}
     {{#if method.metadata.channel_init ~}}
     if !self.status.initializing() {
     {{else}}
@@ -72,7 +122,7 @@ impl Channel {
     if !self.status.connected() {
     {{/if ~}}
     {{/if ~}}
-      return Err(Error::InvalidChannelState(self.status.state()));
+      return Err(self.status.state_error());
     }
 
     {{#if method.metadata.start_hook ~}}
@@ -110,9 +160,10 @@ impl Channel {
       {{/if ~}}
       {{/each_argument ~}}
     }));
+    self.check_fields(&method)?;
 
     {{#if method.metadata.carry_headers ~}}
-    self.send_method_frame_with_body(method, payload, properties, start_hook_res).await
+    self.send_method_frame_with_body(method, payload, properties).await
     {{else}}
     let (promise, send_resolver) = Promise::new();
     if level_enabled!(Level::TRACE) {
@@ -141,6 +192,9 @@ impl Channel {
     {{/if ~}}
     promise.await
     {{/if ~}}
+    {{#unless method.metadata.require_wrapper ~}}
+    }).await
+    {{/unless ~}}
   }
   {{/if ~}}
 
@@ -0,0 +1,244 @@
+//! Count the allocations the client makes for a declare, bind, consume and 1000 publishes
+//! sequence, played against the in-process mock broker.
+
+use amq_protocol::frame::{AMQPContentHeader, AMQPFrame};
+use futures_lite::StreamExt;
+use lapin::{
+    options::*,
+    protocol::{basic, queue, AMQPClass},
+    types::{FieldTable, ShortString},
+    BasicProperties, Channel, Connection, ConnectionProperties,
+};
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+    collections::HashMap,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+mod common;
+
+use common::MockBroker;
+
+const MESSAGES: usize = 1000;
+const BODY_FRAME_SIZE: usize = 128;
+const BODY_FRAMES: usize = 8;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    // Set on the threads of the mock broker, which aren't part of the client
+    static IGNORED: Cell<bool> = Cell::new(false);
+}
+
+struct CountingAllocator;
+
+impl CountingAllocator {
+    fn count(&self) {
+        if !IGNORED.try_with(Cell::get).unwrap_or(true) {
+            ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.count();
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        self.count();
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Routes the publishes to the consumer of the queue named after their routing key, splitting
+/// the body of the deliveries in frames of `BODY_FRAME_SIZE` bytes.
+#[derive(Default)]
+struct Router {
+    consumers: HashMap<String, ShortString>,
+    delivery_tag: u64,
+    publish: Option<basic::Publish>,
+    body_size: u64,
+    body: Vec<u8>,
+}
+
+impl Router {
+    fn handle(&mut self, broker: &mut MockBroker, frame: AMQPFrame) {
+        match frame {
+            AMQPFrame::Method(id, AMQPClass::Queue(queue::AMQPMethod::Declare(declare))) => broker
+                .send_method(
+                    id,
+                    AMQPClass::Queue(queue::AMQPMethod::DeclareOk(queue::DeclareOk {
+                        queue: declare.queue,
+                        message_count: 0,
+                        consumer_count: 0,
+                    })),
+                ),
+            AMQPFrame::Method(id, AMQPClass::Queue(queue::AMQPMethod::Bind(_))) => broker
+                .send_method(
+                    id,
+                    AMQPClass::Queue(queue::AMQPMethod::BindOk(queue::BindOk {})),
+                ),
+            AMQPFrame::Method(id, AMQPClass::Basic(basic::AMQPMethod::Consume(consume))) => {
+                broker.send_method(
+                    id,
+                    AMQPClass::Basic(basic::AMQPMethod::ConsumeOk(basic::ConsumeOk {
+                        consumer_tag: consume.consumer_tag.clone(),
+                    })),
+                );
+                self.consumers
+                    .insert(consume.queue.as_str().to_string(), consume.consumer_tag);
+            }
+            AMQPFrame::Method(_, AMQPClass::Basic(basic::AMQPMethod::Publish(publish))) => {
+                self.publish = Some(publish);
+            }
+            AMQPFrame::Header(_, _, header) => {
+                self.body_size = header.body_size;
+                self.body.clear();
+            }
+            AMQPFrame::Body(id, data) => {
+                self.body.extend(data);
+                if self.body.len() as u64 == self.body_size {
+                    self.route(broker, id);
+                }
+            }
+            frame => common::unexpected(broker, frame),
+        }
+    }
+
+    fn route(&mut self, broker: &mut MockBroker, channel_id: u16) {
+        let publish = self.publish.take().expect("content without basic.publish");
+        let consumer_tag = self
+            .consumers
+            .get(publish.routing_key.as_str())
+            .expect("no consumer for the routing key")
+            .clone();
+        self.delivery_tag += 1;
+        broker.send_method(
+            channel_id,
+            AMQPClass::Basic(basic::AMQPMethod::Deliver(basic::Deliver {
+                consumer_tag,
+                delivery_tag: self.delivery_tag,
+                redelivered: false,
+                exchange: publish.exchange,
+                routing_key: publish.routing_key,
+            })),
+        );
+        broker.send(AMQPFrame::Header(
+            channel_id,
+            60,
+            Box::new(AMQPContentHeader {
+                class_id: 60,
+                weight: 0,
+                body_size: self.body_size,
+                properties: BasicProperties::default(),
+            }),
+        ));
+        for chunk in self.body.chunks(BODY_FRAME_SIZE) {
+            broker.send(AMQPFrame::Body(channel_id, chunk.to_vec()));
+        }
+    }
+}
+
+/// Declare `queue`, bind it to amq.direct, consume it and publish `MESSAGES` messages of
+/// `payload_size` bytes to it, returning how many allocations it took to get them all back.
+async fn round(channel: &Channel, queue: &str, payload_size: usize) -> usize {
+    let start = ALLOCATIONS.load(Ordering::SeqCst);
+    channel
+        .queue_declare(queue, QueueDeclareOptions::default(), FieldTable::default())
+        .await
+        .expect("queue_declare");
+    channel
+        .queue_bind(
+            queue,
+            "amq.direct",
+            queue,
+            QueueBindOptions::default(),
+            FieldTable::default(),
+        )
+        .await
+        .expect("queue_bind");
+    let mut consumer = channel
+        .basic_consume(
+            queue,
+            queue,
+            BasicConsumeOptions::default().no_ack(true),
+            FieldTable::default(),
+        )
+        .await
+        .expect("basic_consume");
+    for _ in 0..MESSAGES {
+        channel
+            .basic_publish(
+                "amq.direct",
+                queue,
+                BasicPublishOptions::default(),
+                vec![0; payload_size],
+                BasicProperties::default(),
+            )
+            .await
+            .expect("basic_publish");
+    }
+    for _ in 0..MESSAGES {
+        let (_, delivery) = consumer
+            .next()
+            .await
+            .expect("consumer ended")
+            .expect("delivery error");
+        assert_eq!(delivery.data().len(), payload_size);
+    }
+    ALLOCATIONS.load(Ordering::SeqCst) - start
+}
+
+#[test]
+fn allocations() {
+    let addr = MockBroker::start(|mut broker| {
+        IGNORED.with(|ignored| ignored.set(true));
+        broker.handshake("en_US", 0);
+        let mut router = Router::default();
+        broker.serve(|broker, frame| router.handle(broker, frame));
+    });
+
+    async_global_executor::block_on(async {
+        let conn = Connection::connect_to_addr(
+            addr,
+            "amqp://127.0.0.1:5672/%2f",
+            ConnectionProperties::default(),
+        )
+        .await
+        .expect("connection error");
+        let channel = conn.create_channel().await.expect("create_channel");
+
+        // Let the buffers of the connection grow to their final size first
+        round(&channel, "warm-up", BODY_FRAME_SIZE * BODY_FRAMES).await;
+        let single_frame = round(&channel, "single-frame", BODY_FRAME_SIZE).await;
+        let split = round(&channel, "split", BODY_FRAME_SIZE * BODY_FRAMES).await;
+
+        // A loose bound, only meant to catch the regressions which add allocations to every
+        // publish or delivery
+        assert!(
+            single_frame < MESSAGES * 64,
+            "{} allocations for {} messages",
+            single_frame,
+            MESSAGES
+        );
+        // Each extra body frame costs its own buffer and sometimes the growth of the delivery's,
+        // but no longer a copy of the consumer tag
+        let per_extra_frame =
+            split.saturating_sub(single_frame) as f64 / (MESSAGES * (BODY_FRAMES - 1)) as f64;
+        assert!(
+            per_extra_frame < 2.0,
+            "{} allocations per extra body frame",
+            per_extra_frame
+        );
+    });
+}
@@ -0,0 +1,32 @@
+use lapin::{CancellationToken, Connection, ConnectionProperties, Error};
+use std::{io::Read, net::TcpListener, thread, time::Duration};
+
+#[test]
+fn cancel_connect() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    // A broker accepting the connection but never answering the protocol header
+    let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+    let addr = listener.local_addr().expect("local_addr");
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().expect("accept");
+        let _ = stream.read_to_end(&mut Vec::new());
+    });
+
+    let token = CancellationToken::new();
+    let canceller = token.clone();
+    thread::spawn(move || {
+        thread::sleep(Duration::from_millis(100));
+        canceller.cancel();
+    });
+
+    async_global_executor::block_on(async {
+        let res = Connection::connect_to_addr(
+            addr,
+            "amqp://127.0.0.1:5672/%2f",
+            ConnectionProperties::default().with_cancellation_token(token),
+        )
+        .await;
+        assert_eq!(res.err(), Some(Error::Cancelled));
+    });
+}
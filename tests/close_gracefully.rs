@@ -0,0 +1,90 @@
+use lapin::{
+    message::DeliveryResult, options::*, types::FieldTable, BasicProperties, Connection,
+    ConnectionProperties,
+};
+use std::time::Duration;
+
+#[test]
+fn close_gracefully() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let addr = std::env::var("AMQP_ADDR").unwrap_or_else(|_| "amqp://127.0.0.1:5672/%2f".into());
+
+    async_global_executor::block_on(async {
+        let conn = Connection::connect(&addr, ConnectionProperties::default())
+            .await
+            .expect("connection error");
+        let channel = conn.create_channel().await.expect("create_channel");
+        channel
+            .queue_declare(
+                "close-gracefully",
+                QueueDeclareOptions::default(),
+                FieldTable::default(),
+            )
+            .await
+            .expect("queue_declare");
+        channel
+            .queue_purge("close-gracefully", QueuePurgeOptions::default())
+            .await
+            .expect("queue_purge");
+        channel
+            .basic_publish(
+                "",
+                "close-gracefully",
+                BasicPublishOptions::default(),
+                b"slow".to_vec(),
+                BasicProperties::default(),
+            )
+            .await
+            .expect("basic_publish");
+
+        let (started, handling) = flume::bounded(1);
+        let consumer = channel
+            .basic_consume(
+                "close-gracefully",
+                "slow-consumer",
+                BasicConsumeOptions::default(),
+                FieldTable::default(),
+            )
+            .await
+            .expect("basic_consume");
+        consumer.set_delegate(move |delivery: DeliveryResult| {
+            let started = started.clone();
+            async move {
+                if let Ok(Some((channel, delivery))) = delivery {
+                    let _ = started.send(());
+                    async_io::Timer::after(Duration::from_millis(200)).await;
+                    channel
+                        .basic_ack(delivery.delivery_tag, BasicAckOptions::default())
+                        .await
+                        .expect("basic_ack");
+                }
+            }
+        });
+        handling.recv_async().await.expect("delivery");
+
+        conn.close_gracefully(Duration::from_secs(5))
+            .await
+            .expect("close_gracefully");
+        assert!(!conn.status().connected());
+
+        // The acknowledgement made it to the server before the connection got closed
+        let conn = Connection::connect(&addr, ConnectionProperties::default())
+            .await
+            .expect("connection error");
+        let channel = conn.create_channel().await.expect("create_channel");
+        let queue = channel
+            .queue_declare(
+                "close-gracefully",
+                QueueDeclareOptions::default().passive(true),
+                FieldTable::default(),
+            )
+            .await
+            .expect("queue_declare");
+        assert_eq!(queue.message_count(), 0);
+        channel
+            .queue_delete("close-gracefully", QueueDeleteOptions::default())
+            .await
+            .expect("queue_delete");
+    });
+}
@@ -0,0 +1,162 @@
+//! An in-process mock broker, playing just enough of the AMQP protocol on the loopback interface
+//! for the tests and benchmarks which don't need a RabbitMQ server.
+//!
+//! This is shared by path with the benchmarks and the tests of the other crates of the
+//! workspace, which each only use parts of it.
+
+#![allow(dead_code)]
+
+use amq_protocol::frame::{gen_frame, parse_frame, AMQPFrame};
+use lapin::{
+    protocol::{channel, connection, AMQPClass},
+    types::{FieldTable, ShortUInt},
+};
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    thread,
+};
+
+pub struct MockBroker {
+    stream: TcpStream,
+    buffer: Vec<u8>,
+}
+
+impl MockBroker {
+    /// Listen on the loopback interface, handing each incoming connection to `serve` on its own
+    /// thread.
+    pub fn start<F: Fn(MockBroker) + Clone + Send + 'static>(serve: F) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+        let addr = listener.local_addr().expect("local_addr");
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let broker = MockBroker {
+                    stream: stream.expect("accept"),
+                    buffer: Vec::new(),
+                };
+                let serve = serve.clone();
+                thread::spawn(move || serve(broker));
+            }
+        });
+        addr
+    }
+
+    pub fn send(&mut self, frame: AMQPFrame) {
+        let buffer = gen_frame(&frame)(Vec::new().into())
+            .expect("failed to serialise frame")
+            .write;
+        self.stream
+            .write_all(&buffer)
+            .expect("failed to send frame");
+    }
+
+    pub fn send_method(&mut self, channel_id: u16, method: AMQPClass) {
+        self.send(AMQPFrame::Method(channel_id, method));
+    }
+
+    /// The next frame sent by the client, `None` once it went away.
+    pub fn receive(&mut self) -> Option<AMQPFrame> {
+        loop {
+            if let Ok((rest, frame)) = parse_frame(self.buffer.as_slice()) {
+                let consumed = self.buffer.len() - rest.len();
+                self.buffer.drain(..consumed);
+                return Some(frame);
+            }
+            let mut buf = [0; 16384];
+            match self.stream.read(&mut buf) {
+                Ok(0) | Err(_) => return None,
+                Ok(read) => self.buffer.extend_from_slice(&buf[..read]),
+            }
+        }
+    }
+
+    fn receive_connection_method(&mut self) -> connection::AMQPMethod {
+        match self.receive() {
+            Some(AMQPFrame::Method(0, AMQPClass::Connection(method))) => method,
+            frame => panic!("unexpected frame: {:?}", frame),
+        }
+    }
+
+    /// Play the server side of the handshake, offering `locales` and `heartbeat`, and return
+    /// what the client sent in its StartOk and Open.
+    pub fn handshake(
+        &mut self,
+        locales: &str,
+        heartbeat: ShortUInt,
+    ) -> (connection::StartOk, connection::Open) {
+        let mut header = [0; 8];
+        self.stream
+            .read_exact(&mut header)
+            .expect("protocol header");
+        assert_eq!(&header, b"AMQP\x00\x00\x09\x01");
+        self.send_method(
+            0,
+            AMQPClass::Connection(connection::AMQPMethod::Start(connection::Start {
+                version_major: 0,
+                version_minor: 9,
+                server_properties: FieldTable::default(),
+                mechanisms: "PLAIN".into(),
+                locales: locales.into(),
+            })),
+        );
+        let start_ok = match self.receive_connection_method() {
+            connection::AMQPMethod::StartOk(start_ok) => start_ok,
+            method => panic!("expected StartOk, got {:?}", method),
+        };
+        self.send_method(
+            0,
+            AMQPClass::Connection(connection::AMQPMethod::Tune(connection::Tune {
+                channel_max: 2047,
+                frame_max: 131_072,
+                heartbeat,
+            })),
+        );
+        match self.receive_connection_method() {
+            connection::AMQPMethod::TuneOk(_) => {}
+            method => panic!("expected TuneOk, got {:?}", method),
+        }
+        let open = match self.receive_connection_method() {
+            connection::AMQPMethod::Open(open) => open,
+            method => panic!("expected Open, got {:?}", method),
+        };
+        self.send_method(
+            0,
+            AMQPClass::Connection(connection::AMQPMethod::OpenOk(connection::OpenOk {})),
+        );
+        (start_ok, open)
+    }
+
+    /// Answer the openings and closings of the channels, handing the other frames to `handle`,
+    /// until the client closes the connection or goes away.
+    pub fn serve<F: FnMut(&mut Self, AMQPFrame)>(&mut self, mut handle: F) {
+        while let Some(frame) = self.receive() {
+            match frame {
+                AMQPFrame::Method(id, AMQPClass::Channel(channel::AMQPMethod::Open(_))) => self
+                    .send_method(
+                        id,
+                        AMQPClass::Channel(channel::AMQPMethod::OpenOk(channel::OpenOk {})),
+                    ),
+                AMQPFrame::Method(id, AMQPClass::Channel(channel::AMQPMethod::Close(_))) => self
+                    .send_method(
+                        id,
+                        AMQPClass::Channel(channel::AMQPMethod::CloseOk(channel::CloseOk {})),
+                    ),
+                AMQPFrame::Method(0, AMQPClass::Connection(connection::AMQPMethod::Close(_))) => {
+                    self.send_method(
+                        0,
+                        AMQPClass::Connection(connection::AMQPMethod::CloseOk(
+                            connection::CloseOk {},
+                        )),
+                    );
+                    return;
+                }
+                frame => handle(self, frame),
+            }
+        }
+    }
+}
+
+/// Fail on any frame the mock broker doesn't answer by itself.
+pub fn unexpected(_: &mut MockBroker, frame: AMQPFrame) {
+    panic!("unexpected frame: {:?}", frame);
+}
@@ -28,9 +28,9 @@ impl ConsumerDelegate for Subscriber {
             println!("received message: {:?}", delivery);
 
             if let Some((channel, delivery)) = delivery.unwrap() {
-                println!("data: {}", std::str::from_utf8(&delivery.data).unwrap());
+                println!("data: {}", std::str::from_utf8(delivery.data()).unwrap());
 
-                assert_eq!(delivery.data, b"Hello world!");
+                assert_eq!(delivery.data(), b"Hello world!");
 
                 subscriber.hello_world.fetch_add(1, Ordering::SeqCst);
 
@@ -0,0 +1,230 @@
+use lapin::{
+    options::*,
+    topology::{
+        BindingDeclaration, ExchangeDeclaration, QueueDeclaration, Topology, VerificationReport,
+    },
+    types::FieldTable,
+    Connection, ConnectionProperties, ExchangeKind,
+};
+
+#[test]
+fn declare_topology_rollback() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let addr = std::env::var("AMQP_ADDR").unwrap_or_else(|_| "amqp://127.0.0.1:5672/%2f".into());
+
+    async_global_executor::block_on(async {
+        let conn = Connection::connect(&addr, ConnectionProperties::default())
+            .await
+            .expect("connection error");
+        let channel = conn.create_channel().await.expect("create_channel");
+
+        let topology = Topology::new()
+            .exchange(ExchangeDeclaration::new(
+                "declare-topology-rollback",
+                ExchangeKind::Fanout,
+            ))
+            .queue(QueueDeclaration::new("declare-topology-rollback"))
+            .binding(BindingDeclaration::queue(
+                "declare-topology-rollback",
+                "declare-topology-rollback",
+                "",
+            ))
+            .binding(BindingDeclaration::queue(
+                "declare-topology-rollback",
+                "declare-topology-missing-exchange",
+                "",
+            ));
+        assert!(channel.declare_topology(&topology).await.is_err());
+
+        // Everything declared before the failure must have been deleted
+        let channel = conn.create_channel().await.expect("create_channel");
+        assert!(channel
+            .queue_declare(
+                "declare-topology-rollback",
+                QueueDeclareOptions::default().passive(true),
+                FieldTable::default(),
+            )
+            .await
+            .is_err());
+        let channel = conn.create_channel().await.expect("create_channel");
+        assert!(channel
+            .exchange_declare(
+                "declare-topology-rollback",
+                ExchangeKind::Fanout,
+                ExchangeDeclareOptions::default().passive(true),
+                FieldTable::default(),
+            )
+            .await
+            .is_err());
+    });
+}
+
+#[test]
+fn declare_topology_rollback_keeps_existing() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let addr = std::env::var("AMQP_ADDR").unwrap_or_else(|_| "amqp://127.0.0.1:5672/%2f".into());
+
+    async_global_executor::block_on(async {
+        let conn = Connection::connect(&addr, ConnectionProperties::default())
+            .await
+            .expect("connection error");
+        let channel = conn.create_channel().await.expect("create_channel");
+        channel
+            .queue_declare(
+                "declare-topology-existing",
+                QueueDeclareOptions::default(),
+                FieldTable::default(),
+            )
+            .await
+            .expect("queue_declare");
+
+        let topology = Topology::new()
+            .queue(QueueDeclaration::new("declare-topology-existing"))
+            .queue(QueueDeclaration::new("declare-topology-new"))
+            .binding(BindingDeclaration::queue(
+                "declare-topology-existing",
+                "declare-topology-missing-exchange",
+                "",
+            ));
+        assert!(channel.declare_topology(&topology).await.is_err());
+
+        // The queue which existed beforehand must still be there, the new one must be gone
+        let channel = conn.create_channel().await.expect("create_channel");
+        channel
+            .queue_declare(
+                "declare-topology-existing",
+                QueueDeclareOptions::default().passive(true),
+                FieldTable::default(),
+            )
+            .await
+            .expect("existing queue was deleted");
+        assert!(channel
+            .queue_declare(
+                "declare-topology-new",
+                QueueDeclareOptions::default().passive(true),
+                FieldTable::default(),
+            )
+            .await
+            .is_err());
+
+        let channel = conn.create_channel().await.expect("create_channel");
+        channel
+            .queue_delete("declare-topology-existing", QueueDeleteOptions::default())
+            .await
+            .expect("queue_delete");
+    });
+}
+
+#[test]
+fn verify_topology() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let addr = std::env::var("AMQP_ADDR").unwrap_or_else(|_| "amqp://127.0.0.1:5672/%2f".into());
+
+    async_global_executor::block_on(async {
+        let conn = Connection::connect(&addr, ConnectionProperties::default())
+            .await
+            .expect("connection error");
+        let channel = conn.create_channel().await.expect("create_channel");
+
+        let topology = Topology::new()
+            .exchange(ExchangeDeclaration::new(
+                "verify-topology",
+                ExchangeKind::Fanout,
+            ))
+            .queue(QueueDeclaration::new("verify-topology"))
+            .binding(BindingDeclaration::queue(
+                "verify-topology",
+                "verify-topology",
+                "",
+            ));
+        channel
+            .declare_topology(&topology)
+            .await
+            .expect("declare_topology");
+
+        // Everything is there
+        let report = channel
+            .verify_topology(&topology)
+            .await
+            .expect("verify_topology");
+        assert!(report.is_ok());
+
+        // One queue and one exchange are missing
+        let expected = topology
+            .clone()
+            .exchange(ExchangeDeclaration::new(
+                "verify-topology-missing",
+                ExchangeKind::Direct,
+            ))
+            .queue(QueueDeclaration::new("verify-topology-missing"))
+            .queue(QueueDeclaration::new("verify-topology-missing-too"));
+        let report = channel
+            .verify_topology(&expected)
+            .await
+            .expect("verify_topology");
+        assert_eq!(
+            report,
+            VerificationReport {
+                missing_exchanges: vec!["verify-topology-missing".into()],
+                missing_queues: vec![
+                    "verify-topology-missing".into(),
+                    "verify-topology-missing-too".into()
+                ],
+                ..VerificationReport::default()
+            }
+        );
+        assert!(!report.is_ok());
+        // The failures happened on dedicated channels
+        assert!(channel.status().connected());
+
+        channel
+            .queue_delete("verify-topology", QueueDeleteOptions::default())
+            .await
+            .expect("queue_delete");
+        channel
+            .exchange_delete("verify-topology", ExchangeDeleteOptions::default())
+            .await
+            .expect("exchange_delete");
+    });
+}
+
+#[test]
+fn verify_topology_exclusive_queue() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let addr = std::env::var("AMQP_ADDR").unwrap_or_else(|_| "amqp://127.0.0.1:5672/%2f".into());
+
+    async_global_executor::block_on(async {
+        let owner = Connection::connect(&addr, ConnectionProperties::default())
+            .await
+            .expect("connection error");
+        let owner_channel = owner.create_channel().await.expect("create_channel");
+        owner_channel
+            .queue_declare(
+                "verify-topology-exclusive",
+                QueueDeclareOptions::default().exclusive(true),
+                FieldTable::default(),
+            )
+            .await
+            .expect("queue_declare");
+
+        // Another connection isn't allowed to access it
+        let conn = Connection::connect(&addr, ConnectionProperties::default())
+            .await
+            .expect("connection error");
+        let channel = conn.create_channel().await.expect("create_channel");
+        let topology = Topology::new().queue(QueueDeclaration::new("verify-topology-exclusive"));
+        let report = channel
+            .verify_topology(&topology)
+            .await
+            .expect("verify_topology");
+        assert_eq!(
+            report.inaccessible_queues,
+            vec!["verify-topology-exclusive".to_string()]
+        );
+        assert!(channel.status().connected());
+    });
+}
@@ -0,0 +1,90 @@
+use lapin::{
+    message::DeliveryResult, options::*, types::FieldTable, BasicProperties, Connection,
+    ConnectionProperties,
+};
+use std::time::Duration;
+
+#[test]
+fn delegate_publish() {
+    // A single thread runs both the delegate and what it awaits
+    std::env::set_var("ASYNC_GLOBAL_EXECUTOR_THREADS", "1");
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let addr = std::env::var("AMQP_ADDR").unwrap_or_else(|_| "amqp://127.0.0.1:5672/%2f".into());
+    let (sender, receiver) = flume::unbounded();
+
+    let _conn = async_global_executor::block_on(async {
+        let conn = Connection::connect(&addr, ConnectionProperties::default())
+            .await
+            .expect("connection error");
+        let channel = conn.create_channel().await.expect("create_channel");
+        channel
+            .confirm_select(ConfirmSelectOptions::default())
+            .await
+            .expect("confirm_select");
+        for queue in &["delegate-requests", "delegate-replies"] {
+            channel
+                .queue_declare(queue, QueueDeclareOptions::default(), FieldTable::default())
+                .await
+                .expect("queue_declare");
+            channel
+                .queue_purge(queue, QueuePurgeOptions::default())
+                .await
+                .expect("queue_purge");
+        }
+
+        let consumer = channel
+            .basic_consume(
+                "delegate-requests",
+                "delegate-publish",
+                BasicConsumeOptions::default(),
+                FieldTable::default(),
+            )
+            .await
+            .expect("basic_consume");
+        consumer.set_delegate(move |delivery: DeliveryResult| {
+            let sender = sender.clone();
+            async move {
+                if let Ok(Some((channel, delivery))) = delivery {
+                    let confirmation = channel
+                        .basic_publish(
+                            "",
+                            "delegate-replies",
+                            BasicPublishOptions::default(),
+                            delivery.data().to_vec(),
+                            BasicProperties::default(),
+                        )
+                        .await
+                        .expect("basic_publish")
+                        .await
+                        .expect("publisher confirm");
+                    channel
+                        .basic_ack(delivery.delivery_tag, BasicAckOptions::default())
+                        .await
+                        .expect("basic_ack");
+                    let _ = sender.send(confirmation.is_ack());
+                }
+            }
+        });
+
+        channel
+            .basic_publish(
+                "",
+                "delegate-requests",
+                BasicPublishOptions::default(),
+                b"request".to_vec(),
+                BasicProperties::default(),
+            )
+            .await
+            .expect("basic_publish")
+            .await
+            .expect("publisher confirm");
+        conn
+    });
+
+    assert_eq!(
+        receiver.recv_timeout(Duration::from_secs(10)),
+        Ok(true),
+        "the delegate didn't get its publish confirmed"
+    );
+}
@@ -0,0 +1,77 @@
+use lapin::{options::*, types::FieldTable, Connection, ConnectionProperties, Error};
+
+#[test]
+fn exclusive_consumer_conflict() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let addr = std::env::var("AMQP_ADDR").unwrap_or_else(|_| "amqp://127.0.0.1:5672/%2f".into());
+
+    async_global_executor::block_on(async {
+        let winner = Connection::connect(&addr, ConnectionProperties::default())
+            .await
+            .expect("connection error");
+        let contender = Connection::connect(&addr, ConnectionProperties::default())
+            .await
+            .expect("connection error");
+        let winner_channel = winner.create_channel().await.expect("create_channel");
+        winner_channel
+            .queue_declare(
+                "exclusive-consumer",
+                QueueDeclareOptions::default(),
+                FieldTable::default(),
+            )
+            .await
+            .expect("queue_declare");
+        let consumer = winner_channel
+            .basic_consume(
+                "exclusive-consumer",
+                "winner",
+                BasicConsumeOptions::default().exclusive(true),
+                FieldTable::default(),
+            )
+            .await
+            .expect("basic_consume");
+
+        for _ in 0..3 {
+            let channel = contender.create_channel().await.expect("create_channel");
+            match channel
+                .basic_consume(
+                    "exclusive-consumer",
+                    "contender",
+                    BasicConsumeOptions::default().exclusive(true),
+                    FieldTable::default(),
+                )
+                .await
+            {
+                Err(error) => {
+                    assert!(error.is_access_refused(), "unexpected error: {:?}", error);
+                    match error {
+                        Error::ProtocolError(error) => {
+                            assert_eq!(error.get_id(), 403);
+                            assert!(error.get_message().contains("exclusive-consumer"));
+                        }
+                        error => panic!("expected a protocol error, got {:?}", error),
+                    }
+                }
+                Ok(consumer) => panic!("expected an error, got {:?}", consumer),
+            }
+            assert!(!channel.status().connected());
+        }
+
+        // The contender wins once the exclusive consumer is gone
+        winner_channel
+            .basic_cancel(consumer.tag().as_str(), BasicCancelOptions::default())
+            .await
+            .expect("basic_cancel");
+        let channel = contender.create_channel().await.expect("create_channel");
+        channel
+            .basic_consume(
+                "exclusive-consumer",
+                "contender",
+                BasicConsumeOptions::default().exclusive(true),
+                FieldTable::default(),
+            )
+            .await
+            .expect("basic_consume");
+    });
+}
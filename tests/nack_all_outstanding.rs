@@ -0,0 +1,87 @@
+use futures_lite::stream::StreamExt;
+use lapin::{options::*, types::FieldTable, BasicProperties, Connection, ConnectionProperties};
+
+#[test]
+fn nack_all_outstanding() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let addr = std::env::var("AMQP_ADDR").unwrap_or_else(|_| "amqp://127.0.0.1:5672/%2f".into());
+
+    async_global_executor::block_on(async {
+        let conn = Connection::connect(&addr, ConnectionProperties::default())
+            .await
+            .expect("connection error");
+        let channel = conn.create_channel().await.expect("create_channel");
+
+        channel
+            .queue_declare(
+                "nack-all-outstanding",
+                QueueDeclareOptions::default(),
+                FieldTable::default(),
+            )
+            .await
+            .expect("queue_declare");
+        channel
+            .queue_purge("nack-all-outstanding", QueuePurgeOptions::default())
+            .await
+            .expect("queue_purge");
+
+        // Nothing is outstanding yet, this must be a no-op
+        channel
+            .nack_all_outstanding(true)
+            .await
+            .expect("nack_all_outstanding");
+
+        for _ in 0..10 {
+            channel
+                .basic_publish(
+                    "",
+                    "nack-all-outstanding",
+                    BasicPublishOptions::default(),
+                    b"Hello world!".to_vec(),
+                    BasicProperties::default(),
+                )
+                .await
+                .expect("basic_publish");
+        }
+
+        let mut consumer = channel
+            .basic_consume(
+                "nack-all-outstanding",
+                "nack-all-outstanding-consumer",
+                BasicConsumeOptions::default(),
+                FieldTable::default(),
+            )
+            .await
+            .expect("basic_consume");
+        for _ in 0..10 {
+            consumer
+                .next()
+                .await
+                .expect("consumer canceled")
+                .expect("error in consumer");
+        }
+        channel
+            .basic_cancel(consumer.tag().as_str(), BasicCancelOptions::default())
+            .await
+            .expect("basic_cancel");
+
+        channel
+            .nack_all_outstanding(true)
+            .await
+            .expect("nack_all_outstanding");
+
+        let queue = channel
+            .queue_declare(
+                "nack-all-outstanding",
+                QueueDeclareOptions::default().passive(true),
+                FieldTable::default(),
+            )
+            .await
+            .expect("queue_declare");
+        assert_eq!(queue.message_count(), 10);
+
+        channel.close(200, "OK").await.expect("channel close");
+        assert!(channel.nack_all_outstanding(true).await.is_err());
+    });
+}
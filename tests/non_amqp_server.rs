@@ -0,0 +1,104 @@
+use lapin::{protocol::constants::FRAME_END, Connection, ConnectionProperties, Error};
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, TcpListener},
+    thread,
+};
+
+// A server reading our protocol header and answering it with a canned greeting
+fn canned_server(greeting: &'static [u8]) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+    let addr = listener.local_addr().expect("local_addr");
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().expect("accept");
+        let mut header = [0; 8];
+        stream.read_exact(&mut header).expect("protocol header");
+        stream.write_all(greeting).expect("greeting");
+        let _ = stream.read_to_end(&mut Vec::new());
+    });
+    addr
+}
+
+fn connect(addr: SocketAddr) -> Error {
+    async_global_executor::block_on(Connection::connect_to_addr(
+        addr,
+        "amqp://127.0.0.1:5672/%2f",
+        ConnectionProperties::default(),
+    ))
+    .err()
+    .expect("connection should fail")
+}
+
+#[test]
+fn http_server() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let addr = canned_server(b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n");
+    let error = connect(addr);
+    assert_eq!(error, Error::NotAnAmqpServer(b"HTTP/1.1 400 Bad".to_vec()));
+    assert_eq!(
+        error.to_string(),
+        "the server doesn't speak AMQP, it answered: 48 54 54 50 2f 31 2e 31 20 34 30 30 20 42 61 64 (this looks like an HTTP server, is this the management port?)"
+    );
+}
+
+#[test]
+fn garbage_frame() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    // A well-formed heartbeat frame, which is not a valid answer to our protocol header
+    let addr = canned_server(&[8, 0, 0, 0, 0, 0, 0, FRAME_END]);
+    assert_eq!(
+        connect(addr),
+        Error::NotAnAmqpServer(vec![8, 0, 0, 0, 0, 0, 0, FRAME_END])
+    );
+}
+
+#[test]
+fn unsupported_version() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let addr = canned_server(b"AMQP\x00\x00\x09\x01");
+    let error = connect(addr);
+    match &error {
+        Error::InvalidProtocolVersion(version) => {
+            assert_eq!(version.to_string(), "0.9.1");
+        }
+        error => panic!("unexpected error: {}", error),
+    }
+    assert_eq!(error.to_string(), "the server only supports AMQP 0.9.1");
+}
+
+#[test]
+fn amqp_1_0_server() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    // The SASL protocol header an AMQP 1.0 broker answers with
+    let addr = canned_server(b"AMQP\x03\x01\x00\x00");
+    let error = connect(addr);
+    match &error {
+        Error::InvalidProtocolVersion(version) => {
+            assert_eq!(version.to_string(), "1.0.0");
+        }
+        error => panic!("unexpected error: {}", error),
+    }
+    assert_eq!(
+        error.to_string(),
+        "the server only supports AMQP 1.0.0 (AMQP 1.0 is a different protocol, lapin only supports AMQP 0.9.1)"
+    );
+}
+
+#[test]
+fn amqp_0_8_server() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let addr = canned_server(b"AMQP\x01\x01\x08\x00");
+    let error = connect(addr);
+    match &error {
+        Error::InvalidProtocolVersion(version) => {
+            assert_eq!(version.to_string(), "0.8.0");
+        }
+        error => panic!("unexpected error: {}", error),
+    }
+    assert_eq!(error.to_string(), "the server only supports AMQP 0.8.0");
+}
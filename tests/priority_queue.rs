@@ -0,0 +1,112 @@
+use lapin::{
+    options::*, BasicProperties, Connection, ConnectionProperties, Error, PriorityValidation,
+};
+
+#[test]
+fn priority_queue() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let addr = std::env::var("AMQP_ADDR").unwrap_or_else(|_| "amqp://127.0.0.1:5672/%2f".into());
+
+    async_global_executor::block_on(async {
+        let conn = Connection::connect(
+            &addr,
+            ConnectionProperties::default().with_priority_validation(PriorityValidation::Warn),
+        )
+        .await
+        .expect("connection error");
+        let channel = conn.create_channel().await.expect("create_channel");
+        channel
+            .confirm_select(ConfirmSelectOptions::default())
+            .await
+            .expect("confirm_select");
+
+        let _ = channel
+            .queue_delete("priority-queue", QueueDeleteOptions::default())
+            .await;
+        channel
+            .declare_priority_queue("priority-queue", 5, QueueDeclareOptions::default())
+            .await
+            .expect("declare_priority_queue");
+
+        // 9 is above the maximum priority, it gets a warning and is handled as a 5
+        for priority in &[1, 9] {
+            channel
+                .basic_publish(
+                    "",
+                    "priority-queue",
+                    BasicPublishOptions::default(),
+                    vec![*priority],
+                    BasicProperties::default().with_priority(*priority),
+                )
+                .await
+                .expect("basic_publish")
+                .await
+                .expect("publisher confirm");
+        }
+
+        let mut received = Vec::new();
+        while let Some(message) = channel
+            .basic_get("priority-queue", BasicGetOptions::default().no_ack(true))
+            .await
+            .expect("basic_get")
+        {
+            received.push(message.delivery.data().to_vec());
+        }
+        assert_eq!(received, vec![vec![9], vec![1]]);
+
+        channel
+            .queue_delete("priority-queue", QueueDeleteOptions::default())
+            .await
+            .expect("queue_delete");
+    });
+}
+
+#[test]
+fn priority_validation_error() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let addr = std::env::var("AMQP_ADDR").unwrap_or_else(|_| "amqp://127.0.0.1:5672/%2f".into());
+
+    async_global_executor::block_on(async {
+        let conn = Connection::connect(
+            &addr,
+            ConnectionProperties::default().with_priority_validation(PriorityValidation::Error),
+        )
+        .await
+        .expect("connection error");
+        let channel = conn.create_channel().await.expect("create_channel");
+        channel
+            .declare_priority_queue(
+                "priority-validation",
+                5,
+                QueueDeclareOptions::default().auto_delete(true),
+            )
+            .await
+            .expect("declare_priority_queue");
+
+        assert!(matches!(
+            channel
+                .basic_publish(
+                    "",
+                    "priority-validation",
+                    BasicPublishOptions::default(),
+                    b"urgent".to_vec(),
+                    BasicProperties::default().with_priority(9),
+                )
+                .await,
+            Err(Error::InvalidArgument(_))
+        ));
+        // Only the publishes through the default exchange can be checked
+        assert!(channel
+            .basic_publish(
+                "amq.direct",
+                "priority-validation",
+                BasicPublishOptions::default(),
+                b"urgent".to_vec(),
+                BasicProperties::default().with_priority(9),
+            )
+            .await
+            .is_ok());
+    });
+}
@@ -0,0 +1,61 @@
+use futures_lite::stream::StreamExt;
+use lapin::{
+    options::*,
+    patterns::pubsub::{Publisher, Subscriber},
+    types::FieldTable,
+    Connection, ConnectionProperties, ExchangeKind,
+};
+
+#[test]
+fn pubsub() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let addr = std::env::var("AMQP_ADDR").unwrap_or_else(|_| "amqp://127.0.0.1:5672/%2f".into());
+
+    async_global_executor::block_on(async {
+        let conn = Connection::connect(&addr, ConnectionProperties::default())
+            .await
+            .expect("connection error");
+        let channel = conn.create_channel().await.expect("create_channel");
+        channel
+            .exchange_declare(
+                "pubsub-events",
+                ExchangeKind::Topic,
+                ExchangeDeclareOptions::default().auto_delete(true),
+                FieldTable::default(),
+            )
+            .await
+            .expect("exchange_declare");
+
+        let mut orders = Subscriber::new(&channel, "pubsub-events", "orders.*")
+            .await
+            .expect("subscriber");
+        let mut everything = Subscriber::new(&channel, "pubsub-events", "#")
+            .await
+            .expect("subscriber");
+        assert_ne!(orders.queue(), everything.queue());
+
+        let publisher = Publisher::new(
+            conn.create_channel().await.expect("create_channel"),
+            "pubsub-events",
+        );
+        publisher
+            .publish("users.created", b"alice")
+            .await
+            .expect("publish");
+        publisher
+            .publish_json("orders.created", &vec![1, 2, 3])
+            .await
+            .expect("publish_json");
+
+        let (routing_key, payload) = orders.next().await.unwrap().unwrap();
+        assert_eq!(routing_key, "orders.created");
+        assert_eq!(payload, b"[1,2,3]");
+
+        let (routing_key, payload) = everything.next().await.unwrap().unwrap();
+        assert_eq!(routing_key, "users.created");
+        assert_eq!(payload, b"alice");
+        let (routing_key, _) = everything.next().await.unwrap().unwrap();
+        assert_eq!(routing_key, "orders.created");
+    });
+}
@@ -0,0 +1,47 @@
+use lapin::{options::*, types::FieldTable, Connection, ConnectionProperties};
+
+#[test]
+fn queue_declare_passive_checked() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let addr = std::env::var("AMQP_ADDR").unwrap_or_else(|_| "amqp://127.0.0.1:5672/%2f".into());
+
+    async_global_executor::block_on(async {
+        let conn = Connection::connect(&addr, ConnectionProperties::default())
+            .await
+            .expect("connection error");
+        let channel = conn.create_channel().await.expect("create_channel");
+
+        let _ = channel
+            .queue_delete("passive-checked", QueueDeleteOptions::default())
+            .await;
+        assert!(channel
+            .queue_declare_passive_checked("passive-checked")
+            .await
+            .expect("queue_declare_passive_checked")
+            .is_none());
+        // The probe didn't close our channel
+        assert!(channel.status().connected());
+
+        channel
+            .queue_declare(
+                "passive-checked",
+                QueueDeclareOptions::default(),
+                FieldTable::default(),
+            )
+            .await
+            .expect("queue_declare");
+        let queue = channel
+            .queue_declare_passive_checked("passive-checked")
+            .await
+            .expect("queue_declare_passive_checked")
+            .expect("queue");
+        assert_eq!(queue.name().as_str(), "passive-checked");
+        assert_eq!(queue.consumer_count(), 0);
+
+        channel
+            .queue_delete("passive-checked", QueueDeleteOptions::default())
+            .await
+            .expect("queue_delete");
+    });
+}
@@ -0,0 +1,73 @@
+use futures_lite::stream::StreamExt;
+use lapin::{
+    options::*, types::FieldTable, BasicProperties, Connection, ConnectionProperties, QueueStats,
+};
+use std::time::Duration;
+
+#[test]
+fn queue_stats() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let addr = std::env::var("AMQP_ADDR").unwrap_or_else(|_| "amqp://127.0.0.1:5672/%2f".into());
+
+    async_global_executor::block_on(async {
+        let conn = Connection::connect(&addr, ConnectionProperties::default())
+            .await
+            .expect("connection error");
+        let channel = conn.create_channel().await.expect("create_channel");
+
+        channel
+            .queue_declare(
+                "queue-stats",
+                QueueDeclareOptions::default(),
+                FieldTable::default(),
+            )
+            .await
+            .expect("queue_declare");
+        channel
+            .queue_purge("queue-stats", QueuePurgeOptions::default())
+            .await
+            .expect("queue_purge");
+
+        let mut stats =
+            Box::pin(channel.queue_stats_stream("queue-stats", Duration::from_millis(100)));
+        assert_eq!(
+            stats.next().await.expect("stats").expect("queue_stats"),
+            QueueStats {
+                messages: 0,
+                consumers: 0,
+            }
+        );
+
+        for _ in 0..3 {
+            channel
+                .basic_publish(
+                    "",
+                    "queue-stats",
+                    BasicPublishOptions::default(),
+                    b"Hello world!".to_vec(),
+                    BasicProperties::default(),
+                )
+                .await
+                .expect("basic_publish");
+        }
+        loop {
+            let current = stats.next().await.expect("stats").expect("queue_stats");
+            if current.messages == 3 {
+                break;
+            }
+            assert!(current.messages < 3);
+        }
+
+        channel
+            .queue_delete("queue-stats", QueueDeleteOptions::default())
+            .await
+            .expect("queue_delete");
+        assert!(stats.next().await.expect("stats").is_err());
+
+        // The failures happened on dedicated channels, ours is still usable
+        assert!(channel.status().connected());
+        assert!(channel.queue_stats("queue-stats").await.is_err());
+        assert!(channel.status().connected());
+    });
+}
@@ -0,0 +1,66 @@
+use lapin::{
+    options::*, reconnect::ReconnectingPublisher, types::FieldTable, BasicProperties, Connection,
+    ConnectionProperties,
+};
+use std::sync::Arc;
+
+#[test]
+fn reconnecting_publisher() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let addr = std::env::var("AMQP_ADDR").unwrap_or_else(|_| "amqp://127.0.0.1:5672/%2f".into());
+
+    async_global_executor::block_on(async {
+        let conn = Arc::new(
+            Connection::connect(&addr, ConnectionProperties::default())
+                .await
+                .expect("connection error"),
+        );
+        let channel = conn.create_channel().await.expect("create_channel");
+        channel
+            .confirm_select(ConfirmSelectOptions::default())
+            .await
+            .expect("confirm_select");
+        channel
+            .queue_declare(
+                "reconnecting-publisher",
+                QueueDeclareOptions::default(),
+                FieldTable::default(),
+            )
+            .await
+            .expect("queue_declare");
+        let publisher = ReconnectingPublisher::new(conn.clone(), channel.clone());
+
+        // Publishing to an exchange which doesn't exist makes the server close the channel
+        channel
+            .basic_publish(
+                "reconnecting-publisher-missing-exchange",
+                "",
+                BasicPublishOptions::default(),
+                b"Hello world!".to_vec(),
+                BasicProperties::default(),
+            )
+            .await
+            .expect("basic_publish");
+        assert!(channel.wait_for_confirms().await.is_err());
+
+        publisher
+            .publish(
+                "",
+                "reconnecting-publisher",
+                b"Hello world!".to_vec(),
+                BasicProperties::default(),
+                BasicPublishOptions::default(),
+            )
+            .await
+            .expect("publish");
+
+        let current = publisher.channel();
+        assert!(current.is_open());
+        assert!(current.status().confirm());
+        current
+            .wait_for_confirms()
+            .await
+            .expect("wait_for_confirms");
+    });
+}
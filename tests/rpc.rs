@@ -0,0 +1,47 @@
+use lapin::{
+    options::*,
+    patterns::rpc::{RpcClient, RpcServer},
+    Connection, ConnectionProperties,
+};
+
+#[test]
+fn rpc() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let addr = std::env::var("AMQP_ADDR").unwrap_or_else(|_| "amqp://127.0.0.1:5672/%2f".into());
+
+    async_global_executor::block_on(async {
+        let conn = Connection::connect(&addr, ConnectionProperties::default())
+            .await
+            .expect("connection error");
+        let server = RpcServer::new(
+            conn.create_channel().await.expect("create_channel"),
+            "rpc-requests",
+        )
+        .await
+        .expect("server");
+        server
+            .channel()
+            .queue_purge("rpc-requests", QueuePurgeOptions::default())
+            .await
+            .expect("queue_purge");
+        async_global_executor::spawn(server.serve(|request| async move {
+            let mut reply = request.data().to_vec();
+            reply.reverse();
+            reply
+        }))
+        .detach();
+
+        let client = RpcClient::new(conn.create_channel().await.expect("create_channel"))
+            .await
+            .expect("client");
+        // Concurrent calls each get their own reply
+        let (first, second) = futures_lite::future::zip(
+            client.call("rpc-requests", b"ping"),
+            client.call("rpc-requests", b"pong"),
+        )
+        .await;
+        assert_eq!(first.expect("call").data(), b"gnip");
+        assert_eq!(second.expect("call").data(), b"gnop");
+    });
+}
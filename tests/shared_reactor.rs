@@ -0,0 +1,77 @@
+use lapin::{reactor::SharedReactorBuilder, Connection, ConnectionProperties};
+use std::{
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
+
+mod common;
+
+use common::MockBroker;
+
+#[test]
+fn shared_reactor() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let addr = MockBroker::start(|mut broker| {
+        broker.handshake("en_US", 0);
+        broker.serve(common::unexpected);
+    });
+    let reactor = SharedReactorBuilder::new();
+    let connect = || {
+        Connection::connect_to_addr(
+            addr,
+            "amqp://127.0.0.1:5672/%2f",
+            ConnectionProperties::default().with_reactor(reactor.clone()),
+        )
+    };
+
+    async_global_executor::block_on(async {
+        let first = connect().await.expect("first connection");
+        let second = connect().await.expect("second connection");
+        assert_eq!(reactor.registered_sockets(), 2);
+
+        first.close(200, "OK").await.expect("close");
+        // The io loop releases its socket once it stops
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while reactor.registered_sockets() != 1 {
+            assert!(Instant::now() < deadline, "socket wasn't released");
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        assert!(second.status().connected());
+        second.create_channel().await.expect("create_channel");
+    });
+}
+
+#[test]
+fn socket_closed_with_connection() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let (closed_sender, closed) = mpsc::channel();
+    let addr = MockBroker::start(move |mut broker| {
+        broker.handshake("en_US", 0);
+        broker.serve(common::unexpected);
+        // Nothing but the end of the stream should follow the connection.close-ok
+        if broker.receive().is_none() {
+            let _ = closed_sender.send(());
+        }
+    });
+    let reactor = SharedReactorBuilder::new();
+
+    async_global_executor::block_on(async {
+        let conn = Connection::connect_to_addr(
+            addr,
+            "amqp://127.0.0.1:5672/%2f",
+            ConnectionProperties::default().with_reactor(reactor.clone()),
+        )
+        .await
+        .expect("connection");
+        conn.close(200, "OK").await.expect("close");
+    });
+
+    closed
+        .recv_timeout(Duration::from_secs(5))
+        .expect("the socket wasn't closed");
+    assert_eq!(reactor.registered_sockets(), 0);
+}
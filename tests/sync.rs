@@ -0,0 +1,59 @@
+use futures_lite::future;
+use lapin::{options::*, types::FieldTable, BasicProperties, Connection, ConnectionProperties};
+
+#[test]
+fn sync() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let addr = std::env::var("AMQP_ADDR").unwrap_or_else(|_| "amqp://127.0.0.1:5672/%2f".into());
+
+    async_global_executor::block_on(async {
+        let conn = Connection::connect(&addr, ConnectionProperties::default())
+            .await
+            .expect("connection error");
+        let channel = conn.create_channel().await.expect("create_channel");
+        channel
+            .confirm_select(ConfirmSelectOptions::default())
+            .await
+            .expect("confirm_select");
+
+        // Nothing is pending yet
+        channel.sync().await.expect("sync");
+
+        let publish = || {
+            channel.basic_publish(
+                "",
+                "sync",
+                BasicPublishOptions::default(),
+                b"Hello world!".to_vec(),
+                BasicProperties::default(),
+            )
+        };
+
+        channel
+            .queue_declare(
+                "sync",
+                QueueDeclareOptions::default(),
+                FieldTable::default(),
+            )
+            .await
+            .expect("queue_declare");
+        let mut confirms = Vec::new();
+        for _ in 0..10 {
+            confirms.push(publish().await.expect("basic_publish"));
+        }
+        let sync = channel.sync();
+
+        // Issued after the sync, must not delay it
+        let late_confirm = publish().await.expect("basic_publish");
+
+        sync.await.expect("sync");
+        for confirm in confirms.iter_mut() {
+            assert!(future::poll_once(confirm).await.is_some());
+        }
+        late_confirm.await.expect("late confirm");
+
+        channel.close(200, "OK").await.expect("channel close");
+        assert!(channel.sync().await.is_err());
+    });
+}
@@ -0,0 +1,67 @@
+use futures_lite::stream::StreamExt;
+use lapin::{
+    options::*,
+    topology::{ExchangeDeclaration, QueueDeclaration},
+    BasicProperties, Connection, ConnectionProperties, ExchangeKind,
+};
+
+#[test]
+fn consume_unroutable() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let addr = std::env::var("AMQP_ADDR").unwrap_or_else(|_| "amqp://127.0.0.1:5672/%2f".into());
+
+    async_global_executor::block_on(async {
+        let conn = Connection::connect(&addr, ConnectionProperties::default())
+            .await
+            .expect("connection error");
+        let channel = conn.create_channel().await.expect("create_channel");
+        channel
+            .confirm_select(ConfirmSelectOptions::default())
+            .await
+            .expect("confirm_select");
+
+        let _ = channel
+            .queue_delete("unroutable-audit", QueueDeleteOptions::default())
+            .await;
+        let declared = channel
+            .declare_with_alternate(
+                ExchangeDeclaration::new("unroutable-main", ExchangeKind::Direct),
+                ExchangeDeclaration::new("unroutable-alternate", ExchangeKind::Fanout),
+                QueueDeclaration::new("unroutable-audit"),
+            )
+            .await
+            .expect("declare_with_alternate");
+        assert_eq!(declared.audit_queue.name().as_str(), "unroutable-audit");
+
+        let mut consumer = channel
+            .consume_unroutable("unroutable-main")
+            .await
+            .expect("consume_unroutable");
+
+        channel
+            .basic_publish(
+                "unroutable-main",
+                "routing-key-with-a-typo",
+                BasicPublishOptions::default(),
+                b"lost".to_vec(),
+                BasicProperties::default(),
+            )
+            .await
+            .expect("basic_publish")
+            .await
+            .expect("publisher confirm");
+
+        let (_, delivery) = consumer
+            .next()
+            .await
+            .expect("consumer stream ended")
+            .expect("delivery error");
+        assert_eq!(delivery.data(), b"lost");
+        assert_eq!(delivery.routing_key.as_str(), "routing-key-with-a-typo");
+        channel
+            .basic_ack(delivery.delivery_tag, BasicAckOptions::default())
+            .await
+            .expect("basic_ack");
+    });
+}
@@ -0,0 +1,67 @@
+use lapin::{
+    options::*, patterns::work_queue::WorkQueue, BasicProperties, Connection, ConnectionProperties,
+    Error,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+struct Task {
+    id: u32,
+}
+
+#[test]
+fn work_queue() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let addr = std::env::var("AMQP_ADDR").unwrap_or_else(|_| "amqp://127.0.0.1:5672/%2f".into());
+
+    async_global_executor::block_on(async {
+        let conn = Connection::connect(&addr, ConnectionProperties::default())
+            .await
+            .expect("connection error");
+        let (sender, mut receiver) = WorkQueue::setup(&conn, "work-queue").await.expect("setup");
+        sender
+            .channel()
+            .queue_purge("work-queue", QueuePurgeOptions::default())
+            .await
+            .expect("queue_purge");
+
+        for id in 0..2 {
+            sender.send(&Task { id }).await.expect("send");
+        }
+        // Not JSON, this one gets rejected
+        sender
+            .channel()
+            .basic_publish(
+                "",
+                "work-queue",
+                BasicPublishOptions::default(),
+                b"{".to_vec(),
+                BasicProperties::default().with_content_type("application/json".into()),
+            )
+            .await
+            .expect("basic_publish")
+            .await
+            .expect("publisher confirm");
+
+        for id in 0..2 {
+            let (task, delivery) = receiver.recv::<Task>().await.expect("recv");
+            assert_eq!(task, Task { id });
+            receiver
+                .channel()
+                .basic_ack(delivery.delivery_tag, BasicAckOptions::default())
+                .await
+                .expect("basic_ack");
+        }
+        assert!(matches!(
+            receiver.recv::<Task>().await,
+            Err(Error::PayloadCodecError(_))
+        ));
+
+        sender
+            .channel()
+            .queue_delete("work-queue", QueueDeleteOptions::default())
+            .await
+            .expect("queue_delete");
+    });
+}
@@ -0,0 +1,97 @@
+use lapin::{
+    options::*, pool::WorkerPool, types::FieldTable, BasicProperties, Connection,
+    ConnectionProperties,
+};
+use parking_lot::Mutex;
+use std::{
+    collections::HashSet,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+#[test]
+fn worker_pool() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let addr = std::env::var("AMQP_ADDR").unwrap_or_else(|_| "amqp://127.0.0.1:5672/%2f".into());
+
+    async_global_executor::block_on(async {
+        let conn = Connection::connect(&addr, ConnectionProperties::default())
+            .await
+            .expect("connection error");
+        let channel = conn.create_channel().await.expect("create_channel");
+        channel
+            .queue_declare(
+                "worker-pool",
+                QueueDeclareOptions::default(),
+                FieldTable::default(),
+            )
+            .await
+            .expect("queue_declare");
+        channel
+            .queue_purge("worker-pool", QueuePurgeOptions::default())
+            .await
+            .expect("queue_purge");
+
+        let threads = Arc::new(Mutex::new(HashSet::new()));
+        let handled = Arc::new(Mutex::new(Vec::new()));
+        let pool = {
+            let threads = threads.clone();
+            let handled = handled.clone();
+            WorkerPool::new(
+                &conn,
+                "worker-pool",
+                4,
+                move |delivery| {
+                    thread::sleep(Duration::from_millis(20));
+                    threads.lock().insert(thread::current().id());
+                    handled.lock().push(delivery.data().to_vec());
+                    // More panics than workers, the pool only survives them if they're caught
+                    if delivery.data()[0] % 4 == 0 {
+                        panic!("poisoned delivery");
+                    }
+                    Ok(())
+                },
+                None,
+            )
+            .await
+            .expect("worker pool")
+        };
+
+        for i in 0..20u8 {
+            channel
+                .basic_publish(
+                    "",
+                    "worker-pool",
+                    BasicPublishOptions::default(),
+                    vec![i],
+                    BasicProperties::default(),
+                )
+                .await
+                .expect("basic_publish");
+        }
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while handled.lock().len() < 20 {
+            assert!(Instant::now() < deadline, "deliveries weren't all handled");
+            thread::sleep(Duration::from_millis(10));
+        }
+        pool.shutdown().await.expect("shutdown");
+
+        let mut handled = handled.lock().clone();
+        handled.sort();
+        assert_eq!(handled, (0..20u8).map(|i| vec![i]).collect::<Vec<_>>());
+        assert!(threads.lock().len() > 1);
+
+        // Everything got acknowledged or rejected
+        let queue = channel
+            .queue_declare(
+                "worker-pool",
+                QueueDeclareOptions::default().passive(true),
+                FieldTable::default(),
+            )
+            .await
+            .expect("queue_declare");
+        assert_eq!(queue.message_count(), 0);
+    });
+}